@@ -61,8 +61,10 @@ impl Cmd for RunArgs {
 
         let figment: Figment = From::from(&self);
         let mut evm_opts = figment.extract::<EvmOpts>()?;
+        let explicit_evm_version = crate::utils::is_configured(&figment, "evm_version");
         let config = Config::from_provider(figment).sanitized();
-        let evm_version = config.evm_version;
+        let evm_version =
+            crate::utils::evm_version(config.evm_version, explicit_evm_version, &evm_opts)?;
         if evm_opts.debug {
             evm_opts.verbosity = 3;
         }
@@ -98,8 +100,9 @@ impl Cmd for RunArgs {
         let bytecode = bytecode.expect("No bytecode").object.into_bytes().unwrap();
         let needs_setup = abi.functions().any(|func| func.name == "setUp");
 
-        let mut cfg = crate::utils::sputnik_cfg(&evm_version);
+        let mut cfg = crate::utils::sputnik_cfg(&evm_version)?;
         cfg.create_contract_limit = None;
+        evm_opts.env.check_gas_price(cfg.has_base_fee)?;
         let vicinity = evm_opts.vicinity()?;
         let backend = evm_opts.backend(&vicinity)?;
 
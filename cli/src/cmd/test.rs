@@ -166,6 +166,7 @@ impl Cmd for TestArgs {
         // merge all configs
         let figment: Figment = From::from(&self);
         let evm_opts = figment.extract::<EvmOpts>()?;
+        let explicit_evm_version = crate::utils::is_configured(&figment, "evm_version");
         let config = Config::from_provider(figment).sanitized();
 
         let TestArgs { json, filter, allow_failure, .. } = self;
@@ -185,8 +186,11 @@ impl Cmd for TestArgs {
         let project = config.project()?;
 
         // prepare the test builder
-        let mut evm_cfg = crate::utils::sputnik_cfg(&config.evm_version);
+        let evm_version =
+            crate::utils::evm_version(config.evm_version, explicit_evm_version, &evm_opts)?;
+        let mut evm_cfg = crate::utils::sputnik_cfg(&evm_version)?;
         evm_cfg.create_contract_limit = None;
+        evm_opts.env.check_gas_price(evm_cfg.has_base_fee)?;
 
         let builder = MultiContractRunnerBuilder::default()
             .fuzzer(fuzzer)
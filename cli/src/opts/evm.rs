@@ -1,6 +1,6 @@
 //! cli arguments for configuring the evm settings
 use clap::Parser;
-use ethers::types::{Address, U256};
+use ethers::types::{Address, H256, U256};
 use evm_adapters::evm_opts::EvmType;
 use foundry_config::{
     figment::{
@@ -12,6 +12,7 @@ use foundry_config::{
     Config,
 };
 use serde::Serialize;
+use std::path::PathBuf;
 
 // `EvmArgs` and `EnvArgs` take the highest precedence in the Config/Figment hierarchy.
 // All vars are opt-in, their default values are expected to be set by the
@@ -48,7 +49,11 @@ pub struct EvmArgs {
     )]
     pub evm_type: EvmType,
 
-    #[clap(help = "fetch state over a remote instead of starting from empty state", long, short)]
+    #[clap(
+        help = "fetch state over a remote instead of starting from empty state, from an http(s)://, ws(s):// or ipc:// url or an IPC socket path",
+        long,
+        short
+    )]
     #[clap(alias = "rpc-url")]
     #[serde(rename = "eth_rpc_url", skip_serializing_if = "Option::is_none")]
     pub fork_url: Option<String>,
@@ -57,6 +62,115 @@ pub struct EvmArgs {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fork_block_number: Option<u64>,
 
+    #[clap(help = "forks at the state right before this transaction was executed", long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_tx_hash: Option<H256>,
+
+    #[clap(help = "persists the fetched fork state to this file, requires a pinned block", long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_cache_path: Option<PathBuf>,
+
+    #[clap(
+        help = "persists the fetched state of each pinned fork to its own file in this directory",
+        long
+    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_cache_dir: Option<PathBuf>,
+
+    #[clap(
+        help = "an RPC endpoint to fail over to if the fork url fails, may be repeated",
+        long = "fork-fallback-url",
+        multiple_occurrences = true
+    )]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fork_fallback_urls: Vec<String>,
+
+    #[clap(help = "how often a failed request to the fork endpoints is retried", long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_retries: Option<u32>,
+
+    #[clap(help = "the delay in milliseconds before the first retry of a fork request", long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_retry_backoff: Option<u64>,
+
+    #[clap(
+        help = "a `name: value` header which is sent with every fork request, may be repeated",
+        long = "fork-header",
+        multiple_occurrences = true
+    )]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fork_headers: Vec<String>,
+
+    #[clap(help = "the maximum number of fork requests which are sent as one batch", long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_batch_size: Option<usize>,
+
+    #[clap(help = "how long in milliseconds a fork request waits for others to batch with", long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_batch_interval: Option<u64>,
+
+    #[clap(
+        help = "how long in seconds fetched fork state is cached, ignored if a block is pinned",
+        long
+    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_cache_ttl: Option<u64>,
+
+    #[clap(help = "forks the pending block, including the transaction pool of the node", long)]
+    #[serde(skip)]
+    pub fork_pending: bool,
+
+    #[clap(
+        help = "the maximum number of fetched accounts and storage slots which are cached",
+        long
+    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_cache_max_entries: Option<usize>,
+
+    #[clap(
+        help = "the maximum number of accounts, storage slots and blocks fetched from the fork, requests which need more fail",
+        long
+    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_request_budget: Option<usize>,
+
+    #[clap(help = "overrides the block.timestamp of the forked block", long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_block_timestamp: Option<u64>,
+
+    #[clap(
+        help = "overrides the block.number of the forked block, without changing the forked state",
+        long
+    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_env_block_number: Option<u64>,
+
+    #[clap(help = "overrides the block.basefee of the forked block", long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_block_base_fee: Option<u64>,
+
+    #[clap(
+        help = "whether the base fee of the forked block is used instead of the --block-base-fee-per-gas, defaults to true",
+        long
+    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_historical_basefee: Option<bool>,
+
+    #[clap(help = "appends every response of the fork endpoints to this fixture file", long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_record: Option<PathBuf>,
+
+    #[clap(
+        help = "answers all fork requests from this fixture file, without network access",
+        long
+    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_replay: Option<PathBuf>,
+
+    #[clap(help = "seeds the state with the accounts of a genesis or state dump file", long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<PathBuf>,
+
     #[clap(help = "the initial balance of each deployed test contract", long)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub initial_balance: Option<U256>,
@@ -107,6 +221,14 @@ impl Provider for EvmArgs {
             dict.insert("ffi".to_string(), self.ffi.into());
         }
 
+        if self.env.disable_base_fee {
+            dict.insert("disable_base_fee".to_string(), self.env.disable_base_fee.into());
+        }
+
+        if self.fork_pending {
+            dict.insert("fork_pending".to_string(), self.fork_pending.into());
+        }
+
         Ok(Map::from([(Config::selected_profile(), dict)]))
     }
 }
@@ -131,6 +253,17 @@ pub struct EnvArgs {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub block_base_fee_per_gas: Option<u64>,
 
+    #[clap(
+        help = "the priority fee paid on top of the base fee, overrides the tx.gasprice with their sum",
+        long
+    )]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_priority_fee: Option<u64>,
+
+    #[clap(help = "accepts a tx.gasprice below the base fee, the base fee is still burnt", long)]
+    #[serde(skip)]
+    pub disable_base_fee: bool,
+
     #[clap(help = "the tx.origin value during EVM execution", long)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tx_origin: Option<Address>,
@@ -2,6 +2,9 @@ use std::{future::Future, str::FromStr, time::Duration};
 
 use ethers::{solc::EvmVersion, types::U256};
 #[cfg(feature = "sputnik-evm")]
+use evm_adapters::evm_opts::EvmOpts;
+use foundry_config::figment::{Figment, Provider};
+#[cfg(feature = "sputnik-evm")]
 use sputnik::Config;
 
 // reexport all `foundry_config::utils`
@@ -28,14 +31,34 @@ pub fn subscriber() {
         .init();
 }
 
+/// Returns the sputnik config of the EVM version, see
+/// [spec_config](evm_adapters::sputnik::spec_config)
+#[cfg(feature = "sputnik-evm")]
+pub fn sputnik_cfg(evm: &EvmVersion) -> eyre::Result<Config> {
+    evm_adapters::sputnik::spec_config(*evm).map(Clone::clone)
+}
+
+/// Returns whether the value of `key` was provided by the user, rather than being a default of
+/// the [foundry_config::Config]
+pub fn is_configured(figment: &Figment, key: &str) -> bool {
+    let defaults = Provider::metadata(&foundry_config::Config::default());
+    figment.find_metadata(key).map(|meta| meta.name != defaults.name).unwrap_or_default()
+}
+
+/// Returns the EVM version to execute with.
+///
+/// When forking at a pinned block, the version which was active at that block is used, unless
+/// one was configured explicitly.
 #[cfg(feature = "sputnik-evm")]
-pub fn sputnik_cfg(evm: &EvmVersion) -> Config {
-    match evm {
-        EvmVersion::Istanbul => Config::istanbul(),
-        EvmVersion::Berlin => Config::berlin(),
-        EvmVersion::London => Config::london(),
-        _ => panic!("Unsupported EVM version"),
+pub fn evm_version(
+    evm_version: EvmVersion,
+    explicit: bool,
+    evm_opts: &EvmOpts,
+) -> eyre::Result<EvmVersion> {
+    if explicit {
+        return Ok(evm_version)
     }
+    Ok(evm_opts.fork_evm_version()?.unwrap_or(evm_version))
 }
 
 /// Securely reads a secret from stdin, or proceeds to return a fallback value
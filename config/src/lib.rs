@@ -14,7 +14,7 @@ pub use figment;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 
-use ethers_core::types::{Address, U256};
+use ethers_core::types::{Address, H256, U256};
 pub use ethers_solc::artifacts::OptimizerDetails;
 use ethers_solc::{
     artifacts::{output_selection::ContractOutputSelection, Optimizer, Settings},
@@ -136,6 +136,50 @@ pub struct Config {
     pub block_number: u64,
     /// pins the block number for the state fork
     pub fork_block_number: Option<u64>,
+    /// forks at the state right before this transaction was executed in its block
+    pub fork_tx_hash: Option<H256>,
+    /// the file the fetched state of a pinned fork is persisted to
+    pub fork_cache_path: Option<PathBuf>,
+    /// the directory the fetched state of each pinned fork is persisted to in a file of its own,
+    /// see [`Self::foundry_rpc_cache_dir()`] for a directory shared by all projects
+    pub fork_cache_dir: Option<PathBuf>,
+    /// RPC endpoints which are used in order if the `eth_rpc_url` fails
+    pub fork_fallback_urls: Vec<String>,
+    /// how often a failed request to the fork endpoints is retried
+    pub fork_retries: u32,
+    /// the delay in milliseconds before the first retry, doubled with every further retry
+    pub fork_retry_backoff: u64,
+    /// headers in the `name: value` format which are sent with every fork request
+    pub fork_headers: Vec<String>,
+    /// the maximum number of concurrent fork requests which are sent as a single batch
+    pub fork_batch_size: usize,
+    /// how long in milliseconds a fork request waits for other requests to batch with
+    pub fork_batch_interval: u64,
+    /// how long in seconds fetched fork state is cached, only used if no block is pinned
+    pub fork_cache_ttl: Option<u64>,
+    /// whether the state of the pending block is forked, only used if no block is pinned
+    pub fork_pending: bool,
+    /// the maximum number of fetched fork accounts and storage slots which are cached
+    pub fork_cache_max_entries: Option<usize>,
+    /// the maximum number of accounts, storage slots and blocks fetched from the fork, requests
+    /// which need more fail
+    pub fork_request_budget: Option<usize>,
+    /// overrides the block.timestamp of the forked block
+    pub fork_block_timestamp: Option<u64>,
+    /// overrides the block.number of the forked block, the state is still fetched from the
+    /// forked block
+    pub fork_env_block_number: Option<u64>,
+    /// overrides the block.basefee of the forked block
+    pub fork_block_base_fee: Option<u64>,
+    /// whether the base fee of the forked block is used instead of the one of the env, defaults to
+    /// true
+    pub use_historical_basefee: Option<bool>,
+    /// the fixture file every response of the fork endpoints is appended to
+    pub fork_record: Option<PathBuf>,
+    /// the fixture file all fork requests are answered from, without any network access
+    pub fork_replay: Option<PathBuf>,
+    /// the genesis or state dump file to seed the state with
+    pub state_file: Option<PathBuf>,
     /// the chainid opcode value
     pub chain_id: Option<Chain>,
     /// Block gas limit
@@ -144,6 +188,11 @@ pub struct Config {
     pub gas_price: u64,
     /// the base fee in a block
     pub block_base_fee_per_gas: u64,
+    /// the priority fee paid to the coinbase, if set the `tx.gasprice` is the base fee plus this
+    /// instead of `gas_price`
+    pub gas_priority_fee: Option<u64>,
+    /// whether a `tx.gasprice` below the base fee is accepted
+    pub disable_base_fee: bool,
     /// the `block.coinbase` value during EVM execution
     pub block_coinbase: Address,
     /// the `block.timestamp` value during EVM execution
@@ -662,6 +711,12 @@ impl Config {
         dirs_next::home_dir().map(|p| p.join(Config::FOUNDRY_DIR_NAME))
     }
 
+    /// Returns the path to the cache dir of fork state `~/.foundry/cache/rpc`, each fork is
+    /// cached at `<chain id>/<block number>` within it
+    pub fn foundry_rpc_cache_dir() -> Option<PathBuf> {
+        Self::foundry_dir().map(|p| p.join("cache").join("rpc"))
+    }
+
     /// Returns the path to the `foundry.toml` file, the file is searched for in
     /// the current working directory and all parent directories until the root,
     /// and the first hit is used.
@@ -815,12 +870,34 @@ impl Default for Config {
             initial_balance: U256::from(0xffffffffffffffffffffffffu128),
             block_number: 0,
             fork_block_number: None,
+            fork_tx_hash: None,
+            fork_cache_path: None,
+            fork_cache_dir: None,
+            fork_fallback_urls: vec![],
+            fork_retries: 0,
+            fork_retry_backoff: 500,
+            fork_headers: vec![],
+            fork_batch_size: 1,
+            fork_batch_interval: 5,
+            fork_cache_ttl: None,
+            fork_pending: false,
+            fork_cache_max_entries: None,
+            fork_request_budget: None,
+            fork_block_timestamp: None,
+            fork_env_block_number: None,
+            fork_block_base_fee: None,
+            use_historical_basefee: None,
+            fork_record: None,
+            fork_replay: None,
+            state_file: None,
             chain_id: None,
             // toml-rs can't handle larger number because integers are stored signed
             // https://github.com/alexcrichton/toml-rs/issues/256
             gas_limit: i64::MAX as u64,
             gas_price: 0,
             block_base_fee_per_gas: 0,
+            gas_priority_fee: None,
+            disable_base_fee: false,
             block_coinbase: Address::zero(),
             block_timestamp: 0,
             block_difficulty: 0,
@@ -2,8 +2,10 @@ use ethers::{
     abi::{Abi, Event, Function, RawLog, Token},
     types::{H160, H256, U256},
 };
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
 
 use ansi_term::Colour;
 
@@ -135,6 +137,8 @@ impl CallTraceArena {
                 };
                 self.arena.push(node);
                 self.arena[entry].children.push(new_trace.idx);
+                new_trace.reentrant = first_entry_depth(self, new_trace.idx).is_some();
+                self.arena[new_trace.idx].trace.reentrant = new_trace.reentrant;
             }
             // we haven't found the parent node, go deeper
             _ => self.push_trace(
@@ -256,6 +260,18 @@ impl CallTraceArena {
         #[cfg(not(feature = "sputnik"))]
         let color = if trace.success { Colour::Green } else { Colour::Red };
 
+        if trace.kind == CallKind::SelfDestruct {
+            full_str.push_str(&*format!(
+                "\n{}[0] {}::{}{{value: {}}}({:?})",
+                left,
+                color.paint(trace.label.clone().unwrap_or_else(|| trace.addr.to_string())),
+                color.paint("selfdestruct"),
+                trace.value,
+                trace.beneficiary.unwrap_or_default()
+            ));
+            return
+        }
+
         // we have to clone the name and abi because identified_contracts is later borrowed
         // immutably
         let res = if let Some((name, abi)) = exec_info.identified_contracts.get(&trace.addr) {
@@ -460,6 +476,43 @@ pub enum LogCallOrder {
     Call(usize),
 }
 
+/// The kind of call that created a call frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CallKind {
+    Call,
+    StaticCall,
+    CallCode,
+    DelegateCall,
+    Create,
+    Create2,
+    /// Not a call frame but the `SELFDESTRUCT` of the account, which sends the whole balance to
+    /// the beneficiary
+    SelfDestruct,
+}
+
+impl Default for CallKind {
+    fn default() -> Self {
+        CallKind::Call
+    }
+}
+
+impl CallKind {
+    /// Whether this frame deployed a new contract
+    pub fn is_create(&self) -> bool {
+        matches!(self, CallKind::Create | CallKind::Create2)
+    }
+
+    /// The annotation appended to a rendered call which doesn't execute in its own context
+    fn annotation(&self) -> &'static str {
+        match self {
+            CallKind::StaticCall => " [staticcall]",
+            CallKind::CallCode => " [callcode]",
+            CallKind::DelegateCall => " [delegatecall]",
+            _ => "",
+        }
+    }
+}
+
 /// Call trace of a tx
 #[derive(Clone, Default, Debug, Deserialize, Serialize)]
 pub struct CallTrace {
@@ -474,6 +527,9 @@ pub struct CallTrace {
     pub addr: H160,
     /// Creation
     pub created: bool,
+    /// The kind of call, delegatecalls and staticcalls are nested under their parent like any
+    /// other call
+    pub kind: CallKind,
     /// Ether value transfer
     pub value: U256,
     /// Call data, including function selector (if applicable)
@@ -482,6 +538,13 @@ pub struct CallTrace {
     pub cost: u64,
     /// Output
     pub output: Vec<u8>,
+    /// The account which received the balance, if this is a selfdestruct
+    #[serde(default)]
+    pub beneficiary: Option<H160>,
+    /// Whether the call entered a contract which was already on the call stack, see
+    /// [reentrant_calls]
+    #[serde(default)]
+    pub reentrant: bool,
 }
 
 impl CallTrace {
@@ -528,7 +591,7 @@ impl CallTrace {
                 }
 
                 full_str.push_str(&*format!(
-                    "\n{}[{}] {}::{}{}({})",
+                    "\n{}[{}] {}::{}{}({}){}",
                     left,
                     self.cost,
                     color.paint(
@@ -543,6 +606,7 @@ impl CallTrace {
                         "".to_string()
                     },
                     strings,
+                    self.kind.annotation(),
                 ));
 
                 if !self.output.is_empty() && self.success {
@@ -566,7 +630,7 @@ impl CallTrace {
         } else {
             // fallback function
             full_str.push_str(&*format!(
-                "\n{}[{}] {}::fallback{}()",
+                "\n{}[{}] {}::fallback{}(){}",
                 left,
                 self.cost,
                 color.paint(
@@ -578,7 +642,8 @@ impl CallTrace {
                     format!("{{value: {}}}", self.value)
                 } else {
                     "".to_string()
-                }
+                },
+                self.kind.annotation(),
             ));
 
             if !self.success {
@@ -593,7 +658,7 @@ impl CallTrace {
 
         // We couldn't decode the function call, so print it as an abstract call
         full_str.push_str(&*format!(
-            "\n{}[{}] {}::{}{}({})",
+            "\n{}[{}] {}::{}{}({}){}",
             left,
             self.cost,
             color.paint(self.label.as_ref().unwrap_or(&self.addr.to_string()).to_string()),
@@ -612,6 +677,7 @@ impl CallTrace {
             } else {
                 hex::encode(&vec![][..])
             },
+            self.kind.annotation(),
         ));
 
         if !self.success {
@@ -625,6 +691,484 @@ impl CallTrace {
     }
 }
 
+/// Functions which are labeled in traces even without an ABI
+static COMMON_ABI: Lazy<Abi> = Lazy::new(|| {
+    ethers::abi::parse_abi(&[
+        "function transfer(address to, uint256 amount) returns (bool)",
+        "function transferFrom(address from, address to, uint256 amount) returns (bool)",
+        "function approve(address spender, uint256 amount) returns (bool)",
+        "function balanceOf(address owner) returns (uint256)",
+        "function allowance(address owner, address spender) returns (uint256)",
+    ])
+    .expect("valid abi")
+});
+
+/// The name of the precompile at the given address, if there is one
+fn precompile_name(addr: &H160) -> Option<&'static str> {
+    if addr[..19].iter().any(|byte| *byte != 0) {
+        return None
+    }
+    Some(match addr[19] {
+        1 => "ecrecover",
+        2 => "sha256",
+        3 => "ripemd160",
+        4 => "identity",
+        5 => "modexp",
+        6 => "ecadd",
+        7 => "ecmul",
+        8 => "ecpairing",
+        9 => "blake2f",
+        _ => return None,
+    })
+}
+
+/// How [format_trace] renders a call tree
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceFormatConfig<'a> {
+    /// Colors successful calls green, failed ones red and cheatcodes blue
+    pub color: bool,
+    /// Calls nested deeper than this are left out, the top level call has depth 0
+    pub max_depth: Option<usize>,
+    /// The ABI to decode calls, outputs, logs and custom errors with
+    pub abi: Option<&'a Abi>,
+    /// Names of addresses which take precedence over the labels recorded in the trace, e.g. the
+    /// labels set after the trace was recorded
+    pub labels: Option<&'a BTreeMap<H160, String>>,
+}
+
+impl<'a> TraceFormatConfig<'a> {
+    fn paint(&self, colour: Colour, s: impl AsRef<str>) -> String {
+        if self.color {
+            colour.paint(s.as_ref()).to_string()
+        } else {
+            s.as_ref().to_string()
+        }
+    }
+
+    /// The name of the address, unlabeled addresses are shortened
+    fn name(&self, trace: &CallTrace) -> String {
+        self.labels
+            .and_then(|labels| labels.get(&trace.addr).cloned())
+            .or_else(|| trace.label.clone())
+            .or_else(|| precompile_name(&trace.addr).map(str::to_string))
+            .unwrap_or_else(|| trace.addr.to_string())
+    }
+
+    fn format_tokens(&self, tokens: &[Token]) -> String {
+        tokens
+            .iter()
+            .map(|token| match (token, self.labels) {
+                (Token::Address(addr), Some(labels)) if labels.contains_key(addr) => {
+                    format!("{} [{:?}]", labels[addr], addr)
+                }
+                _ => format_token(token),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The function of the ABIs with the selector of the call
+    fn function(&self, trace: &CallTrace) -> Option<&'a Function> {
+        if trace.data.len() < 4 {
+            return None
+        }
+        self.abis(&trace.addr).into_iter().find_map(|abi| {
+            abi.functions().find(|func| func.short_signature()[..] == trace.data[..4])
+        })
+    }
+
+    /// The name of the call frame, `name::function` or `name::selector` if the function is not
+    /// known
+    fn frame_name(&self, trace: &CallTrace) -> String {
+        let name = self.name(trace);
+        if trace.kind == CallKind::SelfDestruct {
+            return format!("{}::selfdestruct", name)
+        }
+        if trace.created {
+            return format!("new {}", name)
+        }
+        if let Some(func) = self.function(trace) {
+            return format!("{}::{}", name, func.name)
+        }
+        if precompile_name(&trace.addr).is_some() {
+            return name
+        }
+        match trace.data.get(..4).unwrap_or(&trace.data) {
+            [] => format!("{}::fallback", name),
+            selector => format!("{}::0x{}", name, hex::encode(selector)),
+        }
+    }
+
+    /// The ABIs which may contain the function called at the address, in order of precedence
+    fn abis(&self, addr: &H160) -> Vec<&'a Abi> {
+        let mut abis = Vec::with_capacity(3);
+        #[cfg(feature = "sputnik")]
+        if *addr == *CHEATCODE_ADDRESS {
+            abis.push(&*HEVM_ABI);
+        } else if *addr == *CONSOLE_ADDRESS {
+            abis.push(&*CONSOLE_ABI);
+        }
+        #[cfg(not(feature = "sputnik"))]
+        let _ = addr;
+        abis.extend(self.abi);
+        abis.push(&*COMMON_ABI);
+        abis
+    }
+}
+
+/// Renders the call tree of the arena as text, e.g. for `-vvvv` output.
+///
+/// Every call is shown with the gas it used, functions and their arguments are decoded with the
+/// ABI of the config, or the ABIs of the cheatcodes, precompiles and common ERC20 functions.
+/// Unlike [CallTraceArena::construct_trace_string] this does not need the executor to identify
+/// contracts, addresses are named after their label if they have one.
+///
+/// The labels of the config also apply to traces which were recorded before the addresses were
+/// labeled, e.g. when passing the current labels of the executor.
+pub fn format_trace(arena: &CallTraceArena, config: &TraceFormatConfig<'_>) -> String {
+    let mut out = String::new();
+    format_node(arena, arena.entry, config, "", &mut out);
+    out
+}
+
+/// Renders the call trees of the arenas in the Trace Event Format of `chrome://tracing` and
+/// Perfetto, one complete event per call frame, named like in [format_trace].
+///
+/// The timeline is synthetic: a frame lasts as long as the gas it used, and the gas it used
+/// itself is spread evenly around its nested calls, which are shown within it. The arenas are
+/// laid out one after the other, arenas without any trace are left out.
+pub fn chrome_trace(arenas: &[CallTraceArena], config: &TraceFormatConfig<'_>) -> String {
+    let mut events = Vec::new();
+    let mut ts = 0;
+    for arena in arenas.iter().filter(|arena| is_recorded(arena)) {
+        ts += chrome_events(arena, arena.entry, ts, config, &mut events);
+    }
+    json!({ "traceEvents": events, "displayTimeUnit": "ns" }).to_string()
+}
+
+/// Whether the arena has any trace, the arena of the next transaction is already there but stays
+/// empty until it runs
+fn is_recorded(arena: &CallTraceArena) -> bool {
+    let entry = &arena.arena[arena.entry];
+    !entry.children.is_empty() || entry.trace.cost > 0 || !entry.trace.addr.is_zero()
+}
+
+/// Which contract the gas of a delegatecall or callcode is attributed to in [gas_by_contract]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GasAttribution {
+    /// The contract whose code runs, e.g. the implementation behind a proxy
+    Code,
+    /// The contract whose storage the code runs on, e.g. the proxy
+    Storage,
+}
+
+impl Default for GasAttribution {
+    fn default() -> Self {
+        GasAttribution::Code
+    }
+}
+
+/// The gas the frames of each contract used in all arenas, like the self time of a profiler: the
+/// gas of a frame doesn't include the gas of its nested calls, which is attributed to the called
+/// contracts, so the gas of all contracts adds up to the gas of the top level calls.
+pub fn gas_by_contract(
+    arenas: &[CallTraceArena],
+    attribution: GasAttribution,
+) -> HashMap<H160, u64> {
+    let mut gas = HashMap::new();
+    for arena in arenas.iter().filter(|arena| is_recorded(arena)) {
+        for (idx, node) in arena.arena.iter().enumerate() {
+            if node.trace.kind == CallKind::SelfDestruct {
+                continue
+            }
+            let nested: u64 =
+                node.children.iter().map(|child| arena.arena[*child].trace.cost).sum();
+            let address = attributed_address(arena, idx, attribution);
+            *gas.entry(address).or_default() += node.trace.cost.saturating_sub(nested);
+        }
+    }
+    gas
+}
+
+/// The contract the gas of the frame is attributed to
+fn attributed_address(arena: &CallTraceArena, idx: usize, attribution: GasAttribution) -> H160 {
+    let mut node = &arena.arena[idx];
+    if attribution == GasAttribution::Storage {
+        // the storage of a delegatecall is the one of its caller, which may delegate as well
+        while matches!(node.trace.kind, CallKind::DelegateCall | CallKind::CallCode) {
+            match node.parent {
+                Some(parent) => node = &arena.arena[parent],
+                None => break,
+            }
+        }
+    }
+    node.trace.addr
+}
+
+/// A call which entered a contract that was already on the call stack, found by
+/// [reentrant_calls]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReentrancyEvent {
+    /// The re-entered contract
+    pub address: H160,
+    /// The depth of the outermost frame of the contract on the call stack
+    pub first_depth: usize,
+    /// The depth of the call which re-entered it
+    pub reentry_depth: usize,
+    /// The index of the arena of the call in the arenas which were searched
+    pub arena: usize,
+    /// The index of the node of the call in its arena
+    pub node: usize,
+}
+
+/// The calls in all arenas which entered a contract whose frame hadn't returned yet, like a token
+/// calling back into the vault which is transferring it. This doesn't tell whether the reentrancy
+/// is exploitable.
+///
+/// A contract is on the call stack while a frame runs on its storage, so a delegatecall into a
+/// library is not an entry of the library, nor a re-entry of the caller, but a call out of the
+/// library back into the caller is a re-entry. Calls of a contract to itself are re-entries as
+/// well.
+pub fn reentrant_calls(arenas: &[CallTraceArena]) -> Vec<ReentrancyEvent> {
+    let mut events = Vec::new();
+    for (arena_idx, arena) in arenas.iter().enumerate().filter(|(_, arena)| is_recorded(arena)) {
+        for (idx, node) in arena.arena.iter().enumerate() {
+            if let Some(first_depth) = first_entry_depth(arena, idx) {
+                events.push(ReentrancyEvent {
+                    address: node.trace.addr,
+                    first_depth,
+                    reentry_depth: node.trace.depth,
+                    arena: arena_idx,
+                    node: idx,
+                });
+            }
+        }
+    }
+    events
+}
+
+/// The depth of the outermost frame of the contract a call enters on the call stack, if the call
+/// re-enters it
+fn first_entry_depth(arena: &CallTraceArena, idx: usize) -> Option<usize> {
+    let node = &arena.arena[idx];
+    if !matches!(node.trace.kind, CallKind::Call | CallKind::StaticCall) {
+        return None
+    }
+    let mut first_depth = None;
+    let mut parent = node.parent;
+    while let Some(ancestor) = parent {
+        if attributed_address(arena, ancestor, GasAttribution::Storage) == node.trace.addr {
+            first_depth = Some(arena.arena[ancestor].trace.depth);
+        }
+        parent = arena.arena[ancestor].parent;
+    }
+    first_depth
+}
+
+/// Adds the events of the node and its children, starting at `start`, and returns the duration
+/// of the node
+fn chrome_events(
+    arena: &CallTraceArena,
+    idx: usize,
+    start: u64,
+    config: &TraceFormatConfig<'_>,
+    events: &mut Vec<Value>,
+) -> u64 {
+    let node = &arena.arena[idx];
+    let trace = &node.trace;
+    let event = events.len();
+    events.push(json!({
+        "name": config.frame_name(trace),
+        "cat": format!("{:?}", trace.kind),
+        "ph": "X",
+        "ts": start,
+        "pid": 1,
+        "tid": 1,
+        "args": {
+            "address": format!("{:?}", trace.addr),
+            "gas": trace.cost,
+            "success": trace.success,
+            "value": trace.value.to_string(),
+        },
+    }));
+
+    let nested: u64 = node.children.iter().map(|child| arena.arena[*child].trace.cost).sum();
+    let gap = trace.cost.saturating_sub(nested) / (node.children.len() as u64 + 1);
+    let mut ts = start + gap;
+    for child in &node.children {
+        ts += chrome_events(arena, *child, ts, config, events) + gap;
+    }
+    // the children are never longer than the gas the frame used, unless they used more gas than
+    // the frame itself recorded
+    let duration = trace.cost.max(ts - start);
+    events[event]["dur"] = duration.into();
+    duration
+}
+
+fn format_node(
+    arena: &CallTraceArena,
+    idx: usize,
+    config: &TraceFormatConfig<'_>,
+    left: &str,
+    out: &mut String,
+) {
+    let node = &arena.arena[idx];
+    let trace = &node.trace;
+
+    let colour = if is_cheatcode(&trace.addr) {
+        Colour::Blue
+    } else if trace.success {
+        Colour::Green
+    } else {
+        Colour::Red
+    };
+    let name = config.name(trace);
+    let value =
+        if trace.value.is_zero() { String::new() } else { format!("{{value: {}}}", trace.value) };
+
+    // selfdestructs have no output, the balance is all they move
+    if trace.kind == CallKind::SelfDestruct {
+        let beneficiary = Token::Address(trace.beneficiary.unwrap_or_default());
+        out.push_str(&format!(
+            "\n{}[{}] {}::{}{{value: {}}}({})",
+            left,
+            trace.cost,
+            config.paint(colour, &name),
+            config.paint(colour, "selfdestruct"),
+            trace.value,
+            config.format_tokens(&[beneficiary])
+        ));
+        return
+    }
+
+    let func = config.function(trace);
+    if trace.created {
+        out.push_str(&format!(
+            "\n{}[{}] {} {}",
+            left,
+            trace.cost,
+            config.paint(Colour::Yellow, "→ new"),
+            config.paint(colour, &name)
+        ));
+    } else if let Some(func) = func {
+        let args = match func.decode_input(&trace.data[4..]) {
+            Ok(tokens) => config.format_tokens(&tokens),
+            Err(_) => format!("0x{}", hex::encode(&trace.data[4..])),
+        };
+        out.push_str(&format!(
+            "\n{}[{}] {}::{}{}({}){}",
+            left,
+            trace.cost,
+            config.paint(colour, &name),
+            config.paint(colour, &func.name),
+            value,
+            args,
+            trace.kind.annotation()
+        ));
+    } else if precompile_name(&trace.addr).is_some() {
+        out.push_str(&format!(
+            "\n{}[{}] {}(0x{}){}",
+            left,
+            trace.cost,
+            config.paint(colour, &name),
+            hex::encode(&trace.data),
+            trace.kind.annotation()
+        ));
+    } else {
+        let (selector, args) = trace.data.split_at(trace.data.len().min(4));
+        let selector = if selector.is_empty() {
+            "fallback".to_string()
+        } else {
+            format!("0x{}", hex::encode(selector))
+        };
+        out.push_str(&format!(
+            "\n{}[{}] {}::{}{}({}){}",
+            left,
+            trace.cost,
+            config.paint(colour, &name),
+            config.paint(colour, selector),
+            value,
+            if args.is_empty() { String::new() } else { format!("0x{}", hex::encode(args)) },
+            trace.kind.annotation()
+        ));
+    }
+
+    let nested = left.replace("├─", "│").replace("└─", "  ");
+    let too_deep = config.max_depth.map(|max| trace.depth >= max).unwrap_or_default();
+    if too_deep && !node.children.is_empty() {
+        out.push_str(&format!("\n{}  ├─ ... {} nested calls", nested, node.children.len()));
+    }
+    for ordering in &node.ordering {
+        match ordering {
+            LogCallOrder::Log(index) => out.push_str(&format!(
+                "\n{}  ├─ {}",
+                nested,
+                format_log(&node.logs[*index], config)
+            )),
+            LogCallOrder::Call(_) if too_deep => {}
+            LogCallOrder::Call(index) => {
+                format_node(arena, node.children[*index], config, &format!("{}  ├─ ", nested), out)
+            }
+        }
+    }
+
+    let output = if trace.created && trace.success {
+        format!("{} bytes of code", trace.output.len())
+    } else if !trace.success {
+        foundry_utils::decode_revert(&trace.output, config.abi)
+            .unwrap_or_else(|_| format_bytes(&trace.output))
+    } else {
+        func.and_then(|func| func.decode_output(&trace.output).ok())
+            .map(|tokens| config.format_tokens(&tokens))
+            .filter(|output| !output.is_empty())
+            .unwrap_or_else(|| format_bytes(&trace.output))
+    };
+    out.push_str(&format!("\n{}  └─ {} {}", nested, config.paint(colour, "←"), output));
+}
+
+fn format_log(log: &RawLog, config: &TraceFormatConfig<'_>) -> String {
+    let event = log.topics.first().and_then(|topic0| {
+        config.abi?.events().find(|event| event.signature() == *topic0 && !event.anonymous)
+    });
+    if let Some(event) = event {
+        if let Ok(parsed) = event.parse_log(log.clone()) {
+            let params = parsed
+                .params
+                .iter()
+                .map(|param| {
+                    format!("{}: {}", param.name, config.format_tokens(&[param.value.clone()]))
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            return format!("emit {}({})", config.paint(Colour::Cyan, &event.name), params)
+        }
+    }
+    let topics = log.topics.iter().map(|topic| format!("{:?}", topic)).collect::<Vec<_>>();
+    format!(
+        "emit topics: [{}], data: {}",
+        config.paint(Colour::Cyan, topics.join(", ")),
+        config.paint(Colour::Cyan, format_bytes(&log.data))
+    )
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        "()".to_string()
+    } else {
+        format!("0x{}", hex::encode(bytes))
+    }
+}
+
+#[cfg(feature = "sputnik")]
+fn is_cheatcode(addr: &H160) -> bool {
+    *addr == *CHEATCODE_ADDRESS
+}
+
+#[cfg(not(feature = "sputnik"))]
+fn is_cheatcode(_: &H160) -> bool {
+    false
+}
+
 // very simple fuzzy matching to account for immutables. Will fail for small contracts that are
 // basically all immutable vars
 fn diff_score(bytecode1: &[u8], bytecode2: &[u8]) -> f64 {
@@ -645,3 +1189,188 @@ fn diff_score(bytecode1: &[u8], bytecode2: &[u8]) -> f64 {
     // println!("diff_score {}", diff_chars as f64 / cutoff_len as f64);
     diff_chars as f64 / cutoff_len as f64
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::Tokenizable;
+
+    fn node(idx: usize, parent: Option<usize>, trace: CallTrace) -> CallTraceNode {
+        CallTraceNode { parent, idx, trace: CallTrace { idx, ..trace }, ..Default::default() }
+    }
+
+    fn sample_arena() -> CallTraceArena {
+        let token = H160::repeat_byte(1);
+        let to = H160::repeat_byte(2);
+        let mut data = COMMON_ABI.function("transfer").unwrap().short_signature().to_vec();
+        data.extend(ethers::abi::encode(&[to.into_token(), U256::from(5).into_token()]));
+        let mut root = node(
+            0,
+            None,
+            CallTrace {
+                success: true,
+                addr: token,
+                label: Some("Token".to_string()),
+                data,
+                cost: 30000,
+                output: ethers::abi::encode(&[Token::Bool(true)]),
+                ..Default::default()
+            },
+        );
+        let sha256 = node(
+            1,
+            Some(0),
+            CallTrace {
+                depth: 1,
+                success: true,
+                addr: H160::from_low_u64_be(2),
+                data: vec![0xab],
+                cost: 72,
+                ..Default::default()
+            },
+        );
+        let mut nested = node(
+            2,
+            Some(0),
+            CallTrace {
+                depth: 1,
+                addr: to,
+                data: vec![1, 2, 3, 4],
+                cost: 10,
+                ..Default::default()
+            },
+        );
+        nested.children.push(3);
+        nested.ordering.push(LogCallOrder::Call(0));
+        let deep = node(3, Some(2), CallTrace { depth: 2, addr: to, ..Default::default() });
+        let destruct = node(
+            4,
+            Some(0),
+            CallTrace {
+                depth: 1,
+                success: true,
+                addr: token,
+                label: Some("Token".to_string()),
+                kind: CallKind::SelfDestruct,
+                value: 3.into(),
+                beneficiary: Some(to),
+                ..Default::default()
+            },
+        );
+        root.children = vec![1, 2, 4];
+        root.ordering = vec![LogCallOrder::Call(0), LogCallOrder::Call(1), LogCallOrder::Call(2)];
+        CallTraceArena { arena: vec![root, sha256, nested, deep, destruct], entry: 0 }
+    }
+
+    #[test]
+    fn can_format_trace() {
+        let token = H160::repeat_byte(1);
+        let to = H160::repeat_byte(2);
+        let arena = sample_arena();
+
+        let out = format_trace(&arena, &TraceFormatConfig::default());
+        assert!(out.contains(&format!("[30000] Token::transfer({:?}, 5)", to)), "{}", out);
+        assert!(out.contains("  ├─ [72] sha256(0xab)"), "{}", out);
+        assert!(out.contains("::0x01020304()"), "{}", out);
+        assert!(out.contains("  └─ ← true"), "{}", out);
+        assert!(out.contains("::fallback()"), "{}", out);
+        assert!(out.contains(&format!("[0] Token::selfdestruct{{value: 3}}({:?})", to)), "{}", out);
+        assert!(!out.contains('\u{1b}'), "{}", out);
+
+        let config = TraceFormatConfig { max_depth: Some(1), color: true, ..Default::default() };
+        let out = format_trace(&arena, &config);
+        assert!(out.contains("... 1 nested calls"), "{}", out);
+        assert!(!out.contains("fallback"), "{}", out);
+        assert!(out.contains(&Colour::Red.paint("←").to_string()), "{}", out);
+
+        // unlabeled addresses are shortened, labels apply retroactively and take precedence
+        assert!(out.contains(&format!("{}::0x01020304", to)), "{}", out);
+        let mut labels = BTreeMap::new();
+        labels.insert(to, "alice".to_string());
+        labels.insert(token, "WETH".to_string());
+        let config = TraceFormatConfig { labels: Some(&labels), ..Default::default() };
+        let out = format_trace(&arena, &config);
+        assert!(out.contains(&format!("WETH::transfer(alice [{:?}], 5)", to)), "{}", out);
+        assert!(out.contains("alice::0x01020304()"), "{}", out);
+    }
+
+    #[test]
+    fn can_attribute_gas_to_contracts() {
+        let token = H160::repeat_byte(1);
+        let to = H160::repeat_byte(2);
+        let sha256 = H160::from_low_u64_be(2);
+        let mut arena = sample_arena();
+        let arenas = [arena.clone(), CallTraceArena::default()];
+        let gas = gas_by_contract(&arenas, GasAttribution::Code);
+        let expected: HashMap<_, _> =
+            vec![(token, 30000 - 72 - 10), (sha256, 72), (to, 10)].into_iter().collect();
+        assert_eq!(gas, expected);
+        assert_eq!(gas, gas_by_contract(&arenas, GasAttribution::Storage));
+
+        // the token delegates to `to`, which calls itself
+        arena.arena[2].trace.kind = CallKind::DelegateCall;
+        let arenas = [arena];
+        let gas = gas_by_contract(&arenas, GasAttribution::Code);
+        assert_eq!(gas[&to], 10);
+        let gas = gas_by_contract(&arenas, GasAttribution::Storage);
+        assert_eq!((gas[&token], gas[&to]), (30000 - 72, 0));
+    }
+
+    #[test]
+    fn can_find_reentrant_calls() {
+        let token = H160::repeat_byte(1);
+        let to = H160::repeat_byte(2);
+        let mut arena = sample_arena();
+        // `to` calls itself
+        let arenas = [CallTraceArena::default(), arena.clone()];
+        let expected =
+            ReentrancyEvent { address: to, first_depth: 1, reentry_depth: 2, arena: 1, node: 3 };
+        assert_eq!(reentrant_calls(&arenas), vec![expected]);
+
+        // the delegatecalls of the token into the `to` library run on the token
+        arena.arena[2].trace.kind = CallKind::DelegateCall;
+        arena.arena[3].trace.kind = CallKind::DelegateCall;
+        assert!(reentrant_calls(&[arena.clone()]).is_empty());
+
+        // and a call of the library back into the token re-enters it
+        arena.arena[3].trace.kind = CallKind::StaticCall;
+        arena.arena[3].trace.addr = token;
+        let expected =
+            ReentrancyEvent { address: token, first_depth: 0, reentry_depth: 2, arena: 0, node: 3 };
+        assert_eq!(reentrant_calls(&[arena]), vec![expected]);
+    }
+
+    #[test]
+    fn can_export_chrome_trace() {
+        let arena = sample_arena();
+        let out =
+            chrome_trace(&[arena.clone(), CallTraceArena::default(), arena], &Default::default());
+        let trace: Value = serde_json::from_str(&out).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        // the empty arena is left out
+        assert_eq!(events.len(), 10);
+
+        let names: Vec<_> = events.iter().map(|event| event["name"].as_str().unwrap()).collect();
+        assert_eq!(names[0], "Token::transfer");
+        assert_eq!(names[1], "sha256");
+        assert!(names[2].ends_with("::0x01020304"), "{}", names[2]);
+        assert!(names[3].ends_with("::fallback"), "{}", names[3]);
+        assert_eq!(names[4], "Token::selfdestruct");
+
+        let span = |event: &Value| {
+            let ts = event["ts"].as_u64().unwrap();
+            (ts, ts + event["dur"].as_u64().unwrap())
+        };
+        let root = span(&events[0]);
+        assert_eq!(root, (0, 30000));
+        for (event, parent) in [(1, 0), (2, 0), (3, 2), (4, 0)] {
+            let (start, end) = span(&events[event]);
+            let (parent_start, parent_end) = span(&events[parent]);
+            assert!(parent_start <= start && end <= parent_end, "{} not in {}", event, parent);
+        }
+        // the nested calls don't overlap
+        assert!(span(&events[1]).1 <= span(&events[2]).0);
+        // the next arena starts after the first one
+        assert_eq!(span(&events[5]), (30000, 60000));
+    }
+}
@@ -1,6 +1,9 @@
-use ethers::types::{Address, U256};
+use ethers::{
+    types::{Address, H256, U256},
+    utils::keccak256,
+};
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::{path::PathBuf, str::FromStr};
 
 #[cfg(feature = "sputnik")]
 use sputnik::backend::MemoryVicinity;
@@ -45,13 +48,98 @@ pub struct EvmOpts {
     /// the EVM type you want to use (e.g. sputnik, evmodin)
     pub evm_type: EvmType,
 
-    /// fetch state over a remote instead of starting from empty state
+    /// fetch state over a remote instead of starting from empty state, the url can be an HTTP,
+    /// WebSocket or IPC endpoint, see `Transport::detect`
     #[serde(rename = "eth_rpc_url")]
     pub fork_url: Option<String>,
 
     /// pins the block number for the state fork
     pub fork_block_number: Option<u64>,
 
+    /// forks at the state right before this transaction was executed in its block
+    pub fork_tx_hash: Option<H256>,
+
+    /// persists the fetched fork state to this file, only used if `fork_block_number` is set and
+    /// no `fork_tx_hash` is set
+    pub fork_cache_path: Option<PathBuf>,
+
+    /// persists the fetched state of each fork to a file of its own within this directory, like
+    /// the `fork_cache_path`, which takes precedence if both are set
+    pub fork_cache_dir: Option<PathBuf>,
+
+    /// RPC endpoints which are tried in order if a request to the `fork_url` fails
+    #[serde(default)]
+    pub fork_fallback_urls: Vec<String>,
+
+    /// how often a request to the fork endpoints is retried if it failed because of the
+    /// transport or rate limiting
+    #[serde(default)]
+    pub fork_retries: u32,
+
+    /// the delay in milliseconds before the first retry, doubled with every further retry and
+    /// randomized by up to the same amount
+    #[serde(default)]
+    pub fork_retry_backoff: u64,
+
+    /// headers in the `name: value` format which are sent with every fork request, e.g. to
+    /// authenticate without putting the API key into the url
+    #[serde(default)]
+    pub fork_headers: Vec<String>,
+
+    /// the maximum number of concurrent fork requests which are sent as a single batch request,
+    /// batching is disabled if this is not greater than 1
+    #[serde(default)]
+    pub fork_batch_size: usize,
+
+    /// how long in milliseconds a fork request waits for other requests to batch with
+    #[serde(default)]
+    pub fork_batch_interval: u64,
+
+    /// how long in seconds fetched fork state is cached before it is fetched again, ignored if
+    /// the fork is pinned to a block
+    pub fork_cache_ttl: Option<u64>,
+
+    /// fetches the state of the pending block, including the transactions in the pool of the
+    /// node, ignored if a block or transaction to fork at is set. The results are not
+    /// deterministic, since the pool changes all the time
+    #[serde(default)]
+    pub fork_pending: bool,
+
+    /// the maximum number of fetched accounts and storage slots which are cached, the least
+    /// recently used ones are evicted once there are more
+    pub fork_cache_max_entries: Option<usize>,
+
+    /// the maximum number of accounts, storage slots and blocks which are fetched from the fork,
+    /// accessing state which needs more fetches fails, e.g. to catch a test which stopped hitting
+    /// the cache
+    pub fork_request_budget: Option<usize>,
+
+    /// overrides the block.timestamp of the forked block
+    pub fork_block_timestamp: Option<u64>,
+
+    /// overrides the block.number of the forked block, without changing the block the state is
+    /// fetched from
+    pub fork_env_block_number: Option<u64>,
+
+    /// overrides the block.basefee of the forked block
+    pub fork_block_base_fee: Option<u64>,
+
+    /// whether the historical base fee of the forked block is used, which is the default. If it
+    /// is `false`, the base fee of the env is used instead, e.g. to see what a transaction costs
+    /// in today's fee environment. `fork_block_base_fee` still overrides both
+    #[serde(default)]
+    pub use_historical_basefee: Option<bool>,
+
+    /// appends every response of the fork endpoints to this fixture file
+    pub fork_record: Option<PathBuf>,
+
+    /// answers all fork requests from this fixture file instead of the fork endpoints, requests
+    /// which were not recorded fail
+    pub fork_replay: Option<PathBuf>,
+
+    /// seeds the state with the accounts of this genesis or state dump file
+    pub state_file: Option<PathBuf>,
+
     /// the initial balance of each deployed test contract
     pub initial_balance: U256,
 
@@ -71,14 +159,154 @@ pub struct EvmOpts {
 #[cfg(feature = "sputnik")]
 pub use sputnik_helpers::BackendKind;
 
+/// Errors which may occur when instantiating the backend for a (possibly forked) EVM
+#[derive(thiserror::Error, Debug)]
+pub enum BackendError {
+    #[error("invalid fork url: {0}")]
+    /// The provided RPC url could not be parsed
+    InvalidUrl(String),
+    #[error(transparent)]
+    /// The provider failed while fetching the initial fork state
+    ProviderInit(#[from] ethers::providers::ProviderError),
+    #[error("transaction {0:?} to fork at was not found or is still pending")]
+    /// The transaction to fork at is unknown to the provider
+    TransactionNotFound(H256),
+    #[error("block {0} to fork at was not found")]
+    /// The block to fork at is unknown to the provider
+    BlockNotFound(u64),
+    #[error(
+        "block {block} to fork at is not available, the endpoint is at block {chain_tip}{}",
+        block_hint(*.block, *.chain_tip)
+    )]
+    /// The block the fork is pinned at is not known to the endpoint, either because it is ahead
+    /// of the chain tip or because the node doesn't keep it
+    BlockNotAvailable { block: u64, chain_tip: u64 },
+    #[error("invalid fork header: {0}")]
+    /// A header for the fork requests could not be parsed
+    InvalidHeader(String),
+    #[error("failed to load the state file: {0}")]
+    /// The accounts to seed the state with could not be loaded
+    StateFile(String),
+    #[error("invalid fork fixture: {0}")]
+    /// The fixture to record to or replay from could not be opened
+    Fixture(String),
+    #[error(
+        "transaction {tx_hash:?} is in block {tx_block}, but the fork is pinned at {fork_block}"
+    )]
+    /// Both a block and a transaction to fork at were provided, but they don't match
+    InconsistentForkBlock { tx_hash: H256, tx_block: u64, fork_block: u64 },
+    #[error("failed to load the block to fork at: {0}")]
+    /// The endpoint returned no block, or one without a number, to build the environment from
+    ForkEnv(String),
+}
+
+/// Explains why the endpoint may not know the block, see [BackendError::BlockNotAvailable]
+fn block_hint(block: u64, chain_tip: u64) -> &'static str {
+    if block > chain_tip {
+        " (the block was not mined yet, or the endpoint is still syncing)"
+    } else {
+        " (the endpoint may be a full node which pruned it, forking at old blocks requires an \
+         archive node)"
+    }
+}
+
 // Helper functions for sputnik
 #[cfg(feature = "sputnik")]
 mod sputnik_helpers {
     use super::*;
 
-    use crate::{sputnik::cache::SharedBackend, FAUCET_ACCOUNT};
-    use ethers::providers::Provider;
+    use crate::{
+        hardforks,
+        sputnik::{
+            cache::{DiskCache, SharedBackend},
+            replay_transactions, spec_config, BatchClient, BatchConfig, BlockOverrides,
+            FallbackClient, FixtureClient, RetryPolicy, StateDump, Transport,
+        },
+        FAUCET_ACCOUNT,
+    };
+    use ethers::{
+        providers::{Middleware, Provider, ProviderError},
+        solc::EvmVersion,
+        types::{BlockNumber, Transaction},
+    };
+    use reqwest::{
+        header::{HeaderMap, HeaderName, HeaderValue},
+        Client,
+    };
     use sputnik::backend::MemoryBackend;
+    use std::time::Duration;
+    use tokio::runtime::Runtime;
+
+    /// The provider of the fork endpoints, see [EvmOpts::provider]
+    type ForkProvider = Provider<FixtureClient<FallbackClient<BatchClient>>>;
+
+    /// Returns the mined transaction, making sure its block matches the block the fork is pinned
+    /// at, if any
+    fn fork_tx(
+        rt: &Runtime,
+        provider: &ForkProvider,
+        tx_hash: H256,
+        fork_block: Option<u64>,
+    ) -> Result<(Transaction, u64), BackendError> {
+        let tx = rt
+            .block_on(provider.get_transaction(tx_hash))?
+            .ok_or(BackendError::TransactionNotFound(tx_hash))?;
+        let tx_block = tx.block_number.ok_or(BackendError::TransactionNotFound(tx_hash))?.as_u64();
+        match fork_block {
+            Some(fork_block) if fork_block != tx_block => {
+                Err(BackendError::InconsistentForkBlock { tx_hash, tx_block, fork_block })
+            }
+            _ => Ok((tx, tx_block)),
+        }
+    }
+
+    /// Makes sure the block the fork is pinned at is known to the endpoint, instead of silently
+    /// forking an empty state
+    fn ensure_block_available(
+        rt: &Runtime,
+        provider: &ForkProvider,
+        block: u64,
+    ) -> Result<(), BackendError> {
+        if rt.block_on(provider.get_block(block))?.is_some() {
+            return Ok(())
+        }
+        let chain_tip = rt.block_on(provider.get_block_number())?.as_u64();
+        Err(BackendError::BlockNotAvailable { block, chain_tip })
+    }
+
+    /// Keeps the failed requests apart from the blocks the endpoint doesn't know
+    fn vicinity_error(err: eyre::Report) -> BackendError {
+        match err.downcast::<ProviderError>() {
+            Ok(err) => BackendError::ProviderInit(err),
+            Err(err) => BackendError::ForkEnv(format!("{:#}", err)),
+        }
+    }
+
+    /// Returns the block which includes the transaction, see [fork_tx]
+    fn fork_tx_block(
+        rt: &Runtime,
+        provider: &ForkProvider,
+        tx_hash: H256,
+        fork_block: Option<u64>,
+    ) -> Result<u64, BackendError> {
+        Ok(fork_tx(rt, provider, tx_hash, fork_block)?.1)
+    }
+
+    /// Returns the block which includes the transaction and all the transactions that were
+    /// executed before it in that block
+    fn preceding_transactions(
+        provider: &ForkProvider,
+        tx_hash: H256,
+        fork_block: Option<u64>,
+    ) -> Result<(u64, Vec<Transaction>), BackendError> {
+        let rt = Runtime::new().expect("could not start tokio rt");
+        let tx_block = fork_tx_block(&rt, provider, tx_hash, fork_block)?;
+        let block = rt
+            .block_on(provider.get_block_with_txs(tx_block))?
+            .ok_or(BackendError::BlockNotFound(tx_block))?;
+        let txs = block.transactions.into_iter().take_while(|tx| tx.hash != tx_hash).collect();
+        Ok((tx_block, txs))
+    }
 
     pub enum BackendKind<'a> {
         Simple(MemoryBackend<'a>),
@@ -86,11 +314,16 @@ mod sputnik_helpers {
     }
 
     impl EvmOpts {
+        /// Returns the backend which should be used for executing the tests.
+        ///
+        /// This is always `Ok` when no `fork_url` is set. When forking, the `vicinity` has to be
+        /// the one returned by [vicinity](Self::vicinity), which already made sure that the
+        /// endpoint knows the pinned block.
         #[cfg(feature = "sputnik")]
         pub fn backend<'a>(
             &'a self,
             vicinity: &'a MemoryVicinity,
-        ) -> eyre::Result<BackendKind<'a>> {
+        ) -> Result<BackendKind<'a>, BackendError> {
             let mut backend = MemoryBackend::new(vicinity, Default::default());
             // max out the balance of the faucet
             let faucet =
@@ -101,17 +334,93 @@ mod sputnik_helpers {
             // `0x00a329c0648769A73afAc7F9381E08FB43dBEA72`
             let deployer = backend.state_mut().entry(self.sender).or_insert_with(Default::default);
             deployer.nonce = U256::from(1);
+            // the accounts of the state file take precedence over the ones set up above
+            if let Some(ref path) = self.state_file {
+                let accounts = StateDump::load(path)
+                    .map_err(|err| BackendError::StateFile(format!("{:#}", err)))?
+                    .into_cache();
+                backend.state_mut().extend(accounts);
+            }
 
             let backend = if let Some(ref url) = self.fork_url {
-                let provider = Provider::try_from(url.as_str())?;
+                let provider = self.provider(url)?;
+                // when forking at a transaction, the state is fetched from its parent block and
+                // the transactions preceding it are replayed on top
+                let (pin_block, replay) = match self.fork_tx_hash {
+                    Some(tx_hash) => {
+                        let (tx_block, txs) =
+                            preceding_transactions(&provider, tx_hash, self.fork_block_number)?;
+                        // replayed under the rules which were active at their block, like the
+                        // transaction itself, see `fork_evm_version`
+                        let rt = Runtime::new().expect("could not start tokio rt");
+                        let chain_id = rt.block_on(provider.get_chainid())?.as_u64();
+                        let version = hardforks::evm_version_at(chain_id, tx_block)
+                            .unwrap_or(EvmVersion::London);
+                        let config =
+                            spec_config(version).expect("hardfork schedules are supported");
+                        (Some(tx_block.saturating_sub(1)), Some((config, txs)))
+                    }
+                    // the block was already checked when fetching the vicinity
+                    None => (self.fork_block_number, None),
+                };
+                let replay = replay.filter(|(_, txs)| !txs.is_empty());
                 let init_state = backend.state().clone();
-                let cache = crate::sputnik::new_shared_cache(init_state);
-                let backend = SharedBackend::new(
-                    provider,
-                    cache,
-                    vicinity.clone(),
-                    self.fork_block_number.map(Into::into),
-                );
+                // the replayed state must not end up in the cache of the parent block
+                let cache_location = match (&self.fork_cache_path, &self.fork_cache_dir) {
+                    (Some(path), _) => Some((path, false)),
+                    (None, Some(dir)) => Some((dir, true)),
+                    (None, None) => None,
+                };
+                let disk_cache = match (cache_location, pin_block) {
+                    (Some((path, is_dir)), Some(block)) if replay.is_none() => {
+                        // the chain id of the vicinity may be overridden, so the cache is keyed
+                        // by the one of the endpoint to not mix up the state of different chains
+                        let rt = Runtime::new().expect("could not start tokio rt");
+                        let chain_id = rt.block_on(provider.get_chainid())?.as_u64();
+                        let disk = if is_dir {
+                            DiskCache::in_dir(path, chain_id, block)
+                        } else {
+                            DiskCache::new(path, chain_id, block)
+                        };
+                        Some(disk.exclude(init_state.keys().copied()))
+                    }
+                    _ => None,
+                };
+                let mut state = disk_cache
+                    .as_ref()
+                    .map(|disk| {
+                        disk.load().unwrap_or_else(|err| {
+                            tracing::warn!("Failed to read fork cache {:?}: {}", disk.path(), err);
+                            Default::default()
+                        })
+                    })
+                    .unwrap_or_default();
+                // the locally set up accounts always take precedence over the cached ones
+                state.extend(init_state);
+                let cache = crate::sputnik::new_shared_cache(state);
+                let block_id = match pin_block {
+                    Some(block) => Some(block.into()),
+                    None if self.fork_pending => Some(BlockNumber::Pending.into()),
+                    None => None,
+                };
+                let mut backend =
+                    SharedBackend::new(provider, cache.clone(), vicinity.clone(), block_id);
+                if let Some((config, txs)) = replay {
+                    replay_transactions(&backend, &cache, config, &txs);
+                }
+                if let Some(disk_cache) = disk_cache {
+                    backend = backend.with_disk_cache(disk_cache);
+                }
+                if let Some(ttl) = self.fork_cache_ttl {
+                    backend = backend.with_cache_ttl(Duration::from_secs(ttl));
+                }
+                // set after replaying, so that the replayed state is never evicted
+                if let Some(max_entries) = self.fork_cache_max_entries {
+                    backend = backend.with_cache_limit(max_entries);
+                }
+                if let Some(budget) = self.fork_request_budget {
+                    backend = backend.with_request_budget(budget);
+                }
                 BackendKind::Shared(backend)
             } else {
                 BackendKind::Simple(backend)
@@ -120,21 +429,187 @@ mod sputnik_helpers {
             Ok(backend)
         }
 
+        /// Returns the block environment, fetched from the fork url if one is set.
+        ///
+        /// This is always `Ok` when no `fork_url` is set, and fails with
+        /// [BackendError::BlockNotAvailable] if the endpoint doesn't know the pinned block.
         #[cfg(feature = "sputnik")]
-        pub fn vicinity(&self) -> eyre::Result<MemoryVicinity> {
+        pub fn vicinity(&self) -> Result<MemoryVicinity, BackendError> {
             Ok(if let Some(ref url) = self.fork_url {
-                let provider = ethers::providers::Provider::try_from(url.as_str())?;
-                let rt = tokio::runtime::Runtime::new().expect("could not start tokio rt");
-                rt.block_on(crate::sputnik::vicinity(
-                    &provider,
-                    self.env.chain_id,
-                    self.fork_block_number,
-                    Some(self.env.tx_origin),
-                ))?
+                let provider = self.provider(url)?;
+                let rt = Runtime::new().expect("could not start tokio rt");
+                let tx = match self.fork_tx_hash {
+                    Some(tx_hash) => {
+                        Some(fork_tx(&rt, &provider, tx_hash, self.fork_block_number)?)
+                    }
+                    None => {
+                        if let Some(block) = self.fork_block_number {
+                            ensure_block_available(&rt, &provider, block)?;
+                        }
+                        None
+                    }
+                };
+                let block = tx.as_ref().map(|(_, block)| *block).or(self.fork_block_number);
+                // the transaction is sent by its sender, so its replay sees the same `tx.origin`
+                let origin = Some(tx.as_ref().map_or(self.env.tx_origin, |(tx, _)| tx.from));
+                let mut vicinity = match block {
+                    None if self.fork_pending => rt.block_on(crate::sputnik::vicinity_at(
+                        &provider,
+                        self.env.chain_id,
+                        BlockNumber::Pending,
+                        origin,
+                    )),
+                    block => rt.block_on(crate::sputnik::vicinity(
+                        &provider,
+                        self.env.chain_id,
+                        block,
+                        origin,
+                    )),
+                }
+                .map_err(vicinity_error)?;
+                if let Some(gas_price) = tx.and_then(|(tx, _)| tx.gas_price) {
+                    vicinity.gas_price = gas_price;
+                }
+                self.block_overrides().apply(&mut vicinity);
+                vicinity
             } else {
                 self.env.sputnik_state()
             })
         }
+
+        /// Returns the transaction to fork at, e.g. to replay it with
+        /// [Executor::execute_transaction](crate::sputnik::Executor::execute_transaction) on top of
+        /// the forked state.
+        ///
+        /// This is `None` if no `fork_url` or `fork_tx_hash` is set.
+        pub fn fork_transaction(&self) -> Result<Option<Transaction>, BackendError> {
+            let (url, tx_hash) = match (&self.fork_url, self.fork_tx_hash) {
+                (Some(url), Some(tx_hash)) => (url, tx_hash),
+                _ => return Ok(None),
+            };
+            let provider = self.provider(url)?;
+            let rt = Runtime::new().expect("could not start tokio rt");
+            Ok(Some(fork_tx(&rt, &provider, tx_hash, self.fork_block_number)?.0))
+        }
+
+        /// Returns the chain id of the fork endpoint, e.g. to make sure the intended chain is
+        /// forked.
+        ///
+        /// Unlike the chain id of the [vicinity](Self::vicinity), this is never overridden by the
+        /// `chain_id` of the env. This is `None` if no `fork_url` is set.
+        pub fn fork_chain_id(&self) -> Result<Option<u64>, BackendError> {
+            let url = match self.fork_url {
+                Some(ref url) => url,
+                None => return Ok(None),
+            };
+            let provider = self.provider(url)?;
+            let rt = Runtime::new().expect("could not start tokio rt");
+            Ok(Some(rt.block_on(provider.get_chainid())?.as_u64()))
+        }
+
+        /// Returns the EVM version which was active at the forked block.
+        ///
+        /// This is `None` if no `fork_url` is set, if the fork is not pinned, in which case it
+        /// runs under the latest rules, or if the hardfork schedule of the forked chain is unknown.
+        pub fn fork_evm_version(&self) -> Result<Option<EvmVersion>, BackendError> {
+            let url = match self.fork_url {
+                Some(ref url) => url,
+                None => return Ok(None),
+            };
+            if self.fork_block_number.is_none() && self.fork_tx_hash.is_none() {
+                return Ok(None)
+            }
+            let provider = self.provider(url)?;
+            let rt = Runtime::new().expect("could not start tokio rt");
+            let block = match self.fork_tx_hash {
+                Some(tx_hash) => fork_tx_block(&rt, &provider, tx_hash, self.fork_block_number)?,
+                None => self.fork_block_number.expect("fork is pinned"),
+            };
+            let chain_id = rt.block_on(provider.get_chainid())?.as_u64();
+            let version = hardforks::evm_version_at(chain_id, block);
+            if version.is_none() {
+                tracing::warn!(
+                    "Unknown hardfork schedule of chain {}, falling back to the latest EVM version",
+                    chain_id
+                );
+            }
+            Ok(version)
+        }
+
+        /// Returns the overrides of the forked block environment
+        pub fn block_overrides(&self) -> BlockOverrides {
+            let env_base_fee = (self.use_historical_basefee == Some(false))
+                .then(|| self.env.block_base_fee_per_gas);
+            BlockOverrides::new(
+                self.fork_block_timestamp.map(Into::into),
+                self.fork_env_block_number.map(Into::into),
+                self.fork_block_base_fee.or(env_base_fee).map(Into::into),
+            )
+            .with_priority_fee(self.env.gas_priority_fee.map(Into::into))
+            // the forked block's miner is kept unless a coinbase is set
+            .with_coinbase(Some(self.env.block_coinbase).filter(|coinbase| !coinbase.is_zero()))
+        }
+
+        /// Returns the HTTP client for the fork endpoints, which attaches the `fork_headers` to
+        /// every request
+        fn http_client(&self) -> Result<Client, BackendError> {
+            let mut headers = HeaderMap::new();
+            for header in &self.fork_headers {
+                // the value is not part of the error, since it is likely a secret
+                let (name, value) = header.split_once(':').ok_or_else(|| {
+                    BackendError::InvalidHeader(format!("`{}` is not a `name: value` pair", header))
+                })?;
+                let name = HeaderName::from_str(name.trim()).map_err(|_| {
+                    BackendError::InvalidHeader(format!("invalid header name `{}`", name))
+                })?;
+                let mut value = HeaderValue::from_str(value.trim()).map_err(|_| {
+                    BackendError::InvalidHeader(format!("invalid value of header `{}`", name))
+                })?;
+                value.set_sensitive(true);
+                headers.insert(name, value);
+            }
+            Client::builder()
+                .default_headers(headers)
+                .build()
+                .map_err(|err| BackendError::InvalidHeader(err.to_string()))
+        }
+
+        /// Instantiates a provider for the given url which fails over to the fallback urls,
+        /// without panicking on malformed input.
+        ///
+        /// If a fixture to replay is set, the provider never sends any request, and if one to
+        /// record to is set, all responses are appended to it.
+        fn provider(&self, url: &str) -> Result<ForkProvider, BackendError> {
+            let fixture_err = |err: eyre::Report| BackendError::Fixture(format!("{:#}", err));
+            if let Some(path) = &self.fork_replay {
+                return Ok(Provider::new(FixtureClient::replay_from(path).map_err(fixture_err)?))
+            }
+
+            let batch = BatchConfig::new(
+                self.fork_batch_size,
+                Duration::from_millis(self.fork_batch_interval),
+            );
+            // the headers are sent to all HTTP endpoints
+            let client = self.http_client()?;
+            let endpoint = |url: &str| {
+                Transport::detect(url, client.clone())
+                    .map(|transport| BatchClient::with_transport(transport, batch))
+                    .map_err(|err| BackendError::InvalidUrl(format!("{} ({})", url, err)))
+            };
+            let fallbacks = self
+                .fork_fallback_urls
+                .iter()
+                .map(|url| endpoint(url))
+                .collect::<Result<Vec<_>, _>>()?;
+            let backoff = Duration::from_millis(self.fork_retry_backoff);
+            let retry = RetryPolicy::new(self.fork_retries, backoff).with_jitter(backoff);
+            let client = FallbackClient::new(endpoint(url)?, fallbacks).with_retry(retry);
+            let client = match &self.fork_record {
+                Some(path) => FixtureClient::record_to(client, path).map_err(fixture_err)?,
+                None => FixtureClient::passthrough(client),
+            };
+            Ok(Provider::new(client))
+        }
     }
 }
 
@@ -152,6 +627,15 @@ pub struct Env {
     /// the base fee in a block
     pub block_base_fee_per_gas: u64,
 
+    /// the priority fee paid to the coinbase on top of the base fee, if set the tx.gasprice is
+    /// the base fee plus this instead of `gas_price`
+    pub gas_priority_fee: Option<u64>,
+
+    /// whether a gas price below the base fee is accepted, like on nodes which simulate calls.
+    /// The base fee is still burnt, so the coinbase gets nothing in that case
+    #[serde(default)]
+    pub disable_base_fee: bool,
+
     /// the tx.origin value during EVM execution
     pub tx_origin: Address,
 
@@ -169,16 +653,132 @@ pub struct Env {
 
     /// the block.gaslimit value during EVM execution
     pub block_gas_limit: Option<u64>,
-    // TODO: Add configuration option for base fee.
+
+    /// the hashes returned by `blockhash` for the given block numbers, the remaining ones of the
+    /// last 256 blocks get a deterministic pseudo hash
+    #[serde(default)]
+    pub block_hashes: Vec<(u64, H256)>,
 }
 
 impl Env {
+    /// Sets both the transaction and the block gas limit, leaving everything else intact
+    #[must_use]
+    pub fn with_gas_limit(mut self, gas_limit: u64) -> Self {
+        self.gas_limit = gas_limit;
+        self.block_gas_limit = Some(gas_limit);
+        self
+    }
+
+    /// Sets the tx.gasprice, which the sender pays for all gas used
+    #[must_use]
+    pub fn with_gas_price(mut self, gas_price: u64) -> Self {
+        self.gas_price = gas_price;
+        self
+    }
+
+    /// Sets the base fee of the block, which is burnt for all gas used.
+    ///
+    /// When forking, the base fee of the forked block is used instead, unless
+    /// [use_historical_basefee](EvmOpts::use_historical_basefee) is `false`.
+    #[must_use]
+    pub fn with_base_fee(mut self, base_fee: u64) -> Self {
+        self.block_base_fee_per_gas = base_fee;
+        self
+    }
+
+    /// Sets the priority fee, so that transactions pay the base fee plus this per gas
+    #[must_use]
+    pub fn with_priority_fee(mut self, priority_fee: u64) -> Self {
+        self.gas_priority_fee = Some(priority_fee);
+        self
+    }
+
+    /// Sets whether a gas price below the base fee is accepted, see
+    /// [check_gas_price](Self::check_gas_price)
+    #[must_use]
+    pub fn with_disable_base_fee(mut self, disable: bool) -> Self {
+        self.disable_base_fee = disable;
+        self
+    }
+
+    /// Sets the chain id returned by `CHAINID`, forks default to the chain id of the endpoint
+    #[must_use]
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = Some(chain_id);
+        self
+    }
+
+    /// Seeds the hashes which `blockhash` returns for the given block numbers
+    #[must_use]
+    pub fn with_block_hashes(mut self, block_hashes: Vec<(u64, H256)>) -> Self {
+        self.block_hashes = block_hashes;
+        self
+    }
+
+    /// The hash `blockhash` returns for the given block, if it is one of the last 256.
+    ///
+    /// Blocks which weren't seeded with [with_block_hashes](Self::with_block_hashes) get the
+    /// keccak256 hash of their number, like test nodes and the `roll` cheatcode do.
+    pub fn block_hash(&self, number: u64) -> H256 {
+        if number >= self.block_number || self.block_number - number > 256 {
+            return H256::zero()
+        }
+        match self.block_hashes.iter().find(|(n, _)| *n == number) {
+            Some((_, hash)) => *hash,
+            None => {
+                let mut bytes = [0u8; 32];
+                U256::from(number).to_big_endian(&mut bytes);
+                keccak256(bytes).into()
+            }
+        }
+    }
+
+    /// Sets the coinbase of the block, which collects the priority fees
+    #[must_use]
+    pub fn with_coinbase(mut self, coinbase: Address) -> Self {
+        self.block_coinbase = coinbase;
+        self
+    }
+
+    /// The tx.gasprice, which is the base fee plus the priority fee if one is set
+    pub fn effective_gas_price(&self) -> u64 {
+        match self.gas_priority_fee {
+            Some(fee) => self.block_base_fee_per_gas.saturating_add(fee),
+            None => self.gas_price,
+        }
+    }
+
+    /// Checks that the gas price can be paid under the EIP-1559 rules, if `london` is set.
+    ///
+    /// The gas price may not be below the base fee, unless the base fee check is disabled, and if
+    /// it is set together with a priority fee, it has to be the base fee plus the priority fee.
+    pub fn check_gas_price(&self, london: bool) -> eyre::Result<()> {
+        if !london || self.gas_price == 0 {
+            return Ok(())
+        }
+        let base_fee = self.block_base_fee_per_gas;
+        if let Some(priority_fee) = self.gas_priority_fee {
+            if self.gas_price != base_fee.saturating_add(priority_fee) {
+                eyre::bail!(
+                    "gas price {} does not match the base fee {} plus the priority fee {}",
+                    self.gas_price,
+                    base_fee,
+                    priority_fee
+                )
+            }
+        }
+        if self.gas_price < base_fee && !self.disable_base_fee {
+            eyre::bail!("gas price {} is below the base fee {}", self.gas_price, base_fee)
+        }
+        Ok(())
+    }
+
     #[cfg(feature = "sputnik")]
     pub fn sputnik_state(&self) -> MemoryVicinity {
         MemoryVicinity {
             chain_id: self.chain_id.unwrap_or(99).into(),
 
-            gas_price: self.gas_price.into(),
+            gas_price: self.effective_gas_price().into(),
             origin: self.tx_origin,
 
             block_coinbase: self.block_coinbase,
@@ -187,7 +787,159 @@ impl Env {
             block_difficulty: self.block_difficulty.into(),
             block_base_fee_per_gas: self.block_base_fee_per_gas.into(),
             block_gas_limit: self.block_gas_limit.unwrap_or(self.gas_limit).into(),
-            block_hashes: Vec::new(),
+            // the vicinity holds the hashes of the preceding blocks, most recent first
+            block_hashes: (self.block_number.saturating_sub(256)..self.block_number)
+                .rev()
+                .map(|number| self.block_hash(number))
+                .collect(),
         }
     }
 }
+
+#[cfg(all(test, feature = "sputnik"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unavailable_fork_block() {
+        let path = std::env::temp_dir().join("evm-adapters-unavailable-block.jsonl");
+        let fixture = [
+            r#"{"request":"eth_blockNumber null","result":"0x10"}"#,
+            r#"{"request":"eth_getBlockByNumber [\"0x5\",false]","result":null}"#,
+            r#"{"request":"eth_getBlockByNumber [\"0x64\",false]","result":null}"#,
+        ];
+        std::fs::write(&path, fixture.join("\n")).unwrap();
+
+        let mut opts = EvmOpts {
+            fork_url: Some("http://localhost:8545".to_string()),
+            fork_replay: Some(path),
+            fork_block_number: Some(100),
+            ..Default::default()
+        };
+        let err = opts.vicinity().unwrap_err();
+        assert!(
+            matches!(err, BackendError::BlockNotAvailable { block: 100, chain_tip: 16 }),
+            "{}",
+            err
+        );
+        assert!(err.to_string().contains("not mined yet"), "{}", err);
+
+        opts.fork_block_number = Some(5);
+        let err = opts.vicinity().unwrap_err();
+        assert!(err.to_string().contains("archive node"), "{}", err);
+    }
+
+    #[test]
+    fn fails_if_the_fork_block_is_missing() {
+        let path = std::env::temp_dir().join("evm-adapters-missing-pending-block.jsonl");
+        let fixture = [
+            r#"{"request":"eth_gasPrice null","result":"0x1"}"#,
+            r#"{"request":"eth_chainId null","result":"0x1"}"#,
+            r#"{"request":"eth_getBlockByNumber [\"pending\",false]","result":null}"#,
+        ];
+        std::fs::write(&path, fixture.join("\n")).unwrap();
+
+        let opts = EvmOpts {
+            fork_url: Some("http://localhost:8545".to_string()),
+            fork_replay: Some(path),
+            fork_pending: true,
+            ..Default::default()
+        };
+        let err = opts.vicinity().unwrap_err();
+        assert!(matches!(err, BackendError::ForkEnv(_)), "{}", err);
+        assert!(err.to_string().contains("block Pending not found"), "{}", err);
+    }
+
+    #[test]
+    fn can_use_env_base_fee_on_fork() {
+        use crate::{
+            sputnik::helpers::{new_backend, vm_with_backend},
+            Evm,
+        };
+
+        let env = Env::default()
+            .with_base_fee(10)
+            .with_priority_fee(2)
+            .with_coinbase(Address::repeat_byte(3));
+        // the forked block is post-London
+        let forked = MemoryVicinity { block_base_fee_per_gas: 100.into(), ..env.sputnik_state() };
+        let alice = Address::repeat_byte(1);
+        let bob = Address::repeat_byte(2);
+        // stores 42 in slot 0
+        let code = hex::decode("602a60005500").unwrap();
+        // the fees which are burnt and the gas price paid by the sender
+        let burn = |opts: &EvmOpts| {
+            let mut vicinity = forked.clone();
+            opts.block_overrides().apply(&mut vicinity);
+            let backend = new_backend(&vicinity, Default::default());
+            let mut evm = vm_with_backend(backend);
+            evm.initialize_contracts(vec![(bob, code.clone().into())]);
+            evm.deal(alice, 1_000_000_000.into());
+            let res = evm.call_result(alice, bob, Default::default(), 0.into());
+            assert!(res.success);
+            let paid = U256::from(1_000_000_000) - evm.state().basic(alice).balance;
+            let tip = evm.state().basic(vicinity.block_coinbase).balance;
+            (paid - tip, vicinity.gas_price)
+        };
+
+        let mut opts = EvmOpts { env, ..Default::default() };
+        let (historical, gas_price) = burn(&opts);
+        assert_eq!(gas_price, 102.into());
+
+        opts.use_historical_basefee = Some(false);
+        let (current, gas_price) = burn(&opts);
+        assert_eq!(gas_price, 12.into());
+        assert!(!current.is_zero());
+        assert_eq!(historical, current * 10);
+
+        // an explicit override wins over both
+        opts.fork_block_base_fee = Some(50);
+        assert_eq!(burn(&opts).1, 52.into());
+    }
+
+    #[test]
+    fn can_use_historical_base_fee_of_fork() {
+        use crate::{
+            sputnik::helpers::{vm_with_backend, FORK_URL},
+            Evm,
+        };
+
+        // a post-London block, whose base fee is far above the one of the env
+        let block = 13292465;
+        let alice = Address::repeat_byte(1);
+        let bob = Address::repeat_byte(2);
+        // stores 42 in slot 0
+        let code = hex::decode("602a60005500").unwrap();
+        let burn = |opts: &EvmOpts| {
+            let vicinity = opts.vicinity().unwrap();
+            let backend = match opts.backend(&vicinity).unwrap() {
+                BackendKind::Shared(backend) => backend,
+                BackendKind::Simple(_) => panic!("expected a fork"),
+            };
+            let mut evm = vm_with_backend(backend);
+            evm.initialize_contracts(vec![(bob, code.clone().into())]);
+            evm.deal(alice, U256::exp10(20));
+            let res = evm.call_result(alice, bob, Default::default(), 0.into());
+            assert!(res.success);
+            let paid = U256::exp10(20) - evm.state().basic(alice).balance;
+            (paid, vicinity.block_base_fee_per_gas, res.gas_used)
+        };
+
+        let env = Env::default().with_base_fee(7).with_priority_fee(0);
+        let mut opts = EvmOpts {
+            env,
+            fork_url: Some(FORK_URL.to_string()),
+            fork_block_number: Some(block),
+            ..Default::default()
+        };
+        let (historical, base_fee, _) = burn(&opts);
+        assert!(base_fee > 7.into());
+
+        opts.use_historical_basefee = Some(false);
+        let (current, base_fee, gas_used) = burn(&opts);
+        assert_eq!(base_fee, 7.into());
+        assert!(historical > current);
+        assert!(!current.is_zero());
+        assert!(current <= U256::from(7 * gas_used));
+    }
+}
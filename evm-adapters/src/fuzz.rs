@@ -231,6 +231,29 @@ pub struct FuzzCase {
     pub gas: u64,
 }
 
+/// Wraps a fuzzed value into the inclusive range `[min, max]`, values inside the range are kept
+/// as is.
+///
+/// Values below and above the range wrap around from the opposite end, so that small
+/// changes of the fuzzed value still map to small changes of the result.
+///
+/// # Panics
+///
+/// If `min` is greater than `max`
+pub fn bound(x: U256, min: U256, max: U256) -> U256 {
+    assert!(min <= max, "bound: min {} is greater than max {}", min, max);
+    if x >= min && x <= max {
+        return x
+    }
+    // the range can't include all values, otherwise `x` would be in it
+    let size = max - min + 1;
+    if x > max {
+        min + (x - max - 1) % size
+    } else {
+        max - (min - x - 1) % size
+    }
+}
+
 /// Given a function, it returns a proptest strategy which generates valid abi-encoded calldata
 /// for that function's input types.
 pub fn fuzz_calldata(func: &Function) -> impl Strategy<Value = Bytes> + '_ {
@@ -315,6 +338,20 @@ mod tests {
         Evm,
     };
 
+    #[test]
+    fn can_bound() {
+        let bound = |x: u64, min: u64, max: u64| bound(x.into(), min.into(), max.into()).as_u64();
+        assert_eq!(bound(5, 0, 10), 5);
+        assert_eq!(bound(11, 0, 10), 0);
+        assert_eq!(bound(12, 0, 10), 1);
+        assert_eq!(bound(4, 5, 10), 10);
+        assert_eq!(bound(3, 5, 10), 9);
+        assert_eq!(bound(0, 7, 7), 7);
+        assert_eq!(bound(u64::MAX, 7, 7), 7);
+        assert_eq!(super::bound(U256::MAX, 0.into(), U256::MAX), U256::MAX);
+        assert_eq!(super::bound(U256::MAX, 1.into(), 10.into()), 5.into());
+    }
+
     #[test]
     fn prints_fuzzed_revert_reasons() {
         let mut evm = vm();
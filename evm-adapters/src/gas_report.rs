@@ -11,20 +11,20 @@ use crate::sputnik::cheatcodes::cheatcode_handler::{CHEATCODE_ADDRESS, CONSOLE_A
 
 use comfy_table::{modifiers::UTF8_ROUND_CORNERS, presets::UTF8_FULL, *};
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct GasReport {
     pub report_for: Vec<String>,
     pub contracts: BTreeMap<String, ContractInfo>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ContractInfo {
     pub gas: U256,
     pub size: U256,
     pub functions: BTreeMap<String, GasInfo>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct GasInfo {
     pub calls: Vec<U256>,
     pub min: U256,
@@ -49,6 +49,40 @@ impl GasReport {
         });
     }
 
+    /// Adds the calls of traces whose contracts are not known, e.g. the traces recorded by an
+    /// executor. Contracts are identified by their label or address, and functions by their
+    /// name in the `abi` or their selector.
+    pub fn analyze_unidentified(&mut self, traces: &[CallTraceArena], abi: Option<&Abi>) {
+        let report_for_all = self.report_for.is_empty() || self.report_for.iter().any(|s| s == "*");
+        for node in traces.iter().flat_map(|trace| trace.arena.iter()) {
+            let trace = &node.trace;
+
+            #[cfg(feature = "sputnik")]
+            if trace.addr == *CHEATCODE_ADDRESS || trace.addr == *CONSOLE_ADDRESS {
+                continue
+            }
+
+            let name = trace.label.clone().unwrap_or_else(|| format!("{:?}", trace.addr));
+            if !report_for_all && !self.report_for.contains(&name) {
+                continue
+            }
+            if trace.created {
+                let contract = self.contracts.entry(name).or_insert_with(Default::default);
+                contract.gas = trace.cost.into();
+                contract.size = trace.data.len().into();
+            } else if trace.data.len() >= 4 {
+                let selector = &trace.data[0..4];
+                let func = abi
+                    .and_then(|abi| abi.functions().find(|func| func.short_signature() == selector))
+                    .map(|func| func.name.clone())
+                    .unwrap_or_else(|| format!("0x{}", hex::encode(selector)));
+                let contract = self.contracts.entry(name).or_insert_with(Default::default);
+                let function = contract.functions.entry(func).or_insert_with(Default::default);
+                function.calls.push(trace.cost.into());
+            }
+        }
+    }
+
     fn analyze_trace(
         &mut self,
         trace: &CallTraceArena,
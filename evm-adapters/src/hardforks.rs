@@ -0,0 +1,53 @@
+//! Hardfork schedules of well-known chains
+use ethers::solc::EvmVersion;
+
+/// The blocks at which the EVM versions supported by the executors were activated, in ascending
+/// order.
+///
+/// Hardforks prior to istanbul are not supported by the executors, so all blocks before the
+/// first entry are executed under the rules of the first entry.
+fn schedule(chain_id: u64) -> Option<&'static [(u64, EvmVersion)]> {
+    Some(match chain_id {
+        // mainnet
+        1 => &[
+            (9_069_000, EvmVersion::Istanbul),
+            (12_244_000, EvmVersion::Berlin),
+            (12_965_000, EvmVersion::London),
+        ],
+        // goerli
+        5 => &[
+            (1_561_651, EvmVersion::Istanbul),
+            (4_460_644, EvmVersion::Berlin),
+            (5_062_605, EvmVersion::London),
+        ],
+        // optimism, relaunched under berlin rules and upgraded with bedrock
+        10 => &[(0, EvmVersion::Berlin), (105_235_063, EvmVersion::London)],
+        // arbitrum one, upgraded with nitro
+        42161 => &[(0, EvmVersion::Berlin), (22_207_818, EvmVersion::London)],
+        _ => return None,
+    })
+}
+
+/// Returns the EVM version which was active at the given block of the chain, or `None` if the
+/// hardfork schedule of the chain is unknown
+pub fn evm_version_at(chain_id: u64, block: u64) -> Option<EvmVersion> {
+    let schedule = schedule(chain_id)?;
+    let (_, version) =
+        schedule.iter().rev().find(|(activation, _)| *activation <= block).unwrap_or(&schedule[0]);
+    Some(*version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_find_evm_version() {
+        assert_eq!(evm_version_at(1, 0), Some(EvmVersion::Istanbul));
+        assert_eq!(evm_version_at(1, 12_243_999), Some(EvmVersion::Istanbul));
+        assert_eq!(evm_version_at(1, 12_244_000), Some(EvmVersion::Berlin));
+        assert_eq!(evm_version_at(1, 14_000_000), Some(EvmVersion::London));
+        assert_eq!(evm_version_at(10, 1), Some(EvmVersion::Berlin));
+        assert_eq!(evm_version_at(1337, 1), None);
+    }
+}
@@ -0,0 +1,74 @@
+//! Labels of well-known contracts of popular chains, to make the traces of forks readable
+use ethers::types::Address;
+use std::collections::BTreeMap;
+
+/// Contracts which are deployed to the same address on every chain
+const ALL_CHAINS: &[(&str, &str)] = &[
+    ("0xcA11bde05977b3631167028862bE2a173976CA11", "Multicall3"),
+    ("0x000000000022D473030F116dDEE9F6B43aC78BA3", "Permit2"),
+];
+
+fn registry(chain_id: u64) -> &'static [(&'static str, &'static str)] {
+    match chain_id {
+        // mainnet
+        1 => &[
+            ("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", "WETH"),
+            ("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48", "USDC"),
+            ("0xdAC17F958D2ee523a2206206994597C13D831ec7", "USDT"),
+            ("0x6B175474E89094C44Da98b954EedeAC495271d0F", "DAI"),
+            ("0x2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599", "WBTC"),
+            ("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D", "UniswapV2Router02"),
+            ("0xE592427A0AEce92De3Edee1F18E0157C05861564", "UniswapV3SwapRouter"),
+            ("0x68b3465833fb72A70ecDF485E0e4C7bD8665Fc45", "UniswapV3SwapRouter02"),
+        ],
+        // optimism
+        10 => &[
+            ("0x4200000000000000000000000000000000000006", "WETH"),
+            ("0x0b2C639c533813f4Aa9D7837CAf62653d097Ff85", "USDC"),
+            ("0xE592427A0AEce92De3Edee1F18E0157C05861564", "UniswapV3SwapRouter"),
+        ],
+        // arbitrum one
+        42161 => &[
+            ("0x82aF49447D8a07e3bd95BD0d56f35241523fBab1", "WETH"),
+            ("0xaf88d065e77c8cC2239327C5EDb3A432268e5831", "USDC"),
+            ("0xE592427A0AEce92De3Edee1F18E0157C05861564", "UniswapV3SwapRouter"),
+        ],
+        _ => &[],
+    }
+}
+
+/// The labels of the well-known contracts of the chain, like its WETH, USDC and routers, and of
+/// the contracts which have the same address on every chain, like Multicall3. Only the latter are
+/// known for chains without a registry.
+pub fn known_labels(chain_id: u64) -> BTreeMap<Address, String> {
+    ALL_CHAINS
+        .iter()
+        .chain(registry(chain_id))
+        .map(|(address, label)| {
+            (address.parse().expect("registry addresses are valid"), label.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_label_known_addresses() {
+        let weth: Address = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".parse().unwrap();
+        let labels = known_labels(1);
+        assert_eq!(labels[&weth], "WETH");
+        // the zero address is the default sender, so it isn't renamed
+        assert!(!labels.contains_key(&Address::zero()));
+
+        let labels = known_labels(1337);
+        assert!(!labels.contains_key(&weth));
+        assert_eq!(labels.len(), ALL_CHAINS.len());
+
+        // every registry parses
+        for chain_id in [1, 10, 42161] {
+            assert!(known_labels(chain_id).len() > ALL_CHAINS.len());
+        }
+    }
+}
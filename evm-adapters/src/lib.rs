@@ -19,13 +19,21 @@ pub mod gas_report;
 /// Helpers for easily constructing EVM objects.
 pub mod evm_opts;
 
+pub mod hardforks;
+
+pub mod labels;
+
+pub mod storage_layout;
+
 use ethers::{
-    abi::{Abi, Detokenize, Tokenize},
+    abi::{Abi, Detokenize, RawLog, Token, Tokenize},
     contract::{decode_function_data, encode_function_data},
-    core::types::{Address, Bytes, U256},
+    core::types::{Address, Bytes, H256, U256},
+    solc::EvmVersion,
 };
 
 use foundry_utils::IntoFunction;
+use std::collections::BTreeMap;
 
 use eyre::Result;
 use once_cell::sync::Lazy;
@@ -36,6 +44,46 @@ pub const ASSUME_MAGIC_RETURN_CODE: &[u8] = "FOUNDRY::ASSUME".as_bytes();
 pub static FAUCET_ACCOUNT: Lazy<Address> =
     Lazy::new(|| Address::from_slice(&ethers::utils::keccak256("turbodapp faucet")[12..]));
 
+/// Computes the address a contract is deployed to with `CREATE2`, given the hash of its init code
+pub fn compute_create2_address(deployer: Address, salt: H256, init_code_hash: H256) -> Address {
+    let mut bytes = Vec::with_capacity(85);
+    bytes.push(0xff);
+    bytes.extend_from_slice(deployer.as_bytes());
+    bytes.extend_from_slice(salt.as_bytes());
+    bytes.extend_from_slice(init_code_hash.as_bytes());
+    Address::from_slice(&ethers::utils::keccak256(bytes)[12..])
+}
+
+/// Computes the init code which deploys the contract with the given constructor arguments, by
+/// appending their ABI encoding to the bytecode.
+///
+/// Fails if the arguments don't match the constructor of the ABI, contracts without a
+/// constructor take no arguments.
+pub fn init_code_with_args(bytecode: Bytes, abi: &Abi, args: &[Token]) -> Result<Bytes> {
+    let params = abi.constructor().map(|constructor| &constructor.inputs[..]).unwrap_or_default();
+    if params.len() != args.len() {
+        eyre::bail!(
+            "the constructor takes {} arguments, but {} were given",
+            params.len(),
+            args.len()
+        )
+    }
+    for (i, (param, arg)) in params.iter().zip(args).enumerate() {
+        if !arg.type_check(&param.kind) {
+            eyre::bail!(
+                "constructor argument {} `{}` must be of type {}, but is {:?}",
+                i,
+                param.name,
+                param.kind,
+                arg
+            )
+        }
+    }
+    let mut init_code = bytecode.to_vec();
+    init_code.extend(ethers::abi::encode(args));
+    Ok(init_code.into())
+}
+
 /// Errors related to the EVM call execution
 #[derive(thiserror::Error, Debug)]
 pub enum EvmError {
@@ -43,6 +91,12 @@ pub enum EvmError {
     // TODO: Add proper log printing.
     /// Error which occurred during execution of an EVM transaction
     Execution { reason: String, gas_used: u64, logs: Vec<String> },
+    #[error("deployed code exceeds the contract size limit of {limit} bytes")]
+    /// The deployed code is larger than the limit of the config, see EIP-170
+    ContractSizeLimit { limit: usize },
+    #[error("the deployment address {address:?} already has code or a nonce")]
+    /// The contract would be deployed to an existing account, e.g. one of a fork
+    CreateCollision { address: Address },
     #[error(transparent)]
     /// Error which occurred during ABI encoding / decoding of data
     AbiError(#[from] ethers::contract::AbiError),
@@ -51,6 +105,368 @@ pub enum EvmError {
     Eyre(#[from] eyre::Error),
 }
 
+/// A write of a call in a read-only session, see `Executor::with_read_only` of the sputnik
+/// executor
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error(
+    "read-only violation: {address:?} tried to {}",
+    .slot.map_or_else(|| "modify its account".to_string(), |slot| format!("write slot {:?}", slot))
+)]
+pub struct ReadOnlyViolation {
+    /// The account which would have been modified
+    pub address: Address,
+    /// The storage slot which would have been written, `None` for any other change like a value
+    /// transfer, a deployment, a selfdestruct or a log
+    pub slot: Option<H256>,
+}
+
+/// The outcome of a single call, with everything needed to make assertions about it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallResult {
+    /// Whether the call succeeded
+    pub success: bool,
+    /// Whether the call was rejected by a failing `assume`, i.e. the inputs should be discarded
+    /// instead of counting the call as a revert
+    pub rejected: bool,
+    /// The gas used by the call, without the base and calldata costs
+    pub gas_used: u64,
+    /// The gas refunded by the call, already deducted from `gas_used`
+    pub gas_refunded: u64,
+    /// Whether a call frame exceeded the memory limit of the executor. The frame fails like it
+    /// ran out of gas, so the call only failed if no caller continued after it.
+    pub memory_limit_exceeded: bool,
+    /// Whether the call failed because it ran out of gas. Subcalls which ran out of gas don't
+    /// count if the caller continued.
+    pub out_of_gas: bool,
+    /// The reason why the call failed, the message of an `Error(string)` or `Panic(uint)`
+    /// revert is decoded, see [decoded_revert](Self::decoded_revert) for the decoded parameters
+    pub reverted_reason: Option<String>,
+    /// The data returned by the call
+    pub out: Bytes,
+    /// The logs emitted during the call
+    pub logs: Vec<String>,
+    /// The raw event logs emitted during the call
+    pub raw_logs: Vec<RawLog>,
+    /// The event logs, decoded with the ABI of the executor
+    decoded_logs: Vec<DecodedLog>,
+    /// The revert data, decoded with the ABI of the executor
+    decoded_revert: Option<RevertReason>,
+    /// The accounts changed by the call, only recorded by calls which ask for them
+    pub state_changes: StateChanges,
+    /// The write which failed the call in a read-only session
+    pub read_only_violation: Option<ReadOnlyViolation>,
+    /// The address a contract creation during the call collided with, because it already had
+    /// code or a nonce. The call only fails if it doesn't handle the failed creation.
+    pub create_collision: Option<Address>,
+    /// Whether a call or deployment during the call exceeded the call depth limit, which fails
+    /// it with `CallTooDeep`. Like with any failed subcall, the call only fails if it doesn't
+    /// handle the failure.
+    pub call_depth_exceeded: bool,
+}
+
+impl CallResult {
+    /// The event logs emitted during the call, decoded with the ABI the executor was configured
+    /// with. All logs are [DecodedLog::Raw] if there was no ABI.
+    pub fn decoded_logs(&self) -> &[DecodedLog] {
+        &self.decoded_logs
+    }
+
+    /// Decodes the raw logs of this result with the given ABI
+    pub fn decode_logs(mut self, abi: Option<&Abi>) -> Self {
+        self.decoded_logs =
+            self.raw_logs.iter().cloned().map(|log| DecodedLog::decode(log, abi)).collect();
+        self
+    }
+
+    /// Why the call reverted, decoded with the ABI the executor was configured with. `None` if
+    /// the call did not revert or was rejected by `assume`.
+    pub fn decoded_revert(&self) -> Option<&RevertReason> {
+        self.decoded_revert.as_ref()
+    }
+
+    /// Decodes the output of this result with the given ABI, if it reverted
+    pub fn decode_revert(mut self, abi: Option<&Abi>) -> Self {
+        self.decoded_revert =
+            (!self.success && !self.rejected).then(|| RevertReason::decode(&self.out, abi));
+        self
+    }
+}
+
+/// A call of a bundle, see `Executor::simulate_bundle` of the sputnik executor
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Call {
+    pub from: Address,
+    pub to: Address,
+    pub calldata: Bytes,
+    pub value: U256,
+    /// The EVM version to run only this call under, instead of the configured one
+    pub spec_override: Option<EvmVersion>,
+}
+
+/// The outcome of a bundle of calls
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BundleResult {
+    /// The results of the executed calls in order, an atomic bundle stops at the first call
+    /// which failed
+    pub results: Vec<CallResult>,
+    /// The index of the first call which failed
+    pub failed_call: Option<usize>,
+    /// Whether all changes of the bundle were rolled back, because it was atomic and a call
+    /// failed
+    pub rolled_back: bool,
+    /// The accounts changed by the whole bundle, empty if it was rolled back
+    pub state_changes: StateChanges,
+}
+
+impl BundleResult {
+    /// Whether all calls of the bundle succeeded
+    pub fn success(&self) -> bool {
+        self.failed_call.is_none()
+    }
+}
+
+/// Whether `Executor::run_setup` of the sputnik executor continues after a call failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// The calls after the failed one don't run
+    StopOnFirstError,
+    /// All calls run, each one on top of the state left by the calls before, whether they
+    /// failed or not
+    ContinueAndCollect,
+}
+
+/// The outcome of a sequence of setup calls
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SetupResult {
+    /// The results of the executed calls in order
+    pub results: Vec<CallResult>,
+    /// The indexes of the calls which failed, at most one if the setup stopped on the first
+    /// error
+    pub failed_calls: Vec<usize>,
+}
+
+impl SetupResult {
+    /// Whether all calls of the setup succeeded
+    pub fn success(&self) -> bool {
+        self.failed_calls.is_empty()
+    }
+
+    /// The calls which failed with their index, e.g. to report why each of them reverted
+    pub fn failures(&self) -> impl Iterator<Item = (usize, &CallResult)> {
+        self.failed_calls.iter().map(move |i| (*i, &self.results[*i]))
+    }
+}
+
+/// The outcome of a successful deployment
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeployResult {
+    /// The address of the deployed contract
+    pub address: Address,
+    /// The size of the deployed code in bytes
+    pub code_size: usize,
+    /// The gas used by the deployment, without the base cost
+    pub gas_used: u64,
+    /// The logs emitted by the constructor
+    pub logs: Vec<String>,
+    /// The raw event logs emitted by the constructor
+    pub raw_logs: Vec<RawLog>,
+    /// The salt of the deployment, if it was deployed with `CREATE2`
+    pub salt: Option<H256>,
+    /// The keccak256 hash of the init code, which determines the address of a `CREATE2`
+    /// deployment
+    pub init_code_hash: H256,
+}
+
+/// The accounts and storage slots changed by a call
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateChanges {
+    /// The changed accounts, unchanged accounts are left out
+    pub accounts: BTreeMap<Address, AccountChange>,
+}
+
+/// How a single account was changed, all values are `(before, after)`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountChange {
+    /// Whether the account was empty before, e.g. a deployed contract or the recipient of its
+    /// first transfer
+    pub created: bool,
+    /// Whether the account was destroyed, its storage isn't listed then
+    pub destroyed: bool,
+    pub balance: Option<(U256, U256)>,
+    pub nonce: Option<(U256, U256)>,
+    /// The written slots whose value changed
+    pub storage: BTreeMap<H256, (H256, H256)>,
+}
+
+/// An assertion cheatcode which failed. The test fails, but unlike a failed `require` the
+/// execution continues, so all failed assertions of a test are reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssertionFailure {
+    /// The contract which made the assertion
+    pub address: Address,
+    /// Describes the compared values, e.g. `assertEq failed: 1 != 2`
+    pub message: String,
+}
+
+/// What the revert of a call is expected to look like
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevertExpectation {
+    /// The call reverted for any reason
+    Any,
+    /// The call reverted with this decoded reason, e.g. the message of a `require`
+    Message(String),
+    /// The revert data starts with this selector, e.g. the one of a custom error
+    Selector([u8; 4]),
+    /// The revert data is exactly this
+    Bytes(Bytes),
+}
+
+impl RevertExpectation {
+    /// Checks that the call reverted as expected, the error describes the mismatch
+    pub fn check(&self, res: &CallResult) -> Result<()> {
+        if res.success {
+            eyre::bail!("call did not revert, expected it to revert with {}", self)
+        }
+        if res.rejected {
+            eyre::bail!("call was rejected by `assume`, expected it to revert with {}", self)
+        }
+        let reason = res.reverted_reason.as_deref().unwrap_or_default();
+        let matches = match self {
+            RevertExpectation::Any => true,
+            RevertExpectation::Message(msg) => reason == msg,
+            RevertExpectation::Selector(selector) => res.out.as_ref().starts_with(selector),
+            RevertExpectation::Bytes(bytes) => res.out == *bytes,
+        };
+        if !matches {
+            eyre::bail!(
+                "call reverted with `{}` (data: 0x{}), expected it to revert with {}",
+                reason,
+                hex::encode(&res.out),
+                self
+            )
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for RevertExpectation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RevertExpectation::Any => f.write_str("any reason"),
+            RevertExpectation::Message(msg) => write!(f, "`{}`", msg),
+            RevertExpectation::Selector(selector) => {
+                write!(f, "selector 0x{}", hex::encode(selector))
+            }
+            RevertExpectation::Bytes(bytes) => write!(f, "data 0x{}", hex::encode(bytes)),
+        }
+    }
+}
+
+/// An event log, decoded by the matching event of an ABI if there is one
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedLog {
+    /// The log of a known event, with its parameters in order.
+    ///
+    /// Indexed parameters of dynamic types, like `string`, are only stored as the hash of their
+    /// value in the log, so they are decoded as `bytes32`.
+    Event { name: String, params: Vec<(String, Token)> },
+    /// A log which matched no event of the ABI
+    Raw(RawLog),
+}
+
+impl DecodedLog {
+    /// Decodes the log with the events of the ABI.
+    ///
+    /// Events are matched by their signature first. Anonymous events have no signature, so they
+    /// are only tried if no other event matched, the first one which can decode the log wins.
+    pub fn decode(log: RawLog, abi: Option<&Abi>) -> Self {
+        let abi = match abi {
+            Some(abi) => abi,
+            None => return Self::Raw(log),
+        };
+        let signature = log.topics.first().copied();
+        let (named, anonymous): (Vec<_>, Vec<_>) = abi.events().partition(|event| !event.anonymous);
+        let candidates =
+            named.into_iter().filter(|event| Some(event.signature()) == signature).chain(anonymous);
+        for event in candidates {
+            if let Ok(parsed) = event.parse_log(log.clone()) {
+                let params = parsed.params.into_iter().map(|param| (param.name, param.value));
+                return Self::Event { name: event.name.clone(), params: params.collect() }
+            }
+        }
+        Self::Raw(log)
+    }
+}
+
+/// The decoded data of a revert
+#[derive(Debug, Clone, PartialEq)]
+pub enum RevertReason {
+    /// A custom error of the ABI, with its parameters in order
+    Custom { name: String, params: Vec<(String, Token)> },
+    /// The message of an `Error(string)`, e.g. of a failing `require`
+    Message(String),
+    /// A `Panic(uint256)` of the solidity compiler, e.g. on an arithmetic overflow
+    Panic(U256),
+    /// Data which is neither one of the above, e.g. empty data of a plain `revert()`
+    Raw(Bytes),
+}
+
+impl RevertReason {
+    /// `bytes4(keccak256("Error(string)"))`
+    const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+    /// `bytes4(keccak256("Panic(uint256)"))`
+    const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+    /// Decodes the revert data, custom errors are matched against the errors of the ABI by
+    /// their selector
+    pub fn decode(data: &[u8], abi: Option<&Abi>) -> Self {
+        if data.len() < 4 {
+            return Self::Raw(data.to_vec().into())
+        }
+        let (selector, params) = data.split_at(4);
+        if let Some(abi) = abi {
+            for error in abi.errors().filter(|error| error.signature()[..4] == *selector) {
+                if let Ok(tokens) = error.decode(params) {
+                    let names = error.inputs.iter().map(|input| input.name.clone());
+                    let params = names.zip(tokens).collect();
+                    return Self::Custom { name: error.name.clone(), params }
+                }
+            }
+        }
+        let decoded = if selector == Self::ERROR_SELECTOR {
+            ethers::abi::decode(&[ethers::abi::ParamType::String], params)
+        } else if selector == Self::PANIC_SELECTOR {
+            ethers::abi::decode(&[ethers::abi::ParamType::Uint(256)], params)
+        } else {
+            return Self::Raw(data.to_vec().into())
+        };
+        match decoded.ok().and_then(|mut tokens| tokens.pop()) {
+            Some(Token::String(msg)) => Self::Message(msg),
+            Some(Token::Uint(code)) => Self::Panic(code),
+            _ => Self::Raw(data.to_vec().into()),
+        }
+    }
+}
+
+impl std::fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RevertReason::Custom { name, params } => {
+                let params = params.iter().map(|(_, token)| foundry_utils::format_token(token));
+                write!(f, "{}({})", name, params.collect::<Vec<_>>().join(", "))
+            }
+            RevertReason::Message(msg) => f.write_str(msg),
+            RevertReason::Panic(code) => {
+                match u8::try_from(*code).ok().and_then(foundry_utils::panic_reason) {
+                    Some(reason) => f.write_str(reason),
+                    None => write!(f, "Panic(0x{:x})", code),
+                }
+            }
+            RevertReason::Raw(data) => write!(f, "0x{}", hex::encode(data)),
+        }
+    }
+}
+
 // TODO: Any reason this should be an async trait?
 /// Low-level abstraction layer for interfacing with various EVMs. Once instantiated, one
 /// only needs to specify the transaction parameters
@@ -63,6 +479,16 @@ pub trait Evm<State> {
 
     fn expected_revert(&self) -> Option<&[u8]>;
 
+    /// The caller of the calls which are made without an explicit caller, like
+    /// [setup](Self::setup) and [failed](Self::failed).
+    ///
+    /// This is the caller of the top level call only. A `prank` set up by a test contract
+    /// overrides the `msg.sender` of the calls made by the test contract itself, so both can be
+    /// combined without affecting each other.
+    fn sender(&self) -> Address {
+        Address::zero()
+    }
+
     /// Whether a return reason should be considered successful
     fn is_success(reason: &Self::ReturnReason) -> bool;
     /// Whether a return reason should be considered failing
@@ -95,9 +521,19 @@ pub trait Evm<State> {
     /// Gets all logs from the execution, regardless of reverts
     fn all_logs(&self) -> Vec<String>;
 
+    /// The assertion cheatcodes which failed since the state was last reset, a test with any
+    /// failed assertion fails
+    fn assertion_failures(&self) -> Vec<AssertionFailure> {
+        Vec::new()
+    }
+
     /// Performs a [`call_unchecked`](Self::call_unchecked), checks if execution reverted, and
     /// proceeds to return the decoded response to the user.
-    fn call<D: Detokenize, T: Tokenize, F: IntoFunction>(
+    ///
+    /// The changes of the call are **kept**: storage writes, deployments, balance transfers and
+    /// the increased nonce of the caller are visible to the following calls. Use
+    /// [`call_dry`](Self::call_dry) to query a contract without modifying the state.
+    fn call_committing<D: Detokenize, T: Tokenize, F: IntoFunction>(
         &mut self,
         from: Address,
         to: Address,
@@ -119,11 +555,54 @@ pub trait Evm<State> {
         }
     }
 
+    /// Performs the call like [`call_committing`](Self::call_committing), but on a copy of the
+    /// state which is discarded afterwards, so that **none** of its changes are kept. The gas,
+    /// the logs and the traces of the call are still returned and recorded, like the ones of any
+    /// other call.
+    fn call_dry<D: Detokenize, T: Tokenize, F: IntoFunction>(
+        &mut self,
+        from: Address,
+        to: Address,
+        func: F,
+        args: T,
+        value: U256,
+        abi: Option<&Abi>,
+    ) -> std::result::Result<(D, Self::ReturnReason, u64, Vec<String>), EvmError>
+    where
+        State: Clone,
+    {
+        let state = self.state().clone();
+        let res = self.call_committing(from, to, func, args, value, abi);
+        // the traces are part of the state, but are kept
+        let traces = self.traces();
+        self.reset(state);
+        self.set_traces(traces);
+        res
+    }
+
+    /// Alias of [`call_committing`](Self::call_committing), which keeps the changes of the call
+    #[deprecated(note = "use `call_committing` to keep the changes or `call_dry` to discard them")]
+    fn call<D: Detokenize, T: Tokenize, F: IntoFunction>(
+        &mut self,
+        from: Address,
+        to: Address,
+        func: F,
+        args: T,
+        value: U256,
+        abi: Option<&Abi>,
+    ) -> std::result::Result<(D, Self::ReturnReason, u64, Vec<String>), EvmError> {
+        self.call_committing(from, to, func, args, value, abi)
+    }
+
     fn traces(&self) -> Vec<CallTraceArena> {
         vec![]
     }
 
     fn reset_traces(&mut self) {}
+
+    /// Replaces the recorded traces, e.g. to keep the traces of a call whose state is discarded
+    fn set_traces(&mut self, _traces: Vec<CallTraceArena>) {}
+
     /// Executes the specified EVM call against the state
     // TODO: Should we just make this take a `TransactionRequest` or other more
     // ergonomic type?
@@ -167,8 +646,14 @@ pub trait Evm<State> {
     fn setup(&mut self, address: Address) -> Result<(Self::ReturnReason, Vec<String>)> {
         let span = tracing::trace_span!("setup", ?address);
         let _enter = span.enter();
-        let (_, status, _, logs) =
-            self.call::<(), _, _>(Address::zero(), address, "setUp()", (), 0.into(), None)?;
+        let (_, status, _, logs) = self.call_committing::<(), _, _>(
+            self.sender(),
+            address,
+            "setUp()",
+            (),
+            0.into(),
+            None,
+        )?;
         Ok((status, logs))
     }
 
@@ -176,8 +661,8 @@ pub trait Evm<State> {
     /// see whether the `failed` state var is set. This is to allow compatibility
     /// with dapptools-style DSTest smart contracts to preserve emitting of logs
     fn failed(&mut self, address: Address) -> Result<bool> {
-        let (failed, _, _, _) = self.call::<bool, _, _>(
-            Address::zero(),
+        let (failed, _, _, _) = self.call_committing::<bool, _, _>(
+            self.sender(),
             address,
             "failed()(bool)",
             (),
@@ -208,6 +693,9 @@ pub trait Evm<State> {
         if self.expected_revert().is_some() {
             success = false;
         }
+        if !self.assertion_failures().is_empty() {
+            success = false;
+        }
 
         // Check Success output: Should Fail vs Success
         //
@@ -253,7 +741,7 @@ mod test_helpers {
             evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
 
         let (_, status1, _, _) = evm
-            .call::<(), _, _>(
+            .call_committing::<(), _, _>(
                 Address::zero(),
                 addr,
                 "greet(string)",
@@ -264,7 +752,7 @@ mod test_helpers {
             .unwrap();
 
         let (retdata, status2, _, _) = evm
-            .call::<String, _, _>(
+            .call_committing::<String, _, _>(
                 Address::zero(),
                 addr,
                 "greeting()(string)",
@@ -281,6 +769,50 @@ mod test_helpers {
         });
     }
 
+    pub fn dry_calls_discard_changes<S: Clone, E: Evm<S>>(
+        mut evm: E,
+        compiled: CompactContractRef,
+    ) {
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let greeting = |evm: &mut E| {
+            evm.call_dry::<String, _, _>(
+                Address::zero(),
+                addr,
+                "greeting()(string)",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap()
+            .0
+        };
+
+        let (_, _, gas, _) = evm
+            .call_dry::<(), _, _>(
+                Address::zero(),
+                addr,
+                "greet(string)",
+                "hi".to_owned(),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert!(gas > 0);
+        assert_eq!(greeting(&mut evm), "");
+
+        evm.call_committing::<(), _, _>(
+            Address::zero(),
+            addr,
+            "greet(string)",
+            "hi".to_owned(),
+            0.into(),
+            compiled.abi,
+        )
+        .unwrap();
+        assert_eq!(greeting(&mut evm), "hi");
+    }
+
     pub fn solidity_unit_test<S, E: Evm<S>>(mut evm: E, compiled: CompactContractRef) {
         let (addr, _, _, _) =
             evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
@@ -289,7 +821,14 @@ mod test_helpers {
         let status1 = evm.setup(addr).unwrap().0;
 
         let (_, status2, _, _) = evm
-            .call::<(), _, _>(Address::zero(), addr, "testGreeting()", (), 0.into(), compiled.abi)
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "testGreeting()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
             .unwrap();
 
         vec![status1, status2].iter().for_each(|reason| {
@@ -300,3 +839,115 @@ mod test_helpers {
         // TODO: Add testFail
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_compute_create2_address() {
+        // the examples of EIP-1014
+        let hash = H256::from(ethers::utils::keccak256([0u8]));
+        assert_eq!(
+            compute_create2_address(Address::zero(), H256::zero(), hash),
+            "4D1A2e2bB4F88F0250f26Ffff098B0b30B26BF38".parse().unwrap()
+        );
+        assert_eq!(
+            compute_create2_address(
+                "deadbeef00000000000000000000000000000000".parse().unwrap(),
+                H256::zero(),
+                hash
+            ),
+            "B928f69Bb1D91Cd65274e3c79d8986362984fDA3".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn can_decode_logs() {
+        let abi: Abi = serde_json::from_str(
+            r#"[
+                {"type":"event","name":"Named","anonymous":false,"inputs":[
+                    {"name":"key","type":"string","indexed":true},
+                    {"name":"value","type":"uint256","indexed":false}
+                ]},
+                {"type":"event","name":"Anon","anonymous":true,"inputs":[
+                    {"name":"who","type":"address","indexed":true}
+                ]}
+            ]"#,
+        )
+        .unwrap();
+        let named = abi.event("Named").unwrap();
+        let key = H256::from(ethers::utils::keccak256("key"));
+        let log = RawLog {
+            topics: vec![named.signature(), key],
+            data: ethers::abi::encode(&[Token::Uint(7.into())]),
+        };
+        assert_eq!(
+            DecodedLog::decode(log.clone(), Some(&abi)),
+            DecodedLog::Event {
+                name: "Named".to_string(),
+                params: vec![
+                    ("key".to_string(), Token::FixedBytes(key.as_bytes().to_vec())),
+                    ("value".to_string(), Token::Uint(7.into())),
+                ],
+            }
+        );
+        assert_eq!(DecodedLog::decode(log.clone(), None), DecodedLog::Raw(log));
+
+        let who = Address::repeat_byte(1);
+        let log = RawLog { topics: vec![H256::from(who)], data: vec![] };
+        assert_eq!(
+            DecodedLog::decode(log, Some(&abi)),
+            DecodedLog::Event {
+                name: "Anon".to_string(),
+                params: vec![("who".to_string(), Token::Address(who))],
+            }
+        );
+
+        // no event has two topics and no data
+        let log = RawLog { topics: vec![H256::zero(), H256::zero()], data: vec![] };
+        assert_eq!(DecodedLog::decode(log.clone(), Some(&abi)), DecodedLog::Raw(log));
+    }
+
+    #[test]
+    fn can_decode_reverts() {
+        let abi: Abi = serde_json::from_str(
+            r#"[{"type":"error","name":"Unauthorized","inputs":[
+                {"name":"caller","type":"address"},
+                {"name":"amount","type":"uint256"}
+            ]}]"#,
+        )
+        .unwrap();
+        let caller = Address::repeat_byte(1);
+        let mut data = abi.error("Unauthorized").unwrap().signature()[..4].to_vec();
+        data.extend(ethers::abi::encode(&[Token::Address(caller), Token::Uint(5.into())]));
+        let reason = RevertReason::decode(&data, Some(&abi));
+        assert_eq!(
+            reason,
+            RevertReason::Custom {
+                name: "Unauthorized".to_string(),
+                params: vec![
+                    ("caller".to_string(), Token::Address(caller)),
+                    ("amount".to_string(), Token::Uint(5.into())),
+                ],
+            }
+        );
+        assert_eq!(RevertReason::decode(&data, None), RevertReason::Raw(data.into()));
+
+        let mut data = RevertReason::ERROR_SELECTOR.to_vec();
+        data.extend(ethers::abi::encode(&[Token::String("not allowed".to_string())]));
+        assert_eq!(
+            RevertReason::decode(&data, Some(&abi)),
+            RevertReason::Message("not allowed".to_string())
+        );
+
+        let mut data = RevertReason::PANIC_SELECTOR.to_vec();
+        data.extend(ethers::abi::encode(&[Token::Uint(0x11.into())]));
+        let reason = RevertReason::decode(&data, None);
+        assert_eq!(reason, RevertReason::Panic(0x11.into()));
+        assert_eq!(reason.to_string(), "Arithmetic over/underflow");
+        assert_eq!(RevertReason::Panic(0x99.into()).to_string(), "Panic(0x99)");
+
+        assert_eq!(RevertReason::decode(&[], None), RevertReason::Raw(Bytes::default()));
+    }
+}
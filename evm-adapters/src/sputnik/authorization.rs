@@ -0,0 +1,95 @@
+//! EIP-7702 authorizations, which let an EOA delegate to the code of a contract
+use ethers::{
+    signers::{LocalWallet, Signer},
+    types::{Address, Signature, SignatureError, H256, U256},
+    utils::{keccak256, rlp::RlpStream},
+};
+
+/// The prefix of the code of a delegating account, followed by the address of its delegate
+pub const DELEGATION_PREFIX: [u8; 3] = [0xef, 0x01, 0x00];
+
+/// Prefixes the signed payload of an authorization, so it can't be mistaken for a transaction
+const AUTHORIZATION_MAGIC: u8 = 0x05;
+
+/// A signed authorization of an EOA, the authority, to run the code at `address` whenever it is
+/// called
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authorization {
+    /// The chain the authorization is valid on, `0` for all chains
+    pub chain_id: U256,
+    /// The account whose code is run, the zero address removes the delegation
+    pub address: Address,
+    /// The nonce of the authority, which is increased once the authorization is applied
+    pub nonce: u64,
+    pub signature: Signature,
+}
+
+/// An authorization which was not applied, the remaining authorizations and the call are
+/// executed regardless
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedAuthorization {
+    /// The position of the authorization in the list
+    pub index: usize,
+    /// Why the authorization is invalid
+    pub reason: String,
+}
+
+impl Authorization {
+    /// The hash the authority signs, `keccak256(0x05 || rlp([chain_id, address, nonce]))`
+    pub fn signing_hash(chain_id: U256, address: Address, nonce: u64) -> H256 {
+        let mut rlp = RlpStream::new_list(3);
+        rlp.append(&chain_id);
+        rlp.append(&address);
+        rlp.append(&nonce);
+        let mut payload = vec![AUTHORIZATION_MAGIC];
+        payload.extend_from_slice(&rlp.out());
+        keccak256(payload).into()
+    }
+
+    /// Signs the authorization to delegate the wallet's account to `address`
+    pub fn sign(wallet: &LocalWallet, chain_id: U256, address: Address, nonce: u64) -> Self {
+        let signature = wallet.sign_hash(Self::signing_hash(chain_id, address, nonce), false);
+        Self { chain_id, address, nonce, signature }
+    }
+
+    /// Recovers the authority which signed the authorization
+    pub fn authority(&self) -> Result<Address, SignatureError> {
+        self.signature.recover(Self::signing_hash(self.chain_id, self.address, self.nonce))
+    }
+}
+
+/// The code of an account which delegates to `address`
+pub fn delegation_code(address: Address) -> Vec<u8> {
+    let mut code = DELEGATION_PREFIX.to_vec();
+    code.extend_from_slice(address.as_bytes());
+    code
+}
+
+/// The delegate of an account with the given code, if the account delegates
+pub fn delegated_address(code: &[u8]) -> Option<Address> {
+    match code.strip_prefix(&DELEGATION_PREFIX[..]) {
+        Some(address) if address.len() == 20 => Some(Address::from_slice(address)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_recover_authority() {
+        let wallet: LocalWallet =
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        let delegate = Address::repeat_byte(1);
+        let auth = Authorization::sign(&wallet, 1.into(), delegate, 3);
+        assert_eq!(auth.authority().unwrap(), wallet.address());
+
+        // the signature doesn't cover another nonce
+        let auth = Authorization { nonce: 4, ..auth };
+        assert_ne!(auth.authority().ok(), Some(wallet.address()));
+
+        assert_eq!(delegated_address(&delegation_code(delegate)), Some(delegate));
+        assert_eq!(delegated_address(&[0xef, 0x01, 0x00]), None);
+    }
+}
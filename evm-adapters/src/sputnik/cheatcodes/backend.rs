@@ -1,7 +1,8 @@
 //! Cheatcode-enabled backend implementation
-use super::Cheatcodes;
+use super::{cheatcode_handler::CHEATCODE_ADDRESS, Cheatcodes, BLOCK_HASH_WINDOW};
 use ethers::types::{H160, H256, U256};
 use sputnik::backend::{Backend, Basic};
+use std::collections::BTreeSet;
 
 #[derive(Debug, Clone)]
 /// A cheatcode backend is a wrapper around the inner backend which returns the
@@ -16,60 +17,109 @@ pub struct CheatcodeBackend<B> {
     pub backend: B,
     /// The enabled cheatcodes
     pub cheats: Cheatcodes,
+    /// The balance of the accounts the inner backend doesn't know, only set for the simple
+    /// backend with [with_default_balance](crate::sputnik::Executor::with_default_balance)
+    pub(crate) default_balance: Option<DefaultBalance>,
+}
+
+/// The balance of the accounts the inner backend doesn't know
+#[derive(Debug, Clone)]
+pub(crate) struct DefaultBalance {
+    pub balance: U256,
+    /// The precompiles, which keep the balance of the inner backend like the cheatcode address
+    /// and the coinbase
+    pub precompiles: BTreeSet<H160>,
+}
+
+impl<B: Backend> CheatcodeBackend<B> {
+    /// Moves the block number to `target`, like `roll`.
+    ///
+    /// The blocks after the one of the inner backend only exist locally, so they get their
+    /// [synthetic_block_hash](super::synthetic_block_hash), including the block rolled to, unless a
+    /// hash was set. Moving back forgets the hashes of the blocks after the target.
+    pub fn roll_to(&mut self, target: U256) {
+        let origin = self.backend.block_number();
+        let end = target.saturating_add(U256::one());
+        self.cheats.block_hashes.truncate(end);
+        // a fork knows the hash of the forked block
+        let first = if self.backend.block_hash(origin).is_zero() { origin } else { origin + 1 };
+        self.cheats.block_hashes.advance(first, end);
+        self.cheats.env.block_number = Some(target);
+    }
 }
 
 impl<B: Backend> Backend for CheatcodeBackend<B> {
-    // TODO: Override the return values based on the values of `self.cheats`
     fn gas_price(&self) -> U256 {
-        self.backend.gas_price()
+        self.cheats.env.gas_price.unwrap_or_else(|| self.backend.gas_price())
     }
 
     fn origin(&self) -> H160 {
-        self.cheats.origin.unwrap_or_else(|| self.backend.origin())
+        self.cheats.env.origin.unwrap_or_else(|| self.backend.origin())
     }
 
     fn block_hash(&self, number: U256) -> H256 {
-        self.cheats
-            .block_hashes
-            .get(&number)
-            .cloned()
-            .unwrap_or_else(|| self.backend.block_hash(number))
+        // the window moves with the block number set by `roll`, the current block only has a
+        // hash once it was rolled to
+        let current = self.block_number();
+        let rolled = self.cheats.env.block_number.is_some();
+        if number > current ||
+            current - number > BLOCK_HASH_WINDOW.into() ||
+            (number == current && !rolled)
+        {
+            return H256::zero()
+        }
+        self.cheats.block_hashes.get(number).unwrap_or_else(|| self.backend.block_hash(number))
     }
 
     fn block_number(&self) -> U256 {
-        self.cheats.block_number.unwrap_or_else(|| self.backend.block_number())
+        self.cheats.env.block_number.unwrap_or_else(|| self.backend.block_number())
     }
 
     fn block_coinbase(&self) -> H160 {
-        self.backend.block_coinbase()
+        self.cheats.env.block_coinbase.unwrap_or_else(|| self.backend.block_coinbase())
     }
 
     fn block_timestamp(&self) -> U256 {
-        self.cheats.block_timestamp.unwrap_or_else(|| self.backend.block_timestamp())
+        self.cheats.env.block_timestamp.unwrap_or_else(|| self.backend.block_timestamp())
     }
 
     fn block_base_fee_per_gas(&self) -> U256 {
-        self.cheats.block_base_fee_per_gas.unwrap_or_else(|| self.backend.block_base_fee_per_gas())
+        self.cheats
+            .env
+            .block_base_fee_per_gas
+            .unwrap_or_else(|| self.backend.block_base_fee_per_gas())
     }
 
     fn block_difficulty(&self) -> U256 {
-        self.backend.block_difficulty()
+        self.cheats.env.block_difficulty.unwrap_or_else(|| self.backend.block_difficulty())
     }
 
     fn block_gas_limit(&self) -> U256 {
-        self.backend.block_gas_limit()
+        self.cheats.env.block_gas_limit.unwrap_or_else(|| self.backend.block_gas_limit())
     }
 
     fn chain_id(&self) -> U256 {
-        self.backend.chain_id()
+        self.cheats.env.chain_id.unwrap_or_else(|| self.backend.chain_id())
     }
 
+    // accounts with the default balance still don't exist, so that creating them costs gas like
+    // on chain
     fn exists(&self, address: H160) -> bool {
         self.backend.exists(address)
     }
 
     fn basic(&self, address: H160) -> Basic {
-        self.backend.basic(address)
+        match &self.default_balance {
+            Some(default)
+                if !self.backend.exists(address) &&
+                    address != *CHEATCODE_ADDRESS &&
+                    address != self.block_coinbase() &&
+                    !default.precompiles.contains(&address) =>
+            {
+                Basic { balance: default.balance, nonce: U256::zero() }
+            }
+            _ => self.backend.basic(address),
+        }
     }
 
     fn code(&self, address: H160) -> Vec<u8> {
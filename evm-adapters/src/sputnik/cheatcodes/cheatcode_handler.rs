@@ -1,43 +1,52 @@
 //! Hooks to EVM execution
 use super::{
-    backend::CheatcodeBackend, memory_stackstate_owned::MemoryStackStateOwned, ConsoleCalls,
-    HEVMCalls, HevmConsoleEvents,
+    backend::{CheatcodeBackend, DefaultBalance},
+    memory_stackstate_owned::MemoryStackStateOwned,
+    CheatContext, CheatResult, CheatcodeExtension, CheatcodeRegistrations, Cheatcodes,
+    ConsoleCalls, ExecutionEnv, HEVMCalls, HevmConsoleEvents, StorageSource, ValueCollector,
+    DEFAULT_BLOCK_TIME, HEVM_ABI, MAX_BLOBS_PER_TX, MIN_BLOB_BASE_FEE,
 };
 use crate::{
-    call_tracing::{CallTrace, CallTraceArena, LogCallOrder},
-    sputnik::{cheatcodes::memory_stackstate_owned::ExpectedEmit, Executor, SputnikExecutor},
-    Evm, ASSUME_MAGIC_RETURN_CODE,
+    call_tracing::{CallKind, CallTrace, CallTraceArena, CallTraceNode, LogCallOrder},
+    sputnik::{
+        cheatcodes::memory_stackstate_owned::ExpectedEmit, delegated_address, CodeKind,
+        CoverageMap, DumpState, ExecutionLimits, Executor, MultiForkBackend, ReplayedTransaction,
+        SharedBackend, SputnikExecutor, StateDump, TxOutcome,
+    },
+    storage_layout::{mapping_slot, nested_mapping_slot},
+    AssertionFailure, Evm, ReadOnlyViolation, ASSUME_MAGIC_RETURN_CODE,
 };
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use std::{fs::File, io::Read, path::Path};
 
 use sputnik::{
-    backend::Backend,
+    backend::{Backend, MemoryBackend},
     executor::stack::{
         Log, PrecompileFailure, PrecompileOutput, PrecompileSet, StackExecutor, StackExitKind,
         StackState, StackSubstateMetadata,
     },
-    gasometer, Capture, Config, Context, CreateScheme, ExitError, ExitReason, ExitRevert,
-    ExitSucceed, Handler, Memory, Opcode, Runtime, Transfer,
+    gasometer, Capture, Config, Context, CreateScheme, ExitError, ExitFatal, ExitReason,
+    ExitRevert, ExitSucceed, Handler, Machine, Memory, Opcode, Runtime, Transfer,
 };
-use std::{process::Command, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
 use ethers::{
-    abi::{RawLog, Token},
+    abi::{ParamType, RawLog, Token},
     contract::EthLogDecode,
     core::{abi::AbiDecode, k256::ecdsa::SigningKey, utils},
     signers::{LocalWallet, Signer},
     solc::{artifacts::CompactContractBytecode, ProjectPathsConfig},
-    types::{Address, H160, H256, U256},
+    types::{Address, EIP1186ProofResponse, Transaction, H160, H256, I256, U256},
 };
 
 use std::{convert::Infallible, str::FromStr};
 
 use crate::sputnik::cheatcodes::{
     debugger::{CheatOp, DebugArena, DebugNode, DebugStep, OpCode},
-    memory_stackstate_owned::Prank,
-    patch_hardhat_console_log_selector,
+    format_console_log,
+    memory_stackstate_owned::{AccessedSlots, ExpectedCall, Prank},
+    patch_hardhat_console_log_selector, ExecutionHook, FfiConfig, TraceNodeCallback,
 };
 use once_cell::sync::Lazy;
 
@@ -50,6 +59,77 @@ pub static CHEATCODE_ADDRESS: Lazy<Address> = Lazy::new(|| {
     Address::from_slice(&hex::decode("7109709ECfa91a80626fF3989D68f67F5b1DD12D").unwrap())
 });
 
+/// Which contracts can use the cheatcodes. For all others, calls to the [`CHEATCODE_ADDRESS`]
+/// are regular calls to an account without code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheatcodeAccess {
+    /// Every contract can use the cheatcodes
+    All,
+    /// Only the contract which is called by the transaction, e.g. the test contract
+    TopLevel,
+    /// Only the given contracts
+    Only(BTreeSet<Address>),
+}
+
+impl Default for CheatcodeAccess {
+    fn default() -> Self {
+        CheatcodeAccess::All
+    }
+}
+
+impl CheatcodeAccess {
+    /// Whether the contract can use the cheatcodes, `depth` being the depth of its call frame
+    fn allows(&self, caller: Address, depth: Option<usize>) -> bool {
+        match self {
+            CheatcodeAccess::All => true,
+            CheatcodeAccess::TopLevel => depth == Some(0),
+            CheatcodeAccess::Only(callers) => callers.contains(&caller),
+        }
+    }
+}
+
+/// How the `deal` cheatcode for tokens writes the balance, for tokens whose `balanceOf` is not
+/// the stored value, e.g. rebasing tokens which store shares.
+///
+/// The `balanceOf` is always checked after writing the balance, and a warning is logged if it
+/// doesn't return the dealt amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DealStrategy {
+    /// Writes the amount as is, the default
+    Exact,
+    /// Scales the written value until `balanceOf` returns the amount, like converting the amount
+    /// to shares
+    Scale,
+}
+
+impl Default for DealStrategy {
+    fn default() -> Self {
+        DealStrategy::Exact
+    }
+}
+
+/// How often the written balance is scaled by [`DealStrategy::Scale`]
+const DEAL_SCALE_STEPS: usize = 4;
+
+/// The default maximum memory of all open call frames of a transaction, far more than any
+/// transaction can pay for with a regular gas limit, but low enough that executions without gas
+/// metering can't exhaust the host memory
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 30;
+
+/// The value written to a storage slot to find out whether a call returns the slot
+static DEAL_PROBE: Lazy<H256> = Lazy::new(|| H256::from(utils::keccak256("foundry deal probe")));
+
+/// The indices of the `_owners` and `_balances` mappings of OpenZeppelin's ERC721
+const ERC721_OWNERS_INDEX: u64 = 2;
+const ERC721_BALANCES_INDEX: u64 = 3;
+
+/// The EIP-4844 opcodes, which sputnik doesn't know and which are executed by the handler
+const BLOBHASH: Opcode = Opcode(0x49);
+const BLOBBASEFEE: Opcode = Opcode(0x4a);
+
+/// The gas limit of a call made to find a storage slot
+const PROBE_GAS: u64 = 1_000_000;
+
 // This is the address used by console.sol, vendored by nomiclabs/hardhat:
 // https://github.com/nomiclabs/hardhat/blob/master/packages/hardhat-core/console.sol
 pub static CONSOLE_ADDRESS: Lazy<Address> = Lazy::new(|| {
@@ -103,7 +183,53 @@ pub static DUMMY_OUTPUT: [u8; 320] = [0u8; 320];
 pub struct CheatcodeHandler<H> {
     handler: H,
     enable_ffi: bool,
+    ffi: FfiConfig,
     console_logs: Vec<String>,
+    /// Hooks into the execution, in the order they were registered
+    hooks: Vec<Rc<RefCell<dyn ExecutionHook>>>,
+    /// Custom cheatcodes, in the order they were registered
+    extensions: Vec<Rc<RefCell<Box<dyn CheatcodeExtension>>>>,
+    /// The limits which take precedence over the ones of the config
+    limits: ExecutionLimits,
+    /// What happened during the transactions since it was last taken
+    outcome: TxOutcome,
+    /// Collects the pushed and compared values, if enabled
+    value_collector: Option<ValueCollector>,
+    /// Receives the completed trace nodes if they are streamed instead of buffered, in which case
+    /// the buffered nodes only keep what identifies the call
+    trace_stream: Option<Rc<RefCell<TraceNodeCallback<Box<dyn FnMut(&CallTraceNode)>>>>>,
+    /// Whether the executed program counters are recorded
+    coverage_enabled: bool,
+    coverage: CoverageMap,
+    /// Kind and hash of the code which is currently executed, if coverage is enabled
+    coverage_code: Option<(CodeKind, H256)>,
+    /// Whether `SELFDESTRUCT` only deletes accounts created in the same transaction
+    eip6780: bool,
+    /// The memory of the open call frames below the one which is executed
+    memory_in_use: usize,
+    /// The memory of the executed call frame before its last step, which is counted against the
+    /// limit of the frames it calls
+    frame_memory: usize,
+    /// Mocked precompiles, which take precedence over the precompile set
+    precompile_overrides: PrecompileOverrides,
+    /// Which contracts can use the cheatcodes
+    cheatcode_access: CheatcodeAccess,
+    deal_strategy: DealStrategy,
+}
+
+/// A mocked precompile, called with the input, the gas limit, the context and whether the call
+/// is static, like a [`PrecompileFn`](crate::sputnik::PrecompileFn)
+pub type PrecompileOverride =
+    Rc<dyn Fn(&[u8], Option<u64>, &Context, bool) -> Result<PrecompileOutput, PrecompileFailure>>;
+
+/// The precompiles mocked by a test, by address
+#[derive(Clone, Default)]
+struct PrecompileOverrides(BTreeMap<Address, PrecompileOverride>);
+
+impl std::fmt::Debug for PrecompileOverrides {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
 }
 
 pub(crate) fn convert_log(log: Log) -> Option<String> {
@@ -151,7 +277,10 @@ impl<'a, 'b, B: Backend, P: PrecompileSet> SputnikExecutor<CheatcodeStackState<'
     for CheatcodeStackExecutor<'a, 'b, B, P>
 {
     fn config(&self) -> &Config {
-        self.handler.config()
+        match self.limits.spec_override {
+            Some(config) => config,
+            None => self.handler.config(),
+        }
     }
 
     fn state(&self) -> &CheatcodeStackState<'a, B> {
@@ -176,10 +305,42 @@ impl<'a, 'b, B: Backend, P: PrecompileSet> SputnikExecutor<CheatcodeStackState<'
         self.state().trace_enabled
     }
 
+    fn set_tracing_paused(&mut self, paused: bool) {
+        self.state_mut().trace_paused = paused;
+    }
+
     fn debug_calls(&self) -> Vec<DebugArena> {
         self.state().debug_steps.clone()
     }
 
+    fn set_coverage_enabled(&mut self, enabled: bool) {
+        self.coverage_enabled = enabled;
+    }
+
+    fn execution_limits(&self) -> ExecutionLimits {
+        self.limits
+    }
+
+    fn set_execution_limits(&mut self, limits: ExecutionLimits) -> eyre::Result<()> {
+        if limits.value_collector != self.limits.value_collector {
+            self.value_collector = limits.value_collector.map(ValueCollector::new);
+        }
+        self.limits = limits;
+        Ok(())
+    }
+
+    fn coverage(&self) -> CoverageMap {
+        self.coverage.clone()
+    }
+
+    fn set_coverage(&mut self, coverage: CoverageMap) {
+        self.coverage = coverage;
+    }
+
+    fn collected_values(&self) -> Vec<U256> {
+        self.value_collector.as_ref().map(ValueCollector::values).unwrap_or_default()
+    }
+
     fn gas_left(&self) -> U256 {
         // NB: We do this to avoid `function cannot return without recursing`
         U256::from(self.state().metadata().gasometer().gas())
@@ -198,6 +359,10 @@ impl<'a, 'b, B: Backend, P: PrecompileSet> SputnikExecutor<CheatcodeStackState<'
         self.handler.state().all_logs.clone()
     }
 
+    fn assertion_failures(&self) -> Vec<AssertionFailure> {
+        self.handler.state().assertion_failures.clone()
+    }
+
     fn transact_call(
         &mut self,
         caller: H160,
@@ -207,71 +372,25 @@ impl<'a, 'b, B: Backend, P: PrecompileSet> SputnikExecutor<CheatcodeStackState<'
         gas_limit: u64,
         access_list: Vec<(H160, Vec<H256>)>,
     ) -> (ExitReason, Vec<u8>) {
-        // reset all_logs because its a new call
-        self.state_mut().all_logs = vec![];
-
-        let transaction_cost = gasometer::call_transaction_cost(&data, &access_list);
-        match self.state_mut().metadata_mut().gasometer_mut().record_transaction(transaction_cost) {
-            Ok(()) => (),
-            Err(e) => return (e.into(), Vec::new()),
-        }
-
-        // Initialize initial addresses for EIP-2929
-        if self.config().increase_state_access_gas {
-            let addresses = core::iter::once(caller).chain(core::iter::once(address));
-            self.state_mut().metadata_mut().access_addresses(addresses);
-
-            self.handler.initialize_with_access_list(access_list);
-        }
-
-        self.state_mut().inc_nonce(caller);
-
-        let context = Context { caller, address, apparent_value: value };
-
-        match self.call_inner(
-            address,
-            Some(Transfer { source: caller, target: address, value }),
-            data,
-            Some(gas_limit),
-            false,
-            false,
-            false,
-            context,
-        ) {
-            Capture::Exit((s, v)) => {
-                if self.state().trace_enabled {
-                    self.state_mut().increment_call_index();
-                }
-
-                // check if all expected calls were made
-                if let Some((address, expecteds)) =
-                    self.state().expected_calls.iter().find(|(_, expecteds)| !expecteds.is_empty())
-                {
-                    return (
-                        ExitReason::Revert(ExitRevert::Reverted),
-                        ethers::abi::encode(&[Token::String(format!(
-                            "Expected a call to 0x{} with data {}, but got none",
-                            address,
-                            ethers::types::Bytes::from(expecteds[0].clone())
-                        ))]),
-                    )
-                }
+        let transfer = Transfer { source: caller, target: address, value };
+        self.with_spec_override(|evm| {
+            evm.transact_call_inner(caller, address, Some(transfer), data, gas_limit, access_list)
+        })
+        .unwrap_or_else(|err| (err.into(), Vec::new()))
+    }
 
-                if !self.state().expected_emits.is_empty() {
-                    return (
-                        ExitReason::Revert(ExitRevert::Reverted),
-                        ethers::abi::encode(&[Token::String(
-                            "Expected an emit, but no logs were emitted afterward".to_string(),
-                        )]),
-                    )
-                }
-                (s, v)
-            }
-            Capture::Trap(_) => {
-                self.state_mut().increment_call_index();
-                unreachable!()
-            }
-        }
+    fn transact_static_call(
+        &mut self,
+        caller: H160,
+        address: H160,
+        data: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Vec<u8>) {
+        self.with_spec_override(|evm| {
+            evm.transact_call_inner(caller, address, None, data, gas_limit, access_list)
+        })
+        .unwrap_or_else(|err| (err.into(), Vec::new()))
     }
 
     fn transact_create(
@@ -282,41 +401,33 @@ impl<'a, 'b, B: Backend, P: PrecompileSet> SputnikExecutor<CheatcodeStackState<'
         gas_limit: u64,
         access_list: Vec<(H160, Vec<H256>)>,
     ) -> ExitReason {
-        // reset all_logs because its a new call
-        self.state_mut().all_logs = vec![];
-
-        let transaction_cost = gasometer::create_transaction_cost(&init_code, &access_list);
-        match self.state_mut().metadata_mut().gasometer_mut().record_transaction(transaction_cost) {
-            Ok(()) => (),
-            Err(e) => return e.into(),
-        };
-        self.handler.initialize_with_access_list(access_list);
+        let scheme = CreateScheme::Legacy { caller };
+        self.transact_create_with_scheme(caller, scheme, value, init_code, gas_limit, access_list)
+    }
 
-        match self.create_inner(
-            caller,
-            CreateScheme::Legacy { caller },
-            value,
-            init_code,
-            Some(gas_limit),
-            false,
-        ) {
-            Capture::Exit((s, _, _)) => {
-                if self.state().trace_enabled {
-                    self.state_mut().increment_call_index();
-                }
-                s
-            }
-            Capture::Trap(_) => {
-                self.state_mut().increment_call_index();
-                unreachable!()
-            }
-        }
+    fn transact_create2(
+        &mut self,
+        caller: H160,
+        value: U256,
+        init_code: Vec<u8>,
+        salt: H256,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> ExitReason {
+        let code_hash = H256::from(utils::keccak256(&init_code));
+        let scheme = CreateScheme::Create2 { caller, code_hash, salt };
+        self.transact_create_with_scheme(caller, scheme, value, init_code, gas_limit, access_list)
     }
 
     fn create_address(&self, scheme: CreateScheme) -> Address {
         self.handler.create_address(scheme)
     }
 
+    fn is_precompile(&self, address: Address) -> bool {
+        self.precompile_overrides.0.contains_key(&address) ||
+            self.handler.precompiles().is_precompile(address)
+    }
+
     fn clear_logs(&mut self) {
         self.console_logs.clear();
         self.state_mut().substate.logs_mut().clear()
@@ -327,6 +438,14 @@ impl<'a, 'b, B: Backend, P: PrecompileSet> SputnikExecutor<CheatcodeStackState<'
         logs.into_iter().map(|log| RawLog { topics: log.topics, data: log.data }).collect()
     }
 
+    fn emitted_logs(&self) -> Vec<Log> {
+        self.state().substate.logs().to_vec()
+    }
+
+    fn take_outcome(&mut self) -> TxOutcome {
+        std::mem::take(&mut self.outcome)
+    }
+
     fn traces(&self) -> Vec<CallTraceArena> {
         self.state().traces.clone()
     }
@@ -335,10 +454,31 @@ impl<'a, 'b, B: Backend, P: PrecompileSet> SputnikExecutor<CheatcodeStackState<'
         self.state_mut().reset_traces();
     }
 
+    fn set_traces(&mut self, traces: Vec<CallTraceArena>) {
+        self.state_mut().set_traces(traces);
+    }
+
+    fn labels(&self) -> BTreeMap<Address, String> {
+        self.state().labels.clone()
+    }
+
+    fn set_label(&mut self, address: Address, label: String) -> eyre::Result<()> {
+        self.state_mut().labels.insert(address, label);
+        Ok(())
+    }
+
+    fn cheatcodes_enabled(&self) -> bool {
+        true
+    }
+
     fn logs(&self) -> Vec<String> {
         let logs = self.state().substate.logs().to_vec();
         logs.into_iter().filter_map(convert_log).chain(self.console_logs.clone()).collect()
     }
+
+    fn console_logs(&self) -> Vec<String> {
+        self.console_logs.clone()
+    }
 }
 
 /// A [`MemoryStackStateOwned`] state instantiated over a [`CheatcodeBackend`]
@@ -349,6 +489,59 @@ pub type CheatcodeStackState<'a, B> = MemoryStackStateOwned<'a, CheatcodeBackend
 pub type CheatcodeStackExecutor<'a, 'b, B, P> =
     CheatcodeHandler<StackExecutor<'a, 'b, CheatcodeStackState<'a, B>, P>>;
 
+/// A copy of the state of a cheatcode-enabled [`Executor`] which can be sent to other threads,
+/// to run independent scenarios on top of the same state in parallel, see
+/// [`Executor::fork_session`].
+#[derive(Clone, Debug)]
+pub struct ForkSession<B> {
+    backend: B,
+    cheats: Cheatcodes,
+    /// The accounts touched by the executor the session was created from
+    overlay: StateDump,
+    labels: BTreeMap<Address, String>,
+    storage_sources: BTreeMap<Address, StorageSource>,
+    gas_limit: u64,
+    enable_ffi: bool,
+    ffi: FfiConfig,
+    enable_trace: bool,
+    debug: bool,
+    eip6780: bool,
+    limits: ExecutionLimits,
+    cheatcode_access: CheatcodeAccess,
+    deal_strategy: DealStrategy,
+}
+
+impl<B: Backend + Clone> ForkSession<B> {
+    /// Creates a new executor on top of the state of the session. Its changes are only visible
+    /// to itself, so every thread can create its own executor from the same session.
+    pub fn executor<'a, 'b, P: PrecompileSet>(
+        &self,
+        config: &'a Config,
+        precompiles: &'b P,
+    ) -> Executor<CheatcodeStackState<'a, B>, CheatcodeStackExecutor<'a, 'b, B, P>> {
+        let mut evm = Executor::new_with_cheatcodes(
+            self.backend.clone(),
+            self.gas_limit,
+            config,
+            precompiles,
+            self.enable_ffi,
+            self.enable_trace,
+            self.debug,
+        );
+        evm.set_ffi_config(self.ffi.clone());
+        evm.set_eip6780(self.eip6780);
+        evm.set_cheatcode_access(self.cheatcode_access.clone());
+        evm.set_deal_strategy(self.deal_strategy);
+        evm.executor.set_execution_limits(self.limits).expect("cheatcodes honor every limit");
+        let state = evm.executor.state_mut();
+        state.backend.cheats = self.cheats.clone();
+        state.labels = self.labels.clone();
+        state.storage_sources = self.storage_sources.clone();
+        self.overlay.apply_to(state);
+        evm
+    }
+}
+
 impl<'a, 'b, B: Backend, P: PrecompileSet>
     Executor<CheatcodeStackState<'a, B>, CheatcodeStackExecutor<'a, 'b, B, P>>
 {
@@ -363,7 +556,8 @@ impl<'a, 'b, B: Backend, P: PrecompileSet>
         debug: bool,
     ) -> Self {
         // make this a cheatcode-enabled backend
-        let backend = CheatcodeBackend { backend, cheats: Default::default() };
+        let backend =
+            CheatcodeBackend { backend, cheats: Default::default(), default_balance: None };
 
         // create the memory stack state (owned, so that we can modify the backend via
         // self.state_mut on the transact_call fn)
@@ -372,7 +566,27 @@ impl<'a, 'b, B: Backend, P: PrecompileSet>
 
         // create the executor and wrap it with the cheatcode handler
         let executor = StackExecutor::new_with_precompiles(state, config, precompiles);
-        let executor = CheatcodeHandler { handler: executor, enable_ffi, console_logs: Vec::new() };
+        let executor = CheatcodeHandler {
+            handler: executor,
+            enable_ffi,
+            ffi: Default::default(),
+            console_logs: Vec::new(),
+            hooks: Vec::new(),
+            extensions: Vec::new(),
+            limits: Default::default(),
+            outcome: Default::default(),
+            value_collector: None,
+            coverage_enabled: false,
+            coverage: Default::default(),
+            coverage_code: None,
+            eip6780: false,
+            memory_in_use: 0,
+            frame_memory: 0,
+            precompile_overrides: Default::default(),
+            cheatcode_access: Default::default(),
+            deal_strategy: Default::default(),
+            trace_stream: None,
+        };
 
         let mut evm = Executor::from_executor(executor, gas_limit);
 
@@ -385,1512 +599,4357 @@ impl<'a, 'b, B: Backend, P: PrecompileSet>
 
         evm
     }
-}
 
-// helper for creating an exit type
-fn evm_error(retdata: &str) -> Capture<(ExitReason, Vec<u8>), Infallible> {
-    Capture::Exit((
-        ExitReason::Revert(ExitRevert::Reverted),
-        ethers::abi::encode(&[Token::String(retdata.to_owned())]),
-    ))
-}
+    /// Copies the state of the executor into a session, which executors on other threads can be
+    /// created from.
+    ///
+    /// The accounts touched by this executor are copied, while the backend is cloned. The clones
+    /// of a [`SharedBackend`] share its cache, so remote state fetched by any session is fetched
+    /// only once, while the local changes of every session stay isolated. The sender, the ABI,
+    /// the traces and the gas report are not part of the session.
+    pub fn fork_session(&self) -> ForkSession<B>
+    where
+        B: Clone,
+    {
+        let state = self.executor.state();
+        ForkSession {
+            backend: state.backend.backend.clone(),
+            cheats: state.backend.cheats.clone(),
+            overlay: state.dump_state(),
+            labels: state.labels.clone(),
+            storage_sources: state.storage_sources.clone(),
+            gas_limit: self.gas_limit,
+            enable_ffi: self.executor.enable_ffi,
+            ffi: self.executor.ffi.clone(),
+            enable_trace: state.trace_enabled,
+            debug: state.debug_enabled,
+            eip6780: self.executor.eip6780,
+            limits: self.executor.limits,
+            cheatcode_access: self.executor.cheatcode_access.clone(),
+            deal_strategy: self.executor.deal_strategy,
+        }
+    }
 
-// helper for creating the Expected Revert return type, based on if there was a call or a create,
-// and if there was any decoded retdata that matched the expected revert value.
-fn revert_return_evm<T: ToString>(
-    call: bool,
-    result: Option<(&[u8], &[u8])>,
-    err: impl FnOnce() -> T,
-) -> ExpectRevertReturn {
-    let success =
-        result.map(|(retdata, expected_revert)| retdata == expected_revert).unwrap_or(false);
+    /// Registers a hook into the execution of all following calls and deployments, see
+    /// [`ExecutionHook`] for when it is invoked.
+    ///
+    /// The returned handle gives access to the hook, e.g. to read what it collected. The hooks
+    /// are shared with all clones of the executor, but not with [fork sessions](ForkSession).
+    pub fn add_hook<H: ExecutionHook + 'static>(&mut self, hook: H) -> Rc<RefCell<H>> {
+        let hook = Rc::new(RefCell::new(hook));
+        self.executor.hooks.push(hook.clone());
+        hook
+    }
 
-    match (success, call) {
-        // Success case for CALLs needs to return a dummy output value which
-        // can be decoded
-        (true, true) => ExpectRevertReturn::Call(Capture::Exit((
-            ExitReason::Succeed(ExitSucceed::Returned),
-            DUMMY_OUTPUT.to_vec(),
-        ))),
-        // Success case for CREATE doesn't need to return any value but must return a
-        // dummy address
-        (true, false) => ExpectRevertReturn::Create(Capture::Exit((
-            ExitReason::Succeed(ExitSucceed::Returned),
-            Some(Address::from_str("0000000000000000000000000000000000000001").unwrap()),
-            Vec::new(),
-        ))),
-        // Failure cases just return the abi encoded error
-        (false, true) => ExpectRevertReturn::Call(Capture::Exit((
-            ExitReason::Revert(ExitRevert::Reverted),
-            ethers::abi::encode(&[Token::String(err().to_string())]),
-        ))),
-        (false, false) => ExpectRevertReturn::Create(Capture::Exit((
-            ExitReason::Revert(ExitRevert::Reverted),
-            None,
-            ethers::abi::encode(&[Token::String(err().to_string())]),
-        ))),
+    /// Registers custom cheatcodes, which handle the calls to the cheatcode address with a
+    /// selector none of the built-in cheatcodes has, see [`CheatcodeExtension`].
+    ///
+    /// Like hooks, extensions are shared with all clones of the executor, but not with
+    /// [fork sessions](ForkSession).
+    #[must_use]
+    pub fn with_cheatcode_extension(mut self, extension: Box<dyn CheatcodeExtension>) -> Self {
+        self.executor.extensions.push(Rc::new(RefCell::new(extension)));
+        self
     }
-}
 
-impl<'a, 'b, B: Backend, P: PrecompileSet> CheatcodeStackExecutor<'a, 'b, B, P> {
-    /// Checks whether the provided call reverted with an expected revert reason.
-    fn expected_revert(
-        &mut self,
-        res: ExpectRevertReturn,
-        expected_revert: Option<Vec<u8>>,
-    ) -> ExpectRevertReturn {
-        // return early if there was no revert expected
-        let expected_revert = match expected_revert {
-            Some(inner) => inner,
-            None => return res,
-        };
+    /// Streams the trace of all following calls and deployments to the callback, which is called
+    /// with every node once its call frame completed, in post-order, see
+    /// [`ExecutionHook::trace_node`]. This enables tracing.
+    ///
+    /// Instead of buffering the whole trace, the buffered nodes of calls only keep the selector of
+    /// their calldata and drop their output and logs, so long running executions don't hold on
+    /// to them. Deployments are kept whole. This is enough for the gas report and the names of
+    /// the flamegraph frames, but the buffered trace can't be decoded anymore, so register a hook
+    /// with [`add_hook`](Self::add_hook) instead to also keep the full trace.
+    ///
+    /// Unlike hooks, the callback doesn't inspect the executed steps.
+    pub fn on_trace_node(&mut self, callback: impl FnMut(&CallTraceNode) + 'static) {
+        self.executor.set_tracing_enabled(true);
+        self.executor.trace_stream =
+            Some(Rc::new(RefCell::new(TraceNodeCallback(Box::new(callback)))));
+    }
 
-        let call = res.is_call();
+    /// Applies the `SELFDESTRUCT` rules of EIP-6780, as activated with Cancun: the account is only
+    /// deleted if it was created in the same transaction, otherwise only its balance is sent.
+    ///
+    /// Sputnik implements the rules up to London, where every account which selfdestructed is
+    /// deleted at the end of the transaction, so this is disabled by default.
+    pub fn set_eip6780(&mut self, enabled: bool) {
+        self.executor.eip6780 = enabled;
+    }
 
-        // If the call was successful (i.e. did not revert) return
-        // an error. Otherwise, get the return data
-        let data = match res {
-            ExpectRevertReturn::Create(Capture::Exit((ExitReason::Revert(_e), None, revdata))) => {
-                Some(revdata)
-            }
-            ExpectRevertReturn::Call(Capture::Exit((ExitReason::Revert(_e), revdata))) => {
-                Some(revdata)
-            }
-            _ => return revert_return_evm(call, None, || "Expected revert did not revert"),
-        };
+    /// Restricts which contracts can use the cheatcodes, e.g. so that contracts of a fork can't
+    /// trigger them, see [`CheatcodeAccess`]
+    pub fn set_cheatcode_access(&mut self, access: CheatcodeAccess) {
+        self.executor.cheatcode_access = access;
+    }
 
-        // if there was no revert data return an error
-        let data = match data {
-            Some(inner) => inner,
-            None => {
-                return revert_return_evm(call, None, || "Expected revert did not revert with data")
-            }
-        };
+    /// Only lets the given contracts use the cheatcodes, see
+    /// [`set_cheatcode_access`](Self::set_cheatcode_access)
+    #[must_use]
+    pub fn with_cheatcodes_for(mut self, callers: impl IntoIterator<Item = Address>) -> Self {
+        self.set_cheatcode_access(CheatcodeAccess::Only(callers.into_iter().collect()));
+        self
+    }
 
-        // do the actual check
-        if data.len() >= 4 && data[0..4] == [8, 195, 121, 160] {
-            // its a revert string
-            let decoded_data = ethers::abi::decode(&[ethers::abi::ParamType::Bytes], &data[4..])
-                .expect("String error code, but not actual string");
+    /// Sets how the `deal` cheatcode writes token balances, see [`DealStrategy`]
+    pub fn set_deal_strategy(&mut self, strategy: DealStrategy) {
+        self.executor.deal_strategy = strategy;
+    }
 
-            let decoded_data =
-                decoded_data[0].clone().into_bytes().expect("Can never fail because it is bytes");
+    /// Restricts the commands which can be run by the `ffi` cheatcode, if ffi is enabled
+    pub fn set_ffi_config(&mut self, config: FfiConfig) {
+        self.executor.ffi = config;
+    }
 
-            let err = || {
-                format!(
-                    "Error != expected error: '{}' != '{}'",
-                    String::from_utf8_lossy(&decoded_data[..]),
-                    String::from_utf8_lossy(&expected_revert)
-                )
-            };
-            revert_return_evm(call, Some((&decoded_data, &expected_revert)), err)
-        } else {
-            let err = || {
-                format!(
-                    "Error data != expected error data: 0x{} != 0x{}",
-                    hex::encode(&data),
-                    hex::encode(&expected_revert)
-                )
-            };
-            revert_return_evm(call, Some((&data, &expected_revert)), err)
-        }
+    /// Sets the seconds the timestamp advances per block in [roll](Self::roll), and the seconds
+    /// per block the `skip` cheatcode advances the block number by
+    pub fn set_block_time(&mut self, seconds: u64) {
+        self.executor.state_mut().backend.cheats.block_time = Some(seconds);
     }
 
-    /// Given a transaction's calldata, it tries to parse it a console call and print the call
-    fn console_log(&mut self, input: Vec<u8>) -> Capture<(ExitReason, Vec<u8>), Infallible> {
-        // replacing hardhat style selectors (`uint`) with abigen style (`uint256`)
-        let input = patch_hardhat_console_log_selector(input);
-        let decoded = match ConsoleCalls::decode(&input) {
-            Ok(inner) => inner,
-            Err(err) => return evm_error(&err.to_string()),
+    /// Advances the block number by `blocks` and the timestamp by the block time for every
+    /// block.
+    ///
+    /// The skipped blocks get deterministic hashes, so that `blockhash` returns a non-zero value
+    /// for them, like it would on chain, and like with the `roll` cheatcode the block rolled to
+    /// has one as well. A forked block keeps its real hash.
+    pub fn roll(&mut self, blocks: u64) {
+        let state = self.executor.state_mut();
+        let number = state.block_number();
+        let timestamp = state.block_timestamp();
+        let block_time = state.backend.cheats.block_time.unwrap_or(DEFAULT_BLOCK_TIME);
+        state.backend.roll_to(number.saturating_add(blocks.into()));
+        state.backend.cheats.env.block_timestamp =
+            Some(timestamp.saturating_add(U256::from(blocks).saturating_mul(block_time.into())));
+    }
+
+    /// Sets the hash `blockhash` returns for the block, as long as it is one of the latest 256
+    /// blocks
+    pub fn set_block_hash(&mut self, number: u64, hash: H256) {
+        self.executor.state_mut().backend.cheats.block_hashes.insert(number.into(), hash);
+    }
+
+    /// Sets where the storage of the account is read from, by default slots written locally are
+    /// read from the overlay and all others from the backend.
+    ///
+    /// With [`StorageSource::RemoteAlways`], every read returns the value of the backend, e.g.
+    /// the forked chain, which lets diffing tools compare a slot before and after it was written
+    /// locally. Writes still go to the overlay, so the account's own code never sees them: a
+    /// reentrancy guard stays unlocked, a counter never increases, and `SSTORE` is charged as if
+    /// the slot still held the remote value. On a fork without a pinned block the remote value
+    /// can change between reads, so only use this with a pinned block if the results have to be
+    /// reproducible.
+    pub fn set_storage_source(&mut self, address: Address, source: StorageSource) {
+        let sources = &mut self.executor.state_mut().storage_sources;
+        match source {
+            StorageSource::OverlayFirst => sources.remove(&address),
+            source => sources.insert(address, source),
         };
-        self.console_logs.push(decoded.to_string());
-        Capture::Exit((ExitReason::Succeed(ExitSucceed::Stopped), vec![]))
     }
 
-    /// Adds CheatOp to the latest DebugArena
-    fn add_debug(&mut self, cheatop: CheatOp) {
-        if self.state().debug_enabled {
-            let depth =
-                if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
-            self.state_mut().debug_mut().push_node(
-                0,
-                DebugNode {
-                    address: *CHEATCODE_ADDRESS,
-                    depth,
-                    steps: vec![DebugStep {
-                        op: OpCode::from(cheatop),
-                        memory: Memory::new(0),
-                        ..Default::default()
-                    }],
-                    ..Default::default()
-                },
-            );
-        }
+    /// The expectations, mocks, pranks and recorded accesses which the cheatcodes registered and
+    /// which still apply to the following calls, e.g. to debug a registration which leaked out
+    /// of a test
+    pub fn cheatcode_state(&self) -> CheatcodeRegistrations {
+        self.executor.state().cheatcode_registrations()
     }
 
-    fn prank(
-        &mut self,
-        single_call: bool,
-        msg_sender: Address,
-        caller: Address,
-        origin: Option<Address>,
-    ) -> Result<(), Capture<(ExitReason, Vec<u8>), Infallible>> {
-        let curr_depth =
-            if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
+    /// Resets the cheatcodes to a clean slate between test cases which reuse the executor, and
+    /// returns what they had registered.
+    ///
+    /// This clears the pending `expectRevert`, `expectEmit` and `expectCall` expectations, the
+    /// `mockCall` mocks, the `prank` and `startPrank` of the caller and the accesses recorded
+    /// since `record`. Everything else is kept: the state, including balances set with `deal`,
+    /// storage written with `store` and nonces set with `setNonce`, the block and chain
+    /// overrides like `warp` and `roll`, labels, snapshots, fork sessions, and the logs and
+    /// assertion failures of earlier calls.
+    pub fn clear_cheatcode_state(&mut self) -> CheatcodeRegistrations {
+        self.executor.state_mut().take_cheatcode_registrations()
+    }
 
-        let prank = Prank {
-            prank_caller: msg_sender,
-            new_caller: caller,
-            new_origin: origin,
-            depth: curr_depth,
-        };
-        if single_call {
-            if self.state().next_prank.is_some() {
-                return Err(evm_error("You have an active `prank` call already. Use either `prank` or `startPrank`, not both"));
-            }
-            self.state_mut().next_prank = Some(prank);
-        } else {
-            // startPrank works by using frame depth to determine whether to overwrite
-            // msg.sender if we set a prank caller at a particular depth, it
-            // will continue to use the prank caller for any subsequent calls
-            // until stopPrank is called.
-            //
-            // We additionally have to store the original message sender of the cheatcode caller
-            // so that we dont apply it to any other addresses when depth ==
-            // prank_depth
-            if let Some(Prank { depth, prank_caller, .. }) = self.state().prank {
-                if curr_depth == depth && caller == prank_caller {
-                    return Err(evm_error("You have an active `startPrank` at this frame depth already. Use either `prank` or `startPrank`, not both"));
-                }
-            }
-            self.state_mut().prank = Some(prank);
-        }
-        Ok(())
+    /// The overrides of the environment the calls are executed in, like the block number set by
+    /// `roll`, see [env_mut](Self::env_mut)
+    pub fn env(&self) -> &ExecutionEnv {
+        &self.executor.state().backend.cheats.env
     }
 
-    fn expect_revert(
+    /// The overrides of the environment, to change it in place between calls. Values which are
+    /// not overridden are the ones of the backend, e.g. of the forked block.
+    ///
+    /// The block values, the chain id, `tx.origin` and `tx.gasprice` are read whenever they are
+    /// used, so changing them takes effect with the next call. Only code and state created
+    /// afterwards see the new values though: a new chain id changes `CHAINID` and the signatures
+    /// of the `sign` cheatcode, but signatures made before are still only valid for the old one.
+    /// The block gas limit is returned by `GASLIMIT` and is only the default gas of
+    /// [eth_call](Self::eth_call) and the cap of [estimate_gas](Self::estimate_gas), all other
+    /// calls get the [gas_limit](Self::gas_limit) of the executor.
+    pub fn env_mut(&mut self) -> &mut ExecutionEnv {
+        &mut self.executor.state_mut().backend.cheats.env
+    }
+
+    /// Sets the timestamp of the block, without changing the block number
+    pub fn warp(&mut self, timestamp: u64) {
+        self.executor.state_mut().backend.cheats.env.block_timestamp = Some(timestamp.into());
+    }
+
+    /// Sets the chain id returned by `CHAINID`, e.g. to sign for multiple chains in a test
+    pub fn set_chain_id(&mut self, chain_id: u64) {
+        self.executor.state_mut().backend.cheats.env.chain_id = Some(chain_id.into());
+    }
+
+    /// Sets the versioned hashes of the blobs of the transactions, which `BLOBHASH` returns, and
+    /// the zero hash for indexes past the last blob.
+    ///
+    /// Sputnik predates Cancun, so `BLOBHASH` and `BLOBBASEFEE` are only executed once blob
+    /// hashes or a [blob base fee](Self::with_blob_base_fee) are set. Calls and deployments fail
+    /// if there are more than [MAX_BLOBS_PER_TX] blobs.
+    #[must_use]
+    pub fn with_blob_hashes(mut self, hashes: Vec<H256>) -> Self {
+        self.env_mut().blob_hashes = hashes;
+        self
+    }
+
+    /// Sets the blob base fee of the block, which `BLOBBASEFEE` returns, see
+    /// [with_blob_hashes](Self::with_blob_hashes). It defaults to [MIN_BLOB_BASE_FEE] if only
+    /// blob hashes are set.
+    #[must_use]
+    pub fn with_blob_base_fee(mut self, blob_base_fee: U256) -> Self {
+        self.env_mut().blob_base_fee = Some(blob_base_fee);
+        self
+    }
+
+    /// Mocks the precompile at the given address, e.g. to make `ecrecover` fail or return a
+    /// fixed address. Calls to all other addresses still go to the regular precompiles.
+    ///
+    /// Only available in tests, overrides are not part of a [`ForkSession`].
+    #[cfg(any(test, feature = "sputnik-helpers"))]
+    pub fn override_precompile<F>(&mut self, address: Address, precompile: F)
+    where
+        F: Fn(&[u8], Option<u64>, &Context, bool) -> Result<PrecompileOutput, PrecompileFailure>
+            + 'static,
+    {
+        self.executor.precompile_overrides.0.insert(address, Rc::new(precompile));
+    }
+
+    /// Removes all mocked precompiles, see [`Executor::override_precompile`]
+    #[cfg(any(test, feature = "sputnik-helpers"))]
+    pub fn clear_precompile_overrides(&mut self) {
+        self.executor.precompile_overrides.0.clear();
+    }
+
+    /// Sets the ERC20 balance of `holder` to `amount` and, if a `spender` is given, also its
+    /// allowance for the tokens of `holder`, e.g. to fund an account and approve a router when
+    /// setting up a fork test.
+    ///
+    /// The slots are found like with the `deal` and `dealAllowance` cheatcodes and the total
+    /// supply is adjusted. Fails if `balanceOf(holder)` or `allowance(holder, spender)` don't
+    /// return the amount afterwards.
+    pub fn setup_token_position(
         &mut self,
-        inner: Vec<u8>,
-    ) -> Result<(), Capture<(ExitReason, Vec<u8>), Infallible>> {
-        self.add_debug(CheatOp::EXPECTREVERT);
-        if self.state().expected_revert.is_some() {
-            return Err(evm_error(
-                "You must call another function prior to expecting a second revert.",
-            ))
-        } else {
-            self.state_mut().expected_revert = Some(inner);
+        token: Address,
+        holder: Address,
+        spender: Option<Address>,
+        amount: U256,
+    ) -> eyre::Result<()> {
+        let executor = &mut self.executor;
+        executor.deal_token(token, holder, amount, true, None).map_err(cheatcode_error)?;
+        let balance_of =
+            [&utils::id("balanceOf(address)")[..], &ethers::abi::encode(&[Token::Address(holder)])]
+                .concat();
+        match executor.probe_balance(token, &balance_of) {
+            Some(balance) if balance == amount => {}
+            balance => eyre::bail!(
+                "balanceOf(0x{:x}) of token 0x{:x} is {} instead of {}",
+                holder,
+                token,
+                balance.map_or_else(|| "unknown".to_string(), |b| b.to_string()),
+                amount
+            ),
+        }
+        if let Some(spender) = spender {
+            executor
+                .deal_allowance(token, holder, spender, amount, None)
+                .map_err(cheatcode_error)?;
         }
         Ok(())
     }
+}
 
-    /// Given a transaction's calldata, it tries to parse it as an [`HEVM cheatcode`](super::HEVM)
-    /// call and modify the state accordingly.
-    fn apply_cheatcode(
-        &mut self,
-        input: Vec<u8>,
-        msg_sender: H160,
-    ) -> Capture<(ExitReason, Vec<u8>), Infallible> {
-        let mut res = vec![];
-        let pre_index = self.state().trace_index;
-        let trace = self.start_trace(*CHEATCODE_ADDRESS, input.clone(), 0.into(), false);
-        // Get a mutable ref to the state so we can apply the cheats
-        let decoded = match HEVMCalls::decode(&input) {
-            Ok(inner) => inner,
-            Err(err) => return evm_error(&err.to_string()),
-        };
+impl<'a, 'b, 'c, P: PrecompileSet>
+    Executor<
+        CheatcodeStackState<'a, MemoryBackend<'c>>,
+        CheatcodeStackExecutor<'a, 'b, MemoryBackend<'c>, P>,
+    >
+{
+    /// Gives every account which isn't part of the state of the backend the balance, e.g. to
+    /// experiment without having to `deal` to every account first. The precompiles, the
+    /// cheatcode address and the coinbase are left out, and the accounts still don't exist, so
+    /// transfers to them pay for creating the account like on chain.
+    ///
+    /// This is only available for the simple backend, since on a fork it would hide the real
+    /// balances. Accounts which are modified afterwards, e.g. with `deal` or by a transfer, keep
+    /// their written balance.
+    #[must_use]
+    pub fn with_default_balance(mut self, balance: U256) -> Self {
+        // the precompiles of all specs are at the lowest addresses
+        let precompiles = (1..=0xff)
+            .map(Address::from_low_u64_be)
+            .filter(|address| self.executor.is_precompile(*address))
+            .collect();
+        self.executor.state_mut().backend.default_balance =
+            Some(DefaultBalance { balance, precompiles });
+        self
+    }
 
-        match decoded {
-            HEVMCalls::Warp(inner) => {
-                self.add_debug(CheatOp::WARP);
-                self.state_mut().backend.cheats.block_timestamp = Some(inner.0);
-            }
-            HEVMCalls::Roll(inner) => {
-                self.add_debug(CheatOp::ROLL);
-                self.state_mut().backend.cheats.block_number = Some(inner.0);
-                // insert a random block hash for the specified block number if it was not
-                // specified already
-                self.state_mut()
-                    .backend
-                    .cheats
-                    .block_hashes
-                    .entry(inner.0)
-                    .or_insert_with(H256::random);
-            }
-            HEVMCalls::Fee(inner) => {
-                self.add_debug(CheatOp::FEE);
-                self.state_mut().backend.cheats.block_base_fee_per_gas = Some(inner.0);
-            }
-            HEVMCalls::Store(inner) => {
-                self.add_debug(CheatOp::STORE);
-                self.state_mut().set_storage(inner.0, inner.1.into(), inner.2.into());
-            }
-            HEVMCalls::Load(inner) => {
-                self.add_debug(CheatOp::LOAD);
-                res = self.state_mut().storage(inner.0, inner.1.into()).0.to_vec();
-            }
-            HEVMCalls::Ffi(inner) => {
-                self.add_debug(CheatOp::FFI);
-                let args = inner.0;
-                // if FFI is not explicitly enabled at runtime, do not let this be called
-                // (we could have an FFI cheatcode executor instead but feels like
-                // over engineering)
-                if !self.enable_ffi {
-                    return evm_error(
-                        "ffi disabled: run again with --ffi if you want to allow tests to call external scripts",
-                    );
-                }
+    /// Proofs can only be generated for the canonical state of a fork, so this always fails for
+    /// the simple backend, whose state is all local, see [`Executor::get_proof`]
+    pub fn get_proof(
+        &self,
+        address: Address,
+        _slots: &[H256],
+    ) -> eyre::Result<EIP1186ProofResponse> {
+        eyre::bail!(
+            "can't generate the proof of 0x{:x}: proofs require the remote canonical state of a \
+             fork, but the state of the simple backend is local",
+            address
+        )
+    }
+}
 
-                // execute the command & get the stdout
-                let output = match Command::new(&args[0]).args(&args[1..]).output() {
-                    Ok(res) => res.stdout,
-                    Err(err) => return evm_error(&err.to_string()),
-                };
+impl<'a, 'b, P: PrecompileSet>
+    Executor<
+        CheatcodeStackState<'a, MultiForkBackend>,
+        CheatcodeStackExecutor<'a, 'b, MultiForkBackend, P>,
+    >
+{
+    /// Switches to the fork with the given name. All locally deployed contracts and modified
+    /// accounts are kept, only the remote state is read from the newly selected fork.
+    pub fn select_fork(&mut self, name: &str) -> eyre::Result<()> {
+        self.executor.state_mut().backend.backend.select_fork(name)
+    }
 
-                // get the hex string & decode it
-                let output = unsafe { std::str::from_utf8_unchecked(&output) };
-                let decoded = match hex::decode(&output.trim()[2..]) {
-                    Ok(res) => res,
-                    Err(err) => return evm_error(&err.to_string()),
-                };
+    /// Fetches the merkle proof of the account and the storage slots from the selected fork, see
+    /// [`Executor::get_proof`]
+    pub fn get_proof(
+        &self,
+        address: Address,
+        slots: &[H256],
+    ) -> eyre::Result<EIP1186ProofResponse> {
+        ensure_remote_state(self.executor.state(), address, slots)?;
+        self.executor.state().backend.backend.get_proof(address, slots)
+    }
+}
 
-                // encode the data as Bytes
-                res = ethers::abi::encode(&[Token::Bytes(decoded.to_vec())]);
-            }
-            HEVMCalls::GetCode(inner) => {
-                self.add_debug(CheatOp::GETCODE);
+impl<'a, 'b, P: PrecompileSet>
+    Executor<
+        CheatcodeStackState<'a, SharedBackend>,
+        CheatcodeStackExecutor<'a, 'b, SharedBackend, P>,
+    >
+{
+    /// Fetches the given storage slots of a forked contract up front, so that a following
+    /// execution doesn't need to wait for them
+    pub fn warm_contract(&self, address: Address, slots: &[U256]) -> eyre::Result<()> {
+        self.executor.state().backend.backend.prefetch(address, slots)
+    }
 
-                let path = if inner.0.ends_with(".json") {
-                    Path::new(&inner.0).to_path_buf()
-                } else {
-                    let parts = inner.0.split(':').collect::<Vec<&str>>();
-                    let contract_file = parts[0];
-                    let contract_name = if parts.len() == 1 {
-                        parts[0].replace(".sol", "")
-                    } else {
-                        parts[1].to_string()
-                    };
+    /// Fetches the state the transaction accesses concurrently before it's replayed with
+    /// [`Executor::execute_transaction`], see [`SharedBackend::prefetch_for_tx`]
+    pub fn prefetch_for_tx(&self, tx: &Transaction) -> eyre::Result<()> {
+        self.executor.state().backend.backend.prefetch_for_tx(tx)
+    }
 
-                    let outdir = ProjectPathsConfig::find_artifacts_dir(Path::new("./"));
-                    outdir.join(format!("{}/{}.json", contract_file, contract_name))
-                };
+    /// Fetches the mined transaction with the hash and executes it with
+    /// [`Executor::execute_transaction`], with its sender as the `tx.origin` and at its gas price,
+    /// so that its result and trace can be compared with the ones on chain.
+    ///
+    /// The fork has to be at the state right before the transaction, in the environment of its
+    /// block, like when forking with its `fork_tx_hash`: the parent block is pinned, the
+    /// transactions preceding it are replayed, and a contract creation deploys to the same
+    /// address as on chain. Fails if the transaction is not mined or the fork is at another block.
+    pub fn replay_transaction(&mut self, tx_hash: H256) -> eyre::Result<ReplayedTransaction> {
+        let backend = &self.executor.state().backend.backend;
+        let tx = backend.transaction(tx_hash)?;
+        let block = tx
+            .block_number
+            .ok_or_else(|| eyre::eyre!("transaction {:?} is not mined", tx_hash))?
+            .as_u64();
+        let number = backend.block_number();
+        if number != block.into() {
+            eyre::bail!(
+                "the fork is at block {} while transaction {:?} is in block {}, fork with its \
+                 `fork_tx_hash`",
+                number,
+                tx_hash,
+                block
+            )
+        }
+        Ok(self.execute_with_tx_env(&tx))
+    }
 
-                let mut data = String::new();
-                match File::open(path) {
-                    Ok(mut file) => match file.read_to_string(&mut data) {
-                        Ok(_) => {}
-                        Err(e) => return evm_error(&e.to_string()),
-                    },
-                    Err(e) => return evm_error(&e.to_string()),
-                }
+    /// Executes the transaction with its sender as the `tx.origin` and at its gas price, and
+    /// restores the transaction environment afterwards
+    fn execute_with_tx_env(&mut self, tx: &Transaction) -> ReplayedTransaction {
+        let backend = &mut self.executor.state_mut().backend.backend;
+        let (origin, gas_price) = (backend.origin(), backend.gas_price());
+        backend.set_tx_env(tx.from, tx.gas_price.unwrap_or_default());
+        let replayed = self.execute_transaction(tx);
+        self.executor.state_mut().backend.backend.set_tx_env(origin, gas_price);
+        replayed
+    }
 
-                match serde_json::from_str::<CompactContractBytecode>(&data) {
-                    Ok(contract_file) => {
-                        if let Some(bin) =
-                            contract_file.bytecode.and_then(|bcode| bcode.object.into_bytes())
-                        {
-                            res = ethers::abi::encode(&[Token::Bytes(bin.to_vec())]);
-                        } else {
-                            return evm_error(
-                                "No bytecode for contract. is it abstract or unlinked?",
-                            )
-                        }
-                    }
-                    Err(e) => return evm_error(&e.to_string()),
-                }
-            }
-            HEVMCalls::Addr(inner) => {
-                self.add_debug(CheatOp::ADDR);
-                let sk = inner.0;
-                if sk.is_zero() {
-                    return evm_error("Bad Cheat Code. Private Key cannot be 0.")
-                }
-                // 256 bit priv key -> 32 byte slice
-                let mut bs: [u8; 32] = [0; 32];
-                sk.to_big_endian(&mut bs);
-                let xsk = match SigningKey::from_bytes(&bs) {
-                    Ok(xsk) => xsk,
-                    Err(err) => return evm_error(&err.to_string()),
-                };
-                let addr = utils::secret_key_to_address(&xsk);
-                res = ethers::abi::encode(&[Token::Address(addr)]);
-            }
-            HEVMCalls::Sign(inner) => {
-                self.add_debug(CheatOp::SIGN);
-                let sk = inner.0;
-                let digest = inner.1;
-                if sk.is_zero() {
-                    return evm_error("Bad Cheat Code. Private Key cannot be 0.")
-                }
-                // 256 bit priv key -> 32 byte slice
-                let mut bs: [u8; 32] = [0; 32];
-                sk.to_big_endian(&mut bs);
+    /// Fetches the merkle proof of the account and the storage slots at the forked block with
+    /// `eth_getProof`, e.g. to test light clients or bridges which verify proofs.
+    ///
+    /// Only the canonical state of the fork can be proven, so this fails if the account or one
+    /// of the slots were modified locally.
+    pub fn get_proof(
+        &self,
+        address: Address,
+        slots: &[H256],
+    ) -> eyre::Result<EIP1186ProofResponse> {
+        ensure_remote_state(self.executor.state(), address, slots)?;
+        self.executor.state().backend.backend.get_proof(address, slots)
+    }
 
-                let xsk = match SigningKey::from_bytes(&bs) {
-                    Ok(xsk) => xsk,
-                    Err(err) => return evm_error(&err.to_string()),
-                };
-                let wallet = LocalWallet::from(xsk).with_chain_id(self.handler.chain_id().as_u64());
+    /// Executes the transactions of the next block of the chain on top of the local state and
+    /// moves the block environment to that block, e.g. to run a keeper between real blocks.
+    ///
+    /// The transactions are replayed like with [`Executor::replay_transaction`], with their
+    /// sender as the `tx.origin` and at their gas price. The block number, timestamp and basefee
+    /// set with cheatcodes are reset, and no block rewards are paid.
+    ///
+    /// Fails without executing anything if the next block doesn't build on the block which was
+    /// executed last, e.g. because the chain was reorged.
+    pub fn advance_fork_block(&mut self) -> eyre::Result<Vec<ReplayedTransaction>> {
+        let backend = &self.executor.state().backend.backend;
+        let number = backend.block_number().as_u64();
+        let parent = match backend.block_env_hash() {
+            Some(hash) => hash,
+            None => backend
+                .block_with_txs(number)?
+                .hash
+                .ok_or_else(|| eyre::eyre!("block {} has no hash", number))?,
+        };
+        let block = backend.block_with_txs(number + 1)?;
+        if block.parent_hash != parent {
+            eyre::bail!(
+                "block {} does not build on the executed block {:?}, the chain was reorged",
+                number + 1,
+                parent
+            )
+        }
 
-                // The EVM precompile does not use EIP-155
-                let sig = wallet.sign_hash(digest.into(), false);
+        let state = self.executor.state_mut();
+        state.backend.backend.set_block_env(&block);
+        let cheats = &mut state.backend.cheats;
+        let env = &mut cheats.env;
+        env.block_number = None;
+        env.block_timestamp = None;
+        env.block_base_fee_per_gas = None;
+        env.block_difficulty = None;
+        env.block_coinbase = None;
+        env.block_gas_limit = None;
+        // the hashes of rolled blocks are synthetic, the backend knows the ones of the chain
+        cheats.block_hashes = Default::default();
+
+        Ok(block.transactions.iter().map(|tx| self.execute_with_tx_env(tx)).collect())
+    }
+}
 
-                let recovered = match sig.recover(digest) {
-                    Ok(rec) => rec,
-                    Err(e) => return evm_error(&e.to_string()),
-                };
+/// The order of the secp256k1 curve, private keys must be below it
+static SECP256K1_ORDER: Lazy<U256> = Lazy::new(|| {
+    U256::from_str_radix("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141", 16)
+        .unwrap()
+});
 
-                assert_eq!(recovered, wallet.address());
+// helper for turning the private key of a cheatcode into a signing key
+fn signing_key(sk: U256) -> Result<SigningKey, String> {
+    if sk.is_zero() {
+        return Err("Bad Cheat Code. Private Key cannot be 0.".to_string())
+    }
+    if sk >= *SECP256K1_ORDER {
+        return Err(
+            "Bad Cheat Code. Private Key must be less than the secp256k1 curve order.".to_string()
+        )
+    }
+    // 256 bit priv key -> 32 byte slice
+    let mut bs: [u8; 32] = [0; 32];
+    sk.to_big_endian(&mut bs);
+    SigningKey::from_bytes(&bs).map_err(|err| err.to_string())
+}
 
-                let mut r_bytes = [0u8; 32];
-                let mut s_bytes = [0u8; 32];
-                sig.r.to_big_endian(&mut r_bytes);
-                sig.s.to_big_endian(&mut s_bytes);
-                res = ethers::abi::encode(&[Token::Tuple(vec![
-                    Token::Uint(sig.v.into()),
-                    Token::FixedBytes(r_bytes.to_vec()),
-                    Token::FixedBytes(s_bytes.to_vec()),
-                ])]);
-            }
-            HEVMCalls::Prank0(inner) => {
-                self.add_debug(CheatOp::PRANK);
-                let caller = inner.0;
+// helper for reading the environment variable `key` as the given type, like ffi this touches the
+// host so it is only allowed with ffi enabled
+fn read_env(
+    enable_ffi: bool,
+    key: &str,
+    kind: ParamType,
+    default: Option<Token>,
+) -> Result<Token, String> {
+    if !enable_ffi {
+        return Err("env cheatcodes disabled: run again with --ffi if you want to allow tests to read environment variables".to_string())
+    }
+    let value = match (std::env::var(key), default) {
+        (Ok(value), _) => value,
+        (Err(_), Some(default)) => return Ok(default),
+        (Err(_), None) => return Err(format!("environment variable `{}` not found", key)),
+    };
+    parse_env_value(&kind, &value).map_err(|err| {
+        format!("failed to parse environment variable `{}` as {}: {}", key, kind, err)
+    })
+}
+
+/// Parses the value of an environment variable as `kind`, integers may be hex with a `0x` prefix
+/// while addresses and fixed bytes must be
+fn parse_env_value(kind: &ParamType, value: &str) -> Result<Token, String> {
+    if *kind == ParamType::String {
+        return Ok(Token::String(value.to_string()))
+    }
+    let value = value.trim();
+    match kind {
+        ParamType::Uint(_) => match value.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).map_err(|err| err.to_string()),
+            None => U256::from_dec_str(value).map_err(|err| err.to_string()),
+        }
+        .map(Token::Uint),
+        ParamType::Address => {
+            let hex = value.strip_prefix("0x").ok_or("missing 0x prefix")?;
+            Address::from_str(hex).map(Token::Address).map_err(|err| err.to_string())
+        }
+        ParamType::FixedBytes(len) => {
+            let hex = value.strip_prefix("0x").ok_or("missing 0x prefix")?;
+            match hex::decode(hex) {
+                Ok(bytes) if bytes.len() == *len => Ok(Token::FixedBytes(bytes)),
+                Ok(bytes) => Err(format!("expected {} bytes, got {}", len, bytes.len())),
+                Err(err) => Err(err.to_string()),
+            }
+        }
+        kind => Err(format!("{} is not supported", kind)),
+    }
+}
+
+/// Formats a value compared by an assertion cheatcode, addresses are checksummed and integers
+/// are signed
+fn format_assertion_value(value: &Token) -> String {
+    match value {
+        Token::Int(num) => I256::from_raw(*num).to_string(),
+        Token::Address(addr) => utils::to_checksum(addr, None),
+        value => foundry_utils::format_token(value),
+    }
+}
+
+/// Fails if the account or one of its storage slots were modified on top of the backend, since
+/// only the remote state can be proven
+fn ensure_remote_state<B: Backend>(
+    state: &CheatcodeStackState<'_, B>,
+    address: Address,
+    slots: &[H256],
+) -> eyre::Result<()> {
+    let modified = |what: String| {
+        eyre::eyre!(
+            "{} was modified locally, proofs can only be generated for the remote canonical state",
+            what
+        )
+    };
+    let remote = &state.backend;
+    let basic = state.basic(address);
+    let remote_basic = remote.basic(address);
+    let code_modified =
+        state.substate.known_code(address).map_or(false, |code| code != remote.code(address));
+    if basic.nonce != remote_basic.nonce || basic.balance != remote_basic.balance || code_modified {
+        return Err(modified(format!("account 0x{:x}", address)))
+    }
+    for slot in slots {
+        if let Some(value) = state.substate.known_storage(address, *slot) {
+            if value != remote.storage(address, *slot) {
+                return Err(modified(format!("slot {:?} of 0x{:x}", slot, address)))
+            }
+        }
+    }
+    Ok(())
+}
+
+// helper for creating an exit type
+fn evm_error(retdata: &str) -> Capture<(ExitReason, Vec<u8>), Infallible> {
+    Capture::Exit((
+        ExitReason::Revert(ExitRevert::Reverted),
+        ethers::abi::encode(&[Token::String(retdata.to_owned())]),
+    ))
+}
+
+/// The error of a cheatcode which is used from rust, with the message of its [evm_error]
+fn cheatcode_error(err: Capture<(ExitReason, Vec<u8>), Infallible>) -> eyre::Report {
+    let out = match err {
+        Capture::Exit((_, out)) => out,
+        Capture::Trap(never) => match never {},
+    };
+    match ethers::abi::decode(&[ParamType::String], &out).ok().and_then(|mut t| t.pop()) {
+        Some(Token::String(reason)) => eyre::eyre!(reason),
+        _ => eyre::eyre!("cheatcode failed with 0x{}", hex::encode(out)),
+    }
+}
+
+// helper for creating the Expected Revert return type, based on if there was a call or a create,
+// and if there was any decoded retdata that matched the expected revert value.
+fn revert_return_evm<T: ToString>(
+    call: bool,
+    result: Option<(&[u8], &[u8])>,
+    err: impl FnOnce() -> T,
+) -> ExpectRevertReturn {
+    let success =
+        result.map(|(retdata, expected_revert)| retdata == expected_revert).unwrap_or(false);
+
+    match (success, call) {
+        // Success case for CALLs needs to return a dummy output value which
+        // can be decoded
+        (true, true) => ExpectRevertReturn::Call(Capture::Exit((
+            ExitReason::Succeed(ExitSucceed::Returned),
+            DUMMY_OUTPUT.to_vec(),
+        ))),
+        // Success case for CREATE doesn't need to return any value but must return a
+        // dummy address
+        (true, false) => ExpectRevertReturn::Create(Capture::Exit((
+            ExitReason::Succeed(ExitSucceed::Returned),
+            Some(Address::from_str("0000000000000000000000000000000000000001").unwrap()),
+            Vec::new(),
+        ))),
+        // Failure cases just return the abi encoded error
+        (false, true) => ExpectRevertReturn::Call(Capture::Exit((
+            ExitReason::Revert(ExitRevert::Reverted),
+            ethers::abi::encode(&[Token::String(err().to_string())]),
+        ))),
+        (false, false) => ExpectRevertReturn::Create(Capture::Exit((
+            ExitReason::Revert(ExitRevert::Reverted),
+            None,
+            ethers::abi::encode(&[Token::String(err().to_string())]),
+        ))),
+    }
+}
+
+impl<'a, 'b, B: Backend, P: PrecompileSet> CheatcodeStackExecutor<'a, 'b, B, P> {
+    /// Runs the transaction under the overriding config, if one is set.
+    ///
+    /// Sputnik selects the costs of the opcodes with the config of the executor and every call
+    /// frame meters them with the config of the root gasometer, so the transaction runs on an
+    /// executor and a root gasometer with the overriding config. Its gas and its refunds, capped
+    /// like the overriding config caps them, are recorded on the actual gasometer afterwards.
+    /// The accounts it warms up are not kept.
+    fn with_spec_override<T>(
+        &mut self,
+        transact: impl FnOnce(&mut Self) -> T,
+    ) -> Result<T, ExitError> {
+        let config = match self.limits.spec_override {
+            Some(config) => config,
+            None => return Ok(transact(self)),
+        };
+        let gas = self.state().metadata().gasometer().gas();
+        let root = std::mem::replace(
+            self.state_mut().metadata_mut(),
+            StackSubstateMetadata::new(gas, config),
+        );
+        let executor_config = self.handler.config();
+        self.set_handler_config(config);
+        let out = transact(self);
+        self.set_handler_config(executor_config);
+
+        let metadata = std::mem::replace(self.state_mut().metadata_mut(), root);
+        let used_gas = metadata.gasometer().total_used_gas();
+        let max_refund = used_gas / config.max_refund_quotient;
+        let refund = metadata.gasometer().refunded_gas().min(max_refund as i64);
+        let gasometer = self.state_mut().metadata_mut().gasometer_mut();
+        gasometer.record_cost(used_gas)?;
+        gasometer.record_refund(refund)?;
+        Ok(out)
+    }
+
+    /// Replaces the config of the wrapped executor, keeping its state
+    fn set_handler_config(&mut self, config: &'a Config) {
+        // SAFETY: the executor is moved out and back in without anything in between which could
+        // panic, so it is never dropped twice
+        unsafe {
+            let handler = std::ptr::read(&self.handler);
+            let precompiles = handler.precompiles();
+            let handler =
+                StackExecutor::new_with_precompiles(handler.into_state(), config, precompiles);
+            std::ptr::write(&mut self.handler, handler);
+        }
+    }
+
+    /// Rejects transactions with more blobs than a transaction may have
+    fn check_blobs(&self) -> Result<(), ExitError> {
+        let blobs = self.state().backend.cheats.env.blob_hashes.len();
+        if blobs > MAX_BLOBS_PER_TX {
+            return Err(ExitError::Other(
+                format!(
+                    "the transaction has {} blobs, but at most {} are allowed",
+                    blobs, MAX_BLOBS_PER_TX
+                )
+                .into(),
+            ))
+        }
+        Ok(())
+    }
+
+    /// Executes the call as a transaction, without a transfer the call is static
+    fn transact_call_inner(
+        &mut self,
+        caller: H160,
+        address: H160,
+        transfer: Option<Transfer>,
+        data: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Vec<u8>) {
+        let is_static = transfer.is_none();
+        // reset all_logs because its a new call
+        self.state_mut().all_logs = vec![];
+        if let Err(err) = self.check_blobs() {
+            return (err.into(), Vec::new())
+        }
+
+        let transaction_cost = gasometer::call_transaction_cost(&data, &access_list);
+        match self.state_mut().metadata_mut().gasometer_mut().record_transaction(transaction_cost) {
+            Ok(()) => (),
+            Err(e) => return (e.into(), Vec::new()),
+        }
+
+        // Initialize initial addresses for EIP-2929
+        if self.config().increase_state_access_gas {
+            let addresses = core::iter::once(caller).chain(core::iter::once(address));
+            self.state_mut().metadata_mut().access_addresses(addresses);
+
+            self.handler.initialize_with_access_list(access_list);
+        }
+
+        if !is_static {
+            self.state_mut().inc_nonce(caller);
+        }
+
+        let apparent_value = transfer.as_ref().map(|t| t.value).unwrap_or_default();
+        let context = Context { caller, address, apparent_value };
+
+        match self.call_inner(
+            address,
+            transfer,
+            data,
+            Some(gas_limit),
+            is_static,
+            false,
+            false,
+            context,
+        ) {
+            Capture::Exit((s, v)) => {
+                self.state_mut().finalize_transaction();
+                if self.state().trace_enabled {
+                    self.state_mut().increment_call_index();
+                }
+
+                // check if all expected calls were made, the expectations only hold for this call
+                let expected_calls = std::mem::take(&mut self.state_mut().expected_calls);
+                if let Some((address, expected)) =
+                    expected_calls.iter().find_map(|(addr, calls)| {
+                        calls.iter().find(|call| !call.is_satisfied()).map(|call| (addr, call))
+                    })
+                {
+                    let data = ethers::types::Bytes::from(expected.calldata.clone());
+                    let value = expected
+                        .value
+                        .map(|value| format!(" and value {}", value))
+                        .unwrap_or_default();
+                    let msg = match expected.count {
+                        Some(count) => format!(
+                            "Expected {} call(s) to 0x{} with data {}{}, but got {}",
+                            count, address, data, value, expected.found
+                        ),
+                        None => format!(
+                            "Expected a call to 0x{} with data {}{}, but got none",
+                            address, data, value
+                        ),
+                    };
+                    return (
+                        ExitReason::Revert(ExitRevert::Reverted),
+                        ethers::abi::encode(&[Token::String(msg)]),
+                    )
+                }
+
+                if !self.state().expected_emits.is_empty() {
+                    return (
+                        ExitReason::Revert(ExitRevert::Reverted),
+                        ethers::abi::encode(&[Token::String(
+                            "Expected an emit, but no logs were emitted afterward".to_string(),
+                        )]),
+                    )
+                }
+                (s, v)
+            }
+            Capture::Trap(_) => {
+                self.state_mut().increment_call_index();
+                unreachable!()
+            }
+        }
+    }
+
+    /// Executes a create transaction, deploying to the address given by the scheme
+    fn transact_create_with_scheme(
+        &mut self,
+        caller: H160,
+        scheme: CreateScheme,
+        value: U256,
+        init_code: Vec<u8>,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> ExitReason {
+        // reset all_logs because its a new call
+        self.state_mut().all_logs = vec![];
+        if let Err(err) = self.check_blobs() {
+            return err.into()
+        }
+
+        let transaction_cost = gasometer::create_transaction_cost(&init_code, &access_list);
+        match self.state_mut().metadata_mut().gasometer_mut().record_transaction(transaction_cost) {
+            Ok(()) => (),
+            Err(e) => return e.into(),
+        };
+        self.handler.initialize_with_access_list(access_list);
+
+        match self.create_inner(caller, scheme, value, init_code, Some(gas_limit), false) {
+            Capture::Exit((s, _, _)) => {
+                self.state_mut().finalize_transaction();
+                if self.state().trace_enabled {
+                    self.state_mut().increment_call_index();
+                }
+                s
+            }
+            Capture::Trap(_) => {
+                self.state_mut().increment_call_index();
+                unreachable!()
+            }
+        }
+    }
+
+    /// Checks whether the provided call reverted with an expected revert reason.
+    fn expected_revert(
+        &mut self,
+        res: ExpectRevertReturn,
+        expected_revert: Option<Vec<u8>>,
+    ) -> ExpectRevertReturn {
+        // return early if there was no revert expected
+        let expected_revert = match expected_revert {
+            Some(inner) => inner,
+            None => return res,
+        };
+
+        let call = res.is_call();
+
+        // If the call was successful (i.e. did not revert) return
+        // an error. Otherwise, get the return data
+        let data = match res {
+            ExpectRevertReturn::Create(Capture::Exit((ExitReason::Revert(_e), None, revdata))) => {
+                Some(revdata)
+            }
+            ExpectRevertReturn::Call(Capture::Exit((ExitReason::Revert(_e), revdata))) => {
+                Some(revdata)
+            }
+            _ => return revert_return_evm(call, None, || "Expected revert did not revert"),
+        };
+
+        // if there was no revert data return an error
+        let data = match data {
+            Some(inner) => inner,
+            None => {
+                return revert_return_evm(call, None, || "Expected revert did not revert with data")
+            }
+        };
+
+        // do the actual check
+        if data.len() >= 4 && data[0..4] == [8, 195, 121, 160] {
+            // its a revert string
+            let decoded_data = ethers::abi::decode(&[ethers::abi::ParamType::Bytes], &data[4..])
+                .expect("String error code, but not actual string");
+
+            let decoded_data =
+                decoded_data[0].clone().into_bytes().expect("Can never fail because it is bytes");
+
+            let err = || {
+                format!(
+                    "Error != expected error: '{}' != '{}'",
+                    String::from_utf8_lossy(&decoded_data[..]),
+                    String::from_utf8_lossy(&expected_revert)
+                )
+            };
+            revert_return_evm(call, Some((&decoded_data, &expected_revert)), err)
+        } else {
+            let err = || {
+                format!(
+                    "Error data != expected error data: 0x{} != 0x{}",
+                    hex::encode(&data),
+                    hex::encode(&expected_revert)
+                )
+            };
+            revert_return_evm(call, Some((&data, &expected_revert)), err)
+        }
+    }
+
+    /// Given a transaction's calldata, it tries to parse it a console call and print the call
+    fn console_log(&mut self, input: Vec<u8>) -> Capture<(ExitReason, Vec<u8>), Infallible> {
+        // replacing hardhat style selectors (`uint`) with abigen style (`uint256`)
+        let input = patch_hardhat_console_log_selector(input);
+        let decoded = match ConsoleCalls::decode(&input) {
+            Ok(inner) => inner,
+            Err(err) => return evm_error(&err.to_string()),
+        };
+        let log = format_console_log(&input).unwrap_or_else(|| decoded.to_string());
+        self.console_logs.push(log);
+        Capture::Exit((ExitReason::Succeed(ExitSucceed::Stopped), vec![]))
+    }
+
+    /// Adds CheatOp to the latest DebugArena
+    fn add_debug(&mut self, cheatop: CheatOp) {
+        if self.state().debug_enabled {
+            let depth =
+                if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
+            self.state_mut().debug_mut().push_node(
+                0,
+                DebugNode {
+                    address: *CHEATCODE_ADDRESS,
+                    depth,
+                    steps: vec![DebugStep {
+                        op: OpCode::from(cheatop),
+                        memory: Memory::new(0),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    fn prank(
+        &mut self,
+        single_call: bool,
+        msg_sender: Address,
+        caller: Address,
+        origin: Option<Address>,
+    ) -> Result<(), Capture<(ExitReason, Vec<u8>), Infallible>> {
+        let curr_depth =
+            if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
+
+        let prank = Prank {
+            prank_caller: msg_sender,
+            new_caller: caller,
+            new_origin: origin,
+            depth: curr_depth,
+        };
+        // pranks don't stack, a `prank` and a `startPrank` of the same caller at the same depth
+        // would both apply to the next call
+        let active_start_prank =
+            self.state().prank.as_ref().map_or(false, |prank| {
+                prank.depth == curr_depth && prank.prank_caller == msg_sender
+            });
+        if single_call {
+            if self.state().next_prank.is_some() {
+                return Err(evm_error("You have an active `prank` call already. Use either `prank` or `startPrank`, not both"));
+            }
+            if active_start_prank {
+                return Err(evm_error("You have an active `startPrank` at this frame depth already. Use either `prank` or `startPrank`, not both"));
+            }
+            self.state_mut().next_prank = Some(prank);
+        } else {
+            // startPrank works by using frame depth to determine whether to overwrite
+            // msg.sender if we set a prank caller at a particular depth, it
+            // will continue to use the prank caller for any subsequent calls
+            // until stopPrank is called.
+            //
+            // We additionally have to store the original message sender of the cheatcode caller
+            // so that we dont apply it to any other addresses when depth ==
+            // prank_depth
+            if active_start_prank {
+                return Err(evm_error("You have an active `startPrank` at this frame depth already. Use either `prank` or `startPrank`, not both"));
+            }
+            if self.state().next_prank.is_some() {
+                return Err(evm_error("You have an active `prank` call already. Use either `prank` or `startPrank`, not both"));
+            }
+            self.state_mut().prank = Some(prank);
+        }
+        Ok(())
+    }
+
+    /// The prank which applies to the next call of `msg_sender` as `(mode, msg.sender, tx.origin)`.
+    ///
+    /// The mode is `0` without a prank, `3` for a `prank` and `4` for a `startPrank`, the gaps
+    /// are reserved for broadcasting, as in upstream forge-std.
+    fn read_callers(&self, msg_sender: Address) -> (u8, Address, Address) {
+        let origin = self.handler.origin();
+        let curr_depth =
+            if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
+        if let Some(prank) = &self.state().next_prank {
+            return (3, prank.new_caller, prank.new_origin.unwrap_or(origin))
+        }
+        match &self.state().prank {
+            Some(prank) if prank.depth == curr_depth && prank.prank_caller == msg_sender => {
+                (4, prank.new_caller, prank.new_origin.unwrap_or(origin))
+            }
+            _ => (0, msg_sender, origin),
+        }
+    }
+
+    /// Records a failed assertion of the contract, which fails the test without reverting
+    fn fail_assertion(&mut self, address: Address, message: String) {
+        self.state_mut().assertion_failures.push(AssertionFailure { address, message });
+    }
+
+    /// Records the assertion cheatcode as failed unless it `passed`, the failure describes the
+    /// values with the comparison which held instead, e.g. `assertEq failed: 1 != 2`
+    fn assert_values(
+        &mut self,
+        address: Address,
+        passed: bool,
+        name: &str,
+        left: Token,
+        failed_op: &str,
+        right: Token,
+    ) {
+        self.add_debug(CheatOp::ASSERT);
+        if !passed {
+            let message = format!(
+                "{} failed: {} {} {}",
+                name,
+                format_assertion_value(&left),
+                failed_op,
+                format_assertion_value(&right)
+            );
+            self.fail_assertion(address, message);
+        }
+    }
+
+    /// The call depth which fails calls and deployments, which can only be lowered below the
+    /// `call_stack_limit` of the config
+    fn call_depth_limit(&self) -> usize {
+        let limit = self.config().call_stack_limit;
+        self.limits.call_depth_limit.map_or(limit, |depth| depth.min(limit))
+    }
+
+    /// Returns the error if ffi is not explicitly enabled at runtime, in which case the `ffi`
+    /// cheatcodes must not run any command (we could have an FFI cheatcode executor instead but
+    /// feels like over engineering)
+    fn ensure_ffi_enabled(&self) -> Result<(), String> {
+        if self.enable_ffi {
+            Ok(())
+        } else {
+            Err("ffi disabled: run again with --ffi if you want to allow tests to call external scripts"
+                .to_string())
+        }
+    }
+
+    /// Runs the command of the `ffi` cheatcode and returns its hex decoded stdout as abi encoded
+    /// `bytes`
+    fn run_ffi(&self, args: &[String], stdin: Option<&[u8]>) -> Result<Vec<u8>, String> {
+        self.ensure_ffi_enabled()?;
+        let output = self.ffi.run(args, stdin)?;
+
+        // get the hex string & decode it
+        let output = String::from_utf8_lossy(&output);
+        let output = output.trim();
+        let decoded = hex::decode(output.strip_prefix("0x").unwrap_or(output))
+            .map_err(|err| err.to_string())?;
+
+        // encode the data as Bytes
+        Ok(ethers::abi::encode(&[Token::Bytes(decoded)]))
+    }
+
+    /// Runs the command of the `tryFfi` cheatcode and returns the abi encoded
+    /// `(int32 exitCode, bytes stdout, bytes stderr)` struct.
+    ///
+    /// A command which exits with an error doesn't revert. Unlike with `ffi`, the stdout is
+    /// returned as is, without hex decoding it.
+    fn run_try_ffi(&self, args: &[String], stdin: Option<&[u8]>) -> Result<Vec<u8>, String> {
+        self.ensure_ffi_enabled()?;
+        let output = self.ffi.execute(args, stdin)?;
+        Ok(ethers::abi::encode(&[Token::Tuple(vec![
+            Token::Int(I256::from(output.exit_code).into_raw()),
+            Token::Bytes(output.stdout),
+            Token::Bytes(output.stderr),
+        ])]))
+    }
+
+    /// Sets the ERC20 balance of `who` and adjusts the total supply accordingly if `adjust` is
+    /// set.
+    ///
+    /// The balance slot is computed from the index of the balance mapping if one is given,
+    /// assuming a `mapping(address => uint256)` laid out by solidity. Otherwise it is the slot
+    /// read by `balanceOf(who)` which changes its return value, so any layout is found as long as
+    /// the balance is stored as is.
+    ///
+    /// If `balanceOf(who)` doesn't return the amount afterwards, the written value is adjusted
+    /// according to the [`DealStrategy`], and a warning is logged if it still differs.
+    fn deal_token(
+        &mut self,
+        token: Address,
+        who: Address,
+        amount: U256,
+        adjust: bool,
+        mapping_index: Option<U256>,
+    ) -> Result<(), Capture<(ExitReason, Vec<u8>), Infallible>> {
+        let balance_of =
+            [&utils::id("balanceOf(address)")[..], &ethers::abi::encode(&[Token::Address(who)])]
+                .concat();
+        let balance_slot = match mapping_index {
+            Some(index) => Some(mapping_slot(index, who.into())),
+            None => self.find_slot(token, balance_of.clone()),
+        };
+        let balance_slot = match balance_slot {
+            Some(slot) => slot,
+            None => return Err(evm_error(&format!(
+                "could not find the balance slot of token 0x{:x}, pass the index of the balance mapping to `deal`",
+                token
+            ))),
+        };
+        let prev_balance = self.state().storage(token, balance_slot).into_uint();
+        let written = self.write_balance(token, balance_slot, &balance_of, amount);
+
+        if adjust {
+            let total_slot = match self.find_slot(token, utils::id("totalSupply()").to_vec()) {
+                Some(slot) => slot,
+                None => return Err(evm_error(&format!(
+                    "could not find the totalSupply slot of token 0x{:x}, call `deal` without adjusting it",
+                    token
+                ))),
+            };
+            let total = self.state().storage(token, total_slot).into_uint();
+            let total = total.saturating_sub(prev_balance).saturating_add(written);
+            self.state_mut().set_storage(token, total_slot, H256::from_uint(&total));
+        }
+
+        match self.probe_balance(token, &balance_of) {
+            Some(balance) if balance == amount => {}
+            balance => {
+                let balance = balance.map_or_else(|| "unknown".to_string(), |b| b.to_string());
+                tracing::warn!(
+                    "deal: balanceOf(0x{:x}) of token 0x{:x} is {} instead of {}",
+                    who,
+                    token,
+                    balance,
+                    amount
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the ERC20 allowance of `spender` for the tokens of `owner`.
+    ///
+    /// The allowance slot is computed from the index of the allowance mapping if one is given,
+    /// assuming a `mapping(address => mapping(address => uint256))` laid out by solidity, and
+    /// otherwise found like the balance slot of [`deal_token`](Self::deal_token). The write is
+    /// undone if `allowance(owner, spender)` doesn't return the amount afterwards.
+    fn deal_allowance(
+        &mut self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+        amount: U256,
+        mapping_index: Option<U256>,
+    ) -> Result<(), Capture<(ExitReason, Vec<u8>), Infallible>> {
+        let allowance = [
+            &utils::id("allowance(address,address)")[..],
+            &ethers::abi::encode(&[Token::Address(owner), Token::Address(spender)]),
+        ]
+        .concat();
+        let slot = match mapping_index {
+            Some(index) => Some(nested_mapping_slot(index, &[owner.into(), spender.into()])),
+            None => self.find_slot(token, allowance.clone()),
+        };
+        let slot = match slot {
+            Some(slot) => slot,
+            None => return Err(evm_error(&format!(
+                "could not find the allowance slot of token 0x{:x}, pass the index of the allowance mapping to `dealAllowance`",
+                token
+            ))),
+        };
+
+        let prev = self.state().storage(token, slot);
+        self.state_mut().set_storage(token, slot, H256::from_uint(&amount));
+        match self.probe_balance(token, &allowance) {
+            Some(written) if written == amount => Ok(()),
+            written => {
+                self.state_mut().set_storage(token, slot, prev);
+                let written = written.map_or_else(|| "unknown".to_string(), |w| w.to_string());
+                Err(evm_error(&format!(
+                    "dealAllowance: allowance(0x{:x}, 0x{:x}) of token 0x{:x} is {} instead of {}",
+                    owner, spender, token, written, amount
+                )))
+            }
+        }
+    }
+
+    /// Makes `to` the owner of the ERC721 token `token_id` without minting it, moving it from its
+    /// previous owner if it was already owned.
+    ///
+    /// The slots are computed from the indices of the owner and balance mappings, which default
+    /// to the layout of OpenZeppelin's ERC721. All writes are undone if `ownerOf(token_id)`,
+    /// `balanceOf(to)` and the `balanceOf` of the previous owner don't return the new owner and
+    /// balances afterwards.
+    fn deal_erc721(
+        &mut self,
+        token: Address,
+        to: Address,
+        token_id: U256,
+        mapping_indices: Option<(U256, U256)>,
+    ) -> Result<(), Capture<(ExitReason, Vec<u8>), Infallible>> {
+        let (owners_index, balances_index) = mapping_indices
+            .unwrap_or_else(|| (ERC721_OWNERS_INDEX.into(), ERC721_BALANCES_INDEX.into()));
+        let owner_slot = mapping_slot(owners_index, H256::from_uint(&token_id));
+        let prev_owner = Address::from_slice(&self.state().storage(token, owner_slot)[12..]);
+
+        let mut writes = vec![(owner_slot, self.state().storage(token, owner_slot))];
+        self.state_mut().set_storage(token, owner_slot, to.into());
+        let mut prev_balance = None;
+        if prev_owner != to {
+            if !prev_owner.is_zero() {
+                let slot = mapping_slot(balances_index, prev_owner.into());
+                let balance = self.state().storage(token, slot);
+                writes.push((slot, balance));
+                let balance = balance.into_uint().saturating_sub(U256::one());
+                self.state_mut().set_storage(token, slot, H256::from_uint(&balance));
+                prev_balance = Some(balance);
+            }
+            let slot = mapping_slot(balances_index, to.into());
+            let balance = self.state().storage(token, slot);
+            writes.push((slot, balance));
+            let balance = balance.into_uint().saturating_add(U256::one());
+            self.state_mut().set_storage(token, slot, H256::from_uint(&balance));
+        }
+        let expected_owner = H256::from(to).into_uint();
+        let balance =
+            self.state().storage(token, mapping_slot(balances_index, to.into())).into_uint();
+
+        let owner_of =
+            [&utils::id("ownerOf(uint256)")[..], &ethers::abi::encode(&[Token::Uint(token_id)])]
+                .concat();
+        let balance_of =
+            [&utils::id("balanceOf(address)")[..], &ethers::abi::encode(&[Token::Address(to)])]
+                .concat();
+        let owner = self.probe_balance(token, &owner_of);
+        let written_balance = self.probe_balance(token, &balance_of);
+        let prev_written_balance = prev_balance.map(|balance| {
+            let balance_of = [
+                &utils::id("balanceOf(address)")[..],
+                &ethers::abi::encode(&[Token::Address(prev_owner)]),
+            ]
+            .concat();
+            (balance, self.probe_balance(token, &balance_of))
+        });
+        let mismatch = match (owner, written_balance) {
+            (Some(owner), Some(written)) if owner == expected_owner && written == balance => {
+                match prev_written_balance {
+                    Some((balance, written)) if written != Some(balance) => format!(
+                        "balanceOf(0x{:x}) is {} instead of {}",
+                        prev_owner,
+                        written.map_or_else(|| "unknown".to_string(), |w| w.to_string()),
+                        balance
+                    ),
+                    _ => return Ok(()),
+                }
+            }
+            (Some(owner), written) if owner == expected_owner => format!(
+                "balanceOf(0x{:x}) is {} instead of {}",
+                to,
+                written.map_or_else(|| "unknown".to_string(), |w| w.to_string()),
+                balance
+            ),
+            (owner, _) => format!(
+                "ownerOf({}) is {} instead of 0x{:x}",
+                token_id,
+                owner.map_or_else(
+                    || "unknown".to_string(),
+                    |o| format!("0x{:x}", Address::from_slice(&H256::from_uint(&o)[12..]))
+                ),
+                to
+            ),
+        };
+
+        for (slot, prev) in writes.into_iter().rev() {
+            self.state_mut().set_storage(token, slot, prev);
+        }
+        Err(evm_error(&format!(
+            "dealERC721: {} for token 0x{:x}, pass the indices of the owner and balance mappings to `dealERC721`",
+            mismatch, token
+        )))
+    }
+
+    /// Writes the balance slot so that `balanceOf` returns the amount, according to the
+    /// [`DealStrategy`], and returns the written value
+    fn write_balance(
+        &mut self,
+        token: Address,
+        slot: H256,
+        balance_of: &[u8],
+        amount: U256,
+    ) -> U256 {
+        let mut written = amount;
+        self.state_mut().set_storage(token, slot, H256::from_uint(&written));
+        if self.deal_strategy == DealStrategy::Exact {
+            return written
+        }
+        for _ in 0..DEAL_SCALE_STEPS {
+            let balance = match self.probe_balance(token, balance_of) {
+                Some(balance) if balance != amount && !balance.is_zero() => balance,
+                _ => break,
+            };
+            let scaled = U256::try_from(written.full_mul(amount) / balance).unwrap_or(U256::MAX);
+            // the value is off by rounding, so it is moved by one instead
+            written = match scaled {
+                scaled if scaled != written => scaled,
+                _ if balance < amount => written.saturating_add(U256::one()),
+                _ => written.saturating_sub(U256::one()),
+            };
+            self.state_mut().set_storage(token, slot, H256::from_uint(&written));
+        }
+        written
+    }
+
+    /// The word returned by the call to the token, e.g. its `balanceOf`, if it can be called
+    fn probe_balance(&mut self, token: Address, balance_of: &[u8]) -> Option<U256> {
+        let trace_enabled = std::mem::replace(&mut self.state_mut().trace_enabled, false);
+        let debug_enabled = std::mem::replace(&mut self.state_mut().debug_enabled, false);
+        let out = self.probe_call(token, balance_of.to_vec());
+        self.state_mut().trace_enabled = trace_enabled;
+        self.state_mut().debug_enabled = debug_enabled;
+        out.filter(|out| out.len() >= 32).map(|out| U256::from_big_endian(&out[..32]))
+    }
+
+    /// Finds the storage slot of `target` which holds the word returned by the call, by
+    /// overwriting each slot the call reads and checking whether the return value changes
+    /// accordingly
+    fn find_slot(&mut self, target: Address, calldata: Vec<u8>) -> Option<H256> {
+        // the probing calls are an implementation detail, so they are neither traced nor recorded
+        let trace_enabled = std::mem::replace(&mut self.state_mut().trace_enabled, false);
+        let debug_enabled = std::mem::replace(&mut self.state_mut().debug_enabled, false);
+        let accesses = self.state_mut().accesses.replace(Default::default());
+
+        self.probe_call(target, calldata.clone());
+        let reads = self
+            .state_mut()
+            .accesses
+            .take()
+            .and_then(|record| record.reads.into_inner().remove(&target))
+            .map(AccessedSlots::into_vec)
+            .unwrap_or_default();
+
+        let mut found = None;
+        for slot in reads {
+            let prev = self.state().storage(target, slot);
+            let probe = if prev == *DEAL_PROBE { H256::from_low_u64_be(1) } else { *DEAL_PROBE };
+            self.state_mut().set_storage(target, slot, probe);
+            let out = self.probe_call(target, calldata.clone());
+            self.state_mut().set_storage(target, slot, prev);
+            if out.as_deref() == Some(probe.as_bytes()) {
+                found = Some(slot);
+                break
+            }
+        }
+
+        self.state_mut().accesses = accesses;
+        self.state_mut().trace_enabled = trace_enabled;
+        self.state_mut().debug_enabled = debug_enabled;
+        found
+    }
+
+    /// Makes a static call from the cheatcode address and returns the output if it succeeded.
+    ///
+    /// The probe runs in a discarded substate of its own with [PROBE_GAS], so that neither its
+    /// state changes nor its gas show up in the frame which called the cheatcode.
+    fn probe_call(&mut self, target: Address, calldata: Vec<u8>) -> Option<Vec<u8>> {
+        let gasometer = self.state().metadata().gasometer().clone();
+        self.handler.enter_substate(PROBE_GAS, false);
+        let context =
+            Context { caller: *CHEATCODE_ADDRESS, address: target, apparent_value: U256::zero() };
+        let out = match self.call_inner(
+            target,
+            None,
+            calldata,
+            Some(PROBE_GAS),
+            true,
+            false,
+            false,
+            context,
+        ) {
+            Capture::Exit((ExitReason::Succeed(_), out)) => Some(out),
+            _ => None,
+        };
+        let _ = self.handler.exit_substate(StackExitKind::Failed);
+        *self.state_mut().metadata_mut().gasometer_mut() = gasometer;
+        out
+    }
+
+    fn expect_revert(
+        &mut self,
+        inner: Vec<u8>,
+    ) -> Result<(), Capture<(ExitReason, Vec<u8>), Infallible>> {
+        self.add_debug(CheatOp::EXPECTREVERT);
+        if self.state().expected_revert.is_some() {
+            return Err(evm_error(
+                "You must call another function prior to expecting a second revert.",
+            ))
+        } else {
+            self.state_mut().expected_revert = Some(inner);
+        }
+        Ok(())
+    }
+
+    /// Passes a call with a selector none of the built-in cheatcodes has to the registered
+    /// extensions, until one of them handles it
+    fn apply_extension(&mut self, input: &[u8], msg_sender: H160) -> Option<CheatResult> {
+        let selector = input.get(..4)?;
+        if HEVM_ABI.functions().any(|func| func.short_signature() == selector) {
+            return None
+        }
+        let mut ctx =
+            CheatContext::new(msg_sender, self.handler.state_mut(), &mut self.console_logs);
+        self.extensions.iter().find_map(|extension| extension.borrow_mut().handle(input, &mut ctx))
+    }
+
+    /// Given a transaction's calldata, it tries to parse it as an [`HEVM cheatcode`](super::HEVM)
+    /// call and modify the state accordingly.
+    fn apply_cheatcode(
+        &mut self,
+        input: Vec<u8>,
+        msg_sender: H160,
+    ) -> Capture<(ExitReason, Vec<u8>), Infallible> {
+        let pre_index = self.state().trace_index;
+        let mut trace =
+            self.start_trace(*CHEATCODE_ADDRESS, input.clone(), 0.into(), CallKind::Call);
+        // Get a mutable ref to the state so we can apply the cheats
+        let decoded = match HEVMCalls::decode(&input) {
+            Ok(inner) => inner,
+            Err(err) => {
+                let res = self.apply_extension(&input, msg_sender).unwrap_or(Err(err.to_string()));
+                self.fill_trace(&trace, res.is_ok(), res.as_ref().ok().cloned(), pre_index);
+                return match res {
+                    Ok(res) => Capture::Exit((ExitReason::Succeed(ExitSucceed::Stopped), res)),
+                    Err(err) => evm_error(&err),
+                }
+            }
+        };
+
+        let snapshot = matches!(decoded, HEVMCalls::Snapshot(_));
+        let res = self.run_cheatcode(decoded, msg_sender, &mut trace);
+        // every exit completes the trace, which also ends an untraced call of a paused trace
+        let (reason, output) = match &res {
+            Capture::Exit(exit) => exit,
+            Capture::Trap(_) => unreachable!("Trap is Infallible"),
+        };
+        self.fill_trace(&trace, reason.is_succeed(), Some(output.clone()), pre_index);
+        if snapshot {
+            // the trace of this call is part of the snapshot, so it is completed beforehand
+            self.state_mut().snapshot();
+        }
+        res
+    }
+
+    /// Applies the decoded cheatcode, the trace of its call is completed by
+    /// [`Self::apply_cheatcode`]
+    fn run_cheatcode(
+        &mut self,
+        decoded: HEVMCalls,
+        msg_sender: H160,
+        trace: &mut Option<CallTrace>,
+    ) -> Capture<(ExitReason, Vec<u8>), Infallible> {
+        let mut res = vec![];
+        match decoded {
+            HEVMCalls::Warp(inner) => {
+                self.add_debug(CheatOp::WARP);
+                self.state_mut().backend.cheats.env.block_timestamp = Some(inner.0);
+            }
+            HEVMCalls::Skip(inner) => {
+                self.add_debug(CheatOp::SKIP);
+                // the seconds are most likely a negative number cast to `uint256`
+                if inner.0.bit(255) {
+                    return evm_error("skip: cannot skip a negative amount of time")
+                }
+                let number = self.state().block_number();
+                let timestamp = match self.state().block_timestamp().checked_add(inner.0) {
+                    Some(timestamp) => timestamp,
+                    None => return evm_error("skip: the timestamp overflows"),
+                };
+                let backend = &mut self.state_mut().backend;
+                backend.cheats.env.block_timestamp = Some(timestamp);
+                let block_time = backend.cheats.block_time.unwrap_or(DEFAULT_BLOCK_TIME);
+                let blocks = if block_time == 0 { U256::zero() } else { inner.0 / block_time };
+                if !blocks.is_zero() {
+                    // like with `roll`, the block skipped to has a hash as well
+                    backend.roll_to(number.saturating_add(blocks));
+                }
+            }
+            HEVMCalls::Roll(inner) => {
+                self.add_debug(CheatOp::ROLL);
+                // unlike on chain, the block rolled to has a hash as well
+                self.state_mut().backend.roll_to(inner.0);
+            }
+            HEVMCalls::Fee(inner) => {
+                self.add_debug(CheatOp::FEE);
+                self.state_mut().backend.cheats.env.block_base_fee_per_gas = Some(inner.0);
+            }
+            HEVMCalls::Difficulty(inner) => {
+                self.add_debug(CheatOp::DIFFICULTY);
+                self.state_mut().backend.cheats.env.block_difficulty = Some(inner.0);
+            }
+            HEVMCalls::Prevrandao(inner) => {
+                self.add_debug(CheatOp::PREVRANDAO);
+                // the EVM only implements the rules up to London, where the opcode which became
+                // `PREVRANDAO` still returns the difficulty. Every spec it runs is pre-Merge, so
+                // this is only logged once instead of on every run of a fuzzed test
+                static PRE_MERGE_WARNING: std::sync::Once = std::sync::Once::new();
+                PRE_MERGE_WARNING.call_once(|| {
+                    tracing::warn!(
+                        "prevrandao: the EVM runs pre-Merge rules, setting block.difficulty instead"
+                    )
+                });
+                self.state_mut().backend.cheats.env.block_difficulty =
+                    Some(U256::from_big_endian(&inner.0));
+            }
+            HEVMCalls::GetDifficulty(_) => {
+                self.add_debug(CheatOp::DIFFICULTY);
+                res = ethers::abi::encode(&[Token::Uint(self.state().block_difficulty())]);
+            }
+            HEVMCalls::GetPrevrandao(_) => {
+                self.add_debug(CheatOp::PREVRANDAO);
+                let prevrandao = H256::from_uint(&self.state().block_difficulty());
+                res = ethers::abi::encode(&[Token::FixedBytes(prevrandao.0.to_vec())]);
+            }
+            HEVMCalls::Store(inner) => {
+                self.add_debug(CheatOp::STORE);
+                self.state_mut().set_storage(inner.0, inner.1.into(), inner.2.into());
+            }
+            HEVMCalls::Load(inner) => {
+                self.add_debug(CheatOp::LOAD);
+                res = self.state_mut().storage(inner.0, inner.1.into()).0.to_vec();
+            }
+            HEVMCalls::Ffi0(inner) => {
+                self.add_debug(CheatOp::FFI);
+                res = match self.run_ffi(&inner.0, None) {
+                    Ok(res) => res,
+                    Err(err) => return evm_error(&err),
+                };
+            }
+            HEVMCalls::Ffi1(inner) => {
+                self.add_debug(CheatOp::FFI);
+                res = match self.run_ffi(&inner.0, Some(inner.1.as_ref())) {
+                    Ok(res) => res,
+                    Err(err) => return evm_error(&err),
+                };
+            }
+            HEVMCalls::TryFfi0(inner) => {
+                self.add_debug(CheatOp::FFI);
+                res = match self.run_try_ffi(&inner.0, None) {
+                    Ok(res) => res,
+                    Err(err) => return evm_error(&err),
+                };
+            }
+            HEVMCalls::TryFfi1(inner) => {
+                self.add_debug(CheatOp::FFI);
+                res = match self.run_try_ffi(&inner.0, Some(inner.1.as_ref())) {
+                    Ok(res) => res,
+                    Err(err) => return evm_error(&err),
+                };
+            }
+            HEVMCalls::GetCode(inner) => {
+                self.add_debug(CheatOp::GETCODE);
+
+                let path = if inner.0.ends_with(".json") {
+                    Path::new(&inner.0).to_path_buf()
+                } else {
+                    let parts = inner.0.split(':').collect::<Vec<&str>>();
+                    let contract_file = parts[0];
+                    let contract_name = if parts.len() == 1 {
+                        parts[0].replace(".sol", "")
+                    } else {
+                        parts[1].to_string()
+                    };
+
+                    let outdir = ProjectPathsConfig::find_artifacts_dir(Path::new("./"));
+                    outdir.join(format!("{}/{}.json", contract_file, contract_name))
+                };
+
+                let mut data = String::new();
+                match File::open(path) {
+                    Ok(mut file) => match file.read_to_string(&mut data) {
+                        Ok(_) => {}
+                        Err(e) => return evm_error(&e.to_string()),
+                    },
+                    Err(e) => return evm_error(&e.to_string()),
+                }
+
+                match serde_json::from_str::<CompactContractBytecode>(&data) {
+                    Ok(contract_file) => {
+                        if let Some(bin) =
+                            contract_file.bytecode.and_then(|bcode| bcode.object.into_bytes())
+                        {
+                            res = ethers::abi::encode(&[Token::Bytes(bin.to_vec())]);
+                        } else {
+                            return evm_error(
+                                "No bytecode for contract. is it abstract or unlinked?",
+                            )
+                        }
+                    }
+                    Err(e) => return evm_error(&e.to_string()),
+                }
+            }
+            HEVMCalls::Addr(inner) => {
+                self.add_debug(CheatOp::ADDR);
+                let xsk = match signing_key(inner.0) {
+                    Ok(xsk) => xsk,
+                    Err(err) => return evm_error(&err),
+                };
+                let addr = utils::secret_key_to_address(&xsk);
+                res = ethers::abi::encode(&[Token::Address(addr)]);
+            }
+            HEVMCalls::Sign(inner) => {
+                self.add_debug(CheatOp::SIGN);
+                let digest = inner.1;
+                let xsk = match signing_key(inner.0) {
+                    Ok(xsk) => xsk,
+                    Err(err) => return evm_error(&err),
+                };
+                let wallet = LocalWallet::from(xsk).with_chain_id(self.handler.chain_id().as_u64());
+
+                // The EVM precompile does not use EIP-155
+                let sig = wallet.sign_hash(digest.into(), false);
+
+                let recovered = match sig.recover(digest) {
+                    Ok(rec) => rec,
+                    Err(e) => return evm_error(&e.to_string()),
+                };
+
+                assert_eq!(recovered, wallet.address());
+
+                let mut r_bytes = [0u8; 32];
+                let mut s_bytes = [0u8; 32];
+                sig.r.to_big_endian(&mut r_bytes);
+                sig.s.to_big_endian(&mut s_bytes);
+                res = ethers::abi::encode(&[Token::Tuple(vec![
+                    Token::Uint(sig.v.into()),
+                    Token::FixedBytes(r_bytes.to_vec()),
+                    Token::FixedBytes(s_bytes.to_vec()),
+                ])]);
+            }
+            HEVMCalls::Prank0(inner) => {
+                self.add_debug(CheatOp::PRANK);
+                let caller = inner.0;
                 if let Err(err) = self.prank(true, msg_sender, caller, None) {
                     return err
                 }
             }
-            HEVMCalls::StartPrank0(inner) => {
-                self.add_debug(CheatOp::STARTPRANK);
-                let caller = inner.0;
-                if let Err(err) = self.prank(false, msg_sender, caller, None) {
-                    return err
+            HEVMCalls::StartPrank0(inner) => {
+                self.add_debug(CheatOp::STARTPRANK);
+                let caller = inner.0;
+                if let Err(err) = self.prank(false, msg_sender, caller, None) {
+                    return err
+                }
+            }
+            HEVMCalls::Prank1(inner) => {
+                self.add_debug(CheatOp::PRANK);
+                let caller = inner.0;
+                let origin = inner.1;
+                if let Err(err) = self.prank(true, msg_sender, caller, Some(origin)) {
+                    return err
+                }
+            }
+            HEVMCalls::StartPrank1(inner) => {
+                self.add_debug(CheatOp::STARTPRANK);
+                let caller = inner.0;
+                let origin = inner.1;
+                if let Err(err) = self.prank(false, msg_sender, caller, Some(origin)) {
+                    return err
+                }
+            }
+            HEVMCalls::StopPrank(_) => {
+                self.add_debug(CheatOp::STOPPRANK);
+                self.state_mut().prank = None;
+            }
+            HEVMCalls::ExpectRevert0(inner) => {
+                if let Err(e) = self.expect_revert(inner.0.to_vec()) {
+                    return e
+                }
+            }
+            HEVMCalls::ExpectRevert1(inner) => {
+                if let Err(e) = self.expect_revert(inner.0.to_vec()) {
+                    return e
+                }
+            }
+            HEVMCalls::Deal0(inner) => {
+                self.add_debug(CheatOp::DEAL);
+                let who = inner.0;
+                let value = inner.1;
+                self.state_mut().reset_balance(who);
+                self.state_mut().deposit(who, value);
+            }
+            HEVMCalls::Deal1(inner) => {
+                self.add_debug(CheatOp::DEAL);
+                if let Err(err) = self.deal_token(inner.0, inner.1, inner.2, true, None) {
+                    return err
+                }
+            }
+            HEVMCalls::Deal2(inner) => {
+                self.add_debug(CheatOp::DEAL);
+                if let Err(err) = self.deal_token(inner.0, inner.1, inner.2, inner.3, None) {
+                    return err
+                }
+            }
+            HEVMCalls::Deal3(inner) => {
+                self.add_debug(CheatOp::DEAL);
+                if let Err(err) = self.deal_token(inner.0, inner.1, inner.2, inner.3, Some(inner.4))
+                {
+                    return err
+                }
+            }
+            HEVMCalls::DealAllowance0(inner) => {
+                self.add_debug(CheatOp::DEALALLOWANCE);
+                if let Err(err) = self.deal_allowance(inner.0, inner.1, inner.2, inner.3, None) {
+                    return err
+                }
+            }
+            HEVMCalls::DealAllowance1(inner) => {
+                self.add_debug(CheatOp::DEALALLOWANCE);
+                if let Err(err) =
+                    self.deal_allowance(inner.0, inner.1, inner.2, inner.3, Some(inner.4))
+                {
+                    return err
+                }
+            }
+            HEVMCalls::DealErc7210(inner) => {
+                self.add_debug(CheatOp::DEALERC721);
+                if let Err(err) = self.deal_erc721(inner.0, inner.1, inner.2, None) {
+                    return err
+                }
+            }
+            HEVMCalls::DealErc7211(inner) => {
+                self.add_debug(CheatOp::DEALERC721);
+                if let Err(err) =
+                    self.deal_erc721(inner.0, inner.1, inner.2, Some((inner.3, inner.4)))
+                {
+                    return err
+                }
+            }
+            HEVMCalls::Etch(inner) => {
+                self.add_debug(CheatOp::ETCH);
+                let who = inner.0;
+                let code = inner.1;
+                self.state_mut().set_code(who, code.to_vec());
+            }
+            HEVMCalls::Record(_) => {
+                self.add_debug(CheatOp::RECORD);
+                self.state_mut().accesses = Some(Default::default());
+            }
+            HEVMCalls::Accesses(inner) => {
+                self.add_debug(CheatOp::ACCESSES);
+                let address = inner.0;
+                // we dont reset all records in case user wants to query multiple address
+                if let Some(record_accesses) = &self.state().accesses {
+                    res = ethers::abi::encode(&[
+                        record_accesses
+                            .reads
+                            .borrow_mut()
+                            .remove(&address)
+                            .map(AccessedSlots::into_vec)
+                            .unwrap_or_default()
+                            .into_tokens()[0]
+                            .clone(),
+                        record_accesses
+                            .writes
+                            .borrow_mut()
+                            .remove(&address)
+                            .map(AccessedSlots::into_vec)
+                            .unwrap_or_default()
+                            .into_tokens()[0]
+                            .clone(),
+                    ]);
+                    if record_accesses.reads.borrow().len() == 0 &&
+                        record_accesses.writes.borrow().len() == 0
+                    {
+                        self.state_mut().accesses = None;
+                    }
+                } else {
+                    res = ethers::abi::encode(&[Token::Array(vec![]), Token::Array(vec![])]);
+                }
+            }
+            HEVMCalls::ExpectEmit(inner) => {
+                self.add_debug(CheatOp::EXPECTEMIT);
+                let expected_emit = ExpectedEmit {
+                    depth: if let Some(depth) = self.state().metadata().depth() {
+                        depth + 1
+                    } else {
+                        0
+                    },
+                    log: None,
+                    checks: [inner.0, inner.1, inner.2, inner.3],
+                    found: false,
+                };
+                self.state_mut().expected_emits.push(expected_emit);
+            }
+            HEVMCalls::MockCall(inner) => {
+                self.add_debug(CheatOp::MOCKCALL);
+                self.state_mut()
+                    .mocked_calls
+                    .entry(inner.0)
+                    .or_default()
+                    .insert(inner.1.to_vec(), inner.2.to_vec());
+            }
+            HEVMCalls::ClearMockedCalls(_) => {
+                self.add_debug(CheatOp::CLEARMOCKEDCALLS);
+                self.state_mut().mocked_calls = Default::default();
+            }
+            HEVMCalls::ExpectCall0(inner) => {
+                self.add_debug(CheatOp::EXPECTCALL);
+                let expected = ExpectedCall { calldata: inner.1.to_vec(), ..Default::default() };
+                self.state_mut().expected_calls.entry(inner.0).or_default().push(expected);
+            }
+            HEVMCalls::ExpectCall1(inner) => {
+                self.add_debug(CheatOp::EXPECTCALL);
+                let expected = ExpectedCall {
+                    calldata: inner.2.to_vec(),
+                    value: Some(inner.1),
+                    ..Default::default()
+                };
+                self.state_mut().expected_calls.entry(inner.0).or_default().push(expected);
+            }
+            HEVMCalls::ExpectCall2(inner) => {
+                self.add_debug(CheatOp::EXPECTCALL);
+                let expected = ExpectedCall {
+                    calldata: inner.1.to_vec(),
+                    count: Some(inner.2),
+                    ..Default::default()
+                };
+                self.state_mut().expected_calls.entry(inner.0).or_default().push(expected);
+            }
+            HEVMCalls::Label(inner) => {
+                self.add_debug(CheatOp::LABEL);
+                let address = inner.0;
+                let label = inner.1;
+
+                self.state_mut().labels.insert(address, label);
+            }
+            HEVMCalls::Assume(inner) => {
+                self.add_debug(CheatOp::ASSUME);
+                if !inner.0 {
+                    res = ASSUME_MAGIC_RETURN_CODE.into();
+                    return Capture::Exit((ExitReason::Revert(ExitRevert::Reverted), res))
+                }
+            }
+            HEVMCalls::EnvUint(inner) => {
+                self.add_debug(CheatOp::ENV);
+                match read_env(self.enable_ffi, &inner.0, ParamType::Uint(256), None) {
+                    Ok(token) => res = ethers::abi::encode(&[token]),
+                    Err(err) => return evm_error(&err),
+                }
+            }
+            HEVMCalls::EnvAddress(inner) => {
+                self.add_debug(CheatOp::ENV);
+                match read_env(self.enable_ffi, &inner.0, ParamType::Address, None) {
+                    Ok(token) => res = ethers::abi::encode(&[token]),
+                    Err(err) => return evm_error(&err),
+                }
+            }
+            HEVMCalls::EnvBytes32(inner) => {
+                self.add_debug(CheatOp::ENV);
+                match read_env(self.enable_ffi, &inner.0, ParamType::FixedBytes(32), None) {
+                    Ok(token) => res = ethers::abi::encode(&[token]),
+                    Err(err) => return evm_error(&err),
+                }
+            }
+            HEVMCalls::EnvString(inner) => {
+                self.add_debug(CheatOp::ENV);
+                match read_env(self.enable_ffi, &inner.0, ParamType::String, None) {
+                    Ok(token) => res = ethers::abi::encode(&[token]),
+                    Err(err) => return evm_error(&err),
+                }
+            }
+            HEVMCalls::EnvOr0(inner) => {
+                self.add_debug(CheatOp::ENV);
+                let default = Token::Uint(inner.1);
+                match read_env(self.enable_ffi, &inner.0, ParamType::Uint(256), Some(default)) {
+                    Ok(token) => res = ethers::abi::encode(&[token]),
+                    Err(err) => return evm_error(&err),
+                }
+            }
+            HEVMCalls::EnvOr1(inner) => {
+                self.add_debug(CheatOp::ENV);
+                let default = Token::Address(inner.1);
+                match read_env(self.enable_ffi, &inner.0, ParamType::Address, Some(default)) {
+                    Ok(token) => res = ethers::abi::encode(&[token]),
+                    Err(err) => return evm_error(&err),
+                }
+            }
+            HEVMCalls::EnvOr2(inner) => {
+                self.add_debug(CheatOp::ENV);
+                let default = Token::FixedBytes(inner.1.to_vec());
+                match read_env(self.enable_ffi, &inner.0, ParamType::FixedBytes(32), Some(default))
+                {
+                    Ok(token) => res = ethers::abi::encode(&[token]),
+                    Err(err) => return evm_error(&err),
+                }
+            }
+            HEVMCalls::EnvOr3(inner) => {
+                self.add_debug(CheatOp::ENV);
+                let default = Token::String(inner.1);
+                match read_env(self.enable_ffi, &inner.0, ParamType::String, Some(default)) {
+                    Ok(token) => res = ethers::abi::encode(&[token]),
+                    Err(err) => return evm_error(&err),
+                }
+            }
+            HEVMCalls::GetNonce(inner) => {
+                self.add_debug(CheatOp::GETNONCE);
+                // the nonces of the current transaction were already increased in the state, which
+                // falls through to the backend for accounts it did not touch yet
+                let nonce = self.state().basic(inner.0).nonce;
+                res = ethers::abi::encode(&[Token::Uint(nonce)]);
+            }
+            HEVMCalls::SetNonce(inner) => {
+                self.add_debug(CheatOp::SETNONCE);
+                let (who, nonce) = (inner.0, U256::from(inner.1));
+                let current = self.state().basic(who).nonce;
+                // lowering the nonce would deploy to addresses which may already be in use
+                if nonce < current {
+                    return evm_error(&format!(
+                        "setNonce: new nonce {} is lower than the current nonce {}, use `setNonceUnsafe` instead",
+                        nonce, current
+                    ))
+                }
+                self.state_mut().set_nonce(who, nonce);
+            }
+            HEVMCalls::SetNonceUnsafe(inner) => {
+                self.add_debug(CheatOp::SETNONCE);
+                self.state_mut().set_nonce(inner.0, inner.1.into());
+            }
+            HEVMCalls::ResetNonce(inner) => {
+                self.add_debug(CheatOp::SETNONCE);
+                let who = inner.0;
+                // contracts start with a nonce of 1 since EIP-161
+                let nonce = if self.state().code(who).is_empty() { 0 } else { 1 };
+                self.state_mut().set_nonce(who, nonce.into());
+            }
+            HEVMCalls::PauseTracing(_) => {
+                self.add_debug(CheatOp::PAUSETRACING);
+                self.state_mut().trace_paused = true;
+            }
+            HEVMCalls::ResumeTracing(_) => {
+                self.add_debug(CheatOp::RESUMETRACING);
+                self.state_mut().trace_paused = false;
+            }
+            HEVMCalls::Snapshot(_) => {
+                self.add_debug(CheatOp::SNAPSHOT);
+                let id = U256::from(self.state().snapshots.len());
+                res = ethers::abi::encode(&[Token::Uint(id)]);
+            }
+            HEVMCalls::RevertTo(inner) => {
+                self.add_debug(CheatOp::REVERTTO);
+                let reverted = self.state_mut().revert_to(inner.0);
+                res = ethers::abi::encode(&[Token::Bool(reverted)]);
+                // the restored trace ends before this call
+                if let Some(trace) = trace {
+                    if trace.idx >= self.state().trace().arena.len() {
+                        self.state_mut().trace_mut().push_trace(0, trace);
+                    }
+                }
+            }
+            HEVMCalls::DeleteSnapshot(inner) => {
+                self.add_debug(CheatOp::DELETESNAPSHOT);
+                let deleted = self.state_mut().delete_snapshot(inner.0);
+                res = ethers::abi::encode(&[Token::Bool(deleted)]);
+            }
+            HEVMCalls::DeleteSnapshots(_) => {
+                self.add_debug(CheatOp::DELETESNAPSHOT);
+                self.state_mut().delete_snapshots();
+            }
+            HEVMCalls::AssertTrue(inner) => {
+                self.add_debug(CheatOp::ASSERT);
+                if !inner.0 {
+                    self.fail_assertion(msg_sender, "assertTrue failed".to_string());
+                }
+            }
+            HEVMCalls::AssertFalse(inner) => {
+                self.add_debug(CheatOp::ASSERT);
+                if inner.0 {
+                    self.fail_assertion(msg_sender, "assertFalse failed".to_string());
+                }
+            }
+            HEVMCalls::AssertEq0(inner) => {
+                let passed = inner.0 == inner.1;
+                let (left, right) = (Token::Uint(inner.0), Token::Uint(inner.1));
+                self.assert_values(msg_sender, passed, "assertEq", left, "!=", right);
+            }
+            HEVMCalls::AssertEq1(inner) => {
+                let passed = inner.0 == inner.1;
+                let (left, right) =
+                    (Token::Int(inner.0.into_raw()), Token::Int(inner.1.into_raw()));
+                self.assert_values(msg_sender, passed, "assertEq", left, "!=", right);
+            }
+            HEVMCalls::AssertEq2(inner) => {
+                let passed = inner.0 == inner.1;
+                let (left, right) = (Token::Address(inner.0), Token::Address(inner.1));
+                self.assert_values(msg_sender, passed, "assertEq", left, "!=", right);
+            }
+            HEVMCalls::AssertEq3(inner) => {
+                let passed = inner.0 == inner.1;
+                let (left, right) =
+                    (Token::FixedBytes(inner.0.to_vec()), Token::FixedBytes(inner.1.to_vec()));
+                self.assert_values(msg_sender, passed, "assertEq", left, "!=", right);
+            }
+            HEVMCalls::AssertEq4(inner) => {
+                let passed = inner.0 == inner.1;
+                let (left, right) =
+                    (Token::Bytes(inner.0.to_vec()), Token::Bytes(inner.1.to_vec()));
+                self.assert_values(msg_sender, passed, "assertEq", left, "!=", right);
+            }
+            HEVMCalls::AssertGt(inner) => {
+                let passed = inner.0 > inner.1;
+                let (left, right) = (Token::Uint(inner.0), Token::Uint(inner.1));
+                self.assert_values(msg_sender, passed, "assertGt", left, "<=", right);
+            }
+            HEVMCalls::AssertGe(inner) => {
+                let passed = inner.0 >= inner.1;
+                let (left, right) = (Token::Uint(inner.0), Token::Uint(inner.1));
+                self.assert_values(msg_sender, passed, "assertGe", left, "<", right);
+            }
+            HEVMCalls::AssertLt(inner) => {
+                let passed = inner.0 < inner.1;
+                let (left, right) = (Token::Uint(inner.0), Token::Uint(inner.1));
+                self.assert_values(msg_sender, passed, "assertLt", left, ">=", right);
+            }
+            HEVMCalls::AssertLe(inner) => {
+                let passed = inner.0 <= inner.1;
+                let (left, right) = (Token::Uint(inner.0), Token::Uint(inner.1));
+                self.assert_values(msg_sender, passed, "assertLe", left, ">", right);
+            }
+            HEVMCalls::ReadCallers(_) => {
+                self.add_debug(CheatOp::READCALLERS);
+                let (mode, sender, origin) = self.read_callers(msg_sender);
+                res = ethers::abi::encode(&[
+                    Token::Uint(mode.into()),
+                    Token::Address(sender),
+                    Token::Address(origin),
+                ]);
+            }
+        };
+
+        // TODO: Add more cheat codes.
+        Capture::Exit((ExitReason::Succeed(ExitSucceed::Stopped), res))
+    }
+
+    // NB: This function is copy-pasted from upstream's `execute`, adjusted so that we call the
+    // Runtime with our own handler
+    pub fn execute(&mut self, runtime: &mut Runtime) -> ExitReason {
+        // the runtime is stepped, so that the memory of the frame is known when it calls another
+        loop {
+            self.inspect_step(runtime);
+            self.frame_memory = runtime.machine().memory().len();
+            match runtime.step(self) {
+                Ok(()) => {}
+                Err(Capture::Exit(s)) => return s,
+                Err(Capture::Trap(_)) => unreachable!("Trap is Infallible"),
+            }
+        }
+    }
+
+    /// Counts the memory of the calling frame as in use while a new frame is executed, and returns
+    /// it to be released with [`Self::exit_memory`]
+    fn enter_memory(&mut self) -> usize {
+        let parent_memory = std::mem::take(&mut self.frame_memory);
+        self.memory_in_use += parent_memory;
+        parent_memory
+    }
+
+    /// The memory a new frame may use, given the memory of the open frames below it
+    fn memory_available(&self) -> usize {
+        self.limits.memory_limit.unwrap_or(DEFAULT_MEMORY_LIMIT).saturating_sub(self.memory_in_use)
+    }
+
+    /// Releases the memory of the calling frame once the frame exited.
+    ///
+    /// Sputnik fails the whole transaction if a frame grows its memory past the limit, so the
+    /// frame fails like it ran out of gas instead, and the caller can continue.
+    fn exit_memory(&mut self, parent_memory: usize, reason: ExitReason) -> ExitReason {
+        self.memory_in_use -= parent_memory;
+        match reason {
+            ExitReason::Fatal(ExitFatal::NotSupported) => {
+                self.outcome.memory_limit_exceeded = true;
+                ExitReason::Error(ExitError::OutOfGas)
+            }
+            reason => reason,
+        }
+    }
+
+    /// Whether every step has to be inspected, because of hooks, coverage or the value collector
+    fn inspects_steps(&self) -> bool {
+        !self.hooks.is_empty() || self.coverage_code.is_some() || self.value_collector.is_some()
+    }
+
+    /// Records the coverage of the opcode which is executed next and passes it to the hooks
+    fn inspect_step(&mut self, runtime: &Runtime) {
+        if !self.inspects_steps() {
+            return
+        }
+        let machine = runtime.machine();
+        if let Some((op, stack)) = machine.inspect() {
+            let pc = machine.position().as_ref().map(|p| *p).unwrap_or_default();
+            if let Some((kind, code_hash)) = self.coverage_code {
+                self.coverage.hit(kind, code_hash, pc);
+            }
+            if let Some(collector) = &mut self.value_collector {
+                collector.step(runtime.context().address, pc, op, stack, machine.memory());
+            }
+            for hook in &self.hooks {
+                hook.borrow_mut().step(runtime.context().address, pc, op, stack, machine.memory());
+            }
+        }
+    }
+
+    /// Makes the code the one whose coverage is recorded, if enabled, and returns the code of the
+    /// calling frame which is restored afterwards
+    fn enter_coverage(&mut self, kind: CodeKind, code: &[u8]) -> Option<(CodeKind, H256)> {
+        let code = self.coverage_enabled.then(|| (kind, H256::from(utils::keccak256(code))));
+        std::mem::replace(&mut self.coverage_code, code)
+    }
+
+    /// Executes the call/create while also tracking the state of the machine (including opcodes)
+    fn debug_execute(
+        &mut self,
+        runtime: &mut Runtime,
+        address: Address,
+        code: Rc<Vec<u8>>,
+        creation: bool,
+        selector: Option<[u8; 4]>,
+    ) -> ExitReason {
+        let depth = if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
+
+        match self.debug_run(runtime, address, depth, code, creation, selector) {
+            Capture::Exit(s) => s,
+            Capture::Trap(_) => unreachable!("Trap is Infallible"),
+        }
+    }
+
+    /// Does *not* actually perform a step, just records the debug information for the step
+    fn debug_step(
+        &mut self,
+        runtime: &mut Runtime,
+        code: Rc<Vec<u8>>,
+        steps: &mut Vec<DebugStep>,
+        pc_ic: Rc<BTreeMap<usize, usize>>,
+    ) -> bool {
+        // grab the pc, opcode and stack
+        let pc = runtime.machine().position().as_ref().map(|p| *p).unwrap_or_default();
+        let mut push_bytes = None;
+        let snapshots = self.state().debug_snapshots;
+        let memory_size = runtime.machine().memory().len();
+        let memory = if snapshots { runtime.machine().memory().clone() } else { Memory::new(0) };
+        let gas_remaining = self.state().metadata().gasometer().gas();
+
+        if let Some((op, stack)) = runtime.machine().inspect() {
+            // wrap the op to make it compatible with opcode extensions for cheatops
+            let wrapped_op = OpCode::from(op);
+
+            // check how big the push size is, and grab the pushed bytes if possible
+            if let Some(push_size) = wrapped_op.push_size() {
+                let push_start = pc + 1;
+                let push_end = pc + 1 + push_size as usize;
+                if push_end < code.len() {
+                    push_bytes = Some(code[push_start..push_end].to_vec());
+                } else {
+                    panic!("PUSH{} exceeds limit of codesize", push_size)
+                }
+            }
+
+            // the access is only recorded when the step runs, so this is the state before it
+            let cold_slot = matches!(op, Opcode::SLOAD | Opcode::SSTORE) &&
+                stack.peek(0).map_or(false, |key| {
+                    self.state().is_storage_cold(runtime.context().address, key)
+                });
+
+            // grab the stack data and reverse it (last element is "top" of stack)
+            let mut stack = if snapshots { stack.data().clone() } else { vec![] };
+            stack.reverse();
+            // push the step into the vector
+            steps.push(DebugStep {
+                pc,
+                stack,
+                memory,
+                memory_size,
+                op: wrapped_op,
+                push_bytes,
+                ic: *pc_ic.get(&pc).as_ref().copied().unwrap_or(&0usize),
+                total_gas_used: self.handler.used_gas(),
+                gas_remaining,
+                cold_slot,
+            });
+            match op {
+                Opcode::CREATE |
+                Opcode::CREATE2 |
+                Opcode::CALL |
+                Opcode::CALLCODE |
+                Opcode::DELEGATECALL |
+                Opcode::STATICCALL => {
+                    // this would create an interrupt, have `debug_run` construct a new vec
+                    // to commit the current vector of steps into the debugarena
+                    // this maintains the call hierarchy correctly
+                    true
+                }
+                _ => false,
+            }
+        } else {
+            // failure case.
+            let mut stack =
+                if snapshots { runtime.machine().stack().data().clone() } else { vec![] };
+            stack.reverse();
+            steps.push(DebugStep {
+                pc,
+                stack,
+                memory,
+                memory_size,
+                op: OpCode::from(Opcode::INVALID),
+                push_bytes,
+                ic: *pc_ic.get(&pc).as_ref().copied().unwrap_or(&0usize),
+                total_gas_used: self.handler.used_gas(),
+                gas_remaining,
+                cold_slot: false,
+            });
+            true
+        }
+    }
+
+    fn debug_run(
+        &mut self,
+        runtime: &mut Runtime,
+        address: Address,
+        depth: usize,
+        code: Rc<Vec<u8>>,
+        creation: bool,
+        selector: Option<[u8; 4]>,
+    ) -> Capture<ExitReason, ()> {
+        let mut done = false;
+        let mut res = Capture::Exit(ExitReason::Succeed(ExitSucceed::Returned));
+        let mut steps = Vec::new();
+        // grab the debug instruction pointers for either construct or runtime bytecode
+        let dip = if creation {
+            &mut self.state_mut().debug_instruction_pointers.0
+        } else {
+            &mut self.state_mut().debug_instruction_pointers.1
+        };
+        // get the program counter => instruction counter mapping from memory or construct it
+        let ics = if let Some(pc_ic) = dip.get(&address) {
+            // grabs an Rc<BTreemap> of an already created pc -> ic mapping
+            pc_ic.clone()
+        } else {
+            // builds a program counter to instruction counter map
+            // basically this strips away bytecodes to make it work
+            // with the sourcemap output from the solc compiler
+            let mut pc_ic: BTreeMap<usize, usize> = BTreeMap::new();
+
+            let mut i = 0;
+            let mut push_ctr = 0usize;
+            while i < code.len() {
+                let wrapped_op = OpCode::from(Opcode(code[i]));
+                pc_ic.insert(i, i - push_ctr);
+
+                if let Some(push_size) = wrapped_op.push_size() {
+                    i += push_size as usize;
+                    i += 1;
+                    push_ctr += push_size as usize;
+                } else {
+                    i += 1;
                 }
             }
-            HEVMCalls::Prank1(inner) => {
-                self.add_debug(CheatOp::PRANK);
-                let caller = inner.0;
-                let origin = inner.1;
-                if let Err(err) = self.prank(true, msg_sender, caller, Some(origin)) {
-                    return err
+            let pc_ic = Rc::new(pc_ic);
+
+            dip.insert(address, pc_ic.clone());
+            pc_ic
+        };
+        while !done {
+            // debug step doesnt actually execute the step, it just peeks into the machine
+            // will return true or false, which signifies whether to push the steps
+            // as a node and reset the steps vector or not
+            if self.debug_step(runtime, code.clone(), &mut steps, ics.clone()) && !steps.is_empty()
+            {
+                self.state_mut().debug_mut().push_node(
+                    0,
+                    DebugNode {
+                        address,
+                        depth,
+                        steps: steps.clone(),
+                        creation,
+                        selector,
+                        ..Default::default()
+                    },
+                );
+                steps = Vec::new();
+            }
+            self.inspect_step(runtime);
+            self.frame_memory = runtime.machine().memory().len();
+            // actually executes the opcode step
+            let r = runtime.step(self);
+            match r {
+                Ok(()) => {}
+                Err(e) => {
+                    done = true;
+                    // we wont hit an interrupt when we finish stepping
+                    // so we have add the accumulated steps as if debug_step returned true
+                    if !steps.is_empty() {
+                        self.state_mut().debug_mut().push_node(
+                            0,
+                            DebugNode {
+                                address,
+                                depth,
+                                steps: steps.clone(),
+                                creation,
+                                selector,
+                                ..Default::default()
+                            },
+                        );
+                    }
+                    match e {
+                        Capture::Exit(s) => res = Capture::Exit(s),
+                        Capture::Trap(_) => unreachable!("Trap is Infallible"),
+                    }
                 }
             }
-            HEVMCalls::StartPrank1(inner) => {
-                self.add_debug(CheatOp::STARTPRANK);
-                let caller = inner.0;
-                let origin = inner.1;
-                if let Err(err) = self.prank(false, msg_sender, caller, Some(origin)) {
-                    return err
+        }
+        res
+    }
+
+    fn start_trace(
+        &mut self,
+        address: H160,
+        input: Vec<u8>,
+        transfer: U256,
+        kind: CallKind,
+    ) -> Option<CallTrace> {
+        if self.state().trace_enabled {
+            if self.state().trace_paused || self.state().untraced_calls > 0 {
+                self.state_mut().untraced_calls += 1;
+                return None
+            }
+            let mut trace: CallTrace = CallTrace {
+                // depth only starts tracking at first child substate and is 0. so add 1 when depth
+                // is some.
+                depth: if let Some(depth) = self.state().metadata().depth() {
+                    depth + 1
+                } else {
+                    0
+                },
+                addr: address,
+                created: kind.is_create(),
+                kind,
+                data: input,
+                value: transfer,
+                label: self.state().labels.get(&address).cloned(),
+                ..Default::default()
+            };
+
+            self.state_mut().trace_mut().push_trace(0, &mut trace);
+            self.state_mut().trace_index = trace.idx;
+            Some(trace)
+        } else {
+            None
+        }
+    }
+
+    /// Records the selfdestruct of the account as a node of the current call, which is completed
+    /// with the outcome of the call when it exits
+    fn trace_selfdestruct(&mut self, address: H160, beneficiary: H160, value: U256) {
+        let state = self.state();
+        if !state.trace_enabled || state.trace_paused || state.untraced_calls > 0 {
+            return
+        }
+        let mut trace = CallTrace {
+            depth: state.metadata().depth().map_or(0, |depth| depth + 1),
+            addr: address,
+            label: state.labels.get(&address).cloned(),
+            kind: CallKind::SelfDestruct,
+            value,
+            beneficiary: Some(beneficiary),
+            ..Default::default()
+        };
+        self.state_mut().trace_mut().push_trace(0, &mut trace);
+    }
+
+    /// Passes the node of a completed call frame to the hooks and the trace stream, and drops the
+    /// data of calls which isn't needed anymore if the traces are streamed
+    fn complete_trace_node(&mut self, idx: usize) {
+        if self.hooks.is_empty() && self.trace_stream.is_none() {
+            return
+        }
+        let node = &self.state().trace().arena[idx];
+        for hook in &self.hooks {
+            hook.borrow_mut().trace_node(node);
+        }
+        if let Some(stream) = self.trace_stream.clone() {
+            (stream.borrow_mut().0)(node);
+            let node = &mut self.state_mut().trace_mut().arena[idx];
+            if !node.trace.created {
+                // the selector names the call in the gas report and the flamegraphs
+                node.trace.data.truncate(4);
+                node.trace.data.shrink_to_fit();
+                node.trace.output = Vec::new();
+            }
+            node.logs = Vec::new();
+            node.ordering.retain(|order| matches!(order, LogCallOrder::Call(_)));
+        }
+    }
+
+    fn fill_trace(
+        &mut self,
+        new_trace: &Option<CallTrace>,
+        success: bool,
+        output: Option<Vec<u8>>,
+        pre_trace_index: usize,
+    ) {
+        self.state_mut().trace_index = pre_trace_index;
+        if new_trace.is_none() && self.state().untraced_calls > 0 {
+            self.state_mut().untraced_calls -= 1;
+        }
+        if let Some(new_trace) = new_trace {
+            // cheatcodes should cost 0 gas
+            let used_gas =
+                if new_trace.addr == *CHEATCODE_ADDRESS { 0 } else { self.handler.used_gas() };
+            let trace = &mut self.state_mut().trace_mut().arena[new_trace.idx].trace;
+            trace.output = output.unwrap_or_default();
+            trace.cost = used_gas;
+            trace.success = success;
+            // the selfdestructs of the call only take effect if it succeeds
+            let destructs = self.state().trace().arena[new_trace.idx]
+                .children
+                .iter()
+                .copied()
+                .filter(|child| {
+                    self.state().trace().arena[*child].trace.kind == CallKind::SelfDestruct
+                })
+                .collect::<Vec<_>>();
+            for idx in destructs {
+                self.state_mut().trace_mut().arena[idx].trace.success = success;
+                self.complete_trace_node(idx);
+            }
+            self.complete_trace_node(new_trace.idx);
+        }
+    }
+
+    // NB: This function is copy-pasted from upstream's call_inner
+    #[allow(clippy::too_many_arguments)]
+    fn call_inner(
+        &mut self,
+        code_address: H160,
+        transfer: Option<Transfer>,
+        input: Vec<u8>,
+        target_gas: Option<u64>,
+        is_static: bool,
+        take_l64: bool,
+        take_stipend: bool,
+        context: Context,
+    ) -> Capture<(ExitReason, Vec<u8>), Infallible> {
+        let pre_index = self.state().trace_index;
+        // sputnik only omits the transfer for delegatecalls and staticcalls
+        let kind = match (context.address == code_address, transfer.is_some()) {
+            (true, true) => CallKind::Call,
+            (true, false) => CallKind::StaticCall,
+            (false, true) => CallKind::CallCode,
+            (false, false) => CallKind::DelegateCall,
+        };
+        let trace = self.start_trace(
+            code_address,
+            input.clone(),
+            transfer.as_ref().map(|x| x.value).unwrap_or_default(),
+            kind,
+        );
+
+        macro_rules! try_or_fail {
+            ( $e:expr ) => {
+                match $e {
+                    Ok(v) => v,
+                    Err(e) => {
+                        self.fill_trace(&trace, false, None, pre_index);
+                        return Capture::Exit((e.into(), Vec::new()))
+                    }
                 }
+            };
+        }
+
+        fn l64(gas: u64) -> u64 {
+            gas - gas / 64
+        }
+
+        let after_gas = if take_l64 && self.config().call_l64_after_gas {
+            if self.config().estimate {
+                let initial_after_gas = self.state().metadata().gasometer().gas();
+                let diff = initial_after_gas - l64(initial_after_gas);
+                try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(diff));
+                self.state().metadata().gasometer().gas()
+            } else {
+                l64(self.state().metadata().gasometer().gas())
             }
-            HEVMCalls::StopPrank(_) => {
-                self.add_debug(CheatOp::STOPPRANK);
-                self.state_mut().prank = None;
+        } else {
+            self.state().metadata().gasometer().gas()
+        };
+
+        let target_gas = target_gas.unwrap_or(after_gas);
+        let mut gas_limit = std::cmp::min(target_gas, after_gas);
+
+        try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(gas_limit));
+
+        if let Some(transfer) = transfer.as_ref() {
+            if take_stipend && transfer.value != U256::zero() {
+                gas_limit = gas_limit.saturating_add(self.config().call_stipend);
             }
-            HEVMCalls::ExpectRevert0(inner) => {
-                if let Err(e) = self.expect_revert(inner.0.to_vec()) {
-                    return e
+        }
+
+        let mut code = self.code(code_address);
+        // an EOA which delegates with EIP-7702 runs the code of its delegate
+        if let Some(delegate) = delegated_address(&code) {
+            code = self.code(delegate);
+        }
+        self.handler.enter_substate(gas_limit, is_static);
+        self.state_mut().touch(context.address);
+
+        if let Some(depth) = self.state().metadata().depth() {
+            if depth > self.call_depth_limit() {
+                self.outcome.call_depth_exceeded = true;
+                self.fill_trace(&trace, false, None, pre_index);
+                let _ = self.handler.exit_substate(StackExitKind::Reverted);
+                return Capture::Exit((ExitError::CallTooDeep.into(), Vec::new()))
+            }
+        }
+
+        if let Some(transfer) = transfer {
+            match self.state_mut().transfer(transfer) {
+                Ok(()) => (),
+                Err(e) => {
+                    self.fill_trace(&trace, false, None, pre_index);
+                    let _ = self.handler.exit_substate(StackExitKind::Reverted);
+                    return Capture::Exit((ExitReason::Error(e), Vec::new()))
+                }
+            }
+        }
+
+        let precompile = match self.precompile_overrides.0.get(&code_address) {
+            Some(precompile) => Some(precompile(&input, Some(gas_limit), &context, is_static)),
+            None => self.handler.precompiles().execute(
+                code_address,
+                &input,
+                Some(gas_limit),
+                &context,
+                is_static,
+            ),
+        };
+        if let Some(result) = precompile {
+            return match result {
+                Ok(PrecompileOutput { exit_status, output, cost, logs }) => {
+                    for Log { address, topics, data } in logs {
+                        match self.log(address, topics, data) {
+                            Ok(_) => continue,
+                            Err(error) => {
+                                self.fill_trace(&trace, false, Some(output.clone()), pre_index);
+                                return Capture::Exit((ExitReason::Error(error), output))
+                            }
+                        }
+                    }
+
+                    let _ = self.state_mut().metadata_mut().gasometer_mut().record_cost(cost);
+                    self.fill_trace(&trace, true, Some(output.clone()), pre_index);
+                    let _ = self.handler.exit_substate(StackExitKind::Succeeded);
+                    Capture::Exit((ExitReason::Succeed(exit_status), output))
+                }
+                Err(e) => {
+                    let e = match e {
+                        PrecompileFailure::Error { exit_status } => ExitReason::Error(exit_status),
+                        PrecompileFailure::Revert { exit_status, .. } => {
+                            ExitReason::Revert(exit_status)
+                        }
+                        PrecompileFailure::Fatal { exit_status } => ExitReason::Fatal(exit_status),
+                    };
+                    self.fill_trace(&trace, false, None, pre_index);
+                    let _ = self.handler.exit_substate(StackExitKind::Failed);
+                    Capture::Exit((e, Vec::new()))
                 }
             }
-            HEVMCalls::ExpectRevert1(inner) => {
-                if let Err(e) = self.expect_revert(inner.0.to_vec()) {
-                    return e
-                }
+        }
+
+        // each cfg is about 200 bytes, is this a lot to clone? why does this error
+        // not manifest upstream?
+        let mut config = self.config().clone();
+        let parent_memory = self.enter_memory();
+        config.memory_limit = config.memory_limit.min(self.memory_available());
+        for hook in &self.hooks {
+            hook.borrow_mut().call(&context, code_address, &input);
+        }
+        let hook_context = (!self.hooks.is_empty()).then(|| context.clone());
+        let parent_code = self.enter_coverage(CodeKind::Runtime, &code);
+        let mut runtime;
+        let reason = if self.state().debug_enabled {
+            let code = Rc::new(code);
+            let selector = input.get(..4).and_then(|selector| selector.try_into().ok());
+            runtime = Runtime::new(code.clone(), Rc::new(input), context, &config);
+            self.debug_execute(&mut runtime, code_address, code, false, selector)
+        } else {
+            runtime = Runtime::new(Rc::new(code), Rc::new(input), context, &config);
+            self.execute(&mut runtime)
+        };
+        let reason = self.exit_memory(parent_memory, reason);
+        self.coverage_code = parent_code;
+        if let Some(context) = hook_context {
+            let output = runtime.machine().return_value();
+            for hook in &self.hooks {
+                hook.borrow_mut().call_end(&context, &reason, &output);
+            }
+        }
+
+        // // log::debug!(target: "evm", "Call execution using address {}: {:?}", code_address,
+        // reason);
+
+        match reason {
+            ExitReason::Succeed(s) => {
+                self.fill_trace(&trace, true, Some(runtime.machine().return_value()), pre_index);
+                let _ = self.handler.exit_substate(StackExitKind::Succeeded);
+                Capture::Exit((ExitReason::Succeed(s), runtime.machine().return_value()))
             }
-            HEVMCalls::Deal(inner) => {
-                self.add_debug(CheatOp::DEAL);
-                let who = inner.0;
-                let value = inner.1;
-                self.state_mut().reset_balance(who);
-                self.state_mut().deposit(who, value);
+            ExitReason::Error(e) => {
+                self.fill_trace(&trace, false, Some(runtime.machine().return_value()), pre_index);
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                Capture::Exit((ExitReason::Error(e), Vec::new()))
             }
-            HEVMCalls::Etch(inner) => {
-                self.add_debug(CheatOp::ETCH);
-                let who = inner.0;
-                let code = inner.1;
-                self.state_mut().set_code(who, code.to_vec());
+            ExitReason::Revert(e) => {
+                self.fill_trace(&trace, false, Some(runtime.machine().return_value()), pre_index);
+                let _ = self.handler.exit_substate(StackExitKind::Reverted);
+                Capture::Exit((ExitReason::Revert(e), runtime.machine().return_value()))
             }
-            HEVMCalls::Record(_) => {
-                self.add_debug(CheatOp::RECORD);
-                self.state_mut().accesses = Some(Default::default());
+            ExitReason::Fatal(e) => {
+                self.fill_trace(&trace, false, Some(runtime.machine().return_value()), pre_index);
+                self.state_mut().metadata_mut().gasometer_mut().fail();
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                Capture::Exit((ExitReason::Fatal(e), Vec::new()))
             }
-            HEVMCalls::Accesses(inner) => {
-                self.add_debug(CheatOp::ACCESSES);
-                let address = inner.0;
-                // we dont reset all records in case user wants to query multiple address
-                if let Some(record_accesses) = &self.state().accesses {
-                    res = ethers::abi::encode(&[
-                        record_accesses
-                            .reads
-                            .borrow_mut()
-                            .remove(&address)
-                            .unwrap_or_default()
-                            .into_tokens()[0]
-                            .clone(),
-                        record_accesses
-                            .writes
-                            .borrow_mut()
-                            .remove(&address)
-                            .unwrap_or_default()
-                            .into_tokens()[0]
-                            .clone(),
-                    ]);
-                    if record_accesses.reads.borrow().len() == 0 &&
-                        record_accesses.writes.borrow().len() == 0
-                    {
-                        self.state_mut().accesses = None;
+        }
+    }
+
+    // NB: This function is copy-pasted from upstream's create_inner
+    fn create_inner(
+        &mut self,
+        caller: H160,
+        scheme: CreateScheme,
+        value: U256,
+        init_code: Vec<u8>,
+        target_gas: Option<u64>,
+        take_l64: bool,
+    ) -> Capture<(ExitReason, Option<H160>, Vec<u8>), Infallible> {
+        let pre_index = self.state().trace_index;
+
+        let kind = match scheme {
+            CreateScheme::Create2 { .. } => CallKind::Create2,
+            _ => CallKind::Create,
+        };
+        let address = self.create_address(scheme);
+        let trace = self.start_trace(address, init_code.clone(), value, kind);
+
+        macro_rules! try_or_fail {
+            ( $e:expr ) => {
+                match $e {
+                    Ok(v) => v,
+                    Err(e) => {
+                        self.fill_trace(&trace, false, None, pre_index);
+                        return Capture::Exit((e.into(), None, Vec::new()))
                     }
-                } else {
-                    res = ethers::abi::encode(&[Token::Array(vec![]), Token::Array(vec![])]);
+                }
+            };
+        }
+
+        fn check_first_byte(config: &Config, code: &[u8]) -> Result<(), ExitError> {
+            if config.disallow_executable_format {
+                if let Some(0xef) = code.get(0) {
+                    return Err(ExitError::InvalidCode)
                 }
             }
-            HEVMCalls::ExpectEmit(inner) => {
-                self.add_debug(CheatOp::EXPECTEMIT);
-                let expected_emit = ExpectedEmit {
-                    depth: if let Some(depth) = self.state().metadata().depth() {
-                        depth + 1
-                    } else {
-                        0
-                    },
-                    log: None,
-                    checks: [inner.0, inner.1, inner.2, inner.3],
-                    found: false,
-                };
-                self.state_mut().expected_emits.push(expected_emit);
+            Ok(())
+        }
+
+        fn l64(gas: u64) -> u64 {
+            gas - gas / 64
+        }
+
+        self.state_mut().metadata_mut().access_address(caller);
+        self.state_mut().metadata_mut().access_address(address);
+
+        if let Some(depth) = self.state().metadata().depth() {
+            if depth > self.call_depth_limit() {
+                self.outcome.call_depth_exceeded = true;
+                self.fill_trace(&trace, false, None, pre_index);
+                return Capture::Exit((ExitError::CallTooDeep.into(), None, Vec::new()))
             }
-            HEVMCalls::MockCall(inner) => {
-                self.add_debug(CheatOp::MOCKCALL);
-                self.state_mut()
-                    .mocked_calls
-                    .entry(inner.0)
-                    .or_default()
-                    .insert(inner.1.to_vec(), inner.2.to_vec());
+        }
+
+        if self.balance(caller) < value {
+            self.fill_trace(&trace, false, None, pre_index);
+            return Capture::Exit((ExitError::OutOfFund.into(), None, Vec::new()))
+        }
+
+        let after_gas = if take_l64 && self.config().call_l64_after_gas {
+            if self.config().estimate {
+                let initial_after_gas = self.state().metadata().gasometer().gas();
+                let diff = initial_after_gas - l64(initial_after_gas);
+                try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(diff));
+                self.state().metadata().gasometer().gas()
+            } else {
+                l64(self.state().metadata().gasometer().gas())
             }
-            HEVMCalls::ClearMockedCalls(_) => {
-                self.add_debug(CheatOp::CLEARMOCKEDCALLS);
-                self.state_mut().mocked_calls = Default::default();
+        } else {
+            self.state().metadata().gasometer().gas()
+        };
+
+        let target_gas = target_gas.unwrap_or(after_gas);
+
+        let gas_limit = core::cmp::min(after_gas, target_gas);
+        try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(gas_limit));
+
+        self.state_mut().inc_nonce(caller);
+
+        self.handler.enter_substate(gas_limit, false);
+
+        {
+            // on a fork, the code and nonce of the address are fetched from the endpoint
+            if self.code_size(address) != U256::zero() || self.handler.nonce(address) > U256::zero()
+            {
+                self.fill_trace(&trace, false, None, pre_index);
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                self.outcome.create_collision.get_or_insert(address);
+                return Capture::Exit((ExitError::CreateCollision.into(), None, Vec::new()))
             }
-            HEVMCalls::ExpectCall(inner) => {
-                self.add_debug(CheatOp::EXPECTCALL);
-                self.state_mut().expected_calls.entry(inner.0).or_default().push(inner.1.to_vec());
+
+            self.state_mut().reset_storage(address);
+            self.state_mut().created.insert(address);
+        }
+
+        let context = Context { address, caller, apparent_value: value };
+        let transfer = Transfer { source: caller, target: address, value };
+        match self.state_mut().transfer(transfer) {
+            Ok(()) => (),
+            Err(e) => {
+                self.fill_trace(&trace, false, None, pre_index);
+                let _ = self.handler.exit_substate(StackExitKind::Reverted);
+                return Capture::Exit((ExitReason::Error(e), None, Vec::new()))
             }
-            HEVMCalls::Label(inner) => {
-                self.add_debug(CheatOp::LABEL);
-                let address = inner.0;
-                let label = inner.1;
+        }
 
-                self.state_mut().labels.insert(address, label);
+        if self.config().create_increase_nonce {
+            self.state_mut().inc_nonce(address);
+        }
+
+        let mut config = self.config().clone();
+        let parent_memory = self.enter_memory();
+        config.memory_limit = config.memory_limit.min(self.memory_available());
+        for hook in &self.hooks {
+            hook.borrow_mut().create(&context, &init_code);
+        }
+        let hook_context = (!self.hooks.is_empty()).then(|| context.clone());
+        let parent_code = self.enter_coverage(CodeKind::Creation, &init_code);
+        let mut runtime;
+        let reason = if self.state().debug_enabled {
+            let code = Rc::new(init_code);
+            runtime = Runtime::new(code.clone(), Rc::new(Vec::new()), context, &config);
+            self.debug_execute(&mut runtime, address, code, true, None)
+        } else {
+            runtime = Runtime::new(Rc::new(init_code), Rc::new(Vec::new()), context, &config);
+            self.execute(&mut runtime)
+        };
+        let reason = self.exit_memory(parent_memory, reason);
+        self.coverage_code = parent_code;
+        if let Some(context) = hook_context {
+            for hook in &self.hooks {
+                hook.borrow_mut().create_end(&context, &reason);
+            }
+        }
+        // log::debug!(target: "evm", "Create execution using address {}: {:?}", address, reason);
+
+        match reason {
+            ExitReason::Succeed(s) => {
+                let out = runtime.machine().return_value();
+
+                // As of EIP-3541 code starting with 0xef cannot be deployed
+                if let Err(e) = check_first_byte(self.config(), &out) {
+                    self.state_mut().metadata_mut().gasometer_mut().fail();
+                    self.fill_trace(&trace, false, None, pre_index);
+                    let _ = self.handler.exit_substate(StackExitKind::Failed);
+                    return Capture::Exit((e.into(), None, Vec::new()))
+                }
+
+                let limit = self.config().create_contract_limit;
+                if let Some(limit) = limit.filter(|_| !self.limits.contract_size_limit_disabled) {
+                    if out.len() > limit {
+                        self.state_mut().metadata_mut().gasometer_mut().fail();
+                        self.fill_trace(&trace, false, None, pre_index);
+                        let _ = self.handler.exit_substate(StackExitKind::Failed);
+                        return Capture::Exit((
+                            ExitError::CreateContractLimit.into(),
+                            None,
+                            Vec::new(),
+                        ))
+                    }
+                }
+
+                match self.state_mut().metadata_mut().gasometer_mut().record_deposit(out.len()) {
+                    Ok(()) => {
+                        self.fill_trace(&trace, true, Some(out.clone()), pre_index);
+                        let e = self.handler.exit_substate(StackExitKind::Succeeded);
+                        self.state_mut().set_code(address, out);
+                        // this may overwrite the trace and thats okay
+                        try_or_fail!(e);
+                        Capture::Exit((ExitReason::Succeed(s), Some(address), Vec::new()))
+                    }
+                    Err(e) => {
+                        self.fill_trace(&trace, false, None, pre_index);
+                        let _ = self.handler.exit_substate(StackExitKind::Failed);
+                        Capture::Exit((ExitReason::Error(e), None, Vec::new()))
+                    }
+                }
+            }
+            ExitReason::Error(e) => {
+                self.state_mut().metadata_mut().gasometer_mut().fail();
+                self.fill_trace(&trace, false, None, pre_index);
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                Capture::Exit((ExitReason::Error(e), None, Vec::new()))
+            }
+            ExitReason::Revert(e) => {
+                self.fill_trace(&trace, false, Some(runtime.machine().return_value()), pre_index);
+                let _ = self.handler.exit_substate(StackExitKind::Reverted);
+                Capture::Exit((ExitReason::Revert(e), None, runtime.machine().return_value()))
             }
-            HEVMCalls::Assume(inner) => {
-                self.add_debug(CheatOp::ASSUME);
-                if !inner.0 {
-                    res = ASSUME_MAGIC_RETURN_CODE.into();
-                    return Capture::Exit((ExitReason::Revert(ExitRevert::Reverted), res))
-                }
+            ExitReason::Fatal(e) => {
+                self.state_mut().metadata_mut().gasometer_mut().fail();
+                self.fill_trace(&trace, false, None, pre_index);
+                let _ = self.handler.exit_substate(StackExitKind::Failed);
+                Capture::Exit((ExitReason::Fatal(e), None, Vec::new()))
             }
-        };
-
-        self.fill_trace(&trace, true, Some(res.clone()), pre_index);
-        // cheatcodes should cost 0 gas
-        if let Some(new_trace) = &trace {
-            let trace = &mut self.state_mut().trace_mut().arena[new_trace.idx].trace;
-            trace.cost = 0;
         }
-        // TODO: Add more cheat codes.
-        Capture::Exit((ExitReason::Succeed(ExitSucceed::Stopped), res))
     }
+}
 
-    // NB: This function is copy-pasted from upstream's `execute`, adjusted so that we call the
-    // Runtime with our own handler
-    pub fn execute(&mut self, runtime: &mut Runtime) -> ExitReason {
-        match runtime.run(self) {
-            Capture::Exit(s) => s,
-            Capture::Trap(_) => unreachable!("Trap is Infallible"),
-        }
-    }
+// Delegates everything internally, except the `call_inner` call, which is hooked
+// so that we can modify
+impl<'a, 'b, B: Backend, P: PrecompileSet> Handler for CheatcodeStackExecutor<'a, 'b, B, P> {
+    type CreateInterrupt = Infallible;
+    type CreateFeedback = Infallible;
+    type CallInterrupt = Infallible;
+    type CallFeedback = Infallible;
 
-    /// Executes the call/create while also tracking the state of the machine (including opcodes)
-    fn debug_execute(
+    fn call(
         &mut self,
-        runtime: &mut Runtime,
-        address: Address,
-        code: Rc<Vec<u8>>,
-        creation: bool,
-    ) -> ExitReason {
-        let depth = if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
-
-        match self.debug_run(runtime, address, depth, code, creation) {
-            Capture::Exit(s) => s,
-            Capture::Trap(_) => unreachable!("Trap is Infallible"),
-        }
-    }
+        code_address: H160,
+        transfer: Option<Transfer>,
+        input: Vec<u8>,
+        target_gas: Option<u64>,
+        is_static: bool,
+        context: Context,
+    ) -> Capture<(ExitReason, Vec<u8>), Self::CallInterrupt> {
+        // We intercept calls to the `CHEATCODE_ADDRESS` to apply the cheatcode directly
+        // to the state.
+        // NB: This is very similar to how Optimism's custom intercept logic to "predeploys" work
+        // (e.g. with the StateManager)
+        let depth = self.state().metadata().depth();
+        if code_address == *CHEATCODE_ADDRESS && self.cheatcode_access.allows(context.caller, depth)
+        {
+            self.apply_cheatcode(input, context.caller)
+        } else if code_address == *CONSOLE_ADDRESS {
+            self.console_log(input)
+        } else {
+            // record prior origin
+            let prev_origin = self.state().backend.cheats.env.origin;
 
-    /// Does *not* actually perform a step, just records the debug information for the step
-    fn debug_step(
-        &mut self,
-        runtime: &mut Runtime,
-        code: Rc<Vec<u8>>,
-        steps: &mut Vec<DebugStep>,
-        pc_ic: Rc<BTreeMap<usize, usize>>,
-    ) -> bool {
-        // grab the pc, opcode and stack
-        let pc = runtime.machine().position().as_ref().map(|p| *p).unwrap_or_default();
-        let mut push_bytes = None;
+            // modify execution context depending on the cheatcode
+            let expected_revert = self.state_mut().expected_revert.take();
+            let mut new_context = context;
+            let mut new_transfer = transfer;
+            let curr_depth =
+                if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
 
-        if let Some((op, stack)) = runtime.machine().inspect() {
-            // wrap the op to make it compatible with opcode extensions for cheatops
-            let wrapped_op = OpCode::from(op);
+            // handle `startPrank` - see apply_cheatcodes for more info
+            if let Some(Prank { prank_caller, new_caller, new_origin, depth }) = self.state().prank
+            {
+                // if depth and msg.sender match, perform the prank
+                if curr_depth == depth && new_context.caller == prank_caller {
+                    new_context.caller = new_caller;
 
-            // check how big the push size is, and grab the pushed bytes if possible
-            if let Some(push_size) = wrapped_op.push_size() {
-                let push_start = pc + 1;
-                let push_end = pc + 1 + push_size as usize;
-                if push_end < code.len() {
-                    push_bytes = Some(code[push_start..push_end].to_vec());
-                } else {
-                    panic!("PUSH{} exceeds limit of codesize", push_size)
-                }
-            }
+                    if let Some(t) = &new_transfer {
+                        new_transfer =
+                            Some(Transfer { source: new_caller, target: t.target, value: t.value });
+                    }
 
-            // grab the stack data and reverse it (last element is "top" of stack)
-            let mut stack = stack.data().clone();
-            stack.reverse();
-            // push the step into the vector
-            steps.push(DebugStep {
-                pc,
-                stack,
-                memory: runtime.machine().memory().clone(),
-                op: wrapped_op,
-                push_bytes,
-                ic: *pc_ic.get(&pc).as_ref().copied().unwrap_or(&0usize),
-                total_gas_used: self.handler.used_gas(),
-            });
-            match op {
-                Opcode::CREATE |
-                Opcode::CREATE2 |
-                Opcode::CALL |
-                Opcode::CALLCODE |
-                Opcode::DELEGATECALL |
-                Opcode::STATICCALL => {
-                    // this would create an interrupt, have `debug_run` construct a new vec
-                    // to commit the current vector of steps into the debugarena
-                    // this maintains the call hierarchy correctly
-                    true
+                    // set the origin if the user used the overloaded func
+                    if new_origin.is_some() {
+                        self.state_mut().backend.cheats.env.origin = new_origin;
+                    }
                 }
-                _ => false,
             }
-        } else {
-            // failure case.
-            let mut stack = runtime.machine().stack().data().clone();
-            stack.reverse();
-            steps.push(DebugStep {
-                pc,
-                stack,
-                memory: runtime.machine().memory().clone(),
-                op: OpCode::from(Opcode::INVALID),
-                push_bytes,
-                ic: *pc_ic.get(&pc).as_ref().copied().unwrap_or(&0usize),
-                total_gas_used: self.handler.used_gas(),
-            });
-            true
-        }
-    }
 
-    fn debug_run(
-        &mut self,
-        runtime: &mut Runtime,
-        address: Address,
-        depth: usize,
-        code: Rc<Vec<u8>>,
-        creation: bool,
-    ) -> Capture<ExitReason, ()> {
-        let mut done = false;
-        let mut res = Capture::Exit(ExitReason::Succeed(ExitSucceed::Returned));
-        let mut steps = Vec::new();
-        // grab the debug instruction pointers for either construct or runtime bytecode
-        let dip = if creation {
-            &mut self.state_mut().debug_instruction_pointers.0
-        } else {
-            &mut self.state_mut().debug_instruction_pointers.1
-        };
-        // get the program counter => instruction counter mapping from memory or construct it
-        let ics = if let Some(pc_ic) = dip.get(&address) {
-            // grabs an Rc<BTreemap> of an already created pc -> ic mapping
-            pc_ic.clone()
-        } else {
-            // builds a program counter to instruction counter map
-            // basically this strips away bytecodes to make it work
-            // with the sourcemap output from the solc compiler
-            let mut pc_ic: BTreeMap<usize, usize> = BTreeMap::new();
+            // handle normal `prank`
+            if let Some(Prank { new_caller, new_origin, .. }) = self.state_mut().next_prank.take() {
+                new_context.caller = new_caller;
 
-            let mut i = 0;
-            let mut push_ctr = 0usize;
-            while i < code.len() {
-                let wrapped_op = OpCode::from(Opcode(code[i]));
-                pc_ic.insert(i, i - push_ctr);
+                if let Some(t) = &new_transfer {
+                    new_transfer =
+                        Some(Transfer { source: new_caller, target: t.target, value: t.value });
+                }
 
-                if let Some(push_size) = wrapped_op.push_size() {
-                    i += push_size as usize;
-                    i += 1;
-                    push_ctr += push_size as usize;
-                } else {
-                    i += 1;
+                if new_origin.is_some() {
+                    self.state_mut().backend.cheats.env.origin = new_origin;
                 }
             }
-            let pc_ic = Rc::new(pc_ic);
 
-            dip.insert(address, pc_ic.clone());
-            pc_ic
-        };
-        while !done {
-            // debug step doesnt actually execute the step, it just peeks into the machine
-            // will return true or false, which signifies whether to push the steps
-            // as a node and reset the steps vector or not
-            if self.debug_step(runtime, code.clone(), &mut steps, ics.clone()) && !steps.is_empty()
-            {
-                self.state_mut().debug_mut().push_node(
-                    0,
-                    DebugNode {
-                        address,
-                        depth,
-                        steps: steps.clone(),
-                        creation,
-                        ..Default::default()
-                    },
-                );
-                steps = Vec::new();
+            // handle expected calls, a call fulfills a single expectation so that repeated
+            // expectations need repeated calls
+            let value = new_transfer.as_ref().map(|t| t.value).unwrap_or_default();
+            if let Some(expecteds) = self.state_mut().expected_calls.get_mut(&code_address) {
+                let found = expecteds
+                    .iter()
+                    .position(|expected| {
+                        expected.matches(&input, value) && !expected.is_satisfied()
+                    })
+                    .or_else(|| {
+                        expecteds.iter().position(|expected| expected.matches(&input, value))
+                    });
+                if let Some(idx) = found {
+                    expecteds[idx].found += 1;
+                }
             }
-            // actually executes the opcode step
-            let r = runtime.step(self);
-            match r {
-                Ok(()) => {}
-                Err(e) => {
-                    done = true;
-                    // we wont hit an interrupt when we finish stepping
-                    // so we have add the accumulated steps as if debug_step returned true
-                    if !steps.is_empty() {
-                        self.state_mut().debug_mut().push_node(
-                            0,
-                            DebugNode {
-                                address,
-                                depth,
-                                steps: steps.clone(),
-                                creation,
-                                ..Default::default()
-                            },
-                        );
-                    }
-                    match e {
-                        Capture::Exit(s) => res = Capture::Exit(s),
-                        Capture::Trap(_) => unreachable!("Trap is Infallible"),
-                    }
+
+            // handle mocked calls, an exact match takes precedence over the longest matching
+            // prefix, e.g. a mocked selector
+            if let Some(mocks) = self.state().mocked_calls.get(&code_address) {
+                if let Some(mock_retdata) = mocks.get(&input) {
+                    return Capture::Exit((
+                        ExitReason::Succeed(ExitSucceed::Returned),
+                        mock_retdata.clone(),
+                    ))
+                } else if let Some((_, mock_retdata)) = mocks
+                    .iter()
+                    .filter(|(mock, _)| input.starts_with(mock))
+                    .max_by_key(|(mock, _)| mock.len())
+                {
+                    return Capture::Exit((
+                        ExitReason::Succeed(ExitSucceed::Returned),
+                        mock_retdata.clone(),
+                    ))
                 }
             }
+
+            // perform the call
+            let res = self.call_inner(
+                code_address,
+                new_transfer,
+                input,
+                target_gas,
+                is_static,
+                true,
+                true,
+                new_context,
+            );
+
+            // if we set the origin, now we should reset to previous
+            self.state_mut().backend.cheats.env.origin = prev_origin;
+
+            // handle expected emits
+            if !self.state_mut().expected_emits.is_empty() &&
+                !self
+                    .state()
+                    .expected_emits
+                    .iter()
+                    .filter(|expected| expected.depth == curr_depth)
+                    .all(|expected| expected.found)
+            {
+                return evm_error("Log != expected log")
+            } else {
+                // empty out expected_emits after successfully capturing all of them
+                self.state_mut().expected_emits.retain(|expected| !expected.found);
+            }
+
+            self.expected_revert(ExpectRevertReturn::Call(res), expected_revert).into_call_inner()
         }
-        res
     }
 
-    fn start_trace(
-        &mut self,
-        address: H160,
-        input: Vec<u8>,
-        transfer: U256,
-        creation: bool,
-    ) -> Option<CallTrace> {
-        if self.state().trace_enabled {
-            let mut trace: CallTrace = CallTrace {
-                // depth only starts tracking at first child substate and is 0. so add 1 when depth
-                // is some.
-                depth: if let Some(depth) = self.state().metadata().depth() {
-                    depth + 1
-                } else {
-                    0
-                },
-                addr: address,
-                created: creation,
-                data: input,
-                value: transfer,
-                label: self.state().labels.get(&address).cloned(),
-                ..Default::default()
-            };
+    // Everything else is left the same
+    fn balance(&self, address: H160) -> U256 {
+        self.handler.balance(address)
+    }
 
-            self.state_mut().trace_mut().push_trace(0, &mut trace);
-            self.state_mut().trace_index = trace.idx;
-            Some(trace)
-        } else {
-            None
-        }
+    fn code_size(&self, address: H160) -> U256 {
+        self.handler.code_size(address)
     }
 
-    fn fill_trace(
-        &mut self,
-        new_trace: &Option<CallTrace>,
-        success: bool,
-        output: Option<Vec<u8>>,
-        pre_trace_index: usize,
-    ) {
-        self.state_mut().trace_index = pre_trace_index;
-        if let Some(new_trace) = new_trace {
-            let used_gas = self.handler.used_gas();
-            let trace = &mut self.state_mut().trace_mut().arena[new_trace.idx].trace;
-            trace.output = output.unwrap_or_default();
-            trace.cost = used_gas;
-            trace.success = success;
-        }
+    fn code_hash(&self, address: H160) -> H256 {
+        self.handler.code_hash(address)
     }
 
-    // NB: This function is copy-pasted from upstream's call_inner
-    #[allow(clippy::too_many_arguments)]
-    fn call_inner(
-        &mut self,
-        code_address: H160,
-        transfer: Option<Transfer>,
-        input: Vec<u8>,
-        target_gas: Option<u64>,
-        is_static: bool,
-        take_l64: bool,
-        take_stipend: bool,
-        context: Context,
-    ) -> Capture<(ExitReason, Vec<u8>), Infallible> {
-        let pre_index = self.state().trace_index;
-        let trace = self.start_trace(
-            code_address,
-            input.clone(),
-            transfer.as_ref().map(|x| x.value).unwrap_or_default(),
-            false,
-        );
+    fn code(&self, address: H160) -> Vec<u8> {
+        self.handler.code(address)
+    }
 
-        macro_rules! try_or_fail {
-            ( $e:expr ) => {
-                match $e {
-                    Ok(v) => v,
-                    Err(e) => {
-                        self.fill_trace(&trace, false, None, pre_index);
-                        return Capture::Exit((e.into(), Vec::new()))
-                    }
-                }
-            };
-        }
+    fn storage(&self, address: H160, index: H256) -> H256 {
+        self.handler.storage(address, index)
+    }
 
-        fn l64(gas: u64) -> u64 {
-            gas - gas / 64
-        }
+    fn original_storage(&self, address: H160, index: H256) -> H256 {
+        self.handler.original_storage(address, index)
+    }
 
-        let after_gas = if take_l64 && self.config().call_l64_after_gas {
-            if self.config().estimate {
-                let initial_after_gas = self.state().metadata().gasometer().gas();
-                let diff = initial_after_gas - l64(initial_after_gas);
-                try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(diff));
-                self.state().metadata().gasometer().gas()
-            } else {
-                l64(self.state().metadata().gasometer().gas())
-            }
-        } else {
-            self.state().metadata().gasometer().gas()
-        };
+    fn gas_left(&self) -> U256 {
+        // Need to disambiguate type, because the same method exists in the `SputnikExecutor`
+        // trait and the `Handler` trait.
+        Handler::gas_left(&self.handler)
+    }
 
-        let target_gas = target_gas.unwrap_or(after_gas);
-        let mut gas_limit = std::cmp::min(target_gas, after_gas);
+    fn gas_price(&self) -> U256 {
+        self.handler.gas_price()
+    }
 
-        try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(gas_limit));
+    fn origin(&self) -> H160 {
+        self.handler.origin()
+    }
 
-        if let Some(transfer) = transfer.as_ref() {
-            if take_stipend && transfer.value != U256::zero() {
-                gas_limit = gas_limit.saturating_add(self.config().call_stipend);
-            }
-        }
+    fn block_hash(&self, number: U256) -> H256 {
+        self.handler.block_hash(number)
+    }
 
-        let code = self.code(code_address);
-        self.handler.enter_substate(gas_limit, is_static);
-        self.state_mut().touch(context.address);
+    fn block_number(&self) -> U256 {
+        self.handler.block_number()
+    }
 
-        if let Some(depth) = self.state().metadata().depth() {
-            if depth > self.config().call_stack_limit {
-                self.fill_trace(&trace, false, None, pre_index);
-                let _ = self.handler.exit_substate(StackExitKind::Reverted);
-                return Capture::Exit((ExitError::CallTooDeep.into(), Vec::new()))
-            }
+    fn block_coinbase(&self) -> H160 {
+        self.handler.block_coinbase()
+    }
+
+    fn block_timestamp(&self) -> U256 {
+        self.handler.block_timestamp()
+    }
+
+    fn block_difficulty(&self) -> U256 {
+        self.handler.block_difficulty()
+    }
+
+    fn block_gas_limit(&self) -> U256 {
+        self.handler.block_gas_limit()
+    }
+
+    fn block_base_fee_per_gas(&self) -> U256 {
+        self.handler.block_base_fee_per_gas()
+    }
+
+    fn chain_id(&self) -> U256 {
+        self.handler.chain_id()
+    }
+
+    fn exists(&self, address: H160) -> bool {
+        self.handler.exists(address)
+    }
+
+    fn deleted(&self, address: H160) -> bool {
+        self.handler.deleted(address)
+    }
+
+    fn is_cold(&self, address: H160, index: Option<H256>) -> bool {
+        self.handler.is_cold(address, index)
+    }
+
+    fn set_storage(&mut self, address: H160, index: H256, value: H256) -> Result<(), ExitError> {
+        self.handler.set_storage(address, index, value)
+    }
+
+    fn log(&mut self, address: H160, topics: Vec<H256>, data: Vec<u8>) -> Result<(), ExitError> {
+        if self.state().trace_enabled &&
+            !self.state().trace_paused &&
+            self.state().untraced_calls == 0
+        {
+            let index = self.state().trace_index;
+            let node = &mut self.state_mut().traces.last_mut().expect("no traces").arena[index];
+            node.ordering.push(LogCallOrder::Log(node.logs.len()));
+            node.logs.push(RawLog { topics: topics.clone(), data: data.clone() });
         }
 
-        if let Some(transfer) = transfer {
-            match self.state_mut().transfer(transfer) {
-                Ok(()) => (),
-                Err(e) => {
-                    self.fill_trace(&trace, false, None, pre_index);
-                    let _ = self.handler.exit_substate(StackExitKind::Reverted);
-                    return Capture::Exit((ExitReason::Error(e), Vec::new()))
-                }
-            }
+        if let Some(decoded) =
+            convert_log(Log { address, topics: topics.clone(), data: data.clone() })
+        {
+            self.state_mut().all_logs.push(decoded);
         }
 
-        if let Some(result) = self.handler.precompiles().execute(
-            code_address,
-            &input,
-            Some(gas_limit),
-            &context,
-            is_static,
-        ) {
-            return match result {
-                Ok(PrecompileOutput { exit_status, output, cost, logs }) => {
-                    for Log { address, topics, data } in logs {
-                        match self.log(address, topics, data) {
-                            Ok(_) => continue,
-                            Err(error) => {
-                                self.fill_trace(&trace, false, Some(output.clone()), pre_index);
-                                return Capture::Exit((ExitReason::Error(error), output))
-                            }
-                        }
-                    }
+        if !self.state().expected_emits.is_empty() {
+            // get expected emits
+            let expected_emits = &mut self.state_mut().expected_emits;
+
+            // do we have empty expected emits to fill?
+            if let Some(next_expect_to_fill) =
+                expected_emits.iter_mut().find(|expect| expect.log.is_none())
+            {
+                next_expect_to_fill.log =
+                    Some(RawLog { topics: topics.clone(), data: data.clone() });
+            } else {
+                // no unfilled, grab next unfound
+                // try to fill the first unfound
+                if let Some(next_expect) = expected_emits.iter_mut().find(|expect| !expect.found) {
+                    // unpack the log
+                    if let Some(RawLog { topics: expected_topics, data: expected_data }) =
+                        &next_expect.log
+                    {
+                        if expected_topics[0] == topics[0] {
+                            // same event topic 0, topic length should be the same
+                            let topics_match = topics
+                                .iter()
+                                .skip(1)
+                                .enumerate()
+                                .filter(|(i, _topic)| {
+                                    // do we want to check?
+                                    next_expect.checks[*i]
+                                })
+                                .all(|(i, topic)| topic == &expected_topics[i + 1]);
 
-                    let _ = self.state_mut().metadata_mut().gasometer_mut().record_cost(cost);
-                    self.fill_trace(&trace, true, Some(output.clone()), pre_index);
-                    let _ = self.handler.exit_substate(StackExitKind::Succeeded);
-                    Capture::Exit((ExitReason::Succeed(exit_status), output))
-                }
-                Err(e) => {
-                    let e = match e {
-                        PrecompileFailure::Error { exit_status } => ExitReason::Error(exit_status),
-                        PrecompileFailure::Revert { exit_status, .. } => {
-                            ExitReason::Revert(exit_status)
+                            // check data
+                            next_expect.found = if next_expect.checks[3] {
+                                expected_data == &data && topics_match
+                            } else {
+                                topics_match
+                            };
                         }
-                        PrecompileFailure::Fatal { exit_status } => ExitReason::Fatal(exit_status),
-                    };
-                    self.fill_trace(&trace, false, None, pre_index);
-                    let _ = self.handler.exit_substate(StackExitKind::Failed);
-                    Capture::Exit((e, Vec::new()))
+                    }
                 }
             }
         }
 
-        // each cfg is about 200 bytes, is this a lot to clone? why does this error
-        // not manifest upstream?
-        let config = self.config().clone();
-        let mut runtime;
-        let reason = if self.state().debug_enabled {
-            let code = Rc::new(code);
-            runtime = Runtime::new(code.clone(), Rc::new(input), context, &config);
-            self.debug_execute(&mut runtime, code_address, code, false)
-        } else {
-            runtime = Runtime::new(Rc::new(code), Rc::new(input), context, &config);
-            self.execute(&mut runtime)
-        };
-
-        // // log::debug!(target: "evm", "Call execution using address {}: {:?}", code_address,
-        // reason);
+        self.handler.log(address, topics, data)
+    }
 
-        match reason {
-            ExitReason::Succeed(s) => {
-                self.fill_trace(&trace, true, Some(runtime.machine().return_value()), pre_index);
-                let _ = self.handler.exit_substate(StackExitKind::Succeeded);
-                Capture::Exit((ExitReason::Succeed(s), runtime.machine().return_value()))
-            }
-            ExitReason::Error(e) => {
-                self.fill_trace(&trace, false, Some(runtime.machine().return_value()), pre_index);
-                let _ = self.handler.exit_substate(StackExitKind::Failed);
-                Capture::Exit((ExitReason::Error(e), Vec::new()))
-            }
-            ExitReason::Revert(e) => {
-                self.fill_trace(&trace, false, Some(runtime.machine().return_value()), pre_index);
-                let _ = self.handler.exit_substate(StackExitKind::Reverted);
-                Capture::Exit((ExitReason::Revert(e), runtime.machine().return_value()))
-            }
-            ExitReason::Fatal(e) => {
-                self.fill_trace(&trace, false, Some(runtime.machine().return_value()), pre_index);
-                self.state_mut().metadata_mut().gasometer_mut().fail();
-                let _ = self.handler.exit_substate(StackExitKind::Failed);
-                Capture::Exit((ExitReason::Fatal(e), Vec::new()))
+    fn mark_delete(&mut self, address: H160, target: H160) -> Result<(), ExitError> {
+        // recorded even if the balance stays with the account
+        let balance = self.balance(address);
+        self.trace_selfdestruct(address, target, balance);
+        if self.eip6780 && !self.state().created.contains(&address) {
+            // the account is kept, so sending the balance to itself doesn't burn it
+            if address != target {
+                let value = self.balance(address);
+                self.state_mut().transfer(Transfer { source: address, target, value })?;
             }
+            return Ok(())
         }
+        self.handler.mark_delete(address, target)
     }
 
-    // NB: This function is copy-pasted from upstream's create_inner
-    fn create_inner(
+    fn create(
         &mut self,
         caller: H160,
         scheme: CreateScheme,
         value: U256,
         init_code: Vec<u8>,
         target_gas: Option<u64>,
-        take_l64: bool,
-    ) -> Capture<(ExitReason, Option<H160>, Vec<u8>), Infallible> {
-        let pre_index = self.state().trace_index;
+    ) -> Capture<(ExitReason, Option<H160>, Vec<u8>), Self::CreateInterrupt> {
+        // modify execution context depending on the cheatcode
 
-        let address = self.create_address(scheme);
-        let trace = self.start_trace(address, init_code.clone(), value, true);
+        let prev_origin = self.state().backend.cheats.env.origin;
+        let expected_revert = self.state_mut().expected_revert.take();
+        let mut new_tx_caller = caller;
+        let mut new_scheme = scheme;
+        let curr_depth =
+            if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
 
-        macro_rules! try_or_fail {
-            ( $e:expr ) => {
-                match $e {
-                    Ok(v) => v,
-                    Err(e) => {
-                        self.fill_trace(&trace, false, None, pre_index);
-                        return Capture::Exit((e.into(), None, Vec::new()))
-                    }
-                }
-            };
-        }
+        // handle `startPrank` - see apply_cheatcodes for more info
+        if let Some(Prank { prank_caller, new_caller, new_origin, depth }) = self.state().prank {
+            if curr_depth == depth && new_tx_caller == prank_caller {
+                new_tx_caller = new_caller;
 
-        fn check_first_byte(config: &Config, code: &[u8]) -> Result<(), ExitError> {
-            if config.disallow_executable_format {
-                if let Some(0xef) = code.get(0) {
-                    return Err(ExitError::InvalidCode)
+                if new_origin.is_some() {
+                    self.state_mut().backend.cheats.env.origin = new_origin
                 }
             }
-            Ok(())
         }
 
-        fn l64(gas: u64) -> u64 {
-            gas - gas / 64
-        }
-
-        self.state_mut().metadata_mut().access_address(caller);
-        self.state_mut().metadata_mut().access_address(address);
+        // handle normal `prank`
+        if let Some(Prank { new_caller, new_origin, .. }) = self.state_mut().next_prank.take() {
+            new_tx_caller = new_caller;
 
-        if let Some(depth) = self.state().metadata().depth() {
-            if depth > self.config().call_stack_limit {
-                self.fill_trace(&trace, false, None, pre_index);
-                return Capture::Exit((ExitError::CallTooDeep.into(), None, Vec::new()))
+            if new_origin.is_some() {
+                self.state_mut().backend.cheats.env.origin = new_origin
             }
         }
 
-        if self.balance(caller) < value {
-            self.fill_trace(&trace, false, None, pre_index);
-            return Capture::Exit((ExitError::OutOfFund.into(), None, Vec::new()))
+        if caller != new_tx_caller {
+            new_scheme = match scheme {
+                CreateScheme::Legacy { .. } => CreateScheme::Legacy { caller: new_tx_caller },
+                CreateScheme::Create2 { code_hash, salt, .. } => {
+                    CreateScheme::Create2 { caller: new_tx_caller, code_hash, salt }
+                }
+                _ => scheme,
+            };
         }
 
-        let after_gas = if take_l64 && self.config().call_l64_after_gas {
-            if self.config().estimate {
-                let initial_after_gas = self.state().metadata().gasometer().gas();
-                let diff = initial_after_gas - l64(initial_after_gas);
-                try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(diff));
-                self.state().metadata().gasometer().gas()
-            } else {
-                l64(self.state().metadata().gasometer().gas())
-            }
-        } else {
-            self.state().metadata().gasometer().gas()
-        };
-
-        let target_gas = target_gas.unwrap_or(after_gas);
-
-        let gas_limit = core::cmp::min(after_gas, target_gas);
-        try_or_fail!(self.state_mut().metadata_mut().gasometer_mut().record_cost(gas_limit));
-
-        self.state_mut().inc_nonce(caller);
+        let res = self.create_inner(new_tx_caller, new_scheme, value, init_code, target_gas, true);
 
-        self.handler.enter_substate(gas_limit, false);
+        // if we set the origin, now we should reset to prior origin
+        self.state_mut().backend.cheats.env.origin = prev_origin;
 
+        if !self.state_mut().expected_emits.is_empty() &&
+            !self
+                .state()
+                .expected_emits
+                .iter()
+                .filter(|expected| expected.depth == curr_depth)
+                .all(|expected| expected.found)
         {
-            if self.code_size(address) != U256::zero() {
-                self.fill_trace(&trace, false, None, pre_index);
-                let _ = self.handler.exit_substate(StackExitKind::Failed);
-                return Capture::Exit((ExitError::CreateCollision.into(), None, Vec::new()))
-            }
-
-            if self.handler.nonce(address) > U256::zero() {
-                self.fill_trace(&trace, false, None, pre_index);
-                let _ = self.handler.exit_substate(StackExitKind::Failed);
-                return Capture::Exit((ExitError::CreateCollision.into(), None, Vec::new()))
-            }
-
-            self.state_mut().reset_storage(address);
-        }
-
-        let context = Context { address, caller, apparent_value: value };
-        let transfer = Transfer { source: caller, target: address, value };
-        match self.state_mut().transfer(transfer) {
-            Ok(()) => (),
-            Err(e) => {
-                self.fill_trace(&trace, false, None, pre_index);
-                let _ = self.handler.exit_substate(StackExitKind::Reverted);
-                return Capture::Exit((ExitReason::Error(e), None, Vec::new()))
-            }
-        }
-
-        if self.config().create_increase_nonce {
-            self.state_mut().inc_nonce(address);
-        }
-
-        let config = self.config().clone();
-        let mut runtime;
-        let reason = if self.state().debug_enabled {
-            let code = Rc::new(init_code);
-            runtime = Runtime::new(code.clone(), Rc::new(Vec::new()), context, &config);
-            self.debug_execute(&mut runtime, address, code, true)
+            return revert_return_evm(false, None, || "Log != expected log").into_create_inner()
         } else {
-            runtime = Runtime::new(Rc::new(init_code), Rc::new(Vec::new()), context, &config);
-            self.execute(&mut runtime)
-        };
-        // log::debug!(target: "evm", "Create execution using address {}: {:?}", address, reason);
-
-        match reason {
-            ExitReason::Succeed(s) => {
-                let out = runtime.machine().return_value();
-
-                // As of EIP-3541 code starting with 0xef cannot be deployed
-                if let Err(e) = check_first_byte(self.config(), &out) {
-                    self.state_mut().metadata_mut().gasometer_mut().fail();
-                    self.fill_trace(&trace, false, None, pre_index);
-                    let _ = self.handler.exit_substate(StackExitKind::Failed);
-                    return Capture::Exit((e.into(), None, Vec::new()))
-                }
+            // empty out expected_emits after successfully capturing all of them
+            self.state_mut().expected_emits = Vec::new();
+        }
 
-                if let Some(limit) = self.config().create_contract_limit {
-                    if out.len() > limit {
-                        self.state_mut().metadata_mut().gasometer_mut().fail();
-                        self.fill_trace(&trace, false, None, pre_index);
-                        let _ = self.handler.exit_substate(StackExitKind::Failed);
-                        return Capture::Exit((
-                            ExitError::CreateContractLimit.into(),
-                            None,
-                            Vec::new(),
-                        ))
-                    }
-                }
+        self.expected_revert(ExpectRevertReturn::Create(res), expected_revert).into_create_inner()
+    }
 
-                match self.state_mut().metadata_mut().gasometer_mut().record_deposit(out.len()) {
-                    Ok(()) => {
-                        self.fill_trace(&trace, true, Some(out.clone()), pre_index);
-                        let e = self.handler.exit_substate(StackExitKind::Succeeded);
-                        self.state_mut().set_code(address, out);
-                        // this may overwrite the trace and thats okay
-                        try_or_fail!(e);
-                        Capture::Exit((ExitReason::Succeed(s), Some(address), Vec::new()))
-                    }
-                    Err(e) => {
-                        self.fill_trace(&trace, false, None, pre_index);
-                        let _ = self.handler.exit_substate(StackExitKind::Failed);
-                        Capture::Exit((ExitReason::Error(e), None, Vec::new()))
-                    }
-                }
-            }
-            ExitReason::Error(e) => {
-                self.state_mut().metadata_mut().gasometer_mut().fail();
-                self.fill_trace(&trace, false, None, pre_index);
-                let _ = self.handler.exit_substate(StackExitKind::Failed);
-                Capture::Exit((ExitReason::Error(e), None, Vec::new()))
+    fn pre_validate(
+        &mut self,
+        context: &Context,
+        opcode: sputnik::Opcode,
+        stack: &sputnik::Stack,
+    ) -> Result<(), ExitError> {
+        // sputnik rejects these in a static context, the first one is recorded so that read-only
+        // sessions can name it
+        if self.outcome.rejected_write.is_none() && self.state().metadata().is_static() {
+            let write = match opcode {
+                Opcode::SSTORE => Some(stack.peek(0).ok()),
+                Opcode::CALL => stack.peek(2).ok().filter(|value| !value.is_zero()).map(|_| None),
+                Opcode::CREATE |
+                Opcode::CREATE2 |
+                Opcode::SUICIDE |
+                Opcode::LOG0 |
+                Opcode::LOG1 |
+                Opcode::LOG2 |
+                Opcode::LOG3 |
+                Opcode::LOG4 => Some(None),
+                _ => None,
+            };
+            if let Some(slot) = write {
+                self.outcome.rejected_write =
+                    Some(ReadOnlyViolation { address: context.address, slot });
             }
-            ExitReason::Revert(e) => {
-                self.fill_trace(&trace, false, Some(runtime.machine().return_value()), pre_index);
-                let _ = self.handler.exit_substate(StackExitKind::Reverted);
-                Capture::Exit((ExitReason::Revert(e), None, runtime.machine().return_value()))
+        }
+
+        // sputnik would reject the blob opcodes as invalid, they are executed by `other`
+        if matches!(opcode, BLOBHASH | BLOBBASEFEE) &&
+            self.state().backend.cheats.env.blobs_enabled()
+        {
+            let cost = if opcode == BLOBHASH { 3 } else { 2 };
+            return self.state_mut().metadata_mut().gasometer_mut().record_cost(cost)
+        }
+
+        self.handler.pre_validate(context, opcode, stack)
+    }
+
+    fn other(&mut self, opcode: Opcode, machine: &mut Machine) -> Result<(), ExitError> {
+        let env = &self.state().backend.cheats.env;
+        if !env.blobs_enabled() {
+            return self.handler.other(opcode, machine)
+        }
+        match opcode {
+            BLOBHASH => {
+                let index = U256::from_big_endian(machine.stack_mut().pop()?.as_bytes());
+                let hash = if index < env.blob_hashes.len().into() {
+                    env.blob_hashes[index.as_usize()]
+                } else {
+                    H256::zero()
+                };
+                machine.stack_mut().push(hash)
             }
-            ExitReason::Fatal(e) => {
-                self.state_mut().metadata_mut().gasometer_mut().fail();
-                self.fill_trace(&trace, false, None, pre_index);
-                let _ = self.handler.exit_substate(StackExitKind::Failed);
-                Capture::Exit((ExitReason::Fatal(e), None, Vec::new()))
+            BLOBBASEFEE => {
+                let fee = env.blob_base_fee.unwrap_or_else(|| MIN_BLOB_BASE_FEE.into());
+                machine.stack_mut().push(H256::from_uint(&fee))
             }
+            _ => self.handler.other(opcode, machine),
         }
     }
 }
 
-// Delegates everything internally, except the `call_inner` call, which is hooked
-// so that we can modify
-impl<'a, 'b, B: Backend, P: PrecompileSet> Handler for CheatcodeStackExecutor<'a, 'b, B, P> {
-    type CreateInterrupt = Infallible;
-    type CreateFeedback = Infallible;
-    type CallInterrupt = Infallible;
-    type CallFeedback = Infallible;
+#[cfg(test)]
+mod tests {
+    use crate::{
+        call_tracing::ExecutionInfo,
+        fuzz::FuzzedExecutor,
+        sputnik::{
+            helpers::{
+                fork_backend, fork_provider, new_backend, vm, vm_debug, vm_no_limit, vm_tracing,
+                vm_with_backend, TestSputnikVM, CFG, CFG_NO_LMT, GAS_LIMIT, VICINITY,
+            },
+            PRECOMPILES_MAP,
+        },
+        test_helpers::COMPILED,
+        Evm,
+    };
+    use ethers::types::Bytes;
+    use sputnik::backend::MemoryAccount;
 
-    fn call(
-        &mut self,
-        code_address: H160,
-        transfer: Option<Transfer>,
-        input: Vec<u8>,
-        target_gas: Option<u64>,
-        is_static: bool,
-        context: Context,
-    ) -> Capture<(ExitReason, Vec<u8>), Self::CallInterrupt> {
-        // We intercept calls to the `CHEATCODE_ADDRESS` to apply the cheatcode directly
-        // to the state.
-        // NB: This is very similar to how Optimism's custom intercept logic to "predeploys" work
-        // (e.g. with the StateManager)
-        if code_address == *CHEATCODE_ADDRESS {
-            self.apply_cheatcode(input, context.caller)
-        } else if code_address == *CONSOLE_ADDRESS {
-            self.console_log(input)
-        } else {
-            // record prior origin
-            let prev_origin = self.state().backend.cheats.origin;
+    use super::*;
 
-            // modify execution context depending on the cheatcode
-            let expected_revert = self.state_mut().expected_revert.take();
-            let mut new_context = context;
-            let mut new_transfer = transfer;
-            let curr_depth =
-                if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
+    #[test]
+    fn can_set_default_balance() {
+        let balance = U256::exp10(24);
+        let known = Address::repeat_byte(1);
+        let (from, to) = (Address::repeat_byte(2), Address::repeat_byte(3));
+        let backend = new_backend(&*VICINITY, [(known, Default::default())].into_iter().collect());
+        let mut evm = vm_with_backend(backend).with_default_balance(balance);
+
+        // accounts of the backend's state keep their balance, and so do the precompiles, the
+        // cheatcode address and the coinbase
+        assert_eq!(evm.state().basic(known).balance, U256::zero());
+        assert_eq!(evm.state().basic(from).balance, balance);
+        let coinbase = evm.state().block_coinbase();
+        for address in [Address::from_low_u64_be(1), *CHEATCODE_ADDRESS, coinbase] {
+            assert_eq!(evm.state().basic(address).balance, U256::zero());
+        }
 
-            // handle `startPrank` - see apply_cheatcodes for more info
-            if let Some(Prank { prank_caller, new_caller, new_origin, depth }) = self.state().prank
-            {
-                // if depth and msg.sender match, perform the prank
-                if curr_depth == depth && new_context.caller == prank_caller {
-                    new_context.caller = new_caller;
+        // the unknown sender can pay for the transfer, and written balances win
+        let (_, status, _, _) = evm.call_raw(from, to, Bytes::new(), 5.into(), false).unwrap();
+        assert!(matches!(status, ExitReason::Succeed(_)), "{:?}", status);
+        assert_eq!(evm.state().basic(from).balance, balance - 5);
+        assert_eq!(evm.state().basic(to).balance, balance + 5);
+        evm.deal(from, 1.into());
+        assert_eq!(evm.state().basic(from).balance, 1.into());
+
+        // sending value to an unknown account still pays for creating it
+        let fresh = Address::repeat_byte(4);
+        let caller = Address::repeat_byte(5);
+        // CALL(gas, fresh, 1, 0, 0, 0, 0) STOP
+        let code =
+            hex::decode(format!("6000600060006000600173{}5af100", hex::encode(fresh))).unwrap();
+        let gas_used = |evm: &mut TestSputnikVM<MemoryBackend>| {
+            evm.initialize_contracts([(caller, code.clone().into())]);
+            evm.deal(caller, 1.into());
+            let res = evm.call_result(from, caller, Bytes::new(), 0.into());
+            assert!(res.success);
+            res.gas_used
+        };
+        let expected = gas_used(&mut vm());
+        assert!(expected > 25000);
+        assert_eq!(gas_used(&mut evm), expected);
+    }
 
-                    if let Some(t) = &new_transfer {
-                        new_transfer =
-                            Some(Transfer { source: new_caller, target: t.target, value: t.value });
-                    }
+    #[test]
+    fn can_hook_into_execution() {
+        #[derive(Debug, Default)]
+        struct Recorder {
+            calls: Vec<(Address, Address)>,
+            ended: usize,
+            creates: Vec<Address>,
+            steps: usize,
+        }
 
-                    // set the origin if the user used the overloaded func
-                    self.state_mut().backend.cheats.origin = new_origin;
-                }
+        impl ExecutionHook for Recorder {
+            fn call(&mut self, context: &Context, code_address: Address, _input: &[u8]) {
+                self.calls.push((context.caller, code_address));
             }
-
-            // handle normal `prank`
-            if let Some(Prank { new_caller, new_origin, .. }) = self.state_mut().next_prank.take() {
-                new_context.caller = new_caller;
-
-                if let Some(t) = &new_transfer {
-                    new_transfer =
-                        Some(Transfer { source: new_caller, target: t.target, value: t.value });
-                }
-
-                self.state_mut().backend.cheats.origin = new_origin;
+            fn call_end(&mut self, _context: &Context, _reason: &ExitReason, _output: &[u8]) {
+                self.ended += 1;
             }
-
-            // handle expected calls
-            if let Some(expecteds) = self.state_mut().expected_calls.get_mut(&code_address) {
-                if let Some(found_match) = expecteds.iter().position(|expected| {
-                    expected.len() <= input.len() && expected == &input[..expected.len()]
-                }) {
-                    expecteds.remove(found_match);
-                }
+            fn create(&mut self, context: &Context, _init_code: &[u8]) {
+                self.creates.push(context.address);
             }
-
-            // handle mocked calls
-            if let Some(mocks) = self.state().mocked_calls.get(&code_address) {
-                if let Some(mock_retdata) = mocks.get(&input) {
-                    return Capture::Exit((
-                        ExitReason::Succeed(ExitSucceed::Returned),
-                        mock_retdata.clone(),
-                    ))
-                } else if let Some((_, mock_retdata)) =
-                    mocks.iter().find(|(mock, _)| *mock == &input[..mock.len()])
-                {
-                    return Capture::Exit((
-                        ExitReason::Succeed(ExitSucceed::Returned),
-                        mock_retdata.clone(),
-                    ))
-                }
+            fn step(&mut self, _: Address, _: usize, _: Opcode, _: &sputnik::Stack, _: &Memory) {
+                self.steps += 1;
             }
+        }
 
-            // perform the call
-            let res = self.call_inner(
-                code_address,
-                new_transfer,
-                input,
-                target_gas,
-                is_static,
-                true,
-                true,
-                new_context,
-            );
-
-            // if we set the origin, now we should reset to previous
-            self.state_mut().backend.cheats.origin = prev_origin;
-
-            // handle expected emits
-            if !self.state_mut().expected_emits.is_empty() &&
-                !self
-                    .state()
-                    .expected_emits
-                    .iter()
-                    .filter(|expected| expected.depth == curr_depth)
-                    .all(|expected| expected.found)
-            {
-                return evm_error("Log != expected log")
-            } else {
-                // empty out expected_emits after successfully capturing all of them
-                self.state_mut().expected_emits.retain(|expected| !expected.found);
-            }
+        let mut evm = vm();
+        let recorder = evm.add_hook(Recorder::default());
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+        let (addr, _, _, _) =
+            evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.call_committing::<(), _, _>(
+            from,
+            addr,
+            "greet(string)",
+            "hi".to_string(),
+            0.into(),
+            None,
+        )
+        .unwrap();
+
+        let recorder = recorder.borrow();
+        assert_eq!(recorder.creates, vec![addr]);
+        assert_eq!(recorder.calls, vec![(from, addr)]);
+        assert_eq!(recorder.ended, 1);
+        assert!(recorder.steps > 0);
+    }
 
-            self.expected_revert(ExpectRevertReturn::Call(res), expected_revert).into_call_inner()
+    #[test]
+    fn records_coverage() {
+        let mut evm = vm().with_coverage(true);
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let bytecode = compiled.bytecode().unwrap().clone();
+        let from = Address::repeat_byte(1);
+        let (addr, _, _, _) = evm.deploy(from, bytecode.clone(), 0.into()).unwrap();
+        for _ in 0..2 {
+            evm.call_committing::<(), _, _>(
+                from,
+                addr,
+                "greet(string)",
+                "hi".to_string(),
+                0.into(),
+                None,
+            )
+            .unwrap();
         }
+
+        let coverage = evm.coverage();
+        let init_code = H256::from(utils::keccak256(&bytecode));
+        assert_eq!(coverage.hits(CodeKind::Creation, init_code).unwrap()[&0], 1);
+        let code = H256::from(utils::keccak256(evm.executor.state().code(addr)));
+        let runtime = coverage.hits(CodeKind::Runtime, code).unwrap();
+        assert_eq!(runtime[&0], 2);
+        assert!(coverage.hits(CodeKind::Runtime, init_code).is_none());
+
+        // disabling keeps what was recorded so far
+        let mut evm = evm.with_coverage(false);
+        evm.call_committing::<(), _, _>(
+            from,
+            addr,
+            "greet(string)",
+            "hi".to_string(),
+            0.into(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(evm.coverage(), coverage);
     }
 
-    // Everything else is left the same
-    fn balance(&self, address: H160) -> U256 {
-        self.handler.balance(address)
+    #[test]
+    fn collects_pushed_and_compared_values() {
+        let mut evm = vm().with_value_collector(1024);
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+        let (addr, _, _, _) =
+            evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.call_committing::<(), _, _>(
+            from,
+            addr,
+            "greet(string)",
+            "hi".to_string(),
+            0.into(),
+            None,
+        )
+        .unwrap();
+
+        // the dispatcher pushes the selector and compares it to the one of the calldata
+        let values = evm.collected_values();
+        let selector = U256::from_big_endian(&utils::id("greet(string)"));
+        assert!(values.contains(&selector));
+        assert!(values.windows(2).all(|w| w[0] < w[1]));
+
+        let mut evm = vm().with_value_collector(2);
+        evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        assert_eq!(evm.collected_values().len(), 2);
+        assert!(vm().collected_values().is_empty());
     }
 
-    fn code_size(&self, address: H160) -> U256 {
-        self.handler.code_size(address)
+    #[test]
+    fn static_calls_cannot_modify_state() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+        let (addr, _, _, _) =
+            evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let nonce = evm.executor.state().basic(from).nonce;
+
+        let res = evm.static_call(from, addr, utils::id("time()").to_vec().into());
+        assert!(res.success, "{:?}", res.reverted_reason);
+
+        let res = evm.static_call(from, addr, utils::id("gm()").to_vec().into());
+        assert!(!res.success);
+        assert_eq!(evm.executor.state().storage(addr, H256::zero()), H256::zero());
+        assert_eq!(evm.executor.state().basic(from).nonce, nonce);
     }
 
-    fn code_hash(&self, address: H160) -> H256 {
-        self.handler.code_hash(address)
+    /// Deploys a contract which selfdestructs and is deployed again in the same transaction, and
+    /// then in the next one
+    fn selfdestruct_and_recreate<B: Backend>(evm: &mut TestSputnikVM<'_, B>, eip6780: bool) {
+        evm.set_eip6780(eip6780);
+        let compiled = COMPILED.find("DestructibleFactory").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+        let (factory, _, _, _) =
+            evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let salt = [1u8; 32];
+
+        // the account is only deleted at the end of the transaction, so it still collides
+        let (recreated, _, _, _) = evm
+            .call_committing::<bool, _, _>(
+                from,
+                factory,
+                "destroyAndRecreate(bytes32)",
+                salt,
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(!recreated);
+        let (addr, _, _, _) = evm
+            .call_committing::<Address, _, _>(
+                from,
+                factory,
+                "create(bytes32)",
+                salt,
+                0.into(),
+                None,
+            )
+            .unwrap();
+        assert!(!evm.executor.state().code(addr).is_empty());
+
+        // the contract was created in an earlier transaction
+        evm.deal(addr, 5.into());
+        evm.call_committing::<(), _, _>(from, addr, "destroy(address)", from, 0.into(), None)
+            .unwrap();
+        assert_eq!(evm.executor.state().code(addr).is_empty(), !eip6780);
+        assert_eq!(evm.executor.state().basic(addr).balance, 0.into());
     }
 
-    fn code(&self, address: H160) -> Vec<u8> {
-        self.handler.code(address)
+    #[test]
+    fn selfdestructs_depend_on_eip6780() {
+        selfdestruct_and_recreate(&mut vm(), false);
+        selfdestruct_and_recreate(&mut vm(), true);
     }
 
-    fn storage(&self, address: H160, index: H256) -> H256 {
-        self.handler.storage(address, index)
+    #[test]
+    fn selfdestructs_on_fork() {
+        let backend = fork_backend(None);
+        for eip6780 in [false, true] {
+            let mut evm = vm_with_backend(backend.clone());
+            selfdestruct_and_recreate(&mut evm, eip6780);
+        }
     }
 
-    fn original_storage(&self, address: H160, index: H256) -> H256 {
-        self.handler.original_storage(address, index)
+    #[test]
+    fn fails_calls_exceeding_the_request_budget() {
+        use crate::sputnik::{new_shared_cache, MemCache};
+        use ethers::providers::{Http, Provider};
+        use std::convert::TryFrom;
+
+        // nothing listens on this port, only the cached state is available
+        let provider = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+        let (from, addr) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let mut cache = MemCache::default();
+        cache.insert(from, MemoryAccount { balance: U256::MAX, ..Default::default() });
+        // PUSH1 1 SLOAD STOP, the slot is not cached
+        let code = vec![0x60, 0x01, 0x54, 0x00];
+        cache.insert(addr, MemoryAccount { code, ..Default::default() });
+        let backend =
+            SharedBackend::new(provider, new_shared_cache(cache), (*VICINITY).clone(), None)
+                .with_request_budget(0);
+
+        let mut evm = vm_with_backend(backend);
+        let res = evm.call_result(from, addr, Bytes::default(), 0.into());
+        assert!(!res.success);
+        let reason = res.reverted_reason.unwrap();
+        assert!(reason.contains("request budget of 0"), "{}", reason);
     }
 
-    fn gas_left(&self) -> U256 {
-        // Need to disambiguate type, because the same method exists in the `SputnikExecutor`
-        // trait and the `Handler` trait.
-        Handler::gas_left(&self.handler)
+    #[test]
+    fn storage_overrides_shadow_remote_slots() {
+        use crate::sputnik::{new_shared_cache, MemCache};
+        use ethers::providers::{Http, Provider};
+        use std::{collections::HashMap, convert::TryFrom};
+
+        // nothing listens on this port, the remote state is the cached one
+        let provider = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+        let address = Address::repeat_byte(1);
+        let mut cache = MemCache::default();
+        let mut storage = BTreeMap::new();
+        storage.insert(H256::from_low_u64_be(2), H256::from_low_u64_be(7));
+        storage.insert(H256::from_low_u64_be(3), H256::from_low_u64_be(8));
+        cache.insert(address, MemoryAccount { storage, ..Default::default() });
+        let backend =
+            SharedBackend::new(provider, new_shared_cache(cache), (*VICINITY).clone(), None);
+
+        let overrides: HashMap<U256, U256> =
+            vec![(1.into(), 5.into()), (2.into(), 6.into())].into_iter().collect();
+        let evm = vm_with_backend(backend.clone()).with_storage_overrides(address, overrides);
+        let slot = |slot: u64| evm.executor.state().storage(address, H256::from_low_u64_be(slot));
+        assert_eq!(slot(1), H256::from_low_u64_be(5));
+        assert_eq!(slot(2), H256::from_low_u64_be(6));
+        assert_eq!(slot(3), H256::from_low_u64_be(8));
+        assert_eq!(backend.cache_stats().fetches, 0);
     }
 
-    fn gas_price(&self) -> U256 {
-        self.handler.gas_price()
+    #[test]
+    fn rolls_block_hashes_back_and_forth() {
+        use crate::sputnik::cheatcodes::synthetic_block_hash;
+
+        let mut evm = vm();
+        let number = evm.executor.state().block_number();
+        let hash = |evm: &TestSputnikVM<'_, _>, blocks: u64| {
+            evm.executor.state().block_hash(number + blocks)
+        };
+        let synthetic = |blocks: u64| synthetic_block_hash(number + blocks);
+        assert_eq!(hash(&evm, 0), H256::zero());
+
+        // the block rolled to has a hash like with the `roll` cheatcode
+        evm.roll(1000);
+        assert_eq!(hash(&evm, 1000), synthetic(1000));
+        assert_eq!(hash(&evm, 500), H256::zero());
+
+        // rolling back restores the window before the target and forgets the blocks after it
+        evm.executor.state_mut().backend.roll_to(number + 500);
+        assert_eq!(hash(&evm, 400), synthetic(400));
+        assert_eq!(hash(&evm, 500), synthetic(500));
+        assert_eq!(hash(&evm, 501), H256::zero());
+        evm.roll(10);
+        assert_eq!(hash(&evm, 505), synthetic(505));
     }
 
-    fn origin(&self) -> H160 {
-        self.handler.origin()
+    #[test]
+    fn keeps_fork_block_hash_after_roll() {
+        use crate::sputnik::cheatcodes::synthetic_block_hash;
+        use ethers::providers::Middleware;
+
+        let backend = fork_backend(None);
+        let number = backend.block_number();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let block = |number: U256| {
+            rt.block_on(fork_provider().get_block(number.as_u64())).unwrap().unwrap()
+        };
+        let (forked, older) = (block(number), block(number - 2));
+        let mut evm = vm_with_backend(backend);
+        // the hashes of the blocks before the parent are fetched as well
+        assert_eq!(evm.executor.state().block_hash(number - 2), older.hash.unwrap());
+        assert_eq!(evm.executor.state().block_hash(number), H256::zero());
+
+        evm.roll(5);
+        let state = evm.executor.state();
+        assert_eq!(state.block_hash(number - 1), forked.parent_hash);
+        assert_eq!(state.block_hash(number), forked.hash.unwrap());
+        assert_eq!(state.block_hash(number + 1), synthetic_block_hash(number + 1));
+        assert_eq!(state.block_hash(number + 5), synthetic_block_hash(number + 5));
+        assert_eq!(state.block_hash(number + 6), H256::zero());
+
+        // the forked block is out of the window after 256 blocks
+        evm.roll(252);
+        assert_eq!(evm.executor.state().block_hash(number), H256::zero());
+        assert_ne!(evm.executor.state().block_hash(number + 1), H256::zero());
     }
 
-    fn block_hash(&self, number: U256) -> H256 {
-        self.handler.block_hash(number)
+    #[test]
+    fn surfaces_create_collisions() {
+        // deploys empty code with `CREATE2` and salt 0 twice, the second deployment collides
+        let code: Bytes = hex::decode("6000600060006000f5506000600060006000f55000").unwrap().into();
+        let addr = Address::repeat_byte(2);
+        let from = Address::repeat_byte(1);
+
+        let mut evm = vm();
+        evm.initialize_contracts(vec![(addr, code)]);
+        let res = evm.call_result(from, addr, Bytes::new(), 0.into());
+        // the call continues after the failed creation
+        assert!(res.success);
+        let target =
+            crate::compute_create2_address(addr, H256::zero(), utils::keccak256(b"").into());
+        assert_eq!(res.create_collision, Some(target));
+
+        let res = evm.call_result(from, Address::repeat_byte(3), Bytes::new(), 0.into());
+        assert_eq!(res.create_collision, None);
     }
 
-    fn block_number(&self) -> U256 {
-        self.handler.block_number()
+    #[test]
+    fn can_get_proof_of_fork() {
+        let mut evm = vm_with_backend(fork_backend(Some(13292465)));
+        let dai: Address = "0x6b175474e89094c44da98b954eedeac495271d0f".parse().unwrap();
+        let slots = [H256::zero(), H256::from_low_u64_be(1)];
+        let proof = evm.get_proof(dai, &slots).unwrap();
+        assert_eq!(proof.storage_proof.iter().map(|p| p.key).collect::<Vec<_>>(), slots);
+        assert!(!proof.account_proof.is_empty());
+        // answered from the cache, in the requested order
+        let cached = evm.get_proof(dai, &slots[1..]).unwrap();
+        assert_eq!(cached.storage_proof[0].key, slots[1]);
+
+        evm.executor.state_mut().set_storage(dai, slots[1], H256::repeat_byte(1));
+        let err = evm.get_proof(dai, &slots).unwrap_err();
+        assert!(err.to_string().contains("modified locally"), "{}", err);
+        assert!(evm.get_proof(dai, &slots[..1]).is_ok());
     }
 
-    fn block_coinbase(&self) -> H160 {
-        self.handler.block_coinbase()
+    #[test]
+    fn cant_get_proof_of_local_state() {
+        let evm = vm();
+        let err = evm.get_proof(Address::repeat_byte(1), &[H256::zero()]).unwrap_err();
+        assert!(err.to_string().contains("remote canonical state"), "{}", err);
     }
 
-    fn block_timestamp(&self) -> U256 {
-        self.handler.block_timestamp()
+    #[test]
+    fn can_replay_transaction_by_hash() {
+        use crate::sputnik::{new_shared_cache, vicinity, MemCache};
+        use ethers::providers::Middleware;
+        use std::sync::Arc;
+
+        let provider = fork_provider();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let number = 13292465;
+        let block = rt.block_on(provider.get_block_with_txs(number)).unwrap().unwrap();
+        let tx = block.transactions[0].clone();
+        let receipt = rt.block_on(provider.get_transaction_receipt(tx.hash)).unwrap().unwrap();
+
+        // the state of the parent block in the environment of the block, like `fork_tx_hash`
+        let vicinity = rt.block_on(vicinity(&provider, None, Some(number), None)).unwrap();
+        let backend = SharedBackend::new(
+            Arc::new(provider),
+            new_shared_cache(MemCache::default()),
+            vicinity,
+            Some((number - 1).into()),
+        );
+        let mut evm = vm_with_backend(backend);
+        let replayed = evm.replay_transaction(tx.hash).unwrap();
+        assert_eq!(replayed.result.success, receipt.status == Some(1.into()));
+        assert_eq!(replayed.trace.arena[0].trace.caller, tx.from);
+        // the sender is only the origin of the replay
+        assert_eq!(evm.executor.state().origin(), Address::zero());
+
+        let mut evm = vm_with_backend(fork_backend(Some(number - 1)));
+        let err = evm.replay_transaction(tx.hash).unwrap_err();
+        assert!(err.to_string().contains("fork with its `fork_tx_hash`"), "{}", err);
     }
 
-    fn block_difficulty(&self) -> U256 {
-        self.handler.block_difficulty()
+    #[test]
+    fn can_advance_fork_block() {
+        use ethers::providers::Middleware;
+
+        let number = 13292465;
+        let mut evm = vm_with_backend(fork_backend(Some(number)));
+        evm.roll(5);
+        let replayed = evm.advance_fork_block().unwrap();
+
+        let provider = fork_provider();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let block = rt.block_on(provider.get_block_with_txs(number + 1)).unwrap().unwrap();
+        assert_eq!(replayed.len(), block.transactions.len());
+        // the transactions have the outcome they had on chain, which takes their intrinsic gas
+        // into account
+        for (tx, replayed) in block.transactions.iter().zip(&replayed).take(5) {
+            let receipt = rt.block_on(provider.get_transaction_receipt(tx.hash)).unwrap().unwrap();
+            assert_eq!(replayed.result.success, receipt.status == Some(1.into()), "{:?}", tx.hash);
+        }
+
+        // the block set with `roll` is replaced by the executed one
+        let state = evm.executor.state();
+        assert_eq!(state.block_number(), (number + 1).into());
+        assert_eq!(state.block_timestamp(), block.timestamp);
+        // the senders of the transactions were only the origin while they were executed
+        assert_eq!(state.origin(), Address::zero());
+
+        // the next block builds on the executed one
+        assert!(!evm.advance_fork_block().unwrap().is_empty());
+        assert_eq!(evm.executor.state().block_number(), (number + 2).into());
     }
 
-    fn block_gas_limit(&self) -> U256 {
-        self.handler.block_gas_limit()
+    #[test]
+    fn detects_create_collisions_on_fork() {
+        use crate::EvmError;
+
+        let mut evm = vm_with_backend(fork_backend(None));
+        // the deterministic deployment proxy was deployed by the first transaction of this
+        // account, with its nonce reset the deployment goes to the address of the proxy
+        let deployer: Address = "0x3fab184622dc19b6109349b94811493bf2a45362".parse().unwrap();
+        let proxy: Address = "0x4e59b44847b379578588920ca78fbf26c0b4956c".parse().unwrap();
+        evm.executor.state_mut().set_nonce(deployer, U256::zero());
+        let err = evm.deploy_result(deployer, Bytes::from(vec![0x00]), 0.into()).unwrap_err();
+        match err.downcast_ref::<EvmError>() {
+            Some(EvmError::CreateCollision { address }) => assert_eq!(*address, proxy),
+            _ => panic!("unexpected error: {}", err),
+        }
     }
 
-    fn block_base_fee_per_gas(&self) -> U256 {
-        self.handler.block_base_fee_per_gas()
+    #[test]
+    fn memory_limit_stops_execution() {
+        // mstore(0x200000, 1), which expands the memory to 2MiB
+        let code: Bytes = hex::decode("6001622000005200").unwrap().into();
+        let addr = Address::repeat_byte(2);
+        let from = Address::repeat_byte(1);
+
+        let mut evm = vm();
+        evm.initialize_contracts(vec![(addr, code.clone())]);
+        let res = evm.call_result(from, addr, Bytes::new(), 0.into());
+        assert!(res.success && !res.memory_limit_exceeded);
+
+        let mut evm = vm().with_memory_limit(1 << 20);
+        evm.initialize_contracts(vec![(addr, code)]);
+        let res = evm.call_result(from, addr, Bytes::new(), 0.into());
+        assert!(!res.success && res.memory_limit_exceeded);
     }
 
-    fn chain_id(&self) -> U256 {
-        self.handler.chain_id()
+    #[test]
+    fn memory_limit_is_shared_by_the_open_frames() {
+        // mstore(0xa0000, 1), call(gas(), 3, 0, 0, 0, 0, 0) and returns whether the call succeeded
+        let caller: Bytes =
+            hex::decode("6001620a0000526000600060006000600060035af160005260206000f3")
+                .unwrap()
+                .into();
+        // mstore(0xa0000, 1)
+        let callee: Bytes = hex::decode("6001620a00005200").unwrap().into();
+        let (addr, from) = (Address::repeat_byte(2), Address::repeat_byte(1));
+
+        // each frame stays below the limit, but not both of them together
+        let mut evm = vm().with_memory_limit(1 << 20);
+        evm.initialize_contracts(vec![(addr, caller.clone()), (Address::repeat_byte(3), callee)]);
+        let res = evm.call_result(from, addr, Bytes::new(), 0.into());
+        // only the call which exceeded the limit failed
+        assert!(res.success && res.memory_limit_exceeded);
+        assert_eq!(U256::from_big_endian(&res.out), U256::zero());
+
+        let mut evm = evm.with_memory_limit(2 << 20);
+        let res = evm.call_result(from, addr, Bytes::new(), 0.into());
+        assert!(res.success && !res.memory_limit_exceeded);
+        assert_eq!(U256::from_big_endian(&res.out), U256::one());
     }
 
-    fn exists(&self, address: H160) -> bool {
-        self.handler.exists(address)
+    #[test]
+    fn can_setup_token_positions() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("DealToken").expect("could not find contract");
+        let (token, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let (holder, router) = (Address::repeat_byte(1), Address::repeat_byte(2));
+
+        evm.setup_token_position(token, holder, Some(router), 50.into()).unwrap();
+        let (balance, _, _, _) = evm
+            .call_committing::<U256, _, _>(
+                Address::zero(),
+                token,
+                "balanceOf(address)(uint256)",
+                holder,
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert_eq!(balance, 50.into());
+        let (allowance, _, _, _) = evm
+            .call_committing::<U256, _, _>(
+                Address::zero(),
+                token,
+                "allowance(address,address)(uint256)",
+                (holder, router),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert_eq!(allowance, 50.into());
+        let (total, _, _, _) = evm
+            .call_committing::<U256, _, _>(
+                Address::zero(),
+                token,
+                "totalSupply()(uint256)",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert_eq!(total, 150.into());
+
+        // plain funding without an approval
+        let other = Address::repeat_byte(3);
+        evm.setup_token_position(token, other, None, 7.into()).unwrap();
+
+        // an account without code has no balance slot
+        let err = evm.setup_token_position(Address::repeat_byte(9), holder, None, 1.into());
+        assert!(err.unwrap_err().to_string().contains("could not find the balance slot"));
     }
 
-    fn deleted(&self, address: H160) -> bool {
-        self.handler.deleted(address)
+    #[test]
+    fn can_extend_cheatcodes() {
+        #[derive(Debug)]
+        struct Magic;
+
+        impl CheatcodeExtension for Magic {
+            fn handle(
+                &mut self,
+                calldata: &[u8],
+                ctx: &mut CheatContext<'_>,
+            ) -> Option<CheatResult> {
+                let selector = &calldata[..4];
+                if selector == utils::id("setMagic(address)") {
+                    let target = Address::from_slice(&calldata[16..36]);
+                    ctx.set_storage(target, H256::zero(), H256::from_low_u64_be(42));
+                    ctx.log("magic");
+                    Some(Ok(ethers::abi::encode(&[Token::Address(ctx.caller())])))
+                } else if selector == utils::id("failMagic()") {
+                    Some(Err("no magic".to_string()))
+                } else if selector == utils::id("warp(uint256)") {
+                    Some(Err("built-ins win".to_string()))
+                } else {
+                    None
+                }
+            }
+        }
+
+        let mut evm = vm().with_cheatcode_extension(Box::new(Magic));
+        let compiled = COMPILED.find("CustomCheats").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let cheat = |evm: &mut TestSputnikVM<MemoryBackend>, signature: &str, args: &[Token]| {
+            let mut data = utils::id(signature).to_vec();
+            data.extend(ethers::abi::encode(args));
+            let ((success, out), _, _, logs) = evm
+                .call_committing::<(bool, Bytes), _, _>(
+                    Address::zero(),
+                    addr,
+                    "cheat(bytes)(bool,bytes)",
+                    Bytes::from(data),
+                    0.into(),
+                    compiled.abi,
+                )
+                .unwrap();
+            (success, out.to_vec(), logs)
+        };
+
+        let target = Address::repeat_byte(1);
+        let (success, out, logs) = cheat(&mut evm, "setMagic(address)", &[Token::Address(target)]);
+        assert!(success);
+        assert_eq!(out, ethers::abi::encode(&[Token::Address(addr)]));
+        assert_eq!(logs, vec!["magic".to_string()]);
+        assert_eq!(evm.state().storage(target, H256::zero()), H256::from_low_u64_be(42));
+
+        let (success, out, _) = cheat(&mut evm, "failMagic()", &[]);
+        assert!(!success);
+        assert_eq!(out[4..], ethers::abi::encode(&[Token::String("no magic".to_string())]));
+
+        // the built-in cheatcode is applied instead of the extension
+        let (success, _, _) = cheat(&mut evm, "warp(uint256)", &[Token::Uint(100.into())]);
+        assert!(success);
+        assert_eq!(evm.state().block_timestamp(), 100.into());
+
+        // unknown to both
+        let (success, _, _) = cheat(&mut evm, "unknown()", &[]);
+        assert!(!success);
     }
 
-    fn is_cold(&self, address: H160, index: Option<H256>) -> bool {
-        self.handler.is_cold(address, index)
+    #[test]
+    fn can_clear_cheatcode_state() {
+        let mut evm = vm();
+        let target = Address::repeat_byte(1);
+        assert!(evm.cheatcode_state().is_empty());
+
+        // what a test which reverted early would leave behind
+        let state = evm.executor.state_mut();
+        state.expected_revert = Some(b"reason".to_vec());
+        state.mocked_calls.entry(target).or_default().insert(vec![1, 2, 3, 4], vec![42]);
+        state.expected_calls.entry(target).or_default().push(Default::default());
+        state.prank = Some(Prank { new_caller: target, ..Default::default() });
+        state.accesses = Some(Default::default());
+        evm.warp(100);
+        evm.deal(target, 7.into());
+
+        let registered = evm.cheatcode_state();
+        assert_eq!(registered.expected_revert.as_deref(), Some(&b"reason"[..]));
+        assert_eq!(registered.mocked_calls[&target][&vec![1, 2, 3, 4]], vec![42]);
+        assert_eq!(registered.expected_calls[&target].len(), 1);
+        assert_eq!(registered.prank.unwrap().new_caller, target);
+
+        let cleared = evm.clear_cheatcode_state();
+        assert!(!cleared.is_empty());
+        assert!(cleared.accesses.is_some());
+        assert!(evm.cheatcode_state().is_empty());
+        assert!(evm.expected_revert().is_none());
+
+        // the state and the block overrides are kept
+        assert_eq!(evm.executor.state().block_timestamp(), 100.into());
+        assert_eq!(evm.executor.state().basic(target).balance, 7.into());
     }
 
-    fn set_storage(&mut self, address: H160, index: H256, value: H256) -> Result<(), ExitError> {
-        self.handler.set_storage(address, index, value)
+    #[test]
+    fn call_depth_limit_fails_deep_calls() {
+        // calls itself with the calldata word decremented until it is zero, and reverts if the
+        // nested call failed
+        let code: Bytes = hex::decode(
+            "60003580156023576001900360005260006000602060006000305af1602357600080fd5b00",
+        )
+        .unwrap()
+        .into();
+        let addr = Address::repeat_byte(2);
+        let from = Address::repeat_byte(1);
+        let calldata = |depth: u64| Bytes::from(ethers::abi::encode(&[Token::Uint(depth.into())]));
+
+        let mut evm = vm().with_call_depth_limit(8);
+        evm.initialize_contracts(vec![(addr, code)]);
+        let res = evm.call_result(from, addr, calldata(8), 0.into());
+        assert!(res.success && !res.call_depth_exceeded);
+
+        let res = evm.call_result(from, addr, calldata(9), 0.into());
+        assert!(!res.success && res.call_depth_exceeded);
     }
 
-    fn log(&mut self, address: H160, topics: Vec<H256>, data: Vec<u8>) -> Result<(), ExitError> {
-        if self.state().trace_enabled {
-            let index = self.state().trace_index;
-            let node = &mut self.state_mut().traces.last_mut().expect("no traces").arena[index];
-            node.ordering.push(LogCallOrder::Log(node.logs.len()));
-            node.logs.push(RawLog { topics: topics.clone(), data: data.clone() });
-        }
+    #[test]
+    fn fork_sessions_are_isolated() {
+        let mut evm = vm();
+        let alice = Address::repeat_byte(1);
+        evm.deal(alice, 10.into());
+        evm.label_address(alice, "alice".to_string()).unwrap();
+
+        let session = std::sync::Arc::new(evm.fork_session());
+        let handles = (0..4u64)
+            .map(|i| {
+                let session = std::sync::Arc::clone(&session);
+                std::thread::spawn(move || {
+                    let mut evm = session.executor(&*CFG, &*PRECOMPILES_MAP);
+                    assert_eq!(evm.state().basic(alice).balance, 10.into());
+                    assert_eq!(evm.labels()[&alice], "alice");
+                    evm.deal(alice, i.into());
+                    evm.state().basic(alice).balance
+                })
+            })
+            .collect::<Vec<_>>();
+        let balances = handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>();
+        assert_eq!(balances, vec![0.into(), 1.into(), 2.into(), 3.into()]);
+        assert_eq!(evm.state().basic(alice).balance, 10.into());
+    }
 
-        if let Some(decoded) =
-            convert_log(Log { address, topics: topics.clone(), data: data.clone() })
-        {
-            self.state_mut().all_logs.push(decoded);
-        }
+    #[test]
+    fn can_roll_and_warp() {
+        let mut evm = vm();
+        evm.set_block_time(10);
+        evm.roll(3);
+        let state = evm.executor.state();
+        assert_eq!(state.block_number(), 3.into());
+        assert_eq!(state.block_timestamp(), 30.into());
+        let hash = state.block_hash(1.into());
+        assert_ne!(hash, H256::zero());
+        assert_eq!(state.block_hash(3.into()), H256::zero());
+
+        evm.roll(1);
+        // the hashes of already rolled blocks don't change
+        assert_eq!(evm.executor.state().block_hash(1.into()), hash);
+        assert_ne!(evm.executor.state().block_hash(3.into()), H256::zero());
+
+        evm.warp(5);
+        assert_eq!(evm.executor.state().block_timestamp(), 5.into());
+        assert_eq!(evm.executor.state().block_number(), 4.into());
+    }
 
-        if !self.state().expected_emits.is_empty() {
-            // get expected emits
-            let expected_emits = &mut self.state_mut().expected_emits;
+    #[test]
+    fn can_restrict_cheatcodes() {
+        // warps to 1234 and returns the timestamp
+        let code = hex::decode(format!(
+            "63e5d6bf0260e01b6000526104d26004526000600060246000600073{}5af1504260005260206000f3",
+            hex::encode(CHEATCODE_ADDRESS.as_bytes())
+        ))
+        .unwrap();
+        let addr = Address::repeat_byte(1);
+        let timestamp = |evm: &mut TestSputnikVM<'_, MemoryBackend<'_>>| {
+            let res = evm.call_result(Address::zero(), addr, Bytes::default(), 0.into());
+            assert!(res.success);
+            U256::from_big_endian(&res.out)
+        };
 
-            // do we have empty expected emits to fill?
-            if let Some(next_expect_to_fill) =
-                expected_emits.iter_mut().find(|expect| expect.log.is_none())
-            {
-                next_expect_to_fill.log =
-                    Some(RawLog { topics: topics.clone(), data: data.clone() });
-            } else {
-                // no unfilled, grab next unfound
-                // try to fill the first unfound
-                if let Some(next_expect) = expected_emits.iter_mut().find(|expect| !expect.found) {
-                    // unpack the log
-                    if let Some(RawLog { topics: expected_topics, data: expected_data }) =
-                        &next_expect.log
-                    {
-                        if expected_topics[0] == topics[0] {
-                            // same event topic 0, topic length should be the same
-                            let topics_match = topics
-                                .iter()
-                                .skip(1)
-                                .enumerate()
-                                .filter(|(i, _topic)| {
-                                    // do we want to check?
-                                    next_expect.checks[*i]
-                                })
-                                .all(|(i, topic)| topic == &expected_topics[i + 1]);
+        // calls from other contracts are calls to an empty account
+        let mut evm = vm().with_cheatcodes_for([Address::repeat_byte(2)]);
+        evm.initialize_contracts([(addr, code.clone().into())]);
+        assert_eq!(timestamp(&mut evm), VICINITY.block_timestamp);
 
-                            // check data
-                            next_expect.found = if next_expect.checks[3] {
-                                expected_data == &data && topics_match
-                            } else {
-                                topics_match
-                            };
-                        }
-                    }
-                }
-            }
-        }
+        evm.set_cheatcode_access(CheatcodeAccess::TopLevel);
+        assert_eq!(timestamp(&mut evm), 1234.into());
 
-        self.handler.log(address, topics, data)
+        let mut evm = vm().with_cheatcodes_for([addr]);
+        evm.initialize_contracts([(addr, code.into())]);
+        assert_eq!(timestamp(&mut evm), 1234.into());
     }
 
-    fn mark_delete(&mut self, address: H160, target: H160) -> Result<(), ExitError> {
-        self.handler.mark_delete(address, target)
+    #[test]
+    fn can_change_env_between_calls() {
+        let mut evm = vm();
+        let (coinbase, bob) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        // stores COINBASE, GASLIMIT and NUMBER in the slots 0, 1 and 2
+        let code = hex::decode("41600055456001554360025500").unwrap();
+        evm.initialize_contracts(vec![(bob, code.into())]);
+        assert_eq!(evm.env().block_coinbase, None);
+
+        let env = evm.env_mut();
+        env.block_coinbase = Some(coinbase);
+        env.block_gas_limit = Some(GAS_LIMIT.saturating_mul(2).into());
+        env.block_number = Some(42.into());
+        env.gas_price = Some(2.into());
+        let alice = Address::repeat_byte(3);
+        evm.deal(alice, U256::exp10(18));
+        let res = evm.call_result(alice, bob, Bytes::default(), 0.into());
+        assert!(res.success);
+        // the sender paid for the gas with the overridden gas price
+        let gas = U256::from(res.gas_used + 21000);
+        assert_eq!(evm.state().basic(alice).balance, U256::exp10(18) - gas * 2);
+
+        let slot = |evm: &TestSputnikVM<MemoryBackend>, slot: u64| {
+            evm.state().storage(bob, H256::from_low_u64_be(slot))
+        };
+        assert_eq!(slot(&evm, 0), H256::from(coinbase));
+        assert_eq!(slot(&evm, 1), H256::from_low_u64_be(GAS_LIMIT * 2));
+        assert_eq!(slot(&evm, 2), H256::from_low_u64_be(42));
+        assert_eq!(evm.env().block_number, Some(42.into()));
     }
 
-    fn create(
-        &mut self,
-        caller: H160,
-        scheme: CreateScheme,
-        value: U256,
-        init_code: Vec<u8>,
-        target_gas: Option<u64>,
-    ) -> Capture<(ExitReason, Option<H160>, Vec<u8>), Self::CreateInterrupt> {
-        // modify execution context depending on the cheatcode
-
-        let prev_origin = self.state().backend.cheats.origin;
-        let expected_revert = self.state_mut().expected_revert.take();
-        let mut new_tx_caller = caller;
-        let mut new_scheme = scheme;
-        let curr_depth =
-            if let Some(depth) = self.state().metadata().depth() { depth + 1 } else { 0 };
-
-        // handle `startPrank` - see apply_cheatcodes for more info
-        if let Some(Prank { prank_caller, new_caller, new_origin, depth }) = self.state().prank {
-            if curr_depth == depth && new_tx_caller == prank_caller {
-                new_tx_caller = new_caller;
-
-                self.state_mut().backend.cheats.origin = new_origin
+    #[test]
+    fn can_set_chain_id() {
+        let mut evm = vm();
+        // CHAINID PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+        let code = hex::decode("4660005260206000f3").unwrap();
+        let addr = Address::repeat_byte(1);
+        evm.initialize_contracts([(addr, code.into())]);
+        for chain_id in [None, Some(10), Some(137)] {
+            if let Some(chain_id) = chain_id {
+                evm.set_chain_id(chain_id);
             }
+            let res = evm.call_result(Address::zero(), addr, Bytes::default(), 0.into());
+            let expected = chain_id.map_or(VICINITY.chain_id, U256::from);
+            assert_eq!(U256::from_big_endian(&res.out), expected);
         }
+    }
 
-        // handle normal `prank`
-        if let Some(Prank { new_caller, new_origin, .. }) = self.state_mut().next_prank.take() {
-            new_tx_caller = new_caller;
+    #[test]
+    fn can_read_blobs() {
+        // PUSH1 0 CALLDATALOAD BLOBHASH PUSH1 0 MSTORE BLOBBASEFEE PUSH1 32 MSTORE PUSH1 64
+        // PUSH1 0 RETURN
+        let code = hex::decode("600035496000524a60205260406000f3").unwrap();
+        let addr = Address::repeat_byte(1);
+        let call = |evm: &mut TestSputnikVM<'_, MemoryBackend<'_>>, index: u64| {
+            let calldata = H256::from_low_u64_be(index).as_bytes().to_vec();
+            evm.call_result(Address::zero(), addr, calldata.into(), 0.into())
+        };
 
-            self.state_mut().backend.cheats.origin = new_origin
+        // the blob opcodes are invalid without any blobs
+        let mut evm = vm();
+        evm.initialize_contracts([(addr, code.clone().into())]);
+        assert!(!call(&mut evm, 0).success);
+
+        let hashes = vec![H256::repeat_byte(1), H256::repeat_byte(2)];
+        let mut evm = vm().with_blob_hashes(hashes.clone()).with_blob_base_fee(7.into());
+        evm.initialize_contracts([(addr, code.clone().into())]);
+        for (index, hash) in [(0, hashes[0]), (1, hashes[1]), (2, H256::zero())] {
+            let res = call(&mut evm, index);
+            assert!(res.success);
+            assert_eq!(H256::from_slice(&res.out[..32]), hash);
+            assert_eq!(U256::from_big_endian(&res.out[32..]), 7.into());
         }
 
-        if caller != new_tx_caller {
-            new_scheme = match scheme {
-                CreateScheme::Legacy { .. } => CreateScheme::Legacy { caller: new_tx_caller },
-                CreateScheme::Create2 { code_hash, salt, .. } => {
-                    CreateScheme::Create2 { caller: new_tx_caller, code_hash, salt }
-                }
-                _ => scheme,
-            };
-        }
+        // the blob base fee defaults to the minimum
+        let mut evm = vm().with_blob_hashes(hashes);
+        evm.initialize_contracts([(addr, code.into())]);
+        assert_eq!(U256::from_big_endian(&call(&mut evm, 0).out[32..]), MIN_BLOB_BASE_FEE.into());
+    }
 
-        let res = self.create_inner(new_tx_caller, new_scheme, value, init_code, target_gas, true);
+    #[test]
+    fn rejects_too_many_blobs() {
+        let hashes = vec![H256::repeat_byte(1); MAX_BLOBS_PER_TX + 1];
+        let mut evm = vm().with_blob_hashes(hashes);
+        let addr = Address::repeat_byte(1);
+        let (_, status, _, _) =
+            evm.call_raw(Address::zero(), addr, Bytes::default(), 0.into(), false).unwrap();
+        assert!(format!("{:?}", status).contains("at most 6 are allowed"), "{:?}", status);
+
+        // PUSH1 0 PUSH1 0 RETURN
+        let err = evm.deploy(Address::zero(), hex::decode("60006000f3").unwrap().into(), 0.into());
+        assert!(err.unwrap_err().to_string().contains("at most 6 are allowed"));
+
+        evm.env_mut().blob_hashes.truncate(MAX_BLOBS_PER_TX);
+        assert!(evm.call_result(Address::zero(), addr, Bytes::default(), 0.into()).success);
+    }
 
-        // if we set the origin, now we should reset to prior origin
-        self.state_mut().backend.cheats.origin = prev_origin;
+    #[test]
+    fn can_override_precompiles() {
+        let mut evm = vm();
+        // staticcalls `ecrecover` with the calldata and returns the first word of its output,
+        // reverts if the precompile failed
+        let code =
+            hex::decode("3660006020376020600036602060015afa601857600080fd5b60206000f3").unwrap();
+        let addr = Address::repeat_byte(1);
+        evm.initialize_contracts([(addr, code.into())]);
+        let ecrecover = Address::from_low_u64_be(1);
+
+        evm.override_precompile(ecrecover, |input, _, _, is_static| {
+            assert!(is_static);
+            Ok(PrecompileOutput {
+                exit_status: ExitSucceed::Returned,
+                output: utils::keccak256(input).to_vec(),
+                cost: 100,
+                logs: vec![],
+            })
+        });
+        let res = evm.call_result(Address::zero(), addr, b"hello".to_vec().into(), 0.into());
+        assert!(res.success);
+        assert_eq!(res.out.as_ref(), utils::keccak256(b"hello"));
+
+        evm.override_precompile(ecrecover, |_, _, _, _| {
+            Err(PrecompileFailure::Error { exit_status: ExitError::OutOfGas })
+        });
+        let res = evm.call_result(Address::zero(), addr, b"hello".to_vec().into(), 0.into());
+        assert!(!res.success);
+
+        // the real precompile returns nothing for invalid signatures
+        evm.clear_precompile_overrides();
+        let res = evm.call_result(Address::zero(), addr, b"hello".to_vec().into(), 0.into());
+        assert!(res.success);
+        assert_eq!(res.out.as_ref(), [0u8; 32]);
+    }
 
-        if !self.state_mut().expected_emits.is_empty() &&
-            !self
-                .state()
-                .expected_emits
-                .iter()
-                .filter(|expected| expected.depth == curr_depth)
-                .all(|expected| expected.found)
-        {
-            return revert_return_evm(false, None, || "Log != expected log").into_create_inner()
-        } else {
-            // empty out expected_emits after successfully capturing all of them
-            self.state_mut().expected_emits = Vec::new();
-        }
+    #[test]
+    fn ds_test_logs() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("DebugLogs").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
 
-        self.expected_revert(ExpectRevertReturn::Create(res), expected_revert).into_create_inner()
+        // after the evm call is done, we call `logs` and print it all to the user
+        let (_, _, _, logs) = evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "test_log()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        let expected = [
+            "Hi",
+            "0x1234",
+            "0x1111111111111111111111111111111111111111",
+            "0x41b1a0649752af1b28b3dc29a1556eee781e4a4c3a1f7f53f90fa834de098c4d",
+            "123",
+            "1234",
+            "0x4567",
+            "lol",
+            "addr: 0x2222222222222222222222222222222222222222",
+            "key: 0x41b1a0649752af1b28b3dc29a1556eee781e4a4c3a1f7f53f90fa834de098c4d",
+            "key: 0.000000000000000123",
+            "key: -0.000000000000000123",
+            "key: 1.000000000000000000",
+            "key: -1.000000000000000000",
+            "key: -0.000000000123",
+            "key: -1000000.000000000000",
+            "key: 0.000000000000001234",
+            "key: 1.000000000000000000",
+            "key: 0.000000001234",
+            "key: 1000000.000000000000",
+            "key: 123",
+            "key: 1234",
+            "key: 0x4567",
+            "key: lol",
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+        assert_eq!(logs, expected);
     }
 
-    fn pre_validate(
-        &mut self,
-        context: &Context,
-        opcode: sputnik::Opcode,
-        stack: &sputnik::Stack,
-    ) -> Result<(), ExitError> {
-        self.handler.pre_validate(context, opcode, stack)
+    #[test]
+    fn console_logs() {
+        let mut evm = vm();
+
+        let compiled = COMPILED.find("ConsoleLogs").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+
+        // after the evm call is done, we call `logs` and print it all to the user
+        let (_, _, _, logs) = evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "test_log()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        let expected = [
+            "0x1111111111111111111111111111111111111111",
+            "Hi",
+            "Hi, Hi",
+            "1337",
+            "1337, 1245",
+            "Hi, 1337",
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+        assert_eq!(logs, expected);
+
+        let (_, _, _, logs) = evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "test_log()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert_eq!(logs, expected);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{
-        call_tracing::ExecutionInfo,
-        fuzz::FuzzedExecutor,
-        sputnik::helpers::{vm, vm_no_limit, vm_tracing},
-        test_helpers::COMPILED,
-        Evm,
-    };
+    #[test]
+    fn console_logs_format() {
+        let mut evm = vm();
+
+        let compiled = COMPILED.find("ConsoleLogs").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+
+        let (_, _, _, logs) = evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "test_log_format()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        let expected =
+            ["Hi 1337", "0x1111111111111111111111111111111111111111 has 5 tokens", "100% true"]
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+        assert_eq!(logs, expected);
 
-    use super::*;
+        // the output of all calls is collected until the logs are cleared
+        evm.call_committing::<(), _, _>(
+            Address::zero(),
+            addr,
+            "test_log()",
+            (),
+            0.into(),
+            compiled.abi,
+        )
+        .unwrap();
+        assert_eq!(evm.console_logs().len(), expected.len() + 6);
+        assert_eq!(evm.console_logs()[..expected.len()], expected[..]);
+        evm.clear_logs();
+        assert!(evm.console_logs().is_empty());
+    }
 
     #[test]
-    fn ds_test_logs() {
+    fn console_logs_types() {
         let mut evm = vm();
-        let compiled = COMPILED.find("DebugLogs").expect("could not find contract");
+
+        let compiled = COMPILED.find("ConsoleLogs").expect("could not find contract");
         let (addr, _, _, _) =
             evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
 
         // after the evm call is done, we call `logs` and print it all to the user
         let (_, _, _, logs) = evm
-            .call::<(), _, _>(Address::zero(), addr, "test_log()", (), 0.into(), compiled.abi)
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "test_log_types()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
             .unwrap();
-        let expected = [
-            "Hi",
-            "0x1234",
-            "0x1111111111111111111111111111111111111111",
-            "0x41b1a0649752af1b28b3dc29a1556eee781e4a4c3a1f7f53f90fa834de098c4d",
-            "123",
-            "1234",
-            "0x4567",
-            "lol",
-            "addr: 0x2222222222222222222222222222222222222222",
-            "key: 0x41b1a0649752af1b28b3dc29a1556eee781e4a4c3a1f7f53f90fa834de098c4d",
-            "key: 0.000000000000000123",
-            "key: -0.000000000000000123",
-            "key: 1.000000000000000000",
-            "key: -1.000000000000000000",
-            "key: -0.000000000123",
-            "key: -1000000.000000000000",
-            "key: 0.000000000000001234",
-            "key: 1.000000000000000000",
-            "key: 0.000000001234",
-            "key: 1000000.000000000000",
-            "key: 123",
-            "key: 1234",
-            "key: 0x4567",
-            "key: lol",
-        ]
-        .iter()
-        .map(ToString::to_string)
-        .collect::<Vec<_>>();
+        let expected =
+            ["String", "1337", "-20", "1245", "true", "0x1111111111111111111111111111111111111111"]
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
         assert_eq!(logs, expected);
     }
 
     #[test]
-    fn console_logs() {
+    fn console_logs_types_bytes() {
         let mut evm = vm();
 
         let compiled = COMPILED.find("ConsoleLogs").expect("could not find contract");
@@ -1899,208 +4958,555 @@ mod tests {
 
         // after the evm call is done, we call `logs` and print it all to the user
         let (_, _, _, logs) = evm
-            .call::<(), _, _>(Address::zero(), addr, "test_log()", (), 0.into(), compiled.abi)
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "test_log_types_bytes()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
             .unwrap();
         let expected = [
-            "0x1111111111111111111111111111111111111111",
-            "Hi",
-            "Hi, Hi",
-            "1337",
-            "1337, 1245",
-            "Hi, 1337",
+            r#"Bytes(b"logBytes")"#,
+            r#"Bytes(b"\xfb\xa3\xa4\xb5")"#,
+            "0xfb",
+            "0xfba3",
+            "0xfba3a4",
+            "0xfba3a4b5",
+            "0xfba3a4b500",
+            "0xfba3a4b50000",
+            "0xfba3a4b5000000",
+            "0xfba3a4b500000000",
+            "0xfba3a4b50000000000",
+            "0xfba3a4b5000000000000",
+            "0xfba3a4b500000000000000",
+            "0xfba3a4b50000000000000000",
+            "0xfba3a4b5000000000000000000",
+            "0xfba3a4b500000000000000000000",
+            "0xfba3a4b50000000000000000000000",
+            "0xfba3a4b5000000000000000000000000",
+            "0xfba3a4b500000000000000000000000000",
+            "0xfba3a4b50000000000000000000000000000",
+            "0xfba3a4b5000000000000000000000000000000",
+            "0xfba3a4b500000000000000000000000000000000",
+            "0xfba3a4b50000000000000000000000000000000000",
+            "0xfba3a4b5000000000000000000000000000000000000",
+            "0xfba3a4b500000000000000000000000000000000000000",
+            "0xfba3a4b50000000000000000000000000000000000000000",
+            "0xfba3a4b5000000000000000000000000000000000000000000",
+            "0xfba3a4b500000000000000000000000000000000000000000000",
+            "0xfba3a4b50000000000000000000000000000000000000000000000",
+            "0xfba3a4b5000000000000000000000000000000000000000000000000",
+            "0xfba3a4b500000000000000000000000000000000000000000000000000",
+            "0xfba3a4b50000000000000000000000000000000000000000000000000000",
+            "0xfba3a4b5000000000000000000000000000000000000000000000000000000",
+            "0xfba3a4b500000000000000000000000000000000000000000000000000000000",
         ]
         .iter()
         .map(ToString::to_string)
         .collect::<Vec<_>>();
         assert_eq!(logs, expected);
+    }
 
-        let (_, _, _, logs) = evm
-            .call::<(), _, _>(Address::zero(), addr, "test_log()", (), 0.into(), compiled.abi)
+    #[test]
+    fn records_failed_assertions() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+
+        // failed assertions don't revert, so all of them are recorded
+        let (_, reason, _, _) = evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "failAssertions()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
             .unwrap();
-        assert_eq!(logs, expected);
+        let messages = evm
+            .assertion_failures()
+            .into_iter()
+            .map(|failure| {
+                assert_eq!(failure.address, addr);
+                failure.message
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "assertEq failed: -1 != 1".to_string(),
+                "assertEq failed: 0x0000000000000000000000000000000000000001 != \
+                 0x0000000000000000000000000000000000000002"
+                    .to_string(),
+                "assertGt failed: 1 <= 2".to_string(),
+            ]
+        );
+        assert!(!evm.check_success(addr, &reason, false));
     }
 
     #[test]
-    fn console_logs_types() {
+    fn logs_external_contract() {
         let mut evm = vm();
 
-        let compiled = COMPILED.find("ConsoleLogs").expect("could not find contract");
+        let compiled = COMPILED.find("DebugLogs").expect("could not find contract");
         let (addr, _, _, _) =
             evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
 
         // after the evm call is done, we call `logs` and print it all to the user
         let (_, _, _, logs) = evm
-            .call::<(), _, _>(Address::zero(), addr, "test_log_types()", (), 0.into(), compiled.abi)
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "test_log_elsewhere()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
             .unwrap();
-        let expected =
-            ["String", "1337", "-20", "1245", "true", "0x1111111111111111111111111111111111111111"]
-                .iter()
-                .map(ToString::to_string)
-                .collect::<Vec<_>>();
+        let expected = ["0x1111111111111111111111111111111111111111", "Hi"]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
         assert_eq!(logs, expected);
     }
 
     #[test]
-    fn console_logs_types_bytes() {
+    fn cheatcodes() {
+        let mut evm = vm_no_limit();
+        let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+
+        let state = evm.state().clone();
+        let mut cfg = proptest::test_runner::Config::default();
+        cfg.failure_persistence = None;
+        let runner = proptest::test_runner::TestRunner::new(cfg);
+
+        // ensure the storage slot is set at 10 anyway
+        let (storage_contract, _, _, _) = evm
+            .call_committing::<Address, _, _>(
+                Address::zero(),
+                addr,
+                "store()(address)",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        let (slot, _, _, _) = evm
+            .call_committing::<U256, _, _>(
+                Address::zero(),
+                storage_contract,
+                "slot0()(uint256)",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert_eq!(slot, 10.into());
+
+        let evm = FuzzedExecutor::new(&mut evm, runner, Address::zero());
+
+        let abi = compiled.abi.as_ref().unwrap();
+        for func in abi.functions().filter(|func| func.name.starts_with("test")) {
+            // Skip the FFI unit tests if not in a unix system
+            if func.name.contains("FFI") && !cfg!(unix) {
+                continue
+            }
+
+            let should_fail = func.name.starts_with("testFail");
+            if func.inputs.is_empty() {
+                let (_, reason, _, _) =
+                    evm.as_mut().call_unchecked(Address::zero(), addr, func, (), 0.into()).unwrap();
+                assert!(evm.as_mut().check_success(addr, &reason, should_fail));
+            } else {
+                assert!(evm.fuzz(func, addr, should_fail, Some(abi)).is_ok());
+            }
+
+            evm.as_mut().reset(state.clone());
+        }
+    }
+
+    #[test]
+    fn deals_rebasing_tokens_with_the_scale_strategy() {
+        let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
+        let func = compiled.abi.unwrap().function("dealRebasingToken").unwrap();
+        for (strategy, dealt) in [(DealStrategy::Exact, false), (DealStrategy::Scale, true)] {
+            let mut evm = vm_no_limit();
+            evm.set_deal_strategy(strategy);
+            let (addr, _, _, _) = evm
+                .deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into())
+                .unwrap();
+            let (_, reason, _, _) =
+                evm.call_unchecked(Address::zero(), addr, func, (), 0.into()).unwrap();
+            assert_eq!(evm.check_success(addr, &reason, false), dealt, "{:?}", strategy);
+        }
+    }
+
+    /// A cheatcode vm over a backend with the given accounts, which stand in for the remote state
+    /// of a fork
+    fn vm_with_accounts<'a>(
+        accounts: Vec<(Address, MemoryAccount)>,
+    ) -> TestSputnikVM<'a, MemoryBackend<'a>> {
+        let backend = new_backend(&*VICINITY, accounts.into_iter().collect());
+        Executor::new_with_cheatcodes(
+            backend,
+            GAS_LIMIT,
+            &*CFG_NO_LMT,
+            &*PRECOMPILES_MAP,
+            true,
+            false,
+            false,
+        )
+    }
+
+    #[test]
+    fn store_shadows_backend_storage() {
+        use crate::sputnik::{
+            helpers::{new_backend, CFG_NO_LMT, GAS_LIMIT, VICINITY},
+            PRECOMPILES_MAP,
+        };
+        use sputnik::backend::MemoryAccount;
+
+        // the backend stands in for the remote state of a fork
+        let remote = Address::repeat_byte(1);
+        let mut state = BTreeMap::new();
+        let storage = vec![(H256::zero(), H256::from_low_u64_be(7))].into_iter().collect();
+        state.insert(remote, MemoryAccount { storage, ..Default::default() });
+        let backend = new_backend(&*VICINITY, state);
+        let mut evm = Executor::new_with_cheatcodes(
+            backend,
+            GAS_LIMIT,
+            &*CFG_NO_LMT,
+            &*PRECOMPILES_MAP,
+            true,
+            false,
+            false,
+        );
+
+        let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let ((loaded, stored), _, _, _) = evm
+            .call_committing::<(H256, H256), _, _>(
+                Address::zero(),
+                addr,
+                "storeAndLoad(address,bytes32,bytes32)(bytes32,bytes32)",
+                (remote, H256::zero(), H256::from_low_u64_be(9)),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert_eq!(loaded, H256::from_low_u64_be(7));
+        assert_eq!(stored, H256::from_low_u64_be(9));
+
+        let state = evm.executor.state();
+        assert_eq!(state.storage(remote, H256::zero()), H256::from_low_u64_be(9));
+        assert_eq!(state.backend.backend.storage(remote, H256::zero()), H256::from_low_u64_be(7));
+    }
+
+    #[test]
+    fn can_read_remote_storage() {
+        // returns the value of slot 0
+        let code = hex::decode("60005460005260206000f3").unwrap();
+        let remote = Address::repeat_byte(1);
+        let storage = vec![(H256::zero(), H256::from_low_u64_be(7))].into_iter().collect();
+        let account = MemoryAccount { code, storage, ..Default::default() };
+        let mut evm = vm_with_accounts(vec![(remote, account)]);
+        evm.executor.state_mut().set_storage(remote, H256::zero(), H256::from_low_u64_be(9));
+
+        fn load(evm: &mut TestSputnikVM<'_, MemoryBackend<'_>>) -> U256 {
+            let remote = Address::repeat_byte(1);
+            let (out, _, _, _) =
+                evm.call_raw(Address::zero(), remote, Bytes::default(), 0.into(), true).unwrap();
+            U256::from_big_endian(&out)
+        }
+        assert_eq!(load(&mut evm), 9.into());
+        evm.set_storage_source(remote, StorageSource::RemoteAlways);
+        assert_eq!(load(&mut evm), 7.into());
+        // the session keeps reading the remote value
+        let session = evm.fork_session();
+        assert_eq!(load(&mut session.executor(&*CFG_NO_LMT, &*PRECOMPILES_MAP)), 7.into());
+        evm.set_storage_source(remote, StorageSource::OverlayFirst);
+        assert_eq!(load(&mut evm), 9.into());
+    }
+
+    #[test]
+    fn get_nonce_reads_backend() {
+        let remote = Address::repeat_byte(1);
+        let account = MemoryAccount { nonce: 5.into(), ..Default::default() };
+        let mut evm = vm_with_accounts(vec![(remote, account)]);
+
+        let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let (nonce, _, _, _) = evm
+            .call_committing::<u64, _, _>(
+                Address::zero(),
+                addr,
+                "nonceOf(address)(uint64)",
+                remote,
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert_eq!(nonce, 5);
+    }
+
+    #[test]
+    fn etch_replaces_backend_code() {
+        // returns 7 and 42 respectively
+        let original = hex::decode("600760005260206000f3").unwrap();
+        let etched = hex::decode("602a60005260206000f3").unwrap();
+        let remote = Address::repeat_byte(1);
+        let storage = vec![(H256::zero(), H256::from_low_u64_be(1))].into_iter().collect();
+        let account =
+            MemoryAccount { nonce: 1.into(), balance: 2.into(), storage, code: original.clone() };
+        let mut evm = vm_with_accounts(vec![(remote, account)]);
+
+        let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let (out, _, _, _) = evm
+            .call_committing::<H256, _, _>(
+                Address::zero(),
+                addr,
+                "etchAndCall(address,bytes)(bytes32)",
+                (remote, ethers::types::Bytes::from(etched.clone())),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert_eq!(out, H256::from_low_u64_be(42));
+
+        let state = evm.executor.state();
+        assert_eq!(state.code(remote), etched);
+        assert_eq!(state.basic(remote).nonce, 1.into());
+        assert_eq!(state.basic(remote).balance, 2.into());
+        assert_eq!(state.storage(remote, H256::zero()), H256::from_low_u64_be(1));
+        assert_eq!(state.backend.backend.code(remote), original);
+    }
+
+    #[test]
+    fn can_relabel_addresses() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.call_committing::<(), _, _>(
+            Address::zero(),
+            addr,
+            "testLabel()",
+            (),
+            0.into(),
+            compiled.abi,
+        )
+        .unwrap();
+
+        let bob = Address::from_low_u64_be(1337);
+        assert_eq!(evm.labels()[&bob], "bob");
+        evm.label_address(bob, "alice".to_string()).unwrap();
+        evm.label_address(addr, "CheatCodes".to_string()).unwrap();
+        assert_eq!(evm.labels()[&bob], "alice");
+        assert_eq!(evm.labels()[&addr], "CheatCodes");
+    }
+
+    #[test]
+    fn can_label_known_addresses() {
+        let weth: Address = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".parse().unwrap();
+        let multicall: Address = "0xcA11bde05977b3631167028862bE2a173976CA11".parse().unwrap();
+
+        let mut evm = vm();
+        evm.set_chain_id(1);
+        let evm = evm
+            .with_labels([(Address::zero(), "deployer".to_string())])
+            .with_known_labels()
+            .with_labels([(multicall, "Batcher".to_string())]);
+        let labels = evm.labels();
+        assert_eq!(labels[&weth], "WETH");
+        assert_eq!(labels[&Address::zero()], "deployer");
+        assert_eq!(labels[&multicall], "Batcher");
+
+        // only the contracts which are on every chain are known for other chains
         let mut evm = vm();
+        evm.set_chain_id(1337);
+        let labels = evm.with_known_labels().labels();
+        assert!(!labels.contains_key(&weth));
+        assert_eq!(labels[&multicall], "Multicall3");
+
+        // executors without cheatcodes don't keep labels
+        let backend = new_backend(&*VICINITY, Default::default());
+        let mut evm = Executor::new(GAS_LIMIT, &*CFG, &backend, &*PRECOMPILES_MAP);
+        assert!(evm.label_address(multicall, "Batcher".to_string()).is_err());
+        assert!(evm.with_known_labels().labels().is_empty());
+    }
+
+    #[test]
+    fn ffi_fails_if_disabled() {
+        let mut evm = vm_no_limit();
+        evm.executor.enable_ffi = false;
 
-        let compiled = COMPILED.find("ConsoleLogs").expect("could not find contract");
+        let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
         let (addr, _, _, _) =
             evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
 
-        // after the evm call is done, we call `logs` and print it all to the user
-        let (_, _, _, logs) = evm
-            .call::<(), _, _>(
+        let err = evm
+            .call_committing::<(), _, _>(
                 Address::zero(),
                 addr,
-                "test_log_types_bytes()",
+                "testFFI()",
                 (),
                 0.into(),
                 compiled.abi,
             )
-            .unwrap();
-        let expected = [
-            r#"Bytes(b"logBytes")"#,
-            r#"Bytes(b"\xfb\xa3\xa4\xb5")"#,
-            "0xfb",
-            "0xfba3",
-            "0xfba3a4",
-            "0xfba3a4b5",
-            "0xfba3a4b500",
-            "0xfba3a4b50000",
-            "0xfba3a4b5000000",
-            "0xfba3a4b500000000",
-            "0xfba3a4b50000000000",
-            "0xfba3a4b5000000000000",
-            "0xfba3a4b500000000000000",
-            "0xfba3a4b50000000000000000",
-            "0xfba3a4b5000000000000000000",
-            "0xfba3a4b500000000000000000000",
-            "0xfba3a4b50000000000000000000000",
-            "0xfba3a4b5000000000000000000000000",
-            "0xfba3a4b500000000000000000000000000",
-            "0xfba3a4b50000000000000000000000000000",
-            "0xfba3a4b5000000000000000000000000000000",
-            "0xfba3a4b500000000000000000000000000000000",
-            "0xfba3a4b50000000000000000000000000000000000",
-            "0xfba3a4b5000000000000000000000000000000000000",
-            "0xfba3a4b500000000000000000000000000000000000000",
-            "0xfba3a4b50000000000000000000000000000000000000000",
-            "0xfba3a4b5000000000000000000000000000000000000000000",
-            "0xfba3a4b500000000000000000000000000000000000000000000",
-            "0xfba3a4b50000000000000000000000000000000000000000000000",
-            "0xfba3a4b5000000000000000000000000000000000000000000000000",
-            "0xfba3a4b500000000000000000000000000000000000000000000000000",
-            "0xfba3a4b50000000000000000000000000000000000000000000000000000",
-            "0xfba3a4b5000000000000000000000000000000000000000000000000000000",
-            "0xfba3a4b500000000000000000000000000000000000000000000000000000000",
-        ]
-        .iter()
-        .map(ToString::to_string)
-        .collect::<Vec<_>>();
-        assert_eq!(logs, expected);
+            .unwrap_err();
+        let reason = match err {
+            crate::EvmError::Execution { reason, .. } => reason,
+            _ => panic!("unexpected error"),
+        };
+        assert_eq!(reason, "ffi disabled: run again with --ffi if you want to allow tests to call external scripts");
     }
 
     #[test]
-    fn logs_external_contract() {
-        let mut evm = vm();
+    fn ffi_respects_allowlist() {
+        let mut evm = vm_no_limit();
+        evm.set_ffi_config(FfiConfig {
+            allowlist: Some(vec!["cat".to_string()]),
+            ..Default::default()
+        });
 
-        let compiled = COMPILED.find("DebugLogs").expect("could not find contract");
+        let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
         let (addr, _, _, _) =
             evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
 
-        // after the evm call is done, we call `logs` and print it all to the user
-        let (_, _, _, logs) = evm
-            .call::<(), _, _>(
+        let err = evm
+            .call_committing::<(), _, _>(
                 Address::zero(),
                 addr,
-                "test_log_elsewhere()",
+                "testFFI()",
                 (),
                 0.into(),
                 compiled.abi,
             )
-            .unwrap();
-        let expected = ["0x1111111111111111111111111111111111111111", "Hi"]
-            .iter()
-            .map(ToString::to_string)
-            .collect::<Vec<_>>();
-        assert_eq!(logs, expected);
+            .unwrap_err();
+        let reason = match err {
+            crate::EvmError::Execution { reason, .. } => reason,
+            _ => panic!("unexpected error"),
+        };
+        assert_eq!(reason, "ffi: `echo` is not in the allowlist");
     }
 
     #[test]
-    fn cheatcodes() {
+    fn parses_env_values() {
+        assert_eq!(parse_env_value(&ParamType::Uint(256), " 0x10 "), Ok(Token::Uint(16.into())));
+        assert_eq!(parse_env_value(&ParamType::String, " a "), Ok(Token::String(" a ".into())));
+        assert_eq!(
+            parse_env_value(&ParamType::Address, "01"),
+            Err("missing 0x prefix".to_string())
+        );
+        assert_eq!(parse_env_value(&ParamType::Bool, "true"), Err("bool is not supported".into()));
+    }
+
+    #[test]
+    fn can_read_env() {
         let mut evm = vm_no_limit();
         let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
         let (addr, _, _, _) =
             evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
 
-        let state = evm.state().clone();
-        let mut cfg = proptest::test_runner::Config::default();
-        cfg.failure_persistence = None;
-        let runner = proptest::test_runner::TestRunner::new(cfg);
-
-        // ensure the storage slot is set at 10 anyway
-        let (storage_contract, _, _, _) = evm
-            .call::<Address, _, _>(
+        let prefix = "FOUNDRY_CHEATCODES_ENV";
+        std::env::set_var(format!("{}_UINT", prefix), "0x10");
+        std::env::set_var(format!("{}_ADDRESS", prefix), format!("{:?}", Address::repeat_byte(1)));
+        std::env::set_var(format!("{}_BYTES32", prefix), format!("{:?}", H256::repeat_byte(2)));
+        std::env::set_var(format!("{}_STRING", prefix), " spaced ");
+        let (out, _, _, _) = evm
+            .call_committing::<(U256, Address, H256, String), _, _>(
                 Address::zero(),
                 addr,
-                "store()(address)",
-                (),
+                "readEnv(string)(uint256,address,bytes32,string)",
+                prefix.to_string(),
                 0.into(),
                 compiled.abi,
             )
             .unwrap();
-        let (slot, _, _, _) = evm
-            .call::<U256, _, _>(
+        assert_eq!(
+            out,
+            (16.into(), Address::repeat_byte(1), H256::repeat_byte(2), " spaced ".to_string())
+        );
+
+        std::env::set_var(format!("{}_UINT", prefix), "16");
+        let (out, _, _, _) = evm
+            .call_committing::<(U256, Address, H256, String), _, _>(
                 Address::zero(),
-                storage_contract,
-                "slot0()(uint256)",
-                (),
+                addr,
+                "readEnv(string)(uint256,address,bytes32,string)",
+                prefix.to_string(),
                 0.into(),
                 compiled.abi,
             )
             .unwrap();
-        assert_eq!(slot, 10.into());
-
-        let evm = FuzzedExecutor::new(&mut evm, runner, Address::zero());
-
-        let abi = compiled.abi.as_ref().unwrap();
-        for func in abi.functions().filter(|func| func.name.starts_with("test")) {
-            // Skip the FFI unit test if not in a unix system
-            if func.name == "testFFI" && !cfg!(unix) {
-                continue
-            }
+        assert_eq!(out.0, 16.into());
 
-            let should_fail = func.name.starts_with("testFail");
-            if func.inputs.is_empty() {
-                let (_, reason, _, _) =
-                    evm.as_mut().call_unchecked(Address::zero(), addr, func, (), 0.into()).unwrap();
-                assert!(evm.as_mut().check_success(addr, &reason, should_fail));
-            } else {
-                assert!(evm.fuzz(func, addr, should_fail, Some(abi)).is_ok());
-            }
+        let err = evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "testFailEnvUintUnset()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap_err();
+        let reason = match err {
+            crate::EvmError::Execution { reason, .. } => reason,
+            _ => panic!("unexpected error"),
+        };
+        assert_eq!(reason, "environment variable `FOUNDRY_CHEATCODES_UNSET_ENV` not found");
 
-            evm.as_mut().reset(state.clone());
-        }
+        evm.executor.enable_ffi = false;
+        let err = evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "testEnvOrDefaults()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap_err();
+        assert!(matches!(err, crate::EvmError::Execution { .. }));
     }
 
     #[test]
-    fn ffi_fails_if_disabled() {
+    fn addr_rejects_keys_above_curve_order() {
         let mut evm = vm_no_limit();
-        evm.executor.enable_ffi = false;
 
         let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
         let (addr, _, _, _) =
             evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
 
         let err = evm
-            .call::<(), _, _>(Address::zero(), addr, "testFFI()", (), 0.into(), compiled.abi)
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "testFail_addr_curve_order()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
             .unwrap_err();
         let reason = match err {
             crate::EvmError::Execution { reason, .. } => reason,
             _ => panic!("unexpected error"),
         };
-        assert_eq!(reason, "ffi disabled: run again with --ffi if you want to allow tests to call external scripts");
+        assert_eq!(
+            reason,
+            "Bad Cheat Code. Private Key must be less than the secp256k1 curve order."
+        );
     }
 
     #[test]
@@ -2119,7 +5525,7 @@ mod tests {
 
         // after the evm call is done, we call `logs` and print it all to the user
         let (_, _, _, _) = evm
-            .call::<(), _, _>(
+            .call_committing::<(), _, _>(
                 Address::zero(),
                 addr,
                 "recurseCall(uint256,uint256)",
@@ -2167,6 +5573,310 @@ mod tests {
         println!("{}", trace_string);
     }
 
+    #[test]
+    fn can_pause_tracing() {
+        let mut evm = vm_tracing(false);
+        let compiled = COMPILED.find("PausedTrace").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let call = |evm: &mut TestSputnikVM<'_, MemoryBackend<'_>>, sig: &str| {
+            evm.call_committing::<(), _, _>(Address::zero(), addr, sig, (), 0.into(), compiled.abi)
+                .unwrap();
+            let arena = evm.traces().last().unwrap().clone();
+            arena
+                .arena
+                .iter()
+                .map(|node| (node.trace.addr, node.trace.depth, node.children.len()))
+                .collect::<Vec<_>>()
+        };
+
+        // the root, the `pauseTracing` cheatcode, the second `inner` call and its `leaf` call
+        let nodes = call(&mut evm, "pauseAndResume()");
+        assert_eq!(
+            nodes,
+            vec![(addr, 0, 2), (*CHEATCODE_ADDRESS, 1, 0), (addr, 1, 1), (addr, 2, 0)]
+        );
+        // the calls within the untraced call are skipped, even after tracing resumed
+        let nodes = call(&mut evm, "resumeWithinUntracedCall()");
+        assert_eq!(nodes, vec![(addr, 0, 2), (*CHEATCODE_ADDRESS, 1, 0), (addr, 1, 0)]);
+        // the reverting `skip` ends its untraced call, so the `leaf` call after resuming is traced
+        let nodes = call(&mut evm, "revertWhilePaused()");
+        assert_eq!(nodes, vec![(addr, 0, 2), (*CHEATCODE_ADDRESS, 1, 0), (addr, 1, 0)]);
+
+        // while paused from rust, not even the root is recorded
+        evm.pause_tracing();
+        let nodes = call(&mut evm, "resumeWithinUntracedCall()");
+        assert!(nodes.iter().all(|(addr, _, _)| addr.is_zero()), "{:?}", nodes);
+        evm.resume_tracing();
+        assert_eq!(call(&mut evm, "resumeWithinUntracedCall()").len(), 3);
+    }
+
+    #[test]
+    fn can_attribute_gas_to_contracts() {
+        let compiled = COMPILED.find("GreeterTest").expect("could not find contract");
+        let mut evm = vm_tracing(false);
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.call_committing::<(), _, _>(Address::zero(), addr, "setUp()", (), 0.into(), None)
+            .unwrap();
+        evm.call_committing::<(), _, _>(
+            Address::zero(),
+            addr,
+            "testGreeting()",
+            (),
+            0.into(),
+            None,
+        )
+        .unwrap();
+
+        // the test contract and the greeter it deployed used gas, which adds up to the traces
+        let gas = evm.gas_by_contract();
+        assert!(gas[&addr] > 0);
+        assert!(gas.iter().any(|(contract, gas)| *contract != addr && *gas > 0));
+        let total: u64 = evm.traces().iter().map(|arena| arena.arena[arena.entry].trace.cost).sum();
+        assert_eq!(gas.values().sum::<u64>(), total);
+    }
+
+    #[test]
+    fn can_export_chrome_trace() {
+        let compiled = COMPILED.find("GreeterTest").expect("could not find contract");
+        let mut evm = vm_tracing(false).with_abi(compiled.abi.unwrap().clone());
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.label_address(addr, "GreeterTest".to_string()).unwrap();
+        evm.call_committing::<(), _, _>(Address::zero(), addr, "setUp()", (), 0.into(), None)
+            .unwrap();
+        evm.call_committing::<(), _, _>(
+            Address::zero(),
+            addr,
+            "testGreeting()",
+            (),
+            0.into(),
+            None,
+        )
+        .unwrap();
+
+        let trace: serde_json::Value = serde_json::from_str(&evm.export_chrome_trace()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let span = |name: &str| {
+            let event = events.iter().find(|event| event["name"] == name).expect(name);
+            let ts = event["ts"].as_u64().unwrap();
+            (ts, ts + event["dur"].as_u64().unwrap())
+        };
+        let (start, end) = span("GreeterTest::testGreeting");
+        assert!(start > 0 && end > start);
+        assert!(span("GreeterTest::setUp").1 <= start);
+        // the greeter has the same `greet` function as the test contract, but no label
+        let greet = events
+            .iter()
+            .find(|event| event["name"].as_str().unwrap().ends_with("::greet"))
+            .expect("greet is traced");
+        let ts = greet["ts"].as_u64().unwrap();
+        assert!(start <= ts && ts + greet["dur"].as_u64().unwrap() <= end);
+    }
+
+    #[test]
+    fn traces_selfdestructs() {
+        let mut evm = vm_tracing(false);
+        let (destructs, to_self) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let beneficiary = Address::repeat_byte(3);
+        // PUSH20 beneficiary SELFDESTRUCT, and ADDRESS SELFDESTRUCT
+        let mut code = vec![0x73];
+        code.extend_from_slice(beneficiary.as_bytes());
+        code.push(0xff);
+        evm.initialize_contracts([(destructs, code.into()), (to_self, vec![0x30, 0xff].into())]);
+
+        for (addr, target) in [(destructs, beneficiary), (to_self, to_self)] {
+            evm.deal(addr, 5.into());
+            assert!(evm.call_result(Address::zero(), addr, Bytes::default(), 0.into()).success);
+            let arena = evm.traces().last().unwrap().clone();
+            let node = &arena.arena[arena.arena[0].children[0]];
+            assert_eq!(node.trace.kind, CallKind::SelfDestruct);
+            assert!(node.trace.success);
+            assert_eq!(node.trace.addr, addr);
+            assert_eq!(node.trace.beneficiary, Some(target));
+            assert_eq!(node.trace.value, 5.into());
+        }
+    }
+
+    #[test]
+    fn streams_trace_nodes_in_post_order() {
+        let mut evm = vm();
+        let streamed = Rc::new(RefCell::new(Vec::new()));
+        let nodes = streamed.clone();
+        evm.on_trace_node(move |node| nodes.borrow_mut().push(node.clone()));
+
+        let compiled = COMPILED.find("Trace").expect("could not find contract");
+        let (addr, _, _, _) = evm
+            .deploy(
+                Address::zero(),
+                compiled.bin.unwrap().clone().into_bytes().expect("shouldn't be linked"),
+                0.into(),
+            )
+            .unwrap();
+        streamed.borrow_mut().clear();
+        evm.call_committing::<(), _, _>(
+            Address::zero(),
+            addr,
+            "recurseCall(uint256,uint256)",
+            (U256::from(2u32), U256::from(0u32)),
+            0u32.into(),
+            compiled.abi,
+        )
+        .unwrap();
+
+        let streamed = streamed.borrow();
+        let arena = evm.traces().last().unwrap().clone().arena;
+        assert_eq!(streamed.len(), arena.len());
+        // every node is passed after its children, and the entry call last
+        for (i, node) in streamed.iter().enumerate() {
+            assert!(!streamed[i + 1..].iter().any(|later| later.parent == Some(node.idx)));
+        }
+        assert_eq!(streamed.last().unwrap().idx, 0);
+        assert!(!streamed.last().unwrap().trace.data.is_empty());
+
+        // the buffered trace only keeps the structure and the selectors
+        assert!(arena.iter().all(|node| node.trace.data.len() <= 4 && node.logs.is_empty()));
+        for node in streamed.iter() {
+            assert_eq!(arena[node.idx].children, node.children);
+            assert_eq!(arena[node.idx].trace.data, node.trace.data[..4]);
+            assert!(arena[node.idx].trace.output.is_empty());
+        }
+        // streaming doesn't inspect the steps
+        assert!(!evm.executor.inspects_steps());
+    }
+
+    #[test]
+    fn reverts_traces_to_snapshots() {
+        let mut evm = vm_tracing(false);
+        let compiled = COMPILED.find("CheatCodes").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let (_, status, _, _) = evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "testSnapshot()",
+                (),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert!(matches!(status, ExitReason::Succeed(_)));
+
+        // the deployment after the snapshot was reverted, the cheatcode calls are kept
+        let arena = evm.traces().last().unwrap().clone().arena;
+        assert_eq!(arena.iter().filter(|node| node.trace.kind.is_create()).count(), 1);
+        let cheatcodes = arena.iter().filter(|node| node.trace.addr == *CHEATCODE_ADDRESS);
+        assert!(cheatcodes.clone().all(|node| node.trace.success));
+        assert!(cheatcodes.count() > 2);
+    }
+
+    #[test]
+    fn debug_steps_without_snapshots() {
+        let mut evm = vm_debug(false);
+        evm.executor.state_mut().debug_snapshots = false;
+
+        let compiled = COMPILED.find("Trace").expect("could not find contract");
+        let (addr, _, _, _) = evm
+            .deploy(
+                Address::zero(),
+                compiled.bin.unwrap().clone().into_bytes().expect("shouldn't be linked"),
+                0.into(),
+            )
+            .unwrap();
+        evm.call_committing::<(), _, _>(
+            Address::zero(),
+            addr,
+            "recurseCall(uint256,uint256)",
+            (U256::from(2u32), U256::from(0u32)),
+            0u32.into(),
+            compiled.abi,
+        )
+        .unwrap();
+
+        let mut flattened = vec![];
+        evm.debug_calls()[1].flatten(0, &mut flattened);
+        let steps = flattened.into_iter().flat_map(|(_, steps, _)| steps).collect::<Vec<_>>();
+        assert!(!steps.is_empty());
+        assert!(steps.iter().all(|step| step.stack.is_empty() && step.memory.len() == 0));
+        assert!(steps.iter().any(|step| step.memory_size > 0));
+        assert!(steps.iter().all(|step| step.gas_remaining > 0));
+    }
+
+    #[test]
+    fn gas_breakdown_of_debugged_call() {
+        let mut evm = vm_debug(false);
+        assert_eq!(evm.gas_breakdown(), Default::default());
+
+        let compiled = COMPILED.find("Trace").expect("could not find contract");
+        let (addr, _, _, _) = evm
+            .deploy(
+                Address::zero(),
+                compiled.bin.unwrap().clone().into_bytes().expect("shouldn't be linked"),
+                0.into(),
+            )
+            .unwrap();
+        let (_, _, gas_used, _) = evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "recurseCall(uint256,uint256)",
+                (U256::from(2u32), U256::from(0u32)),
+                0u32.into(),
+                compiled.abi,
+            )
+            .unwrap();
+
+        let breakdown = evm.gas_breakdown();
+        assert!(breakdown.arithmetic > 0);
+        assert!(breakdown.memory > 0);
+        assert!(breakdown.calls.own > 0);
+        assert!(breakdown.calls.forwarded > breakdown.calls.own);
+        assert!(breakdown.total() > 0);
+        // the intrinsic gas and the last step of each frame are not part of the breakdown
+        assert!(breakdown.total() < gas_used);
+    }
+
+    #[test]
+    fn flamegraph_of_debugged_call() {
+        let mut evm = vm_debug(false);
+        assert_eq!(evm.flamegraph_folded(), "");
+
+        let compiled = COMPILED.find("Trace").expect("could not find contract");
+        let (addr, _, _, _) = evm
+            .deploy(
+                Address::zero(),
+                compiled.bin.unwrap().clone().into_bytes().expect("shouldn't be linked"),
+                0.into(),
+            )
+            .unwrap();
+        evm.call_committing::<(), _, _>(
+            Address::zero(),
+            addr,
+            "recurseCall(uint256,uint256)",
+            (U256::from(2u32), U256::from(0u32)),
+            0u32.into(),
+            compiled.abi,
+        )
+        .unwrap();
+
+        let folded = evm.flamegraph_folded();
+        let stacks = folded
+            .lines()
+            .map(|line| {
+                let (stack, gas) = line.rsplit_once(' ').unwrap();
+                assert!(gas.parse::<u64>().unwrap() > 0);
+                stack.split(';').count()
+            })
+            .collect::<Vec<_>>();
+        assert!(stacks.contains(&1));
+        // the recursive calls of the contract to itself
+        assert!(stacks.iter().any(|depth| *depth >= 3), "{}", folded);
+        let selector = hex::encode(utils::id("recurseCall(uint256,uint256)"));
+        assert!(folded.contains(&format!("{:?}::0x{}", addr, selector)), "{}", folded);
+    }
+
     #[test]
     fn tracing_create() {
         use std::collections::BTreeMap;
@@ -2184,7 +5894,7 @@ mod tests {
 
         // after the evm call is done, we call `logs` and print it all to the user
         let (_, _, _, _) = evm
-            .call::<(), _, _>(
+            .call_committing::<(), _, _>(
                 Address::zero(),
                 addr,
                 "recurseCreate(uint256,uint256)",
@@ -2194,6 +5904,14 @@ mod tests {
             )
             .unwrap();
 
+        // every created child is recorded as its own node
+        let arena = &evm.traces()[1].arena;
+        assert_eq!(arena.iter().filter(|node| node.trace.kind == CallKind::Create).count(), 4);
+        assert!(arena
+            .iter()
+            .filter(|node| node.trace.kind.is_create())
+            .all(|node| node.trace.created));
+
         let mut mapping = BTreeMap::new();
         mapping.insert(
             "Trace".to_string(),
@@ -1,8 +1,16 @@
 use sputnik::{Memory, Opcode};
 
-use ethers::types::{Address, H256};
+use ethers::{
+    abi::Abi,
+    solc::sourcemap::{Jump, SourceMap},
+    types::{Address, H256},
+};
 
-use std::{borrow::Cow, fmt::Display};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt::{Display, Write},
+};
 
 #[derive(Debug, Clone)]
 /// An arena of `DebugNode`s
@@ -55,6 +63,359 @@ impl DebugArena {
             self.flatten(*child, flattened);
         });
     }
+
+    /// Aggregates the gas consumed by the steps of all nodes by kind of opcode.
+    ///
+    /// The cost of a step is the gas which remained before the next step of the same call frame,
+    /// so the last step of each frame, e.g. its `RETURN`, is counted as free. Storage accesses are
+    /// only split into cold and warm ones if `cold_access` is set, i.e. the config charges for
+    /// cold state access (EIP-2929), otherwise they are all counted as warm.
+    pub fn gas_breakdown(&self, cold_access: bool) -> GasBreakdown {
+        let mut visitor = BreakdownVisitor { breakdown: GasBreakdown::default(), cold_access };
+        self.walk_frames(&mut visitor);
+        visitor.breakdown
+    }
+
+    /// Walks the steps of all nodes by call frame, passing the gas consumed by every step and the
+    /// gas of every call without the gas consumed by its callees to the visitor.
+    ///
+    /// The nodes are in order of execution, a frame is split into multiple nodes at each of its
+    /// calls, with the nodes of the callee in between.
+    fn walk_frames<V: FrameVisitor>(&self, visitor: &mut V) {
+        struct PendingCall {
+            gas_before: u64,
+            forwarded: u64,
+            children: u64,
+        }
+        struct Frame<F> {
+            depth: usize,
+            consumed: u64,
+            call: Option<PendingCall>,
+            data: F,
+        }
+
+        let mut frames: Vec<Frame<V::Frame>> = Vec::new();
+        for node in self.arena.iter().filter(|node| !node.steps.is_empty()) {
+            while frames.last().map(|frame| frame.depth > node.depth).unwrap_or_default() {
+                let frame = frames.pop().expect("frame exists");
+                if let Some(call) = frames.last_mut().and_then(|parent| parent.call.as_mut()) {
+                    call.children += frame.consumed;
+                }
+            }
+
+            let first = &node.steps[0];
+            let continues = frames
+                .last()
+                .map(|frame| frame.depth == node.depth && frame.call.is_some())
+                .unwrap_or_default();
+            if continues {
+                let frame = frames.last_mut().expect("frame exists");
+                let call = frame.call.take().expect("call is pending");
+                let total = call.gas_before.saturating_sub(first.gas_remaining);
+                visitor.call_returned(
+                    &mut frame.data,
+                    total.saturating_sub(call.children),
+                    call.forwarded,
+                );
+                frame.consumed += total;
+            } else {
+                if frames.last().map(|frame| frame.depth == node.depth).unwrap_or_default() {
+                    frames.pop();
+                }
+                if let Some(call) = frames.last_mut().and_then(|parent| parent.call.as_mut()) {
+                    call.forwarded = first.gas_remaining;
+                }
+                let data = visitor.enter(frames.last().map(|frame| &frame.data), node);
+                frames.push(Frame { depth: node.depth, consumed: 0, call: None, data });
+            }
+
+            let frame = frames.last_mut().expect("frame exists");
+            for steps in node.steps.windows(2) {
+                let gas = steps[0].gas_remaining.saturating_sub(steps[1].gas_remaining);
+                visitor.step(&mut frame.data, node, &steps[0], &steps[1], gas);
+                frame.consumed += gas;
+            }
+            let last = node.steps.last().expect("node has steps");
+            if GasBreakdown::is_call(last.op.0) {
+                frame.call =
+                    Some(PendingCall { gas_before: last.gas_remaining, forwarded: 0, children: 0 });
+            }
+        }
+    }
+
+    /// Folds the steps of all nodes into the stacks of a flamegraph weighted by gas, one
+    /// `frame;frame;frame gas` line per stack, as consumed by `inferno` or `flamegraph.pl`.
+    ///
+    /// The call frames are named `contract::function`, after the [ContractSources] of the
+    /// contract and the function of the `abi` with the selector if there are any, otherwise after
+    /// the address and the selector. The gas of contracts with a source map is attributed to the
+    /// internal functions they jump into. Like in the [gas breakdown](Self::gas_breakdown), the
+    /// last step of each frame is free.
+    pub fn flamegraph_folded(
+        &self,
+        sources: &BTreeMap<Address, ContractSources>,
+        abi: Option<&Abi>,
+    ) -> String {
+        let mut visitor = FlamegraphVisitor { sources, abi, weights: BTreeMap::new() };
+        self.walk_frames(&mut visitor);
+
+        let mut folded = String::new();
+        for (stack, gas) in visitor.weights {
+            let _ = writeln!(folded, "{} {}", stack, gas);
+        }
+        folded
+    }
+}
+
+/// Receives the gas consumed by the call frames of a [DebugArena], see
+/// [DebugArena::walk_frames]
+trait FrameVisitor {
+    /// What is tracked per call frame
+    type Frame;
+
+    /// The node starts a call frame, called by the frame of the `parent` if there is one
+    fn enter(&mut self, parent: Option<&Self::Frame>, node: &DebugNode) -> Self::Frame;
+
+    /// A call of the frame returned, which consumed the `own` gas without the gas consumed by
+    /// the callee, and `forwarded` the gas to the callee
+    fn call_returned(&mut self, frame: &mut Self::Frame, own: u64, forwarded: u64);
+
+    /// The step of the node consumed the gas, `next` is the following step of the node
+    fn step(
+        &mut self,
+        frame: &mut Self::Frame,
+        node: &DebugNode,
+        step: &DebugStep,
+        next: &DebugStep,
+        gas: u64,
+    );
+}
+
+/// Aggregates the [GasBreakdown] of the steps
+struct BreakdownVisitor {
+    breakdown: GasBreakdown,
+    cold_access: bool,
+}
+
+impl FrameVisitor for BreakdownVisitor {
+    type Frame = ();
+
+    fn enter(&mut self, _parent: Option<&()>, _node: &DebugNode) {}
+
+    fn call_returned(&mut self, _frame: &mut (), own: u64, forwarded: u64) {
+        self.breakdown.calls.own += own;
+        self.breakdown.calls.forwarded += forwarded;
+    }
+
+    fn step(
+        &mut self,
+        _frame: &mut (),
+        _node: &DebugNode,
+        step: &DebugStep,
+        _: &DebugStep,
+        gas: u64,
+    ) {
+        self.breakdown.add(step, gas, self.cold_access);
+    }
+}
+
+/// Weighs the stacks of a flamegraph by the gas of their steps
+struct FlamegraphVisitor<'a> {
+    sources: &'a BTreeMap<Address, ContractSources>,
+    abi: Option<&'a Abi>,
+    weights: BTreeMap<String, u64>,
+}
+
+impl FlamegraphVisitor<'_> {
+    fn add(&mut self, stack: &[String], gas: u64) {
+        if gas > 0 {
+            *self.weights.entry(stack.join(";")).or_default() += gas;
+        }
+    }
+}
+
+impl FrameVisitor for FlamegraphVisitor<'_> {
+    /// The call frame followed by the internal functions it jumped into
+    type Frame = Vec<String>;
+
+    fn enter(&mut self, parent: Option<&Vec<String>>, node: &DebugNode) -> Vec<String> {
+        let mut stack = parent.cloned().unwrap_or_default();
+        stack.push(call_frame_name(node, self.sources.get(&node.address), self.abi));
+        stack
+    }
+
+    fn call_returned(&mut self, stack: &mut Vec<String>, own: u64, _forwarded: u64) {
+        self.add(stack, own);
+    }
+
+    fn step(
+        &mut self,
+        stack: &mut Vec<String>,
+        node: &DebugNode,
+        step: &DebugStep,
+        next: &DebugStep,
+        gas: u64,
+    ) {
+        self.add(stack, gas);
+        let contract = match self.sources.get(&node.address) {
+            Some(contract) => contract,
+            None => return,
+        };
+        let source_map =
+            if node.creation { contract.creation.as_ref() } else { contract.runtime.as_ref() };
+        let source_map = match source_map {
+            Some(source_map) => source_map,
+            None => return,
+        };
+        match source_map.get(step.ic).map(|element| &element.jump) {
+            Some(Jump::In) => stack.push(contract.function_at(source_map, next.ic)),
+            // the call frame itself is never left with a jump
+            Some(Jump::Out) if stack.len() > 1 => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The name of the call frame of the node in a flamegraph
+fn call_frame_name(
+    node: &DebugNode,
+    contract: Option<&ContractSources>,
+    abi: Option<&Abi>,
+) -> String {
+    let contract = contract.map_or_else(|| format!("{:?}", node.address), |c| c.name.clone());
+    let function = match node.selector {
+        _ if node.creation => "constructor".to_string(),
+        Some(selector) => abi
+            .and_then(|abi| abi.functions().find(|func| func.short_signature() == selector))
+            .map_or_else(|| format!("0x{}", hex::encode(selector)), |func| func.name.clone()),
+        None => "fallback".to_string(),
+    };
+    format!("{}::{}", contract, function)
+}
+
+/// The source maps of a contract, to attribute the gas in a
+/// [flamegraph](DebugArena::flamegraph_folded) to the functions of the contract
+#[derive(Debug, Clone, Default)]
+pub struct ContractSources {
+    /// The name of the contract
+    pub name: String,
+    /// The source map of the creation code
+    pub creation: Option<SourceMap>,
+    /// The source map of the runtime code
+    pub runtime: Option<SourceMap>,
+    /// The source files by their index in the source maps
+    pub sources: BTreeMap<u32, String>,
+}
+
+impl ContractSources {
+    /// The name of the function which starts at the instruction, its element in the source map
+    /// spans the whole definition of the function
+    fn function_at(&self, source_map: &SourceMap, ic: usize) -> String {
+        let definition = source_map.get(ic).and_then(|element| {
+            let source = self.sources.get(&element.index?)?;
+            source.get(element.offset..element.offset + element.length)
+        });
+        let name = definition
+            .and_then(|definition| {
+                let definition = definition.trim_start();
+                definition.strip_prefix("function").or_else(|| definition.strip_prefix("modifier"))
+            })
+            .and_then(|rest| {
+                rest.trim_start()
+                    .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .next()
+                    .filter(|name| !name.is_empty())
+            });
+        match name {
+            Some(name) => format!("{}::{}", self.name, name),
+            None => format!("{}::internal@{}", self.name, ic),
+        }
+    }
+}
+
+/// The gas consumed by the steps of a [DebugArena], grouped by kind of opcode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GasBreakdown {
+    /// Arithmetic, comparison and bitwise opcodes
+    pub arithmetic: u64,
+    /// Memory accesses, including the copying of calldata, code and returndata into memory
+    pub memory: u64,
+    /// `SLOAD`s and `SSTORE`s
+    pub storage: StorageGas,
+    /// Calls and contract creations
+    pub calls: CallGas,
+    /// All other opcodes
+    pub other: u64,
+}
+
+/// The gas consumed by storage accesses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageGas {
+    pub cold_reads: u64,
+    pub warm_reads: u64,
+    pub cold_writes: u64,
+    pub warm_writes: u64,
+}
+
+/// The gas consumed by calls and contract creations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallGas {
+    /// The gas consumed by the call opcodes themselves, e.g. for the value transfer or
+    /// accessing the callee, without the gas consumed by the callee
+    pub own: u64,
+    /// The gas which was made available to the callees
+    pub forwarded: u64,
+}
+
+impl GasBreakdown {
+    /// The total gas consumed, without the gas forwarded to callees
+    pub fn total(&self) -> u64 {
+        let storage = &self.storage;
+        self.arithmetic +
+            self.memory +
+            storage.cold_reads +
+            storage.warm_reads +
+            storage.cold_writes +
+            storage.warm_writes +
+            self.calls.own +
+            self.other
+    }
+
+    fn is_call(op: Opcode) -> bool {
+        matches!(
+            op,
+            Opcode::CALL |
+                Opcode::CALLCODE |
+                Opcode::DELEGATECALL |
+                Opcode::STATICCALL |
+                Opcode::CREATE |
+                Opcode::CREATE2
+        )
+    }
+
+    /// Adds the gas of a step which is not a call
+    fn add(&mut self, step: &DebugStep, gas: u64, cold_access: bool) {
+        match step.op.0 {
+            Opcode::SLOAD if cold_access && step.cold_slot => self.storage.cold_reads += gas,
+            Opcode::SLOAD => self.storage.warm_reads += gas,
+            Opcode::SSTORE if cold_access && step.cold_slot => self.storage.cold_writes += gas,
+            Opcode::SSTORE => self.storage.warm_writes += gas,
+            Opcode::MLOAD |
+            Opcode::MSTORE |
+            Opcode::MSTORE8 |
+            Opcode::MSIZE |
+            Opcode::CALLDATACOPY |
+            Opcode::CODECOPY |
+            Opcode::EXTCODECOPY |
+            Opcode::RETURNDATACOPY => self.memory += gas,
+            op if (0x01..=0x0b).contains(&op.0) || (0x10..=0x1d).contains(&op.0) => {
+                self.arithmetic += gas
+            }
+            _ => self.other += gas,
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone)]
@@ -76,6 +437,8 @@ pub struct DebugNode {
     pub steps: Vec<DebugStep>,
     /// Contract Creation
     pub creation: bool,
+    /// The selector of the called function, `None` for deployments and calls without one
+    pub selector: Option<[u8; 4]>,
 }
 
 impl DebugNode {
@@ -91,10 +454,12 @@ impl DebugNode {
 pub struct DebugStep {
     /// Program Counter
     pub pc: usize,
-    /// Stack *prior* to running this struct's associated opcode
+    /// Stack *prior* to running this struct's associated opcode, empty if snapshots are disabled
     pub stack: Vec<H256>,
-    /// Memory *prior* to running this struct's associated opcode
+    /// Memory *prior* to running this struct's associated opcode, empty if snapshots are disabled
     pub memory: Memory,
+    /// Size of the memory *prior* to running this struct's associated opcode
+    pub memory_size: usize,
     /// Opcode to be executed
     pub op: OpCode,
     /// Optional bytes that are being pushed onto the stack
@@ -103,6 +468,10 @@ pub struct DebugStep {
     pub ic: usize,
     /// Cumulative gas usage
     pub total_gas_used: u64,
+    /// Gas remaining in the current call frame
+    pub gas_remaining: u64,
+    /// Whether the step is an `SLOAD` or `SSTORE` of a slot the transaction did not access before
+    pub cold_slot: bool,
 }
 
 impl Default for DebugStep {
@@ -111,10 +480,13 @@ impl Default for DebugStep {
             pc: 0,
             stack: vec![],
             memory: Memory::new(0),
+            memory_size: 0,
             op: OpCode(Opcode::INVALID, None),
             push_bytes: None,
             ic: 0,
             total_gas_used: 0,
+            gas_remaining: 0,
+            cold_slot: false,
         }
     }
 }
@@ -160,7 +532,10 @@ impl Display for DebugStep {
 pub enum CheatOp {
     ROLL,
     WARP,
+    SKIP,
     FEE,
+    DIFFICULTY,
+    PREVRANDAO,
     STORE,
     LOAD,
     FFI,
@@ -170,6 +545,8 @@ pub enum CheatOp {
     STARTPRANK,
     STOPPRANK,
     DEAL,
+    DEALALLOWANCE,
+    DEALERC721,
     ETCH,
     EXPECTREVERT,
     RECORD,
@@ -181,6 +558,16 @@ pub enum CheatOp {
     GETCODE,
     LABEL,
     ASSUME,
+    ENV,
+    GETNONCE,
+    READCALLERS,
+    SETNONCE,
+    PAUSETRACING,
+    RESUMETRACING,
+    SNAPSHOT,
+    REVERTTO,
+    DELETESNAPSHOT,
+    ASSERT,
 }
 
 impl From<CheatOp> for OpCode {
@@ -195,7 +582,10 @@ impl CheatOp {
         match self {
             CheatOp::ROLL => "VM_ROLL",
             CheatOp::WARP => "VM_WARP",
+            CheatOp::SKIP => "VM_SKIP",
             CheatOp::FEE => "VM_FEE",
+            CheatOp::DIFFICULTY => "VM_DIFFICULTY",
+            CheatOp::PREVRANDAO => "VM_PREVRANDAO",
             CheatOp::STORE => "VM_STORE",
             CheatOp::LOAD => "VM_LOAD",
             CheatOp::FFI => "VM_FFI",
@@ -205,6 +595,8 @@ impl CheatOp {
             CheatOp::STARTPRANK => "VM_STARTPRANK",
             CheatOp::STOPPRANK => "VM_STOPPRANK",
             CheatOp::DEAL => "VM_DEAL",
+            CheatOp::DEALALLOWANCE => "VM_DEALALLOWANCE",
+            CheatOp::DEALERC721 => "VM_DEALERC721",
             CheatOp::ETCH => "VM_ETCH",
             CheatOp::EXPECTREVERT => "VM_EXPECTREVERT",
             CheatOp::RECORD => "VM_RECORD",
@@ -216,6 +608,16 @@ impl CheatOp {
             CheatOp::GETCODE => "VM_GETCODE",
             CheatOp::LABEL => "VM_LABEL",
             CheatOp::ASSUME => "VM_ASSUME",
+            CheatOp::ENV => "VM_ENV",
+            CheatOp::GETNONCE => "VM_GETNONCE",
+            CheatOp::READCALLERS => "VM_READCALLERS",
+            CheatOp::SETNONCE => "VM_SETNONCE",
+            CheatOp::PAUSETRACING => "VM_PAUSETRACING",
+            CheatOp::RESUMETRACING => "VM_RESUMETRACING",
+            CheatOp::SNAPSHOT => "VM_SNAPSHOT",
+            CheatOp::REVERTTO => "VM_REVERTTO",
+            CheatOp::DELETESNAPSHOT => "VM_DELETESNAPSHOT",
+            CheatOp::ASSERT => "VM_ASSERT",
         }
     }
 }
@@ -410,3 +812,85 @@ impl Display for OpCode {
         write!(f, "{}", n)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(ic: usize, op: Opcode, gas_remaining: u64) -> DebugStep {
+        DebugStep { ic, op: OpCode::from(op), gas_remaining, ..Default::default() }
+    }
+
+    #[test]
+    fn folds_internal_functions() {
+        let source =
+            "contract C { function outer() public { inner(); } function inner() internal {} }";
+        let outer = source.find("function outer").unwrap();
+        let outer_len = source.find(" function inner").unwrap() - outer;
+        let inner = source.find("function inner").unwrap();
+        let inner_len = source.len() - 2 - inner;
+        let source_map = ethers::solc::sourcemap::parse(&format!(
+            "{}:{}:0:-;:::i;{}:{}:0:-;:::o;{}:{}:0:-",
+            outer, outer_len, inner, inner_len, outer, outer_len
+        ))
+        .unwrap();
+        let address = Address::repeat_byte(1);
+        let mut contracts = BTreeMap::new();
+        contracts.insert(
+            address,
+            ContractSources {
+                name: "C".to_string(),
+                runtime: Some(source_map),
+                sources: vec![(0, source.to_string())].into_iter().collect(),
+                ..Default::default()
+            },
+        );
+
+        let steps = vec![
+            step(0, Opcode::PUSH1, 100),
+            step(1, Opcode::JUMP, 97),
+            step(2, Opcode::JUMPDEST, 89),
+            step(3, Opcode::JUMP, 88),
+            step(4, Opcode::STOP, 80),
+        ];
+        let mut node = DebugNode::new(address, 0, steps);
+        node.selector = Some([1, 2, 3, 4]);
+        let arena = DebugArena { arena: vec![node], entry: 0 };
+
+        assert_eq!(
+            arena.flamegraph_folded(&contracts, None),
+            "C::0x01020304 11\nC::0x01020304;C::inner 9\n"
+        );
+        // without sources, the gas is attributed to the call
+        assert_eq!(
+            arena.flamegraph_folded(&Default::default(), None),
+            format!("{:?}::0x01020304 20\n", address)
+        );
+    }
+
+    #[test]
+    fn splits_storage_gas_by_the_accessed_slots() {
+        let cold = |step: DebugStep| DebugStep { cold_slot: true, ..step };
+        let steps = vec![
+            cold(step(0, Opcode::SLOAD, 30000)),
+            step(1, Opcode::SLOAD, 27900),
+            // the charge of a warm write may be equal to the one of a cold one
+            step(2, Opcode::SSTORE, 27800),
+            cold(step(3, Opcode::SSTORE, 22800)),
+            step(4, Opcode::STOP, 700),
+        ];
+        let arena = DebugArena { arena: vec![DebugNode::new(Address::zero(), 0, steps)], entry: 0 };
+
+        let storage = arena.gas_breakdown(true).storage;
+        assert_eq!(
+            storage,
+            StorageGas { cold_reads: 2100, warm_reads: 100, cold_writes: 22100, warm_writes: 5000 }
+        );
+        // without cold state access, all of them are warm
+        let storage = arena.gas_breakdown(false).storage;
+        assert_eq!(
+            storage,
+            StorageGas { warm_reads: 2200, warm_writes: 27100, ..Default::default() }
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! User supplied cheatcodes, which are dispatched by the cheatcode handler next to the built-in
+//! ones
+use super::{
+    backend::CheatcodeBackend, memory_stackstate_owned::MemoryStackStateOwned, Cheatcodes,
+};
+use ethers::types::{Address, H256, U256};
+use sputnik::{backend::Backend, executor::stack::StackState};
+use std::fmt::Debug;
+
+/// The outcome of a custom cheatcode: the ABI encoded data it returns, or the reason it reverts
+/// with
+pub type CheatResult = Result<Vec<u8>, String>;
+
+/// Custom cheatcodes, registered with
+/// [with_cheatcode_extension](crate::sputnik::Executor::with_cheatcode_extension).
+///
+/// A call to the cheatcode address whose selector is not one of the built-in cheatcodes is passed
+/// to the extensions in the order they were registered, until one of them handles it. Built-in
+/// cheatcodes always win a selector collision, so an extension can't replace them, and a call
+/// with a built-in selector but invalid arguments fails without being passed to the extensions.
+/// A call which no extension handles fails like any unknown cheatcode.
+pub trait CheatcodeExtension: Debug {
+    /// Handles the call, whose calldata starts with the selector, or returns `None` if the
+    /// selector isn't one of the cheatcodes of this extension
+    fn handle(&mut self, calldata: &[u8], ctx: &mut CheatContext<'_>) -> Option<CheatResult>;
+}
+
+/// The state a [CheatcodeExtension] can inspect and modify while handling a call
+pub struct CheatContext<'a> {
+    caller: Address,
+    state: &'a mut dyn ExtensionState,
+    console_logs: &'a mut Vec<String>,
+}
+
+impl<'a> CheatContext<'a> {
+    pub(crate) fn new(
+        caller: Address,
+        state: &'a mut dyn ExtensionState,
+        console_logs: &'a mut Vec<String>,
+    ) -> Self {
+        Self { caller, state, console_logs }
+    }
+
+    /// The contract which called the cheatcode
+    pub fn caller(&self) -> Address {
+        self.caller
+    }
+
+    pub fn balance(&self, address: Address) -> U256 {
+        self.state.balance(address)
+    }
+
+    /// Sets the balance of the account, like `deal`
+    pub fn set_balance(&mut self, address: Address, balance: U256) {
+        self.state.set_balance(address, balance)
+    }
+
+    pub fn storage(&self, address: Address, slot: H256) -> H256 {
+        self.state.storage(address, slot)
+    }
+
+    /// Writes the storage slot of the account, like `store`
+    pub fn set_storage(&mut self, address: Address, slot: H256, value: H256) {
+        self.state.set_storage(address, slot, value)
+    }
+
+    pub fn code(&self, address: Address) -> Vec<u8> {
+        self.state.code(address)
+    }
+
+    /// Sets the code of the account, like `etch`
+    pub fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        self.state.set_code(address, code)
+    }
+
+    /// The overrides of the block environment, e.g. to change the timestamp like `warp`
+    pub fn cheats(&mut self) -> &mut Cheatcodes {
+        self.state.cheats()
+    }
+
+    /// Adds a line to the console output of the call, like `console.log`
+    pub fn log(&mut self, message: impl Into<String>) {
+        self.console_logs.push(message.into());
+    }
+}
+
+/// The parts of the cheatcode state which are exposed to extensions, so that [CheatContext]
+/// doesn't depend on the backend
+pub(crate) trait ExtensionState {
+    fn balance(&self, address: Address) -> U256;
+    fn set_balance(&mut self, address: Address, balance: U256);
+    fn storage(&self, address: Address, slot: H256) -> H256;
+    fn set_storage(&mut self, address: Address, slot: H256, value: H256);
+    fn code(&self, address: Address) -> Vec<u8>;
+    fn set_code(&mut self, address: Address, code: Vec<u8>);
+    fn cheats(&mut self) -> &mut Cheatcodes;
+}
+
+impl<'config, B: Backend> ExtensionState for MemoryStackStateOwned<'config, CheatcodeBackend<B>> {
+    fn balance(&self, address: Address) -> U256 {
+        self.basic(address).balance
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        self.reset_balance(address);
+        self.deposit(address, balance);
+    }
+
+    fn storage(&self, address: Address, slot: H256) -> H256 {
+        Backend::storage(self, address, slot)
+    }
+
+    fn set_storage(&mut self, address: Address, slot: H256, value: H256) {
+        StackState::set_storage(self, address, slot, value)
+    }
+
+    fn code(&self, address: Address) -> Vec<u8> {
+        Backend::code(self, address)
+    }
+
+    fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        StackState::set_code(self, address, code)
+    }
+
+    fn cheats(&mut self) -> &mut Cheatcodes {
+        &mut self.backend.cheats
+    }
+}
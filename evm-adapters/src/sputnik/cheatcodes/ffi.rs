@@ -0,0 +1,207 @@
+//! Execution of external commands for the `ffi` cheatcodes
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often a running command is checked for completion
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How many bytes of stderr are kept, the rest is discarded
+const MAX_STDERR: u64 = 4096;
+
+/// The result of a command which ran to completion, see [FfiConfig::execute]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FfiOutput {
+    /// The exit code, `-1` if the command was terminated by a signal
+    pub exit_code: i32,
+    pub stdout: Vec<u8>,
+    /// The first 4096 bytes of stderr
+    pub stderr: Vec<u8>,
+}
+
+/// Restrictions on the commands which can be run by the `ffi` cheatcode.
+///
+/// By default commands are not restricted at all, ffi itself still needs to be enabled
+/// separately.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FfiConfig {
+    /// The command is killed if it takes longer than this
+    pub timeout: Option<Duration>,
+    /// The command is killed if it writes more than this many bytes to stdout
+    pub max_output: Option<usize>,
+    /// The only programs which can be run, if set
+    pub allowlist: Option<Vec<String>>,
+}
+
+impl FfiConfig {
+    /// Runs the command and returns its stdout, the error is the revert reason of the cheatcode.
+    ///
+    /// Unlike [FfiConfig::execute], this also fails if the command exits with an error.
+    pub fn run(&self, args: &[String], stdin: Option<&[u8]>) -> Result<Vec<u8>, String> {
+        let output = self.execute(args, stdin)?;
+        if output.exit_code != 0 {
+            return Err(format!(
+                "ffi: `{}` failed with exit code {}: {}",
+                args[0],
+                output.exit_code,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+        Ok(output.stdout)
+    }
+
+    /// Runs the command, writing `stdin` to its standard input if set, and returns its exit code
+    /// and both of its outputs.
+    ///
+    /// Only the restrictions of the config are errors, a command which exits with an error is
+    /// not. Only stdout counts towards the output limit, stderr is cut off after 4096 bytes.
+    pub fn execute(&self, args: &[String], stdin: Option<&[u8]>) -> Result<FfiOutput, String> {
+        let program = args.first().ok_or_else(|| "ffi: no command given".to_string())?;
+        if let Some(allowlist) = &self.allowlist {
+            if !allowlist.contains(program) {
+                return Err(format!("ffi: `{}` is not in the allowlist", program))
+            }
+        }
+
+        let mut child = Command::new(program)
+            .args(&args[1..])
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("ffi: failed to run `{}`: {}", program, err))?;
+
+        // written from its own thread, so that a command which doesn't read all of its input
+        // before writing its output can't block on a full pipe
+        if let Some(input) = stdin {
+            let mut pipe = child.stdin.take().expect("stdin is piped");
+            let input = input.to_vec();
+            // the pipe is closed when the thread is done, which signals the end of the input,
+            // and a command which exits early breaks the pipe, which is not an error
+            let _ = thread::spawn(move || pipe.write_all(&input));
+        }
+
+        let max_output = self.max_output.unwrap_or(usize::MAX);
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stdout = {
+            let exceeded = Arc::clone(&exceeded);
+            thread::spawn(move || {
+                let mut out = Vec::new();
+                let _ = stdout.take(max_output.saturating_add(1) as u64).read_to_end(&mut out);
+                if out.len() > max_output {
+                    exceeded.store(true, Ordering::Relaxed);
+                }
+                out
+            })
+        };
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let stderr = thread::spawn(move || {
+            let mut out = Vec::new();
+            // only keep the beginning for the error message, but keep draining so that the
+            // command doesn't block on a full pipe
+            let _ = (&mut stderr).take(MAX_STDERR).read_to_end(&mut out);
+            let _ = std::io::copy(&mut stderr, &mut std::io::sink());
+            out
+        });
+
+        let start = Instant::now();
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) => {}
+                Err(err) => return Err(format!("ffi: failed to wait for `{}`: {}", program, err)),
+            }
+            if exceeded.load(Ordering::Relaxed) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "ffi: `{}` exceeded the output limit of {} bytes",
+                    program, max_output
+                ))
+            }
+            if let Some(timeout) = self.timeout {
+                if start.elapsed() > timeout {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(format!("ffi: `{}` timed out after {:?}", program, timeout))
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+        if stdout.len() > max_output {
+            return Err(format!(
+                "ffi: `{}` exceeded the output limit of {} bytes",
+                program, max_output
+            ))
+        }
+        Ok(FfiOutput { exit_code: status.code().unwrap_or(-1), stdout, stderr })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn can_restrict_commands() {
+        let config = FfiConfig::default();
+        assert_eq!(config.run(&args(&["echo", "-n", "hi"]), None).unwrap(), b"hi");
+
+        let config = FfiConfig { timeout: Some(Duration::from_millis(100)), ..Default::default() };
+        let err = config.run(&args(&["sleep", "5"]), None).unwrap_err();
+        assert!(err.contains("timed out"), "{}", err);
+
+        let config = FfiConfig { max_output: Some(16), ..Default::default() };
+        let err = config.run(&args(&["yes"]), None).unwrap_err();
+        assert!(err.contains("output limit"), "{}", err);
+        // stderr doesn't count towards the limit
+        let output =
+            config.execute(&args(&["sh", "-c", "yes | head -c 100000 >&2"]), None).unwrap();
+        assert_eq!(output.stderr.len(), 4096);
+
+        let config = FfiConfig { allowlist: Some(args(&["echo"])), ..Default::default() };
+        assert!(config.run(&args(&["echo"]), None).is_ok());
+        let err = config.run(&args(&["cat"]), None).unwrap_err();
+        assert!(err.contains("allowlist"), "{}", err);
+    }
+
+    #[test]
+    fn surfaces_stderr_on_failure() {
+        let err = FfiConfig::default()
+            .run(&args(&["sh", "-c", "echo oops >&2; exit 3"]), None)
+            .unwrap_err();
+        assert!(err.ends_with(": oops"), "{}", err);
+    }
+
+    #[test]
+    fn can_capture_structured_output() {
+        let config = FfiConfig::default();
+        let output =
+            config.execute(&args(&["sh", "-c", "echo -n out; echo -n err >&2; exit 3"]), None);
+        assert_eq!(
+            output.unwrap(),
+            FfiOutput { exit_code: 3, stdout: b"out".to_vec(), stderr: b"err".to_vec() }
+        );
+
+        // larger than the pipe buffer, to make sure the input doesn't block the output
+        let input = vec![b'a'; 1 << 20];
+        let output = config.execute(&args(&["cat"]), Some(&input)).unwrap();
+        assert_eq!(output.exit_code, 0);
+        assert_eq!(output.stdout, input);
+    }
+}
@@ -0,0 +1,66 @@
+//! User supplied hooks into the execution of the cheatcode handler, e.g. for coverage collectors
+//! or custom assertions
+use crate::call_tracing::CallTraceNode;
+use ethers::types::Address;
+use sputnik::{Context, ExitReason, Memory, Opcode, Stack};
+use std::fmt::Debug;
+
+/// Hooks into the execution of a cheatcode-enabled executor, registered with
+/// [add_hook](crate::sputnik::Executor::add_hook).
+///
+/// Hooks are invoked in the order they were registered, after the built-in tracer and debugger
+/// recorded the call or step. Calls are passed after cheatcodes were applied, so the context
+/// already has the `msg.sender` of an active prank. Calls to the cheatcode and console addresses
+/// are handled by the executor itself and are never passed, neither are calls to precompiles or
+/// calls which failed before any code was executed, e.g. because the call stack is too deep.
+///
+/// All methods do nothing by default.
+pub trait ExecutionHook: Debug {
+    /// Called before the code of the call is executed
+    fn call(&mut self, _context: &Context, _code_address: Address, _input: &[u8]) {}
+
+    /// Called after the code of the call was executed
+    fn call_end(&mut self, _context: &Context, _reason: &ExitReason, _output: &[u8]) {}
+
+    /// Called before the init code of a contract is executed, the `context` has the address the
+    /// contract is deployed to
+    fn create(&mut self, _context: &Context, _init_code: &[u8]) {}
+
+    /// Called after the init code of a contract was executed, the deployment may still fail
+    /// afterwards, e.g. if the deployed code exceeds the size limit
+    fn create_end(&mut self, _context: &Context, _reason: &ExitReason) {}
+
+    /// Called before every opcode is executed, with the stack and memory prior to it.
+    ///
+    /// Registering a hook makes the executor step through every opcode, which is slower than
+    /// running the code at once.
+    fn step(
+        &mut self,
+        _address: Address,
+        _pc: usize,
+        _op: Opcode,
+        _stack: &Stack,
+        _memory: &Memory,
+    ) {
+    }
+
+    /// Called when the frame of a traced call, deployment or selfdestruct completed, with its
+    /// node in the trace arena of the transaction.
+    ///
+    /// Nodes are passed in post-order, i.e. all children of a node are passed before it, and
+    /// their parent and children indices refer to the arena, so the tree can be reconstructed
+    /// from the passed nodes. Nodes are only recorded while tracing is enabled. Reverting to a
+    /// snapshot drops the nodes which were recorded after it, so their indices may be passed
+    /// again for the nodes of later calls.
+    fn trace_node(&mut self, _node: &CallTraceNode) {}
+}
+
+/// Passes the completed trace nodes to a callback, see
+/// [on_trace_node](crate::sputnik::Executor::on_trace_node)
+pub(crate) struct TraceNodeCallback<F>(pub F);
+
+impl<F> Debug for TraceNodeCallback<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceNodeCallback").finish_non_exhaustive()
+    }
+}
@@ -4,19 +4,64 @@ use sputnik::{
     ExitError, Transfer,
 };
 
-use crate::{call_tracing::CallTraceArena, sputnik::cheatcodes::debugger::DebugArena};
+use crate::{
+    call_tracing::CallTraceArena, sputnik::cheatcodes::debugger::DebugArena, AssertionFailure,
+};
 
 use ethers::{
     abi::RawLog,
     types::{H160, H256, U256},
 };
 
-use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    rc::Rc,
+};
 
-#[derive(Clone, Default)]
+/// Where the storage of an account is read from, see
+/// [`Executor::set_storage_source`](crate::sputnik::Executor::set_storage_source)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageSource {
+    /// Slots written by the executor are read from the overlay, all others from the backend
+    OverlayFirst,
+    /// Slots are always read from the backend, e.g. the forked chain, even if they were written
+    RemoteAlways,
+}
+
+impl Default for StorageSource {
+    fn default() -> Self {
+        StorageSource::OverlayFirst
+    }
+}
+
+/// The storage slots accessed since `record` was called, every slot is recorded once per address
+/// in the order it was first accessed
+#[derive(Clone, Default, Debug)]
 pub struct RecordAccess {
-    pub reads: RefCell<BTreeMap<H160, Vec<H256>>>,
-    pub writes: RefCell<BTreeMap<H160, Vec<H256>>>,
+    pub reads: RefCell<BTreeMap<H160, AccessedSlots>>,
+    pub writes: RefCell<BTreeMap<H160, AccessedSlots>>,
+}
+
+/// The storage slots of an address in the order they were first accessed
+#[derive(Clone, Default, Debug)]
+pub struct AccessedSlots {
+    slots: Vec<H256>,
+    seen: HashSet<H256>,
+}
+
+impl AccessedSlots {
+    /// Records an access of the slot unless it was accessed already
+    pub fn insert(&mut self, slot: H256) {
+        if self.seen.insert(slot) {
+            self.slots.push(slot);
+        }
+    }
+
+    /// Returns the accessed slots in the order they were first accessed
+    pub fn into_vec(self) -> Vec<H256> {
+        self.slots
+    }
 }
 
 #[derive(Clone, Default, Debug)]
@@ -28,6 +73,31 @@ pub struct ExpectedEmit {
     pub found: bool,
 }
 
+#[derive(Clone, Default, Debug)]
+pub struct ExpectedCall {
+    /// The calldata, or a prefix of it, the call is expected with
+    pub calldata: Vec<u8>,
+    /// The value the call is expected to transfer, any value matches if not set
+    pub value: Option<U256>,
+    /// How often the call is expected to be made, at least once if not set
+    pub count: Option<u64>,
+    /// How often a matching call was made
+    pub found: u64,
+}
+
+impl ExpectedCall {
+    pub fn matches(&self, input: &[u8], value: U256) -> bool {
+        input.starts_with(&self.calldata) && self.value.map(|v| v == value).unwrap_or(true)
+    }
+
+    pub fn is_satisfied(&self) -> bool {
+        match self.count {
+            Some(count) => self.found == count,
+            None => self.found > 0,
+        }
+    }
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct Prank {
     /// Address of the contract that called prank
@@ -40,6 +110,48 @@ pub struct Prank {
     pub depth: usize,
 }
 
+/// The expectations, mocks, pranks and recorded accesses which the cheatcodes registered and
+/// which apply to the following calls, see
+/// [`Executor::clear_cheatcode_state`](crate::sputnik::Executor::clear_cheatcode_state)
+#[derive(Clone, Default, Debug)]
+pub struct CheatcodeRegistrations {
+    pub expected_revert: Option<Vec<u8>>,
+    pub expected_emits: Vec<ExpectedEmit>,
+    pub expected_calls: BTreeMap<H160, Vec<ExpectedCall>>,
+    /// The mocked return data by address and calldata
+    pub mocked_calls: BTreeMap<H160, BTreeMap<Vec<u8>, Vec<u8>>>,
+    pub next_prank: Option<Prank>,
+    pub prank: Option<Prank>,
+    /// The accesses recorded since `record` was called, if it was
+    pub accesses: Option<RecordAccess>,
+}
+
+impl CheatcodeRegistrations {
+    /// Whether nothing is registered
+    pub fn is_empty(&self) -> bool {
+        self.expected_revert.is_none() &&
+            self.expected_emits.is_empty() &&
+            self.expected_calls.is_empty() &&
+            self.mocked_calls.is_empty() &&
+            self.next_prank.is_none() &&
+            self.prank.is_none() &&
+            self.accesses.is_none()
+    }
+}
+
+/// A checkpoint of the state taken by the `snapshot` cheatcode
+#[derive(Clone)]
+pub struct StateSnapshot<'config> {
+    substate: MemoryStackSubstate<'config>,
+    nonces: BTreeMap<H160, U256>,
+    created: BTreeSet<H160>,
+    destroyed: BTreeSet<H160>,
+    /// The index of the transaction the snapshot was taken in, and its trace at that point
+    call_index: usize,
+    trace: CallTraceArena,
+    trace_index: usize,
+}
+
 /// This struct implementation is copied from [upstream](https://github.com/rust-blockchain/evm/blob/5ecf36ce393380a89c6f1b09ef79f686fe043624/src/executor/stack/state.rs#L412) and modified to own the Backend type.
 ///
 /// We had to copy it so that we can modify the Stack's internal backend, because
@@ -51,6 +163,11 @@ pub struct MemoryStackStateOwned<'config, B> {
     pub substate: MemoryStackSubstate<'config>,
     /// Tracing enabled
     pub trace_enabled: bool,
+    /// Whether tracing is paused, calls which start while paused are not traced
+    pub trace_paused: bool,
+    /// How many untraced calls the current call is nested in. A call is only traced if none of
+    /// its parents was skipped, so that the recorded tree stays connected.
+    pub untraced_calls: usize,
     /// Current call index used for incrementing traces index vec below
     pub call_index: usize,
     /// Temporary value used for putting logs in the correct trace
@@ -67,18 +184,39 @@ pub struct MemoryStackStateOwned<'config, B> {
     pub accesses: Option<RecordAccess>,
     /// All logs accumulated (regardless of revert status)
     pub all_logs: Vec<String>,
+    /// All failed assertion cheatcodes (regardless of revert status)
+    pub assertion_failures: Vec<AssertionFailure>,
     /// Expected events by end of the next call
     pub expected_emits: Vec<ExpectedEmit>,
     pub mocked_calls: BTreeMap<H160, BTreeMap<Vec<u8>, Vec<u8>>>,
-    pub expected_calls: BTreeMap<H160, Vec<Vec<u8>>>,
+    /// Calls expected by the end of the next call
+    pub expected_calls: BTreeMap<H160, Vec<ExpectedCall>>,
     /// Debug enabled
     pub debug_enabled: bool,
+    /// Whether each debug step records a copy of the stack and memory. Turning this off keeps
+    /// the recording small on long running executions.
+    pub debug_snapshots: bool,
     /// An arena allocator of DebugNodes for debugging purposes
     pub debug_steps: Vec<DebugArena>,
     /// Instruction pointers that maps an address to a mapping of pc to ic
     pub debug_instruction_pointers: Dip,
     /// Labels for an address in call traces
     pub labels: BTreeMap<H160, String>,
+    /// Nonces set by the `setNonce` cheatcodes, they take precedence over the substate since it
+    /// can only increase nonces
+    pub nonces: BTreeMap<H160, U256>,
+    /// The nonces set when each open substate was entered, restored if it reverts
+    pub nonce_checkpoints: Vec<BTreeMap<H160, U256>>,
+    /// Accounts created in the current transaction
+    pub created: BTreeSet<H160>,
+    /// Accounts which selfdestructed in the current transaction, they are cleared once it
+    /// finished
+    pub destroyed: BTreeSet<H160>,
+    /// Snapshots taken by the `snapshot` cheatcode by their id, deleted ones are `None` so that
+    /// ids are never reused
+    pub snapshots: Vec<Option<StateSnapshot<'config>>>,
+    /// The accounts whose storage is not read from the overlay first
+    pub storage_sources: BTreeMap<H160, StorageSource>,
 }
 
 impl<'config, B: Backend> MemoryStackStateOwned<'config, B> {
@@ -86,6 +224,30 @@ impl<'config, B: Backend> MemoryStackStateOwned<'config, B> {
         self.substate.deposit(address, value, &self.backend);
     }
 
+    /// Sets the nonce of the account, which is increased as usual afterwards
+    pub fn set_nonce(&mut self, address: H160, nonce: U256) {
+        self.substate.touch(address, &self.backend);
+        self.nonces.insert(address, nonce);
+    }
+
+    /// Clears the accounts which selfdestructed during the transaction and prepares the next one.
+    ///
+    /// Sputnik only marks these accounts as deleted and leaves it to the caller to remove them,
+    /// like the end of a block would.
+    pub fn finalize_transaction(&mut self) {
+        for address in std::mem::take(&mut self.destroyed) {
+            // the selfdestruct was reverted
+            if !self.substate.deleted(address) {
+                continue
+            }
+            self.reset_balance(address);
+            self.set_code(address, vec![]);
+            self.reset_storage(address);
+            self.set_nonce(address, U256::zero());
+        }
+        self.created.clear();
+    }
+
     pub fn increment_call_index(&mut self) {
         self.traces.push(Default::default());
         self.debug_steps.push(Default::default());
@@ -107,6 +269,112 @@ impl<'config, B: Backend> MemoryStackStateOwned<'config, B> {
         self.traces = vec![Default::default()];
         self.call_index = 0;
     }
+
+    /// Replaces the traces, the following transactions are traced after the last one
+    pub fn set_traces(&mut self, traces: Vec<CallTraceArena>) {
+        self.traces = traces;
+        if self.traces.is_empty() {
+            self.traces.push(Default::default());
+        }
+        self.call_index = self.traces.len() - 1;
+        if self.debug_steps.len() < self.traces.len() {
+            self.debug_steps.resize_with(self.traces.len(), Default::default);
+        }
+    }
+
+    /// A copy of what the cheatcodes currently have registered
+    pub fn cheatcode_registrations(&self) -> CheatcodeRegistrations {
+        CheatcodeRegistrations {
+            expected_revert: self.expected_revert.clone(),
+            expected_emits: self.expected_emits.clone(),
+            expected_calls: self.expected_calls.clone(),
+            mocked_calls: self.mocked_calls.clone(),
+            next_prank: self.next_prank.clone(),
+            prank: self.prank.clone(),
+            accesses: self.accesses.clone(),
+        }
+    }
+
+    /// Removes and returns everything the cheatcodes registered
+    pub fn take_cheatcode_registrations(&mut self) -> CheatcodeRegistrations {
+        CheatcodeRegistrations {
+            expected_revert: self.expected_revert.take(),
+            expected_emits: std::mem::take(&mut self.expected_emits),
+            expected_calls: std::mem::take(&mut self.expected_calls),
+            mocked_calls: std::mem::take(&mut self.mocked_calls),
+            next_prank: self.next_prank.take(),
+            prank: self.prank.take(),
+            accesses: self.accesses.take(),
+        }
+    }
+
+    /// Checkpoints the accounts and logs, and the trace of the current transaction, and returns
+    /// the id of the snapshot.
+    ///
+    /// The nonces are checkpointed with the accounts, so that contracts created after reverting
+    /// to the snapshot get the same addresses as the reverted ones.
+    pub fn snapshot(&mut self) -> U256 {
+        let snapshot = StateSnapshot {
+            substate: self.substate.clone(),
+            nonces: self.nonces.clone(),
+            created: self.created.clone(),
+            destroyed: self.destroyed.clone(),
+            call_index: self.call_index,
+            trace: self.trace().clone(),
+            trace_index: self.trace_index,
+        };
+        self.snapshots.push(Some(snapshot));
+        U256::from(self.snapshots.len() - 1)
+    }
+
+    /// Restores the snapshot with the given id and returns whether it existed. The snapshot is
+    /// kept, so it can be reverted to again.
+    ///
+    /// The state can only be restored at the call depth the snapshot was taken at, since the
+    /// open calls are part of it, otherwise nothing is restored. The gas used so far and the
+    /// warm accounts of the current call are kept, and the trace is only restored if the
+    /// snapshot was taken in the same transaction.
+    pub fn revert_to(&mut self, id: U256) -> bool {
+        let snapshot = match self.snapshot_by_id(id) {
+            Some(snapshot) => snapshot.clone(),
+            None => return false,
+        };
+        if snapshot.substate.metadata().depth() != self.substate.metadata().depth() {
+            return false
+        }
+        let metadata = self.substate.metadata().clone();
+        self.substate = snapshot.substate;
+        *self.substate.metadata_mut() = metadata;
+        self.nonces = snapshot.nonces;
+        self.created = snapshot.created;
+        self.destroyed = snapshot.destroyed;
+        if snapshot.call_index == self.call_index {
+            *self.trace_mut() = snapshot.trace;
+            self.trace_index = snapshot.trace_index;
+        }
+        true
+    }
+
+    /// Deletes the snapshot with the given id and returns whether it existed
+    pub fn delete_snapshot(&mut self, id: U256) -> bool {
+        if self.snapshot_by_id(id).is_none() {
+            return false
+        }
+        self.snapshots[id.as_usize()] = None;
+        true
+    }
+
+    /// Deletes all snapshots, new snapshots still get new ids
+    pub fn delete_snapshots(&mut self) {
+        self.snapshots.iter_mut().for_each(|snapshot| *snapshot = None);
+    }
+
+    fn snapshot_by_id(&self, id: U256) -> Option<&StateSnapshot<'config>> {
+        if id >= U256::from(self.snapshots.len()) {
+            return None
+        }
+        self.snapshots[id.as_usize()].as_ref()
+    }
 }
 
 /// Debug Instruction pointers: a tuple with 2 maps, the first being for creation
@@ -131,6 +399,8 @@ impl<'config, B: Backend> MemoryStackStateOwned<'config, B> {
             backend,
             substate: MemoryStackSubstate::new(metadata),
             trace_enabled,
+            trace_paused: false,
+            untraced_calls: 0,
             call_index: 0,
             trace_index: 1,
             traces: vec![Default::default()],
@@ -139,13 +409,21 @@ impl<'config, B: Backend> MemoryStackStateOwned<'config, B> {
             prank: None,
             accesses: None,
             all_logs: Default::default(),
+            assertion_failures: Default::default(),
             expected_emits: Default::default(),
             mocked_calls: Default::default(),
             expected_calls: Default::default(),
             debug_enabled,
+            debug_snapshots: true,
             debug_steps: vec![Default::default()],
             debug_instruction_pointers: (BTreeMap::new(), BTreeMap::new()),
             labels: BTreeMap::new(),
+            nonces: BTreeMap::new(),
+            nonce_checkpoints: vec![],
+            created: BTreeSet::new(),
+            destroyed: BTreeSet::new(),
+            snapshots: vec![],
+            storage_sources: BTreeMap::new(),
         }
     }
 }
@@ -187,7 +465,12 @@ impl<'config, B: Backend> Backend for MemoryStackStateOwned<'config, B> {
     }
 
     fn basic(&self, address: H160) -> Basic {
-        self.substate.known_basic(address).unwrap_or_else(|| self.backend.basic(address))
+        let mut basic =
+            self.substate.known_basic(address).unwrap_or_else(|| self.backend.basic(address));
+        if let Some(nonce) = self.nonces.get(&address) {
+            basic.nonce = *nonce;
+        }
+        basic
     }
 
     fn code(&self, address: H160) -> Vec<u8> {
@@ -196,7 +479,10 @@ impl<'config, B: Backend> Backend for MemoryStackStateOwned<'config, B> {
 
     fn storage(&self, address: H160, key: H256) -> H256 {
         if let Some(record_accesses) = &self.accesses {
-            record_accesses.reads.borrow_mut().entry(address).or_insert_with(Vec::new).push(key);
+            record_accesses.reads.borrow_mut().entry(address).or_default().insert(key);
+        }
+        if self.storage_sources.get(&address) == Some(&StorageSource::RemoteAlways) {
+            return self.backend.storage(address, key)
         }
         self.substate
             .known_storage(address, key)
@@ -222,22 +508,33 @@ impl<'config, B: Backend> StackState<'config> for MemoryStackStateOwned<'config,
     }
 
     fn enter(&mut self, gas_limit: u64, is_static: bool) {
+        self.nonce_checkpoints.push(self.nonces.clone());
         self.substate.enter(gas_limit, is_static)
     }
 
     fn exit_commit(&mut self) -> Result<(), ExitError> {
+        self.nonce_checkpoints.pop();
         self.substate.exit_commit()
     }
 
     fn exit_revert(&mut self) -> Result<(), ExitError> {
+        if let Some(nonces) = self.nonce_checkpoints.pop() {
+            self.nonces = nonces;
+        }
         self.substate.exit_revert()
     }
 
     fn exit_discard(&mut self) -> Result<(), ExitError> {
+        if let Some(nonces) = self.nonce_checkpoints.pop() {
+            self.nonces = nonces;
+        }
         self.substate.exit_discard()
     }
 
     fn is_empty(&self, address: H160) -> bool {
+        if self.nonces.get(&address).map_or(false, |nonce| !nonce.is_zero()) {
+            return false
+        }
         if let Some(known_empty) = self.substate.known_empty(address) {
             return known_empty
         }
@@ -261,11 +558,14 @@ impl<'config, B: Backend> StackState<'config> for MemoryStackStateOwned<'config,
 
     fn inc_nonce(&mut self, address: H160) {
         self.substate.inc_nonce(address, &self.backend);
+        if let Some(nonce) = self.nonces.get_mut(&address) {
+            *nonce += U256::one();
+        }
     }
 
     fn set_storage(&mut self, address: H160, key: H256, value: H256) {
         if let Some(record_accesses) = &self.accesses {
-            record_accesses.writes.borrow_mut().entry(address).or_insert_with(Vec::new).push(key);
+            record_accesses.writes.borrow_mut().entry(address).or_default().insert(key);
         }
         self.substate.set_storage(address, key, value)
     }
@@ -279,6 +579,8 @@ impl<'config, B: Backend> StackState<'config> for MemoryStackStateOwned<'config,
     }
 
     fn set_deleted(&mut self, address: H160) {
+        self.nonces.remove(&address);
+        self.destroyed.insert(address);
         self.substate.set_deleted(address)
     }
 
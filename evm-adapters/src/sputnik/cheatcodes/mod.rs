@@ -1,17 +1,35 @@
 //! Hooks over Sputnik EVM execution which allow runtime logging and modification of chain state
 //! from Solidity (cheatcodes).
 pub mod memory_stackstate_owned;
+pub use memory_stackstate_owned::{CheatcodeRegistrations, StorageSource};
 
 pub mod cheatcode_handler;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
-pub use cheatcode_handler::CheatcodeHandler;
+pub use cheatcode_handler::{CheatcodeAccess, CheatcodeHandler, DealStrategy};
 
 pub mod backend;
 
 pub mod debugger;
 
-use ethers::types::{Address, Selector, H256, U256};
+mod ffi;
+pub use ffi::{FfiConfig, FfiOutput};
+
+mod extension;
+pub use extension::{CheatContext, CheatResult, CheatcodeExtension};
+
+mod hooks;
+pub use hooks::ExecutionHook;
+pub(crate) use hooks::TraceNodeCallback;
+
+mod value_collector;
+pub use value_collector::ValueCollector;
+
+use ethers::{
+    abi::Token,
+    types::{Address, Selector, H256, I256, U256},
+    utils::keccak256,
+};
 use once_cell::sync::Lazy;
 use sputnik::backend::{Backend, MemoryAccount, MemoryBackend};
 
@@ -19,19 +37,131 @@ use sputnik::backend::{Backend, MemoryAccount, MemoryBackend};
 /// Cheatcodes can be used to control the EVM context during setup or runtime,
 /// which can be useful for simulations or specialized unit tests
 pub struct Cheatcodes {
+    /// The overridden environment the calls are executed in
+    pub env: ExecutionEnv,
+    /// The overridden storage slots
+    pub accounts: HashMap<Address, MemoryAccount>,
+    /// The hashes of the blocks the block number advanced over with `roll`
+    pub block_hashes: BlockHashes,
+    /// The seconds the timestamp advances per block when rolling blocks from rust, and the
+    /// seconds per block the `skip` cheatcode advances the block number by, defaults to
+    /// [DEFAULT_BLOCK_TIME]
+    pub block_time: Option<u64>,
+}
+
+/// The overrides of the block and transaction environment the calls are executed in. Values
+/// which are not overridden are the ones of the backend, e.g. of the forked block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionEnv {
     /// The overridden block number
     pub block_number: Option<U256>,
     /// The overridden timestamp
     pub block_timestamp: Option<U256>,
     /// The overridden basefee
     pub block_base_fee_per_gas: Option<U256>,
-    /// The overridden storage slots
-    pub accounts: HashMap<Address, MemoryAccount>,
+    /// The overridden difficulty, which holds the `prevrandao` after the Merge
+    pub block_difficulty: Option<U256>,
+    /// The overridden coinbase, which collects the priority fees
+    pub block_coinbase: Option<Address>,
+    /// The overridden block gas limit
+    pub block_gas_limit: Option<U256>,
+    /// The overridden chain id
+    pub chain_id: Option<U256>,
     /// The overriden tx.origin
     pub origin: Option<Address>,
-    /// The overridden block hashes, whenever `roll` gets
-    /// called.
-    pub block_hashes: HashMap<U256, H256>,
+    /// The overridden tx.gasprice, which the senders pay for the gas their calls use
+    pub gas_price: Option<U256>,
+    /// The versioned hashes of the blobs of the transactions, which `BLOBHASH` returns
+    pub blob_hashes: Vec<H256>,
+    /// The blob base fee of the block, which `BLOBBASEFEE` returns
+    pub blob_base_fee: Option<U256>,
+}
+
+impl ExecutionEnv {
+    /// Whether `BLOBHASH` and `BLOBBASEFEE` are executed, which is the case once blob hashes or
+    /// a blob base fee are set since sputnik predates Cancun. They are invalid opcodes otherwise.
+    pub fn blobs_enabled(&self) -> bool {
+        !self.blob_hashes.is_empty() || self.blob_base_fee.is_some()
+    }
+}
+
+/// The maximum number of blobs of a transaction, see EIP-4844
+pub const MAX_BLOBS_PER_TX: usize = 6;
+
+/// The blob base fee returned by `BLOBBASEFEE` if only blob hashes are set, the minimum blob gas
+/// price of EIP-4844
+pub const MIN_BLOB_BASE_FEE: u64 = 1;
+
+/// The average block time of mainnet in seconds
+pub const DEFAULT_BLOCK_TIME: u64 = 12;
+
+/// `blockhash` only returns the hashes of this many preceding blocks
+pub const BLOCK_HASH_WINDOW: u64 = 256;
+
+/// The deterministic hash of a block whose real hash is not known, e.g. one which was skipped
+/// with `roll`
+pub fn synthetic_block_hash(number: U256) -> H256 {
+    let mut bytes = [0u8; 32];
+    number.to_big_endian(&mut bytes);
+    keccak256(bytes).into()
+}
+
+/// A ring buffer of the hashes of the latest [BLOCK_HASH_WINDOW] blocks, ordered by block number
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockHashes {
+    hashes: VecDeque<(U256, H256)>,
+}
+
+impl BlockHashes {
+    /// The hash of the block, if it is known
+    pub fn get(&self, number: U256) -> Option<H256> {
+        self.position(number).ok().map(|idx| self.hashes[idx].1)
+    }
+
+    /// Sets the hash of the block, e.g. the real one of the forked block
+    pub fn insert(&mut self, number: U256, hash: H256) {
+        match self.position(number) {
+            Ok(idx) => self.hashes[idx].1 = hash,
+            Err(idx) => self.hashes.insert(idx, (number, hash)),
+        }
+        self.prune();
+    }
+
+    /// Records the hashes of the blocks `from..to` when the block number advances from `from` to
+    /// `to`. Blocks which already have a hash keep it, all others get their
+    /// [synthetic_block_hash].
+    pub fn advance(&mut self, from: U256, to: U256) {
+        let mut number = from.max(to.saturating_sub(BLOCK_HASH_WINDOW.into()));
+        while number < to {
+            if self.get(number).is_none() {
+                self.insert(number, synthetic_block_hash(number));
+            }
+            number += U256::one();
+        }
+    }
+
+    /// Forgets the hashes of the blocks from `number` on, e.g. when the block number is moved back
+    pub fn truncate(&mut self, number: U256) {
+        while self.hashes.back().map_or(false, |(n, _)| *n >= number) {
+            self.hashes.pop_back();
+        }
+    }
+
+    fn position(&self, number: U256) -> Result<usize, usize> {
+        self.hashes.binary_search_by_key(&number, |(n, _)| *n)
+    }
+
+    /// Drops the hashes which are out of the window of the latest block
+    fn prune(&mut self) {
+        let latest = match self.hashes.back() {
+            Some((number, _)) => *number,
+            None => return,
+        };
+        let oldest = latest.saturating_sub(BLOCK_HASH_WINDOW.into());
+        while self.hashes.front().map_or(false, |(number, _)| *number < oldest) {
+            self.hashes.pop_front();
+        }
+    }
 }
 
 /// Extension trait over [`Backend`] which provides additional methods for interacting with the
@@ -53,10 +183,18 @@ ethers::contract::abigen!(
     r#"[
             roll(uint256)
             warp(uint256)
+            skip(uint256)
             fee(uint256)
+            difficulty(uint256)
+            prevrandao(bytes32)
+            getDifficulty()(uint256)
+            getPrevrandao()(bytes32)
             store(address,bytes32,bytes32)
             load(address,bytes32)(bytes32)
             ffi(string[])(bytes)
+            ffi(string[],bytes)(bytes)
+            tryFfi(string[])
+            tryFfi(string[],bytes)
             addr(uint256)(address)
             sign(uint256,bytes32)(uint8,bytes32,bytes32)
             prank(address)
@@ -65,6 +203,13 @@ ethers::contract::abigen!(
             startPrank(address,address)
             stopPrank()
             deal(address,uint256)
+            deal(address,address,uint256)
+            deal(address,address,uint256,bool)
+            deal(address,address,uint256,bool,uint256)
+            dealAllowance(address,address,address,uint256)
+            dealAllowance(address,address,address,uint256,uint256)
+            dealERC721(address,address,uint256)
+            dealERC721(address,address,uint256,uint256,uint256)
             etch(address,bytes)
             expectRevert(bytes)
             expectRevert(bytes4)
@@ -74,9 +219,41 @@ ethers::contract::abigen!(
             mockCall(address,bytes,bytes)
             clearMockedCalls()
             expectCall(address,bytes)
+            expectCall(address,uint256,bytes)
+            expectCall(address,bytes,uint64)
             getCode(string)
             label(address,string)
             assume(bool)
+            envUint(string)(uint256)
+            envAddress(string)(address)
+            envBytes32(string)(bytes32)
+            envString(string)(string)
+            envOr(string,uint256)(uint256)
+            envOr(string,address)(address)
+            envOr(string,bytes32)(bytes32)
+            envOr(string,string)(string)
+            getNonce(address)(uint64)
+            readCallers()(uint8,address,address)
+            setNonce(address,uint64)
+            setNonceUnsafe(address,uint64)
+            resetNonce(address)
+            pauseTracing()
+            resumeTracing()
+            snapshot()(uint256)
+            revertTo(uint256)(bool)
+            deleteSnapshot(uint256)(bool)
+            deleteSnapshots()
+            assertTrue(bool)
+            assertFalse(bool)
+            assertEq(uint256,uint256)
+            assertEq(int256,int256)
+            assertEq(address,address)
+            assertEq(bytes32,bytes32)
+            assertEq(bytes,bytes)
+            assertGt(uint256,uint256)
+            assertGe(uint256,uint256)
+            assertLt(uint256,uint256)
+            assertLe(uint256,uint256)
     ]"#,
 );
 pub use hevm_mod::{HEVMCalls, HEVM_ABI};
@@ -121,6 +298,78 @@ pub fn patch_hardhat_console_log_selector(mut input: Vec<u8>) -> Vec<u8> {
     input
 }
 
+/// Formats a `console.log` call whose first argument is a format string, like
+/// `console.log("%s has %d tokens", account, amount)`, the way hardhat does.
+///
+/// Every `%s`, `%d`, `%i` and `%o` is replaced by the next argument and `%%` by `%`, specifiers
+/// without an argument left are kept as they are, and the remaining arguments are appended
+/// separated by spaces. Returns `None` if the first argument is not a string with specifiers,
+/// e.g. `console.log("Hi", 1337)`, which is printed as a plain list of its arguments instead.
+pub fn format_console_log(input: &[u8]) -> Option<String> {
+    let selector = input.get(..4)?;
+    let func = CONSOLE_ABI.functions().find(|func| func.short_signature() == selector)?;
+    let mut args = func.decode_input(&input[4..]).ok()?.into_iter();
+    let format = match args.next()? {
+        Token::String(format) => format,
+        _ => return None,
+    };
+    if args.len() == 0 {
+        return None
+    }
+
+    let mut out = String::with_capacity(format.len());
+    let mut formatted = false;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue
+        }
+        match chars.peek().copied() {
+            Some('%') => {
+                chars.next();
+                out.push('%');
+                formatted = true;
+            }
+            Some(spec) if "sdio".contains(spec) => {
+                chars.next();
+                match args.next() {
+                    Some(arg) => {
+                        out.push_str(&format_console_arg(arg));
+                        formatted = true;
+                    }
+                    None => {
+                        out.push('%');
+                        out.push(spec);
+                    }
+                }
+            }
+            _ => out.push('%'),
+        }
+    }
+    if !formatted {
+        return None
+    }
+    for arg in args {
+        out.push(' ');
+        out.push_str(&format_console_arg(arg));
+    }
+    Some(out)
+}
+
+/// Formats a single `console.log` argument, numbers in decimal and bytes in hex
+fn format_console_arg(arg: Token) -> String {
+    match arg {
+        Token::String(s) => s,
+        Token::Address(address) => format!("{:?}", address),
+        Token::Uint(value) => value.to_string(),
+        Token::Int(value) => I256::from_raw(value).to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => format!("0x{}", hex::encode(bytes)),
+        arg => arg.to_string(),
+    }
+}
+
 /// This contains a map with all the  `hardhat/console.log` log selectors that use `uint` or `int`
 /// as key and the selector of the call with `uint256`,
 ///
@@ -652,4 +901,62 @@ mod tests {
             assert_eq!(abigen.to_vec(), patched);
         }
     }
+
+    #[test]
+    fn can_format_console_logs() {
+        let log = |signature: &str, args: &[Token]| {
+            let mut input = ethers::utils::id(signature).to_vec();
+            input.extend(ethers::abi::encode(args));
+            format_console_log(&input)
+        };
+        let s = |s: &str| Token::String(s.to_string());
+
+        assert_eq!(
+            log("log(string,uint256)", &[s("Hi %s"), Token::Uint(1337.into())]).unwrap(),
+            "Hi 1337"
+        );
+        assert_eq!(
+            log(
+                "log(string,address,uint256)",
+                &[
+                    s("%s has %d tokens"),
+                    Token::Address(Address::repeat_byte(0x11)),
+                    Token::Uint(5.into())
+                ]
+            )
+            .unwrap(),
+            "0x1111111111111111111111111111111111111111 has 5 tokens"
+        );
+        assert_eq!(
+            log("log(string,bool)", &[s("100%% %s"), Token::Bool(true)]).unwrap(),
+            "100% true"
+        );
+        // the arguments without a specifier are appended
+        assert_eq!(log("log(string,string,string)", &[s("%s"), s("a"), s("b")]).unwrap(), "a b");
+        // and specifiers without an argument are kept
+        assert_eq!(log("log(string,string)", &[s("%s %d"), s("a")]).unwrap(), "a %d");
+
+        // plain lists of arguments are not formatted
+        assert!(log("log(string,uint256)", &[s("Hi"), Token::Uint(1337.into())]).is_none());
+        assert!(log("log(string)", &[s("100%%")]).is_none());
+        assert!(log("log(uint256,string)", &[Token::Uint(1.into()), s("%s")]).is_none());
+    }
+
+    #[test]
+    fn keeps_the_latest_block_hashes() {
+        let mut hashes = BlockHashes::default();
+        let forked = H256::repeat_byte(1);
+        hashes.insert(100.into(), forked);
+        hashes.advance(100.into(), 110.into());
+        assert_eq!(hashes.get(100.into()), Some(forked));
+        assert_eq!(hashes.get(109.into()), Some(synthetic_block_hash(109.into())));
+        assert_eq!(hashes.get(110.into()), None);
+
+        // only the hashes of the latest blocks are kept
+        hashes.advance(110.into(), 400.into());
+        assert_eq!(hashes.hashes.len(), BLOCK_HASH_WINDOW as usize);
+        assert_eq!(hashes.get(100.into()), None);
+        assert_eq!(hashes.get(144.into()), Some(synthetic_block_hash(144.into())));
+        assert_eq!(hashes.get(143.into()), None);
+    }
 }
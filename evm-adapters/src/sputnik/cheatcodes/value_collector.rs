@@ -0,0 +1,62 @@
+//! Collects the constants which are pushed or compared during execution, e.g. as a dictionary
+//! for the fuzzer
+use super::ExecutionHook;
+use ethers::types::{Address, H256, U256};
+use sputnik::{Memory, Opcode, Stack};
+use std::collections::BTreeSet;
+
+/// Records the distinct values of all `PUSH` instructions and the operands of all comparisons,
+/// up to a maximum number of values.
+///
+/// Once the maximum is reached, new values are dropped, so the collected values are the first
+/// ones encountered.
+#[derive(Debug, Clone, Default)]
+pub struct ValueCollector {
+    values: BTreeSet<U256>,
+    max_values: usize,
+    /// The address and the expected stack size after the `PUSH` which is executed, the pushed
+    /// value is only on the stack at the next step
+    pending_push: Option<(Address, usize)>,
+}
+
+impl ValueCollector {
+    pub fn new(max_values: usize) -> Self {
+        Self { max_values, ..Default::default() }
+    }
+
+    /// The collected values in ascending order
+    pub fn values(&self) -> Vec<U256> {
+        self.values.iter().copied().collect()
+    }
+
+    fn insert(&mut self, value: H256) {
+        if self.values.len() < self.max_values {
+            self.values.insert(U256::from_big_endian(value.as_bytes()));
+        }
+    }
+}
+
+impl ExecutionHook for ValueCollector {
+    fn step(&mut self, address: Address, _pc: usize, op: Opcode, stack: &Stack, _memory: &Memory) {
+        if let Some((push_address, len)) = self.pending_push.take() {
+            // the push was the last instruction of the frame if we're in another one now
+            if push_address == address && stack.len() == len {
+                if let Ok(value) = stack.peek(0) {
+                    self.insert(value);
+                }
+            }
+        }
+
+        match op {
+            Opcode::EQ | Opcode::LT | Opcode::GT | Opcode::SLT | Opcode::SGT => {
+                for i in 0..2 {
+                    if let Ok(value) = stack.peek(i) {
+                        self.insert(value);
+                    }
+                }
+            }
+            op if op.is_push().is_some() => self.pending_push = Some((address, stack.len() + 1)),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Coverage of the executed bytecode
+use ethers::types::H256;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How many times each program counter of a bytecode was executed
+pub type HitMap = BTreeMap<usize, u64>;
+
+/// Whether the bytecode was executed while deploying a contract or when calling it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum CodeKind {
+    Creation,
+    Runtime,
+}
+
+/// The program counters which were executed, per bytecode hash.
+///
+/// Mapping the program counters to source locations with the source maps of the contracts gives
+/// the line and branch coverage. The hits accumulate until the map is cleared, so they can be
+/// collected over many calls or fuzz runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverageMap {
+    /// Hits of the init code of deployments, by the hash of the init code
+    pub creation: BTreeMap<H256, HitMap>,
+    /// Hits of the code of deployed contracts, by the hash of the code
+    pub runtime: BTreeMap<H256, HitMap>,
+}
+
+impl CoverageMap {
+    /// Records an execution of the opcode at `pc`
+    pub fn hit(&mut self, kind: CodeKind, code_hash: H256, pc: usize) {
+        *self.hits_mut(kind).entry(code_hash).or_default().entry(pc).or_default() += 1;
+    }
+
+    /// The hits of the bytecode with the given hash, if it was executed at all
+    pub fn hits(&self, kind: CodeKind, code_hash: H256) -> Option<&HitMap> {
+        match kind {
+            CodeKind::Creation => self.creation.get(&code_hash),
+            CodeKind::Runtime => self.runtime.get(&code_hash),
+        }
+    }
+
+    /// Adds the hits of another map, e.g. of another executor
+    pub fn merge(&mut self, other: &CoverageMap) {
+        for (kind, bytecodes) in
+            [(CodeKind::Creation, &other.creation), (CodeKind::Runtime, &other.runtime)]
+        {
+            for (code_hash, hits) in bytecodes {
+                let merged = self.hits_mut(kind).entry(*code_hash).or_default();
+                for (pc, count) in hits {
+                    *merged.entry(*pc).or_default() += count;
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.creation.is_empty() && self.runtime.is_empty()
+    }
+
+    fn hits_mut(&mut self, kind: CodeKind) -> &mut BTreeMap<H256, HitMap> {
+        match kind {
+            CodeKind::Creation => &mut self.creation,
+            CodeKind::Runtime => &mut self.runtime,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! A type-erased backend, so that any state source can be used in place of the backends which are
+//! built from the [EvmOpts](crate::evm_opts::EvmOpts)
+use ethers::types::{H160, H256, U256};
+use sputnik::backend::{Backend, Basic};
+use std::{fmt, sync::Arc};
+
+/// A [Backend] over any other backend, e.g. a database of historical state or a test double.
+///
+/// Like with the builtin backends, the state modified during execution lives in the executor's
+/// state on top of this backend, so all cheatcodes and tracing work as usual. Clones share the
+/// inner backend.
+#[derive(Clone)]
+pub struct CustomBackend {
+    inner: Arc<dyn Backend + Send + Sync>,
+}
+
+impl CustomBackend {
+    pub fn new(backend: impl Backend + Send + Sync + 'static) -> Self {
+        Self { inner: Arc::new(backend) }
+    }
+}
+
+impl fmt::Debug for CustomBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomBackend").finish_non_exhaustive()
+    }
+}
+
+impl Backend for CustomBackend {
+    fn gas_price(&self) -> U256 {
+        self.inner.gas_price()
+    }
+    fn origin(&self) -> H160 {
+        self.inner.origin()
+    }
+    fn block_hash(&self, number: U256) -> H256 {
+        self.inner.block_hash(number)
+    }
+    fn block_number(&self) -> U256 {
+        self.inner.block_number()
+    }
+    fn block_coinbase(&self) -> H160 {
+        self.inner.block_coinbase()
+    }
+    fn block_timestamp(&self) -> U256 {
+        self.inner.block_timestamp()
+    }
+    fn block_difficulty(&self) -> U256 {
+        self.inner.block_difficulty()
+    }
+    fn block_gas_limit(&self) -> U256 {
+        self.inner.block_gas_limit()
+    }
+    fn block_base_fee_per_gas(&self) -> U256 {
+        self.inner.block_base_fee_per_gas()
+    }
+    fn chain_id(&self) -> U256 {
+        self.inner.chain_id()
+    }
+    fn exists(&self, address: H160) -> bool {
+        self.inner.exists(address)
+    }
+    fn basic(&self, address: H160) -> Basic {
+        self.inner.basic(address)
+    }
+    fn code(&self, address: H160) -> Vec<u8> {
+        self.inner.code(address)
+    }
+    fn storage(&self, address: H160, index: H256) -> H256 {
+        self.inner.storage(address, index)
+    }
+    fn original_storage(&self, address: H160, index: H256) -> Option<H256> {
+        self.inner.original_storage(address, index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sputnik::{
+        helpers::{new_backend, vm_with_backend, VICINITY},
+        SputnikExecutor,
+    };
+    use sputnik::backend::MemoryAccount;
+
+    #[test]
+    fn can_execute_against_custom_backend() {
+        let alice = H160::repeat_byte(1);
+        let mut state = std::collections::BTreeMap::new();
+        state.insert(alice, MemoryAccount { balance: 10.into(), ..Default::default() });
+        let backend = CustomBackend::new(new_backend(&*VICINITY, state));
+
+        let mut evm = vm_with_backend(backend);
+        assert_eq!(evm.executor.state().basic(alice).balance, 10.into());
+        evm.deal(alice, 20.into());
+        assert_eq!(evm.executor.state().basic(alice).balance, 20.into());
+        assert_eq!(evm.executor.state().backend.backend.basic(alice).balance, 10.into());
+    }
+}
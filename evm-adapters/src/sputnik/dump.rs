@@ -0,0 +1,364 @@
+//! Serializable dumps of the executor state
+use super::{cheatcodes::memory_stackstate_owned::MemoryStackStateOwned, MemCache};
+use crate::{AccountChange, StateChanges};
+
+use ethers::types::{Address, Bytes, H256, U256};
+use eyre::WrapErr;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sputnik::{
+    backend::{Apply, Backend, MemoryAccount},
+    executor::stack::{MemoryStackState, StackState},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    str::FromStr,
+};
+
+/// A dump of accounts and their storage, e.g. to share a minimal reproduction of a failing
+/// execution.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateDump {
+    pub accounts: BTreeMap<Address, AccountDump>,
+    /// Accounts which were deleted locally, so they shadow the remote state
+    #[serde(skip)]
+    deleted: BTreeSet<Address>,
+}
+
+/// A single account of a [StateDump]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountDump {
+    pub balance: U256,
+    pub nonce: U256,
+    pub code: Bytes,
+    pub storage: BTreeMap<H256, H256>,
+    /// Whether the storage was reset locally, so the remote storage is discarded
+    #[serde(skip)]
+    reset_storage: bool,
+}
+
+impl StateDump {
+    /// Dumps the accounts of the cache, e.g. the remote state fetched by a forked backend
+    pub fn from_cache(cache: &MemCache) -> Self {
+        let accounts = cache
+            .iter()
+            .map(|(address, acc)| {
+                let account = AccountDump {
+                    balance: acc.balance,
+                    nonce: acc.nonce,
+                    code: acc.code.clone().into(),
+                    storage: acc.storage.clone(),
+                    reset_storage: false,
+                };
+                (*address, account)
+            })
+            .collect();
+        Self { accounts, deleted: Default::default() }
+    }
+
+    /// Adds all remote accounts of the cache which were read during execution, the local
+    /// accounts of this dump take precedence.
+    pub fn with_remote(self, cache: &MemCache) -> Self {
+        let mut dump = Self::from_cache(cache);
+        for address in &self.deleted {
+            dump.accounts.remove(address);
+        }
+        for (address, local) in self.accounts {
+            match dump.accounts.get_mut(&address) {
+                Some(remote) if !local.reset_storage => {
+                    remote.storage.extend(local.storage);
+                    remote.balance = local.balance;
+                    remote.nonce = local.nonce;
+                    remote.code = local.code;
+                }
+                _ => {
+                    dump.accounts.insert(address, local);
+                }
+            }
+        }
+        dump.deleted = self.deleted;
+        dump
+    }
+
+    /// All accounts of the dump, including the deleted ones as empty accounts
+    pub fn into_accounts(self) -> impl Iterator<Item = (Address, AccountDump)> {
+        let deleted = self.deleted.into_iter().map(|address| (address, AccountDump::default()));
+        self.accounts.into_iter().chain(deleted)
+    }
+
+    /// Writes the dump as json to the given file
+    pub fn write(&self, path: impl AsRef<Path>) -> eyre::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Reads a dump written by [StateDump::write] or the allocation of a genesis file.
+    ///
+    /// Numbers may be 0x-prefixed hex or decimal. Genesis files can be passed as is, all fields
+    /// besides the `alloc` are ignored.
+    pub fn load(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read state file {:?}", path))?;
+        let mut value = match serde_json::from_str(&content)? {
+            Value::Object(value) => value,
+            _ => eyre::bail!("expected {:?} to contain an object", path),
+        };
+        let alloc = match value.remove("alloc").or_else(|| value.remove("accounts")) {
+            Some(Value::Object(alloc)) => alloc,
+            Some(_) => eyre::bail!("expected the accounts of {:?} to be an object", path),
+            None => value,
+        };
+
+        let mut dump = Self::default();
+        for (address, account) in alloc {
+            let account = parse_account(&account)
+                .wrap_err_with(|| format!("invalid account {} in {:?}", address, path))?;
+            let address = Address::from_str(&address)
+                .wrap_err_with(|| format!("invalid address {} in {:?}", address, path))?;
+            dump.accounts.insert(address, account);
+        }
+        Ok(dump)
+    }
+
+    /// Converts the dump into accounts, e.g. to seed a backend with
+    pub fn into_cache(self) -> MemCache {
+        self.accounts
+            .into_iter()
+            .map(|(address, acc)| {
+                let account = MemoryAccount {
+                    nonce: acc.nonce,
+                    balance: acc.balance,
+                    storage: acc.storage,
+                    code: acc.code.to_vec(),
+                };
+                (address, account)
+            })
+            .collect()
+    }
+
+    /// Writes the accounts into the state, on top of its backend
+    pub(crate) fn apply_to<B: Backend>(&self, state: &mut MemoryStackStateOwned<'_, B>) {
+        for address in &self.deleted {
+            state.reset_balance(*address);
+            state.set_code(*address, vec![]);
+            state.reset_storage(*address);
+            state.set_nonce(*address, U256::zero());
+        }
+        for (address, account) in &self.accounts {
+            let address = *address;
+            state.reset_balance(address);
+            state.deposit(address, account.balance);
+            state.set_nonce(address, account.nonce);
+            state.set_code(address, account.code.to_vec());
+            if account.reset_storage {
+                state.reset_storage(address);
+            }
+            for (slot, value) in &account.storage {
+                state.set_storage(address, *slot, *value);
+            }
+        }
+    }
+
+    /// The changes from the `before` state to the accounts of this dump. Accounts and slots
+    /// which have the same value as before are left out, so a dump of all changes made during
+    /// execution gives the changes of the last call.
+    pub(crate) fn changes_since<B: Backend>(&self, before: &B) -> StateChanges {
+        let mut changes = StateChanges::default();
+        for address in &self.deleted {
+            let basic = before.basic(*address);
+            if is_empty(before, *address) {
+                continue
+            }
+            let change = AccountChange {
+                destroyed: true,
+                balance: (!basic.balance.is_zero()).then(|| (basic.balance, U256::zero())),
+                nonce: (!basic.nonce.is_zero()).then(|| (basic.nonce, U256::zero())),
+                ..Default::default()
+            };
+            changes.accounts.insert(*address, change);
+        }
+        for (address, account) in &self.accounts {
+            let basic = before.basic(*address);
+            let storage = account
+                .storage
+                .iter()
+                .filter_map(|(slot, value)| {
+                    let prev = before.storage(*address, *slot);
+                    (prev != *value).then(|| (*slot, (prev, *value)))
+                })
+                .collect();
+            let change = AccountChange {
+                created: is_empty(before, *address) &&
+                    !(account.balance.is_zero() &&
+                        account.nonce.is_zero() &&
+                        account.code.as_ref().is_empty()),
+                destroyed: false,
+                balance: (basic.balance != account.balance)
+                    .then(|| (basic.balance, account.balance)),
+                nonce: (basic.nonce != account.nonce).then(|| (basic.nonce, account.nonce)),
+                storage,
+            };
+            if change != AccountChange::default() {
+                changes.accounts.insert(*address, change);
+            }
+        }
+        changes
+    }
+
+    fn from_applies<B, I>(applies: impl IntoIterator<Item = Apply<I>>, backend: &B) -> Self
+    where
+        B: Backend,
+        I: IntoIterator<Item = (H256, H256)>,
+    {
+        let mut dump = Self::default();
+        for apply in applies {
+            match apply {
+                Apply::Modify { address, basic, code, storage, reset_storage } => {
+                    let code = code.unwrap_or_else(|| backend.code(address));
+                    let account = AccountDump {
+                        balance: basic.balance,
+                        nonce: basic.nonce,
+                        code: code.into(),
+                        storage: storage.into_iter().collect(),
+                        reset_storage,
+                    };
+                    dump.accounts.insert(address, account);
+                }
+                Apply::Delete { address } => {
+                    dump.deleted.insert(address);
+                }
+            }
+        }
+        dump
+    }
+}
+
+/// Whether the account has no balance, nonce or code
+fn is_empty<B: Backend>(backend: &B, address: Address) -> bool {
+    let basic = backend.basic(address);
+    basic.balance.is_zero() && basic.nonce.is_zero() && backend.code(address).is_empty()
+}
+
+/// Parses a number which is either 0x-prefixed hex or decimal, as a string or a json number
+fn parse_u256(value: &Value) -> eyre::Result<U256> {
+    Ok(match value {
+        Value::Number(num) => {
+            num.as_u64().ok_or_else(|| eyre::eyre!("invalid number {}", num))?.into()
+        }
+        Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16)?,
+            None => U256::from_dec_str(s)?,
+        },
+        _ => eyre::bail!("expected a number, found {}", value),
+    })
+}
+
+fn parse_account(value: &Value) -> eyre::Result<AccountDump> {
+    let field = |name: &str| value.get(name).filter(|value| !value.is_null());
+    let balance = field("balance").map(parse_u256).transpose()?.unwrap_or_default();
+    let nonce = field("nonce").map(parse_u256).transpose()?.unwrap_or_default();
+    let code = match field("code") {
+        Some(Value::String(code)) => hex::decode(code.trim_start_matches("0x"))?.into(),
+        Some(code) => eyre::bail!("expected hex code, found {}", code),
+        None => Default::default(),
+    };
+    let mut storage = BTreeMap::new();
+    if let Some(slots) = field("storage") {
+        let slots = slots.as_object().ok_or_else(|| eyre::eyre!("expected a storage object"))?;
+        for (slot, value) in slots {
+            let slot = parse_u256(&Value::String(slot.clone()))?;
+            storage.insert(H256::from_uint(&slot), H256::from_uint(&parse_u256(value)?));
+        }
+    }
+    Ok(AccountDump { balance, nonce, code, storage, reset_storage: true })
+}
+
+/// A state whose local modifications can be dumped
+pub trait DumpState {
+    /// Dumps all accounts which were touched locally, on top of the backend
+    fn dump_state(&self) -> StateDump;
+}
+
+impl<'backend, 'config, B: Backend> DumpState for MemoryStackState<'backend, 'config, B> {
+    fn dump_state(&self) -> StateDump {
+        let (applies, _) = self.clone().deconstruct();
+        StateDump::from_applies(applies, self)
+    }
+}
+
+impl<'config, B: Backend> DumpState for MemoryStackStateOwned<'config, B> {
+    fn dump_state(&self) -> StateDump {
+        let (applies, _) = self.substate.clone().deconstruct(&self.backend);
+        let mut dump = StateDump::from_applies(applies, self);
+        for (address, nonce) in &self.nonces {
+            if let Some(account) = dump.accounts.get_mut(address) {
+                account.nonce = *nonce;
+            }
+        }
+        dump
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sputnik::helpers::vm;
+    use sputnik::backend::MemoryAccount;
+
+    #[test]
+    fn can_load_genesis_alloc() {
+        let path = std::env::temp_dir().join("evm-adapters-genesis-alloc.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "config": { "chainId": 1337 },
+                "alloc": {
+                    "0x0000000000000000000000000000000000000001": {
+                        "balance": "0x10",
+                        "code": "0x6000",
+                        "storage": { "0x01": "0x02" }
+                    },
+                    "0000000000000000000000000000000000000002": { "balance": "100", "nonce": 3 }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let cache = StateDump::load(&path).unwrap().into_cache();
+        let one = &cache[&Address::from_low_u64_be(1)];
+        assert_eq!(one.balance, 16.into());
+        assert_eq!(one.code, vec![0x60, 0x00]);
+        assert_eq!(one.storage[&H256::from_low_u64_be(1)], H256::from_low_u64_be(2));
+        let two = &cache[&Address::from_low_u64_be(2)];
+        assert_eq!(two.balance, 100.into());
+        assert_eq!(two.nonce, 3.into());
+    }
+
+    #[test]
+    fn can_dump_touched_accounts() {
+        let mut evm = vm();
+        let alice = Address::repeat_byte(1);
+        evm.deal(alice, 10.into());
+
+        let dump = evm.dump_state();
+        assert_eq!(dump.accounts[&alice].balance, 10.into());
+
+        let mut remote = MemCache::default();
+        let bob = Address::repeat_byte(2);
+        remote.insert(alice, MemoryAccount { balance: 1.into(), ..Default::default() });
+        remote.insert(bob, MemoryAccount { balance: 2.into(), ..Default::default() });
+        let dump = dump.with_remote(&remote);
+        assert_eq!(dump.accounts[&alice].balance, 10.into());
+        assert_eq!(dump.accounts[&bob].balance, 2.into());
+
+        let path = std::env::temp_dir().join("evm-adapters-state-dump.json");
+        dump.write(&path).unwrap();
+        assert_eq!(StateDump::load(&path).unwrap().accounts, dump.accounts);
+
+        let json = serde_json::to_string(&dump).unwrap();
+        let decoded: StateDump = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.accounts, dump.accounts);
+    }
+}
@@ -1,276 +1,1874 @@
-use crate::{call_tracing::CallTraceArena, Evm, FAUCET_ACCOUNT};
-use ethers::types::{Address, Bytes, U256};
+use crate::{
+    call_tracing::{
+        chrome_trace, gas_by_contract, reentrant_calls, CallTraceArena, GasAttribution,
+        ReentrancyEvent, TraceFormatConfig,
+    },
+    compute_create2_address,
+    gas_report::GasReport,
+    init_code_with_args, AssertionFailure, BundleResult, Call, CallResult, DeployResult, Evm,
+    EvmError, FailurePolicy, ReadOnlyViolation, RevertExpectation, SetupResult,
+    ASSUME_MAGIC_RETURN_CODE, FAUCET_ACCOUNT,
+};
+use ethers::{
+    abi::{Abi, Token},
+    core::k256::ecdsa::SigningKey,
+    solc::EvmVersion,
+    types::{
+        transaction::eip2930::{AccessList, AccessListItem},
+        Address, BlockId, BlockNumber, Bytes, NameOrAddress, Transaction, TransactionRequest, H256,
+        I256, U256,
+    },
+    utils::{keccak256, secret_key_to_address},
+};
 
-use crate::sputnik::cheatcodes::debugger::DebugArena;
+use crate::sputnik::{
+    cheatcodes::{
+        debugger::{ContractSources, DebugArena, GasBreakdown},
+        memory_stackstate_owned::MemoryStackStateOwned,
+    },
+    take_budget_error,
+};
 
 use sputnik::{
-    backend::{Backend, MemoryAccount},
+    backend::{Backend, Log, MemoryAccount},
     executor::stack::{
         MemoryStackState, PrecompileSet, StackExecutor, StackState, StackSubstateMetadata,
     },
-    Config, CreateScheme, ExitReason, ExitRevert, Transfer,
+    gasometer::{self, Gasometer},
+    Config, CreateScheme, ExitError, ExitReason, ExitRevert, Transfer,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
 };
-use std::{collections::BTreeMap, marker::PhantomData};
 
 use eyre::Result;
 
-use super::SputnikExecutor;
+use super::{
+    delegated_address, delegation_code, spec_config, AccountDump, Authorization, CoverageMap,
+    DumpState, ExecutionLimits, SkippedAuthorization, SputnikExecutor, StateDump,
+};
 
 pub type MemoryState = BTreeMap<Address, MemoryAccount>;
 
+/// The balance of the accounts made by [Executor::make_account], `2^96 - 1` like the default
+/// initial balance of the test contracts
+pub const DEFAULT_ACCOUNT_BALANCE: U256 = U256([u64::MAX, u32::MAX as u64, 0, 0]);
+
+/// The reason calls to the cheatcode address revert with in strict mode, see
+/// [Executor::with_cheatcode_strict]
+pub const CHEATCODES_DISABLED: &str =
+    "cheatcodes are not enabled, the executor has to be created with `new_with_cheatcodes`";
+
+/// Every executor honors the limits which the builders set, only spec overrides can be rejected
+const LIMITS_HONORED: &str = "only spec overrides can be rejected";
+
+/// The data of a revert with `Error(reason)`
+fn revert_data(reason: &str) -> Vec<u8> {
+    let mut data = crate::RevertReason::ERROR_SELECTOR.to_vec();
+    data.extend(ethers::abi::encode(&[Token::String(reason.to_string())]));
+    data
+}
+
+/// Code which reverts every call with `Error(reason)`
+fn revert_code(reason: &str) -> Vec<u8> {
+    let data = revert_data(reason);
+    let [hi, lo] = (data.len() as u16).to_be_bytes();
+    // CODECOPY the data after these 15 bytes of code to memory and REVERT with it
+    let mut code =
+        vec![0x61, hi, lo, 0x61, 0x00, 0x0f, 0x60, 0x00, 0x39, 0x61, hi, lo, 0x60, 0x00, 0xfd];
+    code.extend(data);
+    code
+}
+
+/// Converts the access list into the accounts and slots sputnik warms up before a transaction
+fn access_list_items(access_list: &AccessList) -> Vec<(Address, Vec<H256>)> {
+    access_list.0.iter().map(|item| (item.address, item.storage_keys.clone())).collect()
+}
+
+/// Returns the intrinsic gas of a transaction with the calldata, or the init code if it's a
+/// contract creation, and the access list.
+///
+/// Sputnik charges it on top of the gas limit the call of a transaction is executed with, so it
+/// has to be subtracted from the gas limit of a transaction first.
+pub fn intrinsic_gas(
+    config: &Config,
+    data: &[u8],
+    access_list: &[(Address, Vec<H256>)],
+    create: bool,
+) -> Result<u64> {
+    let cost = if create {
+        gasometer::create_transaction_cost(data, access_list)
+    } else {
+        gasometer::call_transaction_cost(data, access_list)
+    };
+    let mut gasometer = Gasometer::new(u64::MAX, config);
+    gasometer
+        .record_transaction(cost)
+        .map_err(|err| eyre::eyre!("invalid transaction: {:?}", err))?;
+    Ok(gasometer.total_used_gas())
+}
+
+/// The outcome of a transaction executed with [Executor::execute_transaction]
+#[derive(Debug, Clone)]
+pub struct ReplayedTransaction {
+    /// The outcome of the transaction, the output of a contract creation is empty
+    pub result: CallResult,
+    /// The address of the deployed contract, if the transaction is a contract creation
+    pub created: Option<Address>,
+    /// The call trace of the transaction, empty if the executor can't trace
+    pub trace: CallTraceArena,
+}
+
+/// A checkpoint taken by [Executor::snapshot]
+struct Snapshot<S> {
+    state: S,
+    /// The number of emitted logs and `console.log` lines when the snapshot was taken
+    logs: (usize, usize),
+}
+
 // TODO: Check if we can implement this as the base layer of an ethers-provider
 // Middleware stack instead of doing RPC calls.
 /// Wrapper around Sputnik Executors which implements the [`Evm`] trait.
 pub struct Executor<S, E> {
     pub executor: E,
     pub gas_limit: u64,
+    sender: Address,
+    /// The ABI to decode the logs and custom errors of calls with
+    abi: Option<Abi>,
+    /// State checkpoints, indexed by their snapshot id
+    snapshots: Vec<Snapshot<S>>,
+    /// The gas used by all calls and deployments, if enabled
+    gas_report: Option<GasReport>,
+    /// Whether calls and deployments are limited to the `gas_limit`
+    gas_metering: bool,
+    /// The sender before an account was impersonated
+    impersonated_from: Option<Address>,
+    /// The logs of all calls and deployments since the logs were last cleared
+    emitted_logs: Vec<Log>,
+    /// The `console.log` output of all calls and deployments since the logs were last cleared
+    console_logs: Vec<String>,
+    /// The balance of the accounts made by [make_account](Self::make_account)
+    account_balance: U256,
+    /// The seeds of the accounts made by [make_account](Self::make_account)
+    made_accounts: BTreeMap<Address, u64>,
+    /// Whether transactions from accounts with code are rejected, see EIP-3607
+    eip3607: bool,
+    /// Whether calls and deployments which modify the state are rejected
+    read_only: bool,
+    /// Whether the gas used is free for the sender, regardless of the gas price
+    free_gas: bool,
     marker: PhantomData<S>,
 }
 
 impl<S, E> Executor<S, E> {
     /// Instantiates the executor given a Sputnik instance.
     pub fn from_executor(executor: E, gas_limit: u64) -> Self {
-        Self { executor, gas_limit, marker: PhantomData }
+        Self {
+            executor,
+            gas_limit,
+            sender: Address::zero(),
+            abi: None,
+            snapshots: vec![],
+            gas_report: None,
+            gas_metering: true,
+            impersonated_from: None,
+            emitted_logs: vec![],
+            console_logs: vec![],
+            account_balance: DEFAULT_ACCOUNT_BALANCE,
+            made_accounts: Default::default(),
+            eip3607: true,
+            read_only: false,
+            free_gas: false,
+            marker: PhantomData,
+        }
+    }
+
+    /// Sets the caller of the calls which are made without an explicit caller, like
+    /// [Evm::setup] and [Evm::failed]
+    #[must_use]
+    pub fn with_sender(mut self, sender: Address) -> Self {
+        self.sender = sender;
+        self
+    }
+
+    /// Changes the caller of the calls which are made without an explicit caller, e.g. between
+    /// calls
+    pub fn set_sender(&mut self, sender: Address) {
+        self.sender = sender;
+    }
+
+    /// Sets the ABI which is used to decode the logs and custom errors of a
+    /// [call_result](Self::call_result), e.g. the merged ABIs of all deployed contracts or an ABI
+    /// parsed from human readable event signatures
+    #[must_use]
+    pub fn with_abi(mut self, abi: Abi) -> Self {
+        self.abi = Some(abi);
+        self
+    }
+
+    /// Sets the balance the accounts made by [make_account](Self::make_account) are funded with,
+    /// defaults to [DEFAULT_ACCOUNT_BALANCE]
+    #[must_use]
+    pub fn with_account_balance(mut self, balance: U256) -> Self {
+        self.account_balance = balance;
+        self
+    }
+
+    /// Sets whether transactions from accounts with code are accepted, e.g. to
+    /// [impersonate](Self::impersonate) a contract on a fork. By default they are rejected like
+    /// on chain since EIP-3607, accounts delegated with EIP-7702 can always send transactions.
+    #[must_use]
+    pub fn with_disable_eip3607(mut self, disable: bool) -> Self {
+        self.eip3607 = !disable;
+        self
+    }
+
+    /// Rejects all calls and deployments which would modify the state, e.g. to explore a fork
+    /// without polluting the state of the session.
+    ///
+    /// Calls are executed in a static context, so that they fail with a [ReadOnlyViolation]
+    /// naming the first write, which includes emitting logs like for a `STATICCALL`. The nonce
+    /// of the caller isn't increased and no fees are charged. Snapshots, reads and the methods
+    /// which discard their changes anyway, like [eth_call](Self::eth_call), work as usual, and
+    /// the state can still be set up directly, e.g. with [deal](Self::deal). Executors without
+    /// cheatcodes don't support static calls, so all their calls fail.
+    #[must_use]
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets whether calls and deployments are limited to the `gas_limit`, see
+    /// [set_gas_metering](Self::set_gas_metering)
+    #[must_use]
+    pub fn with_gas_metering(mut self, enabled: bool) -> Self {
+        self.gas_metering = enabled;
+        self
+    }
+
+    /// Lets the following calls and deployments use all the gas the executor has left instead of
+    /// the `gas_limit`, e.g. for scripts which shouldn't need to guess a gas limit. Once enabled
+    /// again, calls are limited to the `gas_limit` again.
+    ///
+    /// Sputnik still meters the gas, so calls fail if they use more than the executor was created
+    /// with. The gas used is reported as usual, but it is meaningless for gas estimates while
+    /// metering is disabled.
+    pub fn set_gas_metering(&mut self, enabled: bool) {
+        self.gas_metering = enabled;
+    }
+
+    /// Sets whether calls and deployments are simulated for free, e.g. to simulate transactions
+    /// against a fork without funding their senders.
+    ///
+    /// No fees are deducted from the sender and none are paid to the coinbase, as if the gas
+    /// price was zero, and a sender which couldn't pay for the gas isn't an issue either. Unlike
+    /// disabling [gas metering](Self::with_gas_metering), calls are still limited to the
+    /// `gas_limit` and the gas used is reported accurately. The `GASPRICE` and `BASEFEE` opcodes
+    /// still return the configured prices.
+    #[must_use]
+    pub fn with_free_gas(mut self, free: bool) -> Self {
+        self.free_gas = free;
+        self
+    }
+
+    /// The logs emitted since [clear_logs](Self::clear_logs) was last called which match the
+    /// filter, like `eth_getLogs` does: the log has to be emitted by the `address` if it is
+    /// given, and its topics have to match the `topics` which are given at their position.
+    ///
+    /// Logs of reverted calls are not included. Executors without cheatcodes don't record any
+    /// logs.
+    pub fn logs_matching(&self, address: Option<Address>, topics: &[Option<H256>]) -> Vec<Log> {
+        self.emitted_logs
+            .iter()
+            .filter(|log| address.map_or(true, |address| log.address == address))
+            .filter(|log| {
+                topics.len() <= log.topics.len() &&
+                    topics
+                        .iter()
+                        .zip(&log.topics)
+                        .all(|(expected, topic)| expected.map_or(true, |t| t == *topic))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The `console.log` output of all calls and deployments since
+    /// [clear_logs](Self::clear_logs) was last called, in the order they were logged.
+    ///
+    /// Calls to the console address always succeed without doing anything else, and the logged
+    /// values are decoded like hardhat does, including format strings like
+    /// `console.log("%s has %d tokens", account, amount)`. Unlike the logs of a single call,
+    /// this includes the output of reverted calls. Executors without cheatcodes don't record any
+    /// output.
+    pub fn console_logs(&self) -> &[String] {
+        &self.console_logs
+    }
+
+    /// Discards the logs collected so far, e.g. between phases of a test
+    pub fn clear_logs(&mut self) {
+        self.emitted_logs.clear();
+        self.console_logs.clear();
+    }
+
+    /// The gas limit of the next call or deployment
+    fn tx_gas_limit(&self) -> u64 {
+        if self.gas_metering {
+            self.gas_limit
+        } else {
+            // the executor caps this at the gas it has left
+            u64::MAX
+        }
     }
 }
 
-// Concrete implementation over the in-memory backend without cheatcodes
-impl<'a, 'b, B: Backend, P: PrecompileSet>
-    Executor<MemoryStackState<'a, 'a, B>, StackExecutor<'a, 'b, MemoryStackState<'a, 'a, B>, P>>
-{
-    /// Given a gas limit, vm version, initial chain configuration and initial state
-    // TODO: See if we can make lifetimes better here
-    pub fn new(gas_limit: u64, config: &'a Config, backend: &'a B, precompiles: &'b P) -> Self {
-        // setup gasometer
-        let metadata = StackSubstateMetadata::new(gas_limit, config);
-        // setup state
-        let state = MemoryStackState::new(metadata, backend);
-        // setup executor
-        let executor = StackExecutor::new_with_precompiles(state, config, precompiles);
+impl<S: Clone, E: SputnikExecutor<S>> Executor<S, E> {
+    /// Checkpoints the current state, including the logs, and returns the id of the snapshot.
+    ///
+    /// Only the executor's state is copied, the state fetched by a forked backend is shared by
+    /// all snapshots and doesn't need to be fetched again after reverting. The nonces are part of
+    /// it, so a deployment after reverting gets the same address as the reverted one.
+    pub fn snapshot(&mut self) -> U256 {
+        let logs = (self.emitted_logs.len(), self.console_logs.len());
+        self.snapshots.push(Snapshot { state: self.executor.state().clone(), logs });
+        U256::from(self.snapshots.len() - 1)
+    }
+
+    /// Restores the state of the snapshot with the given id and returns whether it existed.
+    ///
+    /// The logs and `console.log` output collected after the snapshot are discarded, logs which
+    /// were [cleared](Self::clear_logs) in the meantime are not restored. The snapshot itself and
+    /// all snapshots taken after it are discarded.
+    pub fn revert(&mut self, id: U256) -> bool {
+        match self.take_snapshot(id) {
+            Some(Snapshot { state, logs }) => {
+                *self.executor.state_mut() = state;
+                self.emitted_logs.truncate(logs.0);
+                self.console_logs.truncate(logs.1);
+                true
+            }
+            None => false,
+        }
+    }
 
-        Self { executor, gas_limit, marker: PhantomData }
+    /// Removes the snapshot with the given id and all snapshots taken after it, without
+    /// restoring it
+    fn take_snapshot(&mut self, id: U256) -> Option<Snapshot<S>> {
+        if id >= U256::from(self.snapshots.len()) {
+            return None
+        }
+        self.snapshots.truncate(id.as_usize() + 1);
+        self.snapshots.pop()
     }
 }
 
-// Note regarding usage of Generic vs Associated Types in traits:
-//
-// We use StackState as a trait and not as an associated type because we want to
-// allow the developer what the db type should be. Whereas for ReturnReason, we want it
-// to be generic across implementations, but we don't want to make it a user-controlled generic.
-impl<'a, S, E> Evm<S> for Executor<S, E>
-where
-    E: SputnikExecutor<S>,
-    S: StackState<'a>,
-{
-    type ReturnReason = ExitReason;
+impl<S, E: SputnikExecutor<S>> Executor<S, E> {
+    /// Starts recording the gas used by all following calls and deployments, grouped by contract
+    /// and function.
+    ///
+    /// The report is built from the call traces, so this enables tracing. Executors without
+    /// tracing support, like the one without cheatcodes, produce an empty report.
+    pub fn enable_gas_report(&mut self) {
+        self.executor.set_tracing_enabled(true);
+        self.gas_report.get_or_insert_with(Default::default);
+    }
 
-    fn revert() -> Self::ReturnReason {
-        ExitReason::Revert(ExitRevert::Reverted)
+    /// The gas used since [enable_gas_report](Self::enable_gas_report) was called, functions are
+    /// named after the executor's ABI if it knows them.
+    pub fn gas_report(&self) -> GasReport {
+        let mut report = self.gas_report.clone().unwrap_or_default();
+        report.finalize();
+        report
     }
 
-    fn expected_revert(&self) -> Option<&[u8]> {
-        self.executor.expected_revert()
+    /// The index of the trace arena the next transaction records into, if the gas report is
+    /// enabled
+    fn gas_report_start(&self) -> Option<usize> {
+        self.gas_report.as_ref().map(|_| self.executor.traces().len().saturating_sub(1))
     }
 
-    fn is_success(reason: &Self::ReturnReason) -> bool {
-        matches!(reason, ExitReason::Succeed(_))
+    /// Adds the traces recorded since the arena at `start` to the gas report
+    fn record_gas(&mut self, start: Option<usize>) {
+        if let (Some(report), Some(start)) = (&mut self.gas_report, start) {
+            let traces = self.executor.traces();
+            report.analyze_unidentified(traces.get(start..).unwrap_or_default(), self.abi.as_ref());
+        }
     }
 
-    fn is_fail(reason: &Self::ReturnReason) -> bool {
-        !Self::is_success(reason)
+    /// Names the address in traces, like the `label` cheatcode. Labeling an address again
+    /// replaces its previous name. Fails on executors without cheatcodes, which don't keep names.
+    pub fn label_address(&mut self, address: Address, label: String) -> Result<()> {
+        self.executor.set_label(address, label)
     }
 
-    fn reset(&mut self, state: S) {
-        let mut _state = self.executor.state_mut();
-        *_state = state;
+    /// The names of all labeled addresses, e.g. to name the addresses of traces which were
+    /// recorded before they were labeled, see
+    /// [TraceFormatConfig](crate::call_tracing::TraceFormatConfig)
+    pub fn labels(&self) -> BTreeMap<Address, String> {
+        self.executor.labels()
     }
 
-    fn set_tracing_enabled(&mut self, enabled: bool) -> bool {
-        self.executor.set_tracing_enabled(enabled)
+    /// Records which program counters are executed by all following calls and deployments, see
+    /// [coverage](Self::coverage).
+    ///
+    /// Disabling it keeps the coverage recorded so far.
+    #[must_use]
+    pub fn with_coverage(mut self, enabled: bool) -> Self {
+        self.executor.set_coverage_enabled(enabled);
+        self
     }
 
-    fn tracing_enabled(&self) -> bool {
-        self.executor.tracing_enabled()
+    /// Collects the constants which are pushed or compared by all following calls and
+    /// deployments, up to `max_values` distinct ones, see
+    /// [collected_values](Self::collected_values). This is meant to seed the dictionary of a
+    /// fuzzer with the magic values of the contracts under test.
+    #[must_use]
+    pub fn with_value_collector(mut self, max_values: usize) -> Self {
+        self.update_limits(|limits| limits.value_collector = Some(max_values))
+            .expect(LIMITS_HONORED);
+        self
     }
 
-    /// Grabs debug steps
-    #[cfg(feature = "sputnik")]
-    fn debug_calls(&self) -> Vec<DebugArena> {
-        self.executor.debug_calls()
+    /// Limits the memory all open call frames of a transaction may use together, so that
+    /// contracts which expand their memory without bounds fail instead of exhausting the host
+    /// memory, e.g. while fuzzing without gas metering. The frame which exceeds the limit fails
+    /// like it ran out of gas, see [CallResult::memory_limit_exceeded].
+    ///
+    /// Defaults to
+    /// [DEFAULT_MEMORY_LIMIT](crate::sputnik::cheatcodes::cheatcode_handler::DEFAULT_MEMORY_LIMIT).
+    #[must_use]
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.update_limits(|limits| limits.memory_limit = Some(bytes)).expect(LIMITS_HONORED);
+        self
     }
 
-    /// given an iterator of contract address to contract bytecode, initializes
-    /// the state with the contract deployed at the specified address
-    fn initialize_contracts<T: IntoIterator<Item = (Address, Bytes)>>(&mut self, contracts: T) {
-        let state_ = self.executor.state_mut();
-        contracts.into_iter().for_each(|(address, bytecode)| {
-            state_.set_code(address, bytecode.to_vec());
-        })
+    /// Lowers the call depth at which calls and deployments fail, e.g. to test how contracts
+    /// handle running out of call depth without recursing 1024 times. The limit can't be raised
+    /// above the `call_stack_limit` of the config, which is 1024 like on chain. Exceeding it
+    /// fails the offending call, see [CallResult::call_depth_exceeded].
+    #[must_use]
+    pub fn with_call_depth_limit(mut self, limit: u16) -> Self {
+        self.update_limits(|limits| limits.call_depth_limit = Some(limit.into()))
+            .expect(LIMITS_HONORED);
+        self
     }
 
-    fn set_balance(&mut self, address: Address, balance: U256) {
-        self.executor
-            .state_mut()
-            .transfer(Transfer { source: *FAUCET_ACCOUNT, target: address, value: balance })
-            .expect("could not transfer funds")
+    /// Sets whether deployed code may exceed the `create_contract_limit` of the config, e.g. to
+    /// test contracts which are split up before going on chain. By default code larger than
+    /// 24KB fails to deploy like on chain since EIP-170, see [EvmError::ContractSizeLimit].
+    #[must_use]
+    pub fn with_disable_contract_size_limit(mut self, disable: bool) -> Self {
+        self.update_limits(|limits| limits.contract_size_limit_disabled = disable)
+            .expect(LIMITS_HONORED);
+        self
     }
 
-    fn state(&self) -> &S {
-        self.executor.state()
+    /// Changes the limits of the executor which take precedence over the ones of its config,
+    /// fails if the executor can't honor them
+    fn update_limits(&mut self, update: impl FnOnce(&mut ExecutionLimits)) -> Result<()> {
+        let mut limits = self.executor.execution_limits();
+        update(&mut limits);
+        self.executor.set_execution_limits(limits)
     }
 
-    fn code(&self, address: Address) -> Vec<u8> {
-        self.executor.state().code(address)
+    /// Stops recording calls and logs in the traces until [resume_tracing](Self::resume_tracing)
+    /// is called, e.g. to only trace the interesting part of a large test. Calls which start
+    /// while tracing is paused are not traced, even if tracing resumes within them.
+    pub fn pause_tracing(&mut self) {
+        self.executor.set_tracing_paused(true);
     }
 
-    fn traces(&self) -> Vec<CallTraceArena> {
-        self.executor.traces()
+    /// Resumes tracing after [pause_tracing](Self::pause_tracing)
+    pub fn resume_tracing(&mut self) {
+        self.executor.set_tracing_paused(false);
     }
 
-    fn reset_traces(&mut self) {
-        self.executor.reset_traces()
+    /// The program counters executed since coverage was enabled, accumulated over all calls and
+    /// deployments
+    pub fn coverage(&self) -> CoverageMap {
+        self.executor.coverage()
     }
 
-    fn all_logs(&self) -> Vec<String> {
-        self.executor.all_logs()
+    /// The distinct values collected by the [value collector](Self::with_value_collector), in
+    /// ascending order
+    pub fn collected_values(&self) -> Vec<U256> {
+        self.executor.collected_values()
     }
 
-    /// Deploys the provided contract bytecode
-    fn deploy(
-        &mut self,
-        from: Address,
-        calldata: Bytes,
-        value: U256,
-    ) -> Result<(Address, ExitReason, u64, Vec<String>)> {
-        let gas_used_before = self.executor.gas_used();
-        let refunded_gas_before = self.executor.gas_refund();
+    /// The gas consumed by the last debugged call or deployment, grouped by kind of opcode.
+    ///
+    /// Debug steps are only recorded in debug mode, otherwise the breakdown is empty.
+    pub fn gas_breakdown(&self) -> GasBreakdown {
+        let cold_access = self.executor.config().increase_state_access_gas;
+        self.last_debugged().map(|arena| arena.gas_breakdown(cold_access)).unwrap_or_default()
+    }
 
-        // The account's created contract address is pre-computed by using the account's nonce
-        // before it executes the contract deployment transaction.
-        let address = self.executor.create_address(CreateScheme::Legacy { caller: from });
-        let status =
-            self.executor.transact_create(from, value, calldata.to_vec(), self.gas_limit, vec![]);
+    /// The last debugged call or deployment as the input of a flamegraph weighted by gas, one
+    /// `frame;frame;frame gas` line per stack, e.g. for `inferno-flamegraph`.
+    ///
+    /// The frames are the calls, named by the address and the function of the ABI of the
+    /// executor, or the selector if the ABI doesn't have it. Debug steps are only recorded in
+    /// debug mode, otherwise this is empty.
+    pub fn flamegraph_folded(&self) -> String {
+        self.flamegraph_folded_with(&BTreeMap::new())
+    }
 
-        // get the deployment logs
-        let logs = self.executor.logs();
-        // and clear them
-        self.executor.clear_logs();
+    /// Like [flamegraph_folded](Self::flamegraph_folded), but the calls of the given contracts are
+    /// named after them, and split further into the internal functions their source maps jump
+    /// into
+    pub fn flamegraph_folded_with(&self, sources: &BTreeMap<Address, ContractSources>) -> String {
+        self.last_debugged()
+            .map(|arena| arena.flamegraph_folded(sources, self.abi.as_ref()))
+            .unwrap_or_default()
+    }
 
-        let refunded_gas = self.executor.gas_refund().saturating_sub(refunded_gas_before);
-        let gas_used_after = self.executor.gas_used();
-        // we dont remove call data costs here because its highly relevant to users
-        let gas = gas_used_after
-            .saturating_sub(gas_used_before)
-            .saturating_sub(refunded_gas)
-            .saturating_sub(21000.into());
+    /// All call traces the executor recorded in the Trace Event Format, to view them in
+    /// `chrome://tracing` or Perfetto, see [chrome_trace](crate::call_tracing::chrome_trace).
+    ///
+    /// The frames are named after the labels and the functions of the ABI of the executor. Only
+    /// the calls need to be traced for this, not the steps of debug mode.
+    pub fn export_chrome_trace(&self) -> String {
+        let labels = self.labels();
+        let config = TraceFormatConfig {
+            abi: self.abi.as_ref(),
+            labels: Some(&labels),
+            ..Default::default()
+        };
+        chrome_trace(&self.executor.traces(), &config)
+    }
 
-        if Self::is_fail(&status) {
-            tracing::trace!(?status, "failed");
-            Err(eyre::eyre!("deployment reverted, reason: {:?}", status))
-        } else {
-            tracing::trace!(?status, ?address, ?gas, "success");
-            Ok((address, status, gas.as_u64(), logs))
-        }
+    /// The gas each contract used itself in all traced calls, e.g. to find the contracts of a
+    /// test scenario which are worth optimizing, see
+    /// [gas_by_contract](crate::call_tracing::gas_by_contract).
+    ///
+    /// The gas of a delegatecall is attributed to the contract whose code runs, like the
+    /// implementation behind a proxy. Only calls made while tracing is enabled are counted.
+    pub fn gas_by_contract(&self) -> HashMap<Address, u64> {
+        self.gas_by_contract_with(GasAttribution::Code)
     }
 
-    /// Runs the selected function
-    fn call_raw(
+    /// Like [gas_by_contract](Self::gas_by_contract), with the gas of delegatecalls attributed
+    /// according to `attribution`, e.g. to the proxies they run for
+    pub fn gas_by_contract_with(&self, attribution: GasAttribution) -> HashMap<Address, u64> {
+        gas_by_contract(&self.executor.traces(), attribution)
+    }
+
+    /// The calls which re-entered a contract that was already on the call stack, see
+    /// [reentrant_calls](crate::call_tracing::reentrant_calls). Delegatecalls, e.g. into
+    /// libraries, don't enter a contract. Only calls made while tracing is enabled are searched.
+    pub fn reentrant_calls(&self) -> Vec<ReentrancyEvent> {
+        reentrant_calls(&self.executor.traces())
+    }
+
+    /// The debug steps of the last debugged call or deployment
+    fn last_debugged(&self) -> Option<DebugArena> {
+        self.executor
+            .debug_calls()
+            .into_iter()
+            .rev()
+            .find(|arena| arena.arena.iter().any(|node| !node.steps.is_empty()))
+    }
+}
+
+impl<S: DumpState, E: SputnikExecutor<S>> Executor<S, E> {
+    /// Dumps all accounts which were touched during execution.
+    ///
+    /// When forking, the remote state which was read can be included with
+    /// [StateDump::with_remote].
+    pub fn dump_state(&self) -> StateDump {
+        self.executor.state().dump_state()
+    }
+
+    /// All accounts which were touched during execution, e.g. to check invariants like the sum
+    /// of all balances after every call.
+    ///
+    /// When forking, remote accounts which were never touched locally are not included, and the
+    /// storage of an account only holds the slots which were written locally. Accounts which were
+    /// destroyed are included as empty accounts.
+    pub fn touched_accounts(&self) -> impl Iterator<Item = (Address, AccountDump)> {
+        self.dump_state().into_accounts()
+    }
+}
+
+impl<'a, S: StackState<'a> + DumpState + Clone, E: SputnikExecutor<S>> Executor<S, E> {
+    /// Executes the call like [call_result](Self::call_result) and records the accounts and
+    /// slots it changed in the [state_changes](CallResult::state_changes).
+    ///
+    /// The previous values are read from a copy of the state, so on a fork the previous value of
+    /// a slot which wasn't modified locally is its remote value.
+    pub fn call_with_state_changes(
         &mut self,
         from: Address,
         to: Address,
         calldata: Bytes,
         value: U256,
-        _is_static: bool,
-    ) -> Result<(Bytes, ExitReason, u64, Vec<String>)> {
-        let gas_used_before = self.executor.gas_used();
-        let refunded_gas_before = self.executor.gas_refund();
-
-        let (status, retdata) =
-            self.executor.transact_call(from, to, value, calldata.to_vec(), self.gas_limit, vec![]);
+    ) -> CallResult {
+        let before = self.executor.state().clone();
+        let mut res = self.call_result(from, to, calldata, value);
+        res.state_changes = self.executor.state().dump_state().changes_since(&before);
+        res
+    }
 
-        tracing::trace!(logs_before = ?self.executor.logs());
+    /// Executes the calls in order, each one on top of the state left by the previous ones, e.g.
+    /// to simulate a flashbots bundle.
+    ///
+    /// A call with a [spec_override](Call::spec_override) runs like with
+    /// [call_result_with_spec](Self::call_result_with_spec), and fails without being executed if
+    /// its EVM version is not supported.
+    ///
+    /// If the bundle is `atomic`, it stops at the first call which fails and all changes of the
+    /// bundle, including its logs, traces and gas report, are rolled back to a snapshot taken
+    /// before the first call. Otherwise the following
+    /// calls are still executed and see the state of the calls before, whether they failed or
+    /// not. Either way, [failed_call](BundleResult::failed_call) is the first call which failed.
+    pub fn simulate_bundle(&mut self, calls: &[Call], atomic: bool) -> BundleResult {
+        let snapshot = self.snapshot();
+        let gas_report = self.gas_report.clone();
+        let mut bundle = BundleResult::default();
+        for (i, call) in calls.iter().enumerate() {
+            let res = self.execute_call(call);
+            let failed = !res.success;
+            bundle.results.push(res);
+            if failed && bundle.failed_call.is_none() {
+                bundle.failed_call = Some(i);
+                if atomic {
+                    // the traces are part of the state, so they are rolled back with it
+                    self.revert(snapshot);
+                    self.gas_report = gas_report;
+                    bundle.rolled_back = true;
+                    return bundle
+                }
+            }
+        }
+        let before = self.take_snapshot(snapshot).expect("snapshot exists").state;
+        bundle.state_changes = self.executor.state().dump_state().changes_since(&before);
+        bundle
+    }
+}
 
-        let refunded_gas = self.executor.gas_refund().saturating_sub(refunded_gas_before);
-        let gas_used_after = self.executor.gas_used();
-        // remove base and calldata costs
-        let gas = foundry_utils::remove_extra_costs(
-            gas_used_after.saturating_sub(gas_used_before).saturating_sub(refunded_gas),
-            calldata.as_ref(),
-        );
+impl<'a, S: StackState<'a>, E: SputnikExecutor<S>> Executor<S, E> {
+    /// Makes calls to the cheatcode address revert with [CHEATCODES_DISABLED] if the executor
+    /// doesn't handle cheatcodes, instead of succeeding like calls to any empty account.
+    ///
+    /// This installs code at the cheatcode address, executors created with
+    /// `new_with_cheatcodes` are not affected.
+    #[must_use]
+    pub fn with_cheatcode_strict(mut self) -> Self {
+        if !self.executor.cheatcodes_enabled() {
+            let address = *super::cheatcodes::cheatcode_handler::CHEATCODE_ADDRESS;
+            self.executor.state_mut().set_code(address, revert_code(CHEATCODES_DISABLED));
+        }
+        self
+    }
 
-        // get the logs
-        let logs = self.executor.logs();
-        tracing::trace!(logs_after = ?self.executor.logs());
-        // clear them
-        self.executor.clear_logs();
+    /// Labels the well-known contracts of the chain of the executor, like WETH, USDC, the
+    /// Uniswap routers and Multicall3, see [known_labels](crate::labels::known_labels).
+    ///
+    /// Addresses which are already labeled keep their names, and the known labels can be
+    /// replaced later with [with_labels](Self::with_labels) or
+    /// [label_address](Self::label_address). Executors without cheatcodes don't keep labels, so
+    /// this only warns on them.
+    #[must_use]
+    pub fn with_known_labels(self) -> Self {
+        let labels = self.labels();
+        // the `chainId` cheatcode can set ids which don't fit into a u64
+        let chain_id = self.executor.state().chain_id().low_u64();
+        let known = crate::labels::known_labels(chain_id);
+        self.with_labels(known.into_iter().filter(|(address, _)| !labels.contains_key(address)))
+    }
 
-        Ok((retdata.into(), status, gas.as_u64(), logs))
+    /// Labels the addresses, replacing their previous names. Executors without cheatcodes don't
+    /// keep labels, so this only warns on them.
+    #[must_use]
+    pub fn with_labels(mut self, labels: impl IntoIterator<Item = (Address, String)>) -> Self {
+        for (address, label) in labels {
+            if let Err(err) = self.label_address(address, label) {
+                tracing::warn!("{}", err);
+                break
+            }
+        }
+        self
     }
-}
 
-#[cfg(any(test, feature = "sputnik-helpers"))]
-pub mod helpers {
-    use super::*;
-    use ethers::types::H160;
-    use sputnik::backend::{MemoryBackend, MemoryVicinity};
+    /// Sets the balance of the sender set with [with_sender](Self::with_sender), so it has to be
+    /// called after it. The sender defaults to the zero address, which has no balance.
+    ///
+    /// The sender pays for the gas its calls use once a `tx.gasprice` is set. With gas metering
+    /// disabled by [with_gas_metering(false)](Self::with_gas_metering), a call can use all the
+    /// gas the executor has left instead of the `gas_limit`, so the balance has to cover that.
+    #[must_use]
+    pub fn with_sender_balance(mut self, balance: U256) -> Self {
+        self.deal(self.sender, balance);
+        self
+    }
 
-    use crate::{
-        fuzz::FuzzedExecutor,
-        sputnik::{
-            cheatcodes::cheatcode_handler::{CheatcodeStackExecutor, CheatcodeStackState},
-            PrecompileFn, PRECOMPILES_MAP,
-        },
-    };
-    use once_cell::sync::Lazy;
+    /// Writes the storage slots of the account, e.g. to restore the state of a contract which was
+    /// captured elsewhere together with its code set by `etch`.
+    ///
+    /// Only the given slots are written. On a fork they shadow the remote slots, which are never
+    /// fetched, and all other slots are still read from the remote state.
+    #[must_use]
+    pub fn with_storage_overrides(mut self, address: Address, slots: HashMap<U256, U256>) -> Self {
+        let state = self.executor.state_mut();
+        for (slot, value) in slots {
+            state.set_storage(address, H256::from_uint(&slot), H256::from_uint(&value));
+        }
+        self
+    }
 
-    pub type TestSputnikVM<'a, B> = Executor<
-        // state
-        CheatcodeStackState<'a, B>,
-        // actual stack executor
-        CheatcodeStackExecutor<'a, 'a, B, BTreeMap<Address, PrecompileFn>>,
-    >;
+    /// Sets the balance of the account, leaving its code, nonce and storage untouched
+    pub fn deal(&mut self, address: Address, balance: U256) {
+        let state = self.executor.state_mut();
+        state.reset_balance(address);
+        state.deposit(address, balance);
+    }
 
-    pub static CFG: Lazy<Config> = Lazy::new(Config::london);
+    /// Makes the account the caller of the calls which are made without an explicit caller,
+    /// like [set_sender](Self::set_sender), until [stop_impersonate](Self::stop_impersonate) is
+    /// called. The balance of the account is increased by `top_up`, if given.
+    ///
+    /// The caller of a call doesn't need a private key, so contracts can be impersonated as
+    /// well, e.g. the owner of a forked protocol, once EIP-3607 is disabled with
+    /// [with_disable_eip3607](Self::with_disable_eip3607). The `tx.origin` of the calls stays the
+    /// origin of the backend though.
+    ///
+    /// The impersonated account pays the fees of its calls at the gas price, so it needs the
+    /// balance to cover them. If it can't pay, the fees are not charged at all, which leaves the
+    /// balances of the sender and the coinbase off from what they would be on chain. The fees
+    /// depend on the gas used, not on the gas limit, so disabling gas metering doesn't change
+    /// them, only a gas price of zero does.
+    pub fn impersonate(&mut self, address: Address, top_up: Option<U256>) {
+        if let Some(top_up) = top_up {
+            let balance = self.executor.state().basic(address).balance;
+            self.deal(address, balance.saturating_add(top_up));
+        }
+        self.impersonated_from.get_or_insert(self.sender);
+        self.sender = address;
+    }
 
-    /// London config without a contract size limit. Useful for testing but is a depature from
-    /// mainnet rules.
-    pub static CFG_NO_LMT: Lazy<Config> = Lazy::new(|| {
-        let mut cfg = Config::london();
-        cfg.create_contract_limit = None;
-        cfg
-    });
+    /// Makes a fresh account and returns its address and private key, e.g. to sign with the
+    /// `sign` cheatcode. The account is funded with the balance set by
+    /// [with_account_balance](Self::with_account_balance).
+    ///
+    /// The private key is derived from the seed, so the same seed always makes the same account,
+    /// and making it again only resets its balance. Different seeds never return the same
+    /// account in a run.
+    pub fn make_account(&mut self, seed: u64) -> (Address, H256) {
+        let mut key = keccak256([b"foundry account".as_ref(), &seed.to_be_bytes()].concat());
+        let address = loop {
+            // hashes which are no valid key or collide with another seed are hashed again
+            if let Ok(signer) = SigningKey::from_bytes(&key) {
+                let address = secret_key_to_address(&signer);
+                match self.made_accounts.get(&address) {
+                    Some(made) if *made != seed => {}
+                    _ => break address,
+                }
+            }
+            key = keccak256(key);
+        };
+        self.made_accounts.insert(address, seed);
+        self.deal(address, self.account_balance);
+        (address, H256::from(key))
+    }
 
-    pub static VICINITY: Lazy<MemoryVicinity> = Lazy::new(new_vicinity);
-    pub const GAS_LIMIT: u64 = 30_000_000;
+    /// Restores the sender from before [impersonate](Self::impersonate) was first called, the
+    /// balance of the impersonated account is kept
+    pub fn stop_impersonate(&mut self) {
+        if let Some(sender) = self.impersonated_from.take() {
+            self.sender = sender;
+        }
+    }
 
-    /// Instantiates a Sputnik EVM with enabled cheatcodes + FFI and a simple non-forking in memory
-    /// backend and tracing disabled
-    pub fn vm<'a>() -> TestSputnikVM<'a, MemoryBackend<'a>> {
-        let backend = new_backend(&*VICINITY, Default::default());
-        Executor::new_with_cheatcodes(
-            backend,
-            GAS_LIMIT,
-            &*CFG,
-            &*PRECOMPILES_MAP,
-            true,
-            false,
-            false,
-        )
+    /// The size of the code of the account in bytes, `0` if nothing is deployed at the address,
+    /// e.g. to check whether a `CREATE2` target was already deployed.
+    ///
+    /// Sputnik backends only serve whole accounts, so a forked account is fetched with its code
+    /// the first time, and answered from the cache afterwards.
+    pub fn code_size(&self, address: Address) -> usize {
+        self.executor.state().code(address).len()
     }
 
-    /// Instantiates a Sputnik EVM with enabled cheatcodes + FFI and a simple non-forking in memory
-    /// backend and tracing disabled, and no contract size limit
-    pub fn vm_no_limit<'a>() -> TestSputnikVM<'a, MemoryBackend<'a>> {
-        let backend = new_backend(&*VICINITY, Default::default());
-        Executor::new_with_cheatcodes(
-            backend,
-            GAS_LIMIT,
-            &*CFG_NO_LMT,
-            &*PRECOMPILES_MAP,
-            true,
-            false,
-            false,
-        )
+    /// Whether the account has a balance, a nonce or code
+    pub fn account_exists(&self, address: Address) -> bool {
+        let state = self.executor.state();
+        let basic = state.basic(address);
+        !basic.balance.is_zero() || !basic.nonce.is_zero() || self.code_size(address) > 0
+    }
+
+    /// Replaces the account with the given one, without executing any transaction.
+    ///
+    /// The account is written into the executor's state, so it shadows the account of the
+    /// backend, including the storage of a forked account. Nonces can only be increased.
+    pub fn insert_account(&mut self, address: Address, account: MemoryAccount) {
+        self.deal(address, account.balance);
+        let state = self.executor.state_mut();
+        state.set_code(address, account.code);
+        state.reset_storage(address);
+        for (index, value) in account.storage {
+            state.set_storage(address, index, value);
+        }
+        let nonce = state.basic(address).nonce;
+        for _ in nonce.as_u64()..account.nonce.as_u64() {
+            state.inc_nonce(address);
+        }
+    }
+
+    /// Calls the contract and returns the outcome of the call, unlike [Evm::call_committing] a
+    /// failing call is not an error. Like it, the changes of the call are kept.
+    pub fn call_result(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+    ) -> CallResult {
+        self.call_result_with_access_list(from, to, calldata, value, None)
+    }
+
+    /// Executes the calls in order, e.g. the deployments by factories, transfers and
+    /// configuration calls which set up the environment of a script, and collects their results
+    /// instead of failing on the first one.
+    ///
+    /// With [FailurePolicy::StopOnFirstError] the calls after the first failed one don't run,
+    /// with [FailurePolicy::ContinueAndCollect] they all run and every failure is recorded.
+    /// Calls run like in [simulate_bundle](Self::simulate_bundle), but nothing is rolled back.
+    pub fn run_setup(&mut self, calls: &[Call], policy: FailurePolicy) -> SetupResult {
+        let mut setup = SetupResult::default();
+        for (i, call) in calls.iter().enumerate() {
+            let res = self.execute_call(call);
+            let failed = !res.success;
+            setup.results.push(res);
+            if failed {
+                setup.failed_calls.push(i);
+                if policy == FailurePolicy::StopOnFirstError {
+                    break
+                }
+            }
+        }
+        setup
+    }
+
+    /// Executes the call of a bundle or setup, a call with an unsupported
+    /// [spec_override](Call::spec_override) fails without being executed
+    fn execute_call(&mut self, call: &Call) -> CallResult {
+        match call.spec_override {
+            Some(spec) => self
+                .call_result_with_spec(call.from, call.to, call.calldata.clone(), call.value, spec)
+                .unwrap_or_else(|err| CallResult {
+                    reverted_reason: Some(err.to_string()),
+                    ..Default::default()
+                }),
+            None => self.call_result(call.from, call.to, call.calldata.clone(), call.value),
+        }
+    }
+
+    /// Executes the call like [call_result](Self::call_result), but under the rules of the given
+    /// EVM version instead of the configured ones, e.g. to compare the gas a call uses under
+    /// Berlin and London on the same state.
+    ///
+    /// Only this call runs under the other rules, its changes to the state are kept as usual and
+    /// the following calls run under the configured rules again. The stock config of the version
+    /// is used, so changes made to the configured one, like raising the `call_stack_limit`,
+    /// don't apply to the call. Fails if sputnik doesn't support the version or if the executor
+    /// has no cheatcodes, since only those can run under other rules.
+    pub fn call_result_with_spec(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        spec: EvmVersion,
+    ) -> Result<CallResult> {
+        let config = spec_config(spec)?;
+        self.update_limits(|limits| limits.spec_override = Some(config))?;
+        let res = self.call_result(from, to, calldata, value);
+        self.update_limits(|limits| limits.spec_override = None)?;
+        Ok(res)
+    }
+
+    /// Executes the call like [call_result](Self::call_result), with the accounts and storage
+    /// slots of the EIP-2930 access list warm from the start, like in a transaction which
+    /// carries the access list. The cost of the access list is charged as well.
+    pub fn call_result_with_access_list(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        access_list: Option<AccessList>,
+    ) -> CallResult {
+        let gas_limit = self.tx_gas_limit();
+        let access_list = access_list.as_ref().map(access_list_items).unwrap_or_default();
+        let (status, res) = self.transact(from, to, calldata, value, gas_limit, access_list);
+        self.decode_result(status, res)
+    }
+
+    /// Calls the contract like [call_result](Self::call_result), with the given gas limit instead
+    /// of the one of the executor if it is set, e.g. to test how a contract handles running out
+    /// of gas, see [CallResult::out_of_gas].
+    ///
+    /// Subcalls still get at most 63/64 of the gas which is left, like with any other limit.
+    pub fn call_result_with_gas_limit(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        gas_limit: Option<u64>,
+    ) -> CallResult {
+        let gas_limit = gas_limit.unwrap_or_else(|| self.tx_gas_limit());
+        let (status, res) = self.transact(from, to, calldata, value, gas_limit, vec![]);
+        self.decode_result(status, res)
+    }
+
+    /// Executes the call like [call_result](Self::call_result) and returns how the balance of
+    /// each of the `addresses` changed, including the fees paid by the sender.
+    ///
+    /// The balances are read from the executor's state, so on a fork they include the local
+    /// modifications on top of the remote state.
+    pub fn call_with_balance_diff(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        addresses: &[Address],
+    ) -> (CallResult, Vec<(Address, I256)>) {
+        let before = addresses
+            .iter()
+            .map(|address| self.executor.state().basic(*address).balance)
+            .collect::<Vec<_>>();
+        let res = self.call_result(from, to, calldata, value);
+        let diffs = addresses
+            .iter()
+            .zip(before)
+            .map(|(address, before)| {
+                let after = self.executor.state().basic(*address).balance;
+                let diff = if after >= before {
+                    I256::from_raw(after - before)
+                } else {
+                    -I256::from_raw(before - after)
+                };
+                (*address, diff)
+            })
+            .collect();
+        (res, diffs)
+    }
+
+    /// Applies the EIP-7702 authorizations and executes the call like
+    /// [call_result](Self::call_result), like a set code transaction would.
+    ///
+    /// Each valid authorization sets the code of its authority to delegate to the authorized
+    /// address, so calls to the authority run that code on the authority's storage. Invalid
+    /// authorizations are skipped and returned instead of failing the call.
+    ///
+    /// Sputnik predates Prague, so the authorizations are applied regardless of the config and
+    /// their intrinsic gas isn't charged. Only executors with cheatcodes run the code of the
+    /// delegate, others only set the delegation.
+    pub fn call_with_authorization_list(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        authorizations: Vec<Authorization>,
+    ) -> (CallResult, Vec<SkippedAuthorization>) {
+        let skipped = authorizations
+            .iter()
+            .enumerate()
+            .filter_map(|(index, auth)| {
+                self.apply_authorization(from, auth)
+                    .err()
+                    .map(|reason| SkippedAuthorization { index, reason })
+            })
+            .collect();
+        (self.call_result(from, to, calldata, value), skipped)
+    }
+
+    /// Validates the authorization and delegates its authority, the error describes why the
+    /// authorization is invalid
+    fn apply_authorization(
+        &mut self,
+        from: Address,
+        auth: &Authorization,
+    ) -> std::result::Result<(), String> {
+        /// Half of the order of secp256k1, higher `s` values are malleable
+        const HALF_ORDER: U256 =
+            U256([0xdfe92f46681b20a0, 0x5d576e7357a4501d, 0xffffffffffffffff, 0x7fffffffffffffff]);
+
+        let chain_id = self.executor.state().chain_id();
+        if !auth.chain_id.is_zero() && auth.chain_id != chain_id {
+            return Err(format!("chain id {} does not match {}", auth.chain_id, chain_id))
+        }
+        if auth.nonce == u64::MAX {
+            return Err("nonce overflows".to_string())
+        }
+        if auth.signature.s > HALF_ORDER {
+            return Err("signature s value is too high".to_string())
+        }
+        let authority = auth.authority().map_err(|err| format!("invalid signature: {}", err))?;
+
+        let state = self.executor.state();
+        let code = state.code(authority);
+        if !code.is_empty() && delegated_address(&code).is_none() {
+            return Err(format!("authority {:?} is a contract", authority))
+        }
+        // the nonce of the sender is increased before the authorizations are applied
+        let nonce = state.basic(authority).nonce + U256::from((authority == from) as u8);
+        if nonce != auth.nonce.into() {
+            return Err(format!("nonce {} does not match {} of {:?}", auth.nonce, nonce, authority))
+        }
+
+        let code = if auth.address.is_zero() { vec![] } else { delegation_code(auth.address) };
+        let state = self.executor.state_mut();
+        state.set_code(authority, code);
+        state.inc_nonce(authority);
+        Ok(())
+    }
+
+    /// Calls the contract like a `STATICCALL`, so that the call fails instead of modifying the
+    /// state, e.g. by writing storage, deploying a contract, selfdestructing or sending value.
+    ///
+    /// Unlike [call_result](Self::call_result), the nonce of the caller is not increased and no
+    /// fees are charged, so the call leaves no trace in the state. Executors without cheatcodes
+    /// don't support static calls and always fail.
+    pub fn static_call(&mut self, from: Address, to: Address, calldata: Bytes) -> CallResult {
+        let gas_limit = self.tx_gas_limit();
+        let data = calldata.to_vec();
+        let (status, res) = self.transact_with(from, &calldata, false, |executor| {
+            executor.transact_static_call(from, to, data, gas_limit, vec![])
+        });
+        self.decode_result(status, res)
+    }
+
+    /// Estimates the gas limit a transaction with the call needs to succeed, like
+    /// `eth_estimateGas`.
+    ///
+    /// A call can only forward 63/64 of its remaining gas to its subcalls, so it may need a
+    /// higher limit than the gas it used. The lowest limit is searched for between the gas used
+    /// and the block gas limit, executing the call on a copy of the state each time, so the
+    /// estimation leaves no changes behind. The estimate includes the intrinsic gas of the
+    /// transaction, which is lower for the accounts and slots warmed by the access list.
+    pub fn estimate_gas(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        access_list: Vec<(Address, Vec<H256>)>,
+    ) -> Result<u64>
+    where
+        S: Clone,
+    {
+        let intrinsic =
+            intrinsic_gas(self.executor.config(), calldata.as_ref(), &access_list, false)?;
+        let cap = match self.executor.state().block_gas_limit() {
+            limit if limit.is_zero() => self.gas_limit,
+            limit => limit.low_u64(),
+        };
+        if cap < intrinsic {
+            eyre::bail!("the intrinsic gas of {} exceeds the block gas limit of {}", intrinsic, cap)
+        }
+
+        // the attempts must not show up in the gas report, and their changes are discarded
+        self.discarding_changes(|evm| {
+            let state = evm.executor.state().clone();
+            // executes the call with the gas limit and returns its outcome and the gas it used
+            let attempt = |evm: &mut Self, gas_limit: u64| {
+                let gas_used_before = evm.executor.gas_used();
+                let (status, res) = evm.transact(
+                    from,
+                    to,
+                    calldata.clone(),
+                    value,
+                    gas_limit - intrinsic,
+                    access_list.clone(),
+                );
+                let gas_used = evm.executor.gas_used().saturating_sub(gas_used_before).low_u64();
+                *evm.executor.state_mut() = state.clone();
+                (status, res, gas_used)
+            };
+
+            let (status, res, gas_used) = attempt(evm, cap);
+            if res.success {
+                // the call fails with less gas than it used
+                let mut lowest_failing = gas_used.saturating_sub(1).max(intrinsic);
+                let mut highest_succeeding = cap;
+                while highest_succeeding - lowest_failing > 1 {
+                    let gas_limit = lowest_failing + (highest_succeeding - lowest_failing) / 2;
+                    if attempt(evm, gas_limit).1.success {
+                        highest_succeeding = gas_limit;
+                    } else {
+                        lowest_failing = gas_limit;
+                    }
+                }
+                Ok(highest_succeeding)
+            } else {
+                let res = evm.decode_result(status, res);
+                Err(eyre::eyre!(
+                    "the call fails even with the block gas limit of {}, reason: {}",
+                    cap,
+                    res.reverted_reason.unwrap_or_default()
+                ))
+            }
+        })
+    }
+
+    /// Runs `f` without keeping any of its effects: the state, including its traces and debug
+    /// steps, the logs, the coverage and the gas report are restored afterwards. The calls of `f`
+    /// are never read-only.
+    fn discarding_changes<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T
+    where
+        S: Clone,
+    {
+        let gas_report = self.gas_report.take();
+        let state = self.executor.state().clone();
+        let logs = (self.emitted_logs.len(), self.console_logs.len());
+        let coverage = self.executor.coverage();
+        let read_only = std::mem::replace(&mut self.read_only, false);
+        let out = f(self);
+        self.read_only = read_only;
+        self.executor.set_coverage(coverage);
+        self.emitted_logs.truncate(logs.0);
+        self.console_logs.truncate(logs.1);
+        *self.executor.state_mut() = state;
+        self.gas_report = gas_report;
+        out
+    }
+
+    /// Executes the transaction like `eth_call` and returns its output, e.g. to reuse calls which
+    /// were built with ethers. Without a `to`, the transaction is a contract creation and the
+    /// deployed code is returned.
+    ///
+    /// The sender defaults to the zero address and the gas to the block gas limit. The call is
+    /// executed on a copy of the state, which is discarded afterwards, so no fees are charged
+    /// and contracts can be the sender like with `eth_call`. The executor only knows the state of
+    /// its current block, so other blocks are an error.
+    pub fn eth_call(&mut self, tx: TransactionRequest, block: BlockId) -> Result<Bytes>
+    where
+        S: Clone,
+    {
+        let number = self.executor.state().block_number();
+        match block {
+            BlockId::Number(BlockNumber::Latest | BlockNumber::Pending) => {}
+            BlockId::Number(BlockNumber::Number(n)) if U256::from(n.as_u64()) == number => {}
+            block => {
+                eyre::bail!("can only call at the current block {}, not at {:?}", number, block)
+            }
+        }
+        let to = match tx.to {
+            Some(NameOrAddress::Address(to)) => Some(to),
+            Some(NameOrAddress::Name(name)) => eyre::bail!("can't resolve the ENS name {}", name),
+            None => None,
+        };
+        let from = tx.from.unwrap_or_default();
+        let calldata = tx.data.unwrap_or_default();
+        let value = tx.value.unwrap_or_default();
+        let gas_limit = match (tx.gas, self.executor.state().block_gas_limit()) {
+            (Some(gas), _) => gas.low_u64(),
+            (None, limit) if limit.is_zero() => self.gas_limit,
+            (None, limit) => limit.low_u64(),
+        };
+
+        let eip3607 = std::mem::replace(&mut self.eip3607, false);
+        let out = self.discarding_changes(|evm| match to {
+            Some(to) => {
+                let res =
+                    evm.call_result_with_gas_limit(from, to, calldata, value, Some(gas_limit));
+                match res.reverted_reason {
+                    None if res.success => Ok(res.out),
+                    reason => Err(EvmError::Execution {
+                        reason: reason.unwrap_or_default(),
+                        gas_used: res.gas_used,
+                        logs: res.logs,
+                    }
+                    .into()),
+                }
+            }
+            None => evm
+                .transact_deploy_create(from, calldata, value)
+                .map(|(_, res)| evm.executor.state().code(res.address).into()),
+        });
+        self.eip3607 = eip3607;
+        out
+    }
+
+    /// Executes the call and returns the accounts and storage slots it accessed as an EIP-2930
+    /// access list, like `eth_createAccessList`.
+    ///
+    /// The sender and the precompiles are always warm, so they are left out. The called contract
+    /// is warm as well, so it's only listed with the slots it accessed of its own storage. The
+    /// call is executed on a copy of the state, which is discarded afterwards. Before Berlin
+    /// nothing is warmed, and the list is empty.
+    pub fn access_list(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+    ) -> AccessList
+    where
+        S: Clone,
+    {
+        // the accesses of earlier transactions are kept by the state, so the call records its
+        // accesses in a substate of its own
+        let gas_limit = self.tx_gas_limit();
+        let outer_gas_limit = self.gas_limit;
+        let accessed = self.discarding_changes(|evm| {
+            evm.executor.state_mut().enter(outer_gas_limit, false);
+            let _ = evm.transact(from, to, calldata, value, gas_limit, vec![]);
+            evm.executor.state().metadata().accessed().clone()
+        });
+
+        let accessed = match accessed {
+            Some(accessed) => accessed,
+            None => return Default::default(),
+        };
+        let mut accounts: BTreeMap<Address, Vec<H256>> = BTreeMap::new();
+        for address in accessed.accessed_addresses {
+            accounts.entry(address).or_default();
+        }
+        for (address, slot) in accessed.accessed_storage {
+            accounts.entry(address).or_default().push(slot);
+        }
+        let items = accounts
+            .into_iter()
+            .filter(|(address, storage_keys)| {
+                *address != from &&
+                    !self.executor.is_precompile(*address) &&
+                    (*address != to || !storage_keys.is_empty())
+            })
+            .map(|(address, storage_keys)| AccessListItem { address, storage_keys })
+            .collect();
+        AccessList(items)
+    }
+
+    /// Decodes the revert reason and the logs of the call
+    fn decode_result(&self, status: ExitReason, mut res: CallResult) -> CallResult {
+        res.reverted_reason =
+            (!res.success && !res.rejected).then(|| match res.read_only_violation {
+                Some(violation) => violation.to_string(),
+                None => foundry_utils::decode_revert(res.out.as_ref(), self.abi.as_ref())
+                    .unwrap_or_else(|_| format!("{:?}", status)),
+            });
+        res.decode_logs(self.abi.as_ref()).decode_revert(self.abi.as_ref())
+    }
+
+    /// Executes an already mined transaction with its sender, value, gas limit and access list,
+    /// e.g. to debug it on a fork at the state right before it, see the `fork_tx_hash` of the
+    /// [EvmOpts](crate::EvmOpts). On a fork, the transaction can be fetched by its hash with
+    /// [Executor::replay_transaction].
+    ///
+    /// The block environment and the gas price are the ones of the backend, which match the
+    /// transaction when forking at it. Tracing is enabled while the transaction is executed, so
+    /// its trace can be compared with the one on chain. Like on chain, the intrinsic gas of the
+    /// transaction is part of its gas limit.
+    pub fn execute_transaction(&mut self, tx: &Transaction) -> ReplayedTransaction {
+        let from = tx.from;
+        let access_list = tx.access_list.as_ref().map(access_list_items).unwrap_or_default();
+        let intrinsic =
+            intrinsic_gas(self.executor.config(), tx.input.as_ref(), &access_list, tx.to.is_none());
+        let gas_limit = match intrinsic {
+            Ok(intrinsic) if intrinsic <= tx.gas.low_u64() => tx.gas.low_u64() - intrinsic,
+            Ok(intrinsic) => {
+                let reason = format!(
+                    "the intrinsic gas of {} exceeds the gas limit of {}",
+                    intrinsic, tx.gas
+                );
+                let result = CallResult { reverted_reason: Some(reason), ..Default::default() };
+                return ReplayedTransaction { result, created: None, trace: Default::default() }
+            }
+            Err(err) => {
+                let result =
+                    CallResult { reverted_reason: Some(err.to_string()), ..Default::default() };
+                return ReplayedTransaction { result, created: None, trace: Default::default() }
+            }
+        };
+
+        let tracing = self.executor.set_tracing_enabled(true);
+        // the trace of the transaction is recorded into the arena of the current transaction
+        let trace_index = self.executor.traces().len().saturating_sub(1);
+
+        let (result, created) = match tx.to {
+            Some(to) => {
+                let (status, res) =
+                    self.transact(from, to, tx.input.clone(), tx.value, gas_limit, access_list);
+                (self.decode_result(status, res), None)
+            }
+            None => {
+                let address = self.executor.create_address(CreateScheme::Legacy { caller: from });
+                let (value, init_code) = (tx.value, tx.input.to_vec());
+                let init_code_hash = H256::from(keccak256(&init_code));
+                let res = self.transact_deploy(from, address, None, init_code_hash, |executor| {
+                    executor.transact_create(from, value, init_code, gas_limit, access_list)
+                });
+                let res = match res {
+                    Ok((_, DeployResult { gas_used, logs, raw_logs, .. })) => {
+                        CallResult { success: true, gas_used, logs, raw_logs, ..Default::default() }
+                    }
+                    Err(err) => {
+                        CallResult { reverted_reason: Some(err.to_string()), ..Default::default() }
+                    }
+                };
+                (res, Some(address))
+            }
+        };
+
+        let trace = self.executor.traces().into_iter().nth(trace_index).unwrap_or_default();
+        self.executor.set_tracing_enabled(tracing);
+        ReplayedTransaction { result, created, trace }
+    }
+
+    /// Calls the contract and checks that the call reverted as expected, e.g. for tests of
+    /// failure cases. The error describes how the revert differed from the expectation.
+    pub fn expect_revert(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        expected: RevertExpectation,
+    ) -> Result<CallResult> {
+        let res = self.call_result(from, to, calldata, value);
+        expected.check(&res)?;
+        Ok(res)
+    }
+
+    /// Deploys the contract with `CREATE2`, to the address given by
+    /// [compute_create2_address](crate::compute_create2_address) for the hash of the init code
+    pub fn deploy_create2(
+        &mut self,
+        deployer: Address,
+        salt: H256,
+        init_code: Bytes,
+        value: U256,
+    ) -> Result<(Address, ExitReason, u64, Vec<String>)> {
+        let (status, res) = self.transact_deploy_create2(deployer, salt, init_code, value)?;
+        Ok((res.address, status, res.gas_used, res.logs))
+    }
+
+    /// Deploys the contract like [Evm::deploy] and returns the details of the deployment.
+    ///
+    /// Deployed code larger than the `create_contract_limit` of the config, 24KB since
+    /// EIP-170, fails with [EvmError::ContractSizeLimit].
+    pub fn deploy_result(
+        &mut self,
+        from: Address,
+        init_code: Bytes,
+        value: U256,
+    ) -> Result<DeployResult> {
+        self.transact_deploy_create(from, init_code, value).map(|(_, res)| res)
+    }
+
+    /// Deploys the contract like [deploy_create2](Self::deploy_create2) and returns the details
+    /// of the deployment, including the salt
+    pub fn deploy_create2_result(
+        &mut self,
+        deployer: Address,
+        salt: H256,
+        init_code: Bytes,
+        value: U256,
+    ) -> Result<DeployResult> {
+        self.transact_deploy_create2(deployer, salt, init_code, value).map(|(_, res)| res)
+    }
+
+    /// Deploys the contract like [deploy_result](Self::deploy_result), with the ABI-encoded
+    /// constructor arguments appended to its bytecode.
+    ///
+    /// Arguments which don't match the constructor of the ABI fail before anything is executed,
+    /// see [init_code_with_args](crate::init_code_with_args).
+    pub fn deploy_with_args(
+        &mut self,
+        deployer: Address,
+        bytecode: Bytes,
+        abi: &Abi,
+        args: &[Token],
+        value: U256,
+    ) -> Result<DeployResult> {
+        let init_code = init_code_with_args(bytecode, abi, args)?;
+        self.deploy_result(deployer, init_code, value)
+    }
+
+    fn transact_deploy_create(
+        &mut self,
+        from: Address,
+        init_code: Bytes,
+        value: U256,
+    ) -> Result<(ExitReason, DeployResult)> {
+        // The account's created contract address is pre-computed by using the account's nonce
+        // before it executes the contract deployment transaction.
+        let address = self.executor.create_address(CreateScheme::Legacy { caller: from });
+        let init_code_hash = H256::from(keccak256(&init_code));
+        let gas_limit = self.tx_gas_limit();
+        self.transact_deploy(from, address, None, init_code_hash, |executor| {
+            executor.transact_create(from, value, init_code.to_vec(), gas_limit, vec![])
+        })
+    }
+
+    fn transact_deploy_create2(
+        &mut self,
+        deployer: Address,
+        salt: H256,
+        init_code: Bytes,
+        value: U256,
+    ) -> Result<(ExitReason, DeployResult)> {
+        let init_code_hash = H256::from(keccak256(&init_code));
+        let address = compute_create2_address(deployer, salt, init_code_hash);
+        let gas_limit = self.tx_gas_limit();
+        self.transact_deploy(deployer, address, Some(salt), init_code_hash, |executor| {
+            executor.transact_create2(deployer, value, init_code.to_vec(), salt, gas_limit, vec![])
+        })
+    }
+
+    /// Executes the deployment to the given address and returns its outcome
+    fn transact_deploy(
+        &mut self,
+        from: Address,
+        address: Address,
+        salt: Option<H256>,
+        init_code_hash: H256,
+        create: impl FnOnce(&mut E) -> ExitReason,
+    ) -> Result<(ExitReason, DeployResult)> {
+        if let Err(status) = self.check_sender(from) {
+            eyre::bail!("deployment rejected, reason: {:?}", status)
+        }
+        if self.read_only {
+            return Err(ReadOnlyViolation { address, slot: None }.into())
+        }
+        let gas_used_before = self.executor.gas_used();
+        let refunded_gas_before = self.executor.gas_refund();
+
+        let gas_report_start = self.gas_report_start();
+        let status = create(&mut self.executor);
+        self.record_gas(gas_report_start);
+
+        // get the deployment logs
+        let logs = self.executor.logs();
+        let raw_logs = self.executor.raw_logs();
+        self.emitted_logs.extend(self.executor.emitted_logs());
+        self.console_logs.extend(self.executor.console_logs());
+        // and clear them
+        self.executor.clear_logs();
+
+        let refunded_gas = self.executor.gas_refund().saturating_sub(refunded_gas_before);
+        let gas_used_after = self.executor.gas_used();
+        let gas = gas_used_after.saturating_sub(gas_used_before).saturating_sub(refunded_gas);
+        self.charge_fees(from, gas);
+        // we dont remove call data costs here because its highly relevant to users
+        let gas = gas.saturating_sub(21000.into());
+
+        if !matches!(status, ExitReason::Succeed(_)) {
+            tracing::trace!(?status, "failed");
+            if let (ExitReason::Error(ExitError::CreateContractLimit), Some(limit)) =
+                (&status, self.executor.config().create_contract_limit)
+            {
+                return Err(EvmError::ContractSizeLimit { limit }.into())
+            }
+            if let ExitReason::Error(ExitError::CreateCollision) = status {
+                return Err(EvmError::CreateCollision { address }.into())
+            }
+            Err(eyre::eyre!("deployment reverted, reason: {:?}", status))
+        } else {
+            tracing::trace!(?status, ?address, ?gas, "success");
+            let res = DeployResult {
+                address,
+                code_size: self.executor.state().code(address).len(),
+                gas_used: gas.as_u64(),
+                logs,
+                raw_logs,
+                salt,
+                init_code_hash,
+            };
+            Ok((status, res))
+        }
+    }
+
+    /// Makes the sender pay for the gas used by a transaction at the `tx.gasprice`.
+    ///
+    /// Like on chain since London, the base fee is burnt and only the priority fee on top of it
+    /// goes to the coinbase. Nothing is charged if the gas price is zero, which is the default,
+    /// or if the gas is [free](Self::with_free_gas).
+    fn charge_fees(&mut self, from: Address, gas_used: U256) {
+        let gas_price = self.executor.state().gas_price();
+        if gas_price.is_zero() || self.free_gas {
+            return
+        }
+        // before London, the whole fee goes to the coinbase
+        let base_fee = if self.executor.config().has_base_fee {
+            self.executor.state().block_base_fee_per_gas()
+        } else {
+            U256::zero()
+        };
+        let coinbase = self.executor.state().block_coinbase();
+        let state = self.executor.state_mut();
+        if state.withdraw(from, gas_used.saturating_mul(gas_price)).is_err() {
+            tracing::trace!(?from, ?gas_used, ?gas_price, "sender can't pay for the gas");
+            return
+        }
+        state.deposit(coinbase, gas_used.saturating_mul(gas_price.saturating_sub(base_fee)));
+    }
+
+    /// Executes the call and returns its status and outcome, without decoding the output
+    fn transact(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        gas_limit: u64,
+        access_list: Vec<(Address, Vec<H256>)>,
+    ) -> (ExitReason, CallResult) {
+        if let Err(status) = self.check_sender(from) {
+            return (status, CallResult::default())
+        }
+        let data = calldata.to_vec();
+        if !self.read_only {
+            return self.transact_with(from, &calldata, true, |executor| {
+                executor.transact_call(from, to, value, data, gas_limit, access_list)
+            })
+        }
+
+        // sending value would change the balances even if the call itself doesn't write
+        if !value.is_zero() {
+            let violation = ReadOnlyViolation { address: from, slot: None };
+            let status = ExitError::Other(violation.to_string().into()).into();
+            return (
+                status,
+                CallResult { read_only_violation: Some(violation), ..Default::default() },
+            )
+        }
+        self.transact_with(from, &calldata, false, |executor| {
+            executor.transact_static_call(from, to, data, gas_limit, access_list)
+        })
+    }
+
+    /// Rejects transactions from accounts with code, unless EIP-3607 is disabled
+    fn check_sender(&self, from: Address) -> Result<(), ExitReason> {
+        if !self.eip3607 {
+            return Ok(())
+        }
+        let code = self.executor.state().code(from);
+        if code.is_empty() || delegated_address(&code).is_some() {
+            return Ok(())
+        }
+        Err(ExitReason::Error(ExitError::Other("sender has code, see EIP-3607".into())))
+    }
+
+    /// Executes the call and collects its outcome, charging the fees to the caller if
+    /// `pays_fees` is set
+    fn transact_with(
+        &mut self,
+        from: Address,
+        calldata: &Bytes,
+        pays_fees: bool,
+        call: impl FnOnce(&mut E) -> (ExitReason, Vec<u8>),
+    ) -> (ExitReason, CallResult) {
+        let gas_used_before = self.executor.gas_used();
+        let refunded_gas_before = self.executor.gas_refund();
+        let _ = self.executor.take_outcome();
+        let _ = take_budget_error();
+
+        let gas_report_start = self.gas_report_start();
+        let (status, retdata) = call(&mut self.executor);
+        self.record_gas(gas_report_start);
+        // the backend continues with empty state when a fork request exceeds the budget, so the
+        // call is failed instead of reporting an outcome based on it
+        let (status, retdata) = match take_budget_error() {
+            Some(err) => (ExitReason::Revert(ExitRevert::Reverted), revert_data(&err)),
+            None => (status, retdata),
+        };
+
+        tracing::trace!(logs_before = ?self.executor.logs());
+
+        let refunded_gas = self.executor.gas_refund().saturating_sub(refunded_gas_before);
+        let gas_used_after = self.executor.gas_used();
+        let gas = gas_used_after.saturating_sub(gas_used_before).saturating_sub(refunded_gas);
+        if pays_fees {
+            self.charge_fees(from, gas);
+        }
+        // remove base and calldata costs
+        let gas = foundry_utils::remove_extra_costs(gas, calldata.as_ref());
+
+        // get the logs
+        let logs = self.executor.logs();
+        let raw_logs = self.executor.raw_logs();
+        self.emitted_logs.extend(self.executor.emitted_logs());
+        self.console_logs.extend(self.executor.console_logs());
+        tracing::trace!(logs_after = ?self.executor.logs());
+        // clear them
+        self.executor.clear_logs();
+
+        let success = matches!(status, ExitReason::Succeed(_));
+        let outcome = self.executor.take_outcome();
+        let res = CallResult {
+            success,
+            memory_limit_exceeded: outcome.memory_limit_exceeded,
+            out_of_gas: matches!(status, ExitReason::Error(ExitError::OutOfGas)),
+            rejected: !success && retdata == ASSUME_MAGIC_RETURN_CODE,
+            gas_used: gas.as_u64(),
+            gas_refunded: refunded_gas.as_u64(),
+            out: retdata.into(),
+            logs,
+            raw_logs,
+            // only the calls of a read-only session are failed by the writes they attempt
+            read_only_violation: outcome.rejected_write.filter(|_| self.read_only && !success),
+            create_collision: outcome.create_collision,
+            call_depth_exceeded: outcome.call_depth_exceeded,
+            ..Default::default()
+        };
+        (status, res)
+    }
+}
+
+impl<'a, B: Backend, E: SputnikExecutor<MemoryStackStateOwned<'a, B>>>
+    Executor<MemoryStackStateOwned<'a, B>, E>
+{
+    /// Sets the nonce of the sender set with [with_sender](Self::with_sender), so it has to be
+    /// called after it.
+    ///
+    /// Fails if the nonce is lower than the current one, since the sender could then deploy to
+    /// the addresses of the contracts it already created.
+    pub fn with_sender_nonce(mut self, nonce: U256) -> Result<Self> {
+        let current = self.executor.state().basic(self.sender).nonce;
+        if nonce < current {
+            eyre::bail!(
+                "can't decrease the nonce of the sender {:?} from {} to {}",
+                self.sender,
+                current,
+                nonce
+            )
+        }
+        self.executor.state_mut().set_nonce(self.sender, nonce);
+        Ok(self)
+    }
+}
+
+// Concrete implementation over the in-memory backend without cheatcodes
+impl<'a, 'b, B: Backend, P: PrecompileSet>
+    Executor<MemoryStackState<'a, 'a, B>, StackExecutor<'a, 'b, MemoryStackState<'a, 'a, B>, P>>
+{
+    /// Given a gas limit, vm version, initial chain configuration and initial state
+    // TODO: See if we can make lifetimes better here
+    pub fn new(gas_limit: u64, config: &'a Config, backend: &'a B, precompiles: &'b P) -> Self {
+        // setup gasometer
+        let metadata = StackSubstateMetadata::new(gas_limit, config);
+        // setup state
+        let state = MemoryStackState::new(metadata, backend);
+        // setup executor
+        let executor = StackExecutor::new_with_precompiles(state, config, precompiles);
+
+        Self {
+            executor,
+            gas_limit,
+            sender: Address::zero(),
+            abi: None,
+            snapshots: vec![],
+            gas_report: None,
+            gas_metering: true,
+            impersonated_from: None,
+            emitted_logs: vec![],
+            console_logs: vec![],
+            account_balance: DEFAULT_ACCOUNT_BALANCE,
+            made_accounts: Default::default(),
+            eip3607: true,
+            read_only: false,
+            free_gas: false,
+            marker: PhantomData,
+        }
+    }
+}
+
+// Note regarding usage of Generic vs Associated Types in traits:
+//
+// We use StackState as a trait and not as an associated type because we want to
+// allow the developer what the db type should be. Whereas for ReturnReason, we want it
+// to be generic across implementations, but we don't want to make it a user-controlled generic.
+impl<'a, S, E> Evm<S> for Executor<S, E>
+where
+    E: SputnikExecutor<S>,
+    S: StackState<'a>,
+{
+    type ReturnReason = ExitReason;
+
+    fn revert() -> Self::ReturnReason {
+        ExitReason::Revert(ExitRevert::Reverted)
+    }
+
+    fn expected_revert(&self) -> Option<&[u8]> {
+        self.executor.expected_revert()
+    }
+
+    fn sender(&self) -> Address {
+        self.sender
+    }
+
+    fn is_success(reason: &Self::ReturnReason) -> bool {
+        matches!(reason, ExitReason::Succeed(_))
+    }
+
+    fn is_fail(reason: &Self::ReturnReason) -> bool {
+        !Self::is_success(reason)
+    }
+
+    fn reset(&mut self, state: S) {
+        let mut _state = self.executor.state_mut();
+        *_state = state;
+    }
+
+    fn set_tracing_enabled(&mut self, enabled: bool) -> bool {
+        self.executor.set_tracing_enabled(enabled)
+    }
+
+    fn tracing_enabled(&self) -> bool {
+        self.executor.tracing_enabled()
+    }
+
+    /// Grabs debug steps
+    #[cfg(feature = "sputnik")]
+    fn debug_calls(&self) -> Vec<DebugArena> {
+        self.executor.debug_calls()
+    }
+
+    /// given an iterator of contract address to contract bytecode, initializes
+    /// the state with the contract deployed at the specified address
+    fn initialize_contracts<T: IntoIterator<Item = (Address, Bytes)>>(&mut self, contracts: T) {
+        let state_ = self.executor.state_mut();
+        contracts.into_iter().for_each(|(address, bytecode)| {
+            state_.set_code(address, bytecode.to_vec());
+        })
+    }
+
+    fn set_balance(&mut self, address: Address, balance: U256) {
+        self.executor
+            .state_mut()
+            .transfer(Transfer { source: *FAUCET_ACCOUNT, target: address, value: balance })
+            .expect("could not transfer funds")
+    }
+
+    fn state(&self) -> &S {
+        self.executor.state()
+    }
+
+    fn code(&self, address: Address) -> Vec<u8> {
+        self.executor.state().code(address)
+    }
+
+    fn traces(&self) -> Vec<CallTraceArena> {
+        self.executor.traces()
+    }
+
+    fn reset_traces(&mut self) {
+        self.executor.reset_traces()
+    }
+
+    fn set_traces(&mut self, traces: Vec<CallTraceArena>) {
+        self.executor.set_traces(traces)
+    }
+
+    fn all_logs(&self) -> Vec<String> {
+        self.executor.all_logs()
+    }
+
+    fn assertion_failures(&self) -> Vec<AssertionFailure> {
+        self.executor.assertion_failures()
+    }
+
+    /// Deploys the provided contract bytecode
+    fn deploy(
+        &mut self,
+        from: Address,
+        calldata: Bytes,
+        value: U256,
+    ) -> Result<(Address, ExitReason, u64, Vec<String>)> {
+        let (status, res) = self.transact_deploy_create(from, calldata, value)?;
+        Ok((res.address, status, res.gas_used, res.logs))
+    }
+
+    /// Runs the selected function
+    fn call_raw(
+        &mut self,
+        from: Address,
+        to: Address,
+        calldata: Bytes,
+        value: U256,
+        _is_static: bool,
+    ) -> Result<(Bytes, ExitReason, u64, Vec<String>)> {
+        let gas_limit = self.tx_gas_limit();
+        let (status, res) = self.transact(from, to, calldata, value, gas_limit, vec![]);
+        if let Some(violation) = res.read_only_violation {
+            return Err(violation.into())
+        }
+        Ok((res.out, status, res.gas_used, res.logs))
+    }
+}
+
+#[cfg(any(test, feature = "sputnik-helpers"))]
+pub mod helpers {
+    use super::*;
+    use ethers::{
+        providers::{Http, Provider},
+        types::H160,
+    };
+    use sputnik::backend::{MemoryBackend, MemoryVicinity};
+    use std::{convert::TryFrom, sync::Arc};
+
+    use crate::{
+        fuzz::FuzzedExecutor,
+        sputnik::{
+            cheatcodes::cheatcode_handler::{CheatcodeStackExecutor, CheatcodeStackState},
+            new_shared_cache, vicinity, MemCache, PrecompileFn, SharedBackend, PRECOMPILES_MAP,
+        },
+    };
+    use once_cell::sync::Lazy;
+
+    pub type TestSputnikVM<'a, B> = Executor<
+        // state
+        CheatcodeStackState<'a, B>,
+        // actual stack executor
+        CheatcodeStackExecutor<'a, 'a, B, BTreeMap<Address, PrecompileFn>>,
+    >;
+
+    pub static CFG: Lazy<Config> = Lazy::new(Config::london);
+
+    /// London config without a contract size limit. Useful for testing but is a depature from
+    /// mainnet rules.
+    pub static CFG_NO_LMT: Lazy<Config> = Lazy::new(|| {
+        let mut cfg = Config::london();
+        cfg.create_contract_limit = None;
+        cfg
+    });
+
+    pub static VICINITY: Lazy<MemoryVicinity> = Lazy::new(new_vicinity);
+    pub const GAS_LIMIT: u64 = 30_000_000;
+
+    /// Instantiates a Sputnik EVM with enabled cheatcodes + FFI and a simple non-forking in memory
+    /// backend and tracing disabled
+    pub fn vm<'a>() -> TestSputnikVM<'a, MemoryBackend<'a>> {
+        let backend = new_backend(&*VICINITY, Default::default());
+        Executor::new_with_cheatcodes(
+            backend,
+            GAS_LIMIT,
+            &*CFG,
+            &*PRECOMPILES_MAP,
+            true,
+            false,
+            false,
+        )
+    }
+
+    /// Instantiates a Sputnik EVM with enabled cheatcodes + FFI and a simple non-forking in memory
+    /// backend and tracing disabled, and no contract size limit
+    pub fn vm_no_limit<'a>() -> TestSputnikVM<'a, MemoryBackend<'a>> {
+        let backend = new_backend(&*VICINITY, Default::default());
+        Executor::new_with_cheatcodes(
+            backend,
+            GAS_LIMIT,
+            &*CFG_NO_LMT,
+            &*PRECOMPILES_MAP,
+            true,
+            false,
+            false,
+        )
     }
 
     /// Instantiates a Sputnik EVM with enabled cheatcodes + FFI and a simple non-forking in memory
@@ -327,6 +1925,41 @@ pub mod helpers {
         }
     }
 
+    /// Instantiates a Sputnik EVM with enabled cheatcodes over the given backend, e.g. a fork,
+    /// with FFI, tracing and debug disabled
+    pub fn vm_with_backend<'a, B: Backend>(backend: B) -> TestSputnikVM<'a, B> {
+        Executor::new_with_cheatcodes(
+            backend,
+            GAS_LIMIT,
+            &*CFG,
+            &*PRECOMPILES_MAP,
+            false,
+            false,
+            false,
+        )
+    }
+
+    /// The mainnet endpoint the fork tests run against
+    pub const FORK_URL: &str = "https://mainnet.infura.io/v3/c60b0bb42f8a4c6481ecd229eddaca27";
+
+    /// A provider for the mainnet endpoint the fork tests run against
+    pub fn fork_provider() -> Provider<Http> {
+        Provider::<Http>::try_from(FORK_URL).unwrap()
+    }
+
+    /// A backend forking mainnet at the given block, or the latest one, with an empty cache
+    pub fn fork_backend(block: Option<u64>) -> SharedBackend {
+        let provider = fork_provider();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let vicinity = rt.block_on(vicinity(&provider, None, block, None)).unwrap();
+        SharedBackend::new(
+            Arc::new(provider),
+            new_shared_cache(MemCache::default()),
+            vicinity,
+            block.map(Into::into),
+        )
+    }
+
     /// Instantiates a FuzzedExecutor over provided Sputnik EVM
     pub fn fuzzvm<'a, B: Backend>(
         evm: &'a mut TestSputnikVM<'a, B>,
@@ -357,33 +1990,215 @@ pub mod helpers {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        sputnik::helpers::vm,
-        test_helpers::{can_call_vm_directly, solidity_unit_test, COMPILED},
-    };
-    use ethers::utils::id;
-    use sputnik::{ExitReason, ExitRevert, ExitSucceed};
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sputnik::{
+            cheatcodes::cheatcode_handler::CHEATCODE_ADDRESS,
+            helpers::{
+                new_backend, vm, vm_no_limit, vm_tracing, vm_with_backend, CFG, GAS_LIMIT, VICINITY,
+            },
+            PRECOMPILES_MAP,
+        },
+        test_helpers::{
+            can_call_vm_directly, dry_calls_discard_changes, solidity_unit_test, COMPILED,
+        },
+    };
+    use ethers::{abi::Token, utils::id};
+    use sputnik::{ExitReason, ExitRevert, ExitSucceed};
+
+    // can bubble up sputnik errors
+    #[test]
+    fn test_oog() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("GreeterTest").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+
+        evm.gas_limit = 0;
+        let err = evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "testGreeting()",
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap_err();
+        let (reason, _) = match err {
+            crate::EvmError::Execution { reason, gas_used, .. } => (reason, gas_used),
+            _ => panic!("unexpected error variant"),
+        };
+        assert_eq!(reason, "Error(OutOfGas)");
+    }
+
+    #[test]
+    fn can_disable_gas_metering() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("GreeterTest").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+
+        evm.gas_limit = 0;
+        evm.set_gas_metering(false);
+        evm.call_committing::<(), _, _>(
+            Address::zero(),
+            addr,
+            "testGreeting()",
+            (),
+            0.into(),
+            None,
+        )
+        .unwrap();
+
+        evm.set_gas_metering(true);
+        assert!(evm
+            .call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "testGreeting()",
+                (),
+                0.into(),
+                None
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn can_snapshot_and_revert() {
+        let mut evm = vm();
+        let alice = Address::repeat_byte(1);
+
+        let empty = evm.snapshot();
+        evm.set_balance(alice, 1.into());
+        let funded = evm.snapshot();
+        evm.set_balance(alice, 2.into());
+        let nested = evm.snapshot();
+
+        assert!(evm.revert(funded));
+        assert_eq!(evm.executor.state().basic(alice).balance, 1.into());
+        // reverting discards the snapshot and all newer ones
+        assert!(!evm.revert(funded));
+        assert!(!evm.revert(nested));
+
+        assert!(evm.revert(empty));
+        assert_eq!(evm.executor.state().basic(alice).balance, 0.into());
+    }
+
+    #[test]
+    fn reverting_discards_the_logs_since_the_snapshot() {
+        let mut evm = vm();
+        // PUSH1 0 PUSH1 0 LOG0 STOP
+        let emitter = Address::repeat_byte(1);
+        evm.initialize_contracts([(emitter, vec![0x60, 0x00, 0x60, 0x00, 0xa0, 0x00].into())]);
+        assert!(evm.call_result(Address::zero(), emitter, Bytes::default(), 0.into()).success);
+        let snapshot = evm.snapshot();
+        for _ in 0..2 {
+            assert!(evm.call_result(Address::zero(), emitter, Bytes::default(), 0.into()).success);
+        }
+        assert_eq!(evm.logs_matching(None, &[]).len(), 3);
+        assert!(evm.revert(snapshot));
+        assert_eq!(evm.logs_matching(None, &[]).len(), 1);
+    }
+
+    #[test]
+    fn can_reject_cheatcodes_if_disabled() {
+        let backend = new_backend(&*VICINITY, Default::default());
+        let precompiles = PRECOMPILES_MAP.clone();
+        let compiled = COMPILED.find("CustomCheats").expect("could not find contract");
+        let mut warp = id("warp(uint256)").to_vec();
+        warp.extend(ethers::abi::encode(&[Token::Uint(1.into())]));
+
+        let cheat = |strict: bool| {
+            let mut evm = Executor::new(GAS_LIMIT, &*CFG, &backend, &precompiles);
+            if strict {
+                evm = evm.with_cheatcode_strict();
+            }
+            let (addr, _, _, _) = evm
+                .deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into())
+                .unwrap();
+            let (out, _, _, _) = evm
+                .call_committing::<(bool, Bytes), _, _>(
+                    Address::zero(),
+                    addr,
+                    "cheat(bytes)(bool,bytes)",
+                    Bytes::from(warp.clone()),
+                    0.into(),
+                    compiled.abi,
+                )
+                .unwrap();
+            out
+        };
+
+        // the call succeeds like any call to an empty account by default
+        assert_eq!(cheat(false), (true, Bytes::default()));
+        let (success, out) = cheat(true);
+        assert!(!success);
+        assert_eq!(
+            crate::RevertReason::decode(&out, None),
+            crate::RevertReason::Message(CHEATCODES_DISABLED.to_string())
+        );
+
+        // executors with cheatcodes still handle them
+        let mut evm = vm().with_cheatcode_strict();
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let ((success, _), _, _, _) = evm
+            .call_committing::<(bool, Bytes), _, _>(
+                Address::zero(),
+                addr,
+                "cheat(bytes)(bool,bytes)",
+                Bytes::from(warp),
+                0.into(),
+                compiled.abi,
+            )
+            .unwrap();
+        assert!(success);
+        assert_eq!(evm.executor.state().code(*CHEATCODE_ADDRESS), Vec::<u8>::new());
+    }
 
-    // can bubble up sputnik errors
     #[test]
-    fn test_oog() {
+    fn redeploys_to_the_same_address_after_revert() {
         let mut evm = vm();
         let compiled = COMPILED.find("GreeterTest").expect("could not find contract");
-        let (addr, _, _, _) =
-            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let bytecode = compiled.bytecode().unwrap().clone();
+        let deployer = Address::zero();
+        let nonce = evm.executor.state().basic(deployer).nonce;
+
+        let id = evm.snapshot();
+        let (addr, _, _, _) = evm.deploy(deployer, bytecode.clone(), 0.into()).unwrap();
+        assert!(evm.revert(id));
+        assert_eq!(evm.executor.state().basic(deployer).nonce, nonce);
+
+        for _ in 0..2 {
+            let id = evm.snapshot();
+            let (redeployed, _, _, _) = evm.deploy(deployer, bytecode.clone(), 0.into()).unwrap();
+            assert_eq!(redeployed, addr);
+            assert!(evm.revert(id));
+        }
+    }
 
-        evm.gas_limit = 0;
-        let err = evm
-            .call::<(), _, _>(Address::zero(), addr, "testGreeting()", (), 0.into(), None)
-            .unwrap_err();
-        let (reason, _) = match err {
-            crate::EvmError::Execution { reason, gas_used, .. } => (reason, gas_used),
-            _ => panic!("unexpected error variant"),
+    #[test]
+    fn can_preload_accounts() {
+        let mut evm = vm();
+        let alice = Address::repeat_byte(1);
+        let account = MemoryAccount {
+            nonce: 3.into(),
+            balance: 100.into(),
+            storage: vec![(H256::repeat_byte(1), H256::repeat_byte(2))].into_iter().collect(),
+            code: vec![0x00],
         };
-        assert_eq!(reason, "Error(OutOfGas)");
+        evm.insert_account(alice, account.clone());
+        assert_eq!(evm.executor.state().basic(alice).nonce, 3.into());
+        assert_eq!(evm.executor.state().code(alice), account.code);
+        assert_eq!(evm.executor.state().storage(alice, H256::repeat_byte(1)), H256::repeat_byte(2));
+
+        evm.deal(alice, 5.into());
+        assert_eq!(evm.executor.state().basic(alice).balance, 5.into());
+        assert_eq!(evm.executor.state().basic(alice).nonce, 3.into());
+        assert_eq!(evm.executor.state().code(alice), account.code);
     }
 
     #[test]
@@ -393,6 +2208,74 @@ mod tests {
         can_call_vm_directly(evm, compiled);
     }
 
+    #[test]
+    fn sputnik_dry_calls_discard_changes() {
+        let evm = vm();
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        dry_calls_discard_changes(evm, compiled);
+    }
+
+    #[test]
+    fn dry_calls_keep_traces_and_logs() {
+        let mut evm = vm_tracing(false);
+        evm.enable_gas_report();
+        // PUSH1 0 PUSH1 0 LOG0 PUSH1 2 PUSH1 1 SSTORE STOP
+        let store = Address::repeat_byte(1);
+        evm.initialize_contracts([(store, hex::decode("60006000a0600260015500").unwrap().into())]);
+        let from = Address::repeat_byte(3);
+        let traced = |traces: Vec<CallTraceArena>| {
+            traces
+                .iter()
+                .flat_map(|arena| &arena.arena)
+                .filter(|node| node.trace.addr == store)
+                .count()
+        };
+
+        evm.call_dry::<(), _, _>(from, store, "f()", (), 0.into(), None).unwrap();
+        assert_eq!(evm.state().storage(store, H256::from_low_u64_be(1)), H256::zero());
+        assert_eq!(evm.logs_matching(Some(store), &[]).len(), 1);
+        assert_eq!(traced(evm.executor.traces()), 1);
+        assert!(!evm.gas_report().contracts.is_empty());
+
+        // the following calls are traced after the dry one
+        evm.call_dry::<(), _, _>(from, store, "f()", (), 0.into(), None).unwrap();
+        assert_eq!(traced(evm.executor.traces()), 2);
+    }
+
+    #[test]
+    fn can_find_reentrant_calls() {
+        let mut evm = vm_tracing(false);
+        let compiled = COMPILED.find("ReentrantReceiver").expect("could not find contract");
+        let (receiver, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let (vault, _, _, _) = evm
+            .call::<Address, _, _>(Address::zero(), receiver, "vault()", (), 0.into(), compiled.abi)
+            .unwrap();
+        evm.call::<(), _, _>(Address::zero(), receiver, "attack()", (), 0.into(), compiled.abi)
+            .unwrap();
+
+        // attack -> withdraw -> onWithdraw -> withdraw -> onWithdraw
+        let expected = [(receiver, 0, 2), (vault, 1, 3), (receiver, 0, 4)];
+        let events = evm.reentrant_calls();
+        let found: Vec<_> = events
+            .iter()
+            .map(|event| (event.address, event.first_depth, event.reentry_depth))
+            .collect();
+        assert_eq!(found, expected);
+
+        // the nodes are marked while tracing
+        let traces = evm.executor.traces();
+        for event in &events {
+            assert!(traces[event.arena].arena[event.node].trace.reentrant);
+        }
+        let marked = traces
+            .iter()
+            .flat_map(|arena| &arena.arena)
+            .filter(|node| node.trace.reentrant)
+            .count();
+        assert_eq!(marked, expected.len());
+    }
+
     #[test]
     fn sputnik_solidity_unit_test() {
         let evm = vm();
@@ -433,7 +2316,7 @@ mod tests {
         assert_eq!(status, ExitReason::Succeed(ExitSucceed::Stopped));
 
         let err = evm
-            .call::<(), _, _>(
+            .call_committing::<(), _, _>(
                 Address::zero(),
                 addr,
                 "testFailGreeting()",
@@ -450,6 +2333,937 @@ mod tests {
         assert_eq!(gas_used, 26569);
     }
 
+    #[test]
+    fn can_return_call_result() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("GreeterTest").expect("could not find contract");
+
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.setup(addr).unwrap();
+
+        let res = evm.call_result(
+            Address::zero(),
+            addr,
+            id("testFailGreeting()").to_vec().into(),
+            0.into(),
+        );
+        assert!(!res.success);
+        assert_eq!(res.reverted_reason.as_deref(), Some("not equal to `hi`"));
+        assert_eq!(res.gas_used, 26569);
+
+        let res =
+            evm.call_result(Address::zero(), addr, id("testGreeting()").to_vec().into(), 0.into());
+        assert!(res.success);
+        assert!(res.reverted_reason.is_none());
+    }
+
+    #[test]
+    fn can_deploy_create2() {
+        let mut evm = vm();
+        let deployer = Address::repeat_byte(1);
+        let salt = H256::repeat_byte(2);
+        // deploys a contract which returns 42
+        let init_code: Bytes =
+            hex::decode("600a600c600039600a6000f3602a60005260206000f3").unwrap().into();
+
+        let expected = compute_create2_address(deployer, salt, keccak256(&init_code).into());
+        let (addr, _, _, _) =
+            evm.deploy_create2(deployer, salt, init_code.clone(), 0.into()).unwrap();
+        assert_eq!(addr, expected);
+        assert_eq!(evm.code(addr), hex::decode("602a60005260206000f3").unwrap());
+
+        // the address is taken now
+        assert!(evm.deploy_create2(deployer, salt, init_code, 0.into()).is_err());
+    }
+
+    #[test]
+    fn can_charge_fees() {
+        use crate::evm_opts::Env;
+
+        let env = Env::default().with_base_fee(10).with_priority_fee(2);
+        assert_eq!(env.effective_gas_price(), 12);
+        let vicinity = env.sputnik_state();
+        let backend = new_backend(&vicinity, Default::default());
+        let mut evm = vm_with_backend(backend);
+
+        let alice = Address::repeat_byte(1);
+        let bob = Address::repeat_byte(2);
+        evm.deal(alice, 1_000_000.into());
+        let res = evm.call_result(alice, bob, Bytes::default(), 0.into());
+        assert!(res.success);
+
+        // a plain transfer costs 21000 gas, of which the base fee is burnt
+        assert_eq!(evm.state().basic(alice).balance, (1_000_000 - 21_000 * 12).into());
+        assert_eq!(evm.state().basic(env.block_coinbase).balance, (21_000 * 2).into());
+
+        // value sent to the coinbase is collected together with the tips
+        let coinbase = Address::repeat_byte(3);
+        let env = env.with_coinbase(coinbase);
+        let backend = new_backend(&env.sputnik_state(), Default::default());
+        let mut evm = vm_with_backend(backend);
+        evm.deal(alice, 1_000_000.into());
+        let res = evm.call_result(alice, coinbase, Bytes::default(), 5.into());
+        assert!(res.success);
+        assert_eq!(evm.state().basic(coinbase).balance, (5 + 21_000 * 2).into());
+    }
+
+    #[test]
+    fn can_simulate_with_free_gas() {
+        use crate::evm_opts::Env;
+
+        let env = Env::default().with_base_fee(10).with_priority_fee(2);
+        let alice = Address::repeat_byte(1);
+        let bob = Address::repeat_byte(2);
+        // stores 42 in slot 0
+        let code = hex::decode("602a60005500").unwrap();
+        let simulate = |free_gas| {
+            let backend = new_backend(&env.sputnik_state(), Default::default());
+            let mut evm = vm_with_backend(backend).with_free_gas(free_gas);
+            evm.initialize_contracts(vec![(bob, code.clone().into())]);
+            evm.deal(alice, 1_000_000.into());
+            let res = evm.call_result(alice, bob, Bytes::default(), 5.into());
+            assert!(res.success);
+            (res.gas_used, evm.state().basic(alice).balance)
+        };
+
+        // the gas is accounted for as usual, but the sender only pays for the value
+        let (paid_gas, paid_balance) = simulate(false);
+        let (free_gas, free_balance) = simulate(true);
+        assert!(free_gas > 0);
+        assert_eq!(free_gas, paid_gas);
+        assert!(paid_balance < free_balance);
+        assert_eq!(free_balance, (1_000_000 - 5).into());
+    }
+
+    #[test]
+    fn can_diff_balances() {
+        use crate::evm_opts::Env;
+
+        let env = Env::default().with_gas_price(3);
+        let backend = new_backend(&env.sputnik_state(), Default::default());
+        let mut evm = vm_with_backend(backend);
+        let alice = Address::repeat_byte(1);
+        let bob = Address::repeat_byte(2);
+        let carol = Address::repeat_byte(3);
+        evm.deal(alice, 1_000_000.into());
+
+        let (res, diffs) = evm.call_with_balance_diff(
+            alice,
+            bob,
+            Bytes::default(),
+            5.into(),
+            &[alice, bob, carol],
+        );
+        assert!(res.success);
+        assert_eq!(
+            diffs,
+            vec![
+                (alice, I256::from(-(5 + 21_000 * 3))),
+                (bob, I256::from(5)),
+                (carol, I256::zero())
+            ]
+        );
+    }
+
+    #[test]
+    fn can_check_deployments() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+        let (addr, _, _, _) =
+            evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        assert_eq!(evm.code_size(addr), evm.code(addr).len());
+        assert!(evm.code_size(addr) > 0);
+        assert!(evm.account_exists(addr));
+
+        let empty = Address::repeat_byte(2);
+        assert_eq!(evm.code_size(empty), 0);
+        assert!(!evm.account_exists(empty));
+        evm.deal(empty, 1.into());
+        assert!(evm.account_exists(empty));
+        assert_eq!(evm.code_size(empty), 0);
+    }
+
+    #[test]
+    fn can_iterate_touched_accounts() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+        let (addr, _, _, _) =
+            evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.deal(Address::repeat_byte(2), 5.into());
+
+        let accounts = evm.touched_accounts().collect::<BTreeMap<_, _>>();
+        assert!(accounts.contains_key(&from));
+        assert!(!accounts[&addr].code.as_ref().is_empty());
+        assert_eq!(accounts[&Address::repeat_byte(2)].balance, 5.into());
+        assert!(!accounts.contains_key(&Address::repeat_byte(3)));
+    }
+
+    #[test]
+    fn can_make_accounts() {
+        let mut evm = vm().with_account_balance(5.into());
+        let (alice, key) = evm.make_account(1);
+        assert_eq!(evm.make_account(1), (alice, key));
+        assert_ne!(evm.make_account(2).0, alice);
+        assert_eq!(evm.state().basic(alice).balance, 5.into());
+
+        let signer = SigningKey::from_bytes(key.as_bytes()).unwrap();
+        assert_eq!(secret_key_to_address(&signer), alice);
+    }
+
+    #[test]
+    fn can_seed_sender() {
+        let sender = Address::repeat_byte(1);
+        let evm = vm()
+            .with_sender(sender)
+            .with_sender_balance(5.into())
+            .with_sender_nonce(3.into())
+            .unwrap();
+        assert_eq!(evm.state().basic(sender).balance, 5.into());
+        assert_eq!(evm.state().basic(sender).nonce, 3.into());
+        assert_eq!(evm.state().basic(Address::zero()).balance, 0.into());
+
+        let err = evm.with_sender_nonce(2.into()).err().unwrap();
+        assert!(err.to_string().contains("can't decrease the nonce"), "{}", err);
+    }
+
+    #[test]
+    fn read_only_rejects_writes() {
+        let mut evm = vm().with_read_only(true);
+        // stores 1 in slot 2, and returns 42
+        let (writes, reads) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        evm.initialize_contracts([
+            (writes, hex::decode("6001600255").unwrap().into()),
+            (reads, hex::decode("602a60005260206000f3").unwrap().into()),
+        ]);
+        let snapshot = evm.snapshot();
+
+        let res = evm.call_result(Address::zero(), reads, Bytes::default(), 0.into());
+        assert!(res.success);
+        assert_eq!(U256::from_big_endian(res.out.as_ref()), 42.into());
+        assert_eq!(evm.state().basic(Address::zero()).nonce, 0.into());
+
+        let res = evm.call_result(Address::zero(), writes, Bytes::default(), 0.into());
+        let violation = ReadOnlyViolation { address: writes, slot: Some(H256::from_low_u64_be(2)) };
+        assert_eq!(res.read_only_violation, Some(violation));
+        assert_eq!(res.reverted_reason, Some(violation.to_string()));
+        assert_eq!(evm.state().storage(writes, H256::from_low_u64_be(2)), H256::zero());
+        let err = evm.call_raw(Address::zero(), writes, Bytes::default(), 0.into(), false);
+        assert_eq!(err.unwrap_err().downcast_ref::<ReadOnlyViolation>(), Some(&violation));
+
+        let res = evm.call_result(Address::zero(), reads, Bytes::default(), 1.into());
+        assert_eq!(
+            res.read_only_violation,
+            Some(ReadOnlyViolation { address: Address::zero(), slot: None })
+        );
+        let err = evm.deploy(Address::zero(), hex::decode("00").unwrap().into(), 0.into());
+        assert!(err.unwrap_err().downcast_ref::<ReadOnlyViolation>().is_some());
+        assert!(evm.revert(snapshot));
+
+        let mut evm = evm.with_read_only(false);
+        assert!(evm.call_result(Address::zero(), writes, Bytes::default(), 0.into()).success);
+        assert_eq!(evm.state().storage(writes, H256::from_low_u64_be(2)), H256::from_low_u64_be(1));
+    }
+
+    #[test]
+    fn can_eth_call() {
+        let mut evm = vm();
+        // stores 1 in slot 0 and returns 42
+        let addr = Address::repeat_byte(1);
+        let reverts = Address::repeat_byte(2);
+        evm.initialize_contracts([
+            (addr, hex::decode("6001600055602a60005260206000f3").unwrap().into()),
+            (reverts, hex::decode("60006000fd").unwrap().into()),
+        ]);
+        let latest = BlockId::Number(BlockNumber::Latest);
+
+        let out = evm.eth_call(TransactionRequest::new().to(addr), latest).unwrap();
+        assert_eq!(U256::from_big_endian(out.as_ref()), 42.into());
+        // the call leaves no changes behind
+        assert_eq!(evm.state().storage(addr, H256::zero()), H256::zero());
+        assert_eq!(evm.state().basic(Address::zero()).nonce, 0.into());
+
+        assert!(evm.eth_call(TransactionRequest::new().to(reverts), latest).is_err());
+        let past = BlockId::Number(BlockNumber::Number(1u64.into()));
+        assert!(evm.eth_call(TransactionRequest::new().to(addr), past).is_err());
+
+        // deploys the code which returns 42
+        let init_code = hex::decode("600a600c600039600a6000f3602a60005260206000f3").unwrap();
+        let out = evm.eth_call(TransactionRequest::new().data(init_code), latest).unwrap();
+        assert_eq!(out.as_ref(), hex::decode("602a60005260206000f3").unwrap());
+    }
+
+    #[test]
+    fn can_filter_logs() {
+        let mut evm = vm();
+        // emits a log with the topic and no data
+        let emitter = |topic: H256| {
+            let mut code = vec![0x7f];
+            code.extend_from_slice(topic.as_bytes());
+            code.extend_from_slice(&[0x60, 0x00, 0x60, 0x00, 0xa1, 0x00]);
+            code
+        };
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let (x, y) = (H256::repeat_byte(1), H256::repeat_byte(2));
+        evm.initialize_contracts([(a, emitter(x).into()), (b, emitter(y).into())]);
+        for to in [a, b, a] {
+            assert!(evm.call_result(Address::zero(), to, Bytes::default(), 0.into()).success);
+        }
+
+        assert_eq!(evm.logs_matching(None, &[]).len(), 3);
+        assert_eq!(evm.logs_matching(Some(a), &[]).len(), 2);
+        assert_eq!(evm.logs_matching(None, &[Some(y)])[0].address, b);
+        assert!(evm.logs_matching(Some(a), &[Some(y)]).is_empty());
+        assert!(evm.logs_matching(None, &[None, None]).is_empty());
+
+        evm.clear_logs();
+        assert!(evm.logs_matching(None, &[]).is_empty());
+    }
+
+    #[test]
+    fn can_limit_gas_of_single_call() {
+        let mut evm = vm();
+        // JUMPDEST PUSH1 0 JUMP, loops until it runs out of gas
+        let looping = Address::repeat_byte(1);
+        // calls the looping contract with all its gas and ignores the failure
+        let mut code = hex::decode("60006000600060006000").unwrap();
+        code.push(0x73);
+        code.extend_from_slice(looping.as_bytes());
+        code.extend_from_slice(&[0x5a, 0xf1, 0x00]);
+        let caller = Address::repeat_byte(2);
+        evm.initialize_contracts([
+            (looping, hex::decode("5b600056").unwrap().into()),
+            (caller, code.into()),
+        ]);
+
+        let res = evm.call_result_with_gas_limit(
+            Address::zero(),
+            looping,
+            Bytes::default(),
+            0.into(),
+            Some(50_000),
+        );
+        assert!(!res.success && res.out_of_gas);
+        assert!(res.reverted_reason.is_some());
+
+        // the caller keeps 1/64 of its gas, which is enough to return
+        let res = evm.call_result_with_gas_limit(
+            Address::zero(),
+            caller,
+            Bytes::default(),
+            0.into(),
+            Some(100_000),
+        );
+        assert!(res.success && !res.out_of_gas);
+        assert!(res.gas_used < 100_000);
+
+        // the executor's limit is used for the following calls
+        let res = evm.call_result(Address::zero(), caller, Bytes::default(), 0.into());
+        assert!(res.success);
+        assert!(res.gas_used > 100_000);
+    }
+
+    #[test]
+    fn can_record_state_changes() {
+        let mut evm = vm();
+        // PUSH1 2 PUSH1 1 SSTORE STOP
+        let addr = Address::repeat_byte(1);
+        evm.initialize_contracts([(addr, hex::decode("600260015500").unwrap().into())]);
+        let from = Address::repeat_byte(2);
+        evm.deal(from, 10.into());
+
+        let res = evm.call_with_state_changes(from, addr, Bytes::default(), 3.into());
+        assert!(res.success);
+        let changes = &res.state_changes.accounts;
+        assert_eq!(
+            changes[&addr].storage[&H256::from_low_u64_be(1)],
+            (H256::zero(), H256::from_low_u64_be(2))
+        );
+        assert_eq!(changes[&addr].balance, Some((0.into(), 3.into())));
+        assert!(!changes[&addr].created);
+        assert_eq!(changes[&from].balance, Some((10.into(), 7.into())));
+
+        // writing the same value again doesn't change the slot
+        let res = evm.call_with_state_changes(from, addr, Bytes::default(), 0.into());
+        assert!(res.success);
+        assert!(!res.state_changes.accounts.contains_key(&addr));
+
+        let fresh = Address::repeat_byte(3);
+        let res = evm.call_with_state_changes(from, fresh, Bytes::default(), 1.into());
+        let change = &res.state_changes.accounts[&fresh];
+        assert!(change.created);
+        assert_eq!(change.balance, Some((0.into(), 1.into())));
+    }
+
+    #[test]
+    fn can_simulate_bundles() {
+        let mut evm = vm();
+        evm.enable_gas_report();
+        // PUSH1 0 PUSH1 0 LOG0 PUSH1 2 PUSH1 1 SSTORE STOP
+        let store = Address::repeat_byte(1);
+        // PUSH1 0 PUSH1 0 REVERT
+        let revert = Address::repeat_byte(2);
+        evm.initialize_contracts([
+            (store, hex::decode("60006000a0600260015500").unwrap().into()),
+            (revert, hex::decode("60006000fd").unwrap().into()),
+        ]);
+        let from = Address::repeat_byte(3);
+        // the gas report only records calls with a selector
+        let calldata: Bytes = vec![0; 4].into();
+        let calls = [store, revert, store].map(|to| Call {
+            from,
+            to,
+            calldata: calldata.clone(),
+            ..Default::default()
+        });
+        let slot = H256::from_low_u64_be(1);
+
+        let bundle = evm.simulate_bundle(&calls, true);
+        assert!(!bundle.success() && bundle.rolled_back);
+        assert_eq!(bundle.failed_call, Some(1));
+        assert_eq!(bundle.results.len(), 2);
+        assert!(bundle.state_changes.accounts.is_empty());
+        assert_eq!(evm.state().storage(store, slot), H256::zero());
+        // the logs, traces and gas of the rolled back calls are gone as well
+        assert!(evm.logs_matching(None, &[]).is_empty());
+        let traces = evm.executor.traces();
+        assert!(traces.iter().flat_map(|arena| &arena.arena).all(|node| node.trace.addr != store));
+        assert!(evm.gas_report().contracts.is_empty());
+
+        let bundle = evm.simulate_bundle(&calls, false);
+        assert!(!bundle.rolled_back);
+        assert_eq!(bundle.failed_call, Some(1));
+        assert_eq!(
+            bundle.results.iter().map(|res| res.success).collect::<Vec<_>>(),
+            [true, false, true]
+        );
+        assert!(bundle.state_changes.accounts.contains_key(&store));
+        assert_eq!(evm.state().storage(store, slot), H256::from_low_u64_be(2));
+        assert_eq!(evm.logs_matching(Some(store), &[]).len(), 2);
+        assert!(!evm.gas_report().contracts.is_empty());
+
+        let bundle = evm.simulate_bundle(&calls[..1], true);
+        assert!(bundle.success() && !bundle.rolled_back);
+    }
+
+    #[test]
+    fn can_run_setup_calls() {
+        // PUSH1 2 PUSH1 1 SSTORE STOP
+        let store = Address::repeat_byte(1);
+        // PUSH1 0 PUSH1 0 REVERT
+        let revert = Address::repeat_byte(2);
+        // PUSH1 3 PUSH1 1 SSTORE STOP
+        let configure = Address::repeat_byte(3);
+        let contracts: Vec<(Address, Bytes)> = vec![
+            (store, hex::decode("600260015500").unwrap().into()),
+            (revert, hex::decode("60006000fd").unwrap().into()),
+            (configure, hex::decode("600360015500").unwrap().into()),
+        ];
+        let from = Address::repeat_byte(4);
+        let calls =
+            [store, revert, configure, revert].map(|to| Call { from, to, ..Default::default() });
+        let slot = H256::from_low_u64_be(1);
+
+        let mut evm = vm();
+        evm.initialize_contracts(contracts.clone());
+        let setup = evm.run_setup(&calls, FailurePolicy::StopOnFirstError);
+        assert!(!setup.success());
+        assert_eq!(setup.results.len(), 2);
+        assert_eq!(setup.failed_calls, [1]);
+        assert_eq!(evm.state().storage(configure, slot), H256::zero());
+
+        let mut evm = vm();
+        evm.initialize_contracts(contracts);
+        let setup = evm.run_setup(&calls, FailurePolicy::ContinueAndCollect);
+        assert_eq!(
+            setup.results.iter().map(|res| res.success).collect::<Vec<_>>(),
+            [true, false, true, false]
+        );
+        assert_eq!(setup.failures().map(|(i, _)| i).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(evm.state().storage(configure, slot), H256::from_low_u64_be(3));
+    }
+
+    #[test]
+    fn can_override_spec_per_call() {
+        use ethers::solc::EvmVersion;
+
+        let mut evm = vm();
+        // PUSH1 0 SLOAD STOP, a cold `SLOAD` costs 800 gas before Berlin and 2100 after
+        let load = Address::repeat_byte(1);
+        evm.initialize_contracts([(load, hex::decode("60005400").unwrap().into())]);
+        let from = Address::repeat_byte(2);
+
+        let res = evm
+            .call_result_with_spec(from, load, Bytes::default(), 0.into(), EvmVersion::Istanbul)
+            .unwrap();
+        assert!(res.success);
+        assert_eq!(res.gas_used, 3 + 800);
+
+        // the override only applied to that call, and didn't warm up the slot
+        let res = evm.call_result(from, load, Bytes::default(), 0.into());
+        assert_eq!(res.gas_used, 3 + 2100);
+
+        let call = Call {
+            from,
+            to: load,
+            spec_override: Some(EvmVersion::Istanbul),
+            ..Default::default()
+        };
+        let bundle = evm.simulate_bundle(&[call.clone()], true);
+        assert_eq!(bundle.results[0].gas_used, 3 + 800);
+
+        let unsupported = Call { spec_override: Some(EvmVersion::Byzantium), ..call };
+        let bundle = evm.simulate_bundle(&[unsupported], true);
+        assert_eq!(bundle.failed_call, Some(0));
+        assert!(bundle.results[0].reverted_reason.as_ref().unwrap().contains("not supported"));
+        assert!(evm
+            .call_result_with_spec(from, load, Bytes::default(), 0.into(), EvmVersion::Byzantium)
+            .is_err());
+
+        // executors without cheatcodes can't run under other rules
+        let backend = new_backend(&*VICINITY, Default::default());
+        let precompiles = PRECOMPILES_MAP.clone();
+        let mut evm = Executor::new(GAS_LIMIT, &*CFG, &backend, &precompiles);
+        evm.initialize_contracts([(load, hex::decode("60005400").unwrap().into())]);
+        assert!(evm
+            .call_result_with_spec(from, load, Bytes::default(), 0.into(), EvmVersion::Istanbul)
+            .is_err());
+    }
+
+    #[test]
+    fn can_delegate_eoas() {
+        use ethers::signers::{LocalWallet, Signer};
+
+        let mut evm = vm();
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+        let (greeter, _, _, _) =
+            evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let wallet: LocalWallet =
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        let eoa = wallet.address();
+        let chain_id = evm.state().chain_id();
+
+        let authorizations = vec![
+            Authorization::sign(&wallet, 1234.into(), greeter, 0),
+            Authorization::sign(&wallet, chain_id, greeter, 1),
+            Authorization::sign(&wallet, chain_id, greeter, 0),
+        ];
+        let calldata: Bytes = id("gm()").to_vec().into();
+        let (res, skipped) =
+            evm.call_with_authorization_list(from, eoa, calldata, 0.into(), authorizations);
+        assert!(res.success);
+        assert_eq!(skipped.iter().map(|skipped| skipped.index).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(evm.state().basic(eoa).nonce, 1.into());
+        assert_eq!(delegated_address(&evm.code(eoa)), Some(greeter));
+
+        // the code of the greeter ran on the storage of the eoa
+        assert_ne!(evm.state().storage(eoa, H256::zero()), H256::zero());
+        assert_eq!(evm.state().storage(greeter, H256::zero()), H256::zero());
+
+        // delegating to the zero address removes the delegation
+        let authorizations = vec![Authorization::sign(&wallet, 0.into(), Address::zero(), 1)];
+        let (_, skipped) =
+            evm.call_with_authorization_list(from, eoa, Bytes::default(), 0.into(), authorizations);
+        assert!(skipped.is_empty());
+        assert!(evm.code(eoa).is_empty());
+    }
+
+    #[test]
+    fn can_estimate_gas() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+        let (addr, _, _, _) =
+            evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let calldata: Bytes = id("gm()").to_vec().into();
+        let nonce = evm.state().basic(from).nonce;
+
+        let estimate = evm.estimate_gas(from, addr, calldata.clone(), 0.into(), vec![]).unwrap();
+        assert!(estimate > 21_000 + 20_000, "{}", estimate);
+        // nothing was written
+        assert_eq!(evm.state().storage(addr, H256::zero()), H256::zero());
+        assert_eq!(evm.state().basic(from).nonce, nonce);
+
+        // the access list is paid for upfront, but warms the slot for the execution
+        let access_list = vec![(addr, vec![H256::zero()])];
+        let with_access_list =
+            evm.estimate_gas(from, addr, calldata, 0.into(), access_list).unwrap();
+        assert_ne!(with_access_list, estimate);
+
+        let err = evm
+            .estimate_gas(from, addr, id("missing()").to_vec().into(), 0.into(), vec![])
+            .unwrap_err();
+        assert!(err.to_string().contains("block gas limit"), "{}", err);
+    }
+
+    #[test]
+    fn estimations_leave_no_logs_or_traces_behind() {
+        let mut evm = vm_tracing(false);
+        // emits a log without topics and data
+        let emitter = Address::repeat_byte(2);
+        evm.initialize_contracts([(emitter, hex::decode("60006000a000").unwrap().into())]);
+        let from = Address::repeat_byte(1);
+        let nodes = |evm: &TestSputnikVM<'_, _>| {
+            evm.traces().iter().map(|trace| trace.arena.len()).collect::<Vec<_>>()
+        };
+        let traces = nodes(&evm);
+
+        evm.estimate_gas(from, emitter, Bytes::default(), 0.into(), vec![]).unwrap();
+        evm.access_list(from, emitter, Bytes::default(), 0.into());
+        assert!(evm.logs_matching(None, &[]).is_empty());
+        assert_eq!(nodes(&evm), traces);
+
+        assert!(evm.call_result(from, emitter, Bytes::default(), 0.into()).success);
+        assert_eq!(evm.logs_matching(None, &[]).len(), 1);
+        assert_ne!(nodes(&evm), traces);
+    }
+
+    #[test]
+    fn can_create_access_list() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("GreeterTestSetup").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+        let (addr, _, _, _) =
+            evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.call_committing::<(), _, _>(from, addr, "setUp()", (), 0.into(), None).unwrap();
+        // the greeter is the first slot of the setup contract
+        let greeter = Address::from(evm.state().storage(addr, H256::zero()));
+
+        let calldata: Bytes = id("greeting()").to_vec().into();
+        let access_list = evm.access_list(from, addr, calldata.clone(), 0.into());
+        let items = access_list
+            .0
+            .into_iter()
+            .map(|item| (item.address, item.storage_keys))
+            .collect::<BTreeMap<_, _>>();
+        // the slot of the called contract is listed as well
+        let expected: BTreeMap<_, _> =
+            vec![(addr, vec![H256::zero()]), (greeter, vec![H256::zero()])].into_iter().collect();
+        assert_eq!(items, expected);
+
+        // warming the accounts and slots upfront is cheaper than accessing them cold, except for
+        // the called contract, which is warm anyway
+        let estimate = evm.estimate_gas(from, addr, calldata.clone(), 0.into(), vec![]).unwrap();
+        let access_list = items.into_iter().filter(|(address, _)| *address != addr).collect();
+        let with_access_list =
+            evm.estimate_gas(from, addr, calldata, 0.into(), access_list).unwrap();
+        assert!(with_access_list < estimate, "{} >= {}", with_access_list, estimate);
+    }
+
+    #[test]
+    fn can_call_with_access_list() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("GreeterTestSetup").expect("could not find contract");
+        let from = Address::repeat_byte(1);
+        let (addr, _, _, _) =
+            evm.deploy(from, compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.call_committing::<(), _, _>(from, addr, "setUp()", (), 0.into(), None).unwrap();
+        let calldata: Bytes = id("greeting()").to_vec().into();
+        let mut access_list = evm.access_list(from, addr, calldata.clone(), 0.into());
+        access_list.0.retain(|item| item.address != addr);
+
+        let cold = evm.call_result(from, addr, calldata.clone(), 0.into());
+        let warm =
+            evm.call_result_with_access_list(from, addr, calldata, 0.into(), Some(access_list));
+        assert!(cold.success && warm.success);
+        assert_eq!(warm.out, cold.out);
+        assert!(warm.gas_used < cold.gas_used, "{} >= {}", warm.gas_used, cold.gas_used);
+    }
+
+    #[test]
+    fn can_impersonate_contracts() {
+        let mut evm = vm().with_sender(Address::repeat_byte(1));
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(evm.sender(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        // contracts can't send transactions since EIP-3607
+        let res = evm.call_result(addr, addr, id("gm()").to_vec().into(), 0.into());
+        assert!(!res.success);
+        assert!(res.reverted_reason.unwrap().contains("EIP-3607"));
+        let mut evm = evm.with_disable_eip3607(true);
+
+        evm.impersonate(addr, Some(100.into()));
+        evm.impersonate(addr, Some(1.into()));
+        assert_eq!(evm.sender(), addr);
+        assert_eq!(evm.state().basic(addr).balance, 101.into());
+        assert!(evm.call_result(evm.sender(), addr, id("gm()").to_vec().into(), 0.into()).success);
+
+        evm.stop_impersonate();
+        assert_eq!(evm.sender(), Address::repeat_byte(1));
+        assert_eq!(evm.state().basic(addr).balance, 101.into());
+    }
+
+    #[test]
+    fn can_set_gas_price() {
+        use crate::evm_opts::Env;
+
+        let env = Env::default().with_gas_price(3);
+        env.check_gas_price(true).unwrap();
+        let backend = new_backend(&env.sputnik_state(), Default::default());
+        let mut evm = vm_with_backend(backend);
+        let alice = Address::repeat_byte(1);
+        evm.deal(alice, 1_000_000.into());
+        assert!(
+            evm.call_result(alice, Address::repeat_byte(2), Bytes::default(), 0.into()).success
+        );
+        assert_eq!(evm.state().basic(alice).balance, (1_000_000 - 21_000 * 3).into());
+
+        // the gas price has to cover the base fee, and match the priority fee on top of it
+        let env = env.with_base_fee(5);
+        assert!(env.check_gas_price(true).is_err());
+        assert!(env.check_gas_price(false).is_ok());
+        assert!(env.clone().with_gas_price(7).with_priority_fee(2).check_gas_price(true).is_ok());
+        assert!(env.clone().with_gas_price(8).with_priority_fee(2).check_gas_price(true).is_err());
+
+        // without the base fee check, the whole fee is still burnt
+        let env = env.with_disable_base_fee(true);
+        env.check_gas_price(true).unwrap();
+        let backend = new_backend(&env.sputnik_state(), Default::default());
+        let mut evm = vm_with_backend(backend);
+        evm.deal(alice, 1_000_000.into());
+        assert!(
+            evm.call_result(alice, Address::repeat_byte(2), Bytes::default(), 0.into()).success
+        );
+        assert_eq!(evm.state().basic(alice).balance, (1_000_000 - 21_000 * 3).into());
+        assert!(evm.state().basic(env.block_coinbase).balance.is_zero());
+    }
+
+    #[test]
+    fn can_seed_block_hashes() {
+        use crate::evm_opts::Env;
+
+        let seeded = H256::repeat_byte(1);
+        let env =
+            Env { block_number: 300, ..Default::default() }.with_block_hashes(vec![(299, seeded)]);
+        let vicinity = env.sputnik_state();
+        let backend = new_backend(&vicinity, Default::default());
+        assert_eq!(backend.block_hash(299.into()), seeded);
+        assert_eq!(backend.block_hash(298.into()), env.block_hash(298));
+        assert_eq!(backend.block_hash(298.into()), keccak256(H256::from_low_u64_be(298)).into());
+        // only the last 256 blocks and no future ones have a hash
+        assert_eq!(backend.block_hash(44.into()), keccak256(H256::from_low_u64_be(44)).into());
+        assert_eq!(backend.block_hash(43.into()), H256::zero());
+        assert_eq!(backend.block_hash(300.into()), H256::zero());
+
+        // `blockhash` returns the seeded hash
+        let mut evm = vm_with_backend(new_backend(&vicinity, Default::default()));
+        let from = Address::repeat_byte(1);
+        // PUSH2 299 BLOCKHASH PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+        let code = hex::decode("61012b4060005260206000f3").unwrap();
+        let to = Address::repeat_byte(2);
+        evm.initialize_contracts([(to, code.into())]);
+        let res = evm.call_result(from, to, Bytes::default(), 0.into());
+        assert_eq!(res.out.as_ref(), seeded.as_bytes());
+    }
+
+    #[test]
+    fn can_report_gas() {
+        let compiled = COMPILED.find("GreeterTest").expect("could not find contract");
+        let mut evm = vm().with_abi(compiled.abi.unwrap().clone());
+        evm.enable_gas_report();
+
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.setup(addr).unwrap();
+        for _ in 0..2 {
+            evm.call_committing::<(), _, _>(
+                Address::zero(),
+                addr,
+                "testGreeting()",
+                (),
+                0.into(),
+                None,
+            )
+            .unwrap();
+        }
+
+        let report = evm.gas_report();
+        assert_eq!(report.contracts.len(), 2);
+        let test = &report.contracts[&format!("{:?}", addr)];
+        assert!(test.gas > 0.into());
+        let calls = &test.functions["testGreeting"];
+        assert_eq!(calls.calls.len(), 2);
+        assert!(calls.min <= calls.mean && calls.mean <= calls.max);
+        assert_eq!(test.functions["setUp"].calls.len(), 1);
+
+        // the greeter is deployed in the setup, and called in both tests
+        let (_, greeter) =
+            report.contracts.iter().find(|(name, _)| **name != format!("{:?}", addr)).unwrap();
+        assert!(greeter.gas > 0.into());
+        assert_eq!(greeter.functions["greet"].calls.len(), 2);
+        assert_eq!(greeter.functions["greeting"].calls.len(), 2);
+    }
+
+    #[test]
+    fn can_replay_transactions() {
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let mut evm = vm().with_abi(compiled.abi.unwrap().clone());
+        let from = Address::repeat_byte(1);
+
+        let deploy = Transaction {
+            from,
+            input: compiled.bytecode().unwrap().clone(),
+            gas: 1_000_000.into(),
+            ..Default::default()
+        };
+        let replayed = evm.execute_transaction(&deploy);
+        assert!(replayed.result.success);
+        let addr = replayed.created.unwrap();
+        assert!(!evm.state().code(addr).is_empty());
+
+        let calldata = compiled
+            .abi
+            .unwrap()
+            .function("greet")
+            .unwrap()
+            .encode_input(&[Token::String("hi".to_string())])
+            .unwrap();
+        let call = Transaction {
+            from,
+            to: Some(addr),
+            input: calldata.into(),
+            gas: 1_000_000.into(),
+            ..Default::default()
+        };
+        let replayed = evm.execute_transaction(&call);
+        assert!(replayed.result.success);
+        assert!(replayed.created.is_none());
+        assert_eq!(replayed.trace.arena[0].trace.address, addr);
+        // tracing is only enabled for the replay
+        assert!(!evm.executor.tracing_enabled());
+
+        // the gas limit of the transaction applies, which includes its intrinsic gas
+        let call = Transaction { gas: 21_000.into(), ..call };
+        let replayed = evm.execute_transaction(&call);
+        assert!(!replayed.result.success);
+        assert!(replayed.result.reverted_reason.unwrap().contains("intrinsic gas"));
+    }
+
+    #[test]
+    fn can_expect_revert() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("GreeterTest").expect("could not find contract");
+
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        evm.setup(addr).unwrap();
+
+        let failing: Bytes = id("testFailGreeting()").to_vec().into();
+        let mut expect = |expected| {
+            evm.expect_revert(Address::zero(), addr, failing.clone(), 0.into(), expected)
+        };
+        assert!(expect(RevertExpectation::Any).is_ok());
+        assert!(expect(RevertExpectation::Message("not equal to `hi`".to_string())).is_ok());
+        // `Error(string)`
+        assert!(expect(RevertExpectation::Selector([8, 195, 121, 160])).is_ok());
+        let err = expect(RevertExpectation::Message("hi".to_string())).unwrap_err();
+        assert!(err.to_string().contains("expected it to revert with `hi`"));
+
+        let passing = id("testGreeting()").to_vec().into();
+        assert!(evm
+            .expect_revert(Address::zero(), addr, passing, 0.into(), RevertExpectation::Any)
+            .is_err());
+    }
+
+    #[test]
+    fn can_tell_rejected_inputs_from_reverts() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("TestAssume").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+
+        let func = compiled.abi.unwrap().function("testAssume").unwrap();
+        let calldata: Bytes = func.encode_input(&[Token::Uint(200.into())]).unwrap().into();
+        let res = evm.call_result(Address::zero(), addr, calldata.clone(), 0.into());
+        assert!(!res.success);
+        assert!(res.rejected);
+        assert!(res.reverted_reason.is_none());
+        assert!(evm
+            .expect_revert(Address::zero(), addr, calldata, 0.into(), RevertExpectation::Any)
+            .is_err());
+
+        let calldata = func.encode_input(&[Token::Uint(1.into())]).unwrap().into();
+        let res = evm.call_result(Address::zero(), addr, calldata, 0.into());
+        assert!(res.success);
+        assert!(!res.rejected);
+    }
+
+    #[test]
+    fn can_describe_deployments() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("Greeter").expect("could not find contract");
+        let init_code = compiled.bytecode().unwrap().clone();
+        let init_code_hash = H256::from(keccak256(&init_code));
+        let from = Address::repeat_byte(1);
+
+        let res = evm.deploy_result(from, init_code.clone(), 0.into()).unwrap();
+        assert_eq!(res.code_size, evm.code(res.address).len());
+        assert!(res.code_size > 0);
+        assert_eq!(res.init_code_hash, init_code_hash);
+        assert_eq!(res.salt, None);
+
+        let salt = H256::repeat_byte(1);
+        let res = evm.deploy_create2_result(from, salt, init_code, 0.into()).unwrap();
+        assert_eq!(res.address, compute_create2_address(from, salt, init_code_hash));
+        assert_eq!(res.salt, Some(salt));
+    }
+
+    #[test]
+    fn can_deploy_with_args() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("ExpectRevertConstructor").expect("could not find contract");
+        let (abi, bytecode) = (compiled.abi.unwrap(), compiled.bytecode().unwrap().clone());
+        let from = Address::repeat_byte(1);
+
+        let res = evm
+            .deploy_with_args(from, bytecode.clone(), abi, &[Token::Uint(1.into())], 0.into())
+            .unwrap();
+        assert!(res.code_size > 0);
+        // the constructor requires the argument to be below 100
+        assert!(evm
+            .deploy_with_args(from, bytecode.clone(), abi, &[Token::Uint(100.into())], 0.into())
+            .is_err());
+
+        let nonce = evm.state().basic(from).nonce;
+        let err = evm.deploy_with_args(from, bytecode.clone(), abi, &[], 0.into()).unwrap_err();
+        assert!(err.to_string().contains("takes 1 arguments, but 0"), "{}", err);
+        let err =
+            evm.deploy_with_args(from, bytecode, abi, &[Token::Bool(true)], 0.into()).unwrap_err();
+        assert!(err.to_string().contains("must be of type uint256"), "{}", err);
+        // nothing was executed
+        assert_eq!(evm.state().basic(from).nonce, nonce);
+    }
+
+    #[test]
+    fn enforces_contract_size_limit() {
+        // returns 0x6001 bytes of empty memory, one more than EIP-170 allows
+        let init_code: Bytes = hex::decode("6160016000f3").unwrap().into();
+        let from = Address::repeat_byte(1);
+
+        let err = vm().deploy_result(from, init_code.clone(), 0.into()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EvmError>(),
+            Some(EvmError::ContractSizeLimit { limit: 0x6000 })
+        ));
+
+        let res = vm_no_limit().deploy_result(from, init_code.clone(), 0.into()).unwrap();
+        assert_eq!(res.code_size, 0x6001);
+
+        let res = vm()
+            .with_disable_contract_size_limit(true)
+            .deploy_result(from, init_code, 0.into())
+            .unwrap();
+        assert_eq!(res.code_size, 0x6001);
+
+        // exactly at the limit is still allowed
+        let init_code: Bytes = hex::decode("6160006000f3").unwrap().into();
+        let res = vm().deploy_result(from, init_code, 0.into()).unwrap();
+        assert_eq!(res.code_size, 0x6000);
+    }
+
     #[test]
     fn test_can_call_large_contract() {
         let mut evm = vm();
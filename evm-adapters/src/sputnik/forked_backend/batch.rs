@@ -0,0 +1,688 @@
+//! A JSON-RPC client which coalesces concurrent requests into batches
+use async_trait::async_trait;
+use ethers::providers::{HttpClientError, JsonRpcClient, ProviderError};
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// The error of the JSON-RPC clients of a fork, see [BatchClient]
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// The request failed or the endpoint answered with a JSON-RPC error
+    #[error(transparent)]
+    Http(#[from] HttpClientError),
+    /// The endpoint answered with an HTTP error status and no JSON-RPC response
+    #[error("the endpoint answered with status {status}: {body}")]
+    Status { status: u16, body: String },
+    /// The request was queued for a batch which was dropped before it was answered
+    #[error("the batch of the `{0}` request was dropped before it was answered")]
+    Unanswered(String),
+    /// The WebSocket or IPC connection to the endpoint failed
+    #[error("the connection to the endpoint failed: {0}")]
+    Connection(String),
+}
+
+impl From<ClientError> for ProviderError {
+    fn from(err: ClientError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(err))
+    }
+}
+
+/// How concurrent requests are coalesced into a single batch request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// The maximum number of requests in a batch, `1` disables batching
+    pub max_batch_size: usize,
+    /// How long the first request of a batch waits for more requests before the batch is sent
+    pub flush_interval: Duration,
+}
+
+impl BatchConfig {
+    /// Sends every request on its own
+    pub fn none() -> Self {
+        Self { max_batch_size: 1, flush_interval: Duration::ZERO }
+    }
+
+    pub fn new(max_batch_size: usize, flush_interval: Duration) -> Self {
+        Self { max_batch_size: max_batch_size.max(1), flush_interval }
+    }
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+/// How the requests of a [BatchClient] are sent to the endpoint.
+///
+/// A WebSocket or IPC connection is kept open by the client and its requests or batches are sent
+/// over it one at a time. It is opened again if it failed, e.g. because the endpoint closed it
+/// or the runtime it was opened in was shut down.
+#[derive(Debug, Clone)]
+pub enum Transport {
+    Http {
+        client: Client,
+        url: Url,
+    },
+    /// A `ws://` or `wss://` endpoint
+    Ws(Url),
+    /// The unix socket of a local node, e.g. geth's `geth.ipc`
+    Ipc(PathBuf),
+}
+
+impl Transport {
+    /// Detects the transport from the scheme of the url: `ws://` and `wss://` urls are WebSocket
+    /// endpoints, `ipc://` urls and absolute paths or paths ending in `.ipc` are IPC sockets,
+    /// `http://` and `https://` urls are sent with the given client.
+    pub fn detect(url: &str, client: Client) -> eyre::Result<Self> {
+        if let Some(path) = url.strip_prefix("ipc://") {
+            return Ok(Transport::Ipc(path.into()))
+        }
+        let path = Path::new(url);
+        if !url.contains("://") && (path.is_absolute() || path.extension() == Some("ipc".as_ref()))
+        {
+            return Ok(Transport::Ipc(url.into()))
+        }
+        let url =
+            Url::parse(url).map_err(|err| eyre::eyre!("invalid endpoint `{}`: {}", url, err))?;
+        match url.scheme() {
+            "http" | "https" => Ok(Transport::Http { client, url }),
+            "ws" | "wss" => Ok(Transport::Ws(url)),
+            scheme => eyre::bail!("unsupported transport {}", scheme),
+        }
+    }
+
+    /// Opens a connection to the WebSocket or IPC endpoint
+    async fn connect(&self) -> Result<Connection, ClientError> {
+        match self {
+            Transport::Http { .. } => unreachable!("HTTP requests don't keep a connection"),
+            Transport::Ws(url) => {
+                let (ws, _) = tokio_tungstenite::connect_async(url.as_str())
+                    .await
+                    .map_err(connection_error)?;
+                Ok(Connection::Ws(Box::new(ws)))
+            }
+            #[cfg(unix)]
+            Transport::Ipc(path) => {
+                let stream = tokio::net::UnixStream::connect(path)
+                    .await
+                    .map_err(|err| ClientError::Connection(format!("{} ({:?})", err, path)))?;
+                Ok(Connection::Ipc { stream, buf: Vec::new() })
+            }
+            #[cfg(not(unix))]
+            Transport::Ipc(path) => Err(ClientError::Connection(format!(
+                "IPC sockets are only supported on unix, not {:?}",
+                path
+            ))),
+        }
+    }
+}
+
+fn to_http(err: reqwest::Error) -> ClientError {
+    ClientError::Http(err.into())
+}
+
+fn connection_error(err: impl std::fmt::Display) -> ClientError {
+    ClientError::Connection(err.to_string())
+}
+
+/// The open connection to a WebSocket or IPC endpoint
+enum Connection {
+    Ws(
+        Box<
+            tokio_tungstenite::WebSocketStream<
+                tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+            >,
+        >,
+    ),
+    #[cfg(unix)]
+    Ipc {
+        stream: tokio::net::UnixStream,
+        /// What was read after the last response
+        buf: Vec<u8>,
+    },
+}
+
+impl Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Connection::Ws(_) => f.write_str("Ws"),
+            #[cfg(unix)]
+            Connection::Ipc { .. } => f.write_str("Ipc"),
+        }
+    }
+}
+
+impl Connection {
+    /// Sends the JSON body and returns the raw response
+    async fn request(&mut self, body: &str) -> Result<String, ClientError> {
+        match self {
+            Connection::Ws(ws) => {
+                use futures::{SinkExt, StreamExt};
+                use tokio_tungstenite::tungstenite::Message;
+
+                ws.send(Message::Text(body.to_string())).await.map_err(connection_error)?;
+                loop {
+                    match ws.next().await {
+                        Some(Ok(Message::Text(text))) => return Ok(text),
+                        Some(Ok(Message::Binary(data))) => {
+                            return String::from_utf8(data).map_err(connection_error)
+                        }
+                        // pings are answered while reading
+                        Some(Ok(_)) => continue,
+                        Some(Err(err)) => return Err(connection_error(err)),
+                        None => return Err(connection_error("the connection was closed")),
+                    }
+                }
+            }
+            #[cfg(unix)]
+            Connection::Ipc { stream, buf } => {
+                use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+                stream.write_all(body.as_bytes()).await.map_err(connection_error)?;
+                let mut chunk = [0u8; 4096];
+                loop {
+                    // the responses aren't delimited, one is complete once it parses
+                    let parsed = {
+                        let mut values = serde_json::Deserializer::from_slice(buf)
+                            .into_iter::<serde::de::IgnoredAny>();
+                        match values.next() {
+                            Some(Ok(_)) => Some(values.byte_offset()),
+                            Some(Err(err)) if !err.is_eof() => {
+                                let text = String::from_utf8_lossy(buf).into_owned();
+                                return Err(HttpClientError::SerdeJson { err, text }.into())
+                            }
+                            _ => None,
+                        }
+                    };
+                    if let Some(end) = parsed {
+                        let rest = buf.split_off(end);
+                        let text = std::mem::replace(buf, rest);
+                        return String::from_utf8(text).map_err(connection_error)
+                    }
+                    let n = stream.read(&mut chunk).await.map_err(connection_error)?;
+                    if n == 0 {
+                        return Err(connection_error("the connection was closed"))
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                }
+            }
+        }
+    }
+}
+
+/// What a queued request is told once its batch was sent, or its leader went away
+enum Answer {
+    Response(Result<Value, ClientError>),
+    /// The request which was going to send the batch was dropped, so this request sends it
+    /// instead
+    Lead,
+}
+
+/// A queued request which waits for its batch to be sent
+struct Queued {
+    method: String,
+    params: Value,
+    tx: oneshot::Sender<Answer>,
+}
+
+impl Debug for Queued {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Queued").field("method", &self.method).finish_non_exhaustive()
+    }
+}
+
+/// A [JsonRpcClient] over a single HTTP, WebSocket or IPC endpoint, which sends concurrent
+/// requests as a single JSON-RPC batch.
+///
+/// The first request of a batch waits for the flush interval, or until the batch is full, before
+/// the whole batch is sent. Batches of a single request are sent as regular requests, and if
+/// the endpoint rejects a batch, its requests are sent one by one instead. If the first request
+/// is dropped before the batch was sent, e.g. because it timed out, the next queued request
+/// sends the batch right away.
+///
+/// Duplicate requests are already merged by the [SharedBackend](super::SharedBackend), so they
+/// are not deduplicated here.
+#[derive(Debug, Clone)]
+pub struct BatchClient {
+    transport: Transport,
+    config: BatchConfig,
+    queue: Arc<Mutex<Vec<Queued>>>,
+    /// The open WebSocket or IPC connection, which is taken while a request is sent over it
+    connection: Arc<futures::lock::Mutex<Option<Connection>>>,
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    jsonrpc: &'static str,
+    id: usize,
+    method: &'a str,
+    params: &'a Value,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: usize,
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<Value>,
+}
+
+impl Response {
+    fn into_result(self, text: &str) -> Result<Value, HttpClientError> {
+        match (self.result, self.error) {
+            (_, Some(err)) => Err(serde_json::from_value(err)
+                .map(HttpClientError::JsonRpcError)
+                .unwrap_or_else(|err| HttpClientError::SerdeJson { err, text: text.to_string() })),
+            (result, None) => Ok(result.unwrap_or_default()),
+        }
+    }
+}
+
+impl BatchClient {
+    pub fn new(url: Url, config: BatchConfig) -> Self {
+        Self::with_client(url, Client::new(), config)
+    }
+
+    /// Creates a new client which sends all requests with the given [Client], e.g. to attach
+    /// authentication headers
+    pub fn with_client(url: Url, client: Client, config: BatchConfig) -> Self {
+        Self::with_transport(Transport::Http { client, url }, config)
+    }
+
+    /// Creates a new client which sends all requests over the given [Transport]
+    pub fn with_transport(transport: Transport, config: BatchConfig) -> Self {
+        Self { transport, config, queue: Default::default(), connection: Default::default() }
+    }
+
+    /// Sends the JSON body to the endpoint and returns the raw response
+    async fn post<T: Serialize>(&self, body: &T) -> Result<String, ClientError> {
+        if let Transport::Http { client, url } = &self.transport {
+            let res = client.post(url.clone()).json(body).send().await.map_err(to_http)?;
+            let status = res.status();
+            let text = res.text().await.map_err(to_http)?;
+            // some endpoints answer JSON-RPC errors with an error status
+            if !status.is_success() && serde_json::from_str::<Value>(&text).is_err() {
+                return Err(ClientError::Status { status: status.as_u16(), body: text })
+            }
+            return Ok(text)
+        }
+        let body = serde_json::to_string(body)
+            .map_err(|err| HttpClientError::SerdeJson { err, text: String::new() })?;
+        let mut connection = self.connection.lock().await;
+        // the connection is taken, so that it's dropped if the request is, since its response
+        // would be read by the next request otherwise
+        let (mut conn, reused) = match connection.take() {
+            Some(conn) => (conn, true),
+            None => (self.transport.connect().await?, false),
+        };
+        let text = match conn.request(&body).await {
+            // the endpoint may have closed the idle connection
+            Err(err) if reused => {
+                tracing::trace!(?err, "request over the open connection failed, reconnecting");
+                conn = self.transport.connect().await?;
+                conn.request(&body).await?
+            }
+            res => res?,
+        };
+        *connection = Some(conn);
+        Ok(text)
+    }
+
+    /// Sends the batch, answering every queued request
+    async fn send(&self, batch: Vec<Queued>) {
+        // requests which were dropped in the meantime don't need an answer
+        let batch = batch.into_iter().filter(|req| !req.tx.is_canceled()).collect::<Vec<_>>();
+        match batch.len() {
+            0 => {}
+            1 => {
+                let Queued { method, params, tx } = batch.into_iter().next().unwrap();
+                let _ = tx.send(Answer::Response(self.send_single(&method, &params).await));
+            }
+            _ => match self.send_batch(&batch).await {
+                Ok(mut responses) => {
+                    for (id, Queued { method, params, tx }) in batch.into_iter().enumerate() {
+                        let res = match responses.remove(&id) {
+                            Some(res) => res,
+                            // the endpoint dropped the request, so we send it again
+                            None => self.send_single(&method, &params).await,
+                        };
+                        let _ = tx.send(Answer::Response(res));
+                    }
+                }
+                Err(err) => {
+                    tracing::trace!(?err, "batch request failed, sending requests one by one");
+                    for Queued { method, params, tx } in batch {
+                        let _ = tx.send(Answer::Response(self.send_single(&method, &params).await));
+                    }
+                }
+            },
+        }
+    }
+
+    /// Sends a regular, non-batched request
+    async fn send_single(&self, method: &str, params: &Value) -> Result<Value, ClientError> {
+        let req = Request { jsonrpc: "2.0", id: 0, method, params };
+        let text = self.post(&req).await?;
+        let res: Response = serde_json::from_str(&text)
+            .map_err(|err| HttpClientError::SerdeJson { err, text: text.clone() })?;
+        Ok(res.into_result(&text)?)
+    }
+
+    /// Sends the requests as a single batch and returns the responses by request id
+    async fn send_batch(
+        &self,
+        batch: &[Queued],
+    ) -> Result<HashMap<usize, Result<Value, ClientError>>, ClientError> {
+        let requests = batch
+            .iter()
+            .enumerate()
+            .map(|(id, req)| Request {
+                jsonrpc: "2.0",
+                id,
+                method: &req.method,
+                params: &req.params,
+            })
+            .collect::<Vec<_>>();
+        let text = self.post(&requests).await?;
+        let responses: Vec<Response> = serde_json::from_str(&text)
+            .map_err(|err| HttpClientError::SerdeJson { err, text: text.clone() })?;
+        Ok(responses
+            .into_iter()
+            .map(|res| (res.id, res.into_result(&text).map_err(Into::into)))
+            .collect())
+    }
+
+    /// Queues the request for the next batch and waits for its response
+    async fn request_batched(&self, method: &str, params: Value) -> Result<Value, ClientError> {
+        let mut lead = false;
+        loop {
+            let (tx, rx) = oneshot::channel();
+            let (is_first, is_full) = {
+                let mut queue = self.queue.lock();
+                queue.push(Queued { method: method.to_string(), params: params.clone(), tx });
+                (queue.len() == 1, queue.len() >= self.config.max_batch_size)
+            };
+            let mut pending = Pending { rx, queue: &self.queue, leading: is_first || lead };
+            // the first request sends the batch after the interval, unless it fills up before
+            if is_first && !is_full && !lead {
+                tokio::time::sleep(self.config.flush_interval).await;
+            }
+            if pending.leading || is_full {
+                pending.leading = false;
+                let batch = std::mem::take(&mut *self.queue.lock());
+                self.send(batch).await;
+            }
+            match (&mut pending.rx).await {
+                Ok(Answer::Response(res)) => return res,
+                // the request was taken out of the queue, and is queued again to send the batch
+                Ok(Answer::Lead) => lead = true,
+                Err(_) => return Err(ClientError::Unanswered(method.to_string())),
+            }
+        }
+    }
+}
+
+/// A request waiting for its batch. If it was going to send the batch, dropping it hands this
+/// over to the next request in the queue.
+struct Pending<'a> {
+    rx: oneshot::Receiver<Answer>,
+    queue: &'a Mutex<Vec<Queued>>,
+    leading: bool,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if !self.leading {
+            return
+        }
+        let mut queue = self.queue.lock();
+        queue.retain(|req| !req.tx.is_connected_to(&self.rx));
+        // the next request which is still waiting sends the batch
+        while !queue.is_empty() {
+            if queue.remove(0).tx.send(Answer::Lead).is_ok() {
+                return
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for BatchClient {
+    type Error = ClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let params = serde_json::to_value(params)
+            .map_err(|err| HttpClientError::SerdeJson { err, text: method.to_string() })?;
+        let value = if self.config.max_batch_size <= 1 {
+            self.send_single(method, &params).await?
+        } else {
+            self.request_batched(method, params).await?
+        };
+        Ok(R::deserialize(&value)
+            .map_err(|err| HttpClientError::SerdeJson { err, text: value.to_string() })?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{Middleware, Provider};
+
+    #[test]
+    fn can_detect_transport() {
+        let detect = |url: &str| Transport::detect(url, Client::new()).unwrap();
+        assert!(matches!(detect("https://localhost:8545"), Transport::Http { .. }));
+        assert!(matches!(detect("wss://localhost:8546"), Transport::Ws(_)));
+        assert!(
+            matches!(detect("ipc:///tmp/geth.ipc"), Transport::Ipc(path) if path == PathBuf::from("/tmp/geth.ipc"))
+        );
+        assert!(matches!(detect("/tmp/geth.ipc"), Transport::Ipc(_)));
+        assert!(matches!(detect("geth.ipc"), Transport::Ipc(_)));
+        assert!(Transport::detect("localhost:8545", Client::new()).is_err());
+        assert!(Transport::detect("ftp://localhost", Client::new()).is_err());
+    }
+
+    /// A unix socket in the temp dir, which is removed if it's left over from an earlier run
+    #[cfg(unix)]
+    fn socket_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("foundry-{}-{}.ipc", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    /// Answers every request, single or batched, with the block number `0x10`, or never answers
+    /// if `answer` is false. Returns the received requests and the number of connections.
+    #[cfg(unix)]
+    fn serve_ipc(path: PathBuf, answer: bool) -> (Arc<Mutex<Vec<Value>>>, Arc<Mutex<usize>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(Mutex::new(0));
+        let listener = tokio::net::UnixListener::bind(path).unwrap();
+        let (requests, accepted) = (received.clone(), connections.clone());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                *accepted.lock() += 1;
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    loop {
+                        let n = stream.read(&mut chunk).await.unwrap();
+                        if n == 0 {
+                            return
+                        }
+                        buf.extend_from_slice(&chunk[..n]);
+                        let req = match serde_json::from_slice::<Value>(&buf) {
+                            Ok(req) => req,
+                            Err(_) => continue,
+                        };
+                        buf.clear();
+                        requests.lock().push(req.clone());
+                        if !answer {
+                            // keeps the connection open without answering
+                            return std::future::pending().await
+                        }
+                        let answer = |req: &Value| serde_json::json!({ "jsonrpc": "2.0", "id": req["id"], "result": "0x10" });
+                        let res = match &req {
+                            Value::Array(reqs) => Value::Array(reqs.iter().map(answer).collect()),
+                            req => answer(req),
+                        };
+                        stream.write_all(format!("{}\n", res).as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        (received, connections)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn can_request_over_ipc() {
+        let path = socket_path("batch");
+        serve_ipc(path.clone(), true);
+
+        let transport = Transport::detect(path.to_str().unwrap(), Client::new()).unwrap();
+        let provider =
+            Provider::new(BatchClient::with_transport(transport.clone(), Default::default()));
+        assert_eq!(provider.get_block_number().await.unwrap(), 16.into());
+
+        // concurrent requests are sent as a batch
+        let config = BatchConfig::new(2, Duration::from_millis(10));
+        let provider = Provider::new(BatchClient::with_transport(transport, config));
+        let (a, b) = futures::join!(provider.get_block_number(), provider.get_block_number());
+        assert_eq!((a.unwrap(), b.unwrap()), (16.into(), 16.into()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn keeps_the_ipc_connection_open() {
+        let path = socket_path("connection");
+        let (received, connections) = serve_ipc(path.clone(), true);
+        let provider = Provider::new(BatchClient::with_transport(
+            Transport::Ipc(path.clone()),
+            Default::default(),
+        ));
+
+        for _ in 0..3 {
+            assert_eq!(provider.get_block_number().await.unwrap(), 16.into());
+        }
+        assert_eq!(received.lock().len(), 3);
+        assert_eq!(*connections.lock(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn fails_with_a_connection_error() {
+        let path = socket_path("missing");
+        let client = BatchClient::with_transport(Transport::Ipc(path), Default::default());
+        let err = client.request::<_, ethers::types::U64>("eth_blockNumber", ()).await.unwrap_err();
+        assert!(matches!(err, ClientError::Connection(_)), "{:?}", err);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn batches_concurrent_requests() {
+        let path = socket_path("batches");
+        let (received, _) = serve_ipc(path.clone(), true);
+        let transport = Transport::Ipc(path.clone());
+        let config = BatchConfig::new(3, Duration::from_secs(10));
+        let provider = Provider::new(BatchClient::with_transport(transport, config));
+
+        // the batch is sent as soon as it's full, without waiting for the interval
+        let (a, b, c) = tokio::time::timeout(Duration::from_secs(5), async {
+            futures::join!(
+                provider.get_block_number(),
+                provider.get_block_number(),
+                provider.get_block_number()
+            )
+        })
+        .await
+        .unwrap();
+        assert_eq!((a.unwrap(), b.unwrap(), c.unwrap()), (16.into(), 16.into(), 16.into()));
+        let received = received.lock().clone();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].as_array().unwrap().len(), 3);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hands_over_the_batch_of_a_dropped_request() {
+        let path = socket_path("handover");
+        serve_ipc(path.clone(), true);
+        let config = BatchConfig::new(10, Duration::from_secs(10));
+        let provider =
+            Provider::new(BatchClient::with_transport(Transport::Ipc(path.clone()), config));
+
+        // the first request waits for the interval, the second one for the first
+        let mut first = Box::pin(provider.get_block_number());
+        let mut second = Box::pin(provider.get_block_number());
+        assert!(futures::poll!(&mut first).is_pending());
+        assert!(futures::poll!(&mut second).is_pending());
+        drop(first);
+        let block = tokio::time::timeout(Duration::from_secs(5), second).await.unwrap();
+        assert_eq!(block.unwrap(), 16.into());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn fails_requests_of_a_dropped_batch() {
+        let path = socket_path("dropped");
+        let (received, _) = serve_ipc(path.clone(), false);
+        let config = BatchConfig::new(10, Duration::from_millis(10));
+        let provider =
+            Provider::new(BatchClient::with_transport(Transport::Ipc(path.clone()), config));
+
+        let mut first = Box::pin(provider.get_block_number());
+        let mut second = Box::pin(provider.get_block_number());
+        assert!(futures::poll!(&mut first).is_pending());
+        assert!(futures::poll!(&mut second).is_pending());
+        // the first request sends the batch, which is never answered
+        assert!(tokio::time::timeout(Duration::from_millis(200), &mut first).await.is_err());
+        assert_eq!(received.lock().len(), 1);
+        drop(first);
+        let err = tokio::time::timeout(Duration::from_secs(5), second).await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("dropped before it was answered"), "{}", err);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_on_error_status() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.read(&mut [0u8; 4096]).await.unwrap();
+            let res = "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 4\r\n\r\nbusy";
+            stream.write_all(res.as_bytes()).await.unwrap();
+        });
+
+        let transport = Transport::detect(&url, Client::new()).unwrap();
+        let provider = Provider::new(BatchClient::with_transport(transport, Default::default()));
+        let err = provider.get_block_number().await.unwrap_err().to_string();
+        assert!(err.contains("status 503: busy"), "{}", err);
+    }
+}
@@ -3,7 +3,14 @@ use sputnik::backend::{Backend, Basic, MemoryAccount, MemoryVicinity};
 
 use ethers::{
     providers::Middleware,
-    types::{Address, BlockId, Bytes, TxHash, H160, H256, U256},
+    types::{
+        transaction::{
+            eip2718::TypedTransaction,
+            eip2930::{AccessList, AccessListWithGasUsed},
+        },
+        Address, Block, BlockId, BlockNumber, Bytes, EIP1186ProofResponse, Transaction,
+        TransactionRequest, TxHash, H160, H256, U256,
+    },
 };
 use futures::{
     channel::mpsc::{channel, Receiver, Sender},
@@ -11,21 +18,44 @@ use futures::{
     task::{Context, Poll},
     Future, FutureExt,
 };
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{hash_map::Entry, BTreeMap, HashMap},
+    cell::RefCell,
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
     pin::Pin,
     sync::{
-        mpsc::{channel as oneshot_channel, Sender as OneshotSender},
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{channel as oneshot_channel, Receiver as OneshotReceiver, Sender as OneshotSender},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
+use crate::sputnik::cheatcodes::BLOCK_HASH_WINDOW;
 use foundry_utils::RuntimeOrHandle;
 
+thread_local! {
+    /// The error of the last fork request of this thread which was refused because of the
+    /// request budget, since the [Backend] can't fail the execution which made it
+    static BUDGET_ERROR: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Takes the error of the fork requests this thread made since it was last taken which were
+/// refused because of the request budget, see [SharedBackend::with_request_budget]
+pub fn take_budget_error() -> Option<String> {
+    BUDGET_ERROR.with(|err| err.borrow_mut().take())
+}
+
 /// A basic in memory cache (address -> Account)
 pub type MemCache = BTreeMap<H160, MemoryAccount>;
 
+/// The `eth_getProof` responses fetched by a [SharedBackend], by account
+pub type ProofCache = BTreeMap<H160, EIP1186ProofResponse>;
+
 /// A state cache that can be shared across threads
 ///
 /// This can can be used as global state cache.
@@ -43,14 +73,641 @@ pub fn new_shared_cache<T>(cache: T) -> SharedCache<T> {
     Arc::new(RwLock::new(cache))
 }
 
+/// Persists the state fetched by a [SharedBackend] as json on disk, so that subsequent runs
+/// against the same fork don't need to fetch it again.
+///
+/// A single file can hold the state of multiple forks, the state of each fork is stored under a
+/// `<chain id>:<block number>` key so that different forks don't clobber each other. A cache
+/// created [in a directory](Self::in_dir) gives each fork its own file at
+/// `<chain id>/<block number>/state.json` within it instead, e.g. to share `~/.foundry/cache/rpc`
+/// across projects.
+///
+/// Writes go to a temporary file which is renamed into place, and concurrent writers, e.g.
+/// parallel test processes, are serialized by a lock file next to the cache file, so the cache is
+/// never corrupted.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    path: PathBuf,
+    key: String,
+    /// Accounts which are set up locally and must never be written to disk
+    exclude: BTreeSet<H160>,
+}
+
+/// The serializable representation of a cached account
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CachedAccount {
+    nonce: U256,
+    balance: U256,
+    code: Bytes,
+    storage: BTreeMap<H256, H256>,
+}
+
+/// The cached state of a fork, the proofs are stored next to the accounts under a `proofs` key
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CachedFork {
+    #[serde(flatten)]
+    accounts: BTreeMap<H160, CachedAccount>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    proofs: ProofCache,
+}
+
+/// The content of a cache file: `<chain id>:<block number> -> (address -> account)`
+type CacheFile = BTreeMap<String, CachedFork>;
+
+/// How long a writer waits for the lock of a cache file
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A lock file older than this is left behind by a crashed writer and removed
+const STALE_LOCK: Duration = Duration::from_secs(60);
+
+/// An exclusive lock on a cache file, held as long as the lock file exists.
+///
+/// The lock file is created atomically and holds a token of its owner, so that the owner only
+/// ever removes its own lock. A stale lock is taken over by atomically renaming it out of the
+/// way, so that only one of the writers waiting for it removes it.
+#[derive(Debug)]
+struct FileLock {
+    path: PathBuf,
+    token: String,
+}
+
+/// Whether the lock file was left behind by a crashed writer
+fn is_stale_lock(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .map(|modified| modified.elapsed().unwrap_or_default() > STALE_LOCK)
+        .unwrap_or(false)
+}
+
+impl FileLock {
+    fn acquire(path: &Path) -> eyre::Result<Self> {
+        static LOCK_ID: AtomicU64 = AtomicU64::new(0);
+        let lock = path.with_extension("lock");
+        let token = format!("{}.{}", std::process::id(), LOCK_ID.fetch_add(1, Ordering::Relaxed));
+        let start = Instant::now();
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(mut file) => {
+                    file.write_all(token.as_bytes())?;
+                    return Ok(Self { path: lock, token })
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if is_stale_lock(&lock) {
+                        let claimed = lock.with_extension(format!("lock.{}.stale", token));
+                        if fs::rename(&lock, &claimed).is_ok() {
+                            if is_stale_lock(&claimed) {
+                                let _ = fs::remove_file(&claimed);
+                            } else {
+                                // another writer took the stale lock over in the meantime
+                                let _ = fs::rename(&claimed, &lock);
+                            }
+                        }
+                        continue
+                    }
+                    if start.elapsed() > LOCK_TIMEOUT {
+                        eyre::bail!("timed out waiting for the lock {:?}", lock)
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // the lock may have been taken over if this writer was stuck for too long
+        if fs::read_to_string(&self.path).map_or(false, |owner| owner == self.token) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl DiskCache {
+    /// Creates a new cache for the state of the given chain at the given block, stored in the
+    /// file at `path` next to the state of other forks cached in the same file.
+    pub fn new(path: impl Into<PathBuf>, chain_id: u64, block_number: u64) -> Self {
+        Self {
+            path: path.into(),
+            key: format!("{}:{}", chain_id, block_number),
+            exclude: Default::default(),
+        }
+    }
+
+    /// Creates a new cache for the state of the given chain at the given block, stored in a file
+    /// of its own at `<chain id>/<block number>/state.json` within the directory `dir`.
+    pub fn in_dir(dir: impl AsRef<Path>, chain_id: u64, block_number: u64) -> Self {
+        let path = dir
+            .as_ref()
+            .join(chain_id.to_string())
+            .join(block_number.to_string())
+            .join("state.json");
+        Self::new(path, chain_id, block_number)
+    }
+
+    /// Excludes the given accounts from being written to disk
+    pub fn exclude(mut self, accounts: impl IntoIterator<Item = H160>) -> Self {
+        self.exclude.extend(accounts);
+        self
+    }
+
+    /// The path of the cache file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn read_file(&self) -> eyre::Result<CacheFile> {
+        if !self.path.exists() {
+            return Ok(Default::default());
+        }
+        let file = fs::File::open(&self.path)?;
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    }
+
+    /// Reads the cached state of this fork, this is empty if nothing was cached yet
+    pub fn load(&self) -> eyre::Result<MemCache> {
+        let mut file = self.read_file()?;
+        Ok(file
+            .remove(&self.key)
+            .unwrap_or_default()
+            .accounts
+            .into_iter()
+            .map(|(addr, acc)| {
+                let acc = MemoryAccount {
+                    nonce: acc.nonce,
+                    balance: acc.balance,
+                    code: acc.code.to_vec(),
+                    storage: acc.storage,
+                };
+                (addr, acc)
+            })
+            .collect())
+    }
+
+    /// Reads the cached proofs of this fork, this is empty if nothing was cached yet
+    pub fn load_proofs(&self) -> eyre::Result<ProofCache> {
+        Ok(self.read_file()?.remove(&self.key).unwrap_or_default().proofs)
+    }
+
+    /// Writes the state and the proofs of this fork to disk, leaving the state of other forks in
+    /// the same file untouched
+    pub fn flush(&self, cache: &MemCache, proofs: &ProofCache) -> eyre::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let _lock = FileLock::acquire(&self.path)?;
+        let mut file = self.read_file().unwrap_or_default();
+        let accounts = cache
+            .iter()
+            .filter(|(addr, _)| !self.exclude.contains(addr))
+            .map(|(addr, acc)| {
+                let acc = CachedAccount {
+                    nonce: acc.nonce,
+                    balance: acc.balance,
+                    code: acc.code.clone().into(),
+                    storage: acc.storage.clone(),
+                };
+                (*addr, acc)
+            })
+            .collect();
+        file.insert(self.key.clone(), CachedFork { accounts, proofs: proofs.clone() });
+        self.write_file(&file)
+    }
+
+    /// Removes the cached state of this fork, leaving the state of other forks untouched
+    pub fn clear(&self) -> eyre::Result<()> {
+        if !self.path.exists() {
+            return Ok(())
+        }
+        let _lock = FileLock::acquire(&self.path)?;
+        let mut file = self.read_file()?;
+        file.remove(&self.key);
+        if file.is_empty() {
+            fs::remove_file(&self.path)?;
+            return Ok(())
+        }
+        self.write_file(&file)
+    }
+
+    /// Replaces the cache file, the lock must be held
+    fn write_file(&self, file: &CacheFile) -> eyre::Result<()> {
+        // write to a temporary file first so that an interrupted write can't corrupt the cache,
+        // and readers only ever see a complete file
+        static TMP_ID: AtomicU64 = AtomicU64::new(0);
+        let tmp = self.path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            TMP_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp, serde_json::to_vec(file)?)?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Flushes the cache to disk once the last [SharedBackend] referencing it is dropped
+#[derive(Debug)]
+struct CacheFlusher {
+    disk: DiskCache,
+    cache: SharedCache<MemCache>,
+    proofs: SharedCache<ProofCache>,
+}
+
+impl CacheFlusher {
+    fn flush(&self) -> eyre::Result<()> {
+        self.disk.flush(&self.cache.read(), &self.proofs.read())
+    }
+}
+
+impl Drop for CacheFlusher {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            tracing::warn!("Failed to write fork cache to {:?}: {}", self.disk.path(), err);
+        }
+    }
+}
+
+/// Counters of how the requests to a [SharedBackend] were answered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Requests answered from the cache
+    pub hits: u64,
+    /// Requests for state which was not cached yet
+    pub misses: u64,
+    /// Requests for cached state which was older than the TTL and fetched again
+    pub expired: u64,
+    /// Fetched accounts and storage slots which are counted against the cache limit
+    pub entries: u64,
+    /// Accounts and storage slots which were removed to stay within the cache limit
+    pub evictions: u64,
+    /// Accounts, storage slots and blocks which were fetched from the endpoint
+    pub fetches: u64,
+}
+
+/// A cached account, one of its storage slots, or its proof
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum CacheKey {
+    Account(Address),
+    Storage(Address, H256),
+    Proof(Address),
+}
+
+/// The least recently used order of the fetched state.
+///
+/// Accounts, storage slots and proofs count as one entry each. Evicting an account also evicts all
+/// of its storage slots.
+#[derive(Debug, Default)]
+struct Lru {
+    max_entries: usize,
+    /// Incremented on every access
+    clock: u64,
+    entries: BTreeMap<CacheKey, u64>,
+    order: BTreeMap<u64, CacheKey>,
+}
+
+impl Lru {
+    fn touch(&mut self, key: CacheKey) {
+        self.clock += 1;
+        if let Some(last_used) = self.entries.insert(key, self.clock) {
+            self.order.remove(&last_used);
+        }
+        self.order.insert(self.clock, key);
+    }
+
+    /// Marks the key as used, if it is tracked
+    fn hit(&mut self, key: CacheKey) {
+        if self.entries.contains_key(&key) {
+            self.touch(key)
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(last_used) = self.entries.remove(key) {
+            self.order.remove(&last_used);
+        }
+    }
+
+    /// Removes the least recently used entry, together with the storage slots if it is an
+    /// account
+    fn pop(&mut self) -> Option<Vec<CacheKey>> {
+        let (last_used, key) = self.order.iter().next().map(|(k, v)| (*k, *v))?;
+        self.order.remove(&last_used);
+        self.entries.remove(&key);
+        let mut evicted = vec![key];
+        if let CacheKey::Account(address) = key {
+            let slots = self
+                .entries
+                .range(
+                    CacheKey::Storage(address, H256::zero())..=
+                        CacheKey::Storage(address, H256::repeat_byte(0xff)),
+                )
+                .map(|(key, _)| *key)
+                .collect::<Vec<_>>();
+            for slot in slots {
+                self.remove(&slot);
+                evicted.push(slot);
+            }
+        }
+        Some(evicted)
+    }
+}
+
+/// Tracks when the remote state was fetched, so that it can be fetched again once it is older
+/// than the TTL.
+///
+/// State without a fetch time, e.g. locally set up accounts, never expires. Likewise only the
+/// state fetched after a cache limit was set can be evicted, so locally set up accounts are never
+/// lost.
+#[derive(Debug, Default)]
+struct CacheControl {
+    /// Whether the state is fetched from a pinned block, which never changes
+    pinned: bool,
+    /// Whether the state is fetched from the pending block, which changes with every transaction
+    /// in the pool, so fetched state is never answered from the cache
+    volatile: bool,
+    ttl: RwLock<Option<Duration>>,
+    /// Whether expired state is answered from the cache while it is fetched again
+    revalidate: AtomicBool,
+    accounts: RwLock<HashMap<Address, Instant>>,
+    storage: RwLock<HashMap<(Address, H256), Instant>>,
+    proofs: RwLock<HashMap<Address, Instant>>,
+    /// Set if the number of fetched entries is limited
+    lru: Mutex<Option<Lru>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    expired: AtomicU64,
+    evictions: AtomicU64,
+    /// The maximum number of fetches, if limited
+    budget: RwLock<Option<u64>>,
+    fetches: AtomicU64,
+}
+
+impl CacheControl {
+    fn new(pinned: bool) -> Self {
+        Self { pinned, ..Default::default() }
+    }
+
+    /// The cache control of the given block
+    fn for_block(block: Option<BlockId>) -> Self {
+        match block {
+            None | Some(BlockId::Number(BlockNumber::Latest)) => Self::new(false),
+            Some(BlockId::Number(BlockNumber::Pending)) => {
+                // fetch times are only recorded if there is a ttl
+                Self {
+                    volatile: true,
+                    ttl: RwLock::new(Some(Duration::ZERO)),
+                    ..Default::default()
+                }
+            }
+            Some(_) => Self::new(true),
+        }
+    }
+
+    fn set_ttl(&self, ttl: Duration) {
+        if self.pinned || self.volatile {
+            tracing::trace!("the fork is pinned to a block or pending, ignoring the cache ttl");
+            return
+        }
+        *self.ttl.write() = Some(ttl);
+    }
+
+    fn set_stale_while_revalidate(&self) {
+        if self.pinned || self.volatile {
+            tracing::trace!(
+                "the fork is pinned to a block or pending, ignoring stale-while-revalidate"
+            );
+            return
+        }
+        self.revalidate.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether expired state is answered from the cache and fetched again in the background
+    fn revalidates(&self) -> bool {
+        self.revalidate.load(Ordering::Relaxed)
+    }
+
+    fn set_max_entries(&self, max_entries: usize) {
+        let mut lru = self.lru.lock();
+        match lru.as_mut() {
+            Some(lru) => lru.max_entries = max_entries,
+            None => *lru = Some(Lru { max_entries, ..Default::default() }),
+        }
+    }
+
+    fn set_budget(&self, max_fetches: u64) {
+        *self.budget.write() = Some(max_fetches);
+    }
+
+    /// Counts a fetch from the endpoint against the budget, returns `false` if the budget is
+    /// used up and the state must not be fetched
+    fn fetch(&self) -> bool {
+        match *self.budget.read() {
+            Some(budget) if self.fetches.load(Ordering::Relaxed) >= budget => false,
+            _ => {
+                self.fetches.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+
+    /// The error of the requests which are refused because the budget is used up
+    fn budget_error(&self) -> Option<eyre::Report> {
+        match *self.budget.read() {
+            Some(budget) if self.fetches.load(Ordering::Relaxed) >= budget => Some(eyre::eyre!(
+                "the fork request budget of {} fetches was exceeded, the state is likely not cached",
+                budget
+            )),
+            _ => None,
+        }
+    }
+
+    fn is_expired(&self, fetched_at: Option<&Instant>) -> bool {
+        if self.volatile {
+            return fetched_at.is_some()
+        }
+        match (*self.ttl.read(), fetched_at) {
+            (Some(ttl), Some(fetched_at)) => fetched_at.elapsed() > ttl,
+            _ => false,
+        }
+    }
+
+    fn account_expired(&self, address: Address) -> bool {
+        self.is_expired(self.accounts.read().get(&address))
+    }
+
+    fn storage_expired(&self, address: Address, idx: H256) -> bool {
+        self.is_expired(self.storage.read().get(&(address, idx)))
+    }
+
+    fn proof_expired(&self, address: Address) -> bool {
+        self.is_expired(self.proofs.read().get(&address))
+    }
+
+    fn account_fetched(&self, address: Address) {
+        if self.ttl.read().is_some() {
+            self.accounts.write().insert(address, Instant::now());
+        }
+        if let Some(lru) = self.lru.lock().as_mut() {
+            lru.touch(CacheKey::Account(address));
+        }
+    }
+
+    fn storage_fetched(&self, address: Address, idx: H256) {
+        if self.ttl.read().is_some() {
+            self.storage.write().insert((address, idx), Instant::now());
+        }
+        if let Some(lru) = self.lru.lock().as_mut() {
+            lru.touch(CacheKey::Storage(address, idx));
+        }
+    }
+
+    fn proof_fetched(&self, address: Address) {
+        if self.ttl.read().is_some() {
+            self.proofs.write().insert(address, Instant::now());
+        }
+        if let Some(lru) = self.lru.lock().as_mut() {
+            lru.touch(CacheKey::Proof(address));
+        }
+    }
+
+    /// Records a request answered from the cache
+    fn hit(&self, key: CacheKey) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        if let Some(lru) = self.lru.lock().as_mut() {
+            lru.hit(key);
+        }
+    }
+
+    /// Removes the least recently used entries from the cache until the limit is met
+    fn evict(&self, cache: &SharedCache<MemCache>, proofs: &SharedCache<ProofCache>) {
+        let mut lru = self.lru.lock();
+        let lru = match lru.as_mut() {
+            Some(lru) => lru,
+            None => return,
+        };
+        let mut cache = cache.write();
+        while lru.entries.len() > lru.max_entries {
+            let evicted = match lru.pop() {
+                Some(evicted) => evicted,
+                None => break,
+            };
+            for key in evicted {
+                match key {
+                    CacheKey::Storage(address, idx) => {
+                        if let Some(acc) = cache.get_mut(&address) {
+                            acc.storage.remove(&idx);
+                        }
+                        self.storage.write().remove(&(address, idx));
+                    }
+                    CacheKey::Account(address) => {
+                        cache.remove(&address);
+                        self.accounts.write().remove(&address);
+                    }
+                    CacheKey::Proof(address) => {
+                        proofs.write().remove(&address);
+                        self.proofs.write().remove(&address);
+                    }
+                }
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Records a request which has to be fetched, `cached` if a stale value was cached
+    fn miss(&self, cached: bool) {
+        let counter = if cached { &self.expired } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            entries: self
+                .lru
+                .lock()
+                .as_ref()
+                .map(|lru| lru.entries.len() as u64)
+                .unwrap_or_default(),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            fetches: self.fetches.load(Ordering::Relaxed),
+        }
+    }
+}
+
 type AccountFuture<Err> =
     Pin<Box<dyn Future<Output = (Result<(U256, U256, Bytes), Err>, Address)> + Send>>;
 type StorageFuture<Err> = Pin<Box<dyn Future<Output = (Result<H256, Err>, Address, H256)> + Send>>;
+type BlockFuture<Err> = Pin<
+    Box<
+        dyn Future<
+                Output = (
+                    Result<Option<Block<Transaction>>, Err>,
+                    u64,
+                    OneshotSender<eyre::Result<Block<Transaction>>>,
+                ),
+            > + Send,
+    >,
+>;
+
+type BlockHashFuture<Err> = Pin<
+    Box<
+        dyn Future<
+                Output = (
+                    Result<Option<Block<TxHash>>, Err>,
+                    u64,
+                    OneshotSender<eyre::Result<H256>>,
+                ),
+            > + Send,
+    >,
+>;
+
+type TransactionFuture<Err> = Pin<
+    Box<
+        dyn Future<
+                Output = (
+                    Result<Option<Transaction>, Err>,
+                    TxHash,
+                    OneshotSender<eyre::Result<Transaction>>,
+                ),
+            > + Send,
+    >,
+>;
+
+type ProofFuture<Err> = Pin<
+    Box<
+        dyn Future<
+                Output = (
+                    Result<EIP1186ProofResponse, Err>,
+                    Address,
+                    OneshotSender<eyre::Result<EIP1186ProofResponse>>,
+                ),
+            > + Send,
+    >,
+>;
+
+type AccessListFuture<Err> = Pin<
+    Box<
+        dyn Future<
+                Output = (
+                    Result<AccessListWithGasUsed, Err>,
+                    OneshotSender<eyre::Result<AccessList>>,
+                ),
+            > + Send,
+    >,
+>;
 
 /// Request variants that are executed by the provider
 enum ProviderRequest<Err> {
     Account(AccountFuture<Err>),
     Storage(StorageFuture<Err>),
+    Block(BlockFuture<Err>),
+    BlockHash(BlockHashFuture<Err>),
+    Transaction(TransactionFuture<Err>),
+    Proof(ProofFuture<Err>),
+    AccessList(AccessListFuture<Err>),
 }
 
 /// The Request type the Backend listens for
@@ -60,6 +717,18 @@ enum BackendRequest {
     Exists(Address, OneshotSender<bool>),
     Code(Address, OneshotSender<Vec<u8>>),
     Storage(Address, H256, OneshotSender<H256>),
+    /// Blocks are not cached (they are only fetched when stepping a fork), so they are always
+    /// requested from the provider
+    Block(u64, OneshotSender<eyre::Result<Block<Transaction>>>),
+    /// The hash of a block, which is cached by the [SharedBackend] itself
+    BlockHash(u64, OneshotSender<eyre::Result<H256>>),
+    /// Transactions are not cached either, like blocks
+    Transaction(TxHash, OneshotSender<eyre::Result<Transaction>>),
+    /// The `eth_getProof` of an account and its storage slots
+    Proof(Address, Vec<H256>, OneshotSender<eyre::Result<EIP1186ProofResponse>>),
+    /// The `eth_createAccessList` of a transaction, which depends on the transaction and not only
+    /// on the state, so it's not cached
+    AccessList(TypedTransaction, OneshotSender<eyre::Result<AccessList>>),
 }
 
 /// Various types of senders waiting for an answer related to get_account request
@@ -78,6 +747,8 @@ struct BackendHandler<M: Middleware> {
     provider: M,
     /// Stores the state.
     cache: SharedCache<MemCache>,
+    /// When the cached state was fetched
+    control: Arc<CacheControl>,
     /// Requests currently in progress
     pending_requests: Vec<ProviderRequest<M::Error>>,
     /// Listeners that wait for a `get_account` related response
@@ -87,6 +758,9 @@ struct BackendHandler<M: Middleware> {
     account_requests: HashMap<Address, (Vec<AccountListener>, BTreeMap<H256, H256>)>,
     /// Listeners that wait for a `get_storage_at` response
     storage_requests: HashMap<(Address, H256), Vec<OneshotSender<H256>>>,
+    /// The fetched proofs with all storage proofs fetched for the same storage root, they can't
+    /// be derived from the cached state
+    proofs: SharedCache<ProofCache>,
     /// Incoming commands.
     incoming: Fuse<Receiver<BackendRequest>>,
     /// The block to fetch data from.
@@ -101,15 +775,19 @@ where
     fn new(
         provider: M,
         cache: SharedCache<MemCache>,
+        proofs: SharedCache<ProofCache>,
+        control: Arc<CacheControl>,
         rx: Receiver<BackendRequest>,
         block_id: Option<BlockId>,
     ) -> Self {
         Self {
             provider,
             cache,
+            control,
             pending_requests: Default::default(),
             account_requests: Default::default(),
             storage_requests: Default::default(),
+            proofs,
             incoming: rx.fuse(),
             block_id,
         }
@@ -118,7 +796,8 @@ where
     /// handle the request in queue in the future.
     ///
     /// We always check:
-    ///  1. if the requested value is already stored in the cache, then answer the sender
+    ///  1. if the requested value is already stored in the cache and not expired, then answer the
+    /// sender
     ///  2. otherwise, fetch it via the provider but check if a request for that value is already in
     /// progress (e.g. another Sender just requested the same account)
     fn on_request(&mut self, req: BackendRequest) {
@@ -129,10 +808,20 @@ where
                     lock.get(&addr).map(|acc| Basic { nonce: acc.nonce, balance: acc.balance });
                 // release the lock
                 drop(lock);
-                if let Some(basic) = basic {
-                    let _ = sender.send(basic);
-                } else {
-                    self.request_account(addr, AccountListener::Basic(sender));
+                match basic {
+                    Some(basic) if !self.control.account_expired(addr) => {
+                        self.control.hit(CacheKey::Account(addr));
+                        let _ = sender.send(basic);
+                    }
+                    Some(basic) if self.control.revalidates() => {
+                        self.control.miss(true);
+                        self.revalidate_account(addr);
+                        let _ = sender.send(basic);
+                    }
+                    basic => {
+                        self.control.miss(basic.is_some());
+                        self.request_account(addr, AccountListener::Basic(sender));
+                    }
                 }
             }
             BackendRequest::Code(addr, sender) => {
@@ -140,10 +829,20 @@ where
                 let code = lock.get(&addr).map(|acc| acc.code.clone());
                 // release the lock
                 drop(lock);
-                if let Some(basic) = code {
-                    let _ = sender.send(basic);
-                } else {
-                    self.request_account(addr, AccountListener::Code(sender));
+                match code {
+                    Some(code) if !self.control.account_expired(addr) => {
+                        self.control.hit(CacheKey::Account(addr));
+                        let _ = sender.send(code);
+                    }
+                    Some(code) if self.control.revalidates() => {
+                        self.control.miss(true);
+                        self.revalidate_account(addr);
+                        let _ = sender.send(code);
+                    }
+                    code => {
+                        self.control.miss(code.is_some());
+                        self.request_account(addr, AccountListener::Code(sender));
+                    }
                 }
             }
             BackendRequest::Exists(addr, sender) => {
@@ -158,9 +857,15 @@ where
                 // release the lock
                 drop(lock);
 
-                if has_account {
+                if has_account && !self.control.account_expired(addr) {
+                    self.control.hit(CacheKey::Account(addr));
+                    let _ = sender.send(exists);
+                } else if has_account && self.control.revalidates() {
+                    self.control.miss(true);
+                    self.revalidate_account(addr);
                     let _ = sender.send(exists);
                 } else {
+                    self.control.miss(has_account);
                     self.request_account(addr, AccountListener::Exists(sender));
                 }
             }
@@ -174,11 +879,21 @@ where
 
                 if has_account {
                     // account is already stored in the cache
-                    if let Some(value) = value {
-                        let _ = sender.send(value);
-                    } else {
-                        // account present but not storage -> fetch storage
-                        self.request_account_storage(addr, idx, sender);
+                    match value {
+                        Some(value) if !self.control.storage_expired(addr, idx) => {
+                            self.control.hit(CacheKey::Storage(addr, idx));
+                            let _ = sender.send(value);
+                        }
+                        Some(value) if self.control.revalidates() => {
+                            self.control.miss(true);
+                            self.revalidate_storage(addr, idx);
+                            let _ = sender.send(value);
+                        }
+                        value => {
+                            // account present but not storage -> fetch storage
+                            self.control.miss(value.is_some());
+                            self.request_account_storage(addr, idx, sender);
+                        }
                     }
                 } else {
                     // account is still missing in the cache
@@ -186,16 +901,106 @@ where
                     if let Some(value) =
                         self.account_requests.get(&addr).and_then(|(_, s)| s.get(&idx).copied())
                     {
+                        self.control.hit(CacheKey::Storage(addr, idx));
                         let _ = sender.send(value);
                     } else {
                         // fetch storage via provider
+                        self.control.miss(false);
                         self.request_account_storage(addr, idx, sender);
                     }
                 }
             }
+            BackendRequest::Block(number, sender) => {
+                if !self.control.fetch() {
+                    // dropping the sender fails the request
+                    return
+                }
+                let provider = self.provider.clone();
+                let fut = Box::pin(async move {
+                    let block = provider.get_block_with_txs(number).await;
+                    (block, number, sender)
+                });
+                self.pending_requests.push(ProviderRequest::Block(fut));
+            }
+            BackendRequest::BlockHash(number, sender) => {
+                if !self.control.fetch() {
+                    return
+                }
+                let provider = self.provider.clone();
+                let fut = Box::pin(async move {
+                    let block = provider.get_block(number).await;
+                    (block, number, sender)
+                });
+                self.pending_requests.push(ProviderRequest::BlockHash(fut));
+            }
+            BackendRequest::Transaction(hash, sender) => {
+                if !self.control.fetch() {
+                    return
+                }
+                let provider = self.provider.clone();
+                let fut = Box::pin(async move {
+                    let tx = provider.get_transaction(hash).await;
+                    (tx, hash, sender)
+                });
+                self.pending_requests.push(ProviderRequest::Transaction(fut));
+            }
+            BackendRequest::Proof(addr, slots, sender) => {
+                let cached = self
+                    .proofs
+                    .read()
+                    .get(&addr)
+                    .filter(|_| !self.control.proof_expired(addr))
+                    .and_then(|proof| select_proof(proof, &slots));
+                if let Some(proof) = cached {
+                    self.control.hit(CacheKey::Proof(addr));
+                    let _ = sender.send(Ok(proof));
+                    return
+                }
+                if !self.control.fetch() {
+                    return
+                }
+                let provider = self.provider.clone();
+                let block_id = self.block_id;
+                let fut = Box::pin(async move {
+                    let proof = provider.get_proof(addr, slots, block_id).await;
+                    (proof, addr, sender)
+                });
+                self.pending_requests.push(ProviderRequest::Proof(fut));
+            }
+            BackendRequest::AccessList(tx, sender) => {
+                if !self.control.fetch() {
+                    return
+                }
+                let provider = self.provider.clone();
+                let block_id = self.block_id;
+                let fut = Box::pin(async move {
+                    let access_list = provider.create_access_list(&tx, block_id).await;
+                    (access_list, sender)
+                });
+                self.pending_requests.push(ProviderRequest::AccessList(fut));
+            }
         }
     }
 
+    /// Caches the fetched proof, together with the storage proofs fetched before if the storage
+    /// root didn't change
+    fn cache_proof(&mut self, address: Address, mut proof: EIP1186ProofResponse) {
+        let mut proofs = self.proofs.write();
+        if let Some(cached) = proofs.remove(&address) {
+            if cached.storage_hash == proof.storage_hash {
+                for storage in cached.storage_proof {
+                    if !proof.storage_proof.iter().any(|p| p.key == storage.key) {
+                        proof.storage_proof.push(storage);
+                    }
+                }
+            }
+        }
+        proofs.insert(address, proof);
+        drop(proofs);
+        self.control.proof_fetched(address);
+        self.control.evict(&self.cache, &self.proofs);
+    }
+
     /// process a request for account's storage
     fn request_account_storage(
         &mut self,
@@ -208,14 +1013,61 @@ where
                 entry.get_mut().push(listener);
             }
             Entry::Vacant(entry) => {
+                if !self.control.fetch() {
+                    // dropping the listener fails the request
+                    return
+                }
                 entry.insert(vec![listener]);
-                let provider = self.provider.clone();
-                let block_id = self.block_id;
-                let fut = Box::pin(async move {
-                    let storage = provider.get_storage_at(address, idx, block_id).await;
-                    (storage, address, idx)
-                });
-                self.pending_requests.push(ProviderRequest::Storage(fut));
+                self.pending_requests.push(self.get_storage_req(address, idx));
+            }
+        }
+    }
+
+    /// returns the future that fetches the storage slot
+    fn get_storage_req(&self, address: Address, idx: H256) -> ProviderRequest<M::Error> {
+        let provider = self.provider.clone();
+        let block_id = self.block_id;
+        let fut = Box::pin(async move {
+            let storage = provider.get_storage_at(address, idx, block_id).await;
+            (storage, address, idx)
+        });
+        ProviderRequest::Storage(fut)
+    }
+
+    /// The balance, nonce and code of the cached account which is answered while it's
+    /// revalidated, if the backend serves stale state
+    fn stale_account(&self, address: Address) -> Option<(U256, U256, Vec<u8>)> {
+        if !self.control.revalidates() {
+            return None
+        }
+        self.cache.read().get(&address).map(|acc| (acc.balance, acc.nonce, acc.code.clone()))
+    }
+
+    /// The cached storage slot which is answered while it's revalidated, if the backend serves
+    /// stale state
+    fn stale_storage(&self, address: Address, idx: H256) -> Option<H256> {
+        if !self.control.revalidates() {
+            return None
+        }
+        self.cache.read().get(&address).and_then(|acc| acc.storage.get(&idx).copied())
+    }
+
+    /// Fetches the expired account again in the background, without anyone waiting for it
+    fn revalidate_account(&mut self, address: Address) {
+        if let Entry::Vacant(entry) = self.account_requests.entry(address) {
+            if self.control.fetch() {
+                entry.insert(Default::default());
+                self.pending_requests.push(self.get_account_req(address));
+            }
+        }
+    }
+
+    /// Fetches the expired storage slot again in the background, without anyone waiting for it
+    fn revalidate_storage(&mut self, address: Address, idx: H256) {
+        if let Entry::Vacant(entry) = self.storage_requests.entry((address, idx)) {
+            if self.control.fetch() {
+                entry.insert(Vec::new());
+                self.pending_requests.push(self.get_storage_req(address, idx));
             }
         }
     }
@@ -241,6 +1093,9 @@ where
                 entry.get_mut().0.push(listener);
             }
             Entry::Vacant(entry) => {
+                if !self.control.fetch() {
+                    return
+                }
                 entry.insert((vec![listener], Default::default()));
                 self.pending_requests.push(self.get_account_req(address));
             }
@@ -268,15 +1123,33 @@ where
             match &mut request {
                 ProviderRequest::Account(fut) => {
                     if let Poll::Ready((resp, addr)) = fut.poll_unpin(cx) {
-                        let (balance, nonce, code) = resp.unwrap_or_else(|_| {
-                            tracing::trace!("Failed to get account for {}", addr);
-                            Default::default()
-                        });
-                        let code = code.to_vec();
+                        let (balance, nonce, code, fetched) = match resp {
+                            Ok((balance, nonce, code)) => (balance, nonce, code.to_vec(), true),
+                            Err(_) => {
+                                tracing::trace!("Failed to get account for {}", addr);
+                                // the stale account stays expired, so it's fetched again on the
+                                // next access
+                                match pin.stale_account(addr) {
+                                    Some((balance, nonce, code)) => (balance, nonce, code, false),
+                                    None => (U256::zero(), U256::zero(), Vec::new(), true),
+                                }
+                            }
+                        };
                         let (listeners, storage) =
                             pin.account_requests.remove(&addr).unwrap_or_default();
-                        let acc = MemoryAccount { nonce, balance, code: code.clone(), storage };
-                        pin.cache.write().insert(addr, acc);
+                        if fetched {
+                            // an expired account is refetched, so its cached storage is kept,
+                            // stale slots expire on their own
+                            let mut cache = pin.cache.write();
+                            let acc = cache.entry(addr).or_default();
+                            acc.nonce = nonce;
+                            acc.balance = balance;
+                            acc.code = code.clone();
+                            acc.storage.extend(storage);
+                            drop(cache);
+                            pin.control.account_fetched(addr);
+                            pin.control.evict(&pin.cache, &pin.proofs);
+                        }
                         // notify all listeners
                         for listener in listeners {
                             match listener {
@@ -298,26 +1171,41 @@ where
                 }
                 ProviderRequest::Storage(fut) => {
                     if let Poll::Ready((resp, addr, idx)) = fut.poll_unpin(cx) {
-                        let value = resp.unwrap_or_else(|_| {
-                            tracing::trace!("Failed to get storage for {} at {}", addr, idx);
-                            Default::default()
-                        });
-                        if let Some(acc) = pin.cache.write().get_mut(&addr) {
-                            acc.storage.insert(idx, value);
-                        } else {
-                            // the account not fetched yet, we either add this value to the storage
-                            // buffer of the request in progress or start the `get_account` request
-                            match pin.account_requests.entry(addr) {
-                                Entry::Occupied(mut entry) => {
-                                    entry.get_mut().1.insert(idx, value);
+                        let (value, fetched) = match resp {
+                            Ok(value) => (value, true),
+                            Err(_) => {
+                                tracing::trace!("Failed to get storage for {} at {}", addr, idx);
+                                // the stale slot stays expired, so it's fetched again on the next
+                                // access
+                                match pin.stale_storage(addr, idx) {
+                                    Some(value) => (value, false),
+                                    None => (Default::default(), true),
                                 }
-                                Entry::Vacant(entry) => {
-                                    let mut storage = BTreeMap::new();
-                                    storage.insert(idx, value);
-                                    entry.insert((vec![], storage));
-                                    pin.pending_requests.push(pin.get_account_req(addr));
+                            }
+                        };
+                        if fetched {
+                            pin.control.storage_fetched(addr, idx);
+                            if let Some(acc) = pin.cache.write().get_mut(&addr) {
+                                acc.storage.insert(idx, value);
+                            } else {
+                                // the account not fetched yet, we either add this value to the
+                                // storage buffer of the request in progress or start the
+                                // `get_account` request
+                                match pin.account_requests.entry(addr) {
+                                    Entry::Occupied(mut entry) => {
+                                        entry.get_mut().1.insert(idx, value);
+                                    }
+                                    // the account is part of fetching the slot, so it doesn't
+                                    // count against the budget
+                                    Entry::Vacant(entry) => {
+                                        let mut storage = BTreeMap::new();
+                                        storage.insert(idx, value);
+                                        entry.insert((vec![], storage));
+                                        pin.pending_requests.push(pin.get_account_req(addr));
+                                    }
                                 }
                             }
+                            pin.control.evict(&pin.cache, &pin.proofs);
                         }
                         // notify all listeners
                         if let Some(listeners) = pin.storage_requests.remove(&(addr, idx)) {
@@ -328,6 +1216,64 @@ where
                         continue
                     }
                 }
+                ProviderRequest::Block(fut) => {
+                    if let Poll::Ready((resp, number, sender)) = fut.poll_unpin(cx) {
+                        let block = resp
+                            .map_err(|err| eyre::eyre!("failed to fetch block {}: {}", number, err))
+                            .and_then(|block| {
+                                block.ok_or_else(|| eyre::eyre!("block {} not found", number))
+                            });
+                        let _ = sender.send(block);
+                        continue
+                    }
+                }
+                ProviderRequest::BlockHash(fut) => {
+                    if let Poll::Ready((resp, number, sender)) = fut.poll_unpin(cx) {
+                        let hash = resp
+                            .map_err(|err| eyre::eyre!("failed to fetch block {}: {}", number, err))
+                            .and_then(|block| {
+                                block
+                                    .and_then(|block| block.hash)
+                                    .ok_or_else(|| eyre::eyre!("block {} not found", number))
+                            });
+                        let _ = sender.send(hash);
+                        continue
+                    }
+                }
+                ProviderRequest::Transaction(fut) => {
+                    if let Poll::Ready((resp, hash, sender)) = fut.poll_unpin(cx) {
+                        let tx = resp
+                            .map_err(|err| {
+                                eyre::eyre!("failed to fetch transaction {:?}: {}", hash, err)
+                            })
+                            .and_then(|tx| {
+                                tx.ok_or_else(|| eyre::eyre!("transaction {:?} not found", hash))
+                            });
+                        let _ = sender.send(tx);
+                        continue
+                    }
+                }
+                ProviderRequest::Proof(fut) => {
+                    if let Poll::Ready((resp, addr, sender)) = fut.poll_unpin(cx) {
+                        let proof = resp.map_err(|err| {
+                            eyre::eyre!("failed to fetch the proof of 0x{:x}: {}", addr, err)
+                        });
+                        if let Ok(proof) = &proof {
+                            pin.cache_proof(addr, proof.clone());
+                        }
+                        let _ = sender.send(proof);
+                        continue
+                    }
+                }
+                ProviderRequest::AccessList(fut) => {
+                    if let Poll::Ready((resp, sender)) = fut.poll_unpin(cx) {
+                        let access_list = resp.map(|resp| resp.access_list).map_err(|err| {
+                            eyre::eyre!("failed to create the access list: {}", err)
+                        });
+                        let _ = sender.send(access_list);
+                        continue
+                    }
+                }
             }
             // not ready, insert and poll again
             pin.pending_requests.push(request);
@@ -341,6 +1287,16 @@ where
     }
 }
 
+/// The cached proof with the storage proofs of the given slots in their order, if all of them
+/// are cached
+fn select_proof(proof: &EIP1186ProofResponse, slots: &[H256]) -> Option<EIP1186ProofResponse> {
+    let storage_proof = slots
+        .iter()
+        .map(|slot| proof.storage_proof.iter().find(|p| p.key == *slot).cloned())
+        .collect::<Option<Vec<_>>>()?;
+    Some(EIP1186ProofResponse { storage_proof, ..proof.clone() })
+}
+
 /// A cloneable backend type that shares access to the backend data with all its clones.
 ///
 /// This backend type is connected to the `BackendHandler` via a mpsc channel. The `BackendHandlers`
@@ -372,6 +1328,11 @@ impl SharedBackend {
     /// Spawns a new `BackendHandler` on a background thread that listens for requests from any
     /// `SharedBackend`. Missing values get inserted in the `cache`.
     ///
+    /// The state is fetched from the `pin_block`, or the latest block if it is `None`. The
+    /// pending block reflects the transactions in the node's pool, which makes results
+    /// non-deterministic since the pool changes all the time, so its state is fetched again on
+    /// every access.
+    ///
     /// NOTE: this should be called with `Arc<Provider>`
     pub fn new<M>(
         provider: M,
@@ -383,43 +1344,351 @@ impl SharedBackend {
         M: Middleware + Unpin + 'static + Clone,
     {
         let (tx, rx) = channel(1);
-        let handler = BackendHandler::new(provider, cache, rx, pin_block);
+        let control = Arc::new(CacheControl::for_block(pin_block));
+        let proofs: SharedCache<ProofCache> = Default::default();
+        let handler = BackendHandler::new(
+            provider,
+            cache.clone(),
+            proofs.clone(),
+            control.clone(),
+            rx,
+            pin_block,
+        );
         // spawn the provider handler to background
         let rt = RuntimeOrHandle::new();
-        std::thread::spawn(move || match rt {
+        let worker = std::thread::spawn(move || match rt {
             RuntimeOrHandle::Runtime(runtime) => runtime.block_on(handler),
             RuntimeOrHandle::Handle(handle) => handle.block_on(handler),
         });
 
-        Self { inner: SharedBackendInner { vicinity: Arc::new(vicinity), backend: tx } }
+        Self {
+            inner: SharedBackendInner {
+                vicinity: Arc::new(vicinity),
+                backend: tx,
+                cache,
+                proofs,
+                control,
+                flusher: None,
+                block_hash: None,
+                block_hashes: Default::default(),
+                worker: Arc::new(Mutex::new(Some(worker))),
+            },
+        }
+    }
+
+    /// Fetches cached state again once it is older than the `ttl`, e.g. when forking at the latest
+    /// block, which changes as the chain advances.
+    ///
+    /// This is ignored if the backend is pinned to a block, since its state never changes.
+    pub fn with_cache_ttl(self, ttl: Duration) -> Self {
+        self.inner.control.set_ttl(ttl);
+        self
+    }
+
+    /// Answers expired state from the cache right away and fetches it again in the background,
+    /// so that only the following reads see the fresh state.
+    ///
+    /// This is meant for interactive, REPL-style use against the latest block, where waiting for
+    /// every fetch is slow. What a read returns depends on how fast the endpoint answers, so it
+    /// must not be used for reproducible tests. State expires after the
+    /// [cache ttl](Self::with_cache_ttl), so without one nothing is fetched again.
+    ///
+    /// This is ignored if the backend is pinned to a block or forks the pending block.
+    pub fn with_stale_while_revalidate(self) -> Self {
+        self.inner.control.set_stale_while_revalidate();
+        self
+    }
+
+    /// Limits the number of fetched accounts, storage slots and proofs in the cache, the least
+    /// recently used ones are evicted once there are more and fetched again when needed.
+    ///
+    /// Only state fetched after this is called counts against the limit, the accounts the cache
+    /// already holds, e.g. locally set up or replayed ones, are never evicted.
+    pub fn with_cache_limit(self, max_entries: usize) -> Self {
+        self.inner.control.set_max_entries(max_entries);
+        self
+    }
+
+    /// Fails the requests of this backend and its clones once more than `max_requests` accounts,
+    /// storage slots or blocks were fetched from the endpoint, e.g. to catch tests in CI which
+    /// stopped hitting the cache.
+    ///
+    /// Requests answered from the cache or merged with a request in progress don't count. Since
+    /// the [Backend] can't return errors, the state which would exceed the budget is empty, and
+    /// the executor fails the call which accessed it, see [take_budget_error].
+    pub fn with_request_budget(self, max_requests: usize) -> Self {
+        self.inner.control.set_budget(max_requests as u64);
+        self
+    }
+
+    /// How the requests to this backend and its clones were answered so far
+    pub fn cache_stats(&self) -> CacheStats {
+        self.inner.control.stats()
+    }
+
+    /// Persists the cache to the given [DiskCache] when [SharedBackend::flush] is called and after
+    /// the last clone of this backend was dropped.
+    ///
+    /// The state must already be loaded into the cache the backend was created with, while the
+    /// proofs cached on disk are loaded right away.
+    pub fn with_disk_cache(mut self, disk: DiskCache) -> Self {
+        match disk.load_proofs() {
+            Ok(proofs) => self.inner.proofs.write().extend(proofs),
+            Err(err) => {
+                tracing::warn!("Failed to read fork cache {:?}: {}", disk.path(), err);
+            }
+        }
+        let (cache, proofs) = (self.inner.cache.clone(), self.inner.proofs.clone());
+        self.inner.flusher = Some(Arc::new(CacheFlusher { disk, cache, proofs }));
+        self
+    }
+
+    /// Fetches the given storage slots of the account concurrently, e.g. to warm up the cache
+    /// before a benchmark. Slots which are already cached and not expired are skipped.
+    pub fn prefetch(&self, address: H160, slots: &[U256]) -> eyre::Result<()> {
+        let missing = {
+            let lock = self.inner.cache.read();
+            let storage = lock.get(&address).map(|acc| &acc.storage);
+            slots
+                .iter()
+                .map(H256::from_uint)
+                .filter(|idx| {
+                    !storage.map(|storage| storage.contains_key(idx)).unwrap_or_default() ||
+                        self.inner.control.storage_expired(address, *idx)
+                })
+                .collect::<Vec<_>>()
+        };
+        // send all requests before waiting, so that the handler executes them concurrently
+        let receivers = missing
+            .into_iter()
+            .map(|idx| {
+                let (sender, rx) = oneshot_channel();
+                let req = BackendRequest::Storage(address, idx, sender);
+                self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
+                Ok(rx)
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        for rx in receivers {
+            self.recv(rx)?;
+        }
+        Ok(())
+    }
+
+    /// Fetches the accounts and their storage slots concurrently, like [SharedBackend::prefetch].
+    /// Accounts and slots which are already cached and not expired are answered by the cache.
+    pub fn prefetch_accounts(&self, accounts: &[(H160, Vec<H256>)]) -> eyre::Result<()> {
+        let mut basic = Vec::new();
+        let mut storage = Vec::new();
+        for (address, slots) in accounts {
+            let (sender, rx) = oneshot_channel();
+            let req = BackendRequest::Basic(*address, sender);
+            self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
+            basic.push(rx);
+            for idx in slots {
+                let (sender, rx) = oneshot_channel();
+                let req = BackendRequest::Storage(*address, *idx, sender);
+                self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
+                storage.push(rx);
+            }
+        }
+        for rx in basic {
+            self.recv(rx)?;
+        }
+        for rx in storage {
+            self.recv(rx)?;
+        }
+        Ok(())
+    }
+
+    /// The accounts and storage slots the transaction accesses at the forked block, like
+    /// `eth_createAccessList`
+    pub fn create_access_list(&self, tx: &TypedTransaction) -> eyre::Result<AccessList> {
+        let (sender, rx) = oneshot_channel();
+        let req = BackendRequest::AccessList(tx.clone(), sender);
+        self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
+        self.recv(rx)?
+    }
+
+    /// Fetches the state the transaction accesses concurrently, so that replaying it doesn't
+    /// fetch its accounts and slots one after another.
+    ///
+    /// The accessed state is created with `eth_createAccessList`. If the endpoint doesn't support
+    /// it, only the sender, the receiver and the access list declared by the transaction are
+    /// prefetched and the rest is fetched lazily during the replay.
+    pub fn prefetch_for_tx(&self, tx: &Transaction) -> eyre::Result<()> {
+        let mut accounts: BTreeMap<H160, BTreeSet<H256>> = BTreeMap::new();
+        accounts.entry(tx.from).or_default();
+        if let Some(to) = tx.to {
+            accounts.entry(to).or_default();
+        }
+
+        let mut request = TransactionRequest::new()
+            .from(tx.from)
+            .value(tx.value)
+            .data(tx.input.clone())
+            .gas(tx.gas);
+        if let Some(to) = tx.to {
+            request = request.to(to);
+        }
+        let created = self.create_access_list(&request.into()).unwrap_or_else(|err| {
+            tracing::debug!("prefetching the declared access list only: {}", err);
+            AccessList::default()
+        });
+        let declared = tx.access_list.iter().flat_map(|access_list| access_list.0.iter());
+        for item in created.0.iter().chain(declared) {
+            accounts.entry(item.address).or_default().extend(item.storage_keys.iter().copied());
+        }
+
+        let accounts = accounts
+            .into_iter()
+            .map(|(address, slots)| (address, slots.into_iter().collect()))
+            .collect::<Vec<_>>();
+        self.prefetch_accounts(&accounts)
+    }
+
+    /// Fetches the block with all its transactions from the provider
+    pub fn block_with_txs(&self, number: u64) -> eyre::Result<Block<Transaction>> {
+        let (sender, rx) = oneshot_channel();
+        let req = BackendRequest::Block(number, sender);
+        self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
+        self.recv(rx)?
+    }
+
+    /// Fetches the hash of the block from the provider, unless it was fetched before
+    fn fetch_block_hash(&self, number: U256) -> eyre::Result<H256> {
+        if let Some(hash) = self.inner.block_hashes.read().get(&number) {
+            return Ok(*hash)
+        }
+        let (sender, rx) = oneshot_channel();
+        let req = BackendRequest::BlockHash(number.as_u64(), sender);
+        self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
+        let hash = self.recv(rx)??;
+        self.inner.block_hashes.write().insert(number, hash);
+        Ok(hash)
+    }
+
+    /// Fetches the transaction with the given hash from the provider
+    pub fn transaction(&self, hash: TxHash) -> eyre::Result<Transaction> {
+        let (sender, rx) = oneshot_channel();
+        let req = BackendRequest::Transaction(hash, sender);
+        self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
+        self.recv(rx)?
+    }
+
+    /// Fetches the merkle proof of the account and the given storage slots at the forked block,
+    /// like `eth_getProof`.
+    ///
+    /// Proofs are cached like the rest of the remote state, so the same proof is only fetched
+    /// once, unless it expired.
+    pub fn get_proof(&self, address: H160, slots: &[H256]) -> eyre::Result<EIP1186ProofResponse> {
+        let (sender, rx) = oneshot_channel();
+        let req = BackendRequest::Proof(address, slots.to_vec(), sender);
+        self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
+        self.recv(rx)?
+    }
+
+    /// Moves the block environment to the given block, e.g. after its transactions were executed
+    /// on top of the fork. The state is still fetched from the forked block.
+    ///
+    /// If the block is the successor of the current one, the hash of the current block is kept
+    /// for `BLOCKHASH`.
+    pub fn set_block_env<TX>(&mut self, block: &Block<TX>) {
+        let mut vicinity = (*self.inner.vicinity).clone();
+        let number = U256::from(block.number.unwrap_or_default().as_u64());
+        if number != vicinity.block_number + 1 {
+            vicinity.block_hashes.clear();
+        }
+        // the most recent hash comes first
+        vicinity.block_hashes.insert(0, block.parent_hash);
+        vicinity.block_hashes.truncate(256);
+        vicinity.block_number = number;
+        vicinity.block_coinbase = block.author;
+        vicinity.block_timestamp = block.timestamp;
+        vicinity.block_difficulty = block.difficulty;
+        vicinity.block_gas_limit = block.gas_limit;
+        vicinity.block_base_fee_per_gas = block.base_fee_per_gas.unwrap_or_default();
+        self.inner.vicinity = Arc::new(vicinity);
+        self.inner.block_hash = block.hash;
+    }
+
+    /// The hash of the block set with [SharedBackend::set_block_env]
+    pub fn block_env_hash(&self) -> Option<H256> {
+        self.inner.block_hash
+    }
+
+    /// Sets the `tx.origin` and `tx.gasprice` of the following executions
+    pub(crate) fn set_tx_env(&mut self, origin: Address, gas_price: U256) {
+        let mut vicinity = (*self.inner.vicinity).clone();
+        vicinity.origin = origin;
+        vicinity.gas_price = gas_price;
+        self.inner.vicinity = Arc::new(vicinity);
+    }
+
+    /// The cache of all fetched accounts
+    pub fn cache(&self) -> &SharedCache<MemCache> {
+        &self.inner.cache
+    }
+
+    /// Writes the cache to disk, this is a noop if no [DiskCache] is configured.
+    pub fn flush(&self) -> eyre::Result<()> {
+        self.inner.flusher.as_ref().map(|flusher| flusher.flush()).transpose()?;
+        Ok(())
+    }
+
+    /// Stops the `BackendHandler` once the requests in progress are answered, waits for its
+    /// thread to finish and writes the cache to disk, e.g. so that the cache is complete before
+    /// the next step of a CI job reads it.
+    ///
+    /// The clones of this backend can't fetch remote state afterwards. Dropping the last clone
+    /// without calling this is still safe, but the cache is then written while requests may
+    /// still be in progress, so their state can be missing from the disk cache.
+    pub fn shutdown(mut self) -> eyre::Result<()> {
+        self.inner.backend.close_channel();
+        if let Some(worker) = self.inner.worker.lock().take() {
+            worker.join().map_err(|_| eyre::eyre!("the backend handler panicked"))?;
+        }
+        self.flush()
+    }
+
+    /// Waits for the answer of the handler, which drops the request if it exceeds the budget.
+    /// The refusal is also recorded for the execution on this thread, see [take_budget_error].
+    fn recv<T>(&self, rx: OneshotReceiver<T>) -> eyre::Result<T> {
+        rx.recv().map_err(|err| match self.inner.control.budget_error() {
+            Some(err) => {
+                BUDGET_ERROR
+                    .with(|budget_error| *budget_error.borrow_mut() = Some(err.to_string()));
+                err
+            }
+            None => err.into(),
+        })
     }
 
     fn do_get_exists(&self, address: H160) -> eyre::Result<bool> {
         let (sender, rx) = oneshot_channel();
         let req = BackendRequest::Exists(address, sender);
         self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
-        Ok(rx.recv()?)
+        self.recv(rx)
     }
 
     fn do_get_basic(&self, address: H160) -> eyre::Result<Basic> {
         let (sender, rx) = oneshot_channel();
         let req = BackendRequest::Basic(address, sender);
         self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
-        Ok(rx.recv()?)
+        self.recv(rx)
     }
 
     fn do_get_code(&self, address: H160) -> eyre::Result<Vec<u8>> {
         let (sender, rx) = oneshot_channel();
         let req = BackendRequest::Code(address, sender);
         self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
-        Ok(rx.recv()?)
+        self.recv(rx)
     }
 
     fn do_get_storage(&self, address: H160, index: H256) -> eyre::Result<H256> {
         let (sender, rx) = oneshot_channel();
         let req = BackendRequest::Storage(address, index, sender);
         self.inner.backend.clone().try_send(req).map_err(|e| eyre::eyre!("{:?}", e))?;
-        Ok(rx.recv()?)
+        self.recv(rx)
     }
 }
 
@@ -430,16 +1699,26 @@ impl Backend for SharedBackend {
     fn origin(&self) -> H160 {
         self.inner.vicinity.origin
     }
+    /// The hashes of the latest 256 blocks are fetched when they are not part of the block
+    /// environment. Unlike `BLOCKHASH`, the hash of the forked block itself is known as well, so
+    /// that it's still returned once the block number was advanced with `roll`.
     fn block_hash(&self, number: U256) -> H256 {
-        if number >= self.inner.vicinity.block_number ||
-            self.inner.vicinity.block_number - number - U256::one() >=
-                U256::from(self.inner.vicinity.block_hashes.len())
-        {
-            H256::default()
-        } else {
-            let index = (self.inner.vicinity.block_number - number - U256::one()).as_usize();
-            self.inner.vicinity.block_hashes[index]
+        let current = self.inner.vicinity.block_number;
+        if number > current || current - number > BLOCK_HASH_WINDOW.into() {
+            return H256::zero()
         }
+        let known = if number == current {
+            self.inner.block_hash
+        } else {
+            let index = (current - number - U256::one()).as_usize();
+            self.inner.vicinity.block_hashes.get(index).copied()
+        };
+        known.unwrap_or_else(|| {
+            self.fetch_block_hash(number).unwrap_or_else(|err| {
+                tracing::trace!("Failed to fetch the hash of block {}: {}", number, err);
+                Default::default()
+            })
+        })
     }
     fn block_number(&self) -> U256 {
         self.inner.vicinity.block_number
@@ -501,6 +1780,18 @@ impl Backend for SharedBackend {
 struct SharedBackendInner {
     vicinity: Arc<MemoryVicinity>,
     backend: Sender<BackendRequest>,
+    cache: SharedCache<MemCache>,
+    /// The fetched proofs, shared with the `BackendHandler`
+    proofs: SharedCache<ProofCache>,
+    control: Arc<CacheControl>,
+    flusher: Option<Arc<CacheFlusher>>,
+    /// The hash of the block of the environment, if it was set with
+    /// [SharedBackend::set_block_env]
+    block_hash: Option<H256>,
+    /// The block hashes which were fetched, shared with all clones
+    block_hashes: Arc<RwLock<BTreeMap<U256, H256>>>,
+    /// The thread of the `BackendHandler`, until it's joined by [SharedBackend::shutdown]
+    worker: Arc<Mutex<Option<std::thread::JoinHandle<()>>>>,
 }
 
 #[cfg(test)]
@@ -508,7 +1799,7 @@ mod tests {
     use crate::sputnik::vicinity;
     use ethers::{
         providers::{Http, Provider},
-        types::Address,
+        types::{transaction::eip2930::AccessListItem, Address},
     };
     use std::convert::TryFrom;
     use tokio::runtime::Runtime;
@@ -552,4 +1843,382 @@ mod tests {
         let mem_acc = cache.read().get(&address).unwrap().clone();
         assert_eq!(mem_acc.storage.len() as u64, max_slots);
     }
+
+    #[test]
+    fn can_deploy_create2_on_fork() {
+        use crate::{
+            compute_create2_address,
+            sputnik::helpers::{fork_backend, vm_with_backend},
+            Evm,
+        };
+        use ethers::{types::Bytes, utils::keccak256};
+
+        let mut evm = vm_with_backend(fork_backend(None));
+
+        // the deterministic deployment proxy runs CREATE2 with the salt and init code it is
+        // called with, and returns the address it deployed to
+        let proxy: Address = "4e59b44847b379578588920ca78fbf26c0b4956c".parse().unwrap();
+        let salt = H256::repeat_byte(1);
+        // deploys code which returns 42
+        let init_code = hex::decode("600a600c600039600a6000f3602a60005260206000f3").unwrap();
+        let calldata: Bytes = [salt.as_bytes(), &init_code].concat().into();
+        let (out, status, _, _) =
+            evm.call_raw(Address::zero(), proxy, calldata, 0.into(), false).unwrap();
+        assert!(matches!(status, sputnik::ExitReason::Succeed(_)), "{:?}", status);
+
+        let expected = compute_create2_address(proxy, salt, keccak256(&init_code).into());
+        assert_eq!(Address::from_slice(&out), expected);
+        assert_eq!(evm.code(expected), hex::decode("602a60005260206000f3").unwrap());
+    }
+
+    #[test]
+    fn prefetch_skips_cached_slots() {
+        // all slots are cached, so no request is sent to the endpoint
+        let provider = Provider::<Http>::try_from("http://localhost:8545").unwrap();
+        let address: Address = "63091244180ae240c87d1f528f5f269134cb07b3".parse().unwrap();
+        let mut cache = MemCache::default();
+        let mut storage = BTreeMap::new();
+        storage.insert(H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        cache.insert(address, MemoryAccount { storage, ..Default::default() });
+
+        let vicinity = crate::sputnik::helpers::new_vicinity();
+        let backend = SharedBackend::new(provider, new_shared_cache(cache), vicinity, None);
+        backend.prefetch(address, &[1.into()]).unwrap();
+        assert_eq!(backend.storage(address, H256::from_low_u64_be(1)), H256::from_low_u64_be(2));
+        assert_eq!(backend.cache_stats(), CacheStats { hits: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn prefetch_for_tx_falls_back_to_declared_access_list() {
+        // nothing listens on this port, so creating the access list fails and only the cached
+        // declared state is prefetched
+        let provider = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+        let (from, to, other) =
+            (Address::repeat_byte(1), Address::repeat_byte(2), Address::repeat_byte(3));
+        let slot = H256::from_low_u64_be(1);
+        let mut cache = MemCache::default();
+        cache.insert(from, MemoryAccount { balance: 5.into(), ..Default::default() });
+        cache.insert(to, MemoryAccount::default());
+        let mut storage = BTreeMap::new();
+        storage.insert(slot, H256::from_low_u64_be(2));
+        cache.insert(other, MemoryAccount { storage, ..Default::default() });
+
+        let vicinity = crate::sputnik::helpers::new_vicinity();
+        let backend = SharedBackend::new(provider, new_shared_cache(cache), vicinity, None);
+        let tx = Transaction {
+            from,
+            to: Some(to),
+            access_list: Some(AccessList(vec![AccessListItem {
+                address: other,
+                storage_keys: vec![slot],
+            }])),
+            ..Default::default()
+        };
+        backend.prefetch_for_tx(&tx).unwrap();
+        // the three accounts and the slot are cache hits, the access list is the only fetch
+        assert_eq!(backend.cache_stats(), CacheStats { hits: 4, fetches: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn request_budget_fails_fetches() {
+        // nothing listens on this port, so every fetch fails right away but still counts
+        let provider = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+        let address = Address::repeat_byte(1);
+        let mut cache = MemCache::default();
+        let mut storage = BTreeMap::new();
+        storage.insert(H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        cache.insert(address, MemoryAccount { storage, ..Default::default() });
+
+        let vicinity = crate::sputnik::helpers::new_vicinity();
+        let backend = SharedBackend::new(provider, new_shared_cache(cache), vicinity, None)
+            .with_request_budget(1);
+        assert_eq!(backend.storage(address, H256::from_low_u64_be(2)), H256::zero());
+        let err = backend.do_get_storage(address, H256::from_low_u64_be(3)).unwrap_err();
+        assert!(err.to_string().contains("request budget of 1"), "{}", err);
+
+        // cache hits don't count
+        assert_eq!(backend.storage(address, H256::from_low_u64_be(1)), H256::from_low_u64_be(2));
+        assert_eq!(backend.cache_stats().fetches, 1);
+        let _ = take_budget_error();
+        assert_eq!(backend.basic(Address::repeat_byte(2)).nonce, 0.into());
+        let err = take_budget_error().unwrap();
+        assert!(err.contains("request budget of 1"), "{}", err);
+        assert!(take_budget_error().is_none());
+    }
+
+    #[test]
+    fn serves_stale_state_while_revalidating() {
+        // nothing listens on this port, the stale state is answered without waiting for it
+        let provider = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+        let address = Address::repeat_byte(1);
+        let (slot, value) = (H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        let mut cache = MemCache::default();
+        let mut storage = BTreeMap::new();
+        storage.insert(slot, value);
+        cache.insert(address, MemoryAccount { storage, balance: 5.into(), ..Default::default() });
+
+        let vicinity = crate::sputnik::helpers::new_vicinity();
+        let backend = SharedBackend::new(provider, new_shared_cache(cache), vicinity, None)
+            .with_cache_ttl(Duration::ZERO)
+            .with_stale_while_revalidate();
+        backend.inner.control.account_fetched(address);
+        backend.inner.control.storage_fetched(address, slot);
+        assert_eq!(backend.basic(address).balance, 5.into());
+        assert_eq!(backend.storage(address, slot), value);
+        let stats = backend.cache_stats();
+        assert_eq!((stats.expired, stats.fetches), (2, 2));
+
+        // the failed revalidations keep the stale state instead of caching empty state
+        assert_eq!(backend.basic(Address::repeat_byte(2)).balance, 0.into());
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(backend.basic(address).balance, 5.into());
+        assert_eq!(backend.storage(address, slot), value);
+        assert_eq!(backend.cache_stats().expired, 4);
+
+        // the state of a pinned block never changes
+        let pinned = CacheControl::for_block(Some(BlockNumber::Number(1.into()).into()));
+        pinned.set_stale_while_revalidate();
+        assert!(!pinned.revalidates());
+    }
+
+    #[test]
+    fn can_set_block_env() {
+        // nothing listens on this port, so only the hashes of the block environment are known
+        let provider = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+        let mut vicinity = crate::sputnik::helpers::new_vicinity();
+        vicinity.block_number = 1.into();
+        vicinity.block_hashes = vec![H256::repeat_byte(1)];
+        let mut backend =
+            SharedBackend::new(provider, new_shared_cache(MemCache::default()), vicinity, None);
+        assert_eq!(backend.block_env_hash(), None);
+
+        let block = Block::<TxHash> {
+            hash: Some(H256::repeat_byte(3)),
+            parent_hash: H256::repeat_byte(2),
+            number: Some(2.into()),
+            timestamp: 100.into(),
+            base_fee_per_gas: Some(7.into()),
+            ..Default::default()
+        };
+        backend.set_block_env(&block);
+        assert_eq!(backend.block_number(), 2.into());
+        assert_eq!(backend.block_timestamp(), 100.into());
+        assert_eq!(backend.block_base_fee_per_gas(), 7.into());
+        assert_eq!(backend.block_hash(1.into()), H256::repeat_byte(2));
+        assert_eq!(backend.block_hash(0.into()), H256::repeat_byte(1));
+        assert_eq!(backend.block_env_hash(), Some(H256::repeat_byte(3)));
+
+        // blocks which don't follow the current one have no previous hashes
+        let block = Block::<TxHash> { number: Some(10.into()), ..block };
+        backend.set_block_env(&block);
+        assert_eq!(backend.block_hash(9.into()), H256::repeat_byte(2));
+        assert_eq!(backend.block_hash(8.into()), H256::zero());
+    }
+
+    #[test]
+    fn cache_ttl_expires_fetched_state() {
+        let address = Address::repeat_byte(1);
+        let idx = H256::from_low_u64_be(1);
+
+        let control = CacheControl::new(false);
+        control.set_ttl(Duration::from_millis(10));
+        // locally set up state has no fetch time and never expires
+        assert!(!control.account_expired(address));
+        control.account_fetched(address);
+        control.storage_fetched(address, idx);
+        assert!(!control.account_expired(address));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(control.account_expired(address));
+        assert!(control.storage_expired(address, idx));
+
+        // the pending state is fetched again on every access
+        let pending = CacheControl::for_block(Some(BlockNumber::Pending.into()));
+        pending.set_ttl(Duration::from_secs(60));
+        assert!(!pending.account_expired(address));
+        pending.account_fetched(address);
+        assert!(pending.account_expired(address));
+
+        // the state of a pinned block never changes
+        let pinned = CacheControl::for_block(Some(BlockNumber::Number(1.into()).into()));
+        pinned.set_ttl(Duration::ZERO);
+        pinned.account_fetched(address);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(!pinned.account_expired(address));
+    }
+
+    #[test]
+    fn cache_limit_evicts_least_recently_used() {
+        let local = Address::repeat_byte(1);
+        let (a, b, c) = (Address::repeat_byte(2), Address::repeat_byte(3), Address::repeat_byte(4));
+        let idx = H256::from_low_u64_be(1);
+        let mut accounts = MemCache::default();
+        accounts.insert(local, MemoryAccount { nonce: 1.into(), ..Default::default() });
+        let cache = new_shared_cache(accounts);
+
+        let control = CacheControl::new(true);
+        control.set_max_entries(2);
+        let fetch = |address: Address, slot: Option<H256>| {
+            let mut lock = cache.write();
+            let acc = lock.entry(address).or_default();
+            match slot {
+                Some(idx) => {
+                    acc.storage.insert(idx, H256::from_low_u64_be(2));
+                    drop(lock);
+                    control.storage_fetched(address, idx);
+                }
+                None => {
+                    drop(lock);
+                    control.account_fetched(address);
+                }
+            }
+            control.evict(&cache, &Default::default());
+        };
+
+        fetch(a, None);
+        fetch(a, Some(idx));
+        control.hit(CacheKey::Account(a));
+        // the slot is the least recently used entry
+        fetch(b, None);
+        assert!(cache.read()[&a].storage.is_empty());
+        fetch(c, None);
+        assert!(!cache.read().contains_key(&a));
+        // the locally set up account is never evicted
+        assert_eq!(cache.read()[&local].nonce, 1.into());
+        let stats = control.stats();
+        assert_eq!((stats.entries, stats.evictions), (2, 2));
+
+        // evicting an account evicts its storage as well
+        let mut lru = Lru { max_entries: 0, ..Default::default() };
+        lru.touch(CacheKey::Account(a));
+        lru.touch(CacheKey::Storage(a, idx));
+        lru.touch(CacheKey::Storage(b, idx));
+        assert_eq!(lru.pop().unwrap(), vec![CacheKey::Account(a), CacheKey::Storage(a, idx)]);
+        assert_eq!(lru.pop().unwrap(), vec![CacheKey::Storage(b, idx)]);
+        assert!(lru.pop().is_none());
+
+        // proofs count against the limit as well
+        let proofs = new_shared_cache(ProofCache::default());
+        proofs.write().insert(c, Default::default());
+        control.proof_fetched(c);
+        control.evict(&cache, &proofs);
+        assert!(proofs.read().contains_key(&c));
+        control.hit(CacheKey::Account(c));
+        control.storage_fetched(b, idx);
+        control.evict(&cache, &proofs);
+        assert!(proofs.read().is_empty());
+        assert!(cache.read().contains_key(&c));
+    }
+
+    #[test]
+    fn disk_cache_roundtrip() {
+        let path = std::env::temp_dir().join(format!("foundry-cache-{}.json", std::process::id()));
+        let local: Address = "00a329c0648769A73afAc7F9381E08FB43dBEA72".parse().unwrap();
+        let remote: Address = "63091244180ae240c87d1f528f5f269134cb07b3".parse().unwrap();
+
+        let mut cache = MemCache::default();
+        cache.insert(local, MemoryAccount { nonce: 1.into(), ..Default::default() });
+        let mut storage = BTreeMap::new();
+        storage.insert(H256::from_low_u64_be(1), H256::from_low_u64_be(2));
+        let acc = MemoryAccount { nonce: 3.into(), balance: 4.into(), code: vec![0x60], storage };
+        cache.insert(remote, acc.clone());
+
+        let mut proofs = ProofCache::default();
+        let proof = EIP1186ProofResponse {
+            address: remote,
+            nonce: 3u64.into(),
+            balance: 4.into(),
+            account_proof: vec![vec![0xf8].into()],
+            ..Default::default()
+        };
+        proofs.insert(remote, proof.clone());
+
+        let mainnet = DiskCache::new(&path, 1, 100).exclude([local]);
+        mainnet.flush(&cache, &proofs).unwrap();
+        // a different fork in the same file must not overwrite the first one
+        let other = DiskCache::new(&path, 1, 101);
+        other.flush(&MemCache::default(), &ProofCache::default()).unwrap();
+
+        let loaded = mainnet.load().unwrap();
+        assert!(!loaded.contains_key(&local));
+        assert_eq!(loaded.get(&remote).unwrap(), &acc);
+        assert_eq!(mainnet.load_proofs().unwrap().get(&remote), Some(&proof));
+        assert!(other.load().unwrap().is_empty());
+        assert!(other.load_proofs().unwrap().is_empty());
+        // nor does the same block of another chain see its state
+        assert!(DiskCache::new(&path, 10, 100).load().unwrap().is_empty());
+
+        // clearing a fork keeps the other forks of the file
+        mainnet.clear().unwrap();
+        assert!(mainnet.load().unwrap().is_empty());
+        other.clear().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn shutdown_flushes_disk_cache() {
+        let path = std::env::temp_dir()
+            .join(format!("foundry-cache-shutdown-{}.json", std::process::id()));
+        let address = Address::repeat_byte(1);
+        let acc = MemoryAccount { nonce: 1.into(), ..Default::default() };
+        let mut cache = MemCache::default();
+        cache.insert(address, acc.clone());
+
+        // nothing listens on this port, the cached state is all there is to flush
+        let provider = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+        let vicinity = crate::sputnik::helpers::new_vicinity();
+        let disk = DiskCache::new(&path, 1, 100);
+        let backend = SharedBackend::new(provider, new_shared_cache(cache), vicinity, None)
+            .with_disk_cache(disk.clone());
+        let clone = backend.clone();
+        backend.shutdown().unwrap();
+        assert_eq!(disk.load().unwrap().get(&address), Some(&acc));
+
+        // the handler is gone, so the clones can't fetch anymore
+        assert!(clone.do_get_storage(Address::repeat_byte(2), H256::zero()).is_err());
+        // the last clone flushes the cache again when it's dropped
+        drop(clone);
+        disk.clear().unwrap();
+    }
+
+    #[test]
+    fn file_lock_only_removes_its_own_lock() {
+        let path =
+            std::env::temp_dir().join(format!("foundry-cache-lock-{}.json", std::process::id()));
+        let lock = FileLock::acquire(&path).unwrap();
+        // the lock was taken over, e.g. because this writer was stuck
+        fs::write(&lock.path, "other").unwrap();
+        let lock_path = lock.path.clone();
+        drop(lock);
+        assert!(lock_path.exists());
+
+        fs::remove_file(&lock_path).unwrap();
+        drop(FileLock::acquire(&path).unwrap());
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn disk_cache_shards_directories() {
+        let dir = std::env::temp_dir().join(format!("foundry-cache-dir-{}", std::process::id()));
+        let addr = Address::repeat_byte(1);
+        let mut cache = MemCache::default();
+        cache.insert(addr, MemoryAccount { nonce: 1.into(), ..Default::default() });
+
+        let mainnet = DiskCache::in_dir(&dir, 1, 100);
+        assert_eq!(mainnet.path(), dir.join("1").join("100").join("state.json"));
+
+        // concurrent writers to the same shard must not corrupt it
+        let writers = (0..4)
+            .map(|_| {
+                let (disk, cache) = (mainnet.clone(), cache.clone());
+                std::thread::spawn(move || disk.flush(&cache, &ProofCache::default()).unwrap())
+            })
+            .collect::<Vec<_>>();
+        writers.into_iter().for_each(|writer| writer.join().unwrap());
+        assert_eq!(mainnet.load().unwrap(), cache);
+        assert!(DiskCache::in_dir(&dir, 1, 101).load().unwrap().is_empty());
+
+        mainnet.clear().unwrap();
+        assert!(mainnet.load().unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
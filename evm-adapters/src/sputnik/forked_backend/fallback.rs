@@ -0,0 +1,172 @@
+//! A JSON-RPC client which fails over to secondary endpoints
+use super::{ClientError, RetryPolicy};
+
+use async_trait::async_trait;
+use ethers::providers::{Http, HttpClientError, JsonRpcClient};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// A [JsonRpcClient] over multiple endpoints.
+///
+/// Requests are sent to the endpoint which last succeeded, starting with the primary one. If a
+/// request fails because of the transport or rate limiting, it is retried against the next
+/// endpoint. Regular JSON-RPC errors are returned as is, since every endpoint would answer the
+/// same.
+///
+/// If all endpoints failed, the request is retried according to the [RetryPolicy], which by
+/// default does not retry at all.
+///
+/// Clones share the endpoints and which of them is active.
+#[derive(Debug)]
+pub struct FallbackClient<C = Http> {
+    inner: Arc<FallbackClientInner<C>>,
+    retry: RetryPolicy,
+}
+
+impl<C> Clone for FallbackClient<C> {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner), retry: self.retry }
+    }
+}
+
+#[derive(Debug)]
+struct FallbackClientInner<C> {
+    clients: Vec<C>,
+    /// Index of the endpoint which last succeeded
+    active: AtomicUsize,
+}
+
+impl<C> FallbackClient<C> {
+    /// Creates a new client which uses `primary` and falls back to `fallbacks` in order
+    pub fn new(primary: C, fallbacks: impl IntoIterator<Item = C>) -> Self {
+        let clients = std::iter::once(primary).chain(fallbacks).collect();
+        Self {
+            inner: Arc::new(FallbackClientInner { clients, active: Default::default() }),
+            retry: RetryPolicy::none(),
+        }
+    }
+
+    /// Sets the policy for retrying requests which failed on all endpoints
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// The index of the endpoint which answered the last request, `0` being the primary one
+    pub fn active(&self) -> usize {
+        self.inner.active.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether another endpoint could answer the request which failed with this error
+pub(crate) fn is_transient(err: &ClientError) -> bool {
+    match err {
+        // 429 is forwarded as is by some providers, -32005 is the "limit exceeded" code
+        ClientError::Http(HttpClientError::JsonRpcError(err)) => {
+            err.code == 429 || err.code == -32005
+        }
+        _ => true,
+    }
+}
+
+impl<C> FallbackClient<C>
+where
+    C: JsonRpcClient,
+    C::Error: Into<ClientError>,
+{
+    /// Sends the request to all endpoints in order, starting with the one which last succeeded
+    async fn request_any<T, R>(&self, method: &str, params: &T) -> Result<R, ClientError>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let clients = &self.inner.clients;
+        let start = self.active();
+        let mut last_err = None;
+        for i in 0..clients.len() {
+            let idx = (start + i) % clients.len();
+            let res: Result<R, ClientError> =
+                clients[idx].request(method, params).await.map_err(Into::into);
+            match res {
+                Ok(res) => {
+                    self.inner.active.store(idx, Ordering::Relaxed);
+                    return Ok(res)
+                }
+                Err(err) if is_transient(&err) => {
+                    tracing::trace!(?err, endpoint = idx, method, "request failed");
+                    if i + 1 < clients.len() {
+                        // a flaky primary endpoint shows up as frequent failovers
+                        tracing::debug!(from = idx, method, "failing over to the next endpoint");
+                    }
+                    last_err = Some(err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Err(last_err.expect("there is always a primary endpoint"))
+    }
+}
+
+#[async_trait]
+impl<C> JsonRpcClient for FallbackClient<C>
+where
+    C: JsonRpcClient,
+    C::Error: Into<ClientError>,
+{
+    type Error = ClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let mut attempt = 0;
+        loop {
+            let err = match self.request_any(method, &params).await {
+                Err(err) if is_transient(&err) && attempt < self.retry.max_retries => err,
+                res => return res,
+            };
+            let delay = self.retry.delay(attempt);
+            tracing::trace!(?err, attempt, ?delay, method, "retrying request");
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{Middleware, Provider};
+    use std::{str::FromStr, time::Duration};
+
+    #[tokio::test]
+    async fn fails_over_unreachable_endpoints() {
+        // nothing listens on these ports, so every request fails at the transport
+        let client = FallbackClient::new(
+            Http::from_str("http://127.0.0.1:1").unwrap(),
+            vec![Http::from_str("http://127.0.0.1:2").unwrap()],
+        );
+        let provider = Provider::new(client.clone());
+        assert!(provider.get_block_number().await.is_err());
+        // the primary endpoint stays active if no endpoint answered
+        assert_eq!(client.active(), 0);
+    }
+
+    #[tokio::test]
+    async fn retries_failed_requests() {
+        let client = FallbackClient::new(Http::from_str("http://127.0.0.1:1").unwrap(), vec![])
+            .with_retry(RetryPolicy::new(2, Duration::from_millis(1)));
+        let provider = Provider::new(client);
+        let start = std::time::Instant::now();
+        assert!(provider.get_block_number().await.is_err());
+        // 1ms before the first and 2ms before the second retry
+        assert!(start.elapsed() >= Duration::from_millis(3));
+    }
+}
@@ -0,0 +1,255 @@
+//! A JSON-RPC client which records responses to a fixture file and replays them offline
+use super::ClientError;
+use async_trait::async_trait;
+use ethers::providers::{Http, HttpClientError, JsonRpcClient, JsonRpcError};
+use eyre::WrapErr;
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// A single recorded request and its response, one per line of the fixture
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    /// The method and the json params, see [request_key]
+    request: String,
+    #[serde(default)]
+    result: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RecordedError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedError {
+    code: i64,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl Entry {
+    fn into_result(self) -> Result<Value, HttpClientError> {
+        match self.error {
+            Some(RecordedError { code, message, data }) => {
+                Err(HttpClientError::JsonRpcError(JsonRpcError { code, message, data }))
+            }
+            None => Ok(self.result),
+        }
+    }
+}
+
+/// Identifies a request in the fixture
+fn request_key(method: &str, params: &Value) -> String {
+    format!("{} {}", method, params)
+}
+
+#[derive(Debug, Clone)]
+enum Mode<C> {
+    Passthrough(C),
+    Record { inner: C, file: Arc<Mutex<File>> },
+    Replay(Arc<HashMap<String, Entry>>),
+}
+
+/// A [JsonRpcClient] which records every response of another client to a fixture file, or
+/// answers all requests from such a fixture without any network access, e.g. to run forked tests
+/// deterministically in CI.
+///
+/// Unlike the [DiskCache](super::DiskCache), the fixture holds the exact request and response
+/// pairs, including empty results and JSON-RPC errors. Transport errors are not recorded. The
+/// fixture is a json file with one request per line, and recording appends to it, so multiple
+/// clients can record into the same fixture.
+///
+/// When replaying, a request which is not in the fixture fails with an error that names it.
+#[derive(Debug, Clone)]
+pub struct FixtureClient<C = Http> {
+    mode: Mode<C>,
+    path: Option<PathBuf>,
+}
+
+impl<C> FixtureClient<C> {
+    /// Forwards all requests to the client, without recording them
+    pub fn passthrough(inner: C) -> Self {
+        Self { mode: Mode::Passthrough(inner), path: None }
+    }
+
+    /// Forwards all requests to the client and appends their responses to the fixture at `path`
+    pub fn record_to(inner: C, path: impl Into<PathBuf>) -> eyre::Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .wrap_err_with(|| format!("failed to open fixture {:?}", path))?;
+        Ok(Self {
+            mode: Mode::Record { inner, file: Arc::new(Mutex::new(file)) },
+            path: Some(path),
+        })
+    }
+
+    /// Answers all requests from the fixture at `path`, written by [FixtureClient::record_to]
+    pub fn replay_from(path: impl Into<PathBuf>) -> eyre::Result<Self> {
+        let path = path.into();
+        let content = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("failed to read fixture {:?}", path))?;
+        let mut entries = HashMap::new();
+        for (i, line) in content.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let entry: Entry = serde_json::from_str(line)
+                .wrap_err_with(|| format!("invalid entry at line {} of {:?}", i + 1, path))?;
+            // a request recorded again replaces the earlier response
+            entries.insert(entry.request.clone(), entry);
+        }
+        Ok(Self { mode: Mode::Replay(Arc::new(entries)), path: Some(path) })
+    }
+
+    /// The fixture which is recorded or replayed, if any
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn display_path(&self) -> std::path::Display<'_> {
+        self.path.as_deref().unwrap_or_else(|| Path::new("")).display()
+    }
+
+    fn record(&self, file: &Mutex<File>, entry: &Entry) {
+        let mut line = serde_json::to_vec(entry).expect("entries are always serializable");
+        line.push(b'\n');
+        // a single write, so that lines of concurrent writers don't interleave
+        if let Err(err) = file.lock().write_all(&line) {
+            tracing::warn!(
+                "Failed to record {} to fixture {}: {}",
+                entry.request,
+                self.display_path(),
+                err
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl<C> JsonRpcClient for FixtureClient<C>
+where
+    C: JsonRpcClient,
+    C::Error: Into<ClientError>,
+{
+    type Error = ClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let value = match &self.mode {
+            Mode::Passthrough(inner) => {
+                return inner.request(method, params).await.map_err(Into::into)
+            }
+            Mode::Record { inner, file } => {
+                let params = serde_json::to_value(params)
+                    .map_err(|err| HttpClientError::SerdeJson { err, text: method.to_string() })?;
+                let res: Result<Value, ClientError> =
+                    inner.request(method, &params).await.map_err(Into::into);
+                let request = request_key(method, &params);
+                match &res {
+                    Ok(result) => {
+                        self.record(file, &Entry { request, result: result.clone(), error: None })
+                    }
+                    Err(ClientError::Http(HttpClientError::JsonRpcError(err))) => {
+                        let error = RecordedError {
+                            code: err.code,
+                            message: err.message.clone(),
+                            data: err.data.clone(),
+                        };
+                        self.record(
+                            file,
+                            &Entry { request, result: Value::Null, error: Some(error) },
+                        )
+                    }
+                    // the transport failing is not a response of the endpoint
+                    Err(_) => {}
+                }
+                res?
+            }
+            Mode::Replay(entries) => {
+                let params = serde_json::to_value(params)
+                    .map_err(|err| HttpClientError::SerdeJson { err, text: method.to_string() })?;
+                let request = request_key(method, &params);
+                match entries.get(&request) {
+                    Some(entry) => entry.clone().into_result()?,
+                    None => {
+                        let message = format!(
+                            "no response for `{}` in fixture {}",
+                            request,
+                            self.display_path()
+                        );
+                        // the forked backend only traces failed requests
+                        tracing::warn!("{}", message);
+                        return Err(HttpClientError::JsonRpcError(JsonRpcError {
+                            code: -32000,
+                            message,
+                            data: None,
+                        })
+                        .into())
+                    }
+                }
+            }
+        };
+        Ok(R::deserialize(&value)
+            .map_err(|err| HttpClientError::SerdeJson { err, text: value.to_string() })?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{Middleware, Provider};
+
+    /// Answers the block number, and fails all other requests
+    #[derive(Debug)]
+    struct Node;
+
+    #[async_trait]
+    impl JsonRpcClient for Node {
+        type Error = HttpClientError;
+
+        async fn request<T, R>(&self, method: &str, _params: T) -> Result<R, Self::Error>
+        where
+            T: Debug + Serialize + Send + Sync,
+            R: DeserializeOwned,
+        {
+            match method {
+                "eth_blockNumber" => Ok(R::deserialize(Value::from("0x10")).unwrap()),
+                _ => Err(HttpClientError::JsonRpcError(JsonRpcError {
+                    code: -32601,
+                    message: format!("{} is not supported", method),
+                    data: None,
+                })),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn can_record_and_replay() {
+        let path = std::env::temp_dir().join("evm-adapters-fixture.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        let provider = Provider::new(FixtureClient::record_to(Node, &path).unwrap());
+        assert_eq!(provider.get_block_number().await.unwrap(), 16.into());
+        assert!(provider.get_chainid().await.is_err());
+
+        let provider = Provider::new(FixtureClient::<Node>::replay_from(&path).unwrap());
+        assert_eq!(provider.get_block_number().await.unwrap(), 16.into());
+        let err = provider.get_chainid().await.unwrap_err().to_string();
+        assert!(err.contains("eth_chainId is not supported"), "{}", err);
+        let err = provider.get_gas_price().await.unwrap_err().to_string();
+        assert!(err.contains("no response for `eth_gasPrice"), "{}", err);
+    }
+}
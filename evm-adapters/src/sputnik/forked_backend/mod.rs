@@ -1,4 +1,19 @@
+pub mod batch;
+pub use batch::{BatchClient, BatchConfig, ClientError, Transport};
 pub mod cache;
-pub use cache::{new_shared_cache, MemCache, SharedBackend, SharedCache};
+pub use cache::{
+    new_shared_cache, take_budget_error, CacheStats, DiskCache, MemCache, ProofCache,
+    SharedBackend, SharedCache,
+};
+pub mod fallback;
+pub use fallback::FallbackClient;
+pub mod fixture;
+pub use fixture::FixtureClient;
+pub mod multi;
+pub use multi::MultiForkBackend;
 pub mod rpc;
 pub use rpc::ForkMemoryBackend;
+pub mod retry;
+pub use retry::RetryPolicy;
+pub mod replay;
+pub use replay::replay_transactions;
@@ -0,0 +1,153 @@
+//! A backend over multiple named forks, one of which is active at a time
+use super::SharedBackend;
+
+use ethers::types::{EIP1186ProofResponse, H160, H256, U256};
+use sputnik::backend::{Backend, Basic};
+use std::collections::BTreeMap;
+
+/// A [Backend] which holds multiple named [SharedBackend]s and delegates all requests to the
+/// currently selected one.
+///
+/// The backend only serves the remote state. Accounts deployed or modified during execution live
+/// in the executor's state on top of the backend, so they are preserved when switching between
+/// forks. Every clone keeps its own selection, while sharing the caches of the forks.
+#[derive(Debug, Clone)]
+pub struct MultiForkBackend {
+    forks: BTreeMap<String, SharedBackend>,
+    selected: String,
+}
+
+impl MultiForkBackend {
+    /// Creates a new backend over the given forks, with `selected` being the active one
+    pub fn new(
+        forks: impl IntoIterator<Item = (String, SharedBackend)>,
+        selected: impl Into<String>,
+    ) -> eyre::Result<Self> {
+        let forks = forks.into_iter().collect::<BTreeMap<_, _>>();
+        let selected = selected.into();
+        if !forks.contains_key(&selected) {
+            eyre::bail!("unknown fork `{}`", selected)
+        }
+        Ok(Self { forks, selected })
+    }
+
+    /// Makes the fork with the given name the active one
+    pub fn select_fork(&mut self, name: &str) -> eyre::Result<()> {
+        if !self.forks.contains_key(name) {
+            eyre::bail!("unknown fork `{}`, available forks: {:?}", name, self.fork_names())
+        }
+        self.selected = name.to_string();
+        Ok(())
+    }
+
+    /// The name of the active fork
+    pub fn selected_fork(&self) -> &str {
+        &self.selected
+    }
+
+    /// The names of all forks
+    pub fn fork_names(&self) -> Vec<&str> {
+        self.forks.keys().map(String::as_str).collect()
+    }
+
+    /// Fetches the merkle proof of the account and the storage slots from the active fork, see
+    /// [SharedBackend::get_proof]
+    pub fn get_proof(&self, address: H160, slots: &[H256]) -> eyre::Result<EIP1186ProofResponse> {
+        self.active().get_proof(address, slots)
+    }
+
+    fn active(&self) -> &SharedBackend {
+        &self.forks[&self.selected]
+    }
+}
+
+impl Backend for MultiForkBackend {
+    fn gas_price(&self) -> U256 {
+        self.active().gas_price()
+    }
+    fn origin(&self) -> H160 {
+        self.active().origin()
+    }
+    fn block_hash(&self, number: U256) -> H256 {
+        self.active().block_hash(number)
+    }
+    fn block_number(&self) -> U256 {
+        self.active().block_number()
+    }
+    fn block_coinbase(&self) -> H160 {
+        self.active().block_coinbase()
+    }
+    fn block_timestamp(&self) -> U256 {
+        self.active().block_timestamp()
+    }
+    fn block_difficulty(&self) -> U256 {
+        self.active().block_difficulty()
+    }
+    fn block_gas_limit(&self) -> U256 {
+        self.active().block_gas_limit()
+    }
+    fn block_base_fee_per_gas(&self) -> U256 {
+        self.active().block_base_fee_per_gas()
+    }
+    fn chain_id(&self) -> U256 {
+        self.active().chain_id()
+    }
+    fn exists(&self, address: H160) -> bool {
+        self.active().exists(address)
+    }
+    fn basic(&self, address: H160) -> Basic {
+        self.active().basic(address)
+    }
+    fn code(&self, address: H160) -> Vec<u8> {
+        self.active().code(address)
+    }
+    fn storage(&self, address: H160, index: H256) -> H256 {
+        self.active().storage(address, index)
+    }
+    fn original_storage(&self, address: H160, index: H256) -> Option<H256> {
+        self.active().original_storage(address, index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sputnik::{new_shared_cache, MemCache};
+    use ethers::providers::{Http, Provider};
+    use std::convert::TryFrom;
+
+    fn backend(chain_id: u64) -> SharedBackend {
+        // no requests are sent, so the endpoint does not need to exist
+        let provider = Provider::<Http>::try_from("http://localhost:8545").unwrap();
+        let vicinity = sputnik::backend::MemoryVicinity {
+            gas_price: Default::default(),
+            origin: Default::default(),
+            chain_id: chain_id.into(),
+            block_hashes: Default::default(),
+            block_number: Default::default(),
+            block_coinbase: Default::default(),
+            block_timestamp: Default::default(),
+            block_difficulty: Default::default(),
+            block_gas_limit: Default::default(),
+            block_base_fee_per_gas: Default::default(),
+        };
+        SharedBackend::new(provider, new_shared_cache(MemCache::default()), vicinity, None)
+    }
+
+    #[test]
+    fn can_switch_forks() {
+        let forks =
+            vec![("mainnet".to_string(), backend(1)), ("optimism".to_string(), backend(10))];
+        assert!(MultiForkBackend::new(forks.clone(), "arbitrum").is_err());
+
+        let mut backend = MultiForkBackend::new(forks, "mainnet").unwrap();
+        assert_eq!(backend.chain_id(), 1.into());
+
+        backend.select_fork("optimism").unwrap();
+        assert_eq!(backend.selected_fork(), "optimism");
+        assert_eq!(backend.chain_id(), 10.into());
+
+        assert!(backend.select_fork("arbitrum").is_err());
+        assert_eq!(backend.selected_fork(), "optimism");
+    }
+}
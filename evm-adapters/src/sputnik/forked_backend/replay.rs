@@ -0,0 +1,117 @@
+//! Replays already mined transactions on top of a forked state
+use super::{MemCache, SharedCache};
+use crate::sputnik::{intrinsic_gas, SputnikExecutor, PRECOMPILES_MAP};
+
+use ethers::types::Transaction;
+use sputnik::{
+    backend::{Apply, Backend},
+    executor::stack::{MemoryStackState, StackExecutor, StackSubstateMetadata},
+    Config,
+};
+
+/// Executes the given transactions in order on top of the `backend` and writes the resulting
+/// state into the `cache`.
+///
+/// This is used to fork at the state right before a transaction: the backend is pinned at the
+/// parent block and all transactions which precede the target transaction in its block are
+/// replayed. Failing transactions are replayed as well, since they still pay for their gas.
+pub fn replay_transactions<B: Backend>(
+    backend: &B,
+    cache: &SharedCache<MemCache>,
+    config: &Config,
+    txs: &[Transaction],
+) {
+    let metadata = StackSubstateMetadata::new(u64::MAX, config);
+    let state = MemoryStackState::new(metadata, backend);
+    let mut executor = StackExecutor::new_with_precompiles(state, config, &*PRECOMPILES_MAP);
+
+    let base_fee = backend.block_base_fee_per_gas();
+    let coinbase = backend.block_coinbase();
+    for tx in txs {
+        let gas_used_before = SputnikExecutor::gas_used(&executor);
+        let access_list: Vec<_> = tx
+            .access_list
+            .as_ref()
+            .map(|list| {
+                list.0.iter().map(|item| (item.address, item.storage_keys.clone())).collect()
+            })
+            .unwrap_or_default();
+        // the call of the transaction is executed with the gas left after the intrinsic gas
+        let intrinsic = intrinsic_gas(config, tx.input.as_ref(), &access_list, tx.to.is_none());
+        let gas_limit = match intrinsic {
+            Ok(intrinsic) if intrinsic <= tx.gas.low_u64() => tx.gas.low_u64() - intrinsic,
+            _ => {
+                tracing::trace!(hash = ?tx.hash, "replayed transaction can't pay its intrinsic gas");
+                continue
+            }
+        };
+        let reason = match tx.to {
+            Some(to) => {
+                SputnikExecutor::transact_call(
+                    &mut executor,
+                    tx.from,
+                    to,
+                    tx.value,
+                    tx.input.to_vec(),
+                    gas_limit,
+                    access_list,
+                )
+                .0
+            }
+            None => SputnikExecutor::transact_create(
+                &mut executor,
+                tx.from,
+                tx.value,
+                tx.input.to_vec(),
+                gas_limit,
+                access_list,
+            ),
+        };
+        tracing::trace!(hash = ?tx.hash, ?reason, "replayed transaction");
+
+        // sputnik does not charge fees, so we pay them manually
+        let gas_used = SputnikExecutor::gas_used(&executor).saturating_sub(gas_used_before);
+        let gas_price = tx.gas_price.unwrap_or_default();
+        let state = executor.state_mut();
+        if state.withdraw(tx.from, gas_used.saturating_mul(gas_price)).is_err() {
+            tracing::trace!(hash = ?tx.hash, "sender can't pay for the replayed transaction");
+        }
+        state.deposit(coinbase, gas_used.saturating_mul(gas_price.saturating_sub(base_fee)));
+    }
+
+    let (applies, _) = executor.into_state().deconstruct();
+    // fetch the code of modified accounts before locking the cache, since the backend may need
+    // the cache to answer
+    let applies = applies
+        .into_iter()
+        .map(|apply| match apply {
+            Apply::Modify { address, basic, code, storage, reset_storage } => {
+                let code = code.unwrap_or_else(|| backend.code(address));
+                let storage = storage.into_iter().collect::<Vec<_>>();
+                Apply::Modify { address, basic, code: Some(code), storage, reset_storage }
+            }
+            Apply::Delete { address } => Apply::Delete { address },
+        })
+        .collect::<Vec<_>>();
+
+    let mut cache = cache.write();
+    for apply in applies {
+        match apply {
+            Apply::Modify { address, basic, code, storage, reset_storage } => {
+                let acc = cache.entry(address).or_default();
+                acc.nonce = basic.nonce;
+                acc.balance = basic.balance;
+                acc.code = code.unwrap_or_default();
+                if reset_storage {
+                    acc.storage.clear();
+                }
+                acc.storage.extend(storage);
+            }
+            Apply::Delete { address } => {
+                // keep an empty entry, otherwise the account would be fetched from the parent
+                // block again
+                cache.insert(address, Default::default());
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Retrying of failed RPC requests
+use rand::Rng;
+use std::time::Duration;
+
+/// How often and after which delay a request to the fork endpoints is retried, if it failed
+/// because of the transport or rate limiting.
+///
+/// The delay grows exponentially with every attempt, `base_delay * 2^attempt`, plus a random
+/// jitter of up to `jitter` so that concurrent requests don't retry in lockstep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times a request is retried before giving up
+    pub max_retries: u32,
+    /// The delay before the first retry
+    pub base_delay: Duration,
+    /// The upper bound of the random delay added to every retry
+    pub jitter: Duration,
+}
+
+impl RetryPolicy {
+    /// Never retries, failing requests are returned as is
+    pub fn none() -> Self {
+        Self { max_retries: 0, base_delay: Duration::ZERO, jitter: Duration::ZERO }
+    }
+
+    /// Retries up to `max_retries` times, starting with a delay of `base_delay`
+    pub fn new(max_retries: u32, base_delay: Duration) -> Self {
+        Self { max_retries, base_delay, jitter: Duration::ZERO }
+    }
+
+    /// Sets the upper bound of the random delay added to every retry
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// The delay before the given retry, starting at `0`
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.base_delay.saturating_mul(2u32.saturating_pow(attempt));
+        let jitter = if self.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            self.jitter.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+        };
+        backoff.saturating_add(jitter)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(100));
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(400));
+
+        let policy = policy.with_jitter(Duration::from_millis(50));
+        let delay = policy.delay(1);
+        assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(250));
+    }
+}
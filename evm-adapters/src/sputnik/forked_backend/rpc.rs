@@ -235,7 +235,7 @@ mod tests {
             evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
 
         let (res, _, _, _) = evm
-            .call::<U256, _, _>(
+            .call_committing::<U256, _, _>(
                 Address::zero(),
                 addr,
                 "time()(uint256)",
@@ -4,22 +4,38 @@ pub use evm::*;
 mod forked_backend;
 pub use forked_backend::*;
 
+mod dump;
+pub use dump::*;
+
+mod custom_backend;
+pub use custom_backend::CustomBackend;
+
+mod coverage;
+pub use coverage::*;
+
+mod authorization;
+pub use authorization::*;
+
 pub mod cheatcodes;
 pub mod state;
 
 use ethers::{
     abi::RawLog,
     providers::Middleware,
-    types::{Address, H160, H256, U256},
+    solc::EvmVersion,
+    types::{Address, BlockNumber, H160, H256, U256},
 };
 
 use sputnik::{
-    backend::MemoryVicinity,
+    backend::{Log, MemoryVicinity},
     executor::stack::{PrecompileFailure, PrecompileOutput, StackExecutor, StackState},
     Config, CreateScheme, ExitError, ExitReason, ExitSucceed,
 };
 
-use crate::{call_tracing::CallTraceArena, sputnik::cheatcodes::debugger::DebugArena};
+use crate::{
+    call_tracing::CallTraceArena, sputnik::cheatcodes::debugger::DebugArena, AssertionFailure,
+    ReadOnlyViolation,
+};
 
 pub use sputnik as sputnik_evm;
 use sputnik_evm::executor::stack::PrecompileSet;
@@ -31,24 +47,42 @@ pub async fn vicinity<M: Middleware>(
     override_chain_id: Option<u64>,
     pin_block: Option<u64>,
     origin: Option<H160>,
-) -> Result<MemoryVicinity, M::Error> {
+) -> eyre::Result<MemoryVicinity>
+where
+    M::Error: 'static,
+{
     let block_number = if let Some(pin_block) = pin_block {
         pin_block
     } else {
         provider.get_block_number().await?.as_u64()
     };
-    let (gas_price, rpc_chain_id, block) = tokio::try_join!(
+    vicinity_at(provider, override_chain_id, block_number.into(), origin).await
+}
+
+/// Like [vicinity], but for any block, e.g. the pending one
+pub async fn vicinity_at<M: Middleware>(
+    provider: &M,
+    override_chain_id: Option<u64>,
+    block: BlockNumber,
+    origin: Option<H160>,
+) -> eyre::Result<MemoryVicinity>
+where
+    M::Error: 'static,
+{
+    let (gas_price, rpc_chain_id, fetched) = tokio::try_join!(
         provider.get_gas_price(),
         provider.get_chainid(),
-        provider.get_block(block_number)
+        provider.get_block(block)
     )?;
-    let block = block.expect("block not found");
+    let block = fetched.ok_or_else(|| eyre::eyre!("block {:?} not found", block))?;
+    let number = block.number.ok_or_else(|| eyre::eyre!("block {:?} has no number", block.hash))?;
 
     Ok(MemoryVicinity {
         origin: origin.unwrap_or_default(),
         chain_id: override_chain_id.map_or(rpc_chain_id, Into::into),
-        block_hashes: Vec::new(),
-        block_number: block.number.expect("block number not found").as_u64().into(),
+        // the hash of the parent is the only one known without fetching more blocks
+        block_hashes: vec![block.parent_hash],
+        block_number: number.as_u64().into(),
         block_coinbase: block.author,
         block_difficulty: block.difficulty,
         block_gas_limit: block.gas_limit,
@@ -58,6 +92,119 @@ pub async fn vicinity<M: Middleware>(
     })
 }
 
+/// Overrides of the block environment of a fork, e.g. to advance the time when testing
+/// time-locked contracts.
+///
+/// These only change the values seen by the executed code, the state is still fetched from the
+/// forked block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockOverrides {
+    /// The `block.timestamp` value
+    pub timestamp: Option<U256>,
+    /// The `block.number` value
+    pub number: Option<U256>,
+    /// The `block.basefee` value
+    pub base_fee: Option<U256>,
+    /// The priority fee, which makes the `tx.gasprice` the base fee plus this
+    pub priority_fee: Option<U256>,
+    /// The `block.coinbase` value, which is credited with the priority fees instead of the
+    /// forked block's miner
+    pub coinbase: Option<Address>,
+}
+
+impl BlockOverrides {
+    pub fn new(timestamp: Option<U256>, number: Option<U256>, base_fee: Option<U256>) -> Self {
+        Self { timestamp, number, base_fee, priority_fee: None, coinbase: None }
+    }
+
+    /// Sets the priority fee on top of the base fee of the block
+    #[must_use]
+    pub fn with_priority_fee(mut self, priority_fee: Option<U256>) -> Self {
+        self.priority_fee = priority_fee;
+        self
+    }
+
+    /// Sets the coinbase of the block
+    #[must_use]
+    pub fn with_coinbase(mut self, coinbase: Option<Address>) -> Self {
+        self.coinbase = coinbase;
+        self
+    }
+
+    /// Whether no value is overridden
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Applies the overrides to the block environment
+    pub fn apply(&self, vicinity: &mut MemoryVicinity) {
+        if let Some(timestamp) = self.timestamp {
+            vicinity.block_timestamp = timestamp;
+        }
+        if let Some(number) = self.number {
+            vicinity.block_number = number;
+        }
+        if let Some(base_fee) = self.base_fee {
+            vicinity.block_base_fee_per_gas = base_fee;
+        }
+        if let Some(coinbase) = self.coinbase {
+            vicinity.block_coinbase = coinbase;
+        }
+        if let Some(priority_fee) = self.priority_fee {
+            vicinity.gas_price = vicinity.block_base_fee_per_gas.saturating_add(priority_fee);
+        }
+    }
+}
+
+static ISTANBUL: Lazy<Config> = Lazy::new(Config::istanbul);
+static BERLIN: Lazy<Config> = Lazy::new(Config::berlin);
+static LONDON: Lazy<Config> = Lazy::new(Config::london);
+
+/// The stock config of the EVM version, istanbul, berlin and london are supported
+pub fn spec_config(version: EvmVersion) -> eyre::Result<&'static Config> {
+    Ok(match version {
+        EvmVersion::Istanbul => &*ISTANBUL,
+        EvmVersion::Berlin => &*BERLIN,
+        EvmVersion::London => &*LONDON,
+        other => eyre::bail!(
+            "EVM version {:?} is not supported by the sputnik executor, use istanbul, berlin or london",
+            other
+        ),
+    })
+}
+
+/// The limits and rules of an executor which take precedence over the ones of its config, see
+/// [SputnikExecutor::set_execution_limits]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecutionLimits {
+    /// The maximum memory of all open call frames of a transaction in bytes, defaults to
+    /// [DEFAULT_MEMORY_LIMIT](cheatcodes::cheatcode_handler::DEFAULT_MEMORY_LIMIT)
+    pub memory_limit: Option<usize>,
+    /// Whether deployed code may exceed the `create_contract_limit` of the config
+    pub contract_size_limit_disabled: bool,
+    /// The call depth which fails calls and deployments, lowers the `call_stack_limit` of the
+    /// config if set
+    pub call_depth_limit: Option<usize>,
+    /// The config which call transactions run under instead of the one of the executor
+    pub spec_override: Option<&'static Config>,
+    /// The maximum number of distinct values which are collected, if values are collected
+    pub value_collector: Option<usize>,
+}
+
+/// What happened during the transactions since the outcome was last taken, see
+/// [SputnikExecutor::take_outcome]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxOutcome {
+    /// The first write which was rejected in a static context
+    pub rejected_write: Option<ReadOnlyViolation>,
+    /// The first address a contract creation collided with
+    pub create_collision: Option<Address>,
+    /// Whether a call or deployment failed because it exceeded the call depth limit
+    pub call_depth_exceeded: bool,
+    /// Whether a call frame failed because it exceeded the memory limit
+    pub memory_limit_exceeded: bool,
+}
+
 /// Abstraction over the StackExecutor used inside of Sputnik, so that we can replace
 /// it with one that implements HEVM-style cheatcodes (or other features).
 pub trait SputnikExecutor<S> {
@@ -82,6 +229,19 @@ pub trait SputnikExecutor<S> {
         access_list: Vec<(H160, Vec<H256>)>,
     ) -> (ExitReason, Vec<u8>);
 
+    /// Executes the call in a static context, so that it fails if it tries to modify the state.
+    /// The nonce of the caller is not increased either.
+    fn transact_static_call(
+        &mut self,
+        _caller: H160,
+        _address: H160,
+        _data: Vec<u8>,
+        _gas_limit: u64,
+        _access_list: Vec<(H160, Vec<H256>)>,
+    ) -> (ExitReason, Vec<u8>) {
+        (ExitError::Other("static calls are not supported by this executor".into()).into(), vec![])
+    }
+
     fn transact_create(
         &mut self,
         caller: H160,
@@ -91,12 +251,39 @@ pub trait SputnikExecutor<S> {
         access_list: Vec<(H160, Vec<H256>)>,
     ) -> ExitReason;
 
+    /// Deploys the contract with `CREATE2`, so that its address only depends on the caller, the
+    /// salt and the init code
+    fn transact_create2(
+        &mut self,
+        caller: H160,
+        value: U256,
+        init_code: Vec<u8>,
+        salt: H256,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> ExitReason;
+
     fn create_address(&self, caller: CreateScheme) -> Address;
 
+    /// Whether the address is one of the executor's precompiles
+    fn is_precompile(&self, address: Address) -> bool;
+
     /// Returns a vector of raw logs that occurred during the previous VM
     /// execution
     fn raw_logs(&self) -> Vec<RawLog>;
 
+    /// The logs of the previous VM execution together with the address which emitted them
+    fn emitted_logs(&self) -> Vec<Log> {
+        vec![]
+    }
+
+    /// Takes what happened during the transactions since this was last called. Executors without
+    /// cheatcodes don't record anything, and fail the whole transaction with
+    /// `ExitFatal::NotSupported` instead of a frame which exceeds the memory limit.
+    fn take_outcome(&mut self) -> TxOutcome {
+        Default::default()
+    }
+
     /// Gets a trace
     fn traces(&self) -> Vec<CallTraceArena> {
         vec![]
@@ -104,10 +291,80 @@ pub trait SputnikExecutor<S> {
 
     fn reset_traces(&mut self) {}
 
+    /// Replaces the recorded traces, executors without cheatcodes don't trace at all
+    fn set_traces(&mut self, _traces: Vec<CallTraceArena>) {}
+
+    /// The names of addresses which are shown in traces instead of the address, executors
+    /// without cheatcodes don't keep any
+    fn labels(&self) -> BTreeMap<Address, String> {
+        Default::default()
+    }
+
+    /// Names the address, replacing its previous name. Fails on executors without cheatcodes,
+    /// which don't keep any names.
+    fn set_label(&mut self, _address: Address, _label: String) -> eyre::Result<()> {
+        eyre::bail!("executors without cheatcodes don't keep labels")
+    }
+
+    /// Whether calls to the cheatcode address are handled as cheatcodes instead of as calls to
+    /// the account at that address
+    fn cheatcodes_enabled(&self) -> bool {
+        false
+    }
+
+    /// Starts or stops recording the executed program counters, executors without cheatcodes
+    /// don't record any
+    fn set_coverage_enabled(&mut self, _enabled: bool) {}
+
+    /// The coverage recorded since it was enabled
+    fn coverage(&self) -> CoverageMap {
+        Default::default()
+    }
+
+    /// Replaces the recorded coverage, e.g. to drop the coverage of a discarded call
+    fn set_coverage(&mut self, _coverage: CoverageMap) {}
+
+    /// The values collected since the collector was set
+    fn collected_values(&self) -> Vec<U256> {
+        Vec::new()
+    }
+
+    /// The limits which take precedence over the ones of the config
+    fn execution_limits(&self) -> ExecutionLimits {
+        Default::default()
+    }
+
+    /// Replaces the limits which take precedence over the ones of the config. Changing the
+    /// maximum of the value collector replaces the values collected so far. Executors without
+    /// cheatcodes always use their config and collect no values, and fail if they are asked to
+    /// run under the rules of another spec.
+    fn set_execution_limits(&mut self, limits: ExecutionLimits) -> eyre::Result<()> {
+        eyre::ensure!(
+            limits.spec_override.is_none(),
+            "executors without cheatcodes always run under the rules of their config"
+        );
+        Ok(())
+    }
+
+    /// Pauses or resumes tracing, while paused calls and logs are not recorded. Executors
+    /// without cheatcodes don't trace at all.
+    fn set_tracing_paused(&mut self, _paused: bool) {}
+
+    /// The assertion cheatcodes which failed, executors without cheatcodes have none
+    fn assertion_failures(&self) -> Vec<AssertionFailure> {
+        Vec::new()
+    }
+
     /// Returns a vector of string parsed logs that occurred during the previous VM
     /// execution
     fn logs(&self) -> Vec<String>;
 
+    /// The `console.log` output of the previous VM execution, without the DSTest logs. Executors
+    /// without cheatcodes don't intercept `console.log`.
+    fn console_logs(&self) -> Vec<String> {
+        Vec::new()
+    }
+
     /// Clears all logs in the current EVM instance, so that subsequent calls to
     /// `logs` do not print duplicate logs on shared EVM instances.
     fn clear_logs(&mut self);
@@ -185,10 +442,26 @@ impl<'a, 'b, S: StackState<'a>, P: PrecompileSet> SputnikExecutor<S>
         self.transact_create(caller, value, data, gas_limit, access_list).0
     }
 
+    fn transact_create2(
+        &mut self,
+        caller: H160,
+        value: U256,
+        init_code: Vec<u8>,
+        salt: H256,
+        gas_limit: u64,
+        access_list: Vec<(H160, Vec<H256>)>,
+    ) -> ExitReason {
+        self.transact_create2(caller, value, init_code, salt, gas_limit, access_list).0
+    }
+
     fn create_address(&self, scheme: CreateScheme) -> Address {
         self.create_address(scheme)
     }
 
+    fn is_precompile(&self, address: Address) -> bool {
+        self.precompiles().is_precompile(address)
+    }
+
     // Empty impls for non-cheatcode handlers
     fn logs(&self) -> Vec<String> {
         vec![]
@@ -283,3 +556,31 @@ pub fn exec(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sputnik::helpers::new_vicinity;
+
+    #[test]
+    fn can_override_block_env() {
+        let mut vicinity = new_vicinity();
+        vicinity.block_number = 100.into();
+        let overrides = BlockOverrides::new(Some(1_000.into()), None, Some(7.into()));
+        assert!(!overrides.is_empty());
+        overrides.apply(&mut vicinity);
+        assert_eq!(vicinity.block_timestamp, 1_000.into());
+        assert_eq!(vicinity.block_number, 100.into());
+        assert_eq!(vicinity.block_base_fee_per_gas, 7.into());
+        assert_eq!(vicinity.gas_price, 0.into());
+
+        // the forked base fee is kept unless it is overridden
+        vicinity.block_base_fee_per_gas = 20.into();
+        BlockOverrides::default().with_priority_fee(Some(3.into())).apply(&mut vicinity);
+        assert_eq!(vicinity.gas_price, 23.into());
+
+        let coinbase = Address::repeat_byte(1);
+        BlockOverrides::default().with_coinbase(Some(coinbase)).apply(&mut vicinity);
+        assert_eq!(vicinity.block_coinbase, coinbase);
+    }
+}
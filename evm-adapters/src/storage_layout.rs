@@ -0,0 +1,62 @@
+//! Storage slots of solidity state variables, following the storage layout of solidity
+//!
+//! The base slot of a variable is the slot solidity assigns it, e.g. as listed by `solc
+//! --storage-layout`. Keys of value types, like addresses and integers, are passed as their
+//! 32-byte ABI encoding, e.g. `H256::from(address)`.
+use ethers::{
+    types::{H256, U256},
+    utils::keccak256,
+};
+
+/// The slot of `key` in a mapping at the `base` slot, i.e. `keccak256(abi.encode(key, base))`
+pub fn mapping_slot(base: U256, key: H256) -> H256 {
+    let mut word = [0u8; 64];
+    word[..32].copy_from_slice(key.as_bytes());
+    base.to_big_endian(&mut word[32..]);
+    H256::from(keccak256(word))
+}
+
+/// The slot of the value of nested mappings at the `base` slot, where the `keys` are in the order
+/// of access, e.g. `[owner, spender]` for `allowance[owner][spender]`.
+///
+/// Every inner mapping is located at the slot of its key in the outer mapping.
+pub fn nested_mapping_slot(base: U256, keys: &[H256]) -> H256 {
+    keys.iter().fold(H256::from_uint(&base), |slot, key| mapping_slot(slot.into_uint(), *key))
+}
+
+/// The slot of the element at `index` of a dynamic array at the `base` slot, whose elements take
+/// up one slot each.
+///
+/// The `base` slot holds the length of the array, the elements start at `keccak256(base)`.
+pub fn dynamic_array_slot(base: U256, index: U256) -> H256 {
+    let start = U256::from_big_endian(&keccak256(H256::from_uint(&base)));
+    H256::from_uint(&start.overflowing_add(index).0)
+}
+
+#[cfg(all(test, feature = "sputnik"))]
+mod tests {
+    use super::*;
+    use crate::{sputnik::helpers::vm, test_helpers::COMPILED, Evm};
+    use ethers::types::Address;
+    use sputnik::backend::Backend;
+
+    #[test]
+    fn matches_compiled_layout() {
+        let mut evm = vm();
+        let compiled = COMPILED.find("StorageLayout").expect("could not find contract");
+        let (addr, _, _, _) =
+            evm.deploy(Address::zero(), compiled.bytecode().unwrap().clone(), 0.into()).unwrap();
+        let read = |slot: H256| evm.state().storage(addr, slot).into_uint();
+
+        let (alice, bob) =
+            (H256::from(Address::repeat_byte(1)), H256::from(Address::repeat_byte(2)));
+        assert_eq!(read(mapping_slot(1.into(), alice)), 10.into());
+        assert_eq!(read(nested_mapping_slot(2.into(), &[alice, bob])), 20.into());
+        assert_eq!(read(nested_mapping_slot(2.into(), &[bob, alice])), 0.into());
+        assert_eq!(read(H256::from_low_u64_be(3)), 2.into());
+        assert_eq!(read(dynamic_array_slot(3.into(), 0.into())), 30.into());
+        assert_eq!(read(dynamic_array_slot(3.into(), 1.into())), 40.into());
+        assert_eq!(read(mapping_slot(4.into(), H256::from_low_u64_be(5))), 50.into());
+        assert_eq!(nested_mapping_slot(4.into(), &[]), H256::from_low_u64_be(4));
+    }
+}
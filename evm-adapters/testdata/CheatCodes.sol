@@ -6,12 +6,28 @@ pragma experimental ABIEncoderV2;
 import "./DsTest.sol";
 
 interface Hevm {
+    struct FfiResult {
+        int32 exitCode;
+        bytes stdout;
+        bytes stderr;
+    }
+
     // Set block.timestamp (newTimestamp)
     function warp(uint256) external;
     // Set block.height (newHeight)
     function roll(uint256) external;
+    // Advances block.timestamp by the seconds and block.number by the blocks in that time
+    function skip(uint256) external;
     // Set block.basefee (newBasefee)
     function fee(uint256) external;
+    // Set block.difficulty (newDifficulty)
+    function difficulty(uint256) external;
+    // Set block.prevrandao (newPrevrandao), which is block.difficulty under pre-Merge rules
+    function prevrandao(bytes32) external;
+    // Gets block.difficulty
+    function getDifficulty() external returns (uint256);
+    // Gets block.prevrandao
+    function getPrevrandao() external returns (bytes32);
     // Loads a storage slot from an address (who, slot)
     function load(address,bytes32) external returns (bytes32);
     // Stores a value to an address' storage slot, (who, slot, value)
@@ -22,6 +38,12 @@ interface Hevm {
     function addr(uint256) external returns (address);
     // Performs a foreign function call via terminal, (stringInputs) => (result)
     function ffi(string[] calldata) external returns (bytes memory);
+    // Like ffi, but passes the input to the command's stdin, (stringInputs, stdin) => (result)
+    function ffi(string[] calldata, bytes calldata) external returns (bytes memory);
+    // Like ffi, but doesn't revert if the command fails and returns the raw stdout, (stringInputs) => (exitCode, stdout, stderr)
+    function tryFfi(string[] calldata) external returns (FfiResult memory);
+    // Like tryFfi, but passes the input to the command's stdin
+    function tryFfi(string[] calldata, bytes calldata) external returns (FfiResult memory);
     // Sets the *next* call's msg.sender to be the input address
     function prank(address) external;
     // Sets all subsequent calls' msg.sender to be the input address until `stopPrank` is called
@@ -34,6 +56,20 @@ interface Hevm {
     function stopPrank() external;
     // Sets an address' balance, (who, newBalance)
     function deal(address, uint256) external;
+    // Sets the ERC20 balance of an address and adjusts the total supply
+    function deal(address, address, uint256) external;
+    // Sets the ERC20 balance of an address, the total supply is only adjusted if the flag is set
+    function deal(address, address, uint256, bool) external;
+    // Same as above, with the index of the balance mapping if it can't be detected
+    function deal(address, address, uint256, bool, uint256) external;
+    // Sets the ERC20 allowance of a spender, (token, owner, spender, amount)
+    function dealAllowance(address, address, address, uint256) external;
+    // Same as above, with the index of the allowance mapping if it can't be detected
+    function dealAllowance(address, address, address, uint256, uint256) external;
+    // Sets the owner of an ERC721 token and adjusts the balances, (token, to, tokenId)
+    function dealERC721(address, address, uint256) external;
+    // Same as above, with the indices of the owner and balance mappings for non-standard layouts
+    function dealERC721(address, address, uint256, uint256, uint256) external;
     // Sets an address' code, (who, newCode)
     function etch(address, bytes calldata) external;
     // Expects an error on next call
@@ -57,12 +93,58 @@ interface Hevm {
     // Expect a call to an address with the specified calldata.
     // Calldata can either be strict or a partial match
     function expectCall(address,bytes calldata) external;
+    // Expect a call to an address with the specified value and calldata
+    function expectCall(address,uint256,bytes calldata) external;
+    // Expect a call to an address with the specified calldata, exactly the given number of times
+    function expectCall(address,bytes calldata,uint64) external;
     // Gets the code from an artifact file. Takes in the relative path to the json file
     function getCode(string calldata) external returns (bytes memory);
     // Labels an address in call traces
     function label(address, string calldata) external;
     // If the condition is false, discard this run's fuzz inputs and generate new ones
     function assume(bool) external;
+    // Reads environment variables, reverts if they are not set. Requires ffi to be enabled
+    function envUint(string calldata) external returns (uint256);
+    function envAddress(string calldata) external returns (address);
+    function envBytes32(string calldata) external returns (bytes32);
+    function envString(string calldata) external returns (string memory);
+    // Reads environment variables, returns the default if they are not set
+    function envOr(string calldata, uint256) external returns (uint256);
+    function envOr(string calldata, address) external returns (address);
+    function envOr(string calldata, bytes32) external returns (bytes32);
+    function envOr(string calldata, string calldata) external returns (string memory);
+    // Gets the nonce of an account
+    function getNonce(address) external returns (uint64);
+    // Reads the prank applying to the next call as (mode, msg.sender, tx.origin), the mode is
+    // 0 without a prank, 3 for `prank` and 4 for `startPrank`
+    function readCallers() external returns (uint8, address, address);
+    // Sets the nonce of an account, reverts if it is lower than the current one
+    function setNonce(address, uint64) external;
+    // Sets the nonce of an account, even if it is lower than the current one
+    function setNonceUnsafe(address, uint64) external;
+    // Resets the nonce of an account to 0 for EOAs and 1 for contracts
+    function resetNonce(address) external;
+    // Stops recording calls and logs in the trace until `resumeTracing` is called
+    function pauseTracing() external;
+    function resumeTracing() external;
+    // Checkpoints the state and returns the id of the snapshot
+    function snapshot() external returns (uint256);
+    // Restores the snapshot, returns false if it doesn't exist
+    function revertTo(uint256) external returns (bool);
+    function deleteSnapshot(uint256) external returns (bool);
+    function deleteSnapshots() external;
+    // Fail the test without reverting if the assertion doesn't hold
+    function assertTrue(bool) external;
+    function assertFalse(bool) external;
+    function assertEq(uint256, uint256) external;
+    function assertEq(int256, int256) external;
+    function assertEq(address, address) external;
+    function assertEq(bytes32, bytes32) external;
+    function assertEq(bytes calldata, bytes calldata) external;
+    function assertGt(uint256, uint256) external;
+    function assertGe(uint256, uint256) external;
+    function assertLt(uint256, uint256) external;
+    function assertLe(uint256, uint256) external;
 }
 
 contract HasStorage {
@@ -110,6 +192,22 @@ contract CheatCodes is DSTest {
         require(block.basefee == fee);
     }
 
+    // Difficulty
+
+    function testDifficulty(uint256 difficulty) public {
+        hevm.difficulty(difficulty);
+        assertEq(block.difficulty, difficulty);
+        assertEq(hevm.getDifficulty(), difficulty);
+    }
+
+    // the EVM runs pre-Merge rules, so `prevrandao` is read with the `DIFFICULTY` opcode
+    function testPrevrandao(bytes32 prevrandao) public {
+        hevm.prevrandao(prevrandao);
+        assertEq(block.difficulty, uint256(prevrandao));
+        assertEq(hevm.getPrevrandao(), prevrandao);
+        assertEq(hevm.getDifficulty(), uint256(prevrandao));
+    }
+
     // Roll
 
     // Underscore does not run the fuzz test?!
@@ -120,6 +218,24 @@ contract CheatCodes is DSTest {
         require(blockhash(block.number) != 0x0);
     }
 
+    function testSkip() public {
+        uint256 timestamp = block.timestamp;
+        uint256 number = block.number;
+        hevm.skip(1 days);
+        require(block.timestamp == timestamp + 1 days, "skip failed");
+        // a block every 12 seconds
+        require(block.number == number + 7200, "blocks not skipped");
+        require(blockhash(block.number - 1) != 0x0, "skipped block has no hash");
+
+        hevm.skip(5);
+        require(block.timestamp == timestamp + 1 days + 5, "short skip failed");
+        require(block.number == number + 7200, "partial block skipped");
+    }
+
+    function testFailSkipNegative() public {
+        hevm.skip(type(uint256).max);
+    }
+
     function testRollHash() public {
         require(blockhash(block.number) == 0x0);
         hevm.roll(5);
@@ -154,6 +270,47 @@ contract CheatCodes is DSTest {
         assertEq(val, x);
     }
 
+    function storeAndLoad(address target, bytes32 slot, bytes32 value)
+        public
+        returns (bytes32 loaded, bytes32 stored)
+    {
+        loaded = hevm.load(target, slot);
+        hevm.store(target, slot, value);
+        stored = hevm.load(target, slot);
+    }
+
+    function readEnv(string memory prefix)
+        public
+        returns (uint256, address, bytes32, string memory)
+    {
+        return (
+            hevm.envUint(string(abi.encodePacked(prefix, "_UINT"))),
+            hevm.envAddress(string(abi.encodePacked(prefix, "_ADDRESS"))),
+            hevm.envBytes32(string(abi.encodePacked(prefix, "_BYTES32"))),
+            hevm.envString(string(abi.encodePacked(prefix, "_STRING")))
+        );
+    }
+
+    function testEnvOrDefaults() public {
+        string memory key = "FOUNDRY_CHEATCODES_UNSET_ENV";
+        assertEq(hevm.envOr(key, uint256(1)), 1);
+        assertEq(hevm.envOr(key, address(2)), address(2));
+        assertEq(hevm.envOr(key, bytes32(uint256(3))), bytes32(uint256(3)));
+        string memory four = "four";
+        assertEq(hevm.envOr(key, four), four);
+    }
+
+    function testFailEnvUintUnset() public {
+        hevm.envUint("FOUNDRY_CHEATCODES_UNSET_ENV");
+    }
+
+    function etchAndCall(address target, bytes calldata code) public returns (bytes32) {
+        hevm.etch(target, code);
+        (bool ok, bytes memory ret) = target.call("");
+        require(ok, "etched call failed");
+        return abi.decode(ret, (bytes32));
+    }
+
     // function prove_store_load_symbolic(uint x) public {
     //     test_store_load_concrete(x);
     // }
@@ -186,6 +343,23 @@ contract CheatCodes is DSTest {
         hevm.addr(0);
     }
 
+    function testFail_sign_zero_sk() public {
+        hevm.sign(0, keccak256("digest"));
+    }
+
+    function testFail_addr_curve_order() public {
+        hevm.addr(0xfffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141);
+    }
+
+    function testFail_sign_above_curve_order() public {
+        hevm.sign(type(uint256).max, keccak256("digest"));
+    }
+
+    function test_sign_canonical_v(bytes32 digest) public {
+        (uint8 v,,) = hevm.sign(1, digest);
+        assertTrue(v == 27 || v == 28);
+    }
+
     function test_addr() public {
         uint sk = 77814517325470205911140941194401928579557062014761831930645393041380819009408;
         address expected = 0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266;
@@ -204,12 +378,131 @@ contract CheatCodes is DSTest {
         assertEq(output, "acab");
     }
 
+    function testFFIStdin() public {
+        string[] memory inputs = new string[](1);
+        inputs[0] = "cat";
+
+        bytes memory res = hevm.ffi(inputs, bytes("0x1234"));
+        assertEq0(res, hex"1234");
+    }
+
+    function testTryFFI() public {
+        string[] memory inputs = new string[](3);
+        inputs[0] = "sh";
+        inputs[1] = "-c";
+        inputs[2] = "printf 0x1234; printf oops >&2; exit 3";
+
+        Hevm.FfiResult memory res = hevm.tryFfi(inputs);
+        assertEq(res.exitCode, 3);
+        // unlike with ffi, the stdout is not hex decoded
+        assertEq(string(res.stdout), "0x1234");
+        assertEq(string(res.stderr), "oops");
+
+        inputs[2] = "cat; exit 1";
+        res = hevm.tryFfi(inputs, bytes("not hex"));
+        assertEq(res.exitCode, 1);
+        assertEq(string(res.stdout), "not hex");
+    }
+
     function testDeal() public {
         address addr = address(1337);
         hevm.deal(addr, 1337);
         assertEq(addr.balance, 1337);
     }
 
+    function testDealToken() public {
+        DealToken token = new DealToken();
+        hevm.deal(address(token), address(1337), 50);
+        assertEq(token.balanceOf(address(1337)), 50);
+        assertEq(token.totalSupply(), 150);
+    }
+
+    function testDealTokenDoesNotChargeProbes() public {
+        DealToken token = new DealToken();
+        uint256 before = gasleft();
+        hevm.deal(address(token), address(1337), 50);
+        // the probes run with a budget of their own, so the cheatcode call stays cheap
+        assertLt(before - gasleft(), 10000);
+        assertEq(token.balanceOf(address(1337)), 50);
+    }
+
+    function testDealTokenWithoutAdjust() public {
+        DealToken token = new DealToken();
+        hevm.deal(address(token), address(1337), 50, false);
+        assertEq(token.balanceOf(address(1337)), 50);
+        assertEq(token.totalSupply(), 100);
+    }
+
+    function testDealTokenMappingIndex() public {
+        DealToken token = new DealToken();
+        hevm.deal(address(token), address(this), 10, true, 2);
+        assertEq(token.balanceOf(address(this)), 10);
+        assertEq(token.totalSupply(), 10);
+    }
+
+    function testDealAllowance() public {
+        DealToken token = new DealToken();
+        hevm.dealAllowance(address(token), address(1337), address(this), 50);
+        assertEq(token.allowance(address(1337), address(this)), 50);
+        assertEq(token.allowance(address(this), address(1337)), 0);
+    }
+
+    function testDealAllowanceMappingIndex() public {
+        DealToken token = new DealToken();
+        hevm.dealAllowance(address(token), address(1337), address(this), 50, 3);
+        assertEq(token.allowance(address(1337), address(this)), 50);
+    }
+
+    function testFailDealAllowanceWrongIndex() public {
+        DealToken token = new DealToken();
+        hevm.dealAllowance(address(token), address(1337), address(this), 50, 2);
+    }
+
+    function testDealERC721() public {
+        DealNFT token = new DealNFT();
+        hevm.dealERC721(address(token), address(1337), 7);
+        assertEq(token.ownerOf(7), address(1337));
+        assertEq(token.balanceOf(address(1337)), 1);
+
+        // dealing it again moves it away from its previous owner
+        hevm.dealERC721(address(token), address(1338), 7);
+        assertEq(token.ownerOf(7), address(1338));
+        assertEq(token.balanceOf(address(1338)), 1);
+        assertEq(token.balanceOf(address(1337)), 0);
+    }
+
+    function testDealERC721Owned() public {
+        DealNFT token = new DealNFT();
+        hevm.dealERC721(address(token), address(1337), 1);
+        assertEq(token.ownerOf(1), address(1337));
+        assertEq(token.balanceOf(address(1337)), 1);
+        assertEq(token.balanceOf(address(this)), 0);
+
+        // dealing it to its owner again changes nothing
+        hevm.dealERC721(address(token), address(1337), 1);
+        assertEq(token.balanceOf(address(1337)), 1);
+    }
+
+    function testDealERC721MappingIndices() public {
+        CustomNFT token = new CustomNFT();
+        hevm.dealERC721(address(token), address(1337), 7, 1, 0);
+        assertEq(token.ownerOf(7), address(1337));
+        assertEq(token.balanceOf(address(1337)), 1);
+    }
+
+    function testFailDealERC721WrongIndices() public {
+        CustomNFT token = new CustomNFT();
+        hevm.dealERC721(address(token), address(1337), 7);
+    }
+
+    // only deals the balance with the `Scale` strategy
+    function dealRebasingToken() public {
+        RebasingToken token = new RebasingToken();
+        hevm.deal(address(token), address(this), 50, false, 1);
+        assertEq(token.balanceOf(address(this)), 50);
+        assertEq(token.sharesOf(address(this)), 25);
+    }
+
     function testPrank() public {
         Prank prank = new Prank();
         address new_sender = address(1337);
@@ -293,6 +586,28 @@ contract CheatCodes is DSTest {
         prank.baz(address(this), tx.origin);
     }
 
+    function testPrankKeepsOrigin() public {
+        Prank prank = new Prank();
+        address new_sender = address(1337);
+        hevm.prank(new_sender);
+        prank.baz(new_sender, tx.origin);
+    }
+
+    function testFailStartPrankTwice() public {
+        hevm.startPrank(address(1337));
+        hevm.startPrank(address(1338));
+    }
+
+    function testFailPrankDuringStartPrank() public {
+        hevm.startPrank(address(1337));
+        hevm.prank(address(1338));
+    }
+
+    function testFailStartPrankDuringPrank() public {
+        hevm.prank(address(1337));
+        hevm.startPrank(address(1338));
+    }
+
     function testPrankStartComplexDual() public {
         // A -> B, B starts pranking, doesnt call stopPrank, A calls C calls D
         // C -> D would be pranked
@@ -364,16 +679,28 @@ contract CheatCodes is DSTest {
         RecordAccess2 target2 = target.record();
         (bytes32[] memory reads, bytes32[] memory writes) = hevm.accesses(address(target));
         (bytes32[] memory reads2, bytes32[] memory writes2) = hevm.accesses(address(target2));
-        assertEq(reads.length, 2); // sstore has to do an sload to grab the original storage, so we effectively have 2 sloads
+        assertEq(reads.length, 1); // the sload done by sstore to grab the original storage is deduplicated
         assertEq(writes.length, 1);
         assertEq(reads[0], bytes32(uint256(1)));
         assertEq(writes[0], bytes32(uint256(1)));
-        assertEq(reads2.length, 2); // sstore has to do an sload to grab the original storage, so we effectively have 2 sloads
+        assertEq(reads2.length, 1);
         assertEq(writes2.length, 1);
         assertEq(reads2[0], bytes32(uint256(2)));
         assertEq(writes2[0], bytes32(uint256(2)));
     }
 
+    function testRecordAccessDeduplicates() public {
+        RecordAccess target = new RecordAccess();
+        hevm.record();
+        target.readTwiceWriteOnce();
+        (bytes32[] memory reads, bytes32[] memory writes) = hevm.accesses(address(target));
+        assertEq(reads.length, 2);
+        assertEq(reads[0], bytes32(uint256(3)));
+        assertEq(reads[1], bytes32(uint256(4)));
+        assertEq(writes.length, 1);
+        assertEq(writes[0], bytes32(uint256(4)));
+    }
+
     event Transfer(address indexed from,address indexed to, uint256 amount);
     function testExpectEmit() public {
         ExpectEmit emitter = new ExpectEmit();
@@ -529,6 +856,27 @@ contract CheatCodes is DSTest {
         assertEq(target.add(6, 4), 10);
     }
 
+    function testMockLongerCalldata() public {
+        MockMe target = new MockMe();
+
+        hevm.mockCall(
+            address(target),
+            abi.encodeWithSelector(target.add.selector),
+            abi.encode(11)
+        );
+        hevm.mockCall(
+            address(target),
+            abi.encodeWithSelector(target.add.selector, 5),
+            abi.encode(12)
+        );
+
+        // calldata shorter than a mock must not match it
+        assertEq(target.numberA(), 1);
+        // the longest matching prefix wins
+        assertEq(target.add(5, 5), 12);
+        assertEq(target.add(6, 4), 11);
+    }
+
     function testClearMockedCalls() public {
         MockMe target = new MockMe();
 
@@ -615,6 +963,60 @@ contract CheatCodes is DSTest {
         target.add(3, 3);
     }
 
+    function testExpectCallWithValue() public {
+        MockMe target = new MockMe();
+        hevm.deal(address(this), 1 ether);
+        hevm.expectCall(
+            address(target),
+            1 gwei,
+            abi.encodeWithSelector(target.pay.selector)
+        );
+        target.pay{value: 1 gwei}();
+    }
+
+    function testFailExpectCallWithValue() public {
+        MockMe target = new MockMe();
+        hevm.deal(address(this), 1 ether);
+        hevm.expectCall(
+            address(target),
+            1 gwei,
+            abi.encodeWithSelector(target.pay.selector)
+        );
+        target.pay{value: 2 gwei}();
+    }
+
+    function testExpectCallCount() public {
+        MockMe target = new MockMe();
+        hevm.expectCall(
+            address(target),
+            abi.encodeWithSelector(target.add.selector),
+            2
+        );
+        target.add(1, 2);
+        target.add(3, 4);
+    }
+
+    function testFailExpectCallCountTooFew() public {
+        MockMe target = new MockMe();
+        hevm.expectCall(
+            address(target),
+            abi.encodeWithSelector(target.add.selector),
+            2
+        );
+        target.add(1, 2);
+    }
+
+    function testFailExpectCallCountTooMany() public {
+        MockMe target = new MockMe();
+        hevm.expectCall(
+            address(target),
+            abi.encodeWithSelector(target.add.selector),
+            1
+        );
+        target.add(1, 2);
+        target.add(1, 2);
+    }
+
     function testGetCode() public {
         bytes memory contractCode = hevm.getCode("./testdata/Contract.json");
         assertEq(
@@ -635,6 +1037,149 @@ contract CheatCodes is DSTest {
         labeled.withInput(address(labeled));
     }
 
+    function testGetNonce() public {
+        uint64 nonce = hevm.getNonce(address(this));
+        new Label();
+        require(hevm.getNonce(address(this)) == nonce + 1, "nonce not increased");
+        // the nonce of the sender was increased at the start of the transaction
+        require(hevm.getNonce(tx.origin) > 0, "sender nonce not increased");
+    }
+
+    function createAddress(address deployer, uint8 nonce) internal pure returns (address) {
+        // the rlp encoding of [deployer, nonce] for nonces in 1..0x7f
+        return address(uint160(uint256(keccak256(abi.encodePacked(bytes1(0xd6), bytes1(0x94), deployer, bytes1(nonce))))));
+    }
+
+    function testSetNonce() public {
+        Deployer deployer = new Deployer();
+        hevm.setNonce(address(deployer), 10);
+        require(hevm.getNonce(address(deployer)) == 10, "nonce not set");
+        require(deployer.deploy() == createAddress(address(deployer), 10), "unexpected address");
+        require(hevm.getNonce(address(deployer)) == 11, "nonce not increased");
+
+        hevm.setNonceUnsafe(address(deployer), 5);
+        require(deployer.deploy() == createAddress(address(deployer), 5), "unexpected address");
+
+        hevm.resetNonce(address(deployer));
+        require(hevm.getNonce(address(deployer)) == 1, "nonce not reset");
+        hevm.resetNonce(address(1337));
+        require(hevm.getNonce(address(1337)) == 0, "eoa nonce not reset");
+    }
+
+    function setNonceAndRevert(address who, uint64 nonce) public {
+        hevm.setNonce(who, nonce);
+        revert("reverted");
+    }
+
+    function testSetNonceRevertsWithTheCall() public {
+        Deployer deployer = new Deployer();
+        (bool success, ) = address(this).call(
+            abi.encodeWithSelector(this.setNonceAndRevert.selector, address(deployer), uint64(10))
+        );
+        require(!success, "call did not revert");
+        require(hevm.getNonce(address(deployer)) == 1, "nonce not reverted");
+        require(deployer.deploy() == createAddress(address(deployer), 1), "unexpected address");
+    }
+
+    function testFailSetNonceLower() public {
+        Deployer deployer = new Deployer();
+        hevm.setNonce(address(deployer), 0);
+    }
+
+    function testPauseTracing() public {
+        hevm.pauseTracing();
+        HasStorage store = new HasStorage();
+        hevm.resumeTracing();
+        require(store.slot0() == 10, "paused calls still execute");
+    }
+
+    function testSnapshot() public {
+        HasStorage store = new HasStorage();
+        uint256 balance = address(1337).balance;
+        uint256 id = hevm.snapshot();
+
+        hevm.deal(address(1337), balance + 1);
+        HasStorage other = new HasStorage();
+        require(hevm.revertTo(id), "snapshot not found");
+        require(address(1337).balance == balance, "balance not restored");
+        require(address(other).code.length == 0, "deployment not reverted");
+        require(store.slot0() == 10, "earlier deployment reverted");
+
+        // the snapshot can be reverted to until it is deleted
+        hevm.deal(address(1337), balance + 1);
+        require(hevm.revertTo(id), "snapshot not kept");
+        require(address(1337).balance == balance, "balance not restored again");
+        require(hevm.deleteSnapshot(id), "snapshot not deleted");
+        require(!hevm.revertTo(id), "deleted snapshot restored");
+
+        uint256 next = hevm.snapshot();
+        require(next > id, "snapshot id reused");
+        hevm.deleteSnapshots();
+        require(!hevm.revertTo(next), "snapshots not deleted");
+    }
+
+    function testSnapshotCreateAddress() public {
+        uint256 id = hevm.snapshot();
+        address first = address(new HasStorage());
+        require(hevm.revertTo(id), "snapshot not found");
+        require(address(new HasStorage()) == first, "create address changed");
+        require(hevm.revertTo(id), "snapshot not kept");
+        require(address(new HasStorage()) == first, "create address changed again");
+    }
+
+    function testAssertions() public {
+        hevm.assertTrue(true);
+        hevm.assertFalse(false);
+        hevm.assertEq(uint256(1), uint256(1));
+        hevm.assertEq(int256(-1), int256(-1));
+        hevm.assertEq(address(this), address(this));
+        hevm.assertEq(bytes32("a"), bytes32("a"));
+        hevm.assertEq(bytes("ab"), bytes("ab"));
+        hevm.assertGt(2, 1);
+        hevm.assertGe(1, 1);
+        hevm.assertLt(1, 2);
+        hevm.assertLe(1, 1);
+    }
+
+    function testFailAssertEq() public {
+        hevm.assertEq(uint256(1), uint256(2));
+    }
+
+    function failAssertions() public {
+        hevm.assertEq(int256(-1), int256(1));
+        hevm.assertEq(address(1), address(2));
+        hevm.assertGt(1, 2);
+        hevm.assertTrue(true);
+    }
+
+    function nonceOf(address who) public returns (uint64) {
+        return hevm.getNonce(who);
+    }
+
+    function testReadCallers() public {
+        address sender = address(1337);
+        address origin = address(1338);
+        (uint8 mode, address msgSender, address txOrigin) = hevm.readCallers();
+        require(mode == 0, "mode without prank");
+        require(msgSender == address(this), "sender without prank");
+        require(txOrigin == tx.origin, "origin without prank");
+
+        hevm.prank(sender, origin);
+        (mode, msgSender, txOrigin) = hevm.readCallers();
+        require(mode == 3 && msgSender == sender && txOrigin == origin, "prank");
+        Prank prank = new Prank();
+        (mode, msgSender, txOrigin) = hevm.readCallers();
+        require(mode == 0, "prank not consumed");
+
+        hevm.startPrank(sender);
+        (mode, msgSender, txOrigin) = hevm.readCallers();
+        require(mode == 4 && msgSender == sender && txOrigin == tx.origin, "startPrank");
+        prank.bar(sender);
+        hevm.stopPrank();
+        (mode, msgSender, txOrigin) = hevm.readCallers();
+        require(mode == 0 && msgSender == address(this), "stopPrank");
+    }
+
     function getCode(address who) internal returns (bytes memory o_code) {
         assembly {
             // retrieve the size of the code, this needs assembly
@@ -652,6 +1197,12 @@ contract CheatCodes is DSTest {
     }
 }
 
+contract Deployer {
+    function deploy() public returns (address) {
+        return address(new Label());
+    }
+}
+
 contract Label {
     function withInput(address labeled) public pure returns (address) {
         return labeled;
@@ -667,6 +1218,15 @@ contract RecordAccess {
         target2.record();
         return target2;
     }
+
+    function readTwiceWriteOnce() public {
+        assembly {
+            let a := sload(3)
+            let b := sload(3)
+            sstore(4, add(add(a, b), sload(4)))
+            sstore(4, add(sload(4), 1))
+        }
+    }
 }
 
 contract RecordAccess2 {
@@ -849,6 +1409,8 @@ contract MockMe {
     function add(uint256 a, uint256 b) public returns (uint256) {
         return a + b;
     }
+
+    function pay() public payable {}
 }
 
 contract MockInner {
@@ -866,3 +1428,67 @@ contract MockInner {
         return "hi";
     }
 }
+
+contract RebasingToken {
+    uint256 public totalShares;
+    mapping(address => uint256) public sharesOf;
+
+    function balanceOf(address who) public view returns (uint256) {
+        return sharesOf[who] * 2;
+    }
+}
+
+// The storage layout of OpenZeppelin's ERC721
+contract DealNFT {
+    string private _name;
+    string private _symbol;
+    mapping(uint256 => address) private _owners;
+    mapping(address => uint256) private _balances;
+
+    constructor() {
+        _owners[1] = msg.sender;
+        _balances[msg.sender] = 1;
+    }
+
+    function ownerOf(uint256 id) public view returns (address) {
+        address owner = _owners[id];
+        require(owner != address(0), "invalid token ID");
+        return owner;
+    }
+
+    function balanceOf(address who) public view returns (uint256) {
+        return _balances[who];
+    }
+}
+
+contract CustomNFT {
+    mapping(address => uint256) public balanceOf;
+    mapping(uint256 => address) private owners;
+
+    function ownerOf(uint256 id) public view returns (address) {
+        address owner = owners[id];
+        require(owner != address(0), "invalid token ID");
+        return owner;
+    }
+}
+
+contract DealToken {
+    address public owner;
+    uint256 public totalSupply;
+    mapping(address => uint256) public balanceOf;
+    mapping(address => mapping(address => uint256)) public allowance;
+
+    constructor() {
+        owner = msg.sender;
+        totalSupply = 100;
+        balanceOf[msg.sender] = 100;
+    }
+}
+
+contract CustomCheats {
+    address constant HEVM_ADDRESS = 0x7109709ECfa91a80626fF3989D68f67F5b1DD12D;
+
+    function cheat(bytes memory data) public returns (bool success, bytes memory out) {
+        (success, out) = HEVM_ADDRESS.call(data);
+    }
+}
@@ -12,6 +12,12 @@ contract ConsoleLogs {
 		console.log("Hi", 1337);
     }
 
+	function test_log_format() public {
+		console.log("Hi %s", 1337);
+		console.log("%s has %d tokens", address(0x1111111111111111111111111111111111111111), 5);
+		console.log("100%% %s", true);
+	}
+
 	function test_log_types() public {
 		console.logString("String");
 		console.logInt(1337);
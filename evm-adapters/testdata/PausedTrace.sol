@@ -0,0 +1,45 @@
+pragma solidity ^0.8.0;
+
+interface TracingHevm {
+    function pauseTracing() external;
+    function resumeTracing() external;
+    function skip(uint256) external;
+}
+
+contract PausedTrace {
+    TracingHevm constant hevm = TracingHevm(0x7109709ECfa91a80626fF3989D68f67F5b1DD12D);
+
+    function leaf() public {}
+
+    function inner() public {
+        this.leaf();
+    }
+
+    // only the second `inner` call and its subcall are traced
+    function pauseAndResume() public {
+        hevm.pauseTracing();
+        this.inner();
+        hevm.resumeTracing();
+        this.inner();
+    }
+
+    function resumeAndCall() public {
+        hevm.resumeTracing();
+        this.leaf();
+    }
+
+    // a cheatcode which reverts while tracing is paused doesn't keep it paused
+    function revertWhilePaused() public {
+        hevm.pauseTracing();
+        try hevm.skip(type(uint256).max) {} catch {}
+        hevm.resumeTracing();
+        this.leaf();
+    }
+
+    // tracing resumes within an untraced call, so only the last `leaf` call is traced
+    function resumeWithinUntracedCall() public {
+        hevm.pauseTracing();
+        this.resumeAndCall();
+        this.leaf();
+    }
+}
@@ -0,0 +1,25 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.0;
+
+contract ReentrantVault {
+    function withdraw() external {
+        ReentrantReceiver(msg.sender).onWithdraw();
+    }
+}
+
+contract ReentrantReceiver {
+    ReentrantVault public vault = new ReentrantVault();
+    bool public reentered;
+
+    function attack() external {
+        vault.withdraw();
+    }
+
+    // enters the vault once more while its first withdrawal is still running
+    function onWithdraw() external {
+        if (!reentered) {
+            reentered = true;
+            vault.withdraw();
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.0;
+
+contract Destructible {
+    function destroy(address payable to) external {
+        selfdestruct(to);
+    }
+}
+
+contract DestructibleFactory {
+    function create(bytes32 salt) public returns (address) {
+        return address(new Destructible{salt: salt}());
+    }
+
+    // returns whether the contract could be deployed again after it selfdestructed
+    function destroyAndRecreate(bytes32 salt) external returns (bool) {
+        Destructible destructible = new Destructible{salt: salt}();
+        destructible.destroy(payable(msg.sender));
+        try new Destructible{salt: salt}() returns (Destructible) {
+            return true;
+        } catch {
+            return false;
+        }
+    }
+}
@@ -0,0 +1,19 @@
+// SPDX-License-Identifier: UNLICENSED
+pragma solidity ^0.8.0;
+
+contract StorageLayout {
+    uint256 public value;
+    mapping(address => uint256) public balanceOf;
+    mapping(address => mapping(address => uint256)) public allowance;
+    uint256[] public values;
+    mapping(uint256 => uint256) public byId;
+
+    constructor() {
+        value = 1;
+        balanceOf[address(0x0101010101010101010101010101010101010101)] = 10;
+        allowance[address(0x0101010101010101010101010101010101010101)][address(0x0202020202020202020202020202020202020202)] = 20;
+        values.push(30);
+        values.push(40);
+        byId[5] = 50;
+    }
+}
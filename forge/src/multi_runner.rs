@@ -1,7 +1,10 @@
 use crate::{runner::TestResult, ContractRunner, TestFilter};
 use evm_adapters::{
     evm_opts::{BackendKind, EvmOpts},
-    sputnik::cheatcodes::{CONSOLE_ABI, HEVMCONSOLE_ABI, HEVM_ABI},
+    sputnik::{
+        cheatcodes::{CONSOLE_ABI, HEVMCONSOLE_ABI, HEVM_ABI},
+        CustomBackend,
+    },
 };
 use foundry_utils::PostLinkInput;
 use sputnik::{backend::Backend, Config};
@@ -31,6 +34,8 @@ pub struct MultiContractRunnerBuilder {
     pub initial_balance: U256,
     /// The EVM Configuration to use
     pub evm_cfg: Option<Config>,
+    /// The backend to run the tests against instead of the one configured by the [EvmOpts]
+    pub backend: Option<CustomBackend>,
 }
 
 pub type DeployableContracts =
@@ -136,6 +141,7 @@ impl MultiContractRunnerBuilder {
             evm_cfg: self.evm_cfg.unwrap_or_else(Config::london),
             sender: self.sender,
             fuzzer: self.fuzzer,
+            backend: self.backend,
             execution_info,
             source_paths,
         })
@@ -164,6 +170,14 @@ impl MultiContractRunnerBuilder {
         self.evm_cfg = Some(evm_cfg);
         self
     }
+
+    /// Runs the tests against the given backend instead of the one built from the [EvmOpts], e.g.
+    /// against a database of historical state
+    #[must_use]
+    pub fn backend(mut self, backend: impl Backend + Send + Sync + 'static) -> Self {
+        self.backend = Some(CustomBackend::new(backend));
+        self
+    }
 }
 
 /// A multi contract runner receives a set of contracts deployed in an EVM instance and proceeds
@@ -188,6 +202,8 @@ pub struct MultiContractRunner {
     sender: Option<Address>,
     /// A map of contract names to absolute source file paths
     source_paths: BTreeMap<String, String>,
+    /// The backend which is used instead of the one configured by the `evm_opts`
+    backend: Option<CustomBackend>,
 }
 
 impl MultiContractRunner {
@@ -197,24 +213,41 @@ impl MultiContractRunner {
         stream_result: Option<Sender<(String, BTreeMap<String, TestResult>)>>,
     ) -> Result<BTreeMap<String, BTreeMap<String, TestResult>>> {
         let contracts = std::mem::take(&mut self.contracts);
-        let vicinity = self.evm_opts.vicinity()?;
-        let backend = self.evm_opts.backend(&vicinity)?;
-        let source_paths = self.source_paths.clone();
-
-        let results = contracts
-            .par_iter()
-            .filter(|(name, _)| filter.matches_path(source_paths.get(*name).unwrap()))
-            .filter(|(name, _)| filter.matches_contract(name))
-            .map(|(name, (abi, deploy_code, libs))| {
-                // unavoidable duplication here?
-                let result = match backend {
+        let results = match self.backend {
+            // a custom backend brings its own state, so there is nothing to build or fork
+            Some(ref backend) => self.test_with(&contracts, backend, filter, stream_result),
+            None => {
+                let vicinity = self.evm_opts.vicinity()?;
+                match self.evm_opts.backend(&vicinity)? {
                     BackendKind::Simple(ref backend) => {
-                        self.run_tests(name, abi, backend, deploy_code.clone(), libs, filter)?
+                        self.test_with(&contracts, backend, filter, stream_result)
                     }
                     BackendKind::Shared(ref backend) => {
-                        self.run_tests(name, abi, backend, deploy_code.clone(), libs, filter)?
+                        self.test_with(&contracts, backend, filter, stream_result)
                     }
-                };
+                }
+            }
+        };
+
+        self.contracts = contracts;
+        Ok(results)
+    }
+
+    /// Runs the tests of all contracts which match the filter against the backend
+    fn test_with<B: Backend + Clone + Send + Sync>(
+        &self,
+        contracts: &BTreeMap<String, (Abi, ethers::prelude::Bytes, Vec<ethers::prelude::Bytes>)>,
+        backend: &B,
+        filter: &(impl TestFilter + Send + Sync),
+        stream_result: Option<Sender<(String, BTreeMap<String, TestResult>)>>,
+    ) -> BTreeMap<String, BTreeMap<String, TestResult>> {
+        contracts
+            .par_iter()
+            .filter(|(name, _)| filter.matches_path(self.source_paths.get(*name).unwrap()))
+            .filter(|(name, _)| filter.matches_contract(name))
+            .map(|(name, (abi, deploy_code, libs))| {
+                let result =
+                    self.run_tests(name, abi, backend, deploy_code.clone(), libs, filter)?;
                 Ok((name.clone(), result))
             })
             .filter_map(|x: Result<_>| x.ok())
@@ -227,10 +260,7 @@ impl MultiContractRunner {
                 }
                 (name, result)
             })
-            .collect::<BTreeMap<_, _>>();
-
-        self.contracts = contracts;
-        Ok(results)
+            .collect::<BTreeMap<_, _>>()
     }
 
     // The _name field is unused because we only want it for tracing
@@ -324,8 +354,22 @@ mod tests {
 
     mod sputnik {
         use super::*;
+        use crate::test_helpers::BACKEND;
         use std::collections::HashMap;
 
+        #[test]
+        fn test_custom_backend_is_not_forked() {
+            // nothing listens on this port, the custom backend brings its own state
+            let opts =
+                EvmOpts { fork_url: Some("http://127.0.0.1:1".to_string()), ..EVM_OPTS.clone() };
+            let mut runner = MultiContractRunnerBuilder::default()
+                .backend(BACKEND.clone())
+                .build(project(), opts)
+                .unwrap();
+            let results = runner.test(&Filter::new("testGm.*", ".*", ".*"), None).unwrap();
+            assert!(results["GmTest.json:GmTest"]["testGm()"].success);
+        }
+
         #[test]
         fn test_sputnik_debug_logs() {
             let mut runner = runner();
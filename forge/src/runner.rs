@@ -347,7 +347,7 @@ impl<'a, B: Backend + Clone + Send + Sync> ContractRunner<'a, B> {
             logs.extend_from_slice(&setup_logs);
         }
 
-        let (status, reason, gas_used, logs) = match evm.call::<(), _, _>(
+        let (status, reason, gas_used, logs) = match evm.call_committing::<(), _, _>(
             self.sender,
             address,
             func.clone(),
@@ -384,6 +384,12 @@ impl<'a, B: Backend + Clone + Send + Sync> ContractRunner<'a, B> {
         let success = evm.check_success(address, &status, should_fail);
         let duration = Instant::now().duration_since(start);
         tracing::debug!(?duration, %success, %gas_used);
+        // report all failed assertions, the test didn't necessarily revert because of them
+        let failures = evm.assertion_failures();
+        let reason = reason.or_else(|| {
+            (!failures.is_empty())
+                .then(|| failures.into_iter().map(|f| f.message).collect::<Vec<_>>().join("\n"))
+        });
 
         Ok(TestResult {
             success,
@@ -234,55 +234,32 @@ pub fn flatten_known_contracts(
     (flattened_funcs, flattened_events, errors_abi)
 }
 
+/// Describes the code of a solidity `Panic(uint256)`, see
+/// <https://docs.soliditylang.org/en/latest/control-structures.html#panic-via-assert-and-error-via-require>
+pub fn panic_reason(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x01 => "Assertion violated",
+        0x11 => "Arithmetic over/underflow",
+        0x12 => "Division or modulo by 0",
+        0x21 => "Conversion into non-existent enum type",
+        0x22 => "Incorrectly encoded storage byte array",
+        0x31 => "`pop()` on empty array",
+        0x32 => "Index out of bounds",
+        0x41 => "Memory allocation overflow",
+        0x51 => "Calling a zero initialized variable of internal function type",
+        _ => return None,
+    })
+}
+
 /// Given an ABI encoded error string with the function signature `Error(string)`, it decodes
 /// it and returns the revert error message.
 pub fn decode_revert(error: &[u8], maybe_abi: Option<&Abi>) -> Result<String> {
     if error.len() >= 4 {
         match error[0..4] {
             // keccak(Panic(uint256))
-            [78, 72, 123, 113] => {
-                // ref: https://soliditydeveloper.com/solidity-0.8
-                match error[error.len() - 1] {
-                    1 => {
-                        // assert
-                        Ok("Assertion violated".to_string())
-                    }
-                    17 => {
-                        // safemath over/underflow
-                        Ok("Arithmetic over/underflow".to_string())
-                    }
-                    18 => {
-                        // divide by 0
-                        Ok("Division or modulo by 0".to_string())
-                    }
-                    33 => {
-                        // conversion into non-existent enum type
-                        Ok("Conversion into non-existent enum type".to_string())
-                    }
-                    34 => {
-                        // incorrectly encoded storage byte array
-                        Ok("Incorrectly encoded storage byte array".to_string())
-                    }
-                    49 => {
-                        // pop() on empty array
-                        Ok("`pop()` on empty array".to_string())
-                    }
-                    50 => {
-                        // index out of bounds
-                        Ok("Index out of bounds".to_string())
-                    }
-                    65 => {
-                        // allocating too much memory or creating too large array
-                        Ok("Memory allocation overflow".to_string())
-                    }
-                    81 => {
-                        // calling a zero initialized variable of internal function type
-                        Ok("Calling a zero initialized variable of internal function type"
-                            .to_string())
-                    }
-                    _ => Err(eyre::Error::msg("Unsupported solidity builtin panic")),
-                }
-            }
+            [78, 72, 123, 113] => panic_reason(error[error.len() - 1])
+                .map(str::to_string)
+                .ok_or_else(|| eyre::Error::msg("Unsupported solidity builtin panic")),
             // keccak(Error(string))
             [8, 195, 121, 160] => {
                 if let Ok(decoded) = abi::decode(&[abi::ParamType::String], &error[4..]) {